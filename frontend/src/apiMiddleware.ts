@@ -128,7 +128,7 @@ export default async function apiMiddleware(
 
   let emailFilterChanged = false;
   const emailFilter = new URLSearchParams();
-  for (const param of ["limit", "status", "before", "labels", "project"]) {
+  for (const param of ["limit", "status", "before", "labels", "project", "source"]) {
     const value = navState.to.params[param];
     if (value != navState.from.params[param]) emailFilterChanged = true;
     if (value) emailFilter.append(param, value);
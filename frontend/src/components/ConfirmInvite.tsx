@@ -43,6 +43,11 @@ export function ConfirmInvite() {
       method: "POST",
     });
 
+    if (res.status === 410) {
+      errorNotification("This invite has expired");
+      return;
+    }
+
     if (res.status !== 201) {
       errorNotification("Could not accept invite");
       console.error(res);
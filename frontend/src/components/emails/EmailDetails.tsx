@@ -1,5 +1,5 @@
 import { useEmails } from "../../hooks/useEmails.ts";
-import { Badge, Group, Paper, SegmentedControl, Table, Text, Tooltip } from "@mantine/core";
+import { Anchor, Badge, Group, Paper, SegmentedControl, Table, Text, Tooltip } from "@mantine/core";
 import { useState } from "react";
 import { Loader } from "../../Loader.tsx";
 import { EmailMetadata, isFullEmail } from "../../types.ts";
@@ -11,6 +11,7 @@ import { Recipients } from "./Recipients.tsx";
 import Header from "../Header.tsx";
 import Label from "./Label.tsx";
 import ProjectLink from "../ProjectLink.tsx";
+import { useOrganizations } from "../../hooks/useOrganizations.ts";
 
 export function getFullStatusDescription(email: EmailMetadata) {
   if (email.status == "delivered") {
@@ -22,6 +23,14 @@ export function getFullStatusDescription(email: EmailMetadata) {
       s += `: ${email.reason}`;
     }
 
+    if (email.hold_reason == "quota") {
+      s += ", retrying once the quota resets";
+    } else if (email.hold_reason == "configuration") {
+      s += ", retry manually after fixing the configuration";
+    } else if (email.hold_reason == "spam") {
+      s += ", retry manually to send it anyway";
+    }
+
     if (email.retry_after) {
       const retry_after_formatted = is_in_the_future(email.retry_after)
         ? "after " + formatDateTime(email.retry_after)
@@ -39,6 +48,7 @@ export function getFullStatusDescription(email: EmailMetadata) {
 
 export default function EmailDetails() {
   const { currentEmail, updateEmail } = useEmails();
+  const { currentOrganization } = useOrganizations();
   const [displayMode, setDisplayMode] = useState("text");
 
   if (!isFullEmail(currentEmail)) {
@@ -86,6 +96,24 @@ export default function EmailDetails() {
       info: "The time that remails received this email",
       value: formatDateTime(currentEmail.created_at),
     },
+    ...(currentEmail.client_ip
+      ? [
+          {
+            header: "Client IP",
+            info: "The IP address of the client that submitted this email, only visible to organization admins",
+            value: currentEmail.client_ip,
+          },
+        ]
+      : []),
+    ...(currentEmail.correlation_id
+      ? [
+          {
+            header: "Correlation ID",
+            info: "Ties the API request that submitted this email to its delivery, see the X-Correlation-Id header",
+            value: currentEmail.correlation_id,
+          },
+        ]
+      : []),
     {
       header: "Total size",
       info: "The size of the whole email",
@@ -171,7 +199,9 @@ export default function EmailDetails() {
             <Text style={{ whiteSpace: "pre-wrap" }}>{text_body}</Text>
           ) : (
             <Text c="dimmed" fs="italic">
-              No plain text version provided
+              {currentEmail.unparseable
+                ? "This email could not be parsed, see the raw version instead"
+                : "No plain text version provided"}
             </Text>
           ))}
         {displayMode === "raw" &&
@@ -182,7 +212,13 @@ export default function EmailDetails() {
               </Text>
               {currentEmail.is_truncated && (
                 <Text c="dimmed" fs="italic">
-                  Email truncated
+                  Email truncated,{" "}
+                  <Anchor
+                    href={`/api/organizations/${currentOrganization?.id}/emails/${currentEmail.id}/raw`}
+                    target="_blank"
+                  >
+                    view the full raw email
+                  </Anchor>
                 </Text>
               )}
             </>
@@ -21,7 +21,7 @@ import InfoTooltip from "../InfoTooltip.tsx";
 function statusIcons(status: EmailStatus) {
   if (status == "processing" || status == "accepted") {
     return <IconClock color="gray" />;
-  } else if (status == "held" || status == "reattempt") {
+  } else if (status == "held" || status == "reattempt" || status == "needs_review") {
     return <IconClock color="orange" />;
   } else if (status == "rejected" || status == "failed") {
     return <IconX color="red" />;
@@ -48,7 +48,7 @@ export function EmailOverview() {
     return <Loader />;
   }
 
-  const setFilter = (filter: "limit" | "status" | "before" | "labels", value: string | null) => {
+  const setFilter = (filter: "limit" | "status" | "before" | "labels" | "source", value: string | null) => {
     navigate(routerState.name, { ...routerState.params, [filter]: value ?? "" });
   };
 
@@ -204,6 +204,16 @@ export function EmailOverview() {
             onChange={(status) => setFilter("status", status.join(","))}
             maxDropdownHeight={400}
           />
+          <NativeSelect
+            label="Sent via"
+            value={currentParams.source || ""}
+            data={[
+              { label: "SMTP or API", value: "" },
+              { label: "SMTP", value: "smtp" },
+              { label: "API", value: "api" },
+            ]}
+            onChange={(event) => setFilter("source", event.currentTarget.value || null)}
+          />
           <DateTimePicker
             label="Created before"
             value={currentParams.before}
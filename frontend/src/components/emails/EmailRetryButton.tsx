@@ -4,7 +4,7 @@ import { IconReload } from "@tabler/icons-react";
 import { useOrganizations } from "../../hooks/useOrganizations.ts";
 import { is_in_the_future } from "../../util.ts";
 import { errorNotification } from "../../notify.tsx";
-import { MaintainerActionIcon, MaintainerButton } from "../RoleButtons.tsx";
+import { AdminActionIcon, AdminButton, MaintainerActionIcon, MaintainerButton } from "../RoleButtons.tsx";
 import { useState } from "react";
 
 export default function EmailRetryButton({
@@ -24,9 +24,11 @@ export default function EmailRetryButton({
   }
 
   const email_endpoint = `/api/organizations/${currentOrganization.id}/emails/${email.id}`;
+  // emails that need review get another round of automatic retries instead of a single retry
+  const requeue = email.status == "needs_review";
 
   async function retry() {
-    const res = await fetch(`${email_endpoint}/retry`, {
+    const res = await fetch(`${email_endpoint}/${requeue ? "requeue" : "retry"}`, {
       method: "PUT",
       headers: {
         "Content-Type": "application/json",
@@ -39,7 +41,7 @@ export default function EmailRetryButton({
     }
 
     notifications.show({
-      title: "Scheduled retry",
+      title: requeue ? "Requeued" : "Scheduled retry",
       message: "Email will be retried soon",
       color: "blue",
       autoClose: 20000,
@@ -70,12 +72,17 @@ export default function EmailRetryButton({
   const tooltip = status_retryable
     ? already_scheduled
       ? "Email is already scheduled to retry as soon as possible"
-      : "(Re-)schedule retry"
+      : requeue
+        ? "Requeue for another round of automatic retries"
+        : "(Re-)schedule retry"
     : `Email is ${email.status}`;
 
+  const RetryActionIcon = requeue ? AdminActionIcon : MaintainerActionIcon;
+  const RetryButton = requeue ? AdminButton : MaintainerButton;
+
   if (small) {
     return (
-      <MaintainerActionIcon
+      <RetryActionIcon
         tooltip={tooltip}
         disabled={!can_retry}
         onClick={onClick}
@@ -84,19 +91,19 @@ export default function EmailRetryButton({
         loading={loading}
       >
         <IconReload />
-      </MaintainerActionIcon>
+      </RetryActionIcon>
     );
   } else {
     return (
-      <MaintainerButton
+      <RetryButton
         tooltip={tooltip}
         leftSection={<IconReload />}
         disabled={!can_retry}
         onClick={onClick}
         loading={loading}
       >
-        Retry
-      </MaintainerButton>
+        {requeue ? "Requeue" : "Retry"}
+      </RetryButton>
     );
   }
 }
@@ -27,11 +27,11 @@ export function Recipient({ details, recipient, props }: { details: DeliveryDeta
 
   let tooltip = "Email not (yet) sent";
   if (status.type == "Failed") {
-    tooltip = "Permanent failure";
+    tooltip = details.bounce == "hard" ? "Permanent failure (hard bounce)" : "Permanent failure";
   } else if (status.type == "Suppressed") {
     tooltip = "Suppressed email address";
   } else if (status.type == "Reattempt") {
-    tooltip = "Temporary failure";
+    tooltip = details.bounce == "soft" ? "Temporary failure (soft bounce)" : "Temporary failure";
   } else if (status.type == "Success") {
     tooltip = `Delivered on ${formatDateTime(status.delivered)}`;
   }
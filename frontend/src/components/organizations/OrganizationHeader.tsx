@@ -1,10 +1,11 @@
-import { IconAlertTriangle, IconBuildings } from "@tabler/icons-react";
+import { IconAlertTriangle, IconBuildings, IconPlayerPause, IconPlayerPlay } from "@tabler/icons-react";
 import { useOrganizations, useOrgRole } from "../../hooks/useOrganizations";
 import Header from "../Header";
-import { ThemeIcon, Tooltip } from "@mantine/core";
+import { Button, Group, ThemeIcon, Tooltip } from "@mantine/core";
 import { errorNotification } from "../../notify";
 import { notifications } from "@mantine/notifications";
 import { useRemails } from "../../hooks/useRemails";
+import { Organization } from "../../types";
 
 export default function OrganizationHeader({ allowRename }: { allowRename?: boolean }) {
   const { currentOrganization } = useOrganizations();
@@ -15,6 +16,36 @@ export default function OrganizationHeader({ allowRename }: { allowRename?: bool
     return null;
   }
 
+  const updateOrganization = (organization: Organization) => {
+    dispatch({ type: "remove_organization", organizationId: organization.id });
+    dispatch({ type: "add_organization", organization });
+  };
+
+  const toggleSendingPaused = async () => {
+    const sending_paused = !currentOrganization.sending_paused;
+    const res = await fetch(`/api/organizations/${currentOrganization.id}/sending_paused`, {
+      method: "PUT",
+      headers: {
+        "Content-Type": "application/json",
+      },
+      body: JSON.stringify({ sending_paused }),
+    });
+    if (res.status !== 200) {
+      errorNotification(`Sending could not be ${sending_paused ? "paused" : "resumed"}`);
+      console.error(res);
+      return;
+    }
+
+    notifications.show({
+      title: sending_paused ? "Sending paused" : "Sending resumed",
+      message: sending_paused
+        ? "New emails are accepted, but only sent once sending is resumed"
+        : "Queued emails will be sent shortly",
+      color: "green",
+    });
+    updateOrganization(await res.json());
+  };
+
   const saveRename = allowRename && isAdmin ? async (values: { name: string }) => {
     const res = await fetch(`/api/organizations/${currentOrganization.id}`, {
       method: "PUT",
@@ -35,8 +66,7 @@ export default function OrganizationHeader({ allowRename }: { allowRename?: bool
       message: "",
       color: "green",
     });
-    dispatch({ type: "remove_organization", organizationId: currentOrganization.id });
-    dispatch({ type: "add_organization", organization: organization });
+    updateOrganization(organization);
   } : undefined;
 
 
@@ -53,13 +83,29 @@ export default function OrganizationHeader({ allowRename }: { allowRename?: bool
     );
   }
 
+  const pause_button = isAdmin ? (
+    <Button
+      variant="light"
+      size="xs"
+      leftSection={currentOrganization.sending_paused ? <IconPlayerPlay size={16} /> : <IconPlayerPause size={16} />}
+      onClick={toggleSendingPaused}
+    >
+      {currentOrganization.sending_paused ? "Resume sending" : "Pause sending"}
+    </Button>
+  ) : null;
+
   return (
     <Header
       name={currentOrganization?.name ?? ""}
       entityType={"Organization"}
       Icon={IconBuildings}
       divider
-      addendum={blocked_warning}
+      addendum={
+        <Group gap="xs">
+          {blocked_warning}
+          {pause_button}
+        </Group>
+      }
       saveRename={saveRename}
     />
   );
@@ -1,4 +1,4 @@
-import { Badge, Button, Card, Container, Divider, Grid, Stack, Text, Title, Tooltip } from "@mantine/core";
+import { Anchor, Badge, Button, Card, Container, Divider, Grid, Stack, Table, Text, Title, Tooltip } from "@mantine/core";
 import { useSubscription } from "../../hooks/useSubscription.ts";
 import { SubscriptionStatus } from "../../types.ts";
 import React from "react";
@@ -6,6 +6,49 @@ import { formatDate } from "../../util.ts";
 import { useOrgRole } from "../../hooks/useOrganizations.ts";
 import OrganizationHeader from "./OrganizationHeader.tsx";
 import InfoAlert from "../InfoAlert.tsx";
+import { useInvoices } from "../../hooks/useInvoices.ts";
+
+function Invoices() {
+  const { invoices } = useInvoices();
+
+  if (!invoices || invoices.length === 0) {
+    return null;
+  }
+
+  return (
+    <>
+      <Title order={3} mt="xl" mb="md">
+        Invoices
+      </Title>
+      <Table>
+        <Table.Thead>
+          <Table.Tr>
+            <Table.Th>Invoice</Table.Th>
+            <Table.Th>Date</Table.Th>
+            <Table.Th>Amount</Table.Th>
+            <Table.Th>Status</Table.Th>
+          </Table.Tr>
+        </Table.Thead>
+        <Table.Tbody>
+          {invoices.map((invoice) => (
+            <Table.Tr key={invoice.invoice_number}>
+              <Table.Td>
+                <Anchor href={invoice.url} target="_blank">
+                  {invoice.invoice_number}
+                </Anchor>
+              </Table.Td>
+              <Table.Td>{formatDate(invoice.date)}</Table.Td>
+              <Table.Td>
+                {invoice.currency} {invoice.amount}
+              </Table.Td>
+              <Table.Td tt="capitalize">{invoice.status.replace("_", " ")}</Table.Td>
+            </Table.Tr>
+          ))}
+        </Table.Tbody>
+      </Table>
+    </>
+  );
+}
 
 export default function Subscription() {
   const { currentSubscription, navigateToSales, navigateToCustomerPortal } = useSubscription();
@@ -88,6 +131,8 @@ export default function Subscription() {
           <Stack gap="md">{details(currentSubscription)}</Stack>
         </Card>
       </Container>
+
+      {isAdmin && <Invoices />}
     </>
   );
 }
@@ -1,6 +1,6 @@
 import { useForm } from "@mantine/form";
-import { Group, Slider, Stack, Switch, Text, TextInput } from "@mantine/core";
-import { ProductIdentifier, Project } from "../../types.ts";
+import { Group, NumberInput, Slider, Stack, Switch, Text, TextInput } from "@mantine/core";
+import { ProductIdentifier, Project, UnauthorizedDomainPolicy } from "../../types.ts";
 import { modals } from "@mantine/modals";
 import { notifications } from "@mantine/notifications";
 import { IconTrash } from "@tabler/icons-react";
@@ -17,6 +17,16 @@ interface FormValues {
   name: string;
   retention_period_days: number;
   plaintext_fallback: boolean;
+  verp: boolean;
+  dedup_window_minutes: number | null;
+  max_automatic_retries: number | null;
+  max_message_age_minutes: number | null;
+  default_from_email: string | null;
+  default_from_name: string | null;
+  message_quota: number | null;
+  unauthorized_domain_policy: UnauthorizedDomainPolicy;
+  rewrite_unaligned_from: boolean;
+  review_exhausted_messages: boolean;
 }
 
 // Values should match `max_retention_period` in `src/moneybird/model.rs`
@@ -46,6 +56,16 @@ export default function ProjectSettings() {
       name: currentProject?.name || "",
       retention_period_days: currentProject?.retention_period_days || 1,
       plaintext_fallback: currentProject?.plaintext_fallback || false,
+      verp: currentProject?.verp || false,
+      dedup_window_minutes: currentProject?.dedup_window_minutes ?? null,
+      max_automatic_retries: currentProject?.max_automatic_retries ?? null,
+      max_message_age_minutes: currentProject?.max_message_age_minutes ?? null,
+      default_from_email: currentProject?.default_from_email ?? null,
+      default_from_name: currentProject?.default_from_name ?? null,
+      message_quota: currentProject?.message_quota ?? null,
+      unauthorized_domain_policy: currentProject?.unauthorized_domain_policy ?? "hold",
+      rewrite_unaligned_from: currentProject?.rewrite_unaligned_from || false,
+      review_exhausted_messages: currentProject?.review_exhausted_messages || false,
     },
     validate: {
       name: (value) => {
@@ -142,6 +162,30 @@ export default function ProjectSettings() {
             onChange={(event) => form.setFieldValue("name", event.currentTarget.value)}
           />
 
+          <Group align="end">
+            <TextInput
+              label="Default sender address"
+              placeholder="noreply@example.com"
+              value={form.values.default_from_email ?? ""}
+              onChange={(event) => {
+                form.setFieldValue("default_from_email", event.currentTarget.value || null);
+                if (!event.currentTarget.value) {
+                  form.setFieldValue("default_from_name", null);
+                }
+              }}
+            />
+            <TextInput
+              label="Default sender name"
+              disabled={form.values.default_from_email === null}
+              value={form.values.default_from_name ?? ""}
+              onChange={(event) => form.setFieldValue("default_from_name", event.currentTarget.value || null)}
+            />
+            <InfoTooltip
+              text="Used for emails sent via the API without a from address. The project must be permitted to use the domain of this address."
+              size="xs"
+            />
+          </Group>
+
           <Stack gap="xs">
             <Group gap="xs" fz="sm" mb={0}>
               Email retention period (max. {max_retention} day){" "}
@@ -174,6 +218,111 @@ export default function ProjectSettings() {
               />
               <InfoTooltip text="If enabled, emails in the project will fall back to being sent without TLS encryption if delivery over TLS fails." size="xs" />
             </Group>
+            <Group mt="sm">
+              <Switch
+                checked={form.values.verp}
+                onChange={(ev) => form.setFieldValue("verp", ev.currentTarget.checked)}
+                label="Enable VERP"
+              />
+              <InfoTooltip text="If enabled, emails in the project are sent with an envelope sender that is unique per recipient, such that bounces can be attributed to a specific recipient." size="xs" />
+            </Group>
+            <Group mt="sm">
+              <Switch
+                checked={form.values.dedup_window_minutes !== null}
+                onChange={(ev) => form.setFieldValue("dedup_window_minutes", ev.currentTarget.checked ? 60 : null)}
+                label="Deduplicate messages"
+              />
+              <InfoTooltip text="If enabled, a message with the same Message-ID as a message created in this project within the deduplication window is not stored or sent again." size="xs" />
+              {form.values.dedup_window_minutes !== null && (
+                <NumberInput
+                  size="xs"
+                  min={1}
+                  max={10080}
+                  suffix=" minutes"
+                  value={form.values.dedup_window_minutes}
+                  onChange={(value) => form.setFieldValue("dedup_window_minutes", typeof value === "number" ? value : 1)}
+                />
+              )}
+            </Group>
+            <Group mt="sm">
+              <Switch
+                checked={form.values.max_automatic_retries !== null}
+                onChange={(ev) => form.setFieldValue("max_automatic_retries", ev.currentTarget.checked ? 5 : null)}
+                label="Limit delivery attempts"
+              />
+              <InfoTooltip text="If enabled, messages in this project are considered failed after this many delivery attempts." size="xs" />
+              {form.values.max_automatic_retries !== null && (
+                <NumberInput
+                  size="xs"
+                  min={1}
+                  max={20}
+                  suffix=" attempts"
+                  value={form.values.max_automatic_retries}
+                  onChange={(value) => form.setFieldValue("max_automatic_retries", typeof value === "number" ? value : 1)}
+                />
+              )}
+            </Group>
+            <Group mt="sm">
+              <Switch
+                checked={form.values.max_message_age_minutes !== null}
+                onChange={(ev) => form.setFieldValue("max_message_age_minutes", ev.currentTarget.checked ? 1440 : null)}
+                label="Limit message lifetime"
+              />
+              <InfoTooltip text="If enabled, messages in this project that could not be delivered within this many minutes are considered failed, even if delivery attempts are left." size="xs" />
+              {form.values.max_message_age_minutes !== null && (
+                <NumberInput
+                  size="xs"
+                  min={1}
+                  max={10080}
+                  suffix=" minutes"
+                  value={form.values.max_message_age_minutes}
+                  onChange={(value) => form.setFieldValue("max_message_age_minutes", typeof value === "number" ? value : 1)}
+                />
+              )}
+            </Group>
+            <Group mt="sm">
+              <Switch
+                checked={form.values.message_quota !== null}
+                onChange={(ev) => form.setFieldValue("message_quota", ev.currentTarget.checked ? 1000 : null)}
+                label="Limit message quota"
+              />
+              <InfoTooltip text="If enabled, this project can send at most this many messages per quota period, such that it can't use up the quota of the whole organization. The quotas of all projects together can't exceed the quota of the organization." size="xs" />
+              {form.values.message_quota !== null && (
+                <NumberInput
+                  size="xs"
+                  min={0}
+                  suffix={` messages (${currentProject.used_message_quota} used)`}
+                  value={form.values.message_quota}
+                  onChange={(value) => form.setFieldValue("message_quota", typeof value === "number" ? value : 0)}
+                />
+              )}
+            </Group>
+            <Group mt="sm">
+              <Switch
+                checked={form.values.unauthorized_domain_policy === "reject"}
+                onChange={(ev) =>
+                  form.setFieldValue("unauthorized_domain_policy", ev.currentTarget.checked ? "reject" : "hold")
+                }
+                label="Reject messages from unauthorized domains"
+              />
+              <InfoTooltip text="If enabled, messages sent from a domain this project is not permitted to use are rejected right away, instead of being held until the domain has been configured." size="xs" />
+            </Group>
+            <Group mt="sm">
+              <Switch
+                checked={form.values.rewrite_unaligned_from}
+                onChange={(ev) => form.setFieldValue("rewrite_unaligned_from", ev.currentTarget.checked)}
+                label="Rewrite From header of forwarded emails"
+              />
+              <InfoTooltip text="If enabled, emails with a From address outside the domain of the envelope sender are sent from the envelope sender instead of being rejected. The original From address is kept as Reply-To address." size="xs" />
+            </Group>
+            <Group mt="sm">
+              <Switch
+                checked={form.values.review_exhausted_messages}
+                onChange={(ev) => form.setFieldValue("review_exhausted_messages", ev.currentTarget.checked)}
+                label="Review emails that exhausted their retries"
+              />
+              <InfoTooltip text="If enabled, emails that could not be delivered within the maximum number of automatic retries need review instead of failing. The organization admins are notified, and can requeue such emails for another round of retries." size="xs" />
+            </Group>
           </Stack>
 
           <Group mt="xl">
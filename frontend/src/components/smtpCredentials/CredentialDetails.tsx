@@ -4,17 +4,20 @@ import { useCredentials } from "../../hooks/useCredentials.ts";
 import { useRemails } from "../../hooks/useRemails.ts";
 import { useForm } from "@mantine/form";
 import { Loader } from "../../Loader.tsx";
-import { SmtpCredential } from "../../types.ts";
+import { SmtpCredential, SmtpCredentialResponse } from "../../types.ts";
 import { modals } from "@mantine/modals";
-import { Container, Group, Stack, Text, Textarea, TextInput, Tooltip } from "@mantine/core";
+import { Alert, Container, Group, Stack, TagsInput, Text, Textarea, TextInput, Tooltip } from "@mantine/core";
 import { notifications } from "@mantine/notifications";
-import { IconKey, IconTrash } from "@tabler/icons-react";
+import { IconInfoCircle, IconKey, IconRefresh, IconTrash } from "@tabler/icons-react";
+import { CopyableCode } from "../CopyableCode.tsx";
 import Header from "../Header.tsx";
 import { errorNotification } from "../../notify.tsx";
 import { MaintainerButton } from "../RoleButtons.tsx";
 
 interface FormValues {
   description: string;
+  allowed_recipient_domains: string[];
+  allowed_sender_addresses: string[];
 }
 
 export default function CredentialDetails() {
@@ -26,6 +29,8 @@ export default function CredentialDetails() {
   const form = useForm<FormValues>({
     initialValues: {
       description: currentCredential?.description ?? "",
+      allowed_recipient_domains: currentCredential?.allowed_recipient_domains ?? [],
+      allowed_sender_addresses: currentCredential?.allowed_sender_addresses ?? [],
     },
   });
 
@@ -72,6 +77,53 @@ export default function CredentialDetails() {
     }
   };
 
+  const confirmRotateCredential = (credential: SmtpCredential) => {
+    modals.openConfirmModal({
+      title: "Please confirm your action",
+      children: (
+        <Text>
+          Are you sure you want to generate a new password for the SMTP credential with the username{" "}
+          <strong>{credential.username}</strong>? The current password will stop working immediately.
+        </Text>
+      ),
+      labels: { confirm: "Rotate password", cancel: "Cancel" },
+      confirmProps: {
+        leftSection: <IconRefresh />,
+      },
+      onCancel: () => { },
+      onConfirm: () => rotateCredential(credential),
+    });
+  };
+
+  const rotateCredential = async (credential: SmtpCredential) => {
+    const res = await fetch(
+      `/api/organizations/${currentOrganization.id}/projects/${currentProject.id}/smtp_credentials/${credential.id}/rotate`,
+      {
+        method: "POST",
+      }
+    );
+    if (res.status !== 200) {
+      errorNotification(`The password of credential ${credential.username} could not be rotated`);
+      console.error(res);
+      return;
+    }
+    const rotated: SmtpCredentialResponse = await res.json();
+
+    modals.open({
+      title: "New password",
+      size: "lg",
+      children: (
+        <Stack>
+          <CopyableCode label="Username">{rotated.username}</CopyableCode>
+          <CopyableCode label="Password">{rotated.cleartext_password}</CopyableCode>
+          <Alert variant="light" title="Save this password somewhere safe!" icon={<IconInfoCircle />}>
+            This password will only be shown once. After you closed this window, we cannot show it again.
+          </Alert>
+        </Stack>
+      ),
+    });
+  };
+
   const save = async (values: FormValues) => {
     const res = await fetch(
       `/api/organizations/${currentOrganization.id}/projects/${currentProject.id}/smtp_credentials/${currentCredential.id}`,
@@ -80,7 +132,12 @@ export default function CredentialDetails() {
         headers: {
           "Content-Type": "application/json",
         },
-        body: JSON.stringify(values),
+        // an empty list means the credential is not restricted
+        body: JSON.stringify({
+          description: values.description,
+          allowed_recipient_domains: values.allowed_recipient_domains.length ? values.allowed_recipient_domains : null,
+          allowed_sender_addresses: values.allowed_sender_addresses.length ? values.allowed_sender_addresses : null,
+        }),
       }
     );
     if (res.status !== 200) {
@@ -115,7 +172,23 @@ export default function CredentialDetails() {
               value={form.values.description}
               onChange={(event) => form.setFieldValue("description", event.currentTarget.value)}
             />
-            <Tooltip label="The password cannot be shown or changed. Please create a new credential if needed and possibly delete this one.">
+            <TagsInput
+              label="Allowed recipient domains"
+              description="Only allow sending to these domains, leave empty to allow any recipient"
+              placeholder="example.com"
+              key={form.key("allowed_recipient_domains")}
+              value={form.values.allowed_recipient_domains}
+              onChange={(value) => form.setFieldValue("allowed_recipient_domains", value)}
+            />
+            <TagsInput
+              label="Allowed sender addresses"
+              description="Only allow sending from these addresses, leave empty to allow any sender"
+              placeholder="noreply@example.com"
+              key={form.key("allowed_sender_addresses")}
+              value={form.values.allowed_sender_addresses}
+              onChange={(value) => form.setFieldValue("allowed_sender_addresses", value)}
+            />
+            <Tooltip label="The password cannot be shown. Rotate it to generate a new password for this credential.">
               <TextInput label="Password" value="••••••••" readOnly variant="filled" />
             </Tooltip>
             <Group>
@@ -127,6 +200,14 @@ export default function CredentialDetails() {
               >
                 Delete
               </MaintainerButton>
+              <MaintainerButton
+                leftSection={<IconRefresh />}
+                variant="outline"
+                onClick={() => confirmRotateCredential(currentCredential)}
+                tooltip="Generate a new password for this SMTP credential"
+              >
+                Rotate password
+              </MaintainerButton>
               <MaintainerButton type="submit" disabled={!form.isDirty()} loading={form.submitting}>
                 Save
               </MaintainerButton>
@@ -4,7 +4,7 @@ import { useProjects } from "../../hooks/useProjects.ts";
 import { useRemails } from "../../hooks/useRemails.ts";
 import { SmtpCredentialResponse } from "../../types.ts";
 import { useForm } from "@mantine/form";
-import { Alert, Button, Group, Modal, Stack, Stepper, Textarea, TextInput, Title } from "@mantine/core";
+import { Alert, Button, Checkbox, Group, Modal, Stack, Stepper, Textarea, TextInput, Title } from "@mantine/core";
 import { IconInfoCircle } from "@tabler/icons-react";
 import { CopyableCode } from "../CopyableCode.tsx";
 import { SmtpInfo } from "./SmtpInfo.tsx";
@@ -13,6 +13,7 @@ import { errorNotification } from "../../notify.tsx";
 interface FormValues {
   username: string;
   description: string;
+  cram_md5: boolean;
 }
 
 interface NewCredentialProps {
@@ -32,6 +33,7 @@ export function NewCredential({ opened, close }: NewCredentialProps) {
     initialValues: {
       username: "",
       description: "",
+      cram_md5: false,
     },
     validate: {
       username: (value) =>
@@ -105,6 +107,13 @@ export function NewCredential({ opened, close }: NewCredentialProps) {
                   error={form.errors.description}
                   onChange={(event) => form.setFieldValue("description", event.currentTarget.value)}
                 />
+                <Checkbox
+                  label="Allow CRAM-MD5 authentication"
+                  description="Only enable this for clients that don't support other methods, as it requires storing a key that is equivalent to the password"
+                  key={form.key("cram_md5")}
+                  checked={form.values.cram_md5}
+                  onChange={(event) => form.setFieldValue("cram_md5", event.currentTarget.checked)}
+                />
                 <Group justify="space-between">
                   <Button onClick={close} variant="outline">
                     Cancel
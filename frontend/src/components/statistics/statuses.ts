@@ -5,6 +5,7 @@ export const ALL_EMAIL_STATUSES: EmailStatus[] = [
   "delivered",
   "failed",
   "held",
+  "needs_review",
   "processing",
   "reattempt",
   "rejected",
@@ -15,6 +16,7 @@ export const STATUS_SERIES: { name: EmailStatus; color: string }[] = [
   { name: "delivered", color: "teal.6" },
   { name: "failed", color: "red.6" },
   { name: "held", color: "orange.6" },
+  { name: "needs_review", color: "pink.6" },
   { name: "processing", color: "blue.6" },
   { name: "reattempt", color: "yellow.6" },
   { name: "rejected", color: "grape.6" },
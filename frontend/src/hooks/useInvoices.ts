@@ -0,0 +1,27 @@
+import { useEffect, useState } from "react";
+import { useOrganizations } from "./useOrganizations";
+import { Invoice } from "../types";
+import { errorNotification } from "../notify";
+
+export function useInvoices() {
+  const { currentOrganization } = useOrganizations();
+  const [invoices, setInvoices] = useState<Invoice[] | null>(null);
+
+  useEffect(() => {
+    if (currentOrganization) {
+      fetch(`/api/organizations/${currentOrganization.id}/subscription/invoices`)
+        .then((res) => {
+          if (res.status === 200) {
+            return res.json();
+          } else {
+            errorNotification("Failed to load the invoices");
+            console.error(res);
+            return null;
+          }
+        })
+        .then(setInvoices);
+    }
+  }, [currentOrganization]);
+
+  return { invoices };
+}
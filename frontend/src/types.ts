@@ -39,7 +39,9 @@ export type DeliveryStatus =
 
 export interface DeliveryDetails {
   status: DeliveryStatus;
+  bounce?: "soft" | "hard";
   log: Log;
+  transcript?: Transcript;
 }
 
 export interface DeliveryDetailsWithRecipient extends DeliveryDetails {
@@ -52,9 +54,27 @@ export interface Log {
     level: string;
     msg: string;
   }>;
+  dropped?: number;
 }
 
-export type EmailStatus = "processing" | "held" | "accepted" | "rejected" | "delivered" | "reattempt" | "failed";
+export interface Transcript {
+  lines: string[];
+  truncated?: boolean;
+}
+
+export type EmailStatus =
+  | "processing"
+  | "held"
+  | "accepted"
+  | "rejected"
+  | "delivered"
+  | "reattempt"
+  | "failed"
+  | "needs_review";
+
+export type HoldReason = "quota" | "configuration" | "spam";
+
+export type MessagePriority = "high" | "normal" | "low";
 
 export interface EmailMetadata {
   id: string;
@@ -63,6 +83,7 @@ export interface EmailMetadata {
   created_at: string;
   recipients: string[];
   status: EmailStatus;
+  hold_reason: HoldReason | undefined;
   reason: string | undefined;
   raw_size: string;
   message_id_header: string;
@@ -70,7 +91,12 @@ export interface EmailMetadata {
   retry_after: string | undefined;
   attempts: number;
   max_attempts: number;
+  expires_at: string | null;
+  unparseable: boolean;
+  client_ip: string | null;
+  correlation_id: string | null;
   label: string | undefined;
+  priority: MessagePriority;
 }
 
 export interface Email extends EmailMetadata {
@@ -101,6 +127,8 @@ export interface RemailsConfig {
   spf_include: string;
   dkim_selector: string;
   moneybird_administration_id: string;
+  invite_expiry_days: number;
+  registration_email_domains: string[];
 }
 
 export interface RuntimeConfig {
@@ -275,7 +303,7 @@ export type Action =
 
 export type OrgBlockStatus = "not_blocked" | "no_sending" | "no_sending_or_receiving" | "full_freeze";
 
-export type PasswordResetState = "NotActive" | "ActiveWithout2Fa" | "ActiveWith2Fa";
+export type PasswordResetState = "NotActive" | "ActiveWithout2Fa" | "ActiveWith2Fa" | "ActiveWithPasskey";
 
 export interface Organization {
   id: string;
@@ -288,13 +316,27 @@ export interface Organization {
   created_at: string;
   updated_at: string;
   block_status: OrgBlockStatus;
+  sending_paused: boolean;
 }
 
+export type UnauthorizedDomainPolicy = "hold" | "reject";
+
 export interface Project {
   id: string;
   name: string;
   retention_period_days: number;
   plaintext_fallback: boolean;
+  verp: boolean;
+  dedup_window_minutes: number | null;
+  max_automatic_retries: number | null;
+  max_message_age_minutes: number | null;
+  default_from_email: string | null;
+  default_from_name: string | null;
+  message_quota: number | null;
+  used_message_quota: number;
+  unauthorized_domain_policy: UnauthorizedDomainPolicy;
+  rewrite_unaligned_from: boolean;
+  review_exhausted_messages: boolean;
   created_at: string;
   updated_at: string;
 }
@@ -322,6 +364,8 @@ export interface Domain {
   domain: string;
   dkim_key_type: "rsa_sha265" | "ed25519";
   dkim_public_key: string;
+  dkim_signed_headers: string[];
+  dkim_canonicalization: "relaxed" | "simple";
   verification_status: DomainVerificationResult | null;
   created_at: string;
   updated_at: string;
@@ -332,6 +376,8 @@ export interface SmtpCredential {
   project_id: string;
   description: string;
   username: string;
+  allowed_recipient_domains: string[] | null;
+  allowed_sender_addresses: string[] | null;
   created_at: string;
   updated_at: string;
 }
@@ -378,6 +424,25 @@ export interface Subscription {
   sales_invoices_url: string;
 }
 
+export type InvoiceStatus =
+  | "draft"
+  | "open"
+  | "scheduled"
+  | "pending_payment"
+  | "late"
+  | "reminded"
+  | "paid"
+  | "uncollectible";
+
+export interface Invoice {
+  invoice_number: string;
+  date: string;
+  amount: string;
+  currency: string;
+  status: InvoiceStatus;
+  url: string;
+}
+
 export type Invite = {
   id: string;
   organization_id: string;
@@ -387,6 +452,7 @@ export type Invite = {
   created_by_name: string;
   created_at: string;
   expires_at: string;
+  email: string | null;
 };
 
 export type CreatedInvite = {
@@ -0,0 +1,2 @@
+-- NULL means the default set of signed headers is used
+ALTER TABLE domains ADD dkim_signed_headers text[];
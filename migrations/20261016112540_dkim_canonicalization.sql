@@ -0,0 +1,6 @@
+CREATE TYPE dkim_canonicalization AS ENUM (
+    'relaxed',
+    'simple'
+);
+
+ALTER TABLE domains ADD dkim_canonicalization dkim_canonicalization NOT NULL DEFAULT 'relaxed';
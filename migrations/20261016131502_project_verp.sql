@@ -0,0 +1,2 @@
+ALTER TABLE projects
+ADD COLUMN verp BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,8 @@
+CREATE TYPE hold_reason AS ENUM ('quota', 'configuration');
+
+ALTER TABLE messages
+    ADD COLUMN hold_reason hold_reason;
+
+UPDATE messages
+SET hold_reason = CASE WHEN reason = 'Quota exceeded' THEN 'quota'::hold_reason ELSE 'configuration'::hold_reason END
+WHERE status = 'held';
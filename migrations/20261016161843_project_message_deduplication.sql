@@ -0,0 +1,4 @@
+ALTER TABLE projects
+ADD COLUMN dedup_window_minutes INTEGER;
+
+CREATE INDEX messages_project_id_message_id_header ON messages (project_id, message_id_header);
@@ -0,0 +1,12 @@
+CREATE TABLE usage_snapshots
+(
+    id                  uuid        PRIMARY KEY DEFAULT gen_random_uuid(),
+    organization_id     uuid        NOT NULL REFERENCES organizations (id) ON DELETE CASCADE,
+    period_end          timestamptz,
+    used_message_quota  bigint      NOT NULL,
+    total_message_quota bigint      NOT NULL,
+    created_at          timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX usage_snapshots_organization_created_at_idx
+    ON usage_snapshots (organization_id, created_at DESC);
@@ -0,0 +1,2 @@
+ALTER TABLE organization_invites
+ADD COLUMN last_sent_at timestamptz;
@@ -0,0 +1,6 @@
+ALTER TABLE projects
+ADD COLUMN max_automatic_retries INTEGER,
+ADD COLUMN max_message_age_minutes INTEGER;
+
+ALTER TABLE messages
+ADD COLUMN expires_at TIMESTAMPTZ;
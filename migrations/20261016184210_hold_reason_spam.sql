@@ -0,0 +1 @@
+ALTER TYPE hold_reason ADD VALUE 'spam';
@@ -0,0 +1,2 @@
+ALTER TABLE messages
+ADD COLUMN unparseable BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,2 @@
+ALTER TABLE organizations
+ADD COLUMN sending_paused BOOLEAN NOT NULL DEFAULT false;
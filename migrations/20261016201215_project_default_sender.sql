@@ -0,0 +1,3 @@
+ALTER TABLE projects
+ADD COLUMN default_from_email TEXT,
+ADD COLUMN default_from_name TEXT;
@@ -0,0 +1,10 @@
+CREATE TABLE webhooks
+(
+    organization_id         uuid PRIMARY KEY REFERENCES organizations (id) ON DELETE CASCADE,
+    url                     text        NOT NULL,
+    signing_secret          text        NOT NULL,
+    previous_signing_secret text,
+    secret_rotated_at       timestamptz,
+    created_at              timestamptz NOT NULL DEFAULT now(),
+    updated_at              timestamptz NOT NULL DEFAULT now()
+);
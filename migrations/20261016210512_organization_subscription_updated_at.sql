@@ -0,0 +1,2 @@
+ALTER TABLE organizations
+ADD COLUMN subscription_updated_at TIMESTAMPTZ;
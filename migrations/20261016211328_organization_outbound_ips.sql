@@ -0,0 +1,8 @@
+-- Outbound IPs dedicated to a single organization, these are not used for any other organization
+CREATE TABLE organization_outbound_ips
+(
+    outbound_ip_id  uuid NOT NULL PRIMARY KEY REFERENCES outbound_ips (id) ON DELETE CASCADE,
+    organization_id uuid NOT NULL REFERENCES organizations (id) ON DELETE CASCADE
+);
+
+CREATE INDEX organization_outbound_ips_organization_id ON organization_outbound_ips (organization_id);
@@ -0,0 +1,4 @@
+CREATE TYPE message_priority AS ENUM ('high', 'normal', 'low');
+
+ALTER TABLE messages
+    ADD COLUMN priority message_priority NOT NULL DEFAULT 'high';
@@ -0,0 +1,5 @@
+-- deleted messages are hidden and can be restored, until their data is purged
+ALTER TABLE messages
+    ADD COLUMN deleted_at timestamptz;
+
+CREATE INDEX messages_deleted_at_idx ON messages (deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,28 @@
+CREATE TYPE organization_export_status AS ENUM (
+    'pending',
+    'ready',
+    'failed'
+);
+
+-- Archives with all data of an organization, generated on request of an organization admin
+CREATE TABLE organization_exports
+(
+    id              uuid                       PRIMARY KEY DEFAULT gen_random_uuid(),
+    organization_id uuid                       NOT NULL REFERENCES organizations (id) ON DELETE CASCADE,
+    requested_by    uuid                       REFERENCES api_users (id) ON DELETE SET NULL,
+    status          organization_export_status NOT NULL DEFAULT 'pending',
+    created_at      timestamptz                NOT NULL DEFAULT now(),
+    expires_at      timestamptz                NOT NULL
+);
+
+CREATE INDEX organization_exports_organization_id_idx ON organization_exports (organization_id);
+
+-- Archives are stored in chunks, such that they can be written and downloaded without
+-- holding the whole archive in memory
+CREATE TABLE organization_export_chunks
+(
+    export_id uuid    NOT NULL REFERENCES organization_exports (id) ON DELETE CASCADE,
+    position  integer NOT NULL,
+    data      bytea   NOT NULL,
+    PRIMARY KEY (export_id, position)
+);
@@ -0,0 +1,5 @@
+-- Optional ceiling on the part of the organization's message quota a project may use,
+-- such that a single project can't exhaust the quota of the whole organization
+ALTER TABLE projects
+    ADD COLUMN message_quota      bigint,
+    ADD COLUMN used_message_quota bigint NOT NULL DEFAULT 0;
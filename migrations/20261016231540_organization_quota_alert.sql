@@ -0,0 +1,4 @@
+-- Highest quota alert threshold, in percent of the message quota, the organization has been
+-- notified about in the current quota period
+ALTER TABLE organizations
+    ADD COLUMN quota_alert_threshold integer NOT NULL DEFAULT 0;
@@ -0,0 +1,5 @@
+ALTER TABLE smtp_credentials
+    -- the key for CRAM-MD5, which is equivalent to the password, so it is only stored on request
+    ADD COLUMN cram_md5_secret       bytea,
+    -- salted verifier in the format of RFC 5803
+    ADD COLUMN scram_sha256_verifier text;
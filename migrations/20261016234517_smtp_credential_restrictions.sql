@@ -0,0 +1,4 @@
+-- NULL means a credential is not restricted
+ALTER TABLE smtp_credentials
+    ADD COLUMN allowed_recipient_domains text[],
+    ADD COLUMN allowed_sender_addresses  text[];
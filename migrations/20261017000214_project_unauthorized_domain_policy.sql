@@ -0,0 +1,5 @@
+-- What to do with messages sent from a domain the project is not permitted to use
+CREATE TYPE unauthorized_domain_policy AS ENUM ('hold', 'reject');
+
+ALTER TABLE projects
+    ADD COLUMN unauthorized_domain_policy unauthorized_domain_policy NOT NULL DEFAULT 'hold';
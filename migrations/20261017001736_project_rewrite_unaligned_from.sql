@@ -0,0 +1,3 @@
+-- Opt-in rewriting of From headers outside the project's domains, instead of rejecting the message
+ALTER TABLE projects
+    ADD COLUMN rewrite_unaligned_from boolean NOT NULL DEFAULT false;
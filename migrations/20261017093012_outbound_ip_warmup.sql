@@ -0,0 +1,14 @@
+-- IPs added from now on start warming up, existing IPs are considered warm (NULL)
+ALTER TABLE outbound_ips
+    ADD COLUMN warmup_started_at timestamptz;
+ALTER TABLE outbound_ips
+    ALTER COLUMN warmup_started_at SET DEFAULT now();
+
+-- Number of messages sent from an outbound IP per hour, only tracked while the IP is warming up
+CREATE TABLE outbound_ip_hourly_usage
+(
+    outbound_ip_id uuid        NOT NULL REFERENCES outbound_ips (id) ON DELETE CASCADE,
+    hour           timestamptz NOT NULL,
+    sent           integer     NOT NULL,
+    PRIMARY KEY (outbound_ip_id, hour)
+);
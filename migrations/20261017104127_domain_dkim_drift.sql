@@ -0,0 +1,5 @@
+-- Set when the DKIM record of a domain that verified successfully no longer matches its key,
+-- cleared once it matches again
+ALTER TABLE domains
+    ADD COLUMN dkim_drifted_at     timestamptz,
+    ADD COLUMN dkim_drift_notified boolean NOT NULL DEFAULT false;
@@ -0,0 +1,8 @@
+-- Messages that exhausted their automatic retries can be put up for review instead of failing
+ALTER TYPE message_status ADD VALUE 'needs_review';
+
+-- Opt-in per project, copied to each message when it is created
+ALTER TABLE projects
+    ADD COLUMN review_exhausted_messages boolean NOT NULL DEFAULT false;
+ALTER TABLE messages
+    ADD COLUMN review_when_exhausted boolean NOT NULL DEFAULT false;
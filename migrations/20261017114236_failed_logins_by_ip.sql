@@ -0,0 +1,7 @@
+-- Failed password logins per client IP address, across all accounts
+CREATE TABLE failed_logins_by_ip
+(
+    ip       inet        PRIMARY KEY,
+    failures integer     NOT NULL,
+    reset_at timestamptz NOT NULL
+);
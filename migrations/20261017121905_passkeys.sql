@@ -0,0 +1,23 @@
+CREATE TABLE passkeys
+(
+    id            uuid PRIMARY KEY,
+    description   text        NOT NULL CHECK ( char_length(description) <= 100 ),
+    user_id       uuid        NOT NULL REFERENCES api_users (id) ON DELETE CASCADE,
+    credential_id bytea       NOT NULL UNIQUE,
+    passkey       jsonb       NOT NULL,
+    last_used     timestamptz,
+    created_at    timestamptz NOT NULL DEFAULT now(),
+    updated_at    timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE TRIGGER update_passkeys_updated_at
+    BEFORE UPDATE
+    ON passkeys
+    FOR EACH ROW
+EXECUTE PROCEDURE update_updated_at_column();
+
+-- State of the registration and authentication ceremonies in progress, which the browser
+-- completes by signing the challenge with the passkey
+ALTER TABLE api_users
+    ADD passkey_registration   jsonb,
+    ADD passkey_authentication jsonb;
@@ -0,0 +1,9 @@
+-- Message-ID header of messages in projects with deduplication enabled,
+-- cleared once the message leaves the deduplication window or is removed
+ALTER TABLE messages
+ADD COLUMN dedup_key varchar;
+
+CREATE UNIQUE INDEX messages_project_id_dedup_key ON messages (project_id, dedup_key)
+WHERE dedup_key IS NOT NULL;
+
+DROP INDEX messages_project_id_message_id_header;
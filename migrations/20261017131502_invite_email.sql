@@ -0,0 +1,3 @@
+-- address the invite link was emailed to, later resends go to the same address
+ALTER TABLE organization_invites
+ADD COLUMN email varchar;
@@ -0,0 +1,3 @@
+ALTER TABLE organizations
+    ADD COLUMN quota_reset_time     time,
+    ADD COLUMN quota_reset_timezone varchar;
@@ -0,0 +1,24 @@
+-- Scale down the project quotas when the message quota of their organization decreases below
+-- their total, such that the project quotas together never exceed the organization's quota
+CREATE FUNCTION clamp_project_message_quotas()
+    RETURNS TRIGGER AS
+$$
+BEGIN
+    UPDATE projects p
+    SET message_quota = p.message_quota * NEW.total_message_quota / allocated.total
+    FROM (SELECT SUM(message_quota) AS total
+          FROM projects
+          WHERE organization_id = NEW.id) allocated
+    WHERE p.organization_id = NEW.id
+      AND p.message_quota IS NOT NULL
+      AND allocated.total > NEW.total_message_quota;
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+CREATE TRIGGER clamp_project_message_quotas
+    AFTER UPDATE OF total_message_quota
+    ON organizations
+    FOR EACH ROW
+    WHEN (NEW.total_message_quota < OLD.total_message_quota)
+EXECUTE PROCEDURE clamp_project_message_quotas();
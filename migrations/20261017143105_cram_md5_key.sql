@@ -0,0 +1,6 @@
+-- CRAM-MD5 secrets used to be the password itself, they are now the HMAC-MD5 inner and outer
+-- states derived from it. The old secrets can't be converted, so those credentials have to be
+-- rotated to use CRAM-MD5 again.
+UPDATE smtp_credentials
+SET cram_md5_secret = NULL
+WHERE octet_length(cram_md5_secret) <> 32;
@@ -0,0 +1,8 @@
+-- Failed password logins per account, from any IP address.
+-- Keyed by the attempted email address, such that unknown accounts are throttled the same way.
+CREATE TABLE failed_logins_by_account
+(
+    email    text        PRIMARY KEY,
+    failures integer     NOT NULL,
+    reset_at timestamptz NOT NULL
+);
@@ -0,0 +1,6 @@
+-- The delivery backlog only consists of messages in a non-terminal state,
+-- which are few compared to all delivered and failed messages
+CREATE INDEX messages_non_terminal_status_idx
+    ON messages (status, created_at)
+    WHERE status IN ('accepted', 'processing', 'held', 'reattempt', 'needs_review')
+      AND deleted_at IS NULL;
@@ -0,0 +1,5 @@
+-- The raw data is streamed in chunks using substring, which only reads the requested part
+-- of uncompressed values. Compressed values are decompressed as a whole for every chunk.
+-- This applies to newly stored data, existing messages are still streamed correctly.
+ALTER TABLE messages
+    ALTER COLUMN raw_data SET STORAGE EXTERNAL;
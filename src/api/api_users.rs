@@ -1,14 +1,16 @@
 use crate::{
     api::{
         ApiState,
+        auth::PasskeyLoginChallenge,
         error::{ApiResult, AppError},
+        pwned_passwords::PwnedPasswords,
         validation::ValidatedJson,
         whoami::{Whoami, WhoamiResponse},
     },
     models::{
-        ApiUser, ApiUserId, ApiUserRepository, ApiUserUpdate, Error, ManageApiUser, Password,
-        PasswordUpdate, PwResetId, ResetLinkCheck, TotpCode, TotpCodeDetails, TotpFinishEnroll,
-        TotpId,
+        ApiUser, ApiUserId, ApiUserRepository, ApiUserUpdate, Error, ManageApiUser, PasskeyDetails,
+        PasskeyFinishEnroll, PasskeyId, Password, PasswordUpdate, PwResetId, ResetLinkCheck,
+        TotpCode, TotpCodeDetails, TotpFinishEnroll, TotpId,
     },
 };
 use axum::{
@@ -18,10 +20,12 @@ use axum::{
 };
 use garde::Validate;
 use http::header;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use tracing::{debug, info};
 use utoipa::ToSchema;
 use utoipa_axum::{router::OpenApiRouter, routes};
+use webauthn_rs::prelude::{CreationChallengeResponse, PublicKeyCredential, Webauthn};
 
 pub fn router() -> OpenApiRouter<ApiState> {
     OpenApiRouter::new()
@@ -30,9 +34,13 @@ pub fn router() -> OpenApiRouter<ApiState> {
         .routes(routes!(get_all, delete_user))
         .routes(routes!(is_password_reset_active))
         .routes(routes!(password_reset))
+        .routes(routes!(start_password_reset_passkey))
         .routes(routes!(update_password, delete_password))
         .routes(routes!(start_enroll_totp, finish_enroll_totp))
         .routes(routes!(totp_codes, delete_totp_code))
+        .routes(routes!(start_enroll_passkey, finish_enroll_passkey))
+        .routes(routes!(passkeys))
+        .routes(routes!(delete_passkey))
 }
 
 fn has_read_access(user_id: ApiUserId, user: &ApiUser) -> Result<(), AppError> {
@@ -175,11 +183,15 @@ pub async fn update_user(
 ))]
 pub async fn update_password(
     State(repo): State<ApiUserRepository>,
+    State(pwned_passwords): State<PwnedPasswords>,
     Path((user_id,)): Path<(ApiUserId,)>,
     user: ApiUser,
     ValidatedJson(update): ValidatedJson<PasswordUpdate>,
 ) -> Result<(), AppError> {
     has_write_access(user_id, &user)?;
+    pwned_passwords
+        .reject_breached(&update.new_password)
+        .await?;
 
     repo.update_password(update, &user_id).await?;
 
@@ -217,6 +229,37 @@ pub async fn is_password_reset_active(
     Ok(Json(active))
 }
 
+#[derive(Deserialize, Validate, ToSchema)]
+struct PasswordResetSecret {
+    #[garde(dive)]
+    reset_secret: Password,
+}
+
+/// Start passkey password reset
+///
+/// Returns the challenge to sign with one of the passkeys of the user, which has to be sent
+/// along with the new password to POST `/login/password/reset/{pw_reset_id}` afterward
+#[utoipa::path(post, path = "/login/password/reset/{pw_reset_id}/passkey",
+    tags = ["internal", "API users"],
+    request_body = PasswordResetSecret,
+    security(()),
+    responses(
+        (status = 200, description = "Successfully started passkey password reset", body = PasskeyLoginChallenge),
+        AppError,
+))]
+async fn start_password_reset_passkey(
+    State(repo): State<ApiUserRepository>,
+    State(webauthn): State<Arc<Webauthn>>,
+    Path((pw_reset_id,)): Path<(PwResetId,)>,
+    ValidatedJson(req): ValidatedJson<PasswordResetSecret>,
+) -> ApiResult<PasskeyLoginChallenge> {
+    let challenge = repo
+        .start_password_reset_passkey(&webauthn, pw_reset_id, &req.reset_secret)
+        .await?;
+
+    Ok(Json(PasskeyLoginChallenge(challenge)))
+}
+
 #[derive(Deserialize, Validate, ToSchema)]
 struct PasswordReset {
     #[garde(dive)]
@@ -225,12 +268,18 @@ struct PasswordReset {
     new_password: Password,
     #[garde(dive)]
     totp_code: Option<TotpCode>,
+    /// The response of the browser to the challenge of POST
+    /// `/login/password/reset/{pw_reset_id}/passkey`
+    #[garde(skip)]
+    #[schema(value_type = Option<Object>)]
+    passkey_credential: Option<PublicKeyCredential>,
 }
 
 /// Reset password
 ///
 /// Set new password using the password reset secret that was sent by mail.
-/// If the user has 2FA activated, they must also provide a valid TOTP code
+/// If the user has a passkey, they must also sign a passkey challenge, otherwise, if the user
+/// has TOTP activated, they must also provide a valid TOTP code
 #[utoipa::path(post, path = "/login/password/reset/{pw_reset_id}",
     tags = ["internal", "API users"],
     request_body = PasswordReset,
@@ -241,14 +290,23 @@ struct PasswordReset {
 ))]
 async fn password_reset(
     State(repo): State<ApiUserRepository>,
+    State(pwned_passwords): State<PwnedPasswords>,
+    State(webauthn): State<Arc<Webauthn>>,
     Path((pw_reset_id,)): Path<(PwResetId,)>,
     ValidatedJson(req): ValidatedJson<PasswordReset>,
 ) -> Result<(), AppError> {
+    // Only look up the new password once the reset link turned out to be valid
+    repo.verify_password_reset(pw_reset_id, &req.reset_secret)
+        .await?;
+    pwned_passwords.reject_breached(&req.new_password).await?;
+
     repo.finish_password_reset(
+        &webauthn,
         pw_reset_id,
         req.reset_secret,
         req.new_password,
         req.totp_code,
+        req.passkey_credential.as_ref(),
     )
     .await?;
 
@@ -384,6 +442,124 @@ pub async fn delete_totp_code(
     Ok(Json(id))
 }
 
+/// Challenge to pass to `navigator.credentials.create()` to enroll a passkey
+#[derive(Serialize, ToSchema)]
+#[serde(transparent)]
+pub struct PasskeyEnrollChallenge(#[schema(value_type = Object)] CreationChallengeResponse);
+
+/// Start the passkey enrollment process
+///
+/// Returns the challenge to create a new passkey with.
+/// To finish enrolling, call POST `/api_user/{user_id}/passkeys/enroll` afterward.
+#[utoipa::path(get, path = "/api_user/{user_id}/passkeys/enroll",
+    tags = ["internal", "API users"],
+    responses(
+        (status = 200, description = "Passkey enrollment successfully started", body = PasskeyEnrollChallenge),
+        AppError,
+))]
+pub async fn start_enroll_passkey(
+    State(repo): State<ApiUserRepository>,
+    State(webauthn): State<Arc<Webauthn>>,
+    Path((user_id,)): Path<(ApiUserId,)>,
+    user: ApiUser,
+) -> ApiResult<PasskeyEnrollChallenge> {
+    has_write_access(user_id, &user)?;
+
+    let challenge = repo.start_enroll_passkey(&webauthn, &user_id).await?;
+
+    info!(
+        user_id = user_id.to_string(),
+        executing_user_id = user.id().to_string(),
+        "started enrolling passkey"
+    );
+
+    Ok(Json(PasskeyEnrollChallenge(challenge)))
+}
+
+/// Finish the passkey enrollment
+///
+/// Send the passkey created for the challenge together with an optional description
+#[utoipa::path(post, path = "/api_user/{user_id}/passkeys/enroll",
+    request_body = PasskeyFinishEnroll,
+    tags = ["internal", "API users"],
+    responses(
+        (status = 200, description = "Passkey enrollment finished", body = PasskeyDetails),
+        AppError,
+))]
+pub async fn finish_enroll_passkey(
+    State(repo): State<ApiUserRepository>,
+    State(webauthn): State<Arc<Webauthn>>,
+    Path((user_id,)): Path<(ApiUserId,)>,
+    user: ApiUser,
+    ValidatedJson(finish): ValidatedJson<PasskeyFinishEnroll>,
+) -> ApiResult<PasskeyDetails> {
+    has_write_access(user_id, &user)?;
+
+    let passkey = repo
+        .finish_enroll_passkey(&webauthn, &user_id, finish)
+        .await?;
+
+    info!(
+        user_id = user_id.to_string(),
+        executing_user_id = user.id().to_string(),
+        passkey_id = passkey.id().to_string(),
+        "finished enrolling passkey"
+    );
+
+    Ok(Json(passkey))
+}
+
+/// List passkeys
+#[utoipa::path(get, path = "/api_user/{user_id}/passkeys",
+    tags = ["internal", "API users"],
+    responses(
+        (status = 200, description = "Successfully fetched passkeys", body = [PasskeyDetails]),
+        AppError,
+))]
+pub async fn passkeys(
+    State(repo): State<ApiUserRepository>,
+    Path((user_id,)): Path<(ApiUserId,)>,
+    user: ApiUser,
+) -> ApiResult<Vec<PasskeyDetails>> {
+    has_read_access(user_id, &user)?;
+
+    let passkeys = repo.passkeys(&user_id).await?;
+
+    debug!(
+        user_id = user_id.to_string(),
+        executing_user_id = user.id().to_string(),
+        "retrieved passkeys"
+    );
+
+    Ok(Json(passkeys))
+}
+
+/// Delete passkey
+#[utoipa::path(delete, path = "/api_user/{user_id}/passkeys/{passkey_id}",
+    tags = ["internal", "API users"],
+    responses(
+        (status = 200, description = "Successfully deleted passkey", body = PasskeyId),
+        AppError,
+))]
+pub async fn delete_passkey(
+    State(repo): State<ApiUserRepository>,
+    Path((user_id, passkey_id)): Path<(ApiUserId, PasskeyId)>,
+    user: ApiUser,
+) -> ApiResult<PasskeyId> {
+    has_write_access(user_id, &user)?;
+
+    let id = repo.delete_passkey(&user_id, &passkey_id).await?;
+
+    info!(
+        user_id = user_id.to_string(),
+        executing_user_id = user.id().to_string(),
+        passkey_id = id.to_string(),
+        "deleted passkey"
+    );
+
+    Ok(Json(id))
+}
+
 #[derive(serde::Deserialize, ToSchema, Validate)]
 #[serde(deny_unknown_fields)]
 pub struct CurrentPassword {
@@ -434,7 +610,7 @@ mod tests {
     use super::*;
     use crate::{
         api::{
-            auth::tests::get_session_cookie,
+            auth::{tests::get_session_cookie, webauthn_origin},
             tests::{TestServer, deserialize_body, serialize_body},
             whoami::Whoami,
         },
@@ -446,6 +622,8 @@ mod tests {
     use regex::Regex;
     use serde_json::json;
     use sqlx::PgPool;
+    use webauthn_authenticator_rs::{WebauthnAuthenticator, softpasskey::SoftPasskey};
+    use webauthn_rs::prelude::RequestChallengeResponse;
 
     impl Whoami {
         fn unwrap_email(&self) -> &str {
@@ -518,7 +696,7 @@ mod tests {
                 // we use json directly here because we don't allow serializing passwords
                 serialize_body(json!({
                     "current_password": "unsecure123",
-                    "new_password": "new-unsecure-password",
+                    "new_password": "new-Horse-7-battery-Staple",
                 })),
             )
             .await
@@ -538,7 +716,7 @@ mod tests {
         users
             .check_password(
                 &"updated-api@user-3".parse().unwrap(),
-                "new-unsecure-password".to_string().into(),
+                "new-Horse-7-battery-Staple".to_string().into(),
             )
             .await
             .unwrap();
@@ -547,7 +725,7 @@ mod tests {
         let response = server
             .delete_with_body(
                 format!("/api/api_user/{user_3}/password"),
-                serialize_body(json!({"current_password": "new-unsecure-password"})),
+                serialize_body(json!({"current_password": "new-Horse-7-battery-Staple"})),
             )
             .await
             .unwrap();
@@ -558,7 +736,7 @@ mod tests {
         let response = server
             .delete_with_body(
                 format!("/api/api_user/{user_3}/password"),
-                serialize_body(json!({"current_password": "new-unsecure-password"})),
+                serialize_body(json!({"current_password": "new-Horse-7-battery-Staple"})),
             )
             .await
             .unwrap();
@@ -614,7 +792,7 @@ mod tests {
                 format!("/api/api_user/{user_3}/password"),
                 serialize_body(json!({
                     "current_password": password,
-                    "new_password": "new-unsecure-password",
+                    "new_password": "new-Horse-7-battery-Staple",
                 })),
             )
             .await
@@ -757,7 +935,7 @@ mod tests {
             .post(
                 format!("/api/{reset_link}"),
                 serialize_body(json!({
-                    "new_password": "thisismynewpassword",
+                    "new_password": "reset-Horse-7-battery-Staple",
                     "reset_secret": "invalidsecret"
                     }
                 )),
@@ -771,7 +949,7 @@ mod tests {
             .post(
                 format!("/api/{reset_link}"),
                 serialize_body(json!({
-                    "new_password": "thisismynewpassword",
+                    "new_password": "reset-Horse-7-battery-Staple",
                     "reset_secret": reset_secret
                     }
                 )),
@@ -786,7 +964,7 @@ mod tests {
                 "/api/login/password",
                 serialize_body(json!({
                     "email": "test-api@user-2",
-                    "password": "thisismynewpassword"
+                    "password": "reset-Horse-7-battery-Staple"
                 })),
             )
             .await
@@ -853,7 +1031,7 @@ mod tests {
             .post(
                 format!("/api/{reset_link}"),
                 serialize_body(json!({
-                    "new_password": "thisismynewpassword",
+                    "new_password": "reset-Horse-7-battery-Staple",
                     "reset_secret": reset_secret,
                     }
                 )),
@@ -867,7 +1045,7 @@ mod tests {
             .post(
                 format!("/api/{reset_link}"),
                 serialize_body(json!({
-                    "new_password": "thisismynewpassword",
+                    "new_password": "reset-Horse-7-battery-Staple",
                     "reset_secret": reset_secret,
                     "totp_code": "123456"
                     }
@@ -896,7 +1074,7 @@ mod tests {
             .post(
                 format!("/api/{reset_link}"),
                 serialize_body(json!({
-                    "new_password": "thisismynewpassword",
+                    "new_password": "reset-Horse-7-battery-Staple",
                     "reset_secret": reset_secret,
                     "totp_code": totp_code
                     }
@@ -912,7 +1090,7 @@ mod tests {
                 "/api/login/password",
                 serialize_body(json!({
                     "email": "test-totp-rate-limit@user-4",
-                    "password": "thisismynewpassword"
+                    "password": "reset-Horse-7-battery-Staple"
                 })),
             )
             .await
@@ -921,6 +1099,153 @@ mod tests {
         let _ = get_session_cookie(response);
     }
 
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "api_users", "projects", "runtime_config",)
+    ))]
+    async fn test_password_reset_with_passkey(pool: PgPool) {
+        let mut server = TestServer::new(pool.clone(), None).await;
+        let origin = webauthn_origin(&server.api_state().config.remails_config);
+        let mut authenticator = WebauthnAuthenticator::new(SoftPasskey::new(true));
+
+        // Enroll a passkey
+        let response = server
+            .post(
+                "/api/login/password",
+                serialize_body(json!({
+                    "email": "test-totp@user-4",
+                    "password": "unsecure123"
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        server
+            .headers
+            .insert("Cookie", get_session_cookie(response));
+
+        let response = server
+            .get("/api/api_user/820128b1-e08f-404d-ad08-e679a7d6b515/passkeys/enroll")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let challenge: CreationChallengeResponse = deserialize_body(response.into_body()).await;
+        let credential = authenticator
+            .do_registration(origin.clone(), challenge)
+            .unwrap();
+        let response = server
+            .post(
+                "/api/api_user/820128b1-e08f-404d-ad08-e679a7d6b515/passkeys/enroll",
+                serialize_body(json!({
+                    "credential": credential,
+                    "description": "test passkey",
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        server.headers.remove("Cookie");
+
+        let res = server
+            .post(
+                "/api/login/password/reset",
+                serialize_body(json! {"test-totp@user-4"}),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let raw_data = sqlx::query_scalar!(
+            r#"
+            SELECT raw_data FROM messages WHERE recipients = '{"test-totp@user-4"}'
+            "#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+
+        let message = MessageParser::default().parse(&raw_data).unwrap();
+        let message = message.body_text(0).unwrap().to_string();
+
+        let regex = Regex::new(r#"https://[^/]*/([^\s#]*)#([^\s)]*)"#).unwrap();
+        let captures = regex.captures(message.as_str()).unwrap();
+        let reset_link = captures.get(1).unwrap().as_str();
+        let reset_secret = captures.get(2).unwrap().as_str();
+
+        // Check reset link status
+        let res = server.get(format!("/api/{reset_link}")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let status: ResetLinkCheck = deserialize_body(res.into_body()).await;
+        assert_eq!(status, ResetLinkCheck::ActiveWithPasskey);
+
+        // Make sure the passkey is required
+        let res = server
+            .post(
+                format!("/api/{reset_link}"),
+                serialize_body(json!({
+                    "new_password": "reset-Horse-7-battery-Staple",
+                    "reset_secret": reset_secret,
+                    }
+                )),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        // The challenge requires the reset secret
+        let res = server
+            .post(
+                format!("/api/{reset_link}/passkey"),
+                serialize_body(json!({
+                    "reset_secret": "invalidsecret"
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let res = server
+            .post(
+                format!("/api/{reset_link}/passkey"),
+                serialize_body(json!({
+                    "reset_secret": reset_secret
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let challenge: RequestChallengeResponse = deserialize_body(res.into_body()).await;
+        let credential = authenticator.do_authentication(origin, challenge).unwrap();
+
+        // Set new password
+        let res = server
+            .post(
+                format!("/api/{reset_link}"),
+                serialize_body(json!({
+                    "new_password": "reset-Horse-7-battery-Staple",
+                    "reset_secret": reset_secret,
+                    "passkey_credential": credential,
+                    }
+                )),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // Login with new password
+        let response = server
+            .post(
+                "/api/login/password",
+                serialize_body(json!({
+                    "email": "test-totp@user-4",
+                    "password": "reset-Horse-7-battery-Staple"
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
     #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "api_users")))]
     async fn test_get_all_api_users(pool: PgPool) {
         let admin: ApiUserId = "deadbeef-4e43-4a66-bbb9-fbcd4a933a34".parse().unwrap();
@@ -1,16 +1,21 @@
 use crate::{
-    api::{ApiState, error::AppError, validation::ValidatedJson, whoami::WhoamiResponse},
+    Environment,
+    api::{
+        ApiState, ClientIp, RemailsConfig, error::AppError, pwned_passwords::PwnedPasswords,
+        validation::ValidatedJson, whoami::WhoamiResponse,
+    },
     models::{
         Actor, ApiKey, ApiKeyRepository, ApiUser, ApiUserId, ApiUserRepository, NewApiUser,
         OrgBlockStatus, OrganizationId, OrganizationRepository, Password, Role,
-        RuntimeConfigRepository, TotpCode,
+        RuntimeConfigRepository, TotpCode, validate_password_strength,
     },
     system_emails::send_password_reset_email,
 };
 use axum::{
     Json,
-    extract::{FromRef, FromRequestParts, OptionalFromRequestParts, State},
-    http::{StatusCode, request::Parts},
+    extract::{FromRef, FromRequestParts, OptionalFromRequestParts, Request, State},
+    http::{StatusCode, header::SET_COOKIE, request::Parts},
+    middleware::Next,
     response::{IntoResponse, IntoResponseParts, Redirect, Response, ResponseParts},
 };
 #[cfg(not(test))]
@@ -22,14 +27,17 @@ use cookie::{Cookie, SameSite};
 use email_address::EmailAddress;
 use garde::Validate;
 use serde::{Deserialize, Serialize};
-use std::convert::Infallible;
 #[cfg(not(test))]
 use std::net::SocketAddr;
+use std::{convert::Infallible, sync::Arc};
 #[cfg(not(test))]
 use tracing::error;
 use tracing::{debug, trace, warn};
 use utoipa::ToSchema;
 use utoipa_axum::{router::OpenApiRouter, routes};
+use webauthn_rs::prelude::{
+    PublicKeyCredential, RequestChallengeResponse, Url, Webauthn, WebauthnBuilder,
+};
 
 // See https://developer.mozilla.org/en-US/docs/Web/HTTP/Guides/Cookies#cookie_prefixes
 #[cfg(not(debug_assertions))]
@@ -42,6 +50,7 @@ pub fn router() -> OpenApiRouter<ApiState> {
         .routes(routes!(password_reset_email))
         .routes(routes!(password_login))
         .routes(routes!(totp_login))
+        .routes(routes!(start_passkey_login, passkey_login))
         .routes(routes!(password_register))
         .routes(routes!(logout))
 }
@@ -162,6 +171,7 @@ impl Authenticated for ApiKey {
 
 pub(super) struct SecureCookieStorage {
     jar: PrivateCookieJar,
+    session_lifetime: SessionLifetime,
 }
 
 impl FromRequestParts<ApiState> for SecureCookieStorage {
@@ -173,7 +183,10 @@ impl FromRequestParts<ApiState> for SecureCookieStorage {
     ) -> Result<Self, Self::Rejection> {
         let jar = PrivateCookieJar::from_headers(&parts.headers, state.config.session_key.clone());
 
-        Ok(Self { jar })
+        Ok(Self {
+            jar,
+            session_lifetime: state.config.session_lifetime,
+        })
     }
 }
 
@@ -192,6 +205,7 @@ impl SecureCookieStorage {
     {
         Self {
             jar: self.jar.remove(cookie),
+            ..self
         }
     }
 
@@ -201,6 +215,7 @@ impl SecureCookieStorage {
     {
         Self {
             jar: self.jar.add(cookie),
+            ..self
         }
     }
 
@@ -215,11 +230,63 @@ pub enum LoginState {
     LoggedIn,
 }
 
+/// How long a session remains valid
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SessionLifetime {
+    /// Sessions expire this long after logging in, regardless of activity
+    pub absolute: Duration,
+    /// Sessions expire when they have not been used for this long
+    pub idle: Duration,
+}
+
+impl Default for SessionLifetime {
+    fn default() -> Self {
+        Self {
+            absolute: Duration::days(7),
+            idle: Duration::hours(24),
+        }
+    }
+}
+
+impl SessionLifetime {
+    /// Configure the lifetime using the `SESSION_LIFETIME_HOURS` and
+    /// `SESSION_IDLE_TIMEOUT_MINUTES` environment variables, defaults to 7 days and 24 hours
+    ///
+    /// Will panic if any of them is not a positive integer
+    #[cfg(not(test))]
+    pub fn from_env() -> Self {
+        fn positive_from_env(name: &str) -> Option<i64> {
+            std::env::var(name).ok().map(|value| {
+                value
+                    .parse::<std::num::NonZeroU32>()
+                    .unwrap_or_else(|_| panic!("{name} must be a positive integer"))
+                    .get()
+                    .into()
+            })
+        }
+
+        let default = Self::default();
+
+        Self {
+            absolute: positive_from_env("SESSION_LIFETIME_HOURS")
+                .map(Duration::hours)
+                .unwrap_or(default.absolute),
+            idle: positive_from_env("SESSION_IDLE_TIMEOUT_MINUTES")
+                .map(Duration::minutes)
+                .unwrap_or(default.idle),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct UserCookie {
     id: ApiUserId,
     state: LoginState,
     expires_at: DateTime<Utc>,
+    /// Cookies issued before the idle timeout was introduced don't have one, they get it on
+    /// their next refresh
+    #[serde(default)]
+    idle_expires_at: Option<DateTime<Utc>>,
 }
 
 impl UserCookie {
@@ -231,11 +298,88 @@ impl UserCookie {
         &self.expires_at
     }
 
-    fn from_api_user(user: &ApiUser, login_state: LoginState) -> Self {
+    /// Whether the session reached its absolute lifetime or has been idle for too long
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at < now || self.idle_expires_at.is_some_and(|idle| idle < now)
+    }
+
+    fn from_api_user(user: &ApiUser, login_state: LoginState, lifetime: SessionLifetime) -> Self {
+        let now = Utc::now();
+
         Self {
             id: *user.id(),
             state: login_state,
-            expires_at: Utc::now() + Duration::days(7),
+            expires_at: now + lifetime.absolute,
+            idle_expires_at: Some(now + lifetime.idle),
+        }
+    }
+
+    /// Restart the idle timeout, which never extends the session beyond its absolute lifetime
+    fn refreshed(self, lifetime: SessionLifetime) -> Self {
+        Self {
+            idle_expires_at: Some((Utc::now() + lifetime.idle).min(self.expires_at)),
+            ..self
+        }
+    }
+
+    /// The session cookie holding this user cookie, the browser drops it when the session
+    /// reaches its absolute lifetime
+    fn into_session_cookie(self) -> Result<Cookie<'static>, serde_json::Error> {
+        let max_age = (self.expires_at - Utc::now()).num_seconds().max(0);
+
+        // Serialize the user data as a string
+        let session_cookie_value = serde_json::to_string(&self)?;
+
+        // Create a new session cookie
+        let mut session_cookie = Cookie::new(SESSION_COOKIE_NAME, session_cookie_value);
+        session_cookie.set_http_only(true);
+        #[cfg(not(debug_assertions))]
+        session_cookie.set_secure(true);
+        session_cookie.set_same_site(SameSite::Lax);
+        session_cookie.set_max_age(cookie::time::Duration::seconds(max_age));
+        session_cookie.set_path("/");
+
+        Ok(session_cookie)
+    }
+}
+
+/// Restarts the idle timeout of valid sessions on every request, such that only sessions that
+/// are not in use expire before their absolute lifetime
+pub(super) async fn refresh_session(
+    State(state): State<ApiState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let jar = PrivateCookieJar::from_headers(req.headers(), state.config.session_key.clone());
+    let session = jar
+        .get(SESSION_COOKIE_NAME)
+        .and_then(|cookie| serde_json::from_str::<UserCookie>(cookie.value()).ok())
+        .filter(|session| !session.is_expired(Utc::now()));
+
+    let res = next.run(req).await;
+
+    let Some(session) = session else {
+        return res;
+    };
+
+    // logging in and out set the session cookie themselves
+    let sets_session_cookie = res.headers().get_all(SET_COOKIE).iter().any(|value| {
+        value
+            .to_str()
+            .is_ok_and(|value| value.starts_with(&format!("{SESSION_COOKIE_NAME}=")))
+    });
+    if sets_session_cookie || res.status() == StatusCode::UNAUTHORIZED {
+        return res;
+    }
+
+    match session
+        .refreshed(state.config.session_lifetime)
+        .into_session_cookie()
+    {
+        Ok(cookie) => (jar.add(cookie), res).into_response(),
+        Err(err) => {
+            warn!("Could not refresh session cookie: {err:?}");
+            res
         }
     }
 }
@@ -274,7 +418,10 @@ pub(super) struct PasswordLogin {
 ///
 /// Returns an authentication cookie.
 /// If the user configured 2FA, you have to call the corresponding path hereafter,
-/// currently only TOTP (`/login/totp`) is supported
+/// either TOTP (`/login/totp`) or a passkey (`/login/passkey`).
+/// After too many failed logins from the same IP address, across accounts, further logins from
+/// that address are refused for a while. Likewise, after too many failed logins for the same
+/// account, from any address, further password logins for that account are refused for a while.
 #[utoipa::path(post, path = "/login/password",
     tags = ["internal", "Auth"],
     security(()),
@@ -289,10 +436,29 @@ pub(super) struct PasswordLogin {
 pub(super) async fn password_login(
     State(repo): State<ApiUserRepository>,
     mut cookie_storage: SecureCookieStorage,
+    ClientIp(client_ip): ClientIp,
     ValidatedJson(login_attempt): ValidatedJson<PasswordLogin>,
 ) -> Result<Response, AppError> {
-    repo.check_password(&login_attempt.email, login_attempt.password)
+    if let Some(ip) = client_ip {
+        repo.check_failed_logins_by_ip(ip).await?;
+    }
+    repo.check_failed_logins_by_account(&login_attempt.email)
         .await?;
+
+    if let Err(err) = repo
+        .check_password(&login_attempt.email, login_attempt.password)
+        .await
+    {
+        if let crate::models::Error::NotFound(_) = &err {
+            if let Some(ip) = client_ip {
+                repo.record_failed_login(ip).await?;
+            }
+            repo.record_failed_login_by_account(&login_attempt.email)
+                .await?;
+        }
+        return Err(err.into());
+    }
+
     let user = repo
         .find_by_email(&login_attempt.email)
         .await?
@@ -356,6 +522,110 @@ pub(super) async fn totp_login(
         .into_response())
 }
 
+/// The origin of the Remails web interface, which passkeys are bound to
+pub(super) fn webauthn_origin(config: &RemailsConfig) -> Url {
+    let scheme = match config.environment {
+        Environment::Development => "http",
+        _ => "https",
+    };
+
+    Url::parse(&format!("{scheme}://{}", config.api_server_name))
+        .expect("API_SERVER_NAME must be a valid host name")
+}
+
+pub(super) fn webauthn(config: &RemailsConfig) -> Webauthn {
+    let origin = webauthn_origin(config);
+    let rp_id = origin
+        .host_str()
+        .expect("API_SERVER_NAME must be a valid host name");
+
+    WebauthnBuilder::new(rp_id, &origin)
+        .and_then(|builder| builder.rp_name("Remails").build())
+        .expect("cannot use API_SERVER_NAME for passkeys")
+}
+
+/// Challenge to pass to `navigator.credentials.get()` to log in with a passkey
+#[derive(Serialize, ToSchema)]
+#[serde(transparent)]
+pub(super) struct PasskeyLoginChallenge(
+    #[schema(value_type = Object)] pub(super) RequestChallengeResponse,
+);
+
+/// The response of the browser to the challenge of the passkey login, see
+/// `navigator.credentials.get()`
+#[derive(Deserialize, Validate, ToSchema)]
+#[serde(transparent)]
+pub(super) struct PasskeyCredential(
+    #[garde(skip)]
+    #[schema(value_type = Object)]
+    PublicKeyCredential,
+);
+
+/// Start passkey login
+///
+/// Second factor authentication with a passkey, as an alternative to TOTP.
+/// Returns the challenge to sign with one of the passkeys of the user, which has to be sent to
+/// POST `/login/passkey` afterward
+#[utoipa::path(get, path = "/login/passkey",
+    tags = ["internal", "Auth"],
+    security(("cookieAuth" = [])),
+    responses(
+        (status = 200, description = "Successfully started passkey login", body = PasskeyLoginChallenge),
+        AppError,
+))]
+pub(super) async fn start_passkey_login(
+    State(repo): State<ApiUserRepository>,
+    State(webauthn): State<Arc<Webauthn>>,
+    user: MfaPending,
+) -> Result<Json<PasskeyLoginChallenge>, AppError> {
+    let challenge = repo.start_passkey_login(&webauthn, &user.id()).await?;
+
+    Ok(Json(PasskeyLoginChallenge(challenge)))
+}
+
+/// Passkey login
+///
+/// Finishes the second factor authentication with a passkey.
+/// Returns an updated authentication cookie
+#[utoipa::path(post, path = "/login/passkey",
+    tags = ["internal", "Auth"],
+    request_body = PasskeyCredential,
+    security(("cookieAuth" = [])),
+    responses(
+        (status = 200, description = "Successfully logged in", body = WhoamiResponse,
+            headers(
+                ("set-cookie", description = "sets the authentication cookie")
+        )),
+        AppError,
+))]
+pub(super) async fn passkey_login(
+    State(repo): State<ApiUserRepository>,
+    State(webauthn): State<Arc<Webauthn>>,
+    mut cookie_storage: SecureCookieStorage,
+    user: MfaPending,
+    ValidatedJson(PasskeyCredential(credential)): ValidatedJson<PasskeyCredential>,
+) -> Result<Response, AppError> {
+    if !repo
+        .check_passkey(&webauthn, &user.id(), &credential)
+        .await?
+    {
+        return Ok((StatusCode::UNAUTHORIZED, Json(WhoamiResponse::MfaPending)).into_response());
+    }
+
+    let user = repo
+        .find_by_id(&user.id())
+        .await?
+        .ok_or(AppError::Unauthorized)?;
+    cookie_storage = login(&user, LoginState::LoggedIn, cookie_storage)?;
+
+    Ok((
+        StatusCode::OK,
+        cookie_storage,
+        Json(WhoamiResponse::logged_in(user)),
+    )
+        .into_response())
+}
+
 #[derive(Deserialize, Validate, ToSchema)]
 pub(super) struct PasswordRegister {
     #[garde(length(min = 1, max = 256))]
@@ -363,7 +633,7 @@ pub(super) struct PasswordRegister {
     name: String,
     #[garde(skip)]
     email: EmailAddress,
-    #[garde(dive)]
+    #[garde(dive, custom(validate_password_strength(&self.name, &self.email)))]
     #[schema(min_length = 10, max_length = 256)]
     password: Password,
 }
@@ -385,6 +655,8 @@ pub(super) struct PasswordRegister {
 pub(super) async fn password_register(
     State(repo): State<ApiUserRepository>,
     State(config_repo): State<RuntimeConfigRepository>,
+    State(remails_config): State<RemailsConfig>,
+    State(pwned_passwords): State<PwnedPasswords>,
     mut cookie_storage: SecureCookieStorage,
     ValidatedJson(register_attempt): ValidatedJson<PasswordRegister>,
 ) -> Result<Response, AppError> {
@@ -392,6 +664,14 @@ pub(super) async fn password_register(
         return Err(AppError::Forbidden);
     }
 
+    remails_config
+        .check_registration_domain(&register_attempt.email)
+        .map_err(AppError::BadRequest)?;
+
+    pwned_passwords
+        .reject_breached(&register_attempt.password)
+        .await?;
+
     let new = NewApiUser {
         email: register_attempt.email,
         name: register_attempt.name.trim().to_string(),
@@ -411,18 +691,9 @@ pub(super) fn login(
     login_state: LoginState,
     cookie_storage: SecureCookieStorage,
 ) -> Result<SecureCookieStorage, serde_json::Error> {
-    // Serialize the user data as a string
-    let cookie = UserCookie::from_api_user(user, login_state);
-    let session_cookie_value = serde_json::to_string(&cookie)?;
-
-    // Create a new session cookie
-    let mut session_cookie = Cookie::new(SESSION_COOKIE_NAME, session_cookie_value);
-    session_cookie.set_http_only(true);
-    #[cfg(not(debug_assertions))]
-    session_cookie.set_secure(true);
-    session_cookie.set_same_site(SameSite::Lax);
-    session_cookie.set_max_age(cookie::time::Duration::days(7));
-    session_cookie.set_path("/");
+    let session_cookie =
+        UserCookie::from_api_user(user, login_state, cookie_storage.session_lifetime)
+            .into_session_cookie()?;
 
     #[cfg(debug_assertions)]
     warn!(
@@ -486,7 +757,7 @@ where
 
         match serde_json::from_str::<UserCookie>(session_cookie.value()) {
             Ok(cookie) => {
-                if cookie.expires_at() < &Utc::now() {
+                if cookie.is_expired(Utc::now()) {
                     warn!(
                         user_id = cookie.id().to_string(),
                         "Received expired user cookie"
@@ -574,7 +845,7 @@ where
 
         match serde_json::from_str::<UserCookie>(session_cookie.value()) {
             Ok(user) => {
-                if user.expires_at() < &Utc::now() {
+                if user.is_expired(Utc::now()) {
                     warn!(
                         user_id = user.id().to_string(),
                         "Received expired user cookie"
@@ -718,12 +989,14 @@ pub mod tests {
     use super::*;
     use crate::{
         api::tests::{TestServer, deserialize_body, serialize_body},
-        models::{RuntimeConfig, TotpCodeDetails},
+        models::{PasskeyDetails, RuntimeConfig, TotpCodeDetails},
     };
     use axum::body::Body;
     use serde_json::json;
     use sqlx::PgPool;
     use totp_rs::TOTP;
+    use webauthn_authenticator_rs::{WebauthnAuthenticator, softpasskey::SoftPasskey};
+    use webauthn_rs::prelude::CreationChallengeResponse;
 
     pub fn get_session_cookie(response: Response<Body>) -> String {
         let cookies = response.headers().get_all("set-cookie");
@@ -751,7 +1024,7 @@ pub mod tests {
                 serialize_body(json!({
                     "name": "New User",
                     "email": "test-api@new-user",
-                    "password": "unsecure123"
+                    "password": "correct-Horse-7-battery-Staple"
                 })),
             )
             .await
@@ -781,7 +1054,7 @@ pub mod tests {
                 "/api/login/password",
                 serialize_body(json!({
                     "email": "test-api@new-user",
-                    "password": "unsecure123"
+                    "password": "correct-Horse-7-battery-Staple"
                 })),
             )
             .await
@@ -802,6 +1075,52 @@ pub mod tests {
         assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
     }
 
+    #[sqlx::test]
+    async fn test_register_weak_password(pool: PgPool) {
+        let server = TestServer::new(pool, None).await;
+
+        // long enough, but easy to guess
+        let response = server
+            .post(
+                "/api/register/password",
+                serialize_body(json!({
+                    "name": "New User",
+                    "email": "test-api@new-user",
+                    "password": "password1234567"
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // strong on its own, but based on the user's email address
+        let response = server
+            .post(
+                "/api/register/password",
+                serialize_body(json!({
+                    "name": "Xyqzvbl Wrtkmnp",
+                    "email": "xyqzvblwrtkmnp@new-user",
+                    "password": "xyqzvblwrtkmnp2024!"
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = server
+            .post(
+                "/api/register/password",
+                serialize_body(json!({
+                    "name": "New User",
+                    "email": "test-api@new-user",
+                    "password": "correct-Horse-7-battery-Staple"
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
     #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "api_users")))]
     async fn test_cannot_login_when_blocked(pool: PgPool) {
         let server = TestServer::new(pool.clone(), None).await;
@@ -812,7 +1131,7 @@ pub mod tests {
                 "/api/login/password",
                 serialize_body(json!({
                     "email": "test-api@blocked-user",
-                    "password": "unsecure123"
+                    "password": "correct-Horse-7-battery-Staple"
                 })),
             )
             .await
@@ -838,7 +1157,7 @@ pub mod tests {
                 serialize_body(json!({
                     "name": "New User",
                     "email": "test-api@new-user",
-                    "password": "unsecure123"
+                    "password": "correct-Horse-7-battery-Staple"
                 })),
             )
             .await
@@ -846,6 +1165,44 @@ pub mod tests {
         assert_eq!(response.status(), StatusCode::FORBIDDEN);
     }
 
+    #[sqlx::test]
+    async fn test_register_email_domain(pool: PgPool) {
+        let server = TestServer::with_config(
+            pool,
+            None,
+            RemailsConfig {
+                registration_email_domains: vec!["example.com".to_string()],
+                ..Default::default()
+            },
+        )
+        .await;
+
+        let register = async |email: &str| {
+            server
+                .post(
+                    "/api/register/password",
+                    serialize_body(json!({
+                        "name": "New User",
+                        "email": email,
+                        "password": "correct-Horse-7-battery-Staple"
+                    })),
+                )
+                .await
+                .unwrap()
+        };
+
+        let response = register("new-user@example.net").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let error: serde_json::Value = deserialize_body(response.into_body()).await;
+        assert_eq!(
+            error["description"],
+            "Only email addresses of example.com can register"
+        );
+
+        let response = register("new-user@Example.com").await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
     #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "api_users")))]
     async fn test_totp_login(pool: PgPool) {
         let mut server = TestServer::new(pool.clone(), None).await;
@@ -1058,6 +1415,154 @@ pub mod tests {
         assert!(matches!(whoami, WhoamiResponse::LoggedIn(_)));
     }
 
+    #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "api_users")))]
+    async fn test_passkey_login(pool: PgPool) {
+        let mut server = TestServer::new(pool, None).await;
+        let origin = webauthn_origin(&server.api_state().config.remails_config);
+        let mut authenticator = WebauthnAuthenticator::new(SoftPasskey::new(true));
+
+        async fn log_in(server: &mut TestServer) -> WhoamiResponse {
+            let response = server
+                .post(
+                    "/api/login/password",
+                    serialize_body(json!({
+                        "email": "test-totp@user-4",
+                        "password": "unsecure123"
+                    })),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            server
+                .headers
+                .insert("Cookie", get_session_cookie(response));
+
+            let whoami = server.get("/api/whoami").await.unwrap();
+            deserialize_body(whoami.into_body()).await
+        }
+
+        assert!(matches!(
+            log_in(&mut server).await,
+            WhoamiResponse::LoggedIn(_)
+        ));
+
+        // Not allowed to enroll passkeys for other accounts
+        let response = server
+            .get("/api/api_user/54432300-128a-46a0-8a83-fe39ce3ce5ef/passkeys/enroll")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // Enroll a passkey
+        let response = server
+            .get("/api/api_user/820128b1-e08f-404d-ad08-e679a7d6b515/passkeys/enroll")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let challenge: CreationChallengeResponse = deserialize_body(response.into_body()).await;
+        let credential = authenticator
+            .do_registration(origin.clone(), challenge)
+            .unwrap();
+
+        let response = server
+            .post(
+                "/api/api_user/820128b1-e08f-404d-ad08-e679a7d6b515/passkeys/enroll",
+                serialize_body(json!({
+                    "credential": credential,
+                    "description": "test passkey",
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The challenge can only be answered once
+        let response = server
+            .post(
+                "/api/api_user/820128b1-e08f-404d-ad08-e679a7d6b515/passkeys/enroll",
+                serialize_body(json!({
+                    "credential": credential,
+                    "description": "test passkey",
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = server
+            .get("/api/api_user/820128b1-e08f-404d-ad08-e679a7d6b515/passkeys")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let passkeys: Vec<PasskeyDetails> = deserialize_body(response.into_body()).await;
+        assert_eq!(passkeys.len(), 1);
+        assert_eq!(passkeys[0].description, "test passkey");
+        assert!(passkeys[0].last_used.is_none());
+
+        // logout
+        let response = server.get("/api/logout").await.unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        server
+            .headers
+            .insert("Cookie", get_session_cookie(response));
+
+        // requires 2FA before actually logged in
+        assert!(matches!(
+            log_in(&mut server).await,
+            WhoamiResponse::MfaPending
+        ));
+        let response = server.get("/api/organizations").await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // A passkey answering another challenge is not accepted
+        let response = server.get("/api/login/passkey").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let stale_challenge: RequestChallengeResponse =
+            deserialize_body(response.into_body()).await;
+        let stale_credential = authenticator
+            .do_authentication(origin.clone(), stale_challenge)
+            .unwrap();
+
+        let response = server.get("/api/login/passkey").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = server
+            .post("/api/login/passkey", serialize_body(&stale_credential))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = server.get("/api/organizations").await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // Finish login with the passkey
+        let response = server.get("/api/login/passkey").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let challenge: RequestChallengeResponse = deserialize_body(response.into_body()).await;
+        let credential = authenticator
+            .do_authentication(origin.clone(), challenge)
+            .unwrap();
+
+        let response = server
+            .post("/api/login/passkey", serialize_body(&credential))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        server
+            .headers
+            .insert("Cookie", get_session_cookie(response));
+
+        // Can access API routes
+        let response = server.get("/api/organizations").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = server
+            .get("/api/api_user/820128b1-e08f-404d-ad08-e679a7d6b515/passkeys")
+            .await
+            .unwrap();
+        let passkeys: Vec<PasskeyDetails> = deserialize_body(response.into_body()).await;
+        assert!(passkeys[0].last_used.is_some());
+    }
+
     #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "api_users")))]
     async fn test_totp_rate_limit(pool: PgPool) {
         let mut server = TestServer::new(pool.clone(), None).await;
@@ -1176,6 +1681,119 @@ pub mod tests {
         assert_eq!(response.status(), StatusCode::OK);
     }
 
+    #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "api_users")))]
+    async fn test_password_rate_limit_by_ip(pool: PgPool) {
+        async fn login(server: &TestServer, email: &str, password: &str) -> StatusCode {
+            server
+                .post(
+                    "/api/login/password",
+                    serialize_body(json!({
+                        "email": email,
+                        "password": password
+                    })),
+                )
+                .await
+                .unwrap()
+                .status()
+        }
+
+        let mut server = TestServer::with_config(
+            pool.clone(),
+            None,
+            RemailsConfig {
+                trusted_proxy_hops: 1,
+                ..Default::default()
+            },
+        )
+        .await;
+
+        // spray wrong passwords across many accounts from a single IP address
+        server.set_header("X-Forwarded-For", Some("203.0.113.7".to_string()));
+        for i in 0..10 {
+            let email = format!("sprayed-{i}@example.com");
+            assert_eq!(
+                login(&server, &email, "wrongwrong").await,
+                StatusCode::NOT_FOUND
+            );
+        }
+
+        // the IP address is throttled, even for an account it did not try before
+        assert_eq!(
+            login(&server, "test-totp-rate-limit@user-4", "unsecure123").await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+
+        // nor can the client evade the limit by prepending addresses of its own
+        server.set_header(
+            "X-Forwarded-For",
+            Some("192.0.2.99, 203.0.113.7".to_string()),
+        );
+        assert_eq!(
+            login(&server, "test-totp-rate-limit@user-4", "unsecure123").await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+
+        // other IP addresses are not affected
+        server.set_header("X-Forwarded-For", Some("198.51.100.23".to_string()));
+        assert_eq!(
+            login(&server, "test-totp-rate-limit@user-4", "unsecure123").await,
+            StatusCode::OK
+        );
+    }
+
+    #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "api_users")))]
+    async fn test_password_rate_limit_by_account(pool: PgPool) {
+        let mut server = TestServer::with_config(
+            pool.clone(),
+            None,
+            RemailsConfig {
+                trusted_proxy_hops: 1,
+                ..Default::default()
+            },
+        )
+        .await;
+        let login = async |server: &TestServer, password: &str| {
+            server
+                .post(
+                    "/api/login/password",
+                    serialize_body(json!({
+                        "email": "test-totp-rate-limit@user-4",
+                        "password": password
+                    })),
+                )
+                .await
+                .unwrap()
+                .status()
+        };
+
+        // guess the password of a single account from many IP addresses
+        for i in 0..10 {
+            server.set_header("X-Forwarded-For", Some(format!("203.0.113.{i}")));
+            assert_eq!(login(&server, "wrongwrong").await, StatusCode::NOT_FOUND);
+        }
+
+        // the account is throttled, even for an IP address that did not try it before
+        server.set_header("X-Forwarded-For", Some("198.51.100.23".to_string()));
+        let response = server
+            .post(
+                "/api/login/password",
+                serialize_body(json!({
+                    "email": "test-totp-rate-limit@user-4",
+                    "password": "unsecure123"
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        // until the window of 15 minutes resets
+        let retry_after: i64 = response.headers()[http::header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((890..=900).contains(&retry_after));
+    }
+
     #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "api_users")))]
     async fn test_org_access(pool: PgPool) {
         let repo = ApiUserRepository::new(pool);
@@ -1229,4 +1847,48 @@ pub mod tests {
         assert!(super_admin.has_org_write_access(&org_2).is_ok());
         assert!(super_admin.has_org_read_access(&org_2).is_ok());
     }
+
+    #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "api_users")))]
+    async fn test_session_idle_expiry(pool: PgPool) {
+        let mut server = TestServer::new(pool, None).await;
+        let user_id: ApiUserId = "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap();
+        let session_key = server.api_state().config.session_key.clone();
+
+        let session = |idle_expires_at: DateTime<Utc>| {
+            let cookie = UserCookie {
+                id: user_id,
+                state: LoginState::LoggedIn,
+                expires_at: Utc::now() + Duration::days(1),
+                idle_expires_at: Some(idle_expires_at),
+            };
+            let jar = PrivateCookieJar::new(session_key.clone())
+                .add(cookie.into_session_cookie().unwrap());
+            get_session_cookie((jar, StatusCode::OK).into_response())
+        };
+
+        // an idle session is rejected
+        server
+            .headers
+            .insert("Cookie", session(Utc::now() - Duration::minutes(1)));
+        let response = server.get("/api/organizations").await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().get("set-cookie").is_none());
+
+        // an active session is accepted and its idle timeout restarts
+        server
+            .headers
+            .insert("Cookie", session(Utc::now() + Duration::minutes(1)));
+        let response = server.get("/api/organizations").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let refreshed = get_session_cookie(response);
+
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert("Cookie", refreshed.parse().unwrap());
+        let refreshed = PrivateCookieJar::from_headers(&headers, session_key)
+            .get(SESSION_COOKIE_NAME)
+            .unwrap();
+        let refreshed: UserCookie = serde_json::from_str(refreshed.value()).unwrap();
+        assert!(refreshed.idle_expires_at.unwrap() > Utc::now() + Duration::hours(23));
+        assert!(!refreshed.is_expired(Utc::now()));
+    }
 }
@@ -6,7 +6,10 @@ use crate::{
         validation::ValidatedJson,
     },
     handler::dns::DomainVerificationStatus,
-    models::{ApiDomain, DomainId, DomainRepository, NewDomain, OrganizationId, ProjectId},
+    models::{
+        ApiDomain, DkimSettings, DomainId, DomainRepository, NewDomain, OrganizationId,
+        OrganizationRepository, ProjectId,
+    },
 };
 use axum::{
     Json,
@@ -22,6 +25,7 @@ pub fn router() -> OpenApiRouter<ApiState> {
         .routes(routes!(create_domain, list_domains))
         .routes(routes!(get_domain, delete_domain, update_domain))
         .routes(routes!(verify_domain))
+        .routes(routes!(update_dkim_settings))
 }
 
 /// Create a new domain
@@ -36,12 +40,19 @@ pub fn router() -> OpenApiRouter<ApiState> {
 )]
 pub(crate) async fn create_domain(
     State(repo): State<DomainRepository>,
+    State(org_repo): State<OrganizationRepository>,
     user: Box<dyn Authenticated>,
     Path(org_id): Path<OrganizationId>,
     ValidatedJson(new): ValidatedJson<NewDomain>,
 ) -> Result<impl IntoResponse, AppError> {
     user.has_org_write_access(&org_id)?;
 
+    if !org_repo.can_create_new_domain(org_id).await? {
+        return Err(AppError::Conflict(
+            "Organization is not allowed to add more domains, upgrade your subscription to increase the limit.".to_owned(),
+        ));
+    }
+
     let domain: ApiDomain = repo.create(&new, org_id, &user).await?.into();
 
     Ok((StatusCode::CREATED, Json(domain)))
@@ -131,6 +142,37 @@ pub async fn update_domain(
     Ok(Json(domain))
 }
 
+/// Update DKIM settings of a domain
+///
+/// Configure which headers are included in the DKIM signature of messages sent from this domain,
+/// and whether the `relaxed` or `simple` canonicalization is used.
+/// Settings that are omitted are left unchanged, and settings that are `null` are reset to their
+/// default: the default set of headers and the `relaxed` canonicalization.
+#[utoipa::path(put, path = "/organizations/{org_id}/domains/{domain_id}/dkim",
+    tags = ["Domains"],
+    params(OrganizationId, DomainId),
+    request_body = DkimSettings,
+    responses(
+        (status = 200, description = "DKIM settings successfully updated", body = ApiDomain),
+        AppError,
+    )
+)]
+pub async fn update_dkim_settings(
+    State(repo): State<DomainRepository>,
+    Path((org_id, domain_id)): Path<(OrganizationId, DomainId)>,
+    user: Box<dyn Authenticated>,
+    ValidatedJson(settings): ValidatedJson<DkimSettings>,
+) -> ApiResult<ApiDomain> {
+    user.has_org_write_access(&org_id)?;
+
+    let domain = repo
+        .update_dkim_settings(org_id, domain_id, &settings, &user)
+        .await?
+        .into();
+
+    Ok(Json(domain))
+}
+
 /// Delete domain
 #[utoipa::path(delete, path = "/organizations/{org_id}/domains/{domain_id}",
     tags = ["Domains"],
@@ -180,8 +222,10 @@ mod tests {
     use sqlx::PgPool;
 
     use crate::{
+        ProductIdentifier, SubscriptionStatus,
         api::tests::{TestServer, deserialize_body, serialize_body},
-        models::{DkimKeyType, ProjectId},
+        mock_subscription,
+        models::{DkimCanonicalization, DkimKeyType, ProjectId},
     };
 
     use super::*;
@@ -304,6 +348,58 @@ mod tests {
         test_domain_lifecycle(pool, vec![]).await;
     }
 
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "api_users", "projects")
+    ))]
+    async fn test_domain_limit(pool: PgPool) {
+        let org_1: OrganizationId = "44729d9f-a7dc-4226-b412-36a7537f5176".parse().unwrap();
+        let user_a = "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap(); // is admin of org 1 and 2
+        let server = TestServer::new(pool.clone(), Some(user_a)).await;
+
+        let set_subscription = async |product: ProductIdentifier| {
+            sqlx::query!(
+                "UPDATE organizations SET current_subscription = $2 WHERE id = $1",
+                *org_1,
+                serde_json::to_value(SubscriptionStatus::Active(mock_subscription(product, None)))
+                    .unwrap()
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+        };
+        let create = async |domain: &str| {
+            server
+                .post(
+                    format!("/api/organizations/{org_1}/domains"),
+                    serialize_body(NewDomain {
+                        domain: domain.to_string(),
+                        dkim_key_type: DkimKeyType::Ed25519,
+                        project_ids: vec![],
+                    }),
+                )
+                .await
+                .unwrap()
+                .status()
+        };
+
+        // a free subscription allows a single domain
+        set_subscription(ProductIdentifier::RmlsFree).await;
+        assert_eq!(create("remails.com").await, StatusCode::CREATED);
+        assert_eq!(create("example.com").await, StatusCode::CONFLICT);
+
+        // larger subscriptions allow more domains
+        set_subscription(ProductIdentifier::RmlsMediumMonthly).await;
+        assert_eq!(create("example.com").await, StatusCode::CREATED);
+
+        let response = server
+            .get(format!("/api/organizations/{org_1}/domains"))
+            .await
+            .unwrap();
+        let domains: Vec<ApiDomain> = deserialize_body(response.into_body()).await;
+        assert_eq!(domains.len(), 2);
+    }
+
     async fn test_domains_no_access(pool: PgPool, domain_id: &str, project_ids: Vec<ProjectId>) {
         let org_1 = "44729d9f-a7dc-4226-b412-36a7537f5176";
         let endpoint = format!("/api/organizations/{org_1}");
@@ -369,4 +465,122 @@ mod tests {
         let org_domain = "ed28baa5-57f7-413f-8c77-7797ba6a8780"; // test-org-1.com
         test_domains_no_access(pool, org_domain, vec![]).await;
     }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "projects", "api_users", "org_domains")
+    ))]
+    async fn test_update_dkim_settings(pool: PgPool) {
+        let org_1 = "44729d9f-a7dc-4226-b412-36a7537f5176";
+        let org_domain = "ed28baa5-57f7-413f-8c77-7797ba6a8780"; // test-org-1.com
+        let endpoint = format!("/api/organizations/{org_1}/domains/{org_domain}");
+
+        let user_a = "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap(); // is admin of org 1 and 2
+        let server = TestServer::new(pool.clone(), Some(user_a)).await;
+
+        // extend the signed headers
+        let mut headers = crate::dkim::DEFAULT_SIGNED_HEADERS
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        headers.push("List-Unsubscribe-Post".to_string());
+        let response = server
+            .put(
+                format!("{endpoint}/dkim"),
+                serialize_body(DkimSettings {
+                    signed_headers: Some(Some(headers.clone())),
+                    canonicalization: Some(Some(DkimCanonicalization::Simple)),
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let domain: ApiDomain = deserialize_body(response.into_body()).await;
+        assert_eq!(domain.dkim_signed_headers(), headers);
+        assert_eq!(domain.dkim_canonicalization(), DkimCanonicalization::Simple);
+
+        // the From header is required
+        let response = server
+            .put(
+                format!("{endpoint}/dkim"),
+                serialize_body(DkimSettings {
+                    signed_headers: Some(Some(vec!["Subject".to_string()])),
+                    canonicalization: None,
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // header names can't contain a colon
+        let response = server
+            .put(
+                format!("{endpoint}/dkim"),
+                serialize_body(DkimSettings {
+                    signed_headers: Some(Some(vec!["From".to_string(), "Subject:".to_string()])),
+                    canonicalization: None,
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // reset to the default headers, omitting the canonicalization leaves it unchanged
+        let response = server
+            .put(
+                format!("{endpoint}/dkim"),
+                serialize_body(serde_json::json!({ "signed_headers": null })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let domain: ApiDomain = deserialize_body(response.into_body()).await;
+        assert_eq!(
+            domain.dkim_signed_headers(),
+            crate::dkim::DEFAULT_SIGNED_HEADERS
+        );
+        assert_eq!(domain.dkim_canonicalization(), DkimCanonicalization::Simple);
+
+        // omitting the headers leaves them unchanged, the canonicalization is reset to `relaxed`
+        let response = server
+            .put(
+                format!("{endpoint}/dkim"),
+                serialize_body(DkimSettings {
+                    signed_headers: Some(Some(headers.clone())),
+                    canonicalization: None,
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = server
+            .put(
+                format!("{endpoint}/dkim"),
+                serialize_body(serde_json::json!({ "canonicalization": null })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let domain: ApiDomain = deserialize_body(response.into_body()).await;
+        assert_eq!(domain.dkim_signed_headers(), headers);
+        assert_eq!(
+            domain.dkim_canonicalization(),
+            DkimCanonicalization::Relaxed
+        );
+
+        // can't update DKIM settings of other organizations
+        let user_b = "94a98d6f-1ec0-49d2-a951-92dc0ff3042a".parse().unwrap(); // is only member of org 2
+        let server = TestServer::new(pool.clone(), Some(user_b)).await;
+        let response = server
+            .put(
+                format!("{endpoint}/dkim"),
+                serialize_body(DkimSettings {
+                    signed_headers: None,
+                    canonicalization: None,
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
 }
@@ -1,10 +1,15 @@
-use crate::{api::oauth, models, models::Error};
+use crate::{
+    api::oauth,
+    models,
+    models::{Error, RateLimitStatus},
+};
 use axum::{
     Json,
     extract::rejection::{JsonRejection, QueryRejection},
-    http::StatusCode,
+    http::{HeaderValue, StatusCode, header::RETRY_AFTER},
     response::IntoResponse,
 };
+use chrono::{DateTime, Utc};
 use serde::Serialize;
 use std::collections::BTreeMap;
 use tokio::task::JoinError;
@@ -22,7 +27,17 @@ pub enum AppError {
     BadRequest(String),
     NotFound,
     Conflict(String),
-    TooManyRequests,
+    Gone(String),
+    /// Throttled until the given moment
+    #[display("TooManyRequests")]
+    TooManyRequests(DateTime<Utc>),
+    #[display("TooManyRequests")]
+    RateLimited(RateLimitStatus),
+    /// The organization used up its message quota, which resets at the given moment, if known
+    #[display("QuotaExceeded")]
+    QuotaExceeded(Option<DateTime<Utc>>),
+    /// The project may not use the domain of a sender address
+    DomainNotAuthorized(String),
     Internal,
     Forbidden,
     Unauthorized,
@@ -31,6 +46,28 @@ pub enum AppError {
     RequestTimeout,
 }
 
+impl AppError {
+    /// Stable, machine-readable code for clients to branch on, which never changes for a variant
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::BadRequest(_) => "bad_request",
+            AppError::NotFound => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::Gone(_) => "gone",
+            AppError::TooManyRequests(_) => "too_many_requests",
+            AppError::RateLimited(_) => "rate_limited",
+            AppError::QuotaExceeded(_) => "quota_exceeded",
+            AppError::DomainNotAuthorized(_) => "domain_not_authorized",
+            AppError::Internal => "internal",
+            AppError::Forbidden => "forbidden",
+            AppError::Unauthorized => "unauthorized",
+            AppError::BadGateway => "bad_gateway",
+            AppError::PayloadTooLarge => "payload_too_large",
+            AppError::RequestTimeout => "request_timeout",
+        }
+    }
+}
+
 #[derive(utoipa::IntoResponses, Serialize)]
 enum ApiError {
     /// Bad Request
@@ -42,6 +79,9 @@ enum ApiError {
     /// Conflict
     #[response(status = CONFLICT)]
     Conflict(ApiErrorResponse),
+    /// Gone
+    #[response(status = GONE)]
+    Gone(ApiErrorResponse),
     /// Too Many Requests
     #[response(status = TOO_MANY_REQUESTS)]
     TooManyRequests(ApiErrorResponse),
@@ -69,6 +109,9 @@ enum ApiError {
 #[response(description = "API error details")]
 #[cfg_attr(test, derive(serde::Deserialize))]
 pub struct ApiErrorResponse {
+    /// Stable, machine-readable error code, e.g., `not_found`, `rate_limited` or `quota_exceeded`
+    #[schema(example = "not_found")]
+    code: String,
     description: String,
     reference: Uuid,
 }
@@ -82,15 +125,21 @@ impl From<AppError> for ApiError {
         );
 
         let content = ApiErrorResponse {
+            code: err.code().to_string(),
             description: err.to_string(),
             reference,
         };
 
         match err {
-            AppError::BadRequest(_) => ApiError::BadRequest(content),
+            AppError::BadRequest(_) | AppError::DomainNotAuthorized(_) => {
+                ApiError::BadRequest(content)
+            }
             AppError::NotFound => ApiError::NotFound(content),
             AppError::Conflict(_) => ApiError::Conflict(content),
-            AppError::TooManyRequests => ApiError::TooManyRequests(content),
+            AppError::Gone(_) => ApiError::Gone(content),
+            AppError::TooManyRequests(_)
+            | AppError::RateLimited(_)
+            | AppError::QuotaExceeded(_) => ApiError::TooManyRequests(content),
             AppError::Internal => ApiError::Internal(content),
             AppError::Forbidden => ApiError::Forbidden(content),
             AppError::Unauthorized => ApiError::Unauthorized(content),
@@ -117,6 +166,7 @@ impl From<oauth::Error> for AppError {
             | oauth::Error::CSRFTokenMismatch => AppError::Unauthorized,
             oauth::Error::Conflict(_) => AppError::Conflict(message),
             oauth::Error::Forbidden => AppError::Forbidden,
+            oauth::Error::EmailDomainNotAllowed(_) => AppError::BadRequest(message),
         }
     }
 }
@@ -182,7 +232,9 @@ impl From<models::Error> for AppError {
             Error::ForeignKeyViolation => AppError::BadRequest("Foreign key violation".to_string()),
             Error::Conflict => AppError::Conflict("Conflict".to_string()),
             Error::BadRequest(err) => AppError::BadRequest(err.to_string()),
-            Error::TooManyRequests => AppError::TooManyRequests,
+            Error::TooManyRequests(reset) => AppError::TooManyRequests(reset),
+            Error::RateLimited(status) => AppError::RateLimited(status),
+            Error::QuotaExceeded(reset) => AppError::QuotaExceeded(reset),
             Error::OrgBlocked => AppError::Forbidden,
             _ => AppError::Internal,
         }
@@ -191,10 +243,30 @@ impl From<models::Error> for AppError {
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
-        Into::<ApiError>::into(self).into_response()
+        // let clients know when they can try again
+        let retry_after = match &self {
+            AppError::TooManyRequests(reset) => Some(*reset),
+            AppError::RateLimited(status) => status.retry_after,
+            AppError::QuotaExceeded(reset) => *reset,
+            _ => None,
+        }
+        .map(|moment| [(RETRY_AFTER, delta_seconds(moment))]);
+
+        if let AppError::RateLimited(status) = self {
+            return (status, retry_after, Into::<ApiError>::into(self)).into_response();
+        }
+
+        (retry_after, Into::<ApiError>::into(self)).into_response()
     }
 }
 
+/// The number of seconds until `moment`, rounded up, as the delta-seconds of a `Retry-After` header
+fn delta_seconds(moment: DateTime<Utc>) -> HeaderValue {
+    let millis = (moment - Utc::now()).num_milliseconds().max(0);
+
+    HeaderValue::from((millis + 999) / 1000)
+}
+
 impl IntoResponses for AppError {
     fn responses() -> BTreeMap<String, RefOr<Response>> {
         ApiError::responses()
@@ -207,6 +279,7 @@ impl IntoResponse for ApiError {
             ApiError::BadRequest(body) => (StatusCode::BAD_REQUEST, Json(body)),
             ApiError::NotFound(body) => (StatusCode::NOT_FOUND, Json(body)),
             ApiError::Conflict(body) => (StatusCode::CONFLICT, Json(body)),
+            ApiError::Gone(body) => (StatusCode::GONE, Json(body)),
             ApiError::TooManyRequests(body) => (StatusCode::TOO_MANY_REQUESTS, Json(body)),
             ApiError::Internal(body) => (StatusCode::INTERNAL_SERVER_ERROR, Json(body)),
             ApiError::Forbidden(body) => (StatusCode::FORBIDDEN, Json(body)),
@@ -218,3 +291,116 @@ impl IntoResponse for ApiError {
         .into_response()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::tests::{TestServer, deserialize_body, serialize_body},
+        test::TestProjects,
+    };
+    use axum::body::Body;
+    use serde_json::json;
+    use sqlx::PgPool;
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "api_users",
+            "projects",
+            "smtp_credentials",
+            "messages"
+        )
+    ))]
+    async fn error_codes(pool: PgPool) {
+        let (org_1, project_1) = TestProjects::Org1Project1.get_ids();
+        let user_1 = "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap(); // is admin of org 1 and 2
+        let user_2 = "94a98d6f-1ec0-49d2-a951-92dc0ff3042a".parse().unwrap();
+        let mut server = TestServer::new(pool.clone(), None).await;
+
+        let response = server.get("/api/organizations").await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let error: ApiErrorResponse = deserialize_body(response.into_body()).await;
+        assert_eq!(error.code, "unauthorized");
+
+        server.set_user(Some(user_2));
+        let response = server
+            .post("/api/organizations", serialize_body(json!({})))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let error: ApiErrorResponse = deserialize_body(response.into_body()).await;
+        assert_eq!(error.code, "bad_request");
+
+        // user 2 is not a member of organization 1
+        let response = server
+            .get("/api/organizations/44729d9f-a7dc-4226-b412-36a7537f5176")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let error: ApiErrorResponse = deserialize_body(response.into_body()).await;
+        assert_eq!(error.code, "forbidden");
+        assert_eq!(error.description, "Forbidden");
+
+        // project 1 is not linked to any domain without the domain fixtures
+        server.set_user(Some(user_1));
+        let response = server
+            .put(
+                format!("/api/organizations/{org_1}/projects/{project_1}"),
+                serialize_body(json!({
+                    "name": "Project 1",
+                    "retention_period_days": 1,
+                    "plaintext_fallback": false,
+                    "default_from_email": "sender@example.com"
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let error: ApiErrorResponse = deserialize_body(response.into_body()).await;
+        assert_eq!(error.code, "domain_not_authorized");
+        assert_eq!(
+            error.description,
+            "Project is not permitted to use domain example.com"
+        );
+
+        sqlx::query!(
+            r#"
+            UPDATE organizations
+            SET used_message_quota = total_message_quota,
+                quota_reset = now() + '1 hour'
+            WHERE id = $1
+            "#,
+            *org_1
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let response = server
+            .put(
+                format!(
+                    "/api/organizations/{org_1}/emails/e165562a-fb6d-423b-b318-fd26f4610634/retry"
+                ),
+                Body::empty(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        // the client may try again once the quota resets
+        let retry_after: i64 = response.headers()[RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((3590..=3600).contains(&retry_after));
+        let error: ApiErrorResponse = deserialize_body(response.into_body()).await;
+        assert_eq!(error.code, "quota_exceeded");
+
+        assert_eq!(AppError::Conflict("taken".to_string()).code(), "conflict");
+        assert_eq!(
+            AppError::TooManyRequests(Utc::now()).code(),
+            "too_many_requests"
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use crate::{
     api::{
-        ApiState,
+        ApiState, RemailsConfig,
         auth::Authenticated,
         error::{ApiResult, AppError},
         validation::ValidatedJson,
@@ -9,6 +9,7 @@ use crate::{
         ApiInvite, ApiUser, CreatedInviteWithPassword, InviteId, InviteRepository, Organization,
         OrganizationId, OrganizationRepository, Password, Role,
     },
+    system_emails::send_invite_email,
 };
 use axum::{
     Json,
@@ -16,6 +17,7 @@ use axum::{
     response::IntoResponse,
 };
 use chrono::{TimeDelta, Utc};
+use email_address::EmailAddress;
 use http::StatusCode;
 use tracing::debug;
 use utoipa_axum::{router::OpenApiRouter, routes};
@@ -24,9 +26,15 @@ pub fn router() -> OpenApiRouter<ApiState> {
     OpenApiRouter::new()
         .routes(routes!(create_invite, get_org_invites))
         .routes(routes!(get_invite, remove_invite, accept_invite))
+        .routes(routes!(resend_invite))
 }
 
+/// Minimum time between two emails for the same invite
+const INVITE_RESEND_INTERVAL: TimeDelta = TimeDelta::minutes(5);
+
 /// Create an invite
+///
+/// The invite link expires after the configured number of days, 7 by default
 #[utoipa::path(post, path = "/invite/{org_id}",
     tags = ["internal", "Organizations"],
     request_body = Role,
@@ -37,13 +45,14 @@ pub fn router() -> OpenApiRouter<ApiState> {
 )]
 pub async fn create_invite(
     State(repo): State<InviteRepository>,
+    State(config): State<RemailsConfig>,
     Path((org_id,)): Path<(OrganizationId,)>,
     user: ApiUser,
     ValidatedJson(role): ValidatedJson<Role>,
 ) -> Result<impl IntoResponse, AppError> {
     user.has_org_admin_access(&org_id)?;
 
-    let expires = Utc::now() + TimeDelta::days(7);
+    let expires = Utc::now() + TimeDelta::days(config.invite_expiry_days.into());
     let invite = repo
         .create(org_id, role, *user.id(), expires, &user)
         .await?;
@@ -104,6 +113,8 @@ pub async fn get_invite(
 }
 
 /// Withdraw an invitation
+///
+/// Revokes a pending invitation, after which its link can no longer be used
 #[utoipa::path(delete, path = "/invite/{org_id}/{invite_id}",
     tags = ["internal", "Organizations"],
     responses(
@@ -123,11 +134,80 @@ pub async fn remove_invite(
     Ok(Json(id))
 }
 
+/// Resend an invitation
+///
+/// Emails the invite link to the provided email address. As only a hash of the invite password is
+/// stored, a new password is generated, which means the previously shared link stops working.
+/// Once an invite has been emailed, it can only be resent to the same address.
+/// An invite can be resent at most once every 5 minutes.
+#[utoipa::path(post, path = "/invite/{org_id}/{invite_id}/resend",
+    tags = ["internal", "Organizations"],
+    request_body = EmailAddress,
+    responses(
+        (status = 200, description = "Successfully resent the invitation", body = CreatedInviteWithPassword),
+        (status = 410, description = "The invite has expired"),
+        AppError
+    )
+)]
+pub async fn resend_invite(
+    State(state): State<ApiState>,
+    State(repo): State<InviteRepository>,
+    Path((org_id, invite_id)): Path<(OrganizationId, InviteId)>,
+    user: ApiUser,
+    Json(email): Json<EmailAddress>,
+) -> ApiResult<CreatedInviteWithPassword> {
+    user.has_org_admin_access(&org_id)?;
+
+    // accepted and withdrawn invites no longer exist
+    let invite = repo.get_by_id(invite_id, org_id).await?;
+    if invite.is_expired() {
+        return Err(AppError::Gone("This invite has expired".to_string()));
+    }
+    if let Some(sent_to) = invite.email()
+        && !sent_to.eq_ignore_ascii_case(email.as_str())
+    {
+        return Err(AppError::BadRequest(format!(
+            "This invite was sent to {sent_to}, create a new invite to invite someone else"
+        )));
+    }
+
+    debug!(
+        user_id = user.id().to_string(),
+        organization_id = org_id.to_string(),
+        invite_id = invite_id.to_string(),
+        "resending invite"
+    );
+
+    // the new password is only stored once the email with the new link has been sent
+    let resent = repo
+        .resend(
+            invite_id,
+            org_id,
+            &email,
+            INVITE_RESEND_INTERVAL,
+            &user,
+            async |resent: &CreatedInviteWithPassword| {
+                send_invite_email(
+                    &state,
+                    email.clone(),
+                    resent,
+                    invite.organization_name(),
+                    invite.created_by_name(),
+                )
+                .await
+            },
+        )
+        .await?;
+
+    Ok(Json(resent))
+}
+
 /// Accept an invitation
 #[utoipa::path(post, path = "/invite/{org_id}/{invite_id}/{password}",
     tags = ["internal", "Organizations"],
     responses(
         (status = 201, description = "Successfully accepted invite to organization", body = Organization),
+        (status = 410, description = "The invite has expired"),
         AppError
     )
 )]
@@ -151,7 +231,7 @@ pub async fn accept_invite(
     }
 
     if invite.is_expired() {
-        return Err(AppError::NotFound);
+        return Err(AppError::Gone("This invite has expired".to_string()));
     }
 
     invites
@@ -209,6 +289,9 @@ mod tests {
             deserialize_body(response.into_body()).await;
         assert_eq!(created_invite.organization_id().to_string(), org_1);
         assert_eq!(*created_invite.created_by(), user_1);
+        let valid_for = created_invite.expires_at() - Utc::now();
+        assert!(valid_for > TimeDelta::days(7) - TimeDelta::minutes(1));
+        assert!(valid_for <= TimeDelta::days(7));
 
         // new invite created
         let response = server.get(format!("/api/invite/{org_1}")).await.unwrap();
@@ -520,7 +603,7 @@ mod tests {
 
         // can't accept expired invite
         let response = server.post(&invite_endpoint, Body::empty()).await.unwrap();
-        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.status(), StatusCode::GONE);
 
         // expired invite will be removed eventually
         let bus_client = BusClient::new_from_env_var().unwrap();
@@ -537,4 +620,164 @@ mod tests {
         let response = server.get(&invite_endpoint).await.unwrap();
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "api_users", "invites", "projects", "runtime_config")
+    ))]
+    async fn test_resend_invite(pool: PgPool) {
+        let user_1 = "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap(); // is admin of org 1 and 2
+        let user_3 = "54432300-128a-46a0-8a83-fe39ce3ce5ef".parse().unwrap(); // is not in any org
+        let org_1 = "44729d9f-a7dc-4226-b412-36a7537f5176";
+        let active_invite: InviteId = "32bba198-fdd8-4cb7-8b82-85857dd2527f".parse().unwrap();
+        let expired_invite: InviteId = "8b01ce56-4304-47c7-b9a6-62bd1b7e8269".parse().unwrap();
+        let mut server = TestServer::new(pool.clone(), Some(user_1)).await;
+
+        // resend the invite
+        let response = server
+            .post(
+                format!("/api/invite/{org_1}/{active_invite}/resend"),
+                serialize_body("invitee@example.com"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let resent: CreatedInviteWithPassword = deserialize_body(response.into_body()).await;
+        assert_eq!(*resent.id(), active_invite);
+
+        // the invite email contains the new link
+        let raw_data = sqlx::query_scalar!(
+            r#"
+            SELECT raw_data FROM messages WHERE recipients = '{"invitee@example.com"}'
+            "#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let message = mail_parser::MessageParser::default()
+            .parse(&raw_data)
+            .unwrap();
+        let text = message.body_text(0).unwrap();
+        assert!(text.contains(&format!(
+            "/invite/{org_1}/{active_invite}/{}",
+            resent.password()
+        )));
+
+        // the invite can't be sent to someone else
+        let response = server
+            .post(
+                format!("/api/invite/{org_1}/{active_invite}/resend"),
+                serialize_body("someone-else@example.com"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // resending again right away is throttled for 5 minutes
+        let response = server
+            .post(
+                format!("/api/invite/{org_1}/{active_invite}/resend"),
+                serialize_body("invitee@example.com"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: i64 = response.headers()[http::header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((290..=300).contains(&retry_after));
+
+        // expired invites can't be resent
+        let response = server
+            .post(
+                format!("/api/invite/{org_1}/{expired_invite}/resend"),
+                serialize_body("invitee@example.com"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::GONE);
+
+        // the old link no longer works, the new one does
+        server.set_user(Some(user_3));
+        let response = server
+            .get(format!("/api/invite/{org_1}/{active_invite}/unsecure"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let invite_endpoint = format!("/api/invite/{org_1}/{active_invite}/{}", resent.password());
+        let response = server.post(&invite_endpoint, Body::empty()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        // accepted invites can't be resent
+        server.set_user(Some(user_1));
+        let response = server
+            .post(
+                format!("/api/invite/{org_1}/{active_invite}/resend"),
+                serialize_body("invitee@example.com"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "api_users", "invites", "projects", "runtime_config")
+    ))]
+    async fn test_resend_invite_send_failure(pool: PgPool) {
+        let user_1 = "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap(); // is admin of org 1 and 2
+        let user_3 = "54432300-128a-46a0-8a83-fe39ce3ce5ef".parse().unwrap(); // is not in any org
+        let org_1 = "44729d9f-a7dc-4226-b412-36a7537f5176";
+        let active_invite: InviteId = "32bba198-fdd8-4cb7-8b82-85857dd2527f".parse().unwrap();
+        let mut server = TestServer::new(pool.clone(), Some(user_1)).await;
+
+        // system emails can't be sent without a system email address
+        sqlx::query!("UPDATE runtime_config SET system_email_address = NULL")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let response = server
+            .post(
+                format!("/api/invite/{org_1}/{active_invite}/resend"),
+                serialize_body("invitee@example.com"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        // the existing link still works, and the invite is not considered sent
+        server.set_user(Some(user_3));
+        let response = server
+            .get(format!("/api/invite/{org_1}/{active_invite}/unsecure"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let invite: ApiInvite = deserialize_body(response.into_body()).await;
+        assert_eq!(invite.email(), None);
+
+        // so it can be resent right away once emails can be sent
+        sqlx::query!("UPDATE runtime_config SET system_email_address = 'noreply@remails.com'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        server.set_user(Some(user_1));
+        let response = server
+            .post(
+                format!("/api/invite/{org_1}/{active_invite}/resend"),
+                serialize_body("invitee@example.com"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = server.get(format!("/api/invite/{org_1}")).await.unwrap();
+        let invites: Vec<ApiInvite> = deserialize_body(response.into_body()).await;
+        let invite = invites
+            .iter()
+            .find(|invite| *invite.id() == active_invite)
+            .unwrap();
+        assert_eq!(invite.email(), Some("invitee@example.com"));
+    }
 }
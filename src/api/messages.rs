@@ -1,34 +1,39 @@
-use std::sync::Arc;
+use std::{convert::Infallible, sync::Arc};
 
 use super::error::{ApiResult, AppError};
 use crate::{
     api::{
-        ApiState,
+        ApiState, ClientIp, CorrelationId, X_CORRELATION_ID,
         auth::Authenticated,
         validation::{ValidatedJson, ValidatedQuery},
     },
     bus::client::BusClient,
-    handler::RetryConfig,
+    handler::{Handler, RetryConfig},
     models::{
-        ApiKey, ApiMessage, ApiMessageMetadata, Label, MessageFilter, MessageId, MessageRepository,
-        MessageStatus, NewApiMessage, OrganizationId, ProjectId, SuppressedEmailAddress,
+        ApiKey, ApiMessage, ApiMessageMetadata, ApiMessageStatus, ApiUser, Created,
+        DomainRepository, ExportFilter, ExportedMessage, Label, MessageFilter, MessageId,
+        MessagePriority, MessageRepository, MessageStatus, NewApiMessage, OrgBlockStatus,
+        OrganizationId, OrganizationRepository, Project, ProjectId, ProjectRepository, QueueDepth,
+        RateLimitStatus, Role, StuckMessage, StuckMessageFilter, SuppressedEmailAddress,
         SuppressedRepository,
     },
 };
 use axum::{
     Json,
+    body::Body,
     extract::{Path, State},
     middleware,
     middleware::Next,
-    response::IntoResponse,
+    response::{IntoResponse, IntoResponseParts, ResponseParts},
 };
 use email_address::EmailAddress;
+use futures::{StreamExt, TryStreamExt, stream};
 use garde::Validate;
-use http::StatusCode;
+use http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header};
 use mail_builder::MessageBuilder;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tower_http::limit::RequestBodyLimitLayer;
-use tracing::{debug, error, warn};
+use tracing::{debug, error, info, warn};
 use utoipa::ToSchema;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
@@ -36,7 +41,15 @@ pub fn router() -> OpenApiRouter<ApiState> {
     OpenApiRouter::new()
         .routes(routes!(list_messages))
         .routes(routes!(get_message, remove_message))
+        .routes(routes!(get_raw_message))
+        .routes(routes!(get_message_status))
+        .routes(routes!(export_messages))
+        .routes(routes!(restore_message))
         .routes(routes!(retry_now))
+        .routes(routes!(requeue_message))
+        .routes(routes!(list_stuck_messages))
+        .routes(routes!(get_queue_depth))
+        .routes(routes!(force_retry))
         .routes(routes!(list_labels))
         .routes(routes!(list_suppressed, unsuppress_email))
 }
@@ -45,6 +58,7 @@ pub fn router() -> OpenApiRouter<ApiState> {
 pub fn create_message_router() -> OpenApiRouter<ApiState> {
     OpenApiRouter::new()
         .routes(routes!(create_message))
+        .routes(routes!(validate_message))
         .layer(RequestBodyLimitLayer::new(1_200_000))
         .layer(middleware::from_fn(|req, next: Next| async move {
             // TODO I'd prefer a more clean solution for catching errors produced by the
@@ -57,6 +71,32 @@ pub fn create_message_router() -> OpenApiRouter<ApiState> {
         }))
 }
 
+static X_RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+static X_RATELIMIT_RESET: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+static X_QUOTA_REMAINING: HeaderName = HeaderName::from_static("x-quota-remaining");
+
+impl IntoResponseParts for RateLimitStatus {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        let headers = res.headers_mut();
+        headers.insert(
+            X_RATELIMIT_REMAINING.clone(),
+            HeaderValue::from(self.remaining.max(0)),
+        );
+        headers.insert(
+            X_RATELIMIT_RESET.clone(),
+            HeaderValue::from(self.reset.timestamp()),
+        );
+        headers.insert(
+            X_QUOTA_REMAINING.clone(),
+            HeaderValue::from(self.remaining_quota.max(0)),
+        );
+
+        Ok(res)
+    }
+}
+
 /// Contains either a simple email address or a name and email address
 #[derive(Debug, Deserialize, Validate, ToSchema)]
 #[serde(untagged)]
@@ -103,8 +143,10 @@ enum EmailAddresses {
 #[derive(Debug, Deserialize, Validate, ToSchema)]
 #[serde(deny_unknown_fields)]
 pub struct EmailParameters {
+    /// If omitted, the project's default sender is used
     #[garde(dive)]
-    from: JsonEmailAddress,
+    #[serde(default)]
+    from: Option<JsonEmailAddress>,
     #[garde(dive)]
     to: EmailAddresses,
     #[schema[max_length = 500]]
@@ -129,6 +171,69 @@ pub struct EmailParameters {
     reply_to: Option<JsonEmailAddress>,
     #[garde(dive)]
     label: Option<Label>,
+    /// Messages with a higher priority are delivered first when Remails is busy,
+    /// use `low` for bulk messages such as newsletters
+    #[garde(skip)]
+    #[serde(default)]
+    priority: MessagePriority,
+    /// Message-ID header of the message without angle brackets, e.g., `order-1234@example.com`.
+    /// Generated if omitted. When the project has deduplication enabled, a message with the
+    /// same Message-ID within the deduplication window is only created once.
+    #[schema[max_length = 500]]
+    #[garde(length(max = 500), custom(validate_message_id))]
+    message_id: Option<String>,
+}
+
+fn validate_message_id(message_id: &Option<String>, _: &()) -> garde::Result {
+    let Some(message_id) = message_id else {
+        return Ok(());
+    };
+
+    let valid = message_id
+        .split_once('@')
+        .is_some_and(|(left, right)| !left.is_empty() && !right.is_empty())
+        && message_id
+            .chars()
+            .all(|c| c.is_ascii_graphic() && !matches!(c, '<' | '>'));
+    if !valid {
+        return Err(garde::Error::new(
+            "must be of the form `id@domain`, without angle brackets or whitespace",
+        ));
+    }
+
+    Ok(())
+}
+
+impl EmailParameters {
+    /// The sender of the message, falling back to the project's default sender
+    fn sender(&mut self, project: &Project) -> Result<JsonEmailAddress, AppError> {
+        if let Some(from) = self.from.take() {
+            return Ok(from);
+        }
+
+        match (&project.default_from_email, &project.default_from_name) {
+            (Some(address), Some(name)) => Ok(JsonEmailAddress::WithName {
+                name: name.clone(),
+                address: address.clone(),
+            }),
+            (Some(address), None) => Ok(JsonEmailAddress::AddressOnly(address.clone())),
+            (None, _) => Err(AppError::BadRequest(
+                "Must provide a from address, the project has no default sender".to_owned(),
+            )),
+        }
+    }
+}
+
+impl EmailAddresses {
+    fn get_mail_addresses(&self) -> Vec<&String> {
+        match self {
+            EmailAddresses::Singular(address) => vec![address.get_mail_address()],
+            EmailAddresses::Multiple(addresses) => addresses
+                .iter()
+                .map(JsonEmailAddress::get_mail_address)
+                .collect(),
+        }
+    }
 }
 
 impl<'a> From<EmailAddresses> for mail_builder::headers::address::Address<'a> {
@@ -143,6 +248,18 @@ impl<'a> From<EmailAddresses> for mail_builder::headers::address::Address<'a> {
 /// Send an email message
 ///
 /// Use this endpoint to send an email message via the HTTP REST API.
+///
+/// The response contains the `X-RateLimit-Remaining`, `X-RateLimit-Reset`, and `X-Quota-Remaining`
+/// headers, which indicate how many more messages can be sent before getting rate limited, the
+/// Unix timestamp at which the rate limit has been fully replenished, and how many messages are
+/// left in the organization's quota.
+/// These headers are also present if the request got rejected because of the rate limit, in
+/// which case the `Retry-After` header contains the number of seconds until the next message can
+/// be sent.
+///
+/// The `X-Correlation-Id` response header contains the ID that ties this request to the delivery
+/// of the message, which is also stored on the message. Set the `X-Correlation-Id` request
+/// header to use your own ID (at most 100 alphanumeric, `-`, `_`, `.` or `:` characters).
 #[utoipa::path(
     post,
     // Note that the /api prefix is added here because its mounted separately to the router because of its higher request size limit
@@ -150,7 +267,15 @@ impl<'a> From<EmailAddresses> for mail_builder::headers::address::Address<'a> {
     tags = ["Emails"],
     request_body = EmailParameters,
     responses(
-        (status = 201, description = "Message created successfully", body = ApiMessageMetadata),
+        (status = 201, description = "Message created successfully", body = ApiMessageMetadata,
+            headers(
+                ("X-RateLimit-Remaining" = i64, description = "Number of messages that can be sent before getting rate limited"),
+                ("X-RateLimit-Reset" = i64, description = "Unix timestamp at which the rate limit has been fully replenished"),
+                ("X-Quota-Remaining" = i64, description = "Number of messages left in the organization's quota"),
+                ("X-Correlation-Id" = String, description = "Ties this request to the delivery of the message"),
+            )
+        ),
+        (status = 200, description = "A message with the same Message-ID was already created within the project's deduplication window", body = ApiMessageMetadata),
         AppError
     )
 )]
@@ -158,54 +283,57 @@ pub async fn create_message(
     State(repo): State<MessageRepository>,
     State(retry_config): State<Arc<RetryConfig>>,
     State(bus_client): State<Arc<BusClient>>,
+    State(projects): State<ProjectRepository>,
     Path((org_id, project_id)): Path<(OrganizationId, ProjectId)>,
     key: ApiKey, // only accessible for API keys
-    ValidatedJson(message): ValidatedJson<EmailParameters>,
+    ClientIp(client_ip): ClientIp,
+    CorrelationId(correlation_id): CorrelationId,
+    ValidatedJson(mut message): ValidatedJson<EmailParameters>,
 ) -> Result<impl IntoResponse, AppError> {
     key.has_org_write_access(&org_id)?;
 
+    let project = projects.get(project_id).await?;
+    if project.org_id() != org_id {
+        return Err(AppError::NotFound);
+    }
+
     // check email rate limit
-    repo.email_creation_rate_limit(project_id).await?;
+    let rate_limit = repo.email_creation_rate_limit(project_id).await?;
 
     // parse from email
-    let from_email = message.from.get_mail_address();
+    let from = message.sender(&project)?;
+    let from_email = from.get_mail_address();
     let from_email = from_email
         .parse()
         .map_err(|_| AppError::BadRequest(format!("Invalid from email: {}", from_email)))?;
 
     // parse recipient's email(s)
-    let recipients =
-        match &message.to {
-            EmailAddresses::Singular(to) => {
-                let address = to.get_mail_address();
-                vec![address.parse().map_err(|_| {
-                    AppError::BadRequest(format!("Invalid recipient email: {address}"))
-                })?]
-            }
-            EmailAddresses::Multiple(to) => {
-                let mut recipients = Vec::with_capacity(to.len());
-                for recipient in to {
-                    let address = recipient.get_mail_address();
-                    recipients.push(address.parse().map_err(|_| {
-                        AppError::BadRequest(format!("Invalid recipient email: {address}"))
-                    })?);
-                }
-                recipients
-            }
-        };
+    let recipients = message
+        .to
+        .get_mail_addresses()
+        .into_iter()
+        .map(|address| {
+            address
+                .parse()
+                .map_err(|_| AppError::BadRequest(format!("Invalid recipient email: {address}")))
+        })
+        .collect::<Result<Vec<EmailAddress>, _>>()?;
     if recipients.is_empty() {
         return Err(AppError::BadRequest(
             "Must have at least one recipient".to_owned(),
         ));
     }
 
-    // generate message ID
+    // use the provided Message-ID header, or generate one
     let message_id = MessageId::new_v4();
-    let message_id_header = MessageRepository::generate_message_id_header(&message_id, &from_email);
+    let message_id_header = message
+        .message_id
+        .take()
+        .unwrap_or_else(|| MessageRepository::generate_message_id_header(&message_id, &from_email));
 
     // set required fields
     let mut message_builder = MessageBuilder::new()
-        .from(message.from)
+        .from(from)
         .to(message.to)
         .subject(message.subject)
         .message_id(message_id_header.as_str());
@@ -246,29 +374,181 @@ pub async fn create_message(
         label: message.label,
         recipients,
         raw_data,
+        client_ip,
+        correlation_id: correlation_id.clone(),
+        priority: message.priority,
     };
 
     debug!(
         organization_id = org_id.to_string(),
         message_id = message_id.to_string(),
         api_key_id = key.id().to_string(),
+        correlation_id,
         "creating message from API"
     );
+    let correlation_header = [(X_CORRELATION_ID.clone(), correlation_id)];
 
-    let message = repo
+    let created = repo
         .create_from_api(message, retry_config.max_automatic_retries)
         .await?;
+    let is_duplicate = matches!(created, Created::Duplicate(_));
+    let mut message = created.into_inner();
+
+    if !key.is_at_least(&org_id, Role::Admin) {
+        message.hide_admin_details();
+    }
+
+    if is_duplicate {
+        debug!(
+            message_id = message.id.to_string(),
+            "returning existing message with the same Message-ID"
+        );
+        return Ok((
+            StatusCode::OK,
+            rate_limit,
+            correlation_header,
+            Json(message),
+        ));
+    }
 
     match repo.get_ready_to_send(message.id).await {
         Ok(bus_message) => {
             bus_client.try_send(&bus_message).await;
         }
+        Err(crate::models::Error::SendingPaused) => {
+            debug!(
+                message_id = message.id.to_string(),
+                "organization paused sending, message is sent once resumed"
+            );
+        }
         Err(e) => {
             error!(message_id = message.id.to_string(), "{e:?}");
         }
     }
 
-    Ok((StatusCode::CREATED, Json(message)))
+    Ok((
+        StatusCode::CREATED,
+        rate_limit,
+        correlation_header,
+        Json(message),
+    ))
+}
+
+/// Result of validating an email message without sending it
+#[derive(Debug, Serialize, ToSchema)]
+#[cfg_attr(test, derive(Deserialize))]
+pub struct MessageValidation {
+    /// Whether the message would be accepted for sending
+    valid: bool,
+    /// Why the message would not be accepted, empty if the message is valid
+    problems: Vec<String>,
+}
+
+/// Validate an email message
+///
+/// Use this endpoint to check whether an email message would be accepted by the 'send an email
+/// message' endpoint, without actually creating or sending it. The message quota and rate limit
+/// are not affected.
+///
+/// It checks the addresses and the body of the message, whether the project is permitted to use
+/// the sender's domain, and whether the organization and project have message quota left.
+/// The DNS configuration of the domain (SPF, DKIM, DMARC) is only checked when actually sending.
+#[utoipa::path(
+    post,
+    // Note that the /api prefix is added here because its mounted separately to the router because of its higher request size limit
+    path = "/api/organizations/{org_id}/projects/{project_id}/emails/validate",
+    tags = ["Emails"],
+    request_body = EmailParameters,
+    responses(
+        (status = 200, description = "Message validated, see `valid` and `problems` for the outcome", body = MessageValidation),
+        AppError
+    )
+)]
+pub async fn validate_message(
+    State(projects): State<ProjectRepository>,
+    State(domains): State<DomainRepository>,
+    State(organizations): State<OrganizationRepository>,
+    Path((org_id, project_id)): Path<(OrganizationId, ProjectId)>,
+    key: ApiKey, // only accessible for API keys
+    ValidatedJson(mut message): ValidatedJson<EmailParameters>,
+) -> ApiResult<MessageValidation> {
+    key.has_org_write_access(&org_id)?;
+
+    let project = projects.get(project_id).await?;
+    if project.org_id() != org_id {
+        return Err(AppError::NotFound);
+    }
+
+    let mut problems = Vec::new();
+
+    let from_email = match message.sender(&project) {
+        Ok(from) => {
+            let from_email = from.get_mail_address();
+            from_email
+                .parse::<EmailAddress>()
+                .inspect_err(|_| problems.push(format!("Invalid from email: {from_email}")))
+                .ok()
+        }
+        Err(AppError::BadRequest(problem)) => {
+            problems.push(problem);
+            None
+        }
+        Err(e) => return Err(e),
+    };
+
+    for address in message.to.get_mail_addresses() {
+        if address.parse::<EmailAddress>().is_err() {
+            problems.push(format!("Invalid recipient email: {address}"));
+        }
+    }
+
+    if message.text_body.is_none() && message.html_body.is_none() {
+        problems.push("Must provide a text_body or html_body".to_owned());
+    }
+
+    // the same domain checks as when the message is processed for sending
+    if let Some(from_email) = from_email {
+        let sender_domain = from_email.domain();
+        match domains
+            .lookup_domain_name(sender_domain, project_id)
+            .await?
+        {
+            None => problems.push(format!(
+                "Project is not permitted to use domain {sender_domain}"
+            )),
+            Some(domain) if !Handler::is_subdomain(sender_domain, &domain.domain) => {
+                problems.push(format!(
+                    "MAIL FROM domain ({sender_domain}) is not a valid (sub-)domain of {}",
+                    domain.domain
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+
+    let organization = organizations
+        .get_by_id(org_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    if organization.block_status() >= OrgBlockStatus::NoSending {
+        problems.push("Organization is blocked from sending messages".to_owned());
+    }
+    if organizations.remaining_project_quota(project_id).await? <= 0 {
+        problems.push("Quota exceeded".to_owned());
+    }
+
+    debug!(
+        organization_id = org_id.to_string(),
+        project_id = project_id.to_string(),
+        api_key_id = key.id().to_string(),
+        problems = problems.len(),
+        "validated message from API"
+    );
+
+    Ok(Json(MessageValidation {
+        valid: problems.is_empty(),
+        problems,
+    }))
 }
 
 /// List all email messages
@@ -295,7 +575,12 @@ pub async fn list_messages(
 ) -> ApiResult<Vec<ApiMessageMetadata>> {
     user.has_org_read_access(&org_id)?;
 
-    let messages = repo.list_message_metadata(org_id, filter).await?;
+    let mut messages = repo.list_message_metadata(org_id, filter).await?;
+    if !user.is_at_least(&org_id, Role::Admin) {
+        messages
+            .iter_mut()
+            .for_each(ApiMessageMetadata::hide_admin_details);
+    }
 
     debug!(
         user_id = user.log_id(),
@@ -312,12 +597,17 @@ pub async fn list_messages(
 /// The message data is truncated to 10,000 ASCII characters.
 /// The `is_truncated` field in the response indicates weather the content
 /// was actually truncated or did fit into the 10,000-character limit.
+///
+/// The response includes an `ETag` header. Send it back in the `If-None-Match` header
+/// to receive an empty `304 Not Modified` response while the message is unchanged.
 #[utoipa::path(
     get,
     path = "/organizations/{org_id}/emails/{message_id}",
     tags = ["Emails"],
     responses(
-        (status = 200, description = "Successfully fetched message", body = ApiMessage),
+        (status = 200, description = "Successfully fetched message", body = ApiMessage,
+            headers(("ETag" = String, description = "Changes whenever the message changes"))),
+        (status = 304, description = "The message did not change since the `If-None-Match` ETag"),
         AppError
     )
 )]
@@ -325,10 +615,32 @@ pub async fn get_message(
     State(repo): State<MessageRepository>,
     Path((org_id, message_id)): Path<(OrganizationId, MessageId)>,
     user: Box<dyn Authenticated>,
-) -> ApiResult<ApiMessage> {
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
     user.has_org_read_access(&org_id)?;
 
-    let message = repo.find_by_id(org_id, message_id).await?;
+    let mut message = repo.find_by_id(org_id, message_id).await?;
+    let admin_details = user.is_at_least(&org_id, Role::Admin);
+    if !admin_details {
+        message.hide_admin_details();
+    }
+
+    let etag = message.etag(admin_details);
+    let etag_header = [(header::ETAG, etag.clone())];
+
+    if headers
+        .get(header::IF_NONE_MATCH)
+        .is_some_and(|if_none_match| etag_matches(if_none_match, &etag))
+    {
+        debug!(
+            user_id = user.log_id(),
+            organization_id = org_id.to_string(),
+            message_id = message_id.to_string(),
+            "message not modified",
+        );
+
+        return Ok((StatusCode::NOT_MODIFIED, etag_header).into_response());
+    }
 
     debug!(
         user_id = user.log_id(),
@@ -337,10 +649,187 @@ pub async fn get_message(
         "retrieved message",
     );
 
-    Ok(Json(message))
+    Ok((etag_header, Json(message)).into_response())
+}
+
+/// Whether any of the tags in an `If-None-Match` header matches `etag`, using the weak
+/// comparison of RFC 9110
+fn etag_matches(if_none_match: &HeaderValue, etag: &str) -> bool {
+    let Ok(if_none_match) = if_none_match.to_str() else {
+        return false;
+    };
+
+    if_none_match.trim() == "*"
+        || if_none_match
+            .split(',')
+            .map(|tag| tag.trim())
+            .any(|tag| tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+/// Get the delivery status of an email message by ID
+///
+/// Unlike [`get_message`], this only returns the status, the number of delivery attempts,
+/// and when the next attempt is made, which is cheap to poll until the message is delivered.
+#[utoipa::path(
+    get,
+    path = "/organizations/{org_id}/emails/{message_id}/status",
+    tags = ["Emails"],
+    responses(
+        (status = 200, description = "Successfully fetched message status", body = ApiMessageStatus),
+        AppError
+    )
+)]
+pub async fn get_message_status(
+    State(repo): State<MessageRepository>,
+    Path((org_id, message_id)): Path<(OrganizationId, MessageId)>,
+    user: Box<dyn Authenticated>,
+) -> ApiResult<ApiMessageStatus> {
+    user.has_org_read_access(&org_id)?;
+
+    let status = repo.message_status(org_id, message_id).await?;
+
+    debug!(
+        user_id = user.log_id(),
+        organization_id = org_id.to_string(),
+        message_id = message_id.to_string(),
+        "retrieved message status",
+    );
+
+    Ok(Json(status))
+}
+
+#[derive(ToSchema)]
+#[schema(format = Binary, value_type = String)]
+struct RawMessage(#[schema(inline)] Vec<u8>);
+
+/// Get the full raw email message by ID
+///
+/// Unlike the `truncated_raw_data` of [`get_message`], the raw message is never truncated.
+#[utoipa::path(
+    get,
+    path = "/organizations/{org_id}/emails/{message_id}/raw",
+    tags = ["Emails"],
+    responses(
+        (status = 200, description = "Successfully fetched raw message", content_type = "message/rfc822", body = inline(RawMessage)),
+        AppError
+    )
+)]
+pub async fn get_raw_message(
+    State(repo): State<MessageRepository>,
+    Path((org_id, message_id)): Path<(OrganizationId, MessageId)>,
+    user: Box<dyn Authenticated>,
+) -> Result<impl IntoResponse, AppError> {
+    user.has_org_read_access(&org_id)?;
+
+    let raw_size = repo.raw_size(org_id, message_id).await?;
+
+    debug!(
+        user_id = user.log_id(),
+        organization_id = org_id.to_string(),
+        message_id = message_id.to_string(),
+        raw_size,
+        "retrieved raw message",
+    );
+
+    let stream = repo
+        .stream_raw_data(org_id, message_id, raw_size)
+        .inspect_err(move |e| {
+            error!(
+                message_id = message_id.to_string(),
+                "failed to stream raw message: {e:?}"
+            )
+        });
+
+    Ok((
+        [(header::CONTENT_TYPE, "message/rfc822")],
+        Body::from_stream(stream),
+    ))
+}
+
+const EXPORT_CSV_HEADER: &str = "id,status,from,recipient_count,created_at,delivered_at\r\n";
+
+/// Quote a CSV field if needed (RFC 4180)
+fn csv_field(value: &str) -> std::borrow::Cow<'_, str> {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\"")).into()
+    } else {
+        value.into()
+    }
+}
+
+fn csv_row(message: &ExportedMessage) -> String {
+    format!(
+        "{},{},{},{},{},{}\r\n",
+        message.id,
+        message.status.to_string().to_lowercase(),
+        csv_field(&message.from_email),
+        message.recipient_count,
+        message.created_at.to_rfc3339(),
+        message
+            .delivered_at
+            .map(|delivered_at| delivered_at.to_rfc3339())
+            .unwrap_or_default(),
+    )
+}
+
+/// Export the email messages of a project as CSV
+///
+/// Exports one row per message, oldest first, with the columns `id`, `status`, `from`,
+/// `recipient_count`, `created_at`, and `delivered_at`. Use the `after` and `before` query
+/// parameters to limit the export to messages created in that range.
+#[utoipa::path(
+    get,
+    path = "/organizations/{org_id}/projects/{project_id}/emails/export",
+    params(ExportFilter),
+    tags = ["Emails"],
+    responses(
+        (status = 200, description = "Successfully exported messages", content_type = "text/csv", body = String),
+        AppError
+    )
+)]
+pub async fn export_messages(
+    State(repo): State<MessageRepository>,
+    Path((org_id, project_id)): Path<(OrganizationId, ProjectId)>,
+    ValidatedQuery(filter): ValidatedQuery<ExportFilter>,
+    user: Box<dyn Authenticated>,
+) -> Result<impl IntoResponse, AppError> {
+    user.has_org_read_access(&org_id)?;
+
+    debug!(
+        user_id = user.log_id(),
+        organization_id = org_id.to_string(),
+        project_id = project_id.to_string(),
+        "exporting messages",
+    );
+
+    let rows = repo
+        .stream_export(org_id, project_id, filter)
+        .map_ok(|page| page.iter().map(csv_row).collect::<String>())
+        .inspect_err(move |e| {
+            error!(
+                organization_id = org_id.to_string(),
+                project_id = project_id.to_string(),
+                "failed to export messages: {e:?}"
+            )
+        });
+    let csv = stream::once(async { Ok(EXPORT_CSV_HEADER.to_owned()) }).chain(rows);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"emails.csv\"",
+            ),
+        ],
+        Body::from_stream(csv),
+    ))
 }
 
 /// Delete email message
+///
+/// Deleted messages are no longer listed nor delivered. Organization admins can restore them
+/// within 7 days, after which the message data is removed.
 #[utoipa::path(
     delete,
     path = "/organizations/{org_id}/emails/{message_id}",
@@ -369,11 +858,44 @@ pub async fn remove_message(
     Ok(Json(id))
 }
 
+/// Restore a deleted email message
+///
+/// Only organization admins can restore messages, and only within 7 days after their deletion.
+/// Pending delivery attempts of the message resume once it is restored.
+#[utoipa::path(
+    put,
+    path = "/organizations/{org_id}/emails/{message_id}/restore",
+    tags = ["Emails"],
+    responses(
+        (status = 200, description = "Successfully restored message", body = MessageId),
+        AppError
+    )
+)]
+pub async fn restore_message(
+    State(repo): State<MessageRepository>,
+    Path((org_id, message_id)): Path<(OrganizationId, MessageId)>,
+    user: Box<dyn Authenticated>,
+) -> ApiResult<MessageId> {
+    user.has_org_admin_access(&org_id)?;
+
+    let id = repo.restore(org_id, message_id).await?;
+
+    info!(
+        user_id = user.log_id(),
+        organization_id = org_id.to_string(),
+        message_id = message_id.to_string(),
+        "restored message",
+    );
+
+    Ok(Json(id))
+}
+
 /// Retry email message
 ///
 /// This will trigger a retry.
 /// It will try to resend the message to any recipients whose delivery attempts did not yet succeed and
 /// who have not previously generated a permanent failure response.
+/// Messages can't be retried while the organization has no message quota left.
 #[utoipa::path(
     put,
     path = "/organizations/{org_id}/emails/{message_id}/retry",
@@ -385,6 +907,7 @@ pub async fn remove_message(
 )]
 pub async fn retry_now(
     State(repo): State<MessageRepository>,
+    State(organizations): State<OrganizationRepository>,
     State(bus_client): State<Arc<BusClient>>,
     Path((org_id, message_id)): Path<(OrganizationId, MessageId)>,
     user: Box<dyn Authenticated>,
@@ -393,7 +916,7 @@ pub async fn retry_now(
 
     let status = repo.message_status(org_id, message_id).await?;
 
-    if status == MessageStatus::Delivered {
+    if status.status == MessageStatus::Delivered {
         warn!(
             message_id = message_id.to_string(),
             user_id = user.log_id(),
@@ -404,10 +927,18 @@ pub async fn retry_now(
         ));
     }
 
+    // the message would only be held again
+    organizations.check_quota(org_id).await?;
+
     match repo.get_ready_to_send(message_id).await {
         Ok(bus_message) => {
             bus_client.try_send(&bus_message).await;
         }
+        Err(crate::models::Error::SendingPaused) => {
+            return Err(AppError::Conflict(
+                "Sending is paused for this organization".to_string(),
+            ));
+        }
         Err(e) => {
             error!(message_id = message_id.to_string(), "{e:?}");
         }
@@ -423,54 +954,226 @@ pub async fn retry_now(
     Ok(())
 }
 
-/// List email labels
+/// Requeue email message
 ///
-/// Lists all labels that exist on at least one email message within that project.
+/// Gives a message that needs review after exhausting its automatic retries another round of
+/// automatic retries, the first of which starts right away.
 #[utoipa::path(
-    get,
-    path = "/organizations/{org_id}/emails/labels",
+    put,
+    path = "/organizations/{org_id}/emails/{message_id}/requeue",
     tags = ["Emails"],
     responses(
-        (status = 200, description = "Successfully fetched labels", body = [Label]),
+        (status = 200, description = "Successfully requeued message"),
         AppError
     )
 )]
-pub async fn list_labels(
+pub async fn requeue_message(
     State(repo): State<MessageRepository>,
-    Path(org_id): Path<OrganizationId>,
+    State(retry_config): State<Arc<RetryConfig>>,
+    Path((org_id, message_id)): Path<(OrganizationId, MessageId)>,
     user: Box<dyn Authenticated>,
-) -> ApiResult<Vec<Label>> {
-    user.has_org_read_access(&org_id)?;
-    let labels = repo.list_labels(org_id).await?;
+) -> Result<(), AppError> {
+    user.has_org_admin_access(&org_id)?;
 
-    Ok(Json(labels))
+    let status = repo.message_status(org_id, message_id).await?;
+    if status.status != MessageStatus::NeedsReview {
+        return Err(AppError::Conflict(
+            "Only messages that need review can be requeued".to_string(),
+        ));
+    }
+
+    repo.requeue_for_retries(org_id, message_id, retry_config.max_automatic_retries)
+        .await?;
+
+    info!(
+        user_id = user.log_id(),
+        organization_id = org_id.to_string(),
+        message_id = message_id.to_string(),
+        "requeued message after review",
+    );
+
+    Ok(())
 }
 
-/// List suppressed email addresses
+/// List stuck email messages
 ///
-/// Email addresses are automatically suppressed when delivery fails repeatedly.
-/// Once an address is suppressed, Remails stops sending emails to it to prevent further failed deliveries.
-/// Occasionally, Remails may retry delivery to a suppressed address to check whether it has become reachable again.
-/// Alternatively, it is also possible to manually remove email addresses from the suppression list.
+/// Lists messages across all organizations that are in a non-terminal state
+/// and have not been updated for a while, least recently updated first.
 #[utoipa::path(
     get,
-    path = "/organizations/{org_id}/emails/suppressed",
-    tags = ["Emails"],
+    path = "/emails/stuck",
+    params(StuckMessageFilter),
+    tags = ["internal", "Emails"],
+    security(("cookieAuth" = [])),
     responses(
-        (status = 200, description = "Successfully fetched suppressed email addresses", body = [SuppressedEmailAddress]),
+        (status = 200, description = "Successfully fetched stuck messages", body = [StuckMessage]),
         AppError
     )
 )]
-pub async fn list_suppressed(
-    State(repo): State<SuppressedRepository>,
-    Path(org_id): Path<OrganizationId>,
-    user: Box<dyn Authenticated>,
-) -> ApiResult<Vec<SuppressedEmailAddress>> {
-    user.has_org_read_access(&org_id)?;
-    let suppressed = repo.list_suppressed(org_id).await?;
+pub async fn list_stuck_messages(
+    State(repo): State<MessageRepository>,
+    ValidatedQuery(filter): ValidatedQuery<StuckMessageFilter>,
+    user: ApiUser,
+) -> ApiResult<Vec<StuckMessage>> {
+    if !user.is_super_admin() {
+        warn!(
+            user_id = user.id().to_string(),
+            "User is not permitted to list stuck messages"
+        );
+        return Err(AppError::Forbidden);
+    }
 
-    Ok(Json(suppressed))
-}
+    let messages = repo.list_stuck(&filter).await?;
+
+    debug!(
+        user_id = user.id().to_string(),
+        "listed {} stuck messages",
+        messages.len()
+    );
+
+    Ok(Json(messages))
+}
+
+/// Get the email queue depth
+///
+/// Counts the messages across all organizations that are in a non-terminal state,
+/// and reports the age of the oldest message that is waiting to be delivered.
+/// Use this to notice a backing up delivery pipeline before messages start to fail.
+#[utoipa::path(
+    get,
+    path = "/emails/queue",
+    tags = ["internal", "Emails"],
+    security(("cookieAuth" = [])),
+    responses(
+        (status = 200, description = "Successfully fetched queue depth", body = QueueDepth),
+        AppError
+    )
+)]
+pub async fn get_queue_depth(
+    State(repo): State<MessageRepository>,
+    user: ApiUser,
+) -> ApiResult<QueueDepth> {
+    if !user.is_super_admin() {
+        warn!(
+            user_id = user.id().to_string(),
+            "User is not permitted to fetch the queue depth"
+        );
+        return Err(AppError::Forbidden);
+    }
+
+    let depth = repo.queue_depth().await?;
+
+    debug!(user_id = user.id().to_string(), "fetched queue depth");
+
+    Ok(Json(depth))
+}
+
+/// Force a retry of an email message
+///
+/// Puts the message back into the send pipeline, regardless of the organization it belongs to.
+#[utoipa::path(
+    put,
+    path = "/emails/{message_id}/force_retry",
+    tags = ["internal", "Emails"],
+    security(("cookieAuth" = [])),
+    responses(
+        (status = 200, description = "Successfully initiated retry"),
+        AppError
+    )
+)]
+pub async fn force_retry(
+    State(repo): State<MessageRepository>,
+    State(bus_client): State<Arc<BusClient>>,
+    Path(message_id): Path<MessageId>,
+    user: ApiUser,
+) -> Result<(), AppError> {
+    if !user.is_super_admin() {
+        warn!(
+            user_id = user.id().to_string(),
+            message_id = message_id.to_string(),
+            "User is not permitted to force a message retry"
+        );
+        return Err(AppError::Forbidden);
+    }
+
+    if repo.status_of(message_id).await? == MessageStatus::Delivered {
+        return Err(AppError::BadRequest(
+            "Message already delivered".to_string(),
+        ));
+    }
+
+    match repo.get_ready_to_send(message_id).await {
+        Ok(bus_message) => {
+            bus_client.try_send(&bus_message).await;
+        }
+        Err(crate::models::Error::SendingPaused) => {
+            return Err(AppError::Conflict(
+                "Sending is paused for this organization".to_string(),
+            ));
+        }
+        Err(e) => {
+            error!(message_id = message_id.to_string(), "{e:?}");
+            return Err(e.into());
+        }
+    }
+
+    info!(
+        user_id = user.id().to_string(),
+        message_id = message_id.to_string(),
+        "forced message retry",
+    );
+
+    Ok(())
+}
+
+/// List email labels
+///
+/// Lists all labels that exist on at least one email message within that project.
+#[utoipa::path(
+    get,
+    path = "/organizations/{org_id}/emails/labels",
+    tags = ["Emails"],
+    responses(
+        (status = 200, description = "Successfully fetched labels", body = [Label]),
+        AppError
+    )
+)]
+pub async fn list_labels(
+    State(repo): State<MessageRepository>,
+    Path(org_id): Path<OrganizationId>,
+    user: Box<dyn Authenticated>,
+) -> ApiResult<Vec<Label>> {
+    user.has_org_read_access(&org_id)?;
+    let labels = repo.list_labels(org_id).await?;
+
+    Ok(Json(labels))
+}
+
+/// List suppressed email addresses
+///
+/// Email addresses are automatically suppressed when delivery fails repeatedly.
+/// Once an address is suppressed, Remails stops sending emails to it to prevent further failed deliveries.
+/// Occasionally, Remails may retry delivery to a suppressed address to check whether it has become reachable again.
+/// Alternatively, it is also possible to manually remove email addresses from the suppression list.
+#[utoipa::path(
+    get,
+    path = "/organizations/{org_id}/emails/suppressed",
+    tags = ["Emails"],
+    responses(
+        (status = 200, description = "Successfully fetched suppressed email addresses", body = [SuppressedEmailAddress]),
+        AppError
+    )
+)]
+pub async fn list_suppressed(
+    State(repo): State<SuppressedRepository>,
+    Path(org_id): Path<OrganizationId>,
+    user: Box<dyn Authenticated>,
+) -> ApiResult<Vec<SuppressedEmailAddress>> {
+    user.has_org_read_access(&org_id)?;
+    let suppressed = repo.list_suppressed(org_id).await?;
+
+    Ok(Json(suppressed))
+}
 
 /// Remove an email address from the suppression list
 #[utoipa::path(
@@ -498,6 +1201,7 @@ mod tests {
     use super::*;
     use crate::{
         api::{
+            RemailsConfig,
             error::ApiErrorResponse,
             tests::{TestServer, deserialize_body, serialize_body},
         },
@@ -507,7 +1211,6 @@ mod tests {
         periodically::Periodically,
         test::TestProjects,
     };
-    use axum::body::Body;
     use chrono::Utc;
     use futures::StreamExt;
     use http::StatusCode;
@@ -626,8 +1329,13 @@ mod tests {
             .unwrap()
             .unwrap();
         assert_eq!(
-            bus_message,
-            BusMessage::EmailReadyToSend(message.id(), "127.0.0.1".parse().unwrap())
+            bus_message.message,
+            BusMessage::EmailReadyToSend(
+                message.id(),
+                "127.0.0.1".parse().unwrap(),
+                None,
+                MessagePriority::High
+            )
         );
 
         // get organization statistics
@@ -665,6 +1373,23 @@ mod tests {
         let mut new_stats: Statistics = deserialize_body(response.into_body()).await;
         new_stats.sort();
         assert_eq!(stats, new_stats);
+
+        // restore message
+        let response = server
+            .put(
+                format!("/api/organizations/{org_1}/emails/{message_1}/restore"),
+                Body::empty(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // check if message is visible again
+        let response = server
+            .get(format!("/api/organizations/{org_1}/emails/{message_1}"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
     }
 
     async fn test_messages_no_access(
@@ -689,6 +1414,32 @@ mod tests {
             .unwrap();
         assert_eq!(response.status(), read_status_code);
 
+        // can't get raw message
+        let response = server
+            .get(format!("/api/organizations/{org_1}/emails/{message_1}/raw"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), read_status_code);
+
+        // can't get message status
+        let response = server
+            .get(format!(
+                "/api/organizations/{org_1}/emails/{message_1}/status"
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), read_status_code);
+
+        // can't export messages
+        let proj_1 = TestProjects::Org1Project1.project_id();
+        let response = server
+            .get(format!(
+                "/api/organizations/{org_1}/projects/{proj_1}/emails/export"
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), read_status_code);
+
         // can't update message to retry asap
         let response = server
             .put(
@@ -699,6 +1450,16 @@ mod tests {
             .unwrap();
         assert_eq!(response.status(), write_status_code);
 
+        // can't requeue message
+        let response = server
+            .put(
+                format!("/api/organizations/{org_1}/emails/{message_1}/requeue"),
+                Body::empty(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), write_status_code);
+
         // can't remove message
         let response = server
             .delete(format!("/api/organizations/{org_1}/emails/{message_1}"))
@@ -706,6 +1467,16 @@ mod tests {
             .unwrap();
         assert_eq!(response.status(), write_status_code);
 
+        // can't restore message
+        let response = server
+            .put(
+                format!("/api/organizations/{org_1}/emails/{message_1}/restore"),
+                Body::empty(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), write_status_code);
+
         // can't view suppressed email addresses
         let response = server
             .get(format!("/api/organizations/{org_1}/emails/suppressed"))
@@ -825,279 +1596,1106 @@ mod tests {
             "messages"
         )
     ))]
-    async fn test_fetch_message_validation(pool: PgPool) {
+    async fn test_get_message_status(pool: PgPool) {
         let org_1 = TestProjects::Org1Project1.org_id();
-        let user_4 = "c33dbd88-43ed-404b-9367-1659a73c8f3a".parse().unwrap(); // is maintainer of org 1
-        let mut server = TestServer::new(pool.clone(), Some(user_4)).await;
-        server.use_api_key(org_1, Role::Maintainer).await;
+        let user_1 = "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap(); // is admin of org 1 and 2
+        let server = TestServer::new(pool.clone(), Some(user_1)).await;
+        let message_held = "10d5ad5f-04ae-489b-9f5a-f5d7e73bc12a"; // held after 1 of 3 attempts
 
-        let too_low_limit = server
-            .get(format!("/api/organizations/{org_1}/emails?limit=0"))
+        let response = server
+            .get(format!(
+                "/api/organizations/{org_1}/emails/{message_held}/status"
+            ))
             .await
             .unwrap();
-        assert_eq!(too_low_limit.status(), StatusCode::BAD_REQUEST);
-        let _: ApiErrorResponse = deserialize_body(too_low_limit.into_body()).await;
-
-        let too_high_limit = server
-            .get(format!("/api/organizations/{org_1}/emails?limit=101"))
+        assert_eq!(response.status(), StatusCode::OK);
+        let status: ApiMessageStatus = deserialize_body(response.into_body()).await;
+        assert_eq!(status.status, MessageStatus::Held);
+        assert_eq!(status.attempts, 1);
+        assert_eq!(status.max_attempts, 3);
+        // held messages are not retried until they are released
+        assert!(status.retry_after.is_none());
+
+        // a message waiting for a retry tells when it is retried
+        let message_reattempt = "2b7ca359-18da-4d90-90c5-ed43f7944585";
+        let response = server
+            .get(format!(
+                "/api/organizations/{org_1}/emails/{message_reattempt}/status"
+            ))
             .await
             .unwrap();
-        assert_eq!(too_high_limit.status(), StatusCode::BAD_REQUEST);
-        let _: ApiErrorResponse = deserialize_body(too_high_limit.into_body()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let status: ApiMessageStatus = deserialize_body(response.into_body()).await;
+        assert_eq!(status.status, MessageStatus::Reattempt);
+        assert!(status.retry_after.unwrap() > Utc::now());
 
-        let invalid_timestamp = server
+        // a message that was not attempted yet has no retry time
+        let message_1 = "e165562a-fb6d-423b-b318-fd26f4610634";
+        let response = server
             .get(format!(
-                "/api/organizations/{org_1}/emails?before=invalid_time"
+                "/api/organizations/{org_1}/emails/{message_1}/status"
             ))
             .await
             .unwrap();
-        assert_eq!(invalid_timestamp.status(), StatusCode::BAD_REQUEST);
-        let _: ApiErrorResponse = deserialize_body(invalid_timestamp.into_body()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let status: ApiMessageStatus = deserialize_body(response.into_body()).await;
+        assert_eq!(status.status, MessageStatus::Processing);
+        assert_eq!(status.attempts, 0);
+        assert_eq!(status.max_attempts, 3);
+        assert!(status.retry_after.is_none());
+
+        // unknown messages are not found
+        let unknown = MessageId::new_v4();
+        let response = server
+            .get(format!(
+                "/api/organizations/{org_1}/emails/{unknown}/status"
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 
     #[sqlx::test(fixtures(
         path = "../fixtures",
-        scripts("organizations", "api_users", "projects", "smtp_credentials")
+        scripts(
+            "organizations",
+            "api_users",
+            "projects",
+            "smtp_credentials",
+            "messages"
+        )
     ))]
-    async fn test_create_message_validation(pool: PgPool) {
-        let (org_1, proj_1) = TestProjects::Org1Project1.get_ids();
-        let user_4 = "c33dbd88-43ed-404b-9367-1659a73c8f3a".parse().unwrap(); // is maintainer of org 1
-        let mut server = TestServer::new(pool.clone(), Some(user_4)).await;
-        server.use_api_key(org_1, Role::Maintainer).await;
+    async fn test_get_message_not_modified(pool: PgPool) {
+        let org_1 = TestProjects::Org1Project1.org_id();
+        let user_1 = "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap(); // is admin of org 1 and 2
+        let mut server = TestServer::new(pool.clone(), Some(user_1)).await;
+        let message_1 = "e165562a-fb6d-423b-b318-fd26f4610634";
+        let path = format!("/api/organizations/{org_1}/emails/{message_1}");
 
-        let too_many_recipients = server
-            .post(
-                format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
-                serialize_body(json!({
-                    "from": "test@example.com",
-                    "to": [
-                        "recipient1@example.com",
-                        "recipient2@example.com",
-                        "recipient3@example.com",
-                        "recipient4@example.com",
-                        "recipient5@example.com",
-                        "recipient6@example.com",
-                        "recipient7@example.com",
-                        "recipient8@example.com",
-                        "recipient9@example.com",
-                        "recipient10@example.com",
-                        "recipient11@example.com",
-                    ],
-                })),
-            )
+        let response = server.get(&path).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let _: ApiMessage = deserialize_body(response.into_body()).await;
+
+        // a repeated request with the ETag is not modified
+        server.set_header("if-none-match", Some(etag.clone()));
+        let response = server.get(&path).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], etag.as_str());
+        let body = axum::body::to_bytes(response.into_body(), 8192)
             .await
             .unwrap();
-        assert_eq!(too_many_recipients.status(), StatusCode::BAD_REQUEST);
+        assert!(body.is_empty());
 
-        let invalid_email = server
-            .post(
-                format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
-                serialize_body(json!({
-                    "from": "test@example.com",
-                    "to": "recipient1atexample.com"
-                })),
-            )
-            .await
-            .unwrap();
-        assert_eq!(invalid_email.status(), StatusCode::BAD_REQUEST);
+        // weak and listed tags match as well
+        server.set_header("if-none-match", Some(format!("\"other\", W/{etag}")));
+        let response = server.get(&path).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
 
-        let invalid_email_list = server
-            .post(
-                format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
-                serialize_body(json!({
-                    "from": "test@example.com",
-                    "to": ["recipient1atexample.com"]
+        // once the message changes, the full message is returned with a new ETag
+        sqlx::query!(
+            "UPDATE messages SET reason = 'changed' WHERE id = $1",
+            message_1.parse::<uuid::Uuid>().unwrap()
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
 
-                })),
+        server.set_header("if-none-match", Some(etag.clone()));
+        let response = server.get(&path).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(response.headers()[header::ETAG], etag.as_str());
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "api_users",
+            "projects",
+            "smtp_credentials",
+            "messages"
+        )
+    ))]
+    async fn test_requeue_message(pool: PgPool) {
+        let org_1 = TestProjects::Org1Project1.org_id();
+        let user_1 = "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap(); // is admin of org 1 and 2
+        let server = TestServer::new(pool.clone(), Some(user_1)).await;
+        let message_1: MessageId = "e165562a-fb6d-423b-b318-fd26f4610634".parse().unwrap();
+        let requeue = async || {
+            server
+                .put(
+                    format!("/api/organizations/{org_1}/emails/{message_1}/requeue"),
+                    Body::empty(),
+                )
+                .await
+                .unwrap()
+                .status()
+        };
+
+        // messages that do not need review cannot be requeued
+        assert_eq!(requeue().await, StatusCode::CONFLICT);
+
+        sqlx::query!(
+            "UPDATE messages SET status = 'needs_review', attempts = 3, max_attempts = 3 WHERE id = $1",
+            *message_1
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        assert_eq!(requeue().await, StatusCode::OK);
+        let status = MessageRepository::new(pool.clone())
+            .message_status(org_1, message_1)
+            .await
+            .unwrap();
+        assert_eq!(status.status, MessageStatus::Reattempt);
+        assert_eq!(status.attempts, 3);
+        assert!(status.max_attempts > 3);
+        assert!(status.retry_after.unwrap() <= Utc::now());
+
+        assert_eq!(requeue().await, StatusCode::CONFLICT);
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "api_users",
+            "projects",
+            "smtp_credentials",
+            "messages"
+        )
+    ))]
+    async fn test_get_raw_message(pool: PgPool) {
+        let org_1 = TestProjects::Org1Project1.org_id();
+        let user_1 = "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap(); // is admin of org 1 and 2
+        let server = TestServer::new(pool.clone(), Some(user_1)).await;
+        let message_1 = "e165562a-fb6d-423b-b318-fd26f4610634";
+
+        // a message much larger than the truncation length, spanning multiple chunks
+        let body = (0..40_000)
+            .map(|i| format!("line {i:05} of a very long message\r\n"))
+            .collect::<String>();
+        let raw_data = format!("From: john@test-org-1.com\r\nSubject: Large\r\n\r\n{body}");
+        assert!(raw_data.len() > 1_000_000);
+        sqlx::query!(
+            "UPDATE messages SET raw_data = $2 WHERE id = $1",
+            message_1.parse::<uuid::Uuid>().unwrap(),
+            raw_data.as_bytes()
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        // the regular endpoint truncates the raw data
+        let response = server
+            .get(format!("/api/organizations/{org_1}/emails/{message_1}"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let message: ApiMessage = deserialize_body(response.into_body()).await;
+        assert!(message.truncated_raw_data.len() < raw_data.len());
+
+        // the raw endpoint returns the full message
+        let response = server
+            .get(format!("/api/organizations/{org_1}/emails/{message_1}/raw"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "message/rfc822"
+        );
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(bytes.len(), raw_data.len());
+        assert_eq!(bytes, raw_data.as_bytes());
+
+        // unknown messages are not found
+        let unknown = MessageId::new_v4();
+        let response = server
+            .get(format!("/api/organizations/{org_1}/emails/{unknown}/raw"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "api_users",
+            "projects",
+            "smtp_credentials",
+            "messages"
+        )
+    ))]
+    async fn test_export_messages(pool: PgPool) {
+        let (org_1, proj_1) = TestProjects::Org1Project1.get_ids();
+        let user_1 = "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap(); // is admin of org 1 and 2
+        let server = TestServer::new(pool.clone(), Some(user_1)).await;
+        let message_1 = "e165562a-fb6d-423b-b318-fd26f4610634";
+
+        sqlx::query!(
+            r#"
+            UPDATE messages
+            SET status = 'delivered',
+                delivery_details = '{
+                    "info@recipient1.com": {"status": {"type": "Success", "delivered": "2025-11-20T14:34:00Z"}, "log": {"lines": []}},
+                    "info@recipient2.com": {"status": {"type": "Success", "delivered": "2025-11-20T14:35:00Z"}, "log": {"lines": []}}
+                }'
+            WHERE id = $1
+            "#,
+            message_1.parse::<uuid::Uuid>().unwrap(),
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let response = server
+            .get(format!(
+                "/api/organizations/{org_1}/projects/{proj_1}/emails/export"
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/csv; charset=utf-8"
+        );
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let csv = String::from_utf8(bytes.to_vec()).unwrap();
+        let mut lines = csv.split_terminator("\r\n");
+        assert_eq!(
+            lines.next(),
+            Some("id,status,from,recipient_count,created_at,delivered_at")
+        );
+        let row = lines
+            .find(|line| line.starts_with(message_1))
+            .expect("seeded message is exported");
+        let columns: Vec<&str> = row.split(',').collect();
+        assert_eq!(columns[1..4], ["delivered", "email@test-org-1.com", "2"]);
+        assert_eq!(columns[5], "2025-11-20T14:35:00+00:00");
+
+        // messages created outside the range are left out
+        let response = server
+            .get(format!(
+                "/api/organizations/{org_1}/projects/{proj_1}/emails/export?after=2999-01-01T00:00:00Z"
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(bytes, EXPORT_CSV_HEADER.as_bytes());
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "api_users",
+            "projects",
+            "smtp_credentials",
+            "messages"
+        )
+    ))]
+    async fn test_fetch_message_validation(pool: PgPool) {
+        let org_1 = TestProjects::Org1Project1.org_id();
+        let user_4 = "c33dbd88-43ed-404b-9367-1659a73c8f3a".parse().unwrap(); // is maintainer of org 1
+        let mut server = TestServer::new(pool.clone(), Some(user_4)).await;
+        server.use_api_key(org_1, Role::Maintainer).await;
+
+        let too_low_limit = server
+            .get(format!("/api/organizations/{org_1}/emails?limit=0"))
+            .await
+            .unwrap();
+        assert_eq!(too_low_limit.status(), StatusCode::BAD_REQUEST);
+        let _: ApiErrorResponse = deserialize_body(too_low_limit.into_body()).await;
+
+        let too_high_limit = server
+            .get(format!("/api/organizations/{org_1}/emails?limit=101"))
+            .await
+            .unwrap();
+        assert_eq!(too_high_limit.status(), StatusCode::BAD_REQUEST);
+        let _: ApiErrorResponse = deserialize_body(too_high_limit.into_body()).await;
+
+        let invalid_timestamp = server
+            .get(format!(
+                "/api/organizations/{org_1}/emails?before=invalid_time"
+            ))
+            .await
+            .unwrap();
+        assert_eq!(invalid_timestamp.status(), StatusCode::BAD_REQUEST);
+        let _: ApiErrorResponse = deserialize_body(invalid_timestamp.into_body()).await;
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "api_users", "projects", "smtp_credentials")
+    ))]
+    async fn test_create_message_validation(pool: PgPool) {
+        let (org_1, proj_1) = TestProjects::Org1Project1.get_ids();
+        let user_4 = "c33dbd88-43ed-404b-9367-1659a73c8f3a".parse().unwrap(); // is maintainer of org 1
+        let mut server = TestServer::new(pool.clone(), Some(user_4)).await;
+        server.use_api_key(org_1, Role::Maintainer).await;
+
+        let too_many_recipients = server
+            .post(
+                format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
+                serialize_body(json!({
+                    "from": "test@example.com",
+                    "to": [
+                        "recipient1@example.com",
+                        "recipient2@example.com",
+                        "recipient3@example.com",
+                        "recipient4@example.com",
+                        "recipient5@example.com",
+                        "recipient6@example.com",
+                        "recipient7@example.com",
+                        "recipient8@example.com",
+                        "recipient9@example.com",
+                        "recipient10@example.com",
+                        "recipient11@example.com",
+                    ],
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(too_many_recipients.status(), StatusCode::BAD_REQUEST);
+
+        let invalid_email = server
+            .post(
+                format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
+                serialize_body(json!({
+                    "from": "test@example.com",
+                    "to": "recipient1atexample.com"
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(invalid_email.status(), StatusCode::BAD_REQUEST);
+
+        let invalid_email_list = server
+            .post(
+                format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
+                serialize_body(json!({
+                    "from": "test@example.com",
+                    "to": ["recipient1atexample.com"]
+
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(invalid_email_list.status(), StatusCode::BAD_REQUEST);
+
+        let missing_recipient = server
+            .post(
+                format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
+                serialize_body(json!({
+                    "from": "test@example.com",
+                    "to": [],
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(missing_recipient.status(), StatusCode::BAD_REQUEST);
+
+        let too_long_subject = server
+            .post(
+                format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
+                serialize_body(json!({
+                    "from": "test@example.com",
+                    "to": ["recipient1@example.com"],
+                    "subject": "A".repeat(501)
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(too_long_subject.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "api_users", "projects", "smtp_credentials")
+    ))]
+    async fn test_message_client_ip(pool: PgPool) {
+        let (org_1, proj_1) = TestProjects::Org1Project1.get_ids();
+        let user_1 = "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap(); // is admin of org 1
+        let user_4 = "c33dbd88-43ed-404b-9367-1659a73c8f3a".parse().unwrap(); // is maintainer of org 1
+        let mut server = TestServer::with_config(
+            pool.clone(),
+            Some(user_1),
+            RemailsConfig {
+                trusted_proxy_hops: 1,
+                ..Default::default()
+            },
+        )
+        .await;
+        server.use_api_key(org_1, Role::Maintainer).await;
+
+        // the last address is the one added by the reverse proxy
+        server.set_header(
+            "X-Forwarded-For",
+            Some("203.0.113.7, 198.51.100.23".to_string()),
+        );
+        let response = server
+            .post(
+                format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
+                serialize_body(json!({
+                    "from": "test@example.com",
+                    "to": "recipient@example.com",
+                    "subject": "subject",
+                    "text_body": "text body",
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let message: ApiMessageMetadata = deserialize_body(response.into_body()).await;
+        // the API key is not an organization admin
+        assert_eq!(message.client_ip, None);
+
+        // organization admins can see the client IP
+        server.set_header("Authorization", None);
+        server.set_user(Some(user_1));
+        let response = server
+            .get(format!("/api/organizations/{org_1}/emails"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let messages: Vec<ApiMessageMetadata> = deserialize_body(response.into_body()).await;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(
+            messages[0].client_ip,
+            Some("198.51.100.23".parse().unwrap())
+        );
+
+        // other members cannot
+        server.set_user(Some(user_4));
+        let response = server
+            .get(format!("/api/organizations/{org_1}/emails/{}", message.id))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let message: serde_json::Value = deserialize_body(response.into_body()).await;
+        assert_eq!(message["client_ip"], serde_json::Value::Null);
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "api_users",
+            "projects",
+            "smtp_credentials",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_message_correlation_id(pool: PgPool) {
+        let (org_1, proj_1) = TestProjects::Org1Project1.get_ids();
+        let user_4 = "c33dbd88-43ed-404b-9367-1659a73c8f3a".parse().unwrap(); // is maintainer of org 1
+        let mut server = TestServer::new(pool.clone(), Some(user_4)).await;
+        server.use_api_key(org_1, Role::Maintainer).await;
+        let mut message_stream = server.message_bus.receive().await.unwrap();
+
+        let create_message = async |server: &TestServer| {
+            let response = server
+                .post(
+                    format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
+                    serialize_body(json!({
+                        "from": "test@example.com",
+                        "to": "recipient@example.com",
+                        "subject": "subject",
+                        "text_body": "text body",
+                    })),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+            let header = response.headers()[&X_CORRELATION_ID]
+                .to_str()
+                .unwrap()
+                .to_owned();
+            let message: ApiMessageMetadata = deserialize_body(response.into_body()).await;
+            (header, message)
+        };
+
+        // a provided correlation ID is stored on the message and passed on for delivery
+        server.set_header("X-Correlation-Id", Some("order-1234".to_owned()));
+        let (header, message) = create_message(&server).await;
+        assert_eq!(header, "order-1234");
+        assert_eq!(message.correlation_id.as_deref(), Some("order-1234"));
+
+        let bus_message = tokio::time::timeout(Duration::from_secs(10), message_stream.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            bus_message.message,
+            BusMessage::EmailReadyToSend(id, _, Some(correlation_id), _)
+                if id == message.id && correlation_id == "order-1234"
+        ));
+
+        // otherwise, a correlation ID is generated
+        for provided in [None, Some("not a valid id".to_owned())] {
+            server.set_header("X-Correlation-Id", provided);
+            let (header, message) = create_message(&server).await;
+            assert!(header.parse::<uuid::Uuid>().is_ok());
+            assert_eq!(message.correlation_id, Some(header));
+        }
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "api_users", "projects", "smtp_credentials")
+    ))]
+    async fn test_create_message(pool: PgPool) {
+        let (org_1, proj_1) = TestProjects::Org1Project1.get_ids();
+        let user_4 = "c33dbd88-43ed-404b-9367-1659a73c8f3a".parse().unwrap(); // is maintainer of org 1
+        let mut server = TestServer::new(pool.clone(), Some(user_4)).await;
+        let api_key_id = server.use_api_key(org_1, Role::Maintainer).await;
+
+        // send email with 1 recipient, text and HTML body, `in_reply_to`, `references` and `reply_to`
+        let response = server
+            .post(
+                format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
+                serialize_body(json!({
+                    "from": "test@example.com",
+                    "to": "recipient@example.com",
+                    "subject": "subject",
+                    "text_body": "text body",
+                    "html_body": "<h1>html body</h1>",
+                    "in_reply_to": "some-message@example.com",
+                    "references": ["some-message@example.com", "some-other-message@example.com"],
+                    "reply_to": "support@example.com",
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let message: ApiMessageMetadata = deserialize_body(response.into_body()).await;
+        assert_eq!(message.from_email.as_str(), "test@example.com");
+        assert_eq!(message.recipients.len(), 1);
+        assert_eq!(message.recipients[0].as_str(), "recipient@example.com");
+        assert_eq!(message.smtp_credential_id, None);
+        assert_eq!(message.api_key_id, Some(api_key_id));
+        assert_eq!(
+            message.message_id_header,
+            format!("REMAILS-{}@example.com", message.id)
+        );
+        assert_eq!(message.label, None);
+
+        // send email with 2 recipients, only text body, and custom from name
+        let response = server
+            .post(
+                format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
+                serialize_body(json!({
+                    "from": {"name": "Test", "address": "test@example.com"},
+                    "to": ["recipient1@example.com", "recipient2@example.com"],
+                    "subject": "subject",
+                    "text_body": "text body",
+                    "label": "Weekly UpDate",
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let message: ApiMessageMetadata = deserialize_body(response.into_body()).await;
+        assert_eq!(message.from_email.as_str(), "test@example.com");
+        let mut recipients = message
+            .recipients
+            .into_iter()
+            .map(|e| e.as_str().to_owned())
+            .collect::<Vec<_>>();
+        recipients.sort();
+        assert_eq!(
+            recipients,
+            vec!["recipient1@example.com", "recipient2@example.com"]
+        );
+        assert_eq!(message.label, Some(Label::new("weekly-update")));
+
+        // send email with 3 recipients, only HTML body
+        let response = server
+            .post(
+                format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
+                serialize_body(json!({
+                    "from": "test@example.com",
+                    "to": ["recipient1@example.com", "recipient2@example.com", {"name": "Recipient 3", "address": "recipient3@example.com"}],
+                    "subject": "subject",
+                    "text_body": "text body",
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let message: ApiMessageMetadata = deserialize_body(response.into_body()).await;
+        assert_eq!(message.from_email.as_str(), "test@example.com");
+        let mut recipients = message
+            .recipients
+            .into_iter()
+            .map(|e| e.as_str().to_owned())
+            .collect::<Vec<_>>();
+        recipients.sort();
+        assert_eq!(
+            recipients,
+            vec![
+                "recipient1@example.com",
+                "recipient2@example.com",
+                "recipient3@example.com"
+            ]
+        );
+
+        // get statistics
+        let response = server
+            .get(format!("/api/organizations/{org_1}/statistics"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let stats: Statistics = deserialize_body(response.into_body()).await;
+        assert_eq!(stats.monthly.len(), 1);
+        assert_eq!(stats.monthly[0].statistics, json!({"processing": 3}));
+        assert_eq!(stats.daily.len(), 1);
+        assert_eq!(stats.daily[0].statistics, json!({"processing": 3}));
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "api_users", "projects", "smtp_credentials")
+    ))]
+    async fn test_create_message_deduplication(pool: PgPool) {
+        let (org_1, proj_1) = TestProjects::Org1Project1.get_ids();
+        let user_4 = "c33dbd88-43ed-404b-9367-1659a73c8f3a".parse().unwrap(); // is maintainer of org 1
+        let mut server = TestServer::new(pool.clone(), Some(user_4)).await;
+        server.use_api_key(org_1, Role::Maintainer).await;
+
+        sqlx::query!(
+            "UPDATE projects SET dedup_window_minutes = 60 WHERE id = $1",
+            *proj_1
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let body = |message_id: Option<&str>| {
+            serialize_body(json!({
+                "from": "test@example.com",
+                "to": "recipient@example.com",
+                "subject": "subject",
+                "text_body": "text body",
+                "message_id": message_id,
+            }))
+        };
+        let uri = format!("/api/organizations/{org_1}/projects/{proj_1}/emails");
+
+        // the Message-ID must be a valid message identifier
+        let response = server
+            .post(&uri, body(Some("<order-1234@example.com>")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // submitting the same Message-ID concurrently only creates a single message
+        let (first, second) = tokio::join!(
+            server.post(&uri, body(Some("order-1234@example.com"))),
+            server.post(&uri, body(Some("order-1234@example.com")))
+        );
+        let mut statuses = [
+            first.as_ref().unwrap().status(),
+            second.as_ref().unwrap().status(),
+        ];
+        statuses.sort();
+        assert_eq!(statuses, [StatusCode::OK, StatusCode::CREATED]);
+        let first: ApiMessageMetadata = deserialize_body(first.unwrap().into_body()).await;
+        let second: ApiMessageMetadata = deserialize_body(second.unwrap().into_body()).await;
+        assert_eq!(first.id, second.id);
+        assert_eq!(first.message_id_header, "order-1234@example.com");
+
+        // resubmitting it later returns the existing message as well
+        let response = server
+            .post(&uri, body(Some("order-1234@example.com")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let resubmitted: ApiMessageMetadata = deserialize_body(response.into_body()).await;
+        assert_eq!(resubmitted.id, first.id);
+
+        // without a Message-ID, every submission is a new message
+        for _ in 0..2 {
+            let response = server.post(&uri, body(None)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+        }
+
+        // once the message is removed, the Message-ID can be used again
+        let response = server
+            .delete(format!("/api/organizations/{org_1}/emails/{}", first.id))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = server
+            .post(&uri, body(Some("order-1234@example.com")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let recreated: ApiMessageMetadata = deserialize_body(response.into_body()).await;
+        assert_ne!(recreated.id, first.id);
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "api_users", "projects", "smtp_credentials")
+    ))]
+    async fn test_create_message_rate_limit_headers(pool: PgPool) {
+        let (org_1, proj_1) = TestProjects::Org1Project1.get_ids();
+        let user_4 = "c33dbd88-43ed-404b-9367-1659a73c8f3a".parse().unwrap(); // is maintainer of org 1
+        let mut server = TestServer::new(pool.clone(), Some(user_4)).await;
+        server.use_api_key(org_1, Role::Maintainer).await;
+
+        let header = |response: &axum::response::Response, name: &HeaderName| -> i64 {
+            response
+                .headers()
+                .get(name)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .parse()
+                .unwrap()
+        };
+
+        let before = Utc::now().timestamp();
+        let response = server
+            .post(
+                format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
+                serialize_body(json!({
+                    "from": "test@example.com",
+                    "to": "recipient@example.com",
+                    "subject": "subject",
+                    "text_body": "text body",
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        // org 1 has a small subscription with 60 rate limit tokens, of which one was just used
+        assert_eq!(header(&response, &X_RATELIMIT_REMAINING), 59);
+        let reset = header(&response, &X_RATELIMIT_RESET);
+        assert!(reset >= before);
+        assert!(reset <= Utc::now().timestamp() + 60);
+        // the quota is only used once the message is actually being sent
+        assert_eq!(header(&response, &X_QUOTA_REMAINING), 800);
+
+        // use up all rate limit tokens
+        sqlx::query!(
+            "UPDATE organizations SET rate_limit_tokens = 0, rate_limit_last_used = now() WHERE id = $1",
+            *org_1
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let response = server
+            .post(
+                format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
+                serialize_body(json!({
+                    "from": "test@example.com",
+                    "to": "recipient@example.com",
+                    "subject": "subject",
+                    "text_body": "text body",
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(header(&response, &X_RATELIMIT_REMAINING), 0);
+        let reset = header(&response, &X_RATELIMIT_RESET);
+        assert!(reset > before);
+        assert_eq!(header(&response, &X_QUOTA_REMAINING), 800);
+
+        // the client may try again once the next token has been added, each second for org 1,
+        // long before the rate limit has been fully replenished
+        let retry_after = header(&response, &http::header::RETRY_AFTER);
+        assert!(retry_after > 0);
+        assert!(retry_after <= 1);
+        assert!(reset - before > 50);
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "api_users", "projects", "smtp_credentials")
+    ))]
+    async fn test_create_message_reject(pool: PgPool) {
+        let (org_1, proj_1) = TestProjects::Org1Project1.get_ids();
+        let user_4 = "c33dbd88-43ed-404b-9367-1659a73c8f3a".parse().unwrap(); // is maintainer of org 1
+        let mut server = TestServer::new(pool.clone(), Some(user_4)).await;
+        server.use_api_key(org_1, Role::Maintainer).await;
+
+        // reject emails without body
+        let response = server
+            .post(
+                format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
+                serialize_body(json!({
+                    "from": "test@example.com",
+                    "to": "recipient@example.com",
+                    "subject": "subject",
+                })),
             )
             .await
             .unwrap();
-        assert_eq!(invalid_email_list.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
 
-        let missing_recipient = server
+        // reject emails without recipients
+        let response = server
             .post(
                 format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
                 serialize_body(json!({
                     "from": "test@example.com",
                     "to": [],
+                    "subject": "subject",
+                    "text_body": "text body",
                 })),
             )
             .await
             .unwrap();
-        assert_eq!(missing_recipient.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
 
-        let too_long_subject = server
+        // reject emails with invalid from email
+        let response = server
             .post(
                 format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
                 serialize_body(json!({
-                    "from": "test@example.com",
-                    "to": ["recipient1@example.com"],
-                    "subject": "A".repeat(501)
+                    "from": "remails.net",
+                    "to": "recipient@example.com",
+                    "subject": "subject",
+                    "text_body": "text body",
                 })),
             )
             .await
             .unwrap();
-        assert_eq!(too_long_subject.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
     #[sqlx::test(fixtures(
         path = "../fixtures",
-        scripts("organizations", "api_users", "projects", "smtp_credentials")
+        scripts(
+            "organizations",
+            "api_users",
+            "projects",
+            "org_domains",
+            "proj_domains"
+        )
     ))]
-    async fn test_create_message(pool: PgPool) {
+    async fn test_validate_message(pool: PgPool) {
         let (org_1, proj_1) = TestProjects::Org1Project1.get_ids();
         let user_4 = "c33dbd88-43ed-404b-9367-1659a73c8f3a".parse().unwrap(); // is maintainer of org 1
         let mut server = TestServer::new(pool.clone(), Some(user_4)).await;
-        let api_key_id = server.use_api_key(org_1, Role::Maintainer).await;
+        server.use_api_key(org_1, Role::Maintainer).await;
+        let organizations = OrganizationRepository::new(pool.clone());
+        let quota_before = organizations.remaining_quota(org_1).await.unwrap();
 
-        // send email with 1 recipient, text and HTML body, `in_reply_to`, `references` and `reply_to`
+        // a message from the domain of the project
         let response = server
             .post(
-                format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
+                format!("/api/organizations/{org_1}/projects/{proj_1}/emails/validate"),
                 serialize_body(json!({
-                    "from": "test@example.com",
-                    "to": "recipient@example.com",
+                    "from": "john@test-org-1-project-1.com",
+                    "to": ["recipient1@example.com", "recipient2@example.com"],
                     "subject": "subject",
                     "text_body": "text body",
-                    "html_body": "<h1>html body</h1>",
-                    "in_reply_to": "some-message@example.com",
-                    "references": ["some-message@example.com", "some-other-message@example.com"],
-                    "reply_to": "support@example.com",
                 })),
             )
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::CREATED);
-        let message: ApiMessageMetadata = deserialize_body(response.into_body()).await;
-        assert_eq!(message.from_email.as_str(), "test@example.com");
-        assert_eq!(message.recipients.len(), 1);
-        assert_eq!(message.recipients[0].as_str(), "recipient@example.com");
-        assert_eq!(message.smtp_credential_id, None);
-        assert_eq!(message.api_key_id, Some(api_key_id));
-        assert_eq!(
-            message.message_id_header,
-            format!("REMAILS-{}@example.com", message.id)
-        );
-        assert_eq!(message.label, None);
+        assert_eq!(response.status(), StatusCode::OK);
+        let validation: MessageValidation = deserialize_body(response.into_body()).await;
+        assert!(validation.valid);
+        assert!(validation.problems.is_empty());
 
-        // send email with 2 recipients, only text body, and custom from name
+        // a message from a domain the project is not permitted to use
         let response = server
             .post(
-                format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
+                format!("/api/organizations/{org_1}/projects/{proj_1}/emails/validate"),
                 serialize_body(json!({
-                    "from": {"name": "Test", "address": "test@example.com"},
-                    "to": ["recipient1@example.com", "recipient2@example.com"],
+                    "from": "john@example.com",
+                    "to": "recipient@example.com",
                     "subject": "subject",
                     "text_body": "text body",
-                    "label": "Weekly UpDate",
                 })),
             )
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::CREATED);
-        let message: ApiMessageMetadata = deserialize_body(response.into_body()).await;
-        assert_eq!(message.from_email.as_str(), "test@example.com");
-        let mut recipients = message
-            .recipients
-            .into_iter()
-            .map(|e| e.as_str().to_owned())
-            .collect::<Vec<_>>();
-        recipients.sort();
+        assert_eq!(response.status(), StatusCode::OK);
+        let validation: MessageValidation = deserialize_body(response.into_body()).await;
+        assert!(!validation.valid);
         assert_eq!(
-            recipients,
-            vec!["recipient1@example.com", "recipient2@example.com"]
+            validation.problems,
+            vec!["Project is not permitted to use domain example.com"]
         );
-        assert_eq!(message.label, Some(Label::new("weekly-update")));
 
-        // send email with 3 recipients, only HTML body
+        // all problems are reported at once
         let response = server
             .post(
-                format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
+                format!("/api/organizations/{org_1}/projects/{proj_1}/emails/validate"),
                 serialize_body(json!({
-                    "from": "test@example.com",
-                    "to": ["recipient1@example.com", "recipient2@example.com", {"name": "Recipient 3", "address": "recipient3@example.com"}],
+                    "from": "john@test-org-1-project-1.com",
+                    "to": "not-an-email-address",
                     "subject": "subject",
-                    "text_body": "text body",
                 })),
             )
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::CREATED);
-        let message: ApiMessageMetadata = deserialize_body(response.into_body()).await;
-        assert_eq!(message.from_email.as_str(), "test@example.com");
-        let mut recipients = message
-            .recipients
-            .into_iter()
-            .map(|e| e.as_str().to_owned())
-            .collect::<Vec<_>>();
-        recipients.sort();
+        assert_eq!(response.status(), StatusCode::OK);
+        let validation: MessageValidation = deserialize_body(response.into_body()).await;
+        assert!(!validation.valid);
         assert_eq!(
-            recipients,
+            validation.problems,
             vec![
-                "recipient1@example.com",
-                "recipient2@example.com",
-                "recipient3@example.com"
+                "Invalid recipient email: not-an-email-address",
+                "Must provide a text_body or html_body"
             ]
         );
 
-        // get statistics
+        // nothing is created, and no quota is consumed
         let response = server
-            .get(format!("/api/organizations/{org_1}/statistics"))
+            .get(format!("/api/organizations/{org_1}/emails"))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
-        let stats: Statistics = deserialize_body(response.into_body()).await;
-        assert_eq!(stats.monthly.len(), 1);
-        assert_eq!(stats.monthly[0].statistics, json!({"processing": 3}));
-        assert_eq!(stats.daily.len(), 1);
-        assert_eq!(stats.daily[0].statistics, json!({"processing": 3}));
+        let messages: Vec<ApiMessageMetadata> = deserialize_body(response.into_body()).await;
+        assert!(messages.is_empty());
+        assert_eq!(
+            organizations.remaining_quota(org_1).await.unwrap(),
+            quota_before
+        );
+
+        // the project used up its own quota, while the organization still has quota left
+        sqlx::query!(
+            "UPDATE projects SET message_quota = 1, used_message_quota = 1 WHERE id = $1",
+            *proj_1
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let response = server
+            .post(
+                format!("/api/organizations/{org_1}/projects/{proj_1}/emails/validate"),
+                serialize_body(json!({
+                    "from": "john@test-org-1-project-1.com",
+                    "to": "recipient@example.com",
+                    "subject": "subject",
+                    "text_body": "text body",
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let validation: MessageValidation = deserialize_body(response.into_body()).await;
+        assert!(!validation.valid);
+        assert_eq!(validation.problems, vec!["Quota exceeded"]);
+        assert!(organizations.remaining_quota(org_1).await.unwrap() > 0);
     }
 
     #[sqlx::test(fixtures(
         path = "../fixtures",
-        scripts("organizations", "api_users", "projects", "smtp_credentials")
+        scripts(
+            "organizations",
+            "api_users",
+            "projects",
+            "org_domains",
+            "proj_domains"
+        )
     ))]
-    async fn test_create_message_reject(pool: PgPool) {
+    async fn test_project_default_sender(pool: PgPool) {
         let (org_1, proj_1) = TestProjects::Org1Project1.get_ids();
         let user_4 = "c33dbd88-43ed-404b-9367-1659a73c8f3a".parse().unwrap(); // is maintainer of org 1
         let mut server = TestServer::new(pool.clone(), Some(user_4)).await;
+
+        let update_project = |default_from_email: &str| NewProject {
+            default_from_email: Some(default_from_email.to_owned()),
+            default_from_name: Some("Test Sender".to_owned()),
+            ..NewProject::test("Project 1 Organization 1")
+        };
+
+        // the project is not permitted to use the domain
+        let response = server
+            .put(
+                format!("/api/organizations/{org_1}/projects/{proj_1}"),
+                serialize_body(update_project("noreply@example.com")),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = server
+            .put(
+                format!("/api/organizations/{org_1}/projects/{proj_1}"),
+                serialize_body(update_project("noreply@test-org-1-project-1.com")),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
         server.use_api_key(org_1, Role::Maintainer).await;
 
-        // reject emails without body
+        // the default sender is used if the message has no from address
         let response = server
             .post(
                 format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
                 serialize_body(json!({
-                    "from": "test@example.com",
                     "to": "recipient@example.com",
                     "subject": "subject",
+                    "text_body": "text body",
                 })),
             )
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let message: ApiMessageMetadata = deserialize_body(response.into_body()).await;
+        assert_eq!(
+            message.from_email.as_str(),
+            "noreply@test-org-1-project-1.com"
+        );
+        let raw_data =
+            sqlx::query_scalar!("SELECT raw_data FROM messages WHERE id = $1", *message.id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        let raw_data = String::from_utf8(raw_data).unwrap();
+        let from_header = raw_data.lines().find(|l| l.starts_with("From: ")).unwrap();
+        assert!(from_header.contains("Test Sender"));
+        assert!(from_header.contains("<noreply@test-org-1-project-1.com>"));
 
-        // reject emails without recipients
+        // an explicit from address overrides the default sender
         let response = server
             .post(
                 format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
                 serialize_body(json!({
-                    "from": "test@example.com",
-                    "to": [],
+                    "from": "john@test-org-1-project-1.com",
+                    "to": "recipient@example.com",
                     "subject": "subject",
                     "text_body": "text body",
                 })),
             )
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let message: ApiMessageMetadata = deserialize_body(response.into_body()).await;
+        assert_eq!(message.from_email.as_str(), "john@test-org-1-project-1.com");
 
-        // reject emails with invalid from email
+        // projects without a default sender require a from address
+        let proj_2 = TestProjects::Org1Project2.project_id();
         let response = server
             .post(
-                format!("/api/organizations/{org_1}/projects/{proj_1}/emails"),
+                format!("/api/organizations/{org_1}/projects/{proj_2}/emails"),
                 serialize_body(json!({
-                    "from": "remails.net",
                     "to": "recipient@example.com",
                     "subject": "subject",
                     "text_body": "text body",
@@ -1224,11 +2822,7 @@ mod tests {
         let response = server
             .put(
                 format!("/api/organizations/{org_1}/projects/{proj_2}"),
-                serialize_body(NewProject {
-                    name: "Project 2 Organization 1".to_owned(),
-                    retention_period_days: 1,
-                    plaintext_fallback: false,
-                }),
+                serialize_body(NewProject::test("Project 2 Organization 1")),
             )
             .await
             .unwrap();
@@ -1313,4 +2907,163 @@ mod tests {
         let suppressed: Vec<SuppressedEmailAddress> = deserialize_body(response.into_body()).await;
         assert!(suppressed.is_empty());
     }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "api_users",
+            "projects",
+            "smtp_credentials",
+            "messages",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_stuck_messages(pool: PgPool) {
+        let user_1 = "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap(); // is admin of org 1 and 2
+        let super_admin = "deadbeef-4e43-4a66-bbb9-fbcd4a933a34".parse().unwrap();
+        let message_1: MessageId = "e165562a-fb6d-423b-b318-fd26f4610634".parse().unwrap();
+
+        // the message has been processing for two hours
+        sqlx::query!("ALTER TABLE messages DISABLE TRIGGER update_messages_updated_at")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query!(
+            "UPDATE messages SET updated_at = now() - INTERVAL '2 hours' WHERE id = $1",
+            *message_1
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let mut server = TestServer::new(pool.clone(), Some(user_1)).await;
+        let mut message_stream = server.message_bus.receive().await.unwrap();
+
+        // regular admins can neither list nor retry stuck messages
+        let response = server.get("/api/emails/stuck").await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = server
+            .put(
+                format!("/api/emails/{message_1}/force_retry"),
+                Body::empty(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // super admins can
+        server.set_user(Some(super_admin));
+        let response = server.get("/api/emails/stuck").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let messages: Vec<StuckMessage> = deserialize_body(response.into_body()).await;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].id, message_1);
+        assert_eq!(messages[0].status, MessageStatus::Processing);
+
+        let response = server
+            .get("/api/emails/stuck?older_than_minutes=180")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let messages: Vec<StuckMessage> = deserialize_body(response.into_body()).await;
+        assert!(messages.is_empty());
+
+        let response = server
+            .put(
+                format!("/api/emails/{message_1}/force_retry"),
+                Body::empty(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // only the retry of the super admin was published
+        let bus_message = tokio::time::timeout(Duration::from_secs(10), message_stream.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            bus_message.message,
+            BusMessage::EmailReadyToSend(
+                message_1,
+                "127.0.0.1".parse().unwrap(),
+                None,
+                MessagePriority::High
+            )
+        );
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "api_users",
+            "projects",
+            "smtp_credentials",
+            "messages"
+        )
+    ))]
+    async fn test_queue_depth(pool: PgPool) {
+        let user_1 = "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap(); // is admin of org 1 and 2
+        let super_admin = "deadbeef-4e43-4a66-bbb9-fbcd4a933a34".parse().unwrap();
+
+        // seed a backlog of two pending messages, and a held message and a message that needs
+        // review, which are older but not pending
+        sqlx::query!("UPDATE messages SET status = 'delivered'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        for (message_id, status, age) in [
+            ("e165562a-fb6d-423b-b318-fd26f4610634", "processing", 2),
+            ("c1e03226-8aad-42a9-8c43-380a5b25cb79", "reattempt", 1),
+            ("10d5ad5f-04ae-489b-9f5a-f5d7e73bc12a", "held", 24),
+            ("2b7ca359-18da-4d90-90c5-ed43f7944585", "needs_review", 48),
+        ] {
+            sqlx::query(
+                "UPDATE messages SET status = $2::message_status, created_at = now() - $3 * INTERVAL '1 hour' WHERE id = $1",
+            )
+            .bind(message_id.parse::<uuid::Uuid>().unwrap())
+            .bind(status)
+            .bind(age)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let mut server = TestServer::new(pool.clone(), Some(user_1)).await;
+
+        // regular admins can not see the queue depth
+        let response = server.get("/api/emails/queue").await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        server.set_user(Some(super_admin));
+        let response = server.get("/api/emails/queue").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let depth: QueueDepth = deserialize_body(response.into_body()).await;
+        assert_eq!(depth.processing, 1);
+        assert_eq!(depth.accepted, 0);
+        assert_eq!(depth.reattempt, 1);
+        assert_eq!(depth.held, 1);
+        assert_eq!(depth.needs_review, 1);
+        let age = depth.oldest_pending_age_seconds.unwrap();
+        assert!(
+            (2 * 3600..2 * 3600 + 60).contains(&age),
+            "unexpected age {age}"
+        );
+
+        // without a backlog, there is no oldest pending message
+        sqlx::query!("UPDATE messages SET status = 'delivered'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let response = server.get("/api/emails/queue").await.unwrap();
+        let depth: QueueDepth = deserialize_body(response.into_body()).await;
+        assert_eq!(
+            depth.processing + depth.accepted + depth.reattempt + depth.held + depth.needs_review,
+            0
+        );
+        assert!(depth.oldest_pending_at.is_none());
+        assert!(depth.oldest_pending_age_seconds.is_none());
+    }
 }
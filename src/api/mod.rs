@@ -1,45 +1,54 @@
 use crate::{
     Environment,
     api::{
+        auth::{SessionLifetime, refresh_session, webauthn},
         error::AppError,
         messages::create_message_router,
         oauth::GithubOauthService,
         openapi::{docs_router, openapi_router},
+        pwned_passwords::PwnedPasswords,
     },
     bus::client::BusClient,
     handler::{RetryConfig, dns::DnsResolver},
     models::{
         ApiKeyRepository, ApiUserRepository, AuditLogRepository, DomainRepository,
-        InviteRepository, MessageRepository, OrganizationRepository, ProjectRepository,
-        RuntimeConfigRepository, SmtpCredentialRepository, StatisticsRepository,
-        SuppressedRepository,
+        InviteRepository, MessageRepository, OrganizationExportRepository, OrganizationRepository,
+        ProjectRepository, RuntimeConfigRepository, SmtpCredentialRepository, StatisticsRepository,
+        SuppressedRepository, WebhookRepository,
     },
     moneybird::MoneyBird,
 };
+use aws_lc_rs::{hkdf, hmac};
 use axum::{
-    BoxError, Json, RequestExt, Router,
-    error_handling::HandleErrorLayer,
-    extract::{ConnectInfo, FromRef, Request, State},
+    Json, RequestExt, Router,
+    extract::{ConnectInfo, FromRef, FromRequestParts, Request, State},
     middleware,
     middleware::Next,
     response::{IntoResponse, Redirect, Response},
     routing::get,
 };
 use base64ct::Encoding;
+use email_address::EmailAddress;
 use http::{
-    HeaderName, HeaderValue, Method, StatusCode,
+    HeaderMap, HeaderName, HeaderValue, Method, StatusCode,
     header::{
-        ACCEPT, ACCEPT_ENCODING, AUTHORIZATION, CONNECTION, COOKIE, HOST, ORIGIN, REFERER,
-        USER_AGENT,
+        ACCEPT, ACCEPT_ENCODING, AUTHORIZATION, CONNECTION, COOKIE, HOST, IF_NONE_MATCH, ORIGIN,
+        REFERER, USER_AGENT,
     },
+    request::Parts,
 };
 use serde::Serialize;
 use sqlx::PgPool;
-use std::{env, net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    convert::Infallible,
+    env,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
 use thiserror::Error;
 use tokio::{net::TcpListener, task::JoinHandle};
 use tokio_util::sync::CancellationToken;
-use tower::ServiceBuilder;
 use tower_http::{
     compression::CompressionLayer, cors::CorsLayer, limit::RequestBodyLimitLayer, trace::TraceLayer,
 };
@@ -49,6 +58,8 @@ use tracing::{
     span,
 };
 use utoipa::ToSchema;
+use uuid::Uuid;
+use webauthn_rs::prelude::Webauthn;
 
 mod api_keys;
 mod api_users;
@@ -59,12 +70,15 @@ mod invites;
 mod messages;
 mod oauth;
 pub mod openapi;
+mod organization_exports;
 mod organizations;
 mod projects;
+mod pwned_passwords;
 mod smtp_credentials;
 mod subscriptions;
 mod system;
 mod validation;
+mod webhooks;
 mod whoami;
 
 static USER_AGENT_VALUE: &str = "remails";
@@ -88,6 +102,18 @@ pub struct RemailsConfig {
     pub spf_include: String,
     pub dkim_selector: String,
     pub moneybird_administration_id: String,
+    /// Number of days an organization invite link remains valid
+    pub invite_expiry_days: u32,
+    /// Lowercase email domains that may register an account, e.g., `example.com`, everyone may
+    /// register if empty
+    pub registration_email_domains: Vec<String>,
+    /// Number of reverse proxies in front of the API that append the address of their client to
+    /// the `X-Forwarded-For` header, the header is ignored if zero
+    #[serde(skip)]
+    pub trusted_proxy_hops: usize,
+    /// Maximum duration of requests per group of routes, see [`RouteTimeouts::from_env`]
+    #[serde(skip)]
+    pub route_timeouts: RouteTimeouts,
 }
 
 impl Default for RemailsConfig {
@@ -117,6 +143,24 @@ impl Default for RemailsConfig {
         let dkim_selector = env::var("DKIM_SELECTOR").expect("DKIM_SELECTOR env var must be set");
         let moneybird_administration_id = env::var("MONEYBIRD_ADMINISTRATION_ID")
             .expect("MONEYBIRD_ADMINISTRATION_ID env var must be set");
+        let invite_expiry_days = env::var("INVITE_EXPIRY_DAYS")
+            .map(|s| s.parse().expect("Invalid INVITE_EXPIRY_DAYS env var"))
+            .unwrap_or(7);
+        let registration_email_domains = env::var("REGISTRATION_EMAIL_DOMAINS")
+            .map(|s| {
+                s.split(",")
+                    .map(|domain| domain.trim().to_lowercase())
+                    .filter(|domain| !domain.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let trusted_proxy_hops = env::var("TRUSTED_PROXY_HOPS")
+            .map(|s| s.parse().expect("Invalid TRUSTED_PROXY_HOPS env var"))
+            .unwrap_or(0);
+        #[cfg(not(test))]
+        let route_timeouts = RouteTimeouts::from_env();
+        #[cfg(test)]
+        let route_timeouts = RouteTimeouts::default();
 
         Self {
             version,
@@ -127,17 +171,127 @@ impl Default for RemailsConfig {
             spf_include,
             dkim_selector,
             moneybird_administration_id,
+            invite_expiry_days,
+            registration_email_domains,
+            trusted_proxy_hops,
+            route_timeouts,
         }
     }
 }
 
+impl RemailsConfig {
+    /// Fails with an explanation for the user if the domain of `email` may not register an account
+    pub fn check_registration_domain(&self, email: &EmailAddress) -> Result<(), String> {
+        if self.registration_email_domains.is_empty()
+            || self
+                .registration_email_domains
+                .iter()
+                .any(|domain| email.domain().eq_ignore_ascii_case(domain))
+        {
+            return Ok(());
+        }
+
+        Err(format!(
+            "Only email addresses of {} can register",
+            self.registration_email_domains.join(", ")
+        ))
+    }
+}
+
+/// Maximum duration of requests per group of routes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RouteTimeouts {
+    /// All routes without a more specific timeout
+    pub default: Duration,
+    /// Creating and validating messages, which may have large payloads
+    pub emails: Duration,
+    /// Creating and downloading organization exports
+    pub exports: Duration,
+    /// Logging in, registering, and logging out
+    pub auth: Duration,
+}
+
+impl Default for RouteTimeouts {
+    fn default() -> Self {
+        Self {
+            default: Duration::from_secs(10),
+            emails: Duration::from_secs(60),
+            exports: Duration::from_secs(60),
+            auth: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RouteTimeouts {
+    /// Configure the timeouts using the `ROUTE_TIMEOUTS` environment variable, a comma-separated
+    /// list of `group=seconds`, where the group is `default`, `emails`, `exports`, or `auth`,
+    /// e.g., `emails=120,auth=3`. Groups that are not listed keep their default timeout.
+    ///
+    /// Will panic if any of the timeouts is invalid
+    #[cfg(not(test))]
+    pub fn from_env() -> Self {
+        env::var("ROUTE_TIMEOUTS")
+            .map(|timeouts| {
+                Self::parse(&timeouts).expect(
+                    "ROUTE_TIMEOUTS must be a comma-separated list of `group=seconds`, \
+                     where the group is `default`, `emails`, `exports`, or `auth`",
+                )
+            })
+            .unwrap_or_default()
+    }
+
+    fn parse(timeouts: &str) -> Option<Self> {
+        let mut parsed = Self::default();
+
+        for timeout in timeouts.split(',').filter(|t| !t.trim().is_empty()) {
+            let (group, seconds) = timeout.split_once('=')?;
+            let seconds = seconds.trim().parse::<std::num::NonZeroU64>().ok()?;
+            let timeout = Duration::from_secs(seconds.get());
+
+            match group.trim() {
+                "default" => parsed.default = timeout,
+                "emails" => parsed.emails = timeout,
+                "exports" => parsed.exports = timeout,
+                "auth" => parsed.auth = timeout,
+                _ => return None,
+            }
+        }
+
+        Some(parsed)
+    }
+
+    /// The longest of the timeouts, which also bounds the middleware in front of all routes
+    /// and the routes outside any group, such as the frontend and the API documentation
+    fn ceiling(&self) -> Duration {
+        self.default
+            .max(self.emails)
+            .max(self.exports)
+            .max(self.auth)
+    }
+}
+
 #[derive(derive_more::Debug)]
 pub struct ApiConfig {
     #[debug("****")]
     session_key: cookie::Key,
+    session_lifetime: SessionLifetime,
     pub remails_config: RemailsConfig,
 }
 
+impl ApiConfig {
+    /// Key to sign temporary export download links with
+    ///
+    /// Derived from the session key with HKDF, such that the key used to sign session cookies
+    /// is never used for anything else.
+    fn export_link_key(&self) -> hmac::Key {
+        hkdf::Salt::new(hkdf::HKDF_SHA256, &[])
+            .extract(self.session_key.master())
+            .expand(&[b"export-link".as_slice()], hmac::HMAC_SHA256)
+            .expect("HMAC-SHA256 key length is a valid HKDF output length")
+            .into()
+    }
+}
+
 #[derive(FromRef, Clone)]
 pub struct ApiState {
     pool: PgPool,
@@ -147,6 +301,8 @@ pub struct ApiState {
     resolver: DnsResolver,
     message_bus: Arc<BusClient>,
     pub retry_config: Arc<RetryConfig>,
+    pwned_passwords: PwnedPasswords,
+    webauthn: Arc<Webauthn>,
 }
 
 impl ApiState {
@@ -191,6 +347,12 @@ impl FromRef<ApiState> for DomainRepository {
     }
 }
 
+impl FromRef<ApiState> for OrganizationExportRepository {
+    fn from_ref(state: &ApiState) -> Self {
+        OrganizationExportRepository::new(state.pool.clone(), state.resolver.clone())
+    }
+}
+
 impl FromRef<ApiState> for ApiUserRepository {
     fn from_ref(state: &ApiState) -> Self {
         ApiUserRepository::new(state.pool.clone())
@@ -221,6 +383,12 @@ impl FromRef<ApiState> for SuppressedRepository {
     }
 }
 
+impl FromRef<ApiState> for WebhookRepository {
+    fn from_ref(state: &ApiState) -> Self {
+        WebhookRepository::new(state.pool.clone())
+    }
+}
+
 impl FromRef<ApiState> for AuditLogRepository {
     fn from_ref(state: &ApiState) -> Self {
         AuditLogRepository::new(state.pool.clone())
@@ -261,6 +429,90 @@ async fn ip_middleware(mut request: Request, next: Next) -> Response {
     next.run(request).instrument(span).await
 }
 
+/// The IP address of the HTTP client
+///
+/// Each of the `TRUSTED_PROXY_HOPS` reverse proxies in front of the API appends the address of
+/// its client to the `X-Forwarded-For` header, so the client is the address that many entries from
+/// the end. Entries before it are supplied by the client itself and cannot be trusted.
+/// Without trusted proxies, or if the header has fewer entries, it is the address of the connection.
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl ClientIp {
+    fn forwarded(headers: &HeaderMap, trusted_proxy_hops: usize) -> Option<IpAddr> {
+        let hop = trusted_proxy_hops.checked_sub(1)?;
+        let entries = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .map(|header| header.to_str().ok())
+            .collect::<Option<Vec<_>>>()?;
+
+        entries
+            .iter()
+            .flat_map(|header| header.split(','))
+            .rev()
+            .nth(hop)
+            .and_then(|ip| ip.trim().parse().ok())
+    }
+}
+
+impl FromRequestParts<ApiState> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ApiState,
+    ) -> Result<Self, Self::Rejection> {
+        let forwarded = Self::forwarded(
+            &parts.headers,
+            state.config.remails_config.trusted_proxy_hops,
+        );
+        let connection = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|connection| connection.ip());
+
+        Ok(Self(forwarded.or(connection)))
+    }
+}
+
+pub(crate) static X_CORRELATION_ID: HeaderName = HeaderName::from_static("x-correlation-id");
+
+/// Ties the log lines of an API request to those of the processing that follows from it,
+/// e.g., the delivery of a submitted message
+///
+/// Taken from the `X-Correlation-Id` request header if that contains a valid ID
+/// (at most 100 alphanumeric, `-`, `_`, `.` or `:` characters), otherwise a new ID is generated.
+pub struct CorrelationId(pub String);
+
+impl CorrelationId {
+    fn is_valid(id: &str) -> bool {
+        (1..=100).contains(&id.len())
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+    }
+}
+
+impl<S> FromRequestParts<S> for CorrelationId
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let provided = parts
+            .headers
+            .get(&X_CORRELATION_ID)
+            .and_then(|header| header.to_str().ok())
+            .filter(|id| Self::is_valid(id));
+
+        Ok(Self(match provided {
+            Some(id) => id.to_owned(),
+            None => Uuid::new_v4().to_string(),
+        }))
+    }
+}
+
 fn cors_layer(api_server_name: &str) -> CorsLayer {
     CorsLayer::new()
         .allow_origin(
@@ -280,6 +532,8 @@ fn cors_layer(api_server_name: &str) -> CorsLayer {
             HOST,
             ORIGIN,
             REFERER,
+            IF_NONE_MATCH,
+            X_CORRELATION_ID.clone(),
             HeaderName::from_static("priority"),
         ])
 }
@@ -299,8 +553,9 @@ impl ApiServer {
         with_frontend: bool,
         with_docs: bool,
         message_bus: BusClient,
+        remails_config: RemailsConfig,
     ) -> ApiServer {
-        let github_oauth = GithubOauthService::new(pool.clone()).unwrap();
+        let github_oauth = GithubOauthService::new(pool.clone(), remails_config.clone()).unwrap();
         let oauth_router = github_oauth.router();
 
         let session_key = match env::var("SESSION_KEY") {
@@ -321,11 +576,17 @@ impl ApiServer {
 
         moneybird.register_webhook();
 
+        let webauthn = Arc::new(webauthn(&remails_config));
+
         let state = ApiState {
             pool,
             config: Arc::new(ApiConfig {
                 session_key,
-                remails_config: Default::default(),
+                #[cfg(not(test))]
+                session_lifetime: SessionLifetime::from_env(),
+                #[cfg(test)]
+                session_lifetime: Default::default(),
+                remails_config,
             }),
             moneybird,
             gh_oauth_service: github_oauth,
@@ -335,12 +596,19 @@ impl ApiServer {
             resolver: DnsResolver::mock("localhost", 0),
             message_bus: Arc::new(message_bus),
             retry_config: Arc::new(RetryConfig::default()),
+            pwned_passwords: PwnedPasswords::from_env(),
+            webauthn,
         };
 
-        let (router, _) = openapi_router().split_for_parts();
+        let timeouts = state.config.remails_config.route_timeouts;
+
+        let (router, _) = openapi_router(&timeouts).split_for_parts();
 
         let mut router = router
-            .merge(oauth_router)
+            .merge(oauth_router.layer(middleware::from_fn_with_state(
+                timeouts.default,
+                request_timeout,
+            )))
             .layer((
                 TraceLayer::new_for_http(),
                 middleware::from_fn(ip_middleware),
@@ -381,6 +649,10 @@ impl ApiServer {
 
         router = router.merge(
             create_message_router()
+                .layer(middleware::from_fn_with_state(
+                    timeouts.emails,
+                    request_timeout,
+                ))
                 .split_for_parts()
                 .0
                 .layer((
@@ -391,6 +663,17 @@ impl ApiServer {
                 .with_state(state.clone()),
         );
 
+        router = router.layer(middleware::from_fn_with_state(
+            state.clone(),
+            refresh_session,
+        ));
+
+        // routes and middleware without a timeout of their own must not hang either
+        router = router.layer(middleware::from_fn_with_state(
+            timeouts.ceiling(),
+            request_timeout,
+        ));
+
         router = router.layer(middleware::from_fn_with_state(
             state.config.clone(),
             append_default_headers,
@@ -398,12 +681,6 @@ impl ApiServer {
 
         router = router.layer(CompressionLayer::new().br(true));
 
-        router = router.layer(
-            ServiceBuilder::new()
-                .layer(HandleErrorLayer::new(handle_timeout_error))
-                .timeout(Duration::from_secs(10)),
-        );
-
         ApiServer {
             socket,
             router,
@@ -452,7 +729,7 @@ async fn wait_for_shutdown(token: CancellationToken) {
 async fn append_default_headers(
     State(config): State<Arc<ApiConfig>>,
     req: Request,
-    next: middleware::Next,
+    next: Next,
 ) -> axum::response::Response {
     let mut res = next.run(req).await;
 
@@ -475,12 +752,11 @@ async fn append_default_headers(
     res
 }
 
-async fn handle_timeout_error(err: BoxError) -> AppError {
-    if err.is::<tower::timeout::error::Elapsed>() {
-        AppError::RequestTimeout
-    } else {
-        AppError::Internal
-    }
+/// Answers requests that take longer than `timeout` with [`AppError::RequestTimeout`]
+async fn request_timeout(State(timeout): State<Duration>, req: Request, next: Next) -> Response {
+    tokio::time::timeout(timeout, next.run(req))
+        .await
+        .unwrap_or_else(|_| AppError::RequestTimeout.into_response())
 }
 
 #[cfg(test)]
@@ -507,6 +783,14 @@ mod tests {
 
     impl TestServer {
         pub async fn new(pool: PgPool, user: Option<ApiUserId>) -> Self {
+            Self::with_config(pool, user, RemailsConfig::default()).await
+        }
+
+        pub async fn with_config(
+            pool: PgPool,
+            user: Option<ApiUserId>,
+            remails_config: RemailsConfig,
+        ) -> Self {
             let http_socket = SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 0);
             let shutdown = CancellationToken::new();
             let message_bus_port = Bus::spawn_random_port().await;
@@ -519,6 +803,7 @@ mod tests {
                 false,
                 false,
                 message_bus_client.clone(),
+                remails_config,
             )
             .await;
 
@@ -542,6 +827,10 @@ mod tests {
             }
         }
 
+        pub fn api_state(&self) -> &ApiState {
+            self.server.api_state()
+        }
+
         pub fn set_header(&mut self, name: &'static str, value: Option<String>) {
             if let Some(value) = value {
                 self.headers.insert(name, value);
@@ -680,4 +969,111 @@ mod tests {
         let _: ApiErrorResponse = serde_json::from_str(&msg).unwrap();
         assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
     }
+
+    #[test]
+    fn route_timeouts() {
+        let timeouts = RouteTimeouts::parse("emails=120, auth=3").unwrap();
+        assert_eq!(timeouts.emails, Duration::from_secs(120));
+        assert_eq!(timeouts.auth, Duration::from_secs(3));
+        assert_eq!(timeouts.default, RouteTimeouts::default().default);
+        assert_eq!(timeouts.exports, RouteTimeouts::default().exports);
+
+        assert_eq!(RouteTimeouts::parse(""), Some(RouteTimeouts::default()));
+        assert!(RouteTimeouts::parse("emails=0").is_none());
+        assert!(RouteTimeouts::parse("emails").is_none());
+        assert!(RouteTimeouts::parse("webhooks=10").is_none());
+
+        assert_eq!(timeouts.ceiling(), Duration::from_secs(120));
+    }
+
+    #[test]
+    fn client_ip_trusted_proxy_hops() {
+        let mut headers = HeaderMap::new();
+        headers.append("x-forwarded-for", "192.0.2.1".parse().unwrap());
+        headers.append(
+            "x-forwarded-for",
+            "203.0.113.7, 198.51.100.23".parse().unwrap(),
+        );
+
+        // without trusted proxies, the header is ignored
+        assert_eq!(ClientIp::forwarded(&headers, 0), None);
+        // a spoofed entry before the one added by the proxy is ignored
+        assert_eq!(
+            ClientIp::forwarded(&headers, 1),
+            Some("198.51.100.23".parse().unwrap())
+        );
+        assert_eq!(
+            ClientIp::forwarded(&headers, 3),
+            Some("192.0.2.1".parse().unwrap())
+        );
+        // the request did not pass through all proxies
+        assert_eq!(ClientIp::forwarded(&headers, 4), None);
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "api_users", "projects")
+    ))]
+    async fn test_request_timeout_per_route(pool: PgPool) {
+        let org_1 = "44729d9f-a7dc-4226-b412-36a7537f5176";
+        let project_1 = "3ba14adf-4de1-4fb6-8c20-50cc2ded5462";
+        let user_1 = "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap(); // is admin of org 1 and 2
+        // any request that waits for the database exceeds a zero timeout
+        let mut server = TestServer::with_config(
+            pool,
+            Some(user_1),
+            RemailsConfig {
+                route_timeouts: RouteTimeouts {
+                    default: Duration::ZERO,
+                    auth: Duration::ZERO,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        )
+        .await;
+
+        let response = server
+            .get(format!("/api/organizations/{org_1}/projects"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        let error: ApiErrorResponse = deserialize_body(response.into_body()).await;
+        assert_eq!(error.code, "request_timeout");
+
+        let response = server
+            .post(
+                "/api/login/password",
+                serialize_body(serde_json::json!({
+                    "email": "test-totp-rate-limit@user-4",
+                    "password": "unsecure123"
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+
+        // exports and emails keep their longer timeout
+        let response = server
+            .get(format!(
+                "/api/organizations/{org_1}/exports/{}",
+                Uuid::new_v4()
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        server.set_user(None);
+        let credentials =
+            base64ct::Base64::encode_string(format!("{}:wrong", Uuid::new_v4()).as_bytes());
+        server.set_header("Authorization", Some(format!("Basic {credentials}")));
+        let response = server
+            .post(
+                format!("/api/organizations/{org_1}/projects/{project_1}/emails"),
+                serialize_body(serde_json::json!({})),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
 }
@@ -26,6 +26,8 @@ pub enum Error {
     Conflict(String),
     #[error("Forbidden")]
     Forbidden,
+    #[error("email domain not allowed: {0}")]
+    EmailDomainNotAllowed(String),
 }
 
 impl Error {
@@ -44,6 +46,7 @@ impl Error {
             Self::Other(_) => "Unforeseen error occurred".to_string(),
             Self::Conflict(_) => "Conflict".to_string(),
             Self::Forbidden => "Forbidden".to_string(),
+            Self::EmailDomainNotAllowed(message) => message.clone(),
         }
     }
 
@@ -61,6 +64,7 @@ impl Error {
             }
             Error::Conflict(_) => StatusCode::CONFLICT,
             Error::Forbidden => StatusCode::FORBIDDEN,
+            Error::EmailDomainNotAllowed(_) => StatusCode::BAD_REQUEST,
         }
     }
 }
@@ -1,7 +1,7 @@
 use super::handlers::{authorize, oauth_login};
 use crate::{
     api::{
-        ApiState, USER_AGENT_VALUE,
+        ApiState, RemailsConfig, USER_AGENT_VALUE,
         oauth::{Error, OAuthService},
         whoami::WhoamiResponse,
     },
@@ -52,6 +52,7 @@ pub struct GithubOauthService {
     http_client: reqwest::Client,
     user_repository: ApiUserRepository,
     config_repository: RuntimeConfigRepository,
+    remails_config: RemailsConfig,
 }
 
 #[derive(Debug, Deserialize)]
@@ -101,7 +102,7 @@ impl GithubOauthService {
     /// # Returns
     ///
     /// Returns a `Result` containing the `GithubOauthService` instance or an `Error` if there was an error creating the service.
-    pub fn new(pool: PgPool) -> Result<Self, Error> {
+    pub fn new(pool: PgPool, remails_config: RemailsConfig) -> Result<Self, Error> {
         let client_id = env::var("OAUTH_CLIENT_ID")
             .map_err(|_| Error::MissingEnvironmentVariable("OAUTH_CLIENT_ID"))?;
         let client_secret = env::var("OAUTH_CLIENT_SECRET")
@@ -129,6 +130,7 @@ impl GithubOauthService {
             http_client,
             user_repository: ApiUserRepository::new(pool.clone()),
             config_repository: RuntimeConfigRepository::new(pool),
+            remails_config,
         })
     }
 
@@ -180,6 +182,10 @@ impl GithubOauthService {
             ))?,
         };
 
+        self.remails_config
+            .check_registration_domain(&email)
+            .map_err(Error::EmailDomainNotAllowed)?;
+
         Ok(self
             .user_repository
             .create(NewApiUser {
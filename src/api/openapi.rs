@@ -1,9 +1,10 @@
 use crate::api::{
-    ApiServerError, ApiState, api_fallback, api_keys, api_users, auth, domains, error, invites,
-    messages, messages::create_message_router, organizations, projects, smtp_credentials,
-    subscriptions, system, wait_for_shutdown, whoami,
+    ApiServerError, ApiState, RouteTimeouts, api_fallback, api_keys, api_users, auth, domains,
+    error, invites, messages, messages::create_message_router, organization_exports, organizations,
+    projects, request_timeout, smtp_credentials, subscriptions, system, wait_for_shutdown,
+    webhooks, whoami,
 };
-use axum::{Json, Router, routing::get};
+use axum::{Json, Router, middleware, routing::get};
 use http::StatusCode;
 use std::{env, net::SocketAddr, time::Duration};
 use tokio::{net::TcpListener, task::JoinHandle};
@@ -21,7 +22,7 @@ use utoipa::{
 };
 use utoipa_axum::router::OpenApiRouter;
 
-pub fn openapi_router() -> OpenApiRouter<ApiState> {
+pub fn openapi_router(timeouts: &RouteTimeouts) -> OpenApiRouter<ApiState> {
     let version = env::var("VERSION").unwrap_or("dev".to_string());
 
     #[derive(utoipa::OpenApi)]
@@ -71,18 +72,35 @@ pub fn openapi_router() -> OpenApiRouter<ApiState> {
     let mut router = OpenApiRouter::with_openapi(api_doc).nest(
         "/api",
         OpenApiRouter::default()
-            .merge(organizations::router())
-            .merge(projects::router())
-            .merge(messages::router())
-            .merge(invites::router())
-            .merge(domains::router())
-            .merge(api_users::router())
-            .merge(whoami::router())
-            .merge(subscriptions::router())
-            .merge(api_keys::router())
-            .merge(smtp_credentials::router())
-            .merge(system::router())
-            .merge(auth::router())
+            .merge(
+                OpenApiRouter::default()
+                    .merge(organizations::router())
+                    .merge(projects::router())
+                    .merge(messages::router())
+                    .merge(invites::router())
+                    .merge(domains::router())
+                    .merge(api_users::router())
+                    .merge(whoami::router())
+                    .merge(subscriptions::router())
+                    .merge(api_keys::router())
+                    .merge(smtp_credentials::router())
+                    .merge(webhooks::router())
+                    .merge(system::router())
+                    .layer(middleware::from_fn_with_state(
+                        timeouts.default,
+                        request_timeout,
+                    )),
+            )
+            .merge(
+                organization_exports::router().layer(middleware::from_fn_with_state(
+                    timeouts.exports,
+                    request_timeout,
+                )),
+            )
+            .merge(auth::router().layer(middleware::from_fn_with_state(
+                timeouts.auth,
+                request_timeout,
+            )))
             .fallback(api_fallback),
     );
 
@@ -103,7 +121,7 @@ pub fn openapi_router() -> OpenApiRouter<ApiState> {
 }
 
 pub fn docs_router() -> Router {
-    let openapi = openapi_router().to_openapi();
+    let openapi = openapi_router(&RouteTimeouts::default()).to_openapi();
 
     memory_serve::load!("openapi")
         .index_file(Some("/scalar.html"))
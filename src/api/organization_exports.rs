@@ -0,0 +1,344 @@
+use crate::{
+    api::{
+        ApiConfig, ApiState,
+        error::{ApiResult, AppError},
+        validation::ValidatedQuery,
+    },
+    models::{
+        ApiUser, OrganizationExport, OrganizationExportId, OrganizationExportRepository,
+        OrganizationExportStatus, OrganizationId,
+    },
+};
+use aws_lc_rs::hmac;
+use axum::{
+    Json,
+    body::Body,
+    extract::{Path, State},
+    response::IntoResponse,
+};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use chrono::Utc;
+use futures::TryStreamExt;
+use garde::Validate;
+use http::{StatusCode, header};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info};
+use utoipa::{IntoParams, ToSchema};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+pub fn router() -> OpenApiRouter<ApiState> {
+    OpenApiRouter::new()
+        .routes(routes!(create_export))
+        .routes(routes!(get_export))
+        .routes(routes!(download_export))
+}
+
+#[derive(Serialize, ToSchema)]
+#[cfg_attr(test, derive(Deserialize))]
+pub struct ApiOrganizationExport {
+    #[serde(flatten)]
+    export: OrganizationExport,
+    /// Temporary link to download the archive, only set once the export is ready
+    download_url: Option<String>,
+}
+
+/// Signature of a temporary download link
+#[derive(Deserialize, IntoParams, Validate)]
+pub struct DownloadSignature {
+    /// Unix timestamp after which the link is no longer valid
+    #[garde(skip)]
+    expires: i64,
+    #[garde(skip)]
+    signature: String,
+}
+
+impl ApiConfig {
+    fn export_link_message(
+        org_id: OrganizationId,
+        export_id: OrganizationExportId,
+        expires: i64,
+    ) -> String {
+        format!("{org_id}/{export_id}/{expires}")
+    }
+
+    /// Link to download the archive of an export, which is valid until the export expires
+    fn export_download_url(&self, export: &OrganizationExport, org_id: OrganizationId) -> String {
+        let expires = export.expires_at().timestamp();
+        let message = Self::export_link_message(org_id, export.id(), expires);
+        let signature = hmac::sign(&self.export_link_key(), message.as_bytes());
+
+        format!(
+            "https://{}/api/organizations/{org_id}/exports/{}/download?expires={expires}&signature={}",
+            self.remails_config.api_server_name,
+            export.id(),
+            Base64UrlUnpadded::encode_string(signature.as_ref())
+        )
+    }
+
+    fn verify_export_link(
+        &self,
+        org_id: OrganizationId,
+        export_id: OrganizationExportId,
+        link: &DownloadSignature,
+    ) -> bool {
+        let Ok(signature) = Base64UrlUnpadded::decode_vec(&link.signature) else {
+            return false;
+        };
+        let message = Self::export_link_message(org_id, export_id, link.expires);
+
+        hmac::verify(&self.export_link_key(), message.as_bytes(), &signature).is_ok()
+    }
+}
+
+/// Request an export of all organization data
+///
+/// Assembles the organization, its members, projects, domains, API keys (without secrets),
+/// and message metadata into a zip archive with JSON files. The archive is generated by a
+/// background job, poll the export to get a temporary download link once it is ready.
+#[utoipa::path(post, path = "/organizations/{org_id}/exports",
+    tags = ["internal", "Organizations"],
+    responses(
+        (status = 202, description = "Export requested", body = OrganizationExport),
+        (status = 409, description = "Another export is still pending"),
+        AppError
+    )
+)]
+pub async fn create_export(
+    State(repo): State<OrganizationExportRepository>,
+    Path((org_id,)): Path<(OrganizationId,)>,
+    user: ApiUser,
+) -> Result<impl IntoResponse, AppError> {
+    user.has_org_admin_access(&org_id)?;
+
+    let export = repo.create(org_id, &user).await?;
+
+    info!(
+        user_id = user.id().to_string(),
+        organization_id = org_id.to_string(),
+        export_id = export.id().to_string(),
+        "requested organization export"
+    );
+
+    Ok((StatusCode::ACCEPTED, Json(export)))
+}
+
+/// Get the status of an organization export
+///
+/// Once the export is ready, the response includes a temporary link to download the archive
+#[utoipa::path(get, path = "/organizations/{org_id}/exports/{export_id}",
+    tags = ["internal", "Organizations"],
+    responses(
+        (status = 200, description = "Successfully fetched export", body = ApiOrganizationExport),
+        AppError
+    )
+)]
+pub async fn get_export(
+    State(state): State<ApiState>,
+    State(repo): State<OrganizationExportRepository>,
+    Path((org_id, export_id)): Path<(OrganizationId, OrganizationExportId)>,
+    user: ApiUser,
+) -> ApiResult<ApiOrganizationExport> {
+    user.has_org_admin_access(&org_id)?;
+
+    debug!(
+        user_id = user.id().to_string(),
+        organization_id = org_id.to_string(),
+        export_id = export_id.to_string(),
+        "get organization export"
+    );
+
+    let export = repo.get(org_id, export_id).await?;
+    let download_url = (export.status() == OrganizationExportStatus::Ready)
+        .then(|| state.config.export_download_url(&export, org_id));
+
+    Ok(Json(ApiOrganizationExport {
+        export,
+        download_url,
+    }))
+}
+
+/// Download the archive of an organization export
+///
+/// This does not require authentication, instead the link is signed and only valid temporarily
+#[utoipa::path(get, path = "/organizations/{org_id}/exports/{export_id}/download",
+    params(DownloadSignature),
+    tags = ["internal", "Organizations"],
+    responses(
+        (status = 200, description = "Successfully downloaded archive", content_type = "application/zip", body = Vec<u8>),
+        (status = 410, description = "The link has expired"),
+        AppError
+    )
+)]
+pub async fn download_export(
+    State(state): State<ApiState>,
+    State(repo): State<OrganizationExportRepository>,
+    Path((org_id, export_id)): Path<(OrganizationId, OrganizationExportId)>,
+    ValidatedQuery(link): ValidatedQuery<DownloadSignature>,
+) -> Result<impl IntoResponse, AppError> {
+    if !state.config.verify_export_link(org_id, export_id, &link) {
+        return Err(AppError::NotFound);
+    }
+
+    if link.expires <= Utc::now().timestamp() {
+        return Err(AppError::Gone("This link has expired".to_string()));
+    }
+
+    debug!(
+        organization_id = org_id.to_string(),
+        export_id = export_id.to_string(),
+        "downloading organization export"
+    );
+
+    let archive = repo
+        .stream_archive(org_id, export_id)
+        .await?
+        .inspect_err(move |e| {
+            error!(
+                export_id = export_id.to_string(),
+                "failed to stream organization export: {e:?}"
+            )
+        });
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip".to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"remails-export-{org_id}.zip\""),
+            ),
+        ],
+        Body::from_stream(archive),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+    use std::io::Cursor;
+    use zip::ZipArchive;
+
+    use crate::{
+        api::tests::{TestServer, deserialize_body},
+        handler::dns::DnsResolver,
+        models::{AuditLogRepository, ExportedMessage, Project},
+        test::TestProjects,
+    };
+
+    use super::*;
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "api_users",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "api_keys",
+            "smtp_credentials",
+            "messages"
+        )
+    ))]
+    async fn test_organization_export(pool: PgPool) {
+        let user_1 = "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap(); // is admin of org 1 and 2
+        let (org_1, proj_1) = TestProjects::Org1Project1.get_ids();
+        let proj_2 = TestProjects::Org2Project1.project_id();
+        let mut server = TestServer::new(pool.clone(), Some(user_1)).await;
+
+        // request export
+        let response = server
+            .post(format!("/api/organizations/{org_1}/exports"), Body::empty())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let export: OrganizationExport = deserialize_body(response.into_body()).await;
+        let export_id = export.id();
+
+        // the export is pending until the background job generated the archive
+        let response = server
+            .get(format!("/api/organizations/{org_1}/exports/{export_id}"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let export: ApiOrganizationExport = deserialize_body(response.into_body()).await;
+        assert_eq!(export.export.status(), OrganizationExportStatus::Pending);
+        assert!(export.download_url.is_none());
+
+        OrganizationExportRepository::new(pool.clone(), DnsResolver::mock("localhost", 1025))
+            .generate_pending()
+            .await
+            .unwrap();
+
+        let response = server
+            .get(format!("/api/organizations/{org_1}/exports/{export_id}"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let export: ApiOrganizationExport = deserialize_body(response.into_body()).await;
+        assert_eq!(export.export.status(), OrganizationExportStatus::Ready);
+        let download_url = export.download_url.unwrap();
+        let download_path = &download_url[download_url.find("/api/").unwrap()..];
+
+        // the link can't be tampered with
+        let response = server
+            .get(format!(
+                "/api/organizations/{org_1}/exports/{export_id}/download?expires={}&signature=invalid",
+                export.export.expires_at().timestamp()
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // the signed link can be used without logging in
+        server.set_user(None);
+        let response = server.get(download_path).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let archive = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(archive)).unwrap();
+
+        // the export contains the projects of the organization only
+        let projects: Vec<Project> =
+            serde_json::from_reader(archive.by_name("projects.json").unwrap()).unwrap();
+        assert!(projects.iter().any(|p| p.id() == proj_1));
+        assert!(projects.iter().all(|p| p.org_id() == org_1));
+        assert!(!projects.iter().any(|p| p.id() == proj_2));
+
+        let messages: Vec<ExportedMessage> =
+            serde_json::from_reader(archive.by_name("messages.json").unwrap()).unwrap();
+        assert!(
+            messages
+                .iter()
+                .any(|m| m.id == "e165562a-fb6d-423b-b318-fd26f4610634".parse().unwrap())
+        );
+
+        // secrets are not exported
+        let api_keys: serde_json::Value =
+            serde_json::from_reader(archive.by_name("api_keys.json").unwrap()).unwrap();
+        assert!(!api_keys.to_string().contains("password_hash"));
+        let domains: serde_json::Value =
+            serde_json::from_reader(archive.by_name("domains.json").unwrap()).unwrap();
+        assert!(!domains.to_string().contains("dkim_pkcs8_der"));
+
+        let audit_log = AuditLogRepository::new(pool).list(org_1).await.unwrap();
+        assert!(
+            audit_log
+                .iter()
+                .any(|entry| entry.action == "Requested organization export")
+        );
+    }
+
+    #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "api_users")))]
+    async fn test_organization_export_no_access(pool: PgPool) {
+        let user_3 = "54432300-128a-46a0-8a83-fe39ce3ce5ef".parse().unwrap(); // has no organizations
+        let org_1 = TestProjects::Org1Project1.org_id();
+        let server = TestServer::new(pool, Some(user_3)).await;
+
+        let response = server
+            .post(format!("/api/organizations/{org_1}/exports"), Body::empty())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -8,16 +8,21 @@ use crate::{
     models::{
         ApiUser, ApiUserId, AuditLogEntry, AuditLogRepository, NewOrganization, OrgBlockStatus,
         Organization, OrganizationId, OrganizationMember, OrganizationRepository, Role,
-        RuntimeConfigRepository, Statistics, StatisticsRepository,
+        RuntimeConfigRepository, Statistics, StatisticsRepository, UsageSnapshot,
     },
+    system_emails::send_block_status_email,
 };
 use axum::{
     Json,
     extract::{Path, State},
     response::IntoResponse,
 };
+use email_address::EmailAddress;
+use garde::Validate;
 use http::StatusCode;
-use tracing::{debug, info};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info, warn};
+use utoipa::ToSchema;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 pub fn router() -> OpenApiRouter<ApiState> {
@@ -29,9 +34,11 @@ pub fn router() -> OpenApiRouter<ApiState> {
             update_organization
         ))
         .routes(routes!(get_statistics))
+        .routes(routes!(get_usage_history))
         .routes(routes!(list_members))
         .routes(routes!(remove_member, update_member_role))
         .routes(routes!(update_block_status))
+        .routes(routes!(update_sending_paused))
         .routes(routes!(get_audit_log))
 }
 
@@ -199,6 +206,36 @@ pub async fn get_statistics(
     Ok(Json(statistics))
 }
 
+/// Get organization usage history
+///
+/// Returns the message quota usage of each past billing period of the organization, oldest first.
+/// A new entry is added every time the organization's message quota is reset.
+#[utoipa::path(get, path = "/organizations/{org_id}/usage",
+    tags = ["Organizations"],
+    responses(
+        (status = 200, description = "Successfully fetched organization usage history", body = [UsageSnapshot]),
+        AppError,
+    )
+)]
+pub async fn get_usage_history(
+    Path((org_id,)): Path<(OrganizationId,)>,
+    State(repo): State<OrganizationRepository>,
+    user: Box<dyn Authenticated>,
+) -> ApiResult<Vec<UsageSnapshot>> {
+    user.has_org_read_access(&org_id)?;
+
+    let history = repo.usage_history(org_id).await?;
+
+    debug!(
+        user_id = user.log_id(),
+        organization_id = org_id.to_string(),
+        "listed usage history ({} periods)",
+        history.len(),
+    );
+
+    Ok(Json(history))
+}
+
 /// List organization members
 ///
 /// Returns all members of the organization. This does not include the API keys, but only the users.
@@ -348,6 +385,7 @@ pub async fn get_audit_log(
 pub async fn update_block_status(
     Path(org_id): Path<OrganizationId>,
     State(repo): State<OrganizationRepository>,
+    State(state): State<ApiState>,
     user: ApiUser, // only users (super admins) are allowed to update block status
     ValidatedJson(block_status): ValidatedJson<OrgBlockStatus>,
 ) -> ApiResult<Organization> {
@@ -355,6 +393,12 @@ pub async fn update_block_status(
         .then_some(())
         .ok_or(AppError::Forbidden)?;
 
+    let previous = repo
+        .get_by_id(org_id)
+        .await?
+        .ok_or(AppError::NotFound)?
+        .block_status();
+
     let organization = repo.update_block_status(org_id, block_status).await?;
 
     info!(
@@ -364,6 +408,97 @@ pub async fn update_block_status(
         "updated organization block status",
     );
 
+    if previous != block_status {
+        notify_admins_of_block_status(&state, &repo, &organization).await;
+    }
+
+    Ok(Json(organization))
+}
+
+/// Sends an email to each admin of the organization explaining its new block status.
+///
+/// The block status is updated regardless, so failures are only logged.
+async fn notify_admins_of_block_status(
+    state: &ApiState,
+    repo: &OrganizationRepository,
+    organization: &Organization,
+) {
+    let members = match repo.list_members(organization.id()).await {
+        Ok(members) => members,
+        Err(err) => {
+            error!(
+                organization_id = organization.id().to_string(),
+                "failed to list admins to notify of block status change: {err}"
+            );
+            return;
+        }
+    };
+
+    for admin in members.iter().filter(|m| *m.role() == Role::Admin) {
+        let Ok(email) = admin.email().parse::<EmailAddress>() else {
+            warn!(
+                user_id = admin.user_id().to_string(),
+                organization_id = organization.id().to_string(),
+                "cannot notify admin of block status change, invalid email address"
+            );
+            continue;
+        };
+
+        if let Err(err) = send_block_status_email(
+            state,
+            email,
+            &organization.name,
+            organization.block_status(),
+        )
+        .await
+        {
+            error!(
+                user_id = admin.user_id().to_string(),
+                organization_id = organization.id().to_string(),
+                "failed to notify admin of block status change: {err}"
+            );
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema, Validate)]
+pub struct SendingPause {
+    #[garde(skip)]
+    sending_paused: bool,
+}
+
+/// Pause or resume sending
+///
+/// While sending is paused, new messages are still accepted, but not delivered.
+/// They are delivered once sending is resumed.
+/// Unlike a block by the Remails admins, organization admins can pause and resume sending themselves.
+#[utoipa::path(put, path = "/organizations/{org_id}/sending_paused",
+    request_body = SendingPause,
+    tags = ["Organizations"],
+    responses(
+        (status = 200, description = "Successfully paused or resumed sending", body = Organization),
+        AppError,
+    )
+)]
+pub async fn update_sending_paused(
+    Path(org_id): Path<OrganizationId>,
+    State(repo): State<OrganizationRepository>,
+    user: Box<dyn Authenticated>,
+    ValidatedJson(pause): ValidatedJson<SendingPause>,
+) -> ApiResult<Organization> {
+    user.has_org_admin_access(&org_id)?;
+
+    let organization = repo
+        .update_sending_paused(org_id, pause.sending_paused, &user)
+        .await?;
+
+    info!(
+        user_id = user.log_id(),
+        organization_id = org_id.to_string(),
+        sending_paused = pause.sending_paused,
+        "updated organization sending pause",
+    );
+
     Ok(Json(organization))
 }
 
@@ -755,6 +890,50 @@ mod tests {
         assert_eq!(response.status(), StatusCode::FORBIDDEN);
     }
 
+    #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "api_users")))]
+    async fn test_pause_sending(pool: PgPool) {
+        let org_1 = TestProjects::Org1Project1.org_id();
+        let user_1 = "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap(); // is admin of org 1 and 2
+        let user_4 = "c33dbd88-43ed-404b-9367-1659a73c8f3a".parse().unwrap(); // is maintainer of org 1
+        let mut server = TestServer::new(pool.clone(), Some(user_4)).await;
+
+        // only organization admins can pause sending
+        let response = server
+            .put(
+                format!("/api/organizations/{org_1}/sending_paused"),
+                serialize_body(SendingPause {
+                    sending_paused: true,
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        server.set_user(Some(user_1));
+        for sending_paused in [true, false] {
+            let response = server
+                .put(
+                    format!("/api/organizations/{org_1}/sending_paused"),
+                    serialize_body(SendingPause { sending_paused }),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let organization: serde_json::Value = deserialize_body(response.into_body()).await;
+            assert_eq!(organization["sending_paused"], sending_paused);
+            // pausing is not a block
+            assert_eq!(organization["block_status"], "not_blocked");
+        }
+
+        let audit_log = AuditLogRepository::new(pool).list(org_1).await.unwrap();
+        let actions = audit_log
+            .iter()
+            .map(|entry| entry.action.as_str())
+            .collect::<Vec<_>>();
+        assert!(actions.contains(&"Paused sending"));
+        assert!(actions.contains(&"Resumed sending"));
+    }
+
     #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "api_users")))]
     async fn test_super_admins_can_unfreeze_organization(pool: PgPool) {
         let org_1 = "44729d9f-a7dc-4226-b412-36a7537f5176";
@@ -822,6 +1001,70 @@ mod tests {
         assert_eq!(response.status(), StatusCode::OK);
     }
 
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "api_users", "projects", "runtime_config")
+    ))]
+    async fn test_block_status_notification(pool: PgPool) {
+        let org_2 = "5d55aec5-136a-407c-952f-5348d4398204";
+        let admin = "deadbeef-4e43-4a66-bbb9-fbcd4a933a34".parse().unwrap(); // is super admin
+        let server = TestServer::new(pool.clone(), Some(admin)).await;
+
+        let notifications = async || {
+            sqlx::query_scalar!(
+                r#"
+                SELECT recipients[1] AS "recipient!" FROM messages
+                WHERE label = 'block-status'
+                ORDER BY recipients[1]
+                "#
+            )
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+        };
+
+        let set_block_status = async |block_status: OrgBlockStatus| {
+            let response = server
+                .put(
+                    format!("/api/organizations/{org_2}/admin"),
+                    serialize_body(block_status),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        };
+
+        // both admins of org 2 are notified
+        set_block_status(OrgBlockStatus::NoSending).await;
+        assert_eq!(
+            notifications().await,
+            vec!["admin@example.com", "test-api@user-2"]
+        );
+
+        let raw_data = sqlx::query_scalar!(
+            r#"
+            SELECT raw_data FROM messages WHERE label = 'block-status' LIMIT 1
+            "#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let message = mail_parser::MessageParser::default()
+            .parse(&raw_data)
+            .unwrap();
+        let text = message.body_text(0).unwrap();
+        assert!(text.contains("Sending emails has been blocked for the test org 2 organization"));
+        assert!(text.contains("support@remails.com"));
+
+        // setting the same block status again does not notify the admins again
+        set_block_status(OrgBlockStatus::NoSending).await;
+        assert_eq!(notifications().await.len(), 2);
+
+        // lifting the block does
+        set_block_status(OrgBlockStatus::NotBlocked).await;
+        assert_eq!(notifications().await.len(), 4);
+    }
+
     #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "api_users")))]
     async fn test_organization_members(pool: PgPool) {
         let user_1 = "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap(); // is admin of org 1 and 2
@@ -1010,4 +1253,66 @@ mod tests {
             .unwrap();
         assert_eq!(response.status(), StatusCode::FORBIDDEN);
     }
+
+    #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "api_users")))]
+    async fn test_usage_history(pool: PgPool) {
+        let user_1 = "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap(); // is admin of org 1 and 2
+        let org_1 = "44729d9f-a7dc-4226-b412-36a7537f5176";
+        let mut server = TestServer::new(pool.clone(), Some(user_1)).await;
+
+        // no history before the first quota reset
+        let response = server
+            .get(format!("/api/organizations/{org_1}/usage"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let history: Vec<UsageSnapshot> = deserialize_body(response.into_body()).await;
+        assert!(history.is_empty());
+
+        // let the current period of org 1 end
+        let period_end = sqlx::query_scalar!(
+            r#"
+            UPDATE organizations
+            SET used_message_quota = 42,
+                quota_reset = now() - INTERVAL '1 minute',
+                moneybird_contact_id = 'usage_history_test_org'
+            WHERE id = $1
+            RETURNING quota_reset AS "quota_reset!"
+            "#,
+            org_1.parse::<uuid::Uuid>().unwrap()
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+
+        crate::moneybird::MoneyBird::new(pool.clone())
+            .await
+            .unwrap()
+            .reset_all_quotas()
+            .await
+            .unwrap();
+
+        let response = server
+            .get(format!("/api/organizations/{org_1}/usage"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let history: Vec<UsageSnapshot> = deserialize_body(response.into_body()).await;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].used_message_quota, 42);
+        assert_eq!(history[0].total_message_quota, 800);
+        assert_eq!(
+            history[0].period_end.map(|d| d.timestamp_millis()),
+            Some(period_end.timestamp_millis())
+        );
+
+        // users outside the organization can't see its usage
+        let user_3 = "54432300-128a-46a0-8a83-fe39ce3ce5ef".parse().unwrap(); // has no organizations
+        server.set_user(Some(user_3));
+        let response = server
+            .get(format!("/api/organizations/{org_1}/usage"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
 }
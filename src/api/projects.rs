@@ -3,10 +3,12 @@ use crate::{
         ApiState,
         auth::Authenticated,
         error::{ApiResult, AppError},
-        validation::ValidatedJson,
+        validation::{ValidatedJson, ValidatedQuery},
     },
+    handler::Handler,
     models::{
-        NewProject, OrganizationId, OrganizationRepository, Project, ProjectId, ProjectRepository,
+        DomainRepository, DomainStatistics, DomainStatisticsFilter, NewProject, OrganizationId,
+        OrganizationRepository, Project, ProjectId, ProjectRepository, StatisticsRepository,
     },
 };
 use axum::{
@@ -14,6 +16,7 @@ use axum::{
     extract::{Path, State},
     response::IntoResponse,
 };
+use email_address::EmailAddress;
 use http::StatusCode;
 use tracing::debug;
 use utoipa_axum::{router::OpenApiRouter, routes};
@@ -22,6 +25,7 @@ pub fn router() -> OpenApiRouter<ApiState> {
     OpenApiRouter::new()
         .routes(routes!(list_projects, create_project,))
         .routes(routes!(update_project, remove_project))
+        .routes(routes!(get_domain_statistics))
 }
 
 /// List projects
@@ -53,9 +57,48 @@ pub async fn list_projects(
     Ok(Json(projects))
 }
 
+/// Check that the project is permitted to use the domain of its default sender address, if any
+async fn check_default_sender(
+    domains: &DomainRepository,
+    project_id: Option<ProjectId>,
+    project: &NewProject,
+) -> Result<(), AppError> {
+    let Some(from_email) = &project.default_from_email else {
+        if project.default_from_name.is_some() {
+            return Err(AppError::BadRequest(
+                "A default sender name requires a default sender address".to_owned(),
+            ));
+        }
+        return Ok(());
+    };
+
+    let from_email: EmailAddress = from_email
+        .parse()
+        .map_err(|_| AppError::BadRequest(format!("Invalid default sender: {from_email}")))?;
+    let sender_domain = from_email.domain();
+
+    // domains are linked to existing projects, so a new project cannot use any domain yet
+    let domain = match project_id {
+        Some(project_id) => {
+            domains
+                .lookup_domain_name(sender_domain, project_id)
+                .await?
+        }
+        None => None,
+    };
+
+    match domain {
+        Some(domain) if Handler::is_subdomain(sender_domain, &domain.domain) => Ok(()),
+        _ => Err(AppError::DomainNotAuthorized(format!(
+            "Project is not permitted to use domain {sender_domain}"
+        ))),
+    }
+}
+
 /// Update a project
 ///
-/// Update details about that project
+/// Update details about that project.
+/// The project must be permitted to use the domain of the default sender address, if set.
 #[utoipa::path(put, path = "/organizations/{org_id}/projects/{proj_id}",
     tags = ["Projects"],
     request_body = NewProject,
@@ -67,6 +110,7 @@ pub async fn list_projects(
 pub async fn update_project(
     State(repo): State<ProjectRepository>,
     State(org_repo): State<OrganizationRepository>,
+    State(domains): State<DomainRepository>,
     Path((org_id, proj_id)): Path<(OrganizationId, ProjectId)>,
     user: Box<dyn Authenticated>,
     ValidatedJson(update): ValidatedJson<NewProject>,
@@ -80,6 +124,8 @@ pub async fn update_project(
         )));
     }
 
+    check_default_sender(&domains, Some(proj_id), &update).await?;
+
     let project = repo.update(org_id, proj_id, &update, &user).await?;
 
     Ok(Json(project))
@@ -99,6 +145,7 @@ pub async fn update_project(
 pub async fn create_project(
     State(repo): State<ProjectRepository>,
     State(org_repo): State<OrganizationRepository>,
+    State(domains): State<DomainRepository>,
     user: Box<dyn Authenticated>,
     Path((org_id,)): Path<(OrganizationId,)>,
     ValidatedJson(new): ValidatedJson<NewProject>,
@@ -118,6 +165,8 @@ pub async fn create_project(
         )));
     }
 
+    check_default_sender(&domains, None, &new).await?;
+
     let project = repo.create(&new, org_id, &user).await?;
 
     Ok((StatusCode::CREATED, Json(project)))
@@ -143,6 +192,40 @@ pub async fn remove_project(
     Ok(Json(project_id))
 }
 
+/// Get per-domain delivery statistics
+///
+/// Returns, per recipient domain, how many recipients of the project's messages have been
+/// delivered, are deferred (i.e., still waiting to be (re)attempted), or have failed.
+/// By default, this covers the messages created in the past 30 days.
+#[utoipa::path(get, path = "/organizations/{org_id}/projects/{proj_id}/statistics/domains",
+    tags = ["Projects"],
+    params(DomainStatisticsFilter),
+    responses(
+        (status = 200, description = "Successfully fetched domain statistics", body = [DomainStatistics]),
+        AppError,
+    )
+)]
+pub async fn get_domain_statistics(
+    State(repo): State<StatisticsRepository>,
+    Path((org_id, proj_id)): Path<(OrganizationId, ProjectId)>,
+    user: Box<dyn Authenticated>,
+    ValidatedQuery(filter): ValidatedQuery<DomainStatisticsFilter>,
+) -> ApiResult<Vec<DomainStatistics>> {
+    user.has_org_read_access(&org_id)?;
+
+    let statistics = repo.get_domain_stats(org_id, proj_id, &filter).await?;
+
+    debug!(
+        user_id = user.log_id(),
+        organization_id = org_id.to_string(),
+        project_id = proj_id.to_string(),
+        "listed domain statistics for {} domains",
+        statistics.len()
+    );
+
+    Ok(Json(statistics))
+}
+
 #[cfg(test)]
 mod tests {
     use chrono::Utc;
@@ -175,11 +258,7 @@ mod tests {
         let response = server
             .post(
                 format!("/api/organizations/{org_1}/projects"),
-                serialize_body(&NewProject {
-                    name: "Test Project".to_string(),
-                    retention_period_days: 1,
-                    plaintext_fallback: false,
-                }),
+                serialize_body(&NewProject::test("Test Project")),
             )
             .await
             .unwrap();
@@ -204,9 +283,8 @@ mod tests {
             .put(
                 format!("/api/organizations/{org_1}/projects/{}", project.id()),
                 serialize_body(&NewProject {
-                    name: "Updated Project".to_string(),
-                    retention_period_days: 1,
                     plaintext_fallback: true,
+                    ..NewProject::test("Updated Project")
                 }),
             )
             .await
@@ -268,11 +346,7 @@ mod tests {
         let response = server
             .post(
                 format!("/api/organizations/{org_1}/projects"),
-                serialize_body(&NewProject {
-                    name: "Test Project".to_string(),
-                    retention_period_days: 1,
-                    plaintext_fallback: false,
-                }),
+                serialize_body(&NewProject::test("Test Project")),
             )
             .await
             .unwrap();
@@ -283,9 +357,8 @@ mod tests {
             .put(
                 format!("/api/organizations/{org_1}/projects/{proj_1}"),
                 serialize_body(&NewProject {
-                    name: "Updated Project".to_string(),
-                    retention_period_days: 1,
                     plaintext_fallback: true,
+                    ..NewProject::test("Updated Project")
                 }),
             )
             .await
@@ -326,11 +399,7 @@ mod tests {
         let response = server
             .post(
                 format!("/api/organizations/{org_1}/projects"),
-                serialize_body(&NewProject {
-                    name: "Test Project".to_string(),
-                    retention_period_days: 1,
-                    plaintext_fallback: false,
-                }),
+                serialize_body(&NewProject::test("Test Project")),
             )
             .await
             .unwrap();
@@ -346,11 +415,7 @@ mod tests {
         let response = server
             .post(
                 format!("/api/organizations/{org_1}/projects"),
-                serialize_body(&NewProject {
-                    name: "Test Project".to_string(),
-                    retention_period_days: 1,
-                    plaintext_fallback: false,
-                }),
+                serialize_body(&NewProject::test("Test Project")),
             )
             .await
             .unwrap();
@@ -369,11 +434,7 @@ mod tests {
         let response = server
             .post(
                 format!("/api/organizations/{org_1}/projects"),
-                serialize_body(&NewProject {
-                    name: "Test Project".to_string(),
-                    retention_period_days: 1,
-                    plaintext_fallback: false,
-                }),
+                serialize_body(&NewProject::test("Test Project")),
             )
             .await
             .unwrap();
@@ -389,11 +450,7 @@ mod tests {
         let response = server
             .post(
                 format!("/api/organizations/{org_1}/projects"),
-                serialize_body(&NewProject {
-                    name: "Test Project 1".to_string(),
-                    retention_period_days: 1,
-                    plaintext_fallback: false,
-                }),
+                serialize_body(&NewProject::test("Test Project 1")),
             )
             .await
             .unwrap();
@@ -403,11 +460,7 @@ mod tests {
         let response = server
             .post(
                 format!("/api/organizations/{org_1}/projects"),
-                serialize_body(&NewProject {
-                    name: "Test Project 2".to_string(),
-                    retention_period_days: 1,
-                    plaintext_fallback: false,
-                }),
+                serialize_body(&NewProject::test("Test Project 2")),
             )
             .await
             .unwrap();
@@ -437,9 +490,8 @@ mod tests {
                 .post(
                     format!("/api/organizations/{org_1}/projects"),
                     serialize_body(&NewProject {
-                        name: format!("Test Project {}", i + 2),
                         retention_period_days: 3, // all paid subscriptions allow at least 3 day retention
-                        plaintext_fallback: false,
+                        ..NewProject::test(&format!("Test Project {}", i + 2))
                     }),
                 )
                 .await
@@ -466,9 +518,8 @@ mod tests {
                 .post(
                     format!("/api/organizations/{org_1}/projects"),
                     serialize_body(&NewProject {
-                        name: "Test Project 1".to_string(),
                         retention_period_days: 3,
-                        plaintext_fallback: false,
+                        ..NewProject::test("Test Project 1")
                     }),
                 )
                 .await
@@ -491,9 +542,8 @@ mod tests {
                 .post(
                     format!("/api/organizations/{org_1}/projects"),
                     serialize_body(&NewProject {
-                        name: "Test Project 1".to_string(),
                         retention_period_days: 30,
-                        plaintext_fallback: false,
+                        ..NewProject::test("Test Project 1")
                     }),
                 )
                 .await
@@ -515,9 +565,8 @@ mod tests {
             .post(
                 format!("/api/organizations/{org_1}/projects"),
                 serialize_body(&NewProject {
-                    name: "Test Project 1".to_string(),
                     retention_period_days: 30,
-                    plaintext_fallback: false,
+                    ..NewProject::test("Test Project 1")
                 }),
             )
             .await
@@ -532,9 +581,8 @@ mod tests {
             .post(
                 format!("/api/organizations/{org_1}/projects"),
                 serialize_body(&NewProject {
-                    name: "Test Project 1".to_string(),
                     retention_period_days: 31,
-                    plaintext_fallback: false,
+                    ..NewProject::test("Test Project 1")
                 }),
             )
             .await
@@ -546,9 +594,8 @@ mod tests {
             .put(
                 format!("/api/organizations/{org_1}/projects/{proj_id}"),
                 serialize_body(&NewProject {
-                    name: "Updated Project".to_string(),
                     retention_period_days: 31,
-                    plaintext_fallback: false,
+                    ..NewProject::test("Updated Project")
                 }),
             )
             .await
@@ -560,9 +607,8 @@ mod tests {
             .put(
                 format!("/api/organizations/{org_1}/projects/{proj_id}"),
                 serialize_body(&NewProject {
-                    name: "Updated Project".to_string(),
                     retention_period_days: 7,
-                    plaintext_fallback: false,
+                    ..NewProject::test("Updated Project")
                 }),
             )
             .await
@@ -571,4 +617,60 @@ mod tests {
         let proj: Project = deserialize_body(response.into_body()).await;
         assert_eq!(proj.retention_period_days, 7);
     }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "api_users",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "smtp_credentials",
+            "delivery_details"
+        )
+    ))]
+    async fn test_domain_statistics(pool: PgPool) {
+        let user_a = "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap(); // is admin of org 1 and 2
+        let user_b = "94a98d6f-1ec0-49d2-a951-92dc0ff3042a".parse().unwrap(); // is admin of org 2
+        let org_1 = "44729d9f-a7dc-4226-b412-36a7537f5176";
+        let proj_1 = "3ba14adf-4de1-4fb6-8c20-50cc2ded5462";
+
+        let server = TestServer::new(pool.clone(), Some(user_a)).await;
+        let response = server
+            .get(format!(
+                "/api/organizations/{org_1}/projects/{proj_1}/statistics/domains"
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let stats: Vec<DomainStatistics> = deserialize_body(response.into_body()).await;
+        assert_eq!(
+            stats,
+            vec![
+                DomainStatistics {
+                    domain: "gmail.com".to_string(),
+                    delivered: 2,
+                    deferred: 0,
+                    failed: 1,
+                },
+                DomainStatistics {
+                    domain: "outlook.com".to_string(),
+                    delivered: 1,
+                    deferred: 1,
+                    failed: 1,
+                },
+            ]
+        );
+
+        // no access to other organizations
+        let server = TestServer::new(pool.clone(), Some(user_b)).await;
+        let response = server
+            .get(format!(
+                "/api/organizations/{org_1}/projects/{proj_1}/statistics/domains"
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
 }
@@ -0,0 +1,238 @@
+//! Breach check of new passwords using the Have I Been Pwned (HIBP) range API
+//!
+//! Only the first 5 hexadecimal characters of the SHA-1 hash of a password are sent to HIBP
+//! (k-anonymity). HIBP responds with the hash suffixes of all breached passwords with that prefix.
+
+use crate::{api::error::AppError, models::Password};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+use url::Url;
+
+const DEFAULT_API_URL: &str = "https://api.pwnedpasswords.com";
+const PREFIX_LENGTH: usize = 5;
+/// How long the response for a hash prefix is reused
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+/// Maximum number of hash prefixes kept in the cache
+const CACHE_CAPACITY: usize = 1024;
+
+#[derive(Clone)]
+pub struct PwnedPasswords {
+    /// `None` if the breach check is disabled
+    inner: Option<Arc<Inner>>,
+}
+
+struct Inner {
+    client: reqwest::Client,
+    api_url: Url,
+    cache: Mutex<HashMap<String, CachedRange>>,
+}
+
+struct CachedRange {
+    fetched_at: Instant,
+    suffixes: Arc<HashSet<String>>,
+}
+
+impl PwnedPasswords {
+    /// Configure the breach check using the following environment variables:
+    /// - `HIBP_CHECK_ENABLED`: set to `true` to enable the check, disabled by default
+    /// - `HIBP_API_URL`: defaults to `https://api.pwnedpasswords.com`
+    /// - `HIBP_TIMEOUT_MS`: timeout of the HIBP request, defaults to 2000 ms
+    pub fn from_env() -> Self {
+        if !env::var("HIBP_CHECK_ENABLED").is_ok_and(|v| v == "true") {
+            return Self::disabled();
+        }
+
+        let api_url = env::var("HIBP_API_URL")
+            .unwrap_or(DEFAULT_API_URL.to_string())
+            .parse()
+            .expect("Invalid HIBP_API_URL env var");
+        let timeout = env::var("HIBP_TIMEOUT_MS")
+            .map(|s| Duration::from_millis(s.parse().expect("Invalid HIBP_TIMEOUT_MS env var")))
+            .unwrap_or(Duration::from_secs(2));
+
+        Self::new(api_url, timeout)
+    }
+
+    pub fn new(api_url: Url, timeout: Duration) -> Self {
+        let client = reqwest::ClientBuilder::new()
+            .timeout(timeout)
+            .user_agent("remails")
+            .build()
+            .expect("Could not create HIBP client");
+
+        Self {
+            inner: Some(Arc::new(Inner {
+                client,
+                api_url,
+                cache: Mutex::new(HashMap::new()),
+            })),
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self { inner: None }
+    }
+
+    /// Check whether the password is known to be part of a data breach
+    ///
+    /// If the check is disabled or HIBP could not be reached in time, the password is considered
+    /// not breached.
+    pub async fn is_breached(&self, password: &Password) -> bool {
+        let Some(inner) = &self.inner else {
+            return false;
+        };
+
+        let hash = password.sha1_hex();
+        let (prefix, suffix) = hash.split_at(PREFIX_LENGTH);
+
+        match inner.range(prefix).await {
+            Ok(suffixes) => suffixes.contains(suffix),
+            Err(e) => {
+                warn!("could not check password against HIBP, skipping breach check: {e}");
+                false
+            }
+        }
+    }
+
+    /// Reject a newly chosen password if it is known to be part of a data breach
+    pub async fn reject_breached(&self, password: &Password) -> Result<(), AppError> {
+        if self.is_breached(password).await {
+            return Err(AppError::BadRequest(
+                "This password has appeared in a data breach, please choose a different password"
+                    .to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Inner {
+    async fn range(&self, prefix: &str) -> Result<Arc<HashSet<String>>, reqwest::Error> {
+        if let Some(cached) = self
+            .cache
+            .lock()
+            .unwrap()
+            .get(prefix)
+            .filter(|cached| cached.fetched_at.elapsed() < CACHE_TTL)
+        {
+            return Ok(cached.suffixes.clone());
+        }
+
+        debug!(prefix, "requesting breached password range from HIBP");
+
+        let mut url = self.api_url.clone();
+        url.set_path(&format!("range/{prefix}"));
+
+        let body = self
+            .client
+            .get(url)
+            .header("Add-Padding", "true")
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        // lines look like `<suffix>:<count>`, padding entries have a count of 0
+        let suffixes = Arc::new(
+            body.lines()
+                .filter_map(|line| {
+                    let (suffix, count) = line.trim().split_once(':')?;
+                    (count != "0").then(|| suffix.to_ascii_uppercase())
+                })
+                .collect::<HashSet<_>>(),
+        );
+
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= CACHE_CAPACITY {
+            cache.retain(|_, cached| cached.fetched_at.elapsed() < CACHE_TTL);
+        }
+        if cache.len() >= CACHE_CAPACITY {
+            cache.clear();
+        }
+        cache.insert(
+            prefix.to_string(),
+            CachedRange {
+                fetched_at: Instant::now(),
+                suffixes: suffixes.clone(),
+            },
+        );
+
+        Ok(suffixes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{Router, extract::Path, routing::get};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::TcpListener;
+
+    /// Serves a range response in which `breached` is the only breached password
+    async fn mock_hibp(breached: &Password, requests: Arc<AtomicUsize>) -> Url {
+        let breached_hash = breached.sha1_hex();
+
+        let router = Router::new().route(
+            "/range/{prefix}",
+            get(move |Path(prefix): Path<String>| {
+                requests.fetch_add(1, Ordering::SeqCst);
+
+                let mut body = "0018A45C4D1DEF81644B54AB7F969B88D65:0\r\n".to_string();
+                if let Some(suffix) = breached_hash.strip_prefix(&prefix) {
+                    body.push_str(&format!("{suffix}:3861493\r\n"));
+                }
+                async move { body }
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        format!("http://{addr}").parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn breached_password() {
+        let breached = Password::new("P@ssw0rd-from-a-breach".to_string());
+        let other = Password::new("correct-Horse-7-battery-Staple".to_string());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let api_url = mock_hibp(&breached, requests.clone()).await;
+
+        let pwned = PwnedPasswords::new(api_url, Duration::from_secs(2));
+        assert!(pwned.is_breached(&breached).await);
+        assert!(!pwned.is_breached(&other).await);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // the range of the breached password is cached
+        assert!(pwned.is_breached(&breached).await);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // disabled check never rejects passwords
+        assert!(!PwnedPasswords::disabled().is_breached(&breached).await);
+    }
+
+    #[tokio::test]
+    async fn unreachable_hibp() {
+        // bind and immediately drop a listener to get a port nobody listens on
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let pwned = PwnedPasswords::new(
+            format!("http://{addr}").parse().unwrap(),
+            Duration::from_millis(500),
+        );
+        let password = Password::new("P@ssw0rd-from-a-breach".to_string());
+        assert!(!pwned.is_breached(&password).await);
+    }
+}
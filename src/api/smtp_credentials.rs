@@ -19,6 +19,7 @@ pub fn router() -> OpenApiRouter<ApiState> {
     OpenApiRouter::new()
         .routes(routes!(create_smtp_credential, list_smtp_credential))
         .routes(routes!(update_smtp_credential, remove_smtp_credential))
+        .routes(routes!(rotate_smtp_credential))
 }
 
 /// Create a new SMTP credential
@@ -67,6 +68,29 @@ pub async fn update_smtp_credential(
     Ok(Json(update))
 }
 
+/// Rotate the password of an SMTP credential
+///
+/// Generates a new password for the credential, while keeping its username.
+/// The old password can no longer be used, the new one is only shown in this response.
+#[utoipa::path(post, path = "/organizations/{org_id}/projects/{proj_id}/smtp_credentials/{credential_id}/rotate",
+    tags = ["SMTP Credentials"],
+    responses(
+        (status = 200, description = "Successfully rotated SMTP credential password", body = SmtpCredentialResponse),
+        AppError,
+    )
+)]
+pub async fn rotate_smtp_credential(
+    State(repo): State<SmtpCredentialRepository>,
+    user: Box<dyn Authenticated>,
+    Path((org_id, proj_id, credential_id)): Path<(OrganizationId, ProjectId, SmtpCredentialId)>,
+) -> ApiResult<SmtpCredentialResponse> {
+    user.has_org_write_access(&org_id)?;
+
+    let rotated = repo.rotate(org_id, proj_id, credential_id, &user).await?;
+
+    Ok(Json(rotated))
+}
+
 /// List all active SMTP credentials
 #[utoipa::path(get, path = "/organizations/{org_id}/projects/{proj_id}/smtp_credentials",
     tags = ["SMTP Credentials"],
@@ -117,6 +141,7 @@ pub async fn remove_smtp_credential(
 
 #[cfg(test)]
 mod tests {
+    use axum::body::Body;
     use sqlx::PgPool;
 
     use crate::{
@@ -151,6 +176,7 @@ mod tests {
         let new_cred = SmtpCredentialRequest {
             description: "Test Credential".to_string(),
             username: "testuser".to_string(),
+            cram_md5: false,
         };
         let response = server
             .post(
@@ -184,6 +210,8 @@ mod tests {
         // update credential
         let updated_cred = SmtpCredentialUpdateRequest {
             description: "Updated Credential".to_string(),
+            allowed_recipient_domains: Some(Some(vec!["example.com".to_string()])),
+            allowed_sender_addresses: Some(Some(vec!["noreply@example.com".to_string()])),
         };
         let response = server
             .put(
@@ -200,6 +228,23 @@ mod tests {
         assert_eq!(credential.description(), updated_cred.description);
         assert_eq!(credential.id(), created_credential.id());
 
+        // restrictions must be valid
+        let response = server
+            .put(
+                format!(
+                    "/api/organizations/{org_1}/projects/{proj_1}/smtp_credentials/{}",
+                    created_credential.id()
+                ),
+                serialize_body(&SmtpCredentialUpdateRequest {
+                    description: "Updated Credential".to_string(),
+                    allowed_recipient_domains: None,
+                    allowed_sender_addresses: Some(Some(vec!["not an address".to_string()])),
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
         // list credentials
         let response = server
             .get(format!(
@@ -213,6 +258,26 @@ mod tests {
         assert_eq!(credentials[0].id(), created_credential.id());
         assert_eq!(credentials[0].description(), updated_cred.description);
 
+        // rotate credential password
+        let response = server
+            .post(
+                format!(
+                    "/api/organizations/{org_1}/projects/{proj_1}/smtp_credentials/{}/rotate",
+                    created_credential.id()
+                ),
+                Body::empty(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let rotated: SmtpCredentialResponse = deserialize_body(response.into_body()).await;
+        assert_eq!(rotated.id(), created_credential.id());
+        assert_eq!(rotated.username(), created_credential.username());
+        assert_ne!(
+            rotated.cleartext_password(),
+            created_credential.cleartext_password()
+        );
+
         // remove credential
         let response = server
             .delete(format!(
@@ -262,6 +327,7 @@ mod tests {
                 serialize_body(&SmtpCredentialRequest {
                     description: "Test Credential".to_string(),
                     username: "testuser".to_string(),
+                    cram_md5: false,
                 }),
             )
             .await
@@ -274,12 +340,26 @@ mod tests {
                 format!("/api/organizations/{org_1}/projects/{proj_1}/smtp_credentials/{cred_1}"),
                 serialize_body(&SmtpCredentialUpdateRequest {
                     description: "Updated Credential".to_string(),
+                    allowed_recipient_domains: None,
+                    allowed_sender_addresses: None,
                 }),
             )
             .await
             .unwrap();
         assert_eq!(response.status(), write_status_code);
 
+        // can't rotate credentials
+        let response = server
+            .post(
+                format!(
+                    "/api/organizations/{org_1}/projects/{proj_1}/smtp_credentials/{cred_1}/rotate"
+                ),
+                Body::empty(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), write_status_code);
+
         // can't delete credentials
         let response = server
             .delete(format!(
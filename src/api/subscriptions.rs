@@ -5,14 +5,17 @@ use crate::{
         error::{ApiResult, AppError},
         validation::ValidatedJson,
     },
-    models::{ApiUser, OrganizationId, OrganizationRepository},
-    moneybird::{MoneyBird, MoneybirdWebhookPayload, SubscriptionStatus},
+    models::{ApiUser, OrganizationId, OrganizationRepository, QuotaResetSchedule},
+    moneybird::{
+        Invoice, MoneyBird, MoneybirdWebhookPayload, ProductIdentifier, SubscriptionChangePreview,
+        SubscriptionStatus,
+    },
 };
 use axum::{
     Json,
     extract::{Path, State},
 };
-use tracing::debug;
+use tracing::{debug, info};
 use url::Url;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
@@ -21,6 +24,12 @@ pub fn router() -> OpenApiRouter<ApiState> {
         .routes(routes!(get_subscription))
         .routes(routes!(get_sales_link))
         .routes(routes!(customer_management_link))
+        .routes(routes!(list_invoices))
+        .routes(routes!(preview_subscription_change))
+        .routes(routes!(
+            get_quota_reset_schedule,
+            update_quota_reset_schedule
+        ))
         .routes(routes!(moneybird_webhook))
 }
 
@@ -123,6 +132,133 @@ pub async fn customer_management_link(
     Ok(Json(moneybird.customer_contact_portal(org_id).await?))
 }
 
+/// List invoices
+///
+/// Lists the invoices sent to the organization, newest first
+#[utoipa::path(get, path = "/organizations/{org_id}/subscription/invoices",
+    tags = ["internal", "Subscription"],
+    security(("cookieAuth" = [])),
+    responses(
+        (status = 200, description = "Successfully fetched invoices", body = [Invoice]),
+        AppError
+))]
+pub async fn list_invoices(
+    State(moneybird): State<MoneyBird>,
+    user: ApiUser,
+    Path((org_id,)): Path<(OrganizationId,)>,
+) -> ApiResult<Vec<Invoice>> {
+    user.has_org_admin_access(&org_id)?;
+
+    debug!(
+        user_id = user.id().to_string(),
+        organization_id = org_id.to_string(),
+        "list invoices"
+    );
+
+    Ok(Json(moneybird.list_invoices(org_id).await?))
+}
+
+/// Preview subscription change
+///
+/// Shows the resulting quota and the moment the change takes effect when changing to another
+/// product, without changing the subscription
+#[utoipa::path(get, path = "/organizations/{org_id}/subscription/preview/{product}",
+    tags = ["internal", "Subscription"],
+    security(("cookieAuth" = [])),
+    responses(
+        (status = 200, description = "Successfully previewed subscription change", body = SubscriptionChangePreview),
+        AppError
+))]
+pub async fn preview_subscription_change(
+    State(moneybird): State<MoneyBird>,
+    user: ApiUser,
+    Path((org_id, product)): Path<(OrganizationId, ProductIdentifier)>,
+) -> ApiResult<SubscriptionChangePreview> {
+    user.has_org_read_access(&org_id)?;
+
+    if product == ProductIdentifier::NotSubscribed {
+        return Err(AppError::BadRequest(
+            "Cannot preview a change to no subscription".to_string(),
+        ));
+    }
+
+    debug!(
+        user_id = user.id().to_string(),
+        organization_id = org_id.to_string(),
+        product = product.to_string(),
+        "preview subscription change"
+    );
+
+    Ok(Json(
+        moneybird
+            .preview_subscription_change(org_id, product)
+            .await?,
+    ))
+}
+
+/// Get the quota reset time
+///
+/// Returns the time of day at which the message quota resets, on the last day of each
+/// subscription period. Fields that are not set use the Remails default.
+#[utoipa::path(get, path = "/organizations/{org_id}/subscription/quota_reset_time",
+    tags = ["internal", "Subscription"],
+    security(("cookieAuth" = [])),
+    responses(
+        (status = 200, description = "Successfully fetched the quota reset time", body = QuotaResetSchedule),
+        AppError
+))]
+pub async fn get_quota_reset_schedule(
+    State(repo): State<OrganizationRepository>,
+    user: ApiUser,
+    Path((org_id,)): Path<(OrganizationId,)>,
+) -> ApiResult<QuotaResetSchedule> {
+    user.has_org_read_access(&org_id)?;
+
+    debug!(
+        user_id = user.id().to_string(),
+        organization_id = org_id.to_string(),
+        "get quota reset time"
+    );
+
+    Ok(Json(repo.quota_reset_schedule(org_id).await?))
+}
+
+/// Update the quota reset time
+///
+/// Changes the time of day at which the message quota resets, starting with the current
+/// subscription period
+#[utoipa::path(put, path = "/organizations/{org_id}/subscription/quota_reset_time",
+    request_body = QuotaResetSchedule,
+    tags = ["internal", "Subscription"],
+    security(("cookieAuth" = [])),
+    responses(
+        (status = 200, description = "Successfully updated the quota reset time", body = QuotaResetSchedule),
+        AppError
+))]
+pub async fn update_quota_reset_schedule(
+    State(repo): State<OrganizationRepository>,
+    State(moneybird): State<MoneyBird>,
+    user: ApiUser,
+    Path((org_id,)): Path<(OrganizationId,)>,
+    ValidatedJson(schedule): ValidatedJson<QuotaResetSchedule>,
+) -> ApiResult<QuotaResetSchedule> {
+    user.has_org_admin_access(&org_id)?;
+
+    let schedule = repo
+        .update_quota_reset_schedule(org_id, &schedule, &user)
+        .await?;
+    let quota_reset = moneybird.refresh_quota_reset(org_id).await?;
+
+    info!(
+        user_id = user.id().to_string(),
+        organization_id = org_id.to_string(),
+        quota_reset = ?quota_reset,
+        "updated quota reset time"
+    );
+
+    Ok(Json(schedule))
+}
+
 /// Moneybird webhook endpoint
 #[utoipa::path(post, path = "/webhook/moneybird",
     request_body = MoneybirdWebhookPayload,
@@ -144,19 +280,61 @@ pub async fn moneybird_webhook(
 #[cfg(test)]
 mod test {
     use crate::{
-        ProductIdentifier, SubscriptionStatus,
+        Invoice, ProductIdentifier, SubscriptionChange, SubscriptionChangePreview,
+        SubscriptionStatus,
         api::{
             tests::{TestServer, deserialize_body, serialize_body},
             whoami::WhoamiResponse,
         },
-        models::{OrgBlockStatus, OrgRole, Organization, Role},
+        models::{OrgBlockStatus, OrgRole, Organization, QuotaResetSchedule, Role},
+        moneybird::mock_invoices,
     };
-    use chrono::Utc;
+    use chrono::{DateTime, Days, NaiveTime, Utc};
     use http::StatusCode;
-    use serde_json::json;
+    use serde_json::{Value, json};
     use sqlx::PgPool;
     use std::time::Duration;
 
+    /// Moneybird webhook payload. Important are: webhook_id, webhook_token, product.identifier,
+    /// and contact.id
+    fn subscription_webhook(product: &str, updated_at: Option<DateTime<Utc>>) -> Value {
+        json!({
+            "administration_id": "mock_admin_id",
+            "webhook_id": "mock_webhook_id",
+            "webhook_token": "supersecuretoken",
+            "entity_type": "Subscription",
+            "action": "subscription_created",
+            "entity": {
+                "id": "mock_subscription_id",
+                "administration_id": "mock_admin_id",
+                "start_date": Utc::now().date_naive(),
+                "end_date": serde_json::Value::Null,
+                "updated_at": updated_at,
+                "product": {
+                    "id": "mock_product_id",
+                    "administration_id": "mock_admin_id",
+                    "description": "Webhook test product",
+                    "title": "Webhook test",
+                    "identifier": product
+                },
+                "contact": {
+                    "id": "webhook_test_org",
+                    "company_name": "mock company B.V.",
+                    "email": "mock_email@company.com",
+                    "phone": "+1234567",
+                    "address1": "mock_address1",
+                    "address2": "mock_address2",
+                    "zipcode": "1234AB",
+                    "city": "Nijmegen",
+                    "country": "NL",
+                    "sales_invoices_url": "https://tweedegolf.com",
+                    "contact_people": []
+                },
+                "recurring_sales_invoice_id": "mock_recurring_sales_invoice_id"
+            },
+        })
+    }
+
     #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "api_users")))]
     async fn webhook(db: PgPool) {
         let server = TestServer::new(
@@ -168,44 +346,11 @@ mod test {
         // Wait for the webhook to be registered
         tokio::time::sleep(Duration::from_secs(1)).await;
 
-        // Send webhook. Important are: webhook_id, webhook_token, product.identifier, and contact.id
+        // Send webhook
         let response = server
             .post(
                 "/api/webhook/moneybird",
-                serialize_body(json!({
-                    "administration_id": "mock_admin_id",
-                    "webhook_id": "mock_webhook_id",
-                    "webhook_token": "supersecuretoken",
-                    "entity_type": "Subscription",
-                    "action": "subscription_created",
-                    "entity": {
-                        "id": "mock_subscription_id",
-                        "administration_id": "mock_admin_id",
-                        "start_date": Utc::now().date_naive(),
-                        "end_date": serde_json::Value::Null,
-                        "product": {
-                            "id": "mock_product_id",
-                            "administration_id": "mock_admin_id",
-                            "description": "Webhook test product",
-                            "title": "Webhook test",
-                            "identifier": "RMLS-FREE"
-                        },
-                        "contact": {
-                            "id": "webhook_test_org",
-                            "company_name": "mock company B.V.",
-                            "email": "mock_email@company.com",
-                            "phone": "+1234567",
-                            "address1": "mock_address1",
-                            "address2": "mock_address2",
-                            "zipcode": "1234AB",
-                            "city": "Nijmegen",
-                            "country": "NL",
-                            "sales_invoices_url": "https://tweedegolf.com",
-                            "contact_people": []
-                        },
-                        "recurring_sales_invoice_id": "mock_recurring_sales_invoice_id"
-                    },
-                })),
+                serialize_body(subscription_webhook("RMLS-FREE", None)),
             )
             .await
             .unwrap();
@@ -251,4 +396,304 @@ mod test {
             }
         );
     }
+
+    #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "api_users")))]
+    async fn webhook_out_of_order(db: PgPool) {
+        let server = TestServer::new(
+            db.clone(),
+            Some("d57373be-cb77-4a2b-9e6e-66b28c4b5c7e".parse().unwrap()),
+        )
+        .await;
+
+        // Wait for the webhook to be registered
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        let newer = Utc::now();
+        let older = newer - chrono::Duration::minutes(5);
+
+        // the newer event arrives first, the older one is redelivered afterward
+        for (product, updated_at) in [("RMLS-TINY-MONTHLY", newer), ("RMLS-FREE", older)] {
+            let response = server
+                .post(
+                    "/api/webhook/moneybird",
+                    serialize_body(subscription_webhook(product, Some(updated_at))),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = server
+            .get("/api/organizations/ad76a517-3ff2-4d84-8299-742847782d4d")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let org: Organization = deserialize_body(response.into_body()).await;
+        let SubscriptionStatus::Active(subscription) = org.current_subscription() else {
+            panic!("No active subscription found in Organization");
+        };
+        assert_eq!(
+            *subscription.product_id(),
+            ProductIdentifier::RmlsTinyMonthly
+        );
+        assert_eq!(
+            org.total_message_quota(),
+            ProductIdentifier::RmlsTinyMonthly.monthly_quota() as i64
+        );
+
+        // a redelivery of the newest event is applied again
+        let response = server
+            .post(
+                "/api/webhook/moneybird",
+                serialize_body(subscription_webhook("RMLS-TINY-MONTHLY", Some(newer))),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // concurrent deliveries of an older and a newer event end with the newer state,
+        // regardless of which one is processed first
+        let deliver = async |product: &str, updated_at| {
+            server
+                .post(
+                    "/api/webhook/moneybird",
+                    serialize_body(subscription_webhook(product, Some(updated_at))),
+                )
+                .await
+                .unwrap()
+                .status()
+        };
+        for i in 1..=6 {
+            let newest = newer + chrono::Duration::minutes(2 * i);
+            let older = newest - chrono::Duration::minutes(1);
+            let (newest_product, older_product) = if i % 2 == 0 {
+                ("RMLS-SMALL-MONTHLY", "RMLS-FREE")
+            } else {
+                ("RMLS-FREE", "RMLS-SMALL-MONTHLY")
+            };
+
+            let (newest_status, older_status) = tokio::join!(
+                deliver(newest_product, newest),
+                deliver(older_product, older)
+            );
+            assert_eq!(newest_status, StatusCode::OK);
+            assert_eq!(older_status, StatusCode::OK);
+
+            let response = server
+                .get("/api/organizations/ad76a517-3ff2-4d84-8299-742847782d4d")
+                .await
+                .unwrap();
+            let org: Organization = deserialize_body(response.into_body()).await;
+            let SubscriptionStatus::Active(subscription) = org.current_subscription() else {
+                panic!("No active subscription found in Organization");
+            };
+            assert_eq!(
+                serde_json::to_value(subscription.product_id()).unwrap(),
+                newest_product
+            );
+        }
+    }
+
+    #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "api_users")))]
+    async fn list_invoices(db: PgPool) {
+        // read-only member of an organization with a Moneybird contact
+        let mut server = TestServer::new(
+            db.clone(),
+            Some("d57373be-cb77-4a2b-9e6e-66b28c4b5c7e".parse().unwrap()),
+        )
+        .await;
+        let invoices_url =
+            "/api/organizations/ad76a517-3ff2-4d84-8299-742847782d4d/subscription/invoices";
+
+        // only organization admins can see the invoices
+        let response = server.get(invoices_url).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        sqlx::query!(
+            r#"
+            UPDATE api_users_organizations
+            SET role = 'admin'
+            WHERE api_user_id = 'd57373be-cb77-4a2b-9e6e-66b28c4b5c7e'
+              AND organization_id = 'ad76a517-3ff2-4d84-8299-742847782d4d'
+            "#
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let response = server.get(invoices_url).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let invoices: Vec<Invoice> = deserialize_body(response.into_body()).await;
+        assert_eq!(invoices, mock_invoices());
+
+        // admin of an organization without a Moneybird contact
+        server.set_user(Some(
+            "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap(),
+        ));
+        let response = server
+            .get("/api/organizations/44729d9f-a7dc-4226-b412-36a7537f5176/subscription/invoices")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let invoices: Vec<Invoice> = deserialize_body(response.into_body()).await;
+        assert!(invoices.is_empty());
+
+        // not a member of the organization
+        let response = server.get(invoices_url).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "api_users")))]
+    async fn preview_subscription_change(db: PgPool) {
+        // admin of org 1, which has an active RMLS-SMALL-MONTHLY subscription
+        let server = TestServer::new(
+            db.clone(),
+            Some("9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap()),
+        )
+        .await;
+        let org_url = "/api/organizations/44729d9f-a7dc-4226-b412-36a7537f5176";
+
+        let response = server.get(org_url).await.unwrap();
+        let org: Organization = deserialize_body(response.into_body()).await;
+        let current_product = org.current_subscription().active_product().clone();
+        assert_eq!(current_product, ProductIdentifier::RmlsSmallMonthly);
+
+        // a downgrade takes effect at the end of the current subscription period, which the mock
+        // API puts at the day before the next invoice date, 10 days from now
+        let response = server
+            .get(&format!("{org_url}/subscription/preview/RMLS-TINY-MONTHLY"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let preview: SubscriptionChangePreview = deserialize_body(response.into_body()).await;
+        assert_eq!(
+            preview,
+            SubscriptionChangePreview {
+                current_product: current_product.clone(),
+                current_monthly_quota: current_product.monthly_quota(),
+                product: ProductIdentifier::RmlsTinyMonthly,
+                monthly_quota: ProductIdentifier::RmlsTinyMonthly.monthly_quota(),
+                change: SubscriptionChange::Downgrade,
+                effective_at: Utc::now()
+                    .date_naive()
+                    .checked_add_days(Days::new(9))
+                    .unwrap()
+                    .and_time(NaiveTime::from_hms_opt(23, 59, 59).unwrap())
+                    .and_utc(),
+            }
+        );
+
+        // an upgrade takes effect immediately
+        let before = Utc::now();
+        let response = server
+            .get(&format!(
+                "{org_url}/subscription/preview/RMLS-MEDIUM-MONTHLY"
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let preview: SubscriptionChangePreview = deserialize_body(response.into_body()).await;
+        assert_eq!(preview.change, SubscriptionChange::Upgrade);
+        assert_eq!(
+            preview.monthly_quota,
+            ProductIdentifier::RmlsMediumMonthly.monthly_quota()
+        );
+        assert!(preview.effective_at >= before && preview.effective_at <= Utc::now());
+
+        // the stored subscription is unchanged
+        let response = server.get(org_url).await.unwrap();
+        let after: Organization = deserialize_body(response.into_body()).await;
+        assert_eq!(after.current_subscription(), org.current_subscription());
+        assert_eq!(after.total_message_quota(), org.total_message_quota());
+
+        let response = server
+            .get(&format!("{org_url}/subscription/preview/NOT-SUBSCRIBED"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "api_users")))]
+    async fn quota_reset_time_per_organization(db: PgPool) {
+        // admin of org 1 and 2, which both have an active subscription
+        let mut server = TestServer::new(
+            db.clone(),
+            Some("9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap()),
+        )
+        .await;
+        let org_1_url = "/api/organizations/44729d9f-a7dc-4226-b412-36a7537f5176";
+        let org_2_url = "/api/organizations/5d55aec5-136a-407c-952f-5348d4398204";
+
+        let response = server
+            .get(&format!("{org_1_url}/subscription/quota_reset_time"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let schedule: QuotaResetSchedule = deserialize_body(response.into_body()).await;
+        assert_eq!(schedule, QuotaResetSchedule::default());
+
+        let response = server
+            .put(
+                &format!("{org_1_url}/subscription/quota_reset_time"),
+                serialize_body(json!({"time": "09:00:00", "timezone": "Mars/Olympus_Mons"})),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Tokyo is UTC+9 all year round
+        let response = server
+            .put(
+                &format!("{org_1_url}/subscription/quota_reset_time"),
+                serialize_body(json!({"time": "09:00:00", "timezone": "Asia/Tokyo"})),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the current period of org 1 ends on the day before the next invoice date of the mock
+        // API, 10 days from now, at the new time of day
+        let period_end = Utc::now()
+            .date_naive()
+            .checked_add_days(Days::new(9))
+            .unwrap();
+        let response = server.get(org_1_url).await.unwrap();
+        let org: Organization = deserialize_body(response.into_body()).await;
+        assert_eq!(
+            org.quota_reset(),
+            Some(
+                period_end
+                    .and_time(NaiveTime::from_hms_opt(0, 0, 0).unwrap())
+                    .and_utc()
+            )
+        );
+
+        // org 2 keeps the default, which applies to its unchanged RMLS-SMALL-MONTHLY subscription
+        let response = server
+            .get(&format!(
+                "{org_2_url}/subscription/preview/RMLS-SMALL-MONTHLY"
+            ))
+            .await
+            .unwrap();
+        let preview: SubscriptionChangePreview = deserialize_body(response.into_body()).await;
+        assert_eq!(
+            preview.effective_at,
+            period_end
+                .and_time(NaiveTime::from_hms_opt(23, 59, 59).unwrap())
+                .and_utc()
+        );
+
+        // only organization admins can change it
+        server.set_user(Some(
+            "c33dbd88-43ed-404b-9367-1659a73c8f3a".parse().unwrap(),
+        ));
+        let response = server
+            .put(
+                &format!("{org_1_url}/subscription/quota_reset_time"),
+                serialize_body(json!({"time": "10:00:00"})),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
 }
@@ -0,0 +1,321 @@
+use crate::{
+    api::{
+        ApiState,
+        auth::Authenticated,
+        error::{ApiResult, AppError},
+        validation::ValidatedJson,
+    },
+    models::{OrganizationId, Webhook, WebhookRepository, WebhookRequest, WebhookSecrets},
+};
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use tracing::{debug, info};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+pub fn router() -> OpenApiRouter<ApiState> {
+    OpenApiRouter::new()
+        .routes(routes!(get_webhook, update_webhook, remove_webhook))
+        .routes(routes!(get_webhook_secrets))
+        .routes(routes!(rotate_webhook_secret))
+}
+
+/// Get the webhook
+///
+/// Returns the endpoint delivery events of the organization are posted to
+#[utoipa::path(get, path = "/organizations/{org_id}/webhook",
+    tags = ["Webhooks"],
+    responses(
+        (status = 200, description = "Successfully fetched the webhook", body = Webhook),
+        AppError,
+    )
+)]
+pub async fn get_webhook(
+    Path((org_id,)): Path<(OrganizationId,)>,
+    State(repo): State<WebhookRepository>,
+    user: Box<dyn Authenticated>,
+) -> ApiResult<Webhook> {
+    user.has_org_admin_access(&org_id)?;
+
+    let webhook = repo.get(org_id).await?.ok_or(AppError::NotFound)?;
+
+    debug!(
+        user_id = user.log_id(),
+        organization_id = org_id.to_string(),
+        "retrieved webhook"
+    );
+
+    Ok(Json(webhook))
+}
+
+/// Set the webhook
+///
+/// After each delivery attempt, an event is posted to this URL.
+/// Requests are signed with the webhook signing secret, which is generated when the webhook is first set.
+#[utoipa::path(put, path = "/organizations/{org_id}/webhook",
+    tags = ["Webhooks"],
+    request_body = WebhookRequest,
+    responses(
+        (status = 200, description = "Successfully updated the webhook", body = Webhook),
+        AppError,
+    )
+)]
+pub async fn update_webhook(
+    Path((org_id,)): Path<(OrganizationId,)>,
+    State(repo): State<WebhookRepository>,
+    user: Box<dyn Authenticated>,
+    ValidatedJson(request): ValidatedJson<WebhookRequest>,
+) -> ApiResult<Webhook> {
+    user.has_org_admin_access(&org_id)?;
+
+    let webhook = repo.set(org_id, &request, &user).await?;
+
+    info!(
+        user_id = user.log_id(),
+        organization_id = org_id.to_string(),
+        "updated webhook"
+    );
+
+    Ok(Json(webhook))
+}
+
+/// Remove the webhook
+#[utoipa::path(delete, path = "/organizations/{org_id}/webhook",
+    tags = ["Webhooks"],
+    responses(
+        (status = 200, description = "Successfully removed the webhook", body = OrganizationId),
+        AppError,
+    )
+)]
+pub async fn remove_webhook(
+    Path((org_id,)): Path<(OrganizationId,)>,
+    State(repo): State<WebhookRepository>,
+    user: Box<dyn Authenticated>,
+) -> ApiResult<OrganizationId> {
+    user.has_org_admin_access(&org_id)?;
+
+    repo.remove(org_id, &user).await?;
+
+    info!(
+        user_id = user.log_id(),
+        organization_id = org_id.to_string(),
+        "removed webhook"
+    );
+
+    Ok(Json(org_id))
+}
+
+/// Get the webhook signing secrets
+///
+/// Returns the secret requests are currently signed with and, after a rotation, the previous secret.
+/// Accept signatures made with either secret until your webhook receiver uses the current secret.
+#[utoipa::path(get, path = "/organizations/{org_id}/webhook/secrets",
+    tags = ["Webhooks"],
+    responses(
+        (status = 200, description = "Successfully fetched the webhook signing secrets", body = WebhookSecrets),
+        AppError,
+    )
+)]
+pub async fn get_webhook_secrets(
+    Path((org_id,)): Path<(OrganizationId,)>,
+    State(repo): State<WebhookRepository>,
+    user: Box<dyn Authenticated>,
+) -> ApiResult<WebhookSecrets> {
+    user.has_org_admin_access(&org_id)?;
+
+    let secrets = repo.secrets(org_id).await?;
+
+    debug!(
+        user_id = user.log_id(),
+        organization_id = org_id.to_string(),
+        "retrieved webhook signing secrets"
+    );
+
+    Ok(Json(secrets))
+}
+
+/// Rotate the webhook signing secret
+///
+/// Requests are signed with a newly generated secret from now on.
+/// The replaced secret is returned as the previous secret, such that webhook receivers can
+/// keep accepting it until they are updated.
+/// A secret that was already the previous secret before the rotation is no longer valid.
+#[utoipa::path(post, path = "/organizations/{org_id}/webhook/secrets/rotate",
+    tags = ["Webhooks"],
+    responses(
+        (status = 200, description = "Successfully rotated the webhook signing secret", body = WebhookSecrets),
+        AppError,
+    )
+)]
+pub async fn rotate_webhook_secret(
+    Path((org_id,)): Path<(OrganizationId,)>,
+    State(repo): State<WebhookRepository>,
+    user: Box<dyn Authenticated>,
+) -> ApiResult<WebhookSecrets> {
+    user.has_org_admin_access(&org_id)?;
+
+    let secrets = repo.rotate_secret(org_id, &user).await?;
+
+    info!(
+        user_id = user.log_id(),
+        organization_id = org_id.to_string(),
+        "rotated webhook signing secret"
+    );
+
+    Ok(Json(secrets))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::tests::{TestServer, deserialize_body, serialize_body},
+        handler::webhook::{sign, verify},
+        models::AuditLogRepository,
+        test::TestProjects,
+    };
+    use axum::body::Body;
+    use chrono::Utc;
+    use http::StatusCode;
+    use sqlx::PgPool;
+
+    #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "api_users")))]
+    async fn test_webhook_secret_rotation(pool: PgPool) {
+        let org_1 = TestProjects::Org1Project1.org_id();
+        let user_1 = "9244a050-7d72-451a-9248-4b43d5108235".parse().unwrap(); // is admin of org 1 and 2
+        let user_4 = "c33dbd88-43ed-404b-9367-1659a73c8f3a".parse().unwrap(); // is maintainer of org 1
+        let mut server = TestServer::new(pool.clone(), Some(user_1)).await;
+
+        // there are no secrets before the webhook is set
+        let response = server
+            .get(format!("/api/organizations/{org_1}/webhook/secrets"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // webhooks must use https
+        let response = server
+            .put(
+                format!("/api/organizations/{org_1}/webhook"),
+                serialize_body(WebhookRequest {
+                    url: "ftp://example.com/webhook".to_string(),
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // nor point to our own network
+        let response = server
+            .put(
+                format!("/api/organizations/{org_1}/webhook"),
+                serialize_body(WebhookRequest {
+                    url: "https://169.254.169.254/latest/meta-data/".to_string(),
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = server
+            .put(
+                format!("/api/organizations/{org_1}/webhook"),
+                serialize_body(WebhookRequest {
+                    url: "https://example.com/webhook".to_string(),
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let webhook: serde_json::Value = deserialize_body(response.into_body()).await;
+        assert_eq!(webhook["url"], "https://example.com/webhook");
+
+        let response = server
+            .get(format!("/api/organizations/{org_1}/webhook/secrets"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let original: WebhookSecrets = deserialize_body(response.into_body()).await;
+        assert!(original.previous.is_none());
+
+        // only organization admins can see or rotate the secrets
+        server.set_user(Some(user_4));
+        let response = server
+            .get(format!("/api/organizations/{org_1}/webhook/secrets"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = server
+            .post(
+                format!("/api/organizations/{org_1}/webhook/secrets/rotate"),
+                Body::empty(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        server.set_user(Some(user_1));
+        let response = server
+            .post(
+                format!("/api/organizations/{org_1}/webhook/secrets/rotate"),
+                Body::empty(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let rotated: WebhookSecrets = deserialize_body(response.into_body()).await;
+        assert_ne!(rotated.current, original.current);
+        assert_eq!(rotated.previous.as_ref(), Some(&original.current));
+        assert!(rotated.rotated_at.is_some());
+
+        // during the rotation, requests signed with either secret are accepted
+        let now = Utc::now();
+        let accepted = [
+            rotated.current.as_str(),
+            rotated.previous.as_deref().unwrap(),
+        ];
+        for secret in [&original.current, &rotated.current] {
+            let signature = sign(secret, now.timestamp(), b"{}");
+            assert!(verify(&accepted, &signature, b"{}", now));
+        }
+
+        // changing the URL keeps the secrets
+        let response = server
+            .put(
+                format!("/api/organizations/{org_1}/webhook"),
+                serialize_body(WebhookRequest {
+                    url: "https://example.com/other".to_string(),
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = server
+            .get(format!("/api/organizations/{org_1}/webhook/secrets"))
+            .await
+            .unwrap();
+        let secrets: WebhookSecrets = deserialize_body(response.into_body()).await;
+        assert_eq!(secrets.current, rotated.current);
+
+        let response = server
+            .delete(format!("/api/organizations/{org_1}/webhook"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = server
+            .get(format!("/api/organizations/{org_1}/webhook"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let audit_log = AuditLogRepository::new(pool).list(org_1).await.unwrap();
+        let actions = audit_log
+            .iter()
+            .map(|entry| entry.action.as_str())
+            .collect::<Vec<_>>();
+        assert!(actions.contains(&"Updated webhook"));
+        assert!(actions.contains(&"Rotated webhook signing secret"));
+        assert!(actions.contains(&"Removed webhook"));
+    }
+}
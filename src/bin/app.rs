@@ -96,6 +96,9 @@ async fn main() -> anyhow::Result<()> {
             if let Err(e) = periodically.retry_messages().await {
                 error!("Error retrying: {e}")
             };
+            if let Err(e) = periodically.generate_exports().await {
+                error!("Error generating exports: {e}")
+            }
             if let Err(e) = periodically.clean_up().await {
                 error!("Error during clean up: {e}")
             }
@@ -4,9 +4,14 @@ use sqlx::{
     ConnectOptions,
     postgres::{PgConnectOptions, PgPoolOptions},
 };
-use std::{sync::Arc, time::Duration};
+use std::{
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::net::TcpListener;
 use tokio_util::sync::CancellationToken;
-use tracing::{info, warn};
+use tracing::{error, info, warn};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -37,6 +42,35 @@ async fn main() -> anyhow::Result<()> {
         bus_client.clone(),
         shutdown.clone(),
     );
+
+    // the health endpoint only listens on localhost, unless configured otherwise
+    if let Ok(port) = std::env::var("INBOUND_HEALTH_PORT") {
+        let port = port.parse().expect("INBOUND_HEALTH_PORT must be a u16");
+        let address = std::env::var("INBOUND_HEALTH_ADDRESS")
+            .map(|address| {
+                address
+                    .parse()
+                    .expect("INBOUND_HEALTH_ADDRESS must be an IPv4 address")
+            })
+            .unwrap_or(Ipv4Addr::LOCALHOST);
+        let socket = SocketAddrV4::new(address, port);
+        let listener = TcpListener::bind(socket)
+            .await
+            .context("failed to bind health endpoint")?;
+        let health_router = smtp_server.health_router();
+        let shutdown = shutdown.clone();
+
+        info!("health endpoint listening on {socket}");
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, health_router)
+                .with_graceful_shutdown(shutdown.cancelled_owned())
+                .await
+            {
+                error!("health endpoint error: {e}");
+            }
+        });
+    }
+
     let join_handle = smtp_server.spawn();
 
     shutdown_signal(shutdown.clone()).await;
@@ -6,9 +6,14 @@ use sqlx::{
     ConnectOptions,
     postgres::{PgConnectOptions, PgPoolOptions},
 };
-use std::{sync::Arc, time::Duration};
+use std::{
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::net::TcpListener;
 use tokio_util::sync::CancellationToken;
-use tracing::{info, warn};
+use tracing::{error, info, warn};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -36,6 +41,28 @@ async fn main() -> anyhow::Result<()> {
 
     let message_handler =
         Handler::new(pool, Arc::new(handler_config), bus_client, shutdown.clone()).await;
+
+    // the outbound nodes use the host network, so the health endpoint is only served on localhost
+    if let Ok(port) = std::env::var("OUTBOUND_HEALTH_PORT") {
+        let port = port.parse().expect("OUTBOUND_HEALTH_PORT must be a u16");
+        let socket = SocketAddrV4::new(Ipv4Addr::LOCALHOST, port);
+        let listener = TcpListener::bind(socket)
+            .await
+            .context("failed to bind health endpoint")?;
+        let health_router = message_handler.health_router();
+        let shutdown = shutdown.clone();
+
+        info!("health endpoint listening on {socket}");
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, health_router)
+                .with_graceful_shutdown(shutdown.cancelled_owned())
+                .await
+            {
+                error!("health endpoint error: {e}");
+            }
+        });
+    }
+
     let join_handle = message_handler.spawn();
 
     shutdown_signal(shutdown.clone()).await;
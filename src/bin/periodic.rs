@@ -40,11 +40,13 @@ async fn main() -> anyhow::Result<()> {
     let mut check_nodes_interval = time::interval(Duration::from_secs(10)); // Every 10 seconds
     let mut message_retry_interval = time::interval(Duration::from_secs(10)); // Every 10 seconds
     let mut domain_verification_interval = time::interval(Duration::from_secs(5 * 60)); // Every 5 minutes
+    let mut export_interval = time::interval(Duration::from_secs(10)); // Every 10 seconds
     let mut reset_all_quotas_interval = time::interval(Duration::from_secs(10 * 60)); // Every 10 minutes
     let mut clean_up_interval = time::interval(Duration::from_secs(4 * 60 * 60)); // Every 4 hours
     check_nodes_interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
     message_retry_interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
     domain_verification_interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+    export_interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
     reset_all_quotas_interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
     clean_up_interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
 
@@ -76,6 +78,13 @@ async fn main() -> anyhow::Result<()> {
                         update_healthcheck("domain_verification")
                     }
                 },
+                _ = export_interval.tick() => {
+                    if let Err(err) = periodically.generate_exports().await {
+                        error!("Failed to generate organization exports: {}", err);
+                    } else {
+                        update_healthcheck("generate_exports")
+                    }
+                },
                 _ = reset_all_quotas_interval.tick() => {
                     if let Err(err) = periodically.reset_all_quotas().await {
                         error!("Failed to reset all quotas: {}", err);
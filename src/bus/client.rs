@@ -1,19 +1,136 @@
 use async_stream::stream;
+use rand::RngExt;
 use serde::{Deserialize, Serialize};
-use std::net::IpAddr;
+use std::{net::IpAddr, time::Duration};
 
-use crate::models::{MessageId, MessageStatus};
+use crate::{
+    models::{MessageId, MessagePriority, MessageStatus, OrganizationId},
+    telemetry::{TraceContext, current_trace_context},
+};
 
 use futures::{Stream, StreamExt};
 use tracing::log::trace;
 
-pub type BusStream<'a> = std::pin::Pin<Box<dyn Stream<Item = BusMessage> + Send + 'a>>;
+/// Version of the wire format of bus messages, which is included in every [`BusEnvelope`]
+///
+/// Increase this whenever the wire format of an existing [`BusMessage`] changes, such that during
+/// a rolling deploy, services can tell messages of newer services apart from malformed messages.
+/// Adding a variant does not change the wire format, as services skip variants they don't know.
+///
+/// - 0: unversioned, `EmailReadyToSend` may lack the correlation ID and priority
+/// - 1: `EmailReadyToSend` includes the correlation ID and priority
+pub const BUS_PROTOCOL_VERSION: u32 = 1;
+
+pub type BusStream<'a> = std::pin::Pin<Box<dyn Stream<Item = BusEnvelope> + Send + 'a>>;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub enum BusMessage {
-    /// Message is ready to be sent from [`IpAddr`]
-    EmailReadyToSend(MessageId, IpAddr),
+    /// Message is ready to be sent from [`IpAddr`], with the correlation ID of the API request
+    /// that submitted the message, if any, and the priority of the message
+    ///
+    /// The correlation ID and priority are left out by senders from before they were added,
+    /// such messages are sent with the default priority.
+    EmailReadyToSend(
+        MessageId,
+        IpAddr,
+        #[serde(default)] Option<String>,
+        #[serde(default)] MessagePriority,
+    ),
     EmailDeliveryAttempted(MessageId, MessageStatus),
+    /// The organization has used at least this percentage of its message quota,
+    /// published once per alert threshold per quota period
+    QuotaThresholdReached(OrganizationId, i32),
+}
+
+/// A [`BusMessage`] together with the wire format version and trace context of its sender
+///
+/// The trace context is left out if it is empty, i.e., if OpenTelemetry is not configured.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct BusEnvelope<M = BusMessage> {
+    #[serde(flatten)]
+    pub message: M,
+    /// Senders from before the wire format was versioned leave this out, which is version 0
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "TraceContext::is_empty")]
+    pub trace_context: TraceContext,
+}
+
+impl BusEnvelope {
+    /// Deserialize an envelope received from the bus
+    ///
+    /// Messages of older versions are decoded as the current [`BusMessage`], as fields added since
+    /// have a default. Messages of newer versions are skipped, even if they happen to deserialize,
+    /// as they might mean something else. Variants this service doesn't know and messages that
+    /// can't be deserialized are skipped instead of ending the stream.
+    pub(super) fn from_wire(text: &str) -> Option<Self> {
+        #[derive(Deserialize)]
+        struct Version {
+            #[serde(default)]
+            version: u32,
+        }
+
+        let version = match serde_json::from_str::<Version>(text) {
+            Ok(Version { version }) => version,
+            Err(e) => {
+                tracing::error!("could not deserialize WS message: {e:?}");
+                return None;
+            }
+        };
+
+        if version > BUS_PROTOCOL_VERSION {
+            tracing::warn!(
+                version,
+                "skipping bus message of a newer version (ours is {BUS_PROTOCOL_VERSION})"
+            );
+            return None;
+        }
+
+        serde_json::from_str(text)
+            .inspect_err(|e| {
+                // serde reports a variant that was added by a newer service like this
+                if e.to_string().starts_with("no variant of enum BusMessage") {
+                    tracing::warn!(version, "skipping unknown bus message: {text}");
+                } else {
+                    tracing::error!(version, "could not deserialize WS message: {e:?}");
+                }
+            })
+            .ok()
+    }
+}
+
+/// Delay between attempts to reconnect to the message bus, which doubles after every failed
+/// attempt up to a maximum, such that the bus is not hammered during a prolonged outage
+#[derive(Debug, Clone)]
+pub struct ReconnectBackoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl ReconnectBackoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            current: initial,
+        }
+    }
+
+    /// The delay before the next attempt, which is randomly chosen between half of and the full
+    /// current interval, such that clients do not reconnect all at once
+    pub fn next_delay(&mut self) -> Duration {
+        let half = self.current / 2;
+        let delay = half + half.mul_f64(rand::rng().random_range(0.0..=1.0));
+        self.current = (self.current * 2).min(self.max);
+
+        delay
+    }
+
+    /// Start over at the initial interval, e.g., after connecting successfully
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
 }
 
 #[derive(Clone)]
@@ -50,7 +167,11 @@ impl BusClient {
     pub async fn send(&self, message: &BusMessage) -> Result<(), reqwest::Error> {
         self.client
             .post(format!("http://{}:{}/post", self.domain_name, self.port))
-            .json(&message)
+            .json(&BusEnvelope {
+                message,
+                version: BUS_PROTOCOL_VERSION,
+                trace_context: current_trace_context(),
+            })
             .send()
             .await?
             .error_for_status()?;
@@ -80,9 +201,8 @@ impl BusClient {
             while let Some(Ok(msg)) = receiver.next().await {
                 match msg {
                     tokio_tungstenite::tungstenite::Message::Text(m) => {
-                        match serde_json::from_str(&m) {
-                            Ok(m) => yield m,
-                            Err(e) => tracing::error!("could not deserialize WS message: {e:?}"),
+                        if let Some(envelope) = BusEnvelope::from_wire(&m) {
+                            yield envelope;
                         }
                     }
                     m => {
@@ -93,18 +213,38 @@ impl BusClient {
         }))
     }
 
+    /// Receive messages from the message bus, without reconnecting
+    ///
+    /// Stream will end when the WebSocket disconnects or the connection fails
+    pub fn receive_until_disconnected(&'_ self) -> BusStream<'_> {
+        Box::pin(stream! {
+            match self.receive().await {
+                Ok(mut stream) => {
+                    while let Some(message) = stream.next().await {
+                        yield message;
+                    }
+                },
+                Err(e) => tracing::error!("failed to connect to the message bus: {e:?}"),
+            }
+        })
+    }
+
     /// Receive messages from the message bus, while automatically reconnecting the WebSocket
-    /// (with some timeout delay between connection attempts)
-    pub fn receive_auto_reconnect(&'_ self, timeout: std::time::Duration) -> BusStream<'_> {
+    /// (with an exponentially growing delay between failing connection attempts)
+    pub fn receive_auto_reconnect(&'_ self, mut backoff: ReconnectBackoff) -> BusStream<'_> {
         Box::pin(stream! {
             loop {
                 match self.receive().await {
-                    Ok(mut stream) => while let Some(message) = stream.next().await {
-                        yield message;
+                    Ok(mut stream) => {
+                        backoff.reset();
+                        while let Some(message) = stream.next().await {
+                            yield message;
+                        }
                     },
                     Err(e) => {
-                        tracing::error!("reconnecting in {timeout:?} seconds... {e:?}");
-                        tokio::time::sleep(timeout).await;
+                        let delay = backoff.next_delay();
+                        tracing::error!("reconnecting in {delay:?}... {e:?}");
+                        tokio::time::sleep(delay).await;
                     },
                 }
             }
@@ -125,8 +265,15 @@ impl BusClient {
                 .unwrap()
                 .unwrap()
             {
-                BusMessage::EmailReadyToSend(_, _) => ready += 1,
-                BusMessage::EmailDeliveryAttempted(_, _) => attempted += 1,
+                BusEnvelope {
+                    message: BusMessage::EmailReadyToSend(..),
+                    ..
+                } => ready += 1,
+                BusEnvelope {
+                    message: BusMessage::EmailDeliveryAttempted(_, _),
+                    ..
+                } => attempted += 1,
+                _ => {}
             }
         }
     }
@@ -3,16 +3,22 @@ pub mod server;
 
 #[cfg(test)]
 mod tests {
-    use std::net::{Ipv4Addr, SocketAddrV4};
+    use std::{
+        net::{Ipv4Addr, SocketAddrV4},
+        time::Duration,
+    };
 
     use futures::StreamExt;
     use rand::RngExt;
     use tokio::sync::broadcast;
     use uuid::Uuid;
 
-    use crate::bus::{
-        client::{BusClient, BusMessage},
-        server::Bus,
+    use crate::{
+        bus::{
+            client::{BUS_PROTOCOL_VERSION, BusClient, BusEnvelope, BusMessage, ReconnectBackoff},
+            server::Bus,
+        },
+        models::MessagePriority,
     };
 
     #[tokio::test]
@@ -25,16 +31,202 @@ mod tests {
         let mut stream2 = client.receive().await.unwrap();
 
         // send a message
-        let message =
-            BusMessage::EmailReadyToSend(Uuid::new_v4().into(), "1.1.1.1".parse().unwrap());
+        let message = BusMessage::EmailReadyToSend(
+            Uuid::new_v4().into(),
+            "1.1.1.1".parse().unwrap(),
+            None,
+            MessagePriority::High,
+        );
         client.send(&message).await.unwrap();
 
         // both listeners should receive the message
         let received = stream1.next().await.unwrap();
-        assert_eq!(received, message);
+        assert_eq!(received.message, message);
 
         let received = stream2.next().await.unwrap();
-        assert_eq!(received, message);
+        assert_eq!(received.message, message);
+    }
+
+    #[test]
+    fn envelope_serialization() {
+        let message = BusMessage::EmailReadyToSend(
+            Uuid::new_v4().into(),
+            "1.1.1.1".parse().unwrap(),
+            None,
+            MessagePriority::High,
+        );
+
+        // without trace context, the envelope is serialized as the message and its version
+        let envelope = BusEnvelope {
+            message: &message,
+            version: BUS_PROTOCOL_VERSION,
+            trace_context: Default::default(),
+        };
+        let mut expected = serde_json::to_value(&message).unwrap();
+        expected["version"] = BUS_PROTOCOL_VERSION.into();
+        assert_eq!(serde_json::to_value(&envelope).unwrap(), expected);
+
+        // messages from senders without versioning are still understood
+        let received: BusEnvelope =
+            serde_json::from_str(&serde_json::to_string(&message).unwrap()).unwrap();
+        assert_eq!(received.message, message);
+        assert_eq!(received.version, 0);
+        assert!(received.trace_context.is_empty());
+
+        let trace_context = [(
+            "traceparent".to_owned(),
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01".to_owned(),
+        )]
+        .into();
+        let envelope = BusEnvelope {
+            message: &message,
+            version: BUS_PROTOCOL_VERSION,
+            trace_context,
+        };
+        let received: BusEnvelope =
+            serde_json::from_str(&serde_json::to_string(&envelope).unwrap()).unwrap();
+        assert_eq!(received.message, message);
+        assert_eq!(received.version, BUS_PROTOCOL_VERSION);
+        assert_eq!(received.trace_context, envelope.trace_context);
+    }
+
+    #[test]
+    fn email_ready_to_send_older_formats() {
+        let id = Uuid::new_v4();
+
+        // from before the correlation ID was propagated
+        let received: BusEnvelope =
+            serde_json::from_str(&format!(r#"{{"EmailReadyToSend":["{id}","1.1.1.1"]}}"#)).unwrap();
+        assert_eq!(
+            received.message,
+            BusMessage::EmailReadyToSend(
+                id.into(),
+                "1.1.1.1".parse().unwrap(),
+                None,
+                MessagePriority::default(),
+            )
+        );
+
+        // from before messages had a priority
+        let received: BusEnvelope = serde_json::from_str(&format!(
+            r#"{{"EmailReadyToSend":["{id}","1.1.1.1","correlation"]}}"#
+        ))
+        .unwrap();
+        assert_eq!(
+            received.message,
+            BusMessage::EmailReadyToSend(
+                id.into(),
+                "1.1.1.1".parse().unwrap(),
+                Some("correlation".to_owned()),
+                MessagePriority::default(),
+            )
+        );
+    }
+
+    #[test]
+    fn decode_wire_versions() {
+        let id = Uuid::new_v4();
+        let message = |priority| {
+            BusMessage::EmailReadyToSend(id.into(), "1.1.1.1".parse().unwrap(), None, priority)
+        };
+
+        // unversioned
+        let received =
+            BusEnvelope::from_wire(&format!(r#"{{"EmailReadyToSend":["{id}","1.1.1.1"]}}"#))
+                .unwrap();
+        assert_eq!(received.message, message(MessagePriority::default()));
+        assert_eq!(received.version, 0);
+
+        // every older version is decoded
+        for version in 1..=BUS_PROTOCOL_VERSION {
+            let received = BusEnvelope::from_wire(&format!(
+                r#"{{"EmailReadyToSend":["{id}","1.1.1.1",null,"low"],"version":{version}}}"#
+            ))
+            .unwrap();
+            assert_eq!(received.message, message(MessagePriority::Low));
+            assert_eq!(received.version, version);
+        }
+
+        // a newer version is skipped, even if it deserializes
+        assert!(
+            BusEnvelope::from_wire(&format!(
+                r#"{{"EmailReadyToSend":["{id}","1.1.1.1",null,"low"],"version":{}}}"#,
+                BUS_PROTOCOL_VERSION + 1
+            ))
+            .is_none()
+        );
+        // so is a variant added by a newer service of the same version
+        assert!(
+            BusEnvelope::from_wire(&format!(
+                r#"{{"SomethingNew":[1,2],"version":{BUS_PROTOCOL_VERSION}}}"#
+            ))
+            .is_none()
+        );
+        assert!(BusEnvelope::from_wire(r#"{"EmailReadyToSend":"malformed"}"#).is_none());
+        assert!(BusEnvelope::from_wire("not json").is_none());
+    }
+
+    #[tokio::test]
+    async fn skip_unknown_message() {
+        let bus_port = Bus::spawn_random_port().await;
+        let client = BusClient::new(bus_port, "localhost".to_owned()).unwrap();
+        let mut stream = client.receive().await.unwrap();
+
+        // a message of a newer version, a variant this service doesn't know, and a malformed message
+        for unknown in [
+            format!(
+                r#"{{"EmailReadyToSend":[1,2],"version":{}}}"#,
+                BUS_PROTOCOL_VERSION + 1
+            ),
+            format!(r#"{{"SomethingNew":[1,2],"version":{BUS_PROTOCOL_VERSION}}}"#),
+            r#"{"EmailReadyToSend":"malformed"}"#.to_owned(),
+        ] {
+            reqwest::Client::new()
+                .post(format!("http://localhost:{bus_port}/post"))
+                .body(unknown)
+                .send()
+                .await
+                .unwrap()
+                .error_for_status()
+                .unwrap();
+        }
+
+        let message = BusMessage::EmailReadyToSend(
+            Uuid::new_v4().into(),
+            "1.1.1.1".parse().unwrap(),
+            None,
+            MessagePriority::High,
+        );
+        client.send(&message).await.unwrap();
+
+        // the unknown messages are skipped without ending the stream
+        let received = tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received.message, message);
+        assert_eq!(received.version, BUS_PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn reconnect_backoff() {
+        let initial = Duration::from_millis(100);
+        let mut backoff = ReconnectBackoff::new(initial, Duration::from_millis(1000));
+
+        // the interval doubles after every failed attempt up to the maximum,
+        // the delay is between half of the interval and the full interval
+        let intervals = [100, 200, 400, 800, 1000, 1000].map(Duration::from_millis);
+        for interval in intervals {
+            let delay = backoff.next_delay();
+            assert!(
+                delay >= interval / 2 && delay <= interval,
+                "delay {delay:?} not within interval {interval:?}"
+            );
+        }
+
+        // after a successful connection, it starts over
+        backoff.reset();
+        assert!(backoff.next_delay() <= initial);
     }
 
     #[tokio::test]
@@ -44,16 +236,23 @@ mod tests {
 
         // start receiving, even though message bus is offline
         let client = BusClient::new(port, "localhost".to_owned()).unwrap();
-        let mut stream = client.receive_auto_reconnect(std::time::Duration::from_millis(500));
+        let mut stream = client.receive_auto_reconnect(ReconnectBackoff::new(
+            Duration::from_millis(500),
+            Duration::from_secs(1),
+        ));
 
-        let message =
-            BusMessage::EmailReadyToSend(Uuid::new_v4().into(), "1.1.1.1".parse().unwrap());
+        let message = BusMessage::EmailReadyToSend(
+            Uuid::new_v4().into(),
+            "1.1.1.1".parse().unwrap(),
+            None,
+            MessagePriority::High,
+        );
         client.send(&message).await.unwrap_err(); // should error
         client.try_send(&message).await; // ignores error
 
         let mut listen = async || {
             let received = stream.next().await.unwrap();
-            assert_eq!(received, message);
+            assert_eq!(received.message, message);
         };
 
         let host_and_post = async || {
@@ -64,9 +263,9 @@ mod tests {
             tokio::spawn(bus.serve());
 
             // send message after listener has had time to reconnect
-            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            tokio::time::sleep(Duration::from_secs(1)).await;
             client.send(&message).await.unwrap();
-            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            tokio::time::sleep(Duration::from_secs(5)).await;
             panic!("timeout!");
         };
 
@@ -1,15 +1,21 @@
-use mail_auth::{common::headers::HeaderWriter, dkim::DkimSigner};
+use mail_auth::{
+    common::headers::HeaderWriter,
+    dkim::{Canonicalization, DkimSigner},
+};
 
-use crate::models::{Domain, MailAuthSigningKey};
+use crate::models::{DkimCanonicalization, Domain, MailAuthSigningKey};
 
 pub struct PrivateKey<'a> {
     domain: &'a str,
     selector: &'a str,
     sign_key: MailAuthSigningKey,
     pub_key: aws_lc_rs::encoding::PublicKeyX509Der<'a>,
+    signed_headers: Vec<&'a str>,
+    canonicalization: Canonicalization,
 }
 
-const SIGNED_HEADERS: [&str; 26] = [
+/// Headers that are signed, unless configured otherwise for the domain
+pub const DEFAULT_SIGNED_HEADERS: [&str; 26] = [
     "From",
     "Subject",
     "Date",
@@ -45,6 +51,14 @@ impl<'a> PrivateKey<'a> {
             selector,
             sign_key: domain.dkim_key.signing_key()?,
             pub_key: domain.dkim_key.pub_key()?,
+            signed_headers: match &domain.dkim_signed_headers {
+                Some(headers) => headers.iter().map(String::as_str).collect(),
+                None => DEFAULT_SIGNED_HEADERS.to_vec(),
+            },
+            canonicalization: match domain.dkim_canonicalization {
+                DkimCanonicalization::Relaxed => Canonicalization::Relaxed,
+                DkimCanonicalization::Simple => Canonicalization::Simple,
+            },
         })
     }
 
@@ -53,12 +67,127 @@ impl<'a> PrivateKey<'a> {
         self.pub_key.as_ref()
     }
 
-    pub fn dkim_header(self, msg: &mail_parser::Message) -> Result<String, mail_auth::Error> {
+    /// Signs the message, the same canonicalization is used for the headers and the body,
+    /// resulting in either `c=relaxed/relaxed` or `c=simple/simple`
+    pub fn dkim_header(self, raw_message: &[u8]) -> Result<String, mail_auth::Error> {
         let signer = DkimSigner::from_key(self.sign_key)
             .domain(self.domain)
             .selector(self.selector)
-            .headers(SIGNED_HEADERS);
+            .headers(self.signed_headers)
+            .header_canonicalization(self.canonicalization)
+            .body_canonicalization(self.canonicalization);
 
-        signer.sign(&msg.raw_message).map(|x| x.to_header())
+        signer.sign(raw_message).map(|x| x.to_header())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::{handler::dns::DnsResolver, models::DomainRepository, test::TestProjects};
+
+    const MESSAGE: &str = "From: \"John Doe\" <email@test-org-1.com>\r\n\
+        To: \"James Smith\" <info@recipient1.com>\r\n\
+        Subject: Hi!\r\n\
+        List-Unsubscribe: <https://test-org-1.com/unsubscribe>\r\n\
+        List-Unsubscribe-Post: List-Unsubscribe=One-Click\r\n\
+        \r\n\
+        Hello world!\r\n";
+
+    /// Returns the value of a tag of a DKIM-Signature header
+    fn tag(dkim_header: &str, name: &str) -> String {
+        dkim_header
+            .trim_start_matches("DKIM-Signature:")
+            .split(';')
+            .map(|tag| tag.split_whitespace().collect::<String>())
+            .find_map(|tag| {
+                tag.strip_prefix(name)
+                    .and_then(|tag| tag.strip_prefix('='))
+                    .map(ToString::to_string)
+            })
+            .unwrap()
+    }
+
+    /// Returns the header names listed in the `h=` tag of a DKIM-Signature header
+    fn signed_headers(dkim_header: &str) -> Vec<String> {
+        tag(dkim_header, "h")
+            .split(':')
+            .map(str::to_lowercase)
+            .collect()
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "projects", "org_domains")
+    ))]
+    async fn test_configured_signed_headers(pool: PgPool) {
+        let repo = DomainRepository::new(pool, DnsResolver::mock("localhost", 1025));
+        let org_1 = TestProjects::Org1Project1.org_id();
+        let org_1_domain_1 = "ed28baa5-57f7-413f-8c77-7797ba6a8780".parse().unwrap();
+        let mut domain = repo.get(org_1, org_1_domain_1).await.unwrap();
+
+        // by default, List-Unsubscribe-Post is not signed
+        let header = PrivateKey::new(&domain, "remails")
+            .unwrap()
+            .dkim_header(MESSAGE.as_bytes())
+            .unwrap();
+        let headers = signed_headers(&header);
+        assert!(headers.contains(&"from".to_string()));
+        assert!(headers.contains(&"list-unsubscribe".to_string()));
+        assert!(!headers.contains(&"list-unsubscribe-post".to_string()));
+
+        // extend the default list of signed headers
+        let mut extended: Vec<String> = DEFAULT_SIGNED_HEADERS
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        extended.push("List-Unsubscribe-Post".to_string());
+        domain.dkim_signed_headers = Some(extended);
+
+        let header = PrivateKey::new(&domain, "remails")
+            .unwrap()
+            .dkim_header(MESSAGE.as_bytes())
+            .unwrap();
+        let headers = signed_headers(&header);
+        assert!(headers.contains(&"from".to_string()));
+        assert!(headers.contains(&"list-unsubscribe".to_string()));
+        assert!(headers.contains(&"list-unsubscribe-post".to_string()));
+
+        // restrict the signed headers
+        domain.dkim_signed_headers = Some(vec!["From".to_string(), "Subject".to_string()]);
+
+        let header = PrivateKey::new(&domain, "remails")
+            .unwrap()
+            .dkim_header(MESSAGE.as_bytes())
+            .unwrap();
+        assert_eq!(signed_headers(&header), vec!["from", "subject"]);
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "projects", "org_domains")
+    ))]
+    async fn test_configured_canonicalization(pool: PgPool) {
+        let repo = DomainRepository::new(pool, DnsResolver::mock("localhost", 1025));
+        let org_1 = TestProjects::Org1Project1.org_id();
+        let org_1_domain_1 = "ed28baa5-57f7-413f-8c77-7797ba6a8780".parse().unwrap();
+        let mut domain = repo.get(org_1, org_1_domain_1).await.unwrap();
+
+        // relaxed by default
+        assert_eq!(domain.dkim_canonicalization, DkimCanonicalization::Relaxed);
+        let header = PrivateKey::new(&domain, "remails")
+            .unwrap()
+            .dkim_header(MESSAGE.as_bytes())
+            .unwrap();
+        assert_eq!(tag(&header, "c"), "relaxed/relaxed");
+
+        domain.dkim_canonicalization = DkimCanonicalization::Simple;
+        let header = PrivateKey::new(&domain, "remails")
+            .unwrap()
+            .dkim_header(MESSAGE.as_bytes())
+            .unwrap();
+        assert_eq!(tag(&header, "c"), "simple/simple");
     }
 }
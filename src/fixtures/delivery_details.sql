@@ -0,0 +1,68 @@
+INSERT INTO messages (id, message_id_header, smtp_credential_id, organization_id, project_id, status, from_email, recipients,
+                      raw_data, message_data, delivery_details, retry_after, attempts, max_attempts, created_at)
+VALUES ('0b6a1c1e-8f1e-4a43-9d4e-2d6f0c1b3a01', 'REMAILS-0b6a1c1e-8f1e-4a43-9d4e-2d6f0c1b3a01@remails.net',
+        '9442cbbf-9897-4af7-9766-4ac9c1bf49cf',
+        '44729d9f-a7dc-4226-b412-36a7537f5176',
+        '3ba14adf-4de1-4fb6-8c20-50cc2ded5462',
+        'delivered',
+        'email@test-org-1.com',
+        '{"info@gmail.com", "other@gmail.com", "info@outlook.com"}',
+        ''::bytea,
+        'null'::jsonb,
+        '{
+          "info@gmail.com": {"status": {"type": "Success", "delivered": "2026-01-01T00:00:00Z"}, "log": {"lines": []}},
+          "other@gmail.com": {"status": {"type": "Success", "delivered": "2026-01-01T00:00:00Z"}, "log": {"lines": []}},
+          "info@outlook.com": {"status": {"type": "Reattempt"}, "log": {"lines": []}}
+        }'::jsonb,
+        NULL,
+        1, 3,
+        now() - INTERVAL '1 hour'),
+       ('0b6a1c1e-8f1e-4a43-9d4e-2d6f0c1b3a02', 'REMAILS-0b6a1c1e-8f1e-4a43-9d4e-2d6f0c1b3a02@remails.net',
+        '9442cbbf-9897-4af7-9766-4ac9c1bf49cf',
+        '44729d9f-a7dc-4226-b412-36a7537f5176',
+        '3ba14adf-4de1-4fb6-8c20-50cc2ded5462',
+        'failed',
+        'email@test-org-1.com',
+        '{"info@gmail.com", "info@outlook.com", "blocked@outlook.com"}',
+        ''::bytea,
+        'null'::jsonb,
+        '{
+          "info@gmail.com": {"status": {"type": "Failed"}, "log": {"lines": []}},
+          "info@outlook.com": {"status": {"type": "Success", "delivered": "2026-01-01T00:00:00Z"}, "log": {"lines": []}},
+          "blocked@outlook.com": {"status": {"type": "Suppressed"}, "log": {"lines": []}}
+        }'::jsonb,
+        NULL,
+        1, 3,
+        now() - INTERVAL '2 hours'),
+       -- outside of the queried time range
+       ('0b6a1c1e-8f1e-4a43-9d4e-2d6f0c1b3a03', 'REMAILS-0b6a1c1e-8f1e-4a43-9d4e-2d6f0c1b3a03@remails.net',
+        '9442cbbf-9897-4af7-9766-4ac9c1bf49cf',
+        '44729d9f-a7dc-4226-b412-36a7537f5176',
+        '3ba14adf-4de1-4fb6-8c20-50cc2ded5462',
+        'delivered',
+        'email@test-org-1.com',
+        '{"info@gmail.com"}',
+        ''::bytea,
+        'null'::jsonb,
+        '{
+          "info@gmail.com": {"status": {"type": "Success", "delivered": "2026-01-01T00:00:00Z"}, "log": {"lines": []}}
+        }'::jsonb,
+        NULL,
+        1, 3,
+        now() - INTERVAL '40 days'),
+       -- other project
+       ('0b6a1c1e-8f1e-4a43-9d4e-2d6f0c1b3a04', 'REMAILS-0b6a1c1e-8f1e-4a43-9d4e-2d6f0c1b3a04@remails.net',
+        '9442cbbf-9897-4af7-9766-4ac9c1bf49cf',
+        '44729d9f-a7dc-4226-b412-36a7537f5176',
+        'da12d059-d86e-4ac6-803d-d013045f68ff',
+        'delivered',
+        'email@test-org-1.com',
+        '{"info@gmail.com"}',
+        ''::bytea,
+        'null'::jsonb,
+        '{
+          "info@gmail.com": {"status": {"type": "Success", "delivered": "2026-01-01T00:00:00Z"}, "log": {"lines": []}}
+        }'::jsonb,
+        NULL,
+        1, 3,
+        now() - INTERVAL '1 hour');
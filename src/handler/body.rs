@@ -0,0 +1,286 @@
+//! Transfer of the message data during delivery
+//!
+//! Only the headers added by Remails (e.g., the DKIM signature) are kept in memory while
+//! delivering. The stored message data is streamed from the database in chunks for each
+//! transfer, such that large messages are never held in the send buffer as a whole.
+
+use crate::models::{MessageId, MessageRepository, OrganizationId};
+use futures::{Stream, StreamExt, stream};
+use mail_send::{SmtpClient, smtp::AssertReply};
+use std::{borrow::Cow, io, pin::pin, sync::Arc};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+#[derive(Clone)]
+pub(crate) struct OutboundBody {
+    /// Headers prepended to the stored message data
+    headers: Arc<[u8]>,
+    source: Source,
+}
+
+#[derive(Clone)]
+enum Source {
+    Buffered(Arc<[u8]>),
+    Stored {
+        repository: MessageRepository,
+        organization_id: OrganizationId,
+        message_id: MessageId,
+        size: i32,
+    },
+}
+
+impl OutboundBody {
+    /// Stream the message data from the database, prefixed by the headers added by Remails
+    pub(crate) fn stored(
+        headers: &[u8],
+        repository: MessageRepository,
+        organization_id: OrganizationId,
+        message_id: MessageId,
+        size: usize,
+    ) -> Self {
+        Self {
+            headers: headers.into(),
+            source: Source::Stored {
+                repository,
+                organization_id,
+                message_id,
+                size: size.try_into().unwrap_or(i32::MAX),
+            },
+        }
+    }
+
+    /// The same message data, preceded by a `Return-Path` header naming the envelope sender
+    pub(crate) fn with_return_path(&self, mail_from: &str) -> Self {
+        let mut headers = format!("Return-Path: <{mail_from}>\r\n").into_bytes();
+        headers.extend_from_slice(&self.headers);
+
+        Self {
+            headers: headers.into(),
+            source: self.source.clone(),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn buffered(raw_data: &[u8]) -> Self {
+        Self {
+            headers: Arc::new([]),
+            source: Source::Buffered(raw_data.into()),
+        }
+    }
+
+    fn chunks(&self) -> impl Stream<Item = Result<Cow<'_, [u8]>, crate::models::Error>> + '_ {
+        let headers = stream::once(async { Ok(Cow::Borrowed(&*self.headers)) });
+
+        let data = match &self.source {
+            Source::Buffered(data) => {
+                stream::once(async { Ok(Cow::Borrowed(&**data)) }).left_stream()
+            }
+            Source::Stored {
+                repository,
+                organization_id,
+                message_id,
+                size,
+            } => repository
+                .stream_raw_data(*organization_id, *message_id, *size)
+                .map(|chunk| chunk.map(Cow::Owned))
+                .right_stream(),
+        };
+
+        headers.chain(data)
+    }
+
+    /// Write the message data, applying the SMTP transparency procedure,
+    /// followed by the end of data indicator
+    async fn write_to<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut stuffer = DotStuffer::default();
+        let mut chunks = pin!(self.chunks());
+
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk.map_err(io::Error::other)?;
+            writer.write_all(&stuffer.stuff(&chunk)).await?;
+        }
+
+        writer.write_all(b"\r\n.\r\n").await?;
+        writer.flush().await
+    }
+
+    /// Issue the DATA command and transfer the message data over the connection
+    ///
+    /// The whole transfer is bounded by the timeout of the client.
+    pub(crate) async fn transfer<T>(&self, client: &mut SmtpClient<T>) -> mail_send::Result<()>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        tokio::time::timeout(client.timeout, async {
+            client.cmd(b"DATA\r\n").await?.assert_code(354)?;
+            self.write_to(&mut client.stream).await?;
+            client.read().await?.assert_positive_completion()
+        })
+        .await
+        .map_err(|_| mail_send::Error::Timeout)?
+    }
+}
+
+/// The SMTP transparency procedure (RFC 5321, section 4.5.2), applied chunk by chunk
+///
+/// Like `mail-send`, a dot is doubled if it follows a CR or LF, as these must only
+/// appear together in a message.
+struct DotStuffer {
+    after_line_break: bool,
+}
+
+impl Default for DotStuffer {
+    fn default() -> Self {
+        Self {
+            after_line_break: true,
+        }
+    }
+}
+
+impl DotStuffer {
+    fn stuff<'a>(&mut self, chunk: &'a [u8]) -> Cow<'a, [u8]> {
+        let mut stuffed: Option<Vec<u8>> = None;
+        let mut last_pos = 0;
+
+        for (pos, &byte) in chunk.iter().enumerate() {
+            if byte == b'.' && self.after_line_break {
+                let stuffed = stuffed.get_or_insert_with(|| Vec::with_capacity(chunk.len() + 64));
+                stuffed.extend_from_slice(&chunk[last_pos..pos]);
+                stuffed.push(b'.');
+                last_pos = pos;
+            }
+            self.after_line_break = byte == b'\r' || byte == b'\n';
+        }
+
+        match stuffed {
+            Some(mut stuffed) => {
+                stuffed.extend_from_slice(&chunk[last_pos..]);
+                Cow::Owned(stuffed)
+            }
+            None => Cow::Borrowed(chunk),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        models::NewMessage,
+        test::{TestProjects, test_credential},
+    };
+    use sqlx::PgPool;
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    /// Records the written data and the largest single write
+    #[derive(Default)]
+    struct RecordingWriter {
+        data: Vec<u8>,
+        largest_write: usize,
+    }
+
+    impl AsyncWrite for RecordingWriter {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.largest_write = self.largest_write.max(buf.len());
+            self.data.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn dot_stuffing() {
+        let mut stuffer = DotStuffer::default();
+        assert_eq!(
+            &*stuffer.stuff(b".leading\r\nmid.dle\r\n..double\r\n"),
+            b"..leading\r\nmid.dle\r\n...double\r\n"
+        );
+
+        // the line break and the dot are split over two chunks
+        let mut stuffer = DotStuffer::default();
+        assert_eq!(&*stuffer.stuff(b"first line\r\n"), b"first line\r\n");
+        assert_eq!(&*stuffer.stuff(b".second line"), b"..second line");
+        assert!(matches!(stuffer.stuff(b" continues."), Cow::Borrowed(_)));
+    }
+
+    #[tokio::test]
+    async fn buffered_body() {
+        let body = OutboundBody::buffered(b"Subject: Hi!\r\n\r\nHello world!\r\n.\r\n");
+        let mut writer = RecordingWriter::default();
+        body.write_to(&mut writer).await.unwrap();
+
+        assert_eq!(
+            writer.data,
+            b"Subject: Hi!\r\n\r\nHello world!\r\n..\r\n\r\n.\r\n"
+        );
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "projects", "org_domains", "proj_domains")
+    ))]
+    async fn stream_large_message(pool: PgPool) {
+        let org_id = TestProjects::Org1Project1.org_id();
+        let credential = test_credential(&pool).await;
+
+        let line = ".All work and no play makes Jack a dull boy.\r\n";
+        let raw_data = format!(
+            "From: john@test-org-1-project-1.com\r\nTo: james@test.com\r\nSubject: Hi!\r\n\r\n{}",
+            line.repeat(100_000)
+        );
+        assert!(raw_data.len() > 4_000_000);
+
+        let repository = MessageRepository::new(pool);
+        let mut message = NewMessage::new(
+            credential.id(),
+            "john@test-org-1-project-1.com".parse().unwrap(),
+        );
+        message.recipients = vec!["james@test.com".parse().unwrap()];
+        message.raw_data = raw_data.into_bytes();
+        let message_id = repository.create(message, 1).await.unwrap().into_inner();
+        let mut message = repository.get_if_org_may_send(message_id).await.unwrap();
+        let stored = message.raw_data.clone();
+
+        let dkim = "DKIM-Signature: v=1; a=rsa-sha256; d=test-org-1-project-1.com; s=remails;\r\n";
+        message.prepend_headers(&[dkim]);
+        assert_eq!(message.prepended_headers(), dkim.as_bytes());
+        assert_eq!(message.stored_size(), stored.len());
+
+        let body = OutboundBody::stored(
+            message.prepended_headers(),
+            repository,
+            org_id,
+            message_id,
+            message.stored_size(),
+        );
+        drop(message);
+
+        let mut writer = RecordingWriter::default();
+        body.write_to(&mut writer).await.unwrap();
+
+        // every line starts with a dot, which is doubled
+        let stored = String::from_utf8(stored).unwrap();
+        let expected = format!("{dkim}{}\r\n.\r\n", stored.replace("\r\n.", "\r\n.."));
+        assert_eq!(writer.data.len(), expected.len());
+        assert!(writer.data == expected.as_bytes());
+
+        // the message has been written in chunks, not as a whole
+        assert!(writer.largest_write < stored.len() / 4);
+    }
+}
@@ -14,6 +14,13 @@ pub enum LogLevel {
 #[derive(Debug, Deserialize, Serialize, Default, ToSchema)]
 pub struct ConnectionLog {
     lines: Vec<LogLine>,
+    /// Number of older lines that were dropped to bound the size of the log
+    #[serde(default, skip_serializing_if = "is_zero")]
+    dropped: usize,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
 }
 
 #[derive(Debug, Deserialize, Serialize, ToSchema)]
@@ -32,4 +39,19 @@ impl ConnectionLog {
         };
         self.lines.push(line);
     }
+
+    /// Append the lines of another log, e.g., one collected in a separate task
+    pub fn append(&mut self, other: ConnectionLog) {
+        self.lines.extend(other.lines);
+        self.dropped += other.dropped;
+    }
+
+    /// Drop the oldest lines, such that at most `max_lines` remain
+    ///
+    /// The most recent lines are kept, as they include the outcome of the last delivery attempt
+    pub fn rotate(&mut self, max_lines: usize) {
+        let excess = self.lines.len().saturating_sub(max_lines);
+        self.lines.drain(..excess);
+        self.dropped += excess;
+    }
 }
@@ -1,9 +1,8 @@
 #[cfg(test)]
 use crate::handler::mock;
-use crate::models::Error;
-use base64ct::{Base64Unpadded, Encoding};
+use crate::models;
+use base64ct::{Base64, Base64Unpadded, Encoding};
 use chrono::{DateTime, Utc};
-#[cfg(not(test))]
 use hickory_resolver::{
     Resolver,
     config::{LookupIpStrategy::Ipv4Only, NameServerConfig, ResolverConfig, ResolverOpts},
@@ -11,8 +10,14 @@ use hickory_resolver::{
     proto::xfer::Protocol,
 };
 use serde::{Deserialize, Serialize};
-use std::ops::Range;
-use tracing::{debug, trace};
+use std::{
+    borrow::Cow,
+    net::{IpAddr, SocketAddr},
+    ops::Range,
+};
+use thiserror::Error;
+use tracing::{debug, trace, warn};
+use url::Host;
 use utoipa::ToSchema;
 
 //TODO: do we want to do anything with DNS errors?
@@ -20,6 +25,47 @@ pub enum ResolveError {
     #[allow(dead_code)]
     Dns(hickory_resolver::ResolveError),
     AllServersExhausted,
+    /// The domain could not be converted to its ASCII form (IDNA)
+    InvalidDomain(String),
+    /// None of the resolvers answered within the query timeout
+    Timeout,
+}
+
+/// Why a single TXT record could not be retrieved
+#[derive(Debug, Clone, Copy, Error)]
+enum RecordError {
+    #[error("could not retrieve DNS record")]
+    Lookup,
+    #[error("record unavailable")]
+    Unavailable,
+    #[error("multiple conflicting DNS records available")]
+    Multiple,
+    #[error("could not decode record")]
+    Undecodable,
+}
+
+/// Why the DKIM record of a domain does not match the key Remails signs with
+#[derive(Debug, Clone, Error)]
+pub enum DkimError {
+    #[error("could not retrieve DKIM record {record}")]
+    Lookup { record: String },
+    #[error("no DKIM record published at {record}")]
+    NotPublished { record: String },
+    #[error("multiple conflicting DKIM records published at {record}")]
+    Multiple { record: String },
+    #[error("DKIM record at {record} is malformed: {reason}")]
+    Malformed {
+        record: String,
+        reason: &'static str,
+    },
+    #[error(
+        "public key in DKIM record at {record} does not match, expected p={expected} but found p={found}"
+    )]
+    KeyMismatch {
+        record: String,
+        expected: String,
+        found: String,
+    },
 }
 
 #[derive(Clone)]
@@ -28,6 +74,13 @@ pub struct DnsResolver {
     pub(crate) resolver: Resolver<TokioConnectionProvider>,
     #[cfg(test)]
     pub(crate) resolver: mock::Resolver,
+    /// Tried in order if the primary resolver fails or does not answer in time
+    #[cfg(not(test))]
+    pub(crate) fallback: Vec<Resolver<TokioConnectionProvider>>,
+    #[cfg(test)]
+    pub(crate) fallback: Vec<mock::Resolver>,
+    /// How long to wait for a resolver to answer, before failing over to the next one
+    pub(crate) query_timeout: std::time::Duration,
     pub dkim_selector: String,
     pub spf_include: String,
 }
@@ -78,14 +131,24 @@ impl VerifyResult {
     }
 }
 
-impl From<Result<&'static str, &'static str>> for VerifyResult {
-    fn from(value: Result<&'static str, &'static str>) -> Self {
-        VerifyResult {
-            status: value
-                .map(|_| VerifyResultStatus::Success)
-                .unwrap_or(VerifyResultStatus::Error),
-            reason: value.unwrap_or_else(|e| e).to_string(),
-            value: None,
+impl DkimError {
+    /// Whether the published record no longer matches the key, as opposed to the record
+    /// being temporarily unavailable or ambiguous
+    pub fn is_drift(&self) -> bool {
+        matches!(
+            self,
+            DkimError::NotPublished { .. }
+                | DkimError::KeyMismatch { .. }
+                | DkimError::Malformed { .. }
+        )
+    }
+}
+
+impl From<Result<&'static str, DkimError>> for VerifyResult {
+    fn from(value: Result<&'static str, DkimError>) -> Self {
+        match value {
+            Ok(reason) => VerifyResult::success(reason),
+            Err(err) => VerifyResult::error(err.to_string(), None),
         }
     }
 }
@@ -112,59 +175,237 @@ impl Default for DnsResolver {
     }
 }
 
-impl DnsResolver {
-    #[cfg(not(test))]
-    pub fn new() -> Self {
+/// Parse a comma-separated list of resolver IPs, optionally with a port,
+/// e.g., `192.0.2.53,198.51.100.53:5353`
+fn parse_servers(
+    var: &str,
+    servers: Option<&str>,
+    default_port: u16,
+) -> Result<Vec<SocketAddr>, String> {
+    servers
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|server| !server.is_empty())
+        .map(|server| {
+            server
+                .parse::<SocketAddr>()
+                .or_else(|_| {
+                    server
+                        .parse::<IpAddr>()
+                        .map(|ip| SocketAddr::new(ip, default_port))
+                })
+                .map_err(|_| format!("'{server}' in {var} is not an IP address"))
+        })
+        .collect()
+}
+
+/// Upstream servers that resolve DNS records for Remails
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsUpstream {
+    /// The built-in DNS-over-TLS resolvers of DNS4EU and Quad9
+    BuiltIn,
+    /// The resolvers configured on the host, e.g., in `/etc/resolv.conf`
+    System,
+    /// Specific resolvers over plain DNS (UDP and TCP)
+    Plain(Vec<SocketAddr>),
+    /// Specific resolvers over DNS-over-TLS, whose certificates must be valid for `name`
+    Tls {
+        servers: Vec<SocketAddr>,
+        name: String,
+    },
+    /// Specific resolvers over DNS-over-HTTPS, whose certificates must be valid for `name`
+    Https {
+        servers: Vec<SocketAddr>,
+        name: String,
+        /// Defaults to `/dns-query`
+        endpoint: Option<String>,
+    },
+}
+
+impl DnsUpstream {
+    /// Configure the upstream DNS resolvers using the following environment variables:
+    /// - `DNS_RESOLVER`: one of `builtin` (default), `system`, `plain`, `tls`, or `https`
+    /// - `DNS_RESOLVER_SERVERS`: comma-separated list of resolver IPs, optionally with a port,
+    ///   e.g., `192.0.2.53,198.51.100.53:5353`, required for `plain`, `tls`, and `https`
+    /// - `DNS_RESOLVER_TLS_NAME`: name in the resolvers' certificate, required for `tls` and
+    ///   `https`
+    /// - `DNS_RESOLVER_HTTP_ENDPOINT`: path of the DNS-over-HTTPS endpoint, defaults to
+    ///   `/dns-query`
+    ///
+    /// Will panic if the configuration is invalid, such that misconfiguration is noticed at
+    /// startup instead of failing lookups later on
+    pub fn from_env() -> Self {
+        let var = |name| std::env::var(name).ok();
+
+        Self::parse(
+            var("DNS_RESOLVER").as_deref(),
+            var("DNS_RESOLVER_SERVERS").as_deref(),
+            var("DNS_RESOLVER_TLS_NAME"),
+            var("DNS_RESOLVER_HTTP_ENDPOINT"),
+        )
+        .unwrap_or_else(|err| panic!("Invalid DNS resolver configuration: {err}"))
+    }
+
+    fn parse(
+        mode: Option<&str>,
+        servers: Option<&str>,
+        name: Option<String>,
+        endpoint: Option<String>,
+    ) -> Result<Self, String> {
+        let servers = |default_port| -> Result<Vec<SocketAddr>, String> {
+            let servers = parse_servers("DNS_RESOLVER_SERVERS", servers, default_port)?;
+            if servers.is_empty() {
+                return Err("DNS_RESOLVER_SERVERS must list at least one resolver".to_owned());
+            }
+
+            Ok(servers)
+        };
+        let name = || {
+            name.clone()
+                .filter(|name| !name.trim().is_empty())
+                .ok_or("DNS_RESOLVER_TLS_NAME must be set for DNS-over-TLS and DNS-over-HTTPS")
+        };
+
+        match mode.map(str::trim) {
+            None | Some("") | Some("builtin") => Ok(Self::BuiltIn),
+            Some("system") => Ok(Self::System),
+            Some("plain") => Ok(Self::Plain(servers(53)?)),
+            Some("tls") => Ok(Self::Tls {
+                servers: servers(853)?,
+                name: name()?,
+            }),
+            Some("https") => Ok(Self::Https {
+                servers: servers(443)?,
+                name: name()?,
+                endpoint,
+            }),
+            Some(other) => Err(format!(
+                "unknown DNS_RESOLVER '{other}', expected one of builtin, system, plain, tls, or https"
+            )),
+        }
+    }
+
+    fn resolver_config(&self) -> ResolverConfig {
+        let name_server =
+            |socket_addr: SocketAddr, protocol: Protocol, tls_dns_name: Option<&str>| {
+                NameServerConfig {
+                    socket_addr,
+                    protocol,
+                    tls_dns_name: tls_dns_name.map(str::to_owned),
+                    http_endpoint: None,
+                    trust_negative_responses: false,
+                    bind_addr: None,
+                }
+            };
+
+        let mut resolver_config = ResolverConfig::new();
+        match self {
+            DnsUpstream::BuiltIn => {
+                // protective (DNS4EU)
+                for ip in ["86.54.11.1:853", "86.54.11.201:853"] {
+                    resolver_config.add_name_server(name_server(
+                        ip.parse().unwrap(),
+                        Protocol::Tls,
+                        Some("protective.joindns4.eu"),
+                    ));
+                }
+
+                // Malware Blocking, DNSSEC Validation (Quad9)
+                for ip in ["9.9.9.9:853", "149.112.112.112:853"] {
+                    resolver_config.add_name_server(name_server(
+                        ip.parse().unwrap(),
+                        Protocol::Tls,
+                        Some("dns.quad9.net"),
+                    ));
+                }
+            }
+            DnsUpstream::System => {
+                let (system_config, _) = hickory_resolver::system_conf::read_system_conf()
+                    .unwrap_or_else(|err| {
+                        panic!("Could not read the DNS resolver configuration of the host: {err}")
+                    });
+                resolver_config = system_config;
+            }
+            DnsUpstream::Plain(servers) => {
+                for server in servers {
+                    resolver_config.add_name_server(name_server(*server, Protocol::Udp, None));
+                    resolver_config.add_name_server(name_server(*server, Protocol::Tcp, None));
+                }
+            }
+            DnsUpstream::Tls { servers, name } => {
+                for server in servers {
+                    resolver_config.add_name_server(name_server(
+                        *server,
+                        Protocol::Tls,
+                        Some(name.as_str()),
+                    ));
+                }
+            }
+            DnsUpstream::Https {
+                servers,
+                name,
+                endpoint,
+            } => {
+                for server in servers {
+                    resolver_config.add_name_server(NameServerConfig {
+                        http_endpoint: endpoint.clone(),
+                        ..name_server(*server, Protocol::Https, Some(name.as_str()))
+                    });
+                }
+            }
+        }
+
+        resolver_config
+    }
+
+    fn build_resolver(&self) -> Resolver<TokioConnectionProvider> {
         let mut resolver_options = ResolverOpts::default();
         // The cluster does not support DualStack
         resolver_options.ip_strategy = Ipv4Only;
         resolver_options.negative_max_ttl = Some(std::time::Duration::from_secs(20));
         resolver_options.attempts = 4;
 
-        let mut resolver_config = ResolverConfig::new();
-        // protective (DNS4EU)
-        resolver_config.add_name_server(NameServerConfig {
-            socket_addr: "86.54.11.1:853".parse().unwrap(),
-            protocol: Protocol::Tls,
-            tls_dns_name: Some("protective.joindns4.eu".to_string()),
-            http_endpoint: None,
-            trust_negative_responses: false,
-            bind_addr: None,
-        });
-        resolver_config.add_name_server(NameServerConfig {
-            socket_addr: "86.54.11.201:853".parse().unwrap(),
-            protocol: Protocol::Tls,
-            tls_dns_name: Some("protective.joindns4.eu".to_string()),
-            http_endpoint: None,
-            trust_negative_responses: false,
-            bind_addr: None,
-        });
+        Resolver::builder_with_config(self.resolver_config(), TokioConnectionProvider::default())
+            .with_options(resolver_options)
+            .build()
+    }
+}
 
-        // Malware Blocking, DNSSEC Validation (Quad9)
-        resolver_config.add_name_server(NameServerConfig {
-            socket_addr: "9.9.9.9:853".parse().unwrap(),
-            protocol: Protocol::Tls,
-            tls_dns_name: Some("dns.quad9.net".to_string()),
-            http_endpoint: None,
-            trust_negative_responses: false,
-            bind_addr: None,
-        });
-        resolver_config.add_name_server(NameServerConfig {
-            socket_addr: "149.112.112.112:853".parse().unwrap(),
-            protocol: Protocol::Tls,
-            tls_dns_name: Some("dns.quad9.net".to_string()),
-            http_endpoint: None,
-            trust_negative_responses: false,
-            bind_addr: None,
-        });
+impl DnsResolver {
+    /// Configure the resolvers as described in [`DnsUpstream::from_env`], with the following
+    /// additional environment variables:
+    /// - `DNS_RESOLVER_FALLBACK_SERVERS`: comma-separated list of resolver IPs, optionally with
+    ///   a port, queried over plain DNS in order if the primary resolvers fail or time out
+    /// - `DNS_QUERY_TIMEOUT_MS`: how long to wait for a resolver to answer, defaults to 5000
+    ///
+    /// Will panic if any of the variables is set to an invalid value
+    #[cfg(not(test))]
+    pub fn new() -> Self {
+        let fallback = parse_servers(
+            "DNS_RESOLVER_FALLBACK_SERVERS",
+            std::env::var("DNS_RESOLVER_FALLBACK_SERVERS")
+                .ok()
+                .as_deref(),
+            53,
+        )
+        .unwrap_or_else(|err| panic!("Invalid DNS resolver configuration: {err}"));
+        let query_timeout = std::env::var("DNS_QUERY_TIMEOUT_MS")
+            .map(|millis| {
+                millis
+                    .parse::<std::num::NonZeroU64>()
+                    .expect("DNS_QUERY_TIMEOUT_MS must be a positive number of milliseconds")
+                    .get()
+            })
+            .unwrap_or(5000);
 
         Self {
-            resolver: Resolver::builder_with_config(
-                resolver_config,
-                TokioConnectionProvider::default(),
-            )
-            .with_options(resolver_options)
-            .build(),
+            resolver: DnsUpstream::from_env().build_resolver(),
+            fallback: fallback
+                .into_iter()
+                .map(|server| DnsUpstream::Plain(vec![server]).build_resolver())
+                .collect(),
+            query_timeout: std::time::Duration::from_millis(query_timeout),
             dkim_selector: std::env::var("DKIM_SELECTOR")
                 .expect("DKIM_SELECTOR environment variable not set"),
             spf_include: std::env::var("SPF_INCLUDE")
@@ -194,7 +435,12 @@ impl DnsResolver {
             resolver: mock::Resolver {
                 host: (domain, port),
                 txt: records,
+                delay: std::time::Duration::ZERO,
+                txt_fails: false,
+                mx_domain: None,
             },
+            fallback: Vec::new(),
+            query_timeout: std::time::Duration::from_secs(5),
             dkim_selector: "remails-testing".to_string(),
             spf_include: "include:spf.remails.net".to_string(),
         }
@@ -207,15 +453,40 @@ impl DnsResolver {
     ) -> Result<(String, u16), ResolveError> {
         let smtp_port = 25;
 
+        // internationalized domain names must be looked up in their ASCII (punycode) form
+        let domain = Self::to_ascii_domain(domain).map_err(ResolveError::InvalidDomain)?;
+
         // from https://docs.rs/hickory-resolver/latest/hickory_resolver/struct.Resolver.html#method.mx_lookup:
         // "hint queries that end with a ‘.’ are fully qualified names and are cheaper lookups"
         let domain = format!("{domain}{}", if domain.ends_with('.') { "" } else { "." });
 
-        let lookup = self
-            .resolver
-            .mx_lookup(&domain)
-            .await
-            .map_err(ResolveError::Dns)?;
+        let mut result = Err(ResolveError::Timeout);
+        for resolver in std::iter::once(&self.resolver).chain(&self.fallback) {
+            match tokio::time::timeout(self.query_timeout, resolver.mx_lookup(&domain)).await {
+                Ok(Ok(lookup)) => {
+                    result = Ok(lookup);
+                    break;
+                }
+                // the resolver answered, asking another one will not give a different answer
+                Ok(Err(err)) if err.is_no_records_found() => {
+                    result = Err(ResolveError::Dns(err));
+                    break;
+                }
+                Ok(Err(err)) => {
+                    warn!(domain, "MX lookup failed, trying the next resolver: {err}");
+                    result = Err(ResolveError::Dns(err));
+                }
+                Err(_) => {
+                    warn!(
+                        domain,
+                        "MX lookup timed out after {:?}, trying the next resolver",
+                        self.query_timeout
+                    );
+                    result = Err(ResolveError::Timeout);
+                }
+            }
+        }
+        let lookup = result?;
 
         let Some(destination) = lookup
             .iter()
@@ -240,14 +511,30 @@ impl DnsResolver {
         Ok((destination.exchange().to_utf8(), smtp_port))
     }
 
+    /// Convert an internationalized domain name to its ASCII form using IDNA (UTS #46),
+    /// e.g. `bücher.example` becomes `xn--bcher-kva.example`
+    pub fn to_ascii_domain(domain: &str) -> Result<Cow<'_, str>, String> {
+        if domain.is_ascii() {
+            return Ok(Cow::Borrowed(domain));
+        }
+
+        match Host::parse(domain) {
+            Ok(Host::Domain(ascii)) => Ok(Cow::Owned(ascii)),
+            Ok(_) => Err(format!("'{domain}' is not a domain name")),
+            Err(err) => Err(format!(
+                "'{domain}' is not a valid internationalized domain: {err}"
+            )),
+        }
+    }
+
     async fn get_singular_dns_record(
         &self,
         record: &str,
         starting_with: &str,
-    ) -> Result<String, &'static str> {
+    ) -> Result<String, RecordError> {
         trace!("requesting DNS record {record}");
         let Ok(record) = self.resolver.txt_lookup(record).await else {
-            return Err("could not retrieve DNS record");
+            return Err(RecordError::Lookup);
         };
 
         let mut record = record.into_iter().filter(|r| {
@@ -258,11 +545,11 @@ impl DnsResolver {
                 .eq(starting_with.as_bytes())
         });
         let Some(first_record) = record.next() else {
-            return Err("record unavailable");
+            return Err(RecordError::Unavailable);
         };
 
         if let Some(_next_record) = record.next() {
-            return Err("multiple conflicting DNS records available");
+            return Err(RecordError::Multiple);
         }
 
         let data = first_record
@@ -272,34 +559,61 @@ impl DnsResolver {
             .copied()
             .collect::<Vec<_>>();
 
-        String::from_utf8(data).or(Err("could not decode record"))
+        String::from_utf8(data).or(Err(RecordError::Undecodable))
     }
 
     pub async fn verify_dkim(
         &self,
         domain: &str,
         dkim_pk_from_db: &[u8],
-    ) -> Result<&'static str, &'static str> {
+    ) -> Result<&'static str, DkimError> {
         let domain = domain.trim_matches('.');
-        let record = format!("{}._domainkey.{domain}.", self.dkim_selector);
-        let dkim_data = self.get_singular_dns_record(&record, "v=DKIM1").await?;
+        let record = format!("{}._domainkey.{domain}", self.dkim_selector);
+        let dkim_data = self
+            .get_singular_dns_record(&format!("{record}."), "v=DKIM1")
+            .await
+            .map_err(|err| match err {
+                RecordError::Lookup => DkimError::Lookup {
+                    record: record.clone(),
+                },
+                RecordError::Unavailable => DkimError::NotPublished {
+                    record: record.clone(),
+                },
+                RecordError::Multiple => DkimError::Multiple {
+                    record: record.clone(),
+                },
+                RecordError::Undecodable => DkimError::Malformed {
+                    record: record.clone(),
+                    reason: "not valid UTF-8",
+                },
+            })?;
         trace!("dkim data: {dkim_data:?}");
 
+        let malformed = |reason| DkimError::Malformed {
+            record: record.clone(),
+            reason,
+        };
+
         let dns_key = dkim_data
             .split(';')
             .filter_map(|field| field.trim().split_once('='))
             .find(|(key, _value)| *key == "p")
-            .ok_or("could not get public key from record")?
-            .1;
+            .ok_or_else(|| malformed("missing public key (p=)"))?
+            .1
+            .trim();
 
-        let Ok(dns_key) = Base64Unpadded::decode_vec(dns_key) else {
-            return Err("could not decode DKIM key");
+        let Ok(decoded_key) = Base64Unpadded::decode_vec(dns_key) else {
+            return Err(malformed("public key (p=) is not valid base64"));
         };
 
-        if dns_key.iter().eq(dkim_pk_from_db) {
+        if decoded_key.iter().eq(dkim_pk_from_db) {
             Ok("available!")
         } else {
-            Err("public key in DNS record does not match")
+            Err(DkimError::KeyMismatch {
+                record,
+                expected: Base64::encode_string(dkim_pk_from_db),
+                found: dns_key.to_string(),
+            })
         }
     }
 
@@ -308,7 +622,7 @@ impl DnsResolver {
         let record = format!("{domain}.");
         let spf_data = match self.get_singular_dns_record(&record, "v=spf1").await {
             Ok(spf_data) => spf_data,
-            Err(reason) => return VerifyResult::error(reason, None),
+            Err(reason) => return VerifyResult::error(reason.to_string(), None),
         };
         trace!("spf data: {spf_data:?}");
 
@@ -339,7 +653,7 @@ impl DnsResolver {
         let record = format!("_dmarc.{domain}.");
         let dmarc_data = match self.get_singular_dns_record(&record, "v=DMARC1").await {
             Ok(dmarc_data) => dmarc_data,
-            Err(reason) => return VerifyResult::info(reason, None),
+            Err(reason) => return VerifyResult::info(reason.to_string(), None),
         };
         trace!("dmarc data: {dmarc_data:?}");
 
@@ -373,14 +687,28 @@ impl DnsResolver {
         &self,
         domain_name: &str,
         dkim_pk: &[u8],
-    ) -> Result<DomainVerificationStatus, Error> {
-        Ok(DomainVerificationStatus {
+    ) -> Result<DomainVerificationStatus, models::Error> {
+        Ok(self.verify_domain_dkim(domain_name, dkim_pk).await.0)
+    }
+
+    /// Like [`Self::verify_domain`], also returning why the DKIM record did not match, if it did not
+    pub async fn verify_domain_dkim(
+        &self,
+        domain_name: &str,
+        dkim_pk: &[u8],
+    ) -> (DomainVerificationStatus, Option<DkimError>) {
+        let dkim = self.verify_dkim(domain_name, dkim_pk).await;
+        let dkim_error = dkim.as_ref().err().cloned();
+
+        let status = DomainVerificationStatus {
             timestamp: Utc::now(),
-            dkim: self.verify_dkim(domain_name, dkim_pk).await.into(),
+            dkim: dkim.into(),
             spf: self.verify_spf(domain_name).await,
             dmarc: self.verify_dmarc(domain_name).await,
             a: self.any_a_record(domain_name).await,
-        })
+        };
+
+        (status, dkim_error)
     }
 }
 
@@ -392,6 +720,83 @@ mod test {
 
     use super::*;
 
+    #[tokio::test]
+    async fn internationalized_mail_domain() {
+        assert_eq!(
+            DnsResolver::to_ascii_domain("example.com").unwrap(),
+            "example.com"
+        );
+        assert_eq!(
+            DnsResolver::to_ascii_domain("bücher.example").unwrap(),
+            "xn--bcher-kva.example"
+        );
+        assert_eq!(
+            DnsResolver::to_ascii_domain("ÉCOLE.example").unwrap(),
+            "xn--cole-9oa.example"
+        );
+        assert_eq!(
+            DnsResolver::to_ascii_domain("münchen.de").unwrap(),
+            "xn--mnchen-3ya.de"
+        );
+        DnsResolver::to_ascii_domain("bü cher.example").expect_err("should not be valid IDNA");
+
+        // the MX record is looked up using the ASCII form of the domain
+        let mut dns = DnsResolver::mock("localhost", 0);
+        dns.resolver.mx_domain = Some("xn--bcher-kva.example.");
+        let mut priority = 0..65536;
+        let Ok((hostname, _)) = dns
+            .resolve_mail_domain("bücher.example", &mut priority)
+            .await
+        else {
+            panic!("should resolve internationalized domain");
+        };
+        assert_eq!(hostname, "localhost");
+
+        // other domains have no MX record in this mock
+        let mut priority = 0..65536;
+        assert!(
+            dns.resolve_mail_domain("example.com", &mut priority)
+                .await
+                .is_err()
+        );
+
+        let mut priority = 0..65536;
+        assert!(matches!(
+            dns.resolve_mail_domain("bü cher.example", &mut priority)
+                .await,
+            Err(ResolveError::InvalidDomain(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn slow_resolver_fallback() {
+        let mut dns = DnsResolver::mock("primary", 2525);
+        dns.query_timeout = std::time::Duration::from_millis(100);
+        dns.resolver.delay = std::time::Duration::from_secs(10);
+        let mut fallback = DnsResolver::mock("fallback", 2526).resolver;
+        dns.fallback.push(fallback.clone());
+
+        // the slow primary resolver fails over to the fallback resolver within the timeout
+        let start = std::time::Instant::now();
+        let (hostname, port) = dns
+            .resolve_mail_domain("example.com", &mut (0..65536))
+            .await
+            .unwrap_or_else(|_| panic!("should resolve using the fallback resolver"));
+        assert_eq!((hostname.as_str(), port), ("fallback", 2526));
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+
+        // if all resolvers are too slow, the lookup fails
+        fallback.delay = std::time::Duration::from_secs(10);
+        dns.fallback = vec![fallback];
+        let start = std::time::Instant::now();
+        assert!(matches!(
+            dns.resolve_mail_domain("example.com", &mut (0..65536))
+                .await,
+            Err(ResolveError::Timeout)
+        ));
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    }
+
     #[tokio::test]
     async fn dkim_verification() {
         let domain = "localhost";
@@ -404,9 +809,56 @@ mod test {
         dns.verify_dkim(domain, &dkim_key).await.unwrap();
 
         dns.resolver.txt[0] = "v=DKIM1; k=rsa; p=wrongDkimKey";
-        dns.verify_dkim(domain, &dkim_key)
-            .await
-            .expect_err("should error");
+        let err = dns.verify_dkim(domain, &dkim_key).await.unwrap_err();
+        let DkimError::KeyMismatch {
+            record,
+            expected,
+            found,
+        } = &err
+        else {
+            panic!("expected a key mismatch, got {err:?}");
+        };
+        assert_eq!(record, "remails-testing._domainkey.localhost");
+        assert_eq!(Base64::decode_vec(expected).unwrap(), dkim_key);
+        assert_eq!(found, "wrongDkimKey");
+        assert!(err.to_string().contains("found p=wrongDkimKey"));
+
+        dns.resolver.txt[0] = ""; // DKIM record does not exist
+        let err = dns.verify_dkim(domain, &dkim_key).await.unwrap_err();
+        assert!(matches!(err, DkimError::NotPublished { .. }));
+        assert_eq!(
+            err.to_string(),
+            "no DKIM record published at remails-testing._domainkey.localhost"
+        );
+
+        dns.resolver.txt[0] = "v=DKIM1; k=rsa";
+        let err = dns.verify_dkim(domain, &dkim_key).await.unwrap_err();
+        assert!(matches!(
+            err,
+            DkimError::Malformed {
+                reason: "missing public key (p=)",
+                ..
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "DKIM record at remails-testing._domainkey.localhost is malformed: missing public key (p=)"
+        );
+
+        dns.resolver.txt[0] = "v=DKIM1; k=rsa; p=not base64!";
+        let err = dns.verify_dkim(domain, &dkim_key).await.unwrap_err();
+        assert!(matches!(
+            err,
+            DkimError::Malformed {
+                reason: "public key (p=) is not valid base64",
+                ..
+            }
+        ));
+
+        dns.resolver.txt[0] = "v=DKIM1; k=rsa; p=wrongDkimKey";
+        dns.resolver.txt.push("v=DKIM1; k=rsa; p=otherDkimKey");
+        let err = dns.verify_dkim(domain, &dkim_key).await.unwrap_err();
+        assert!(matches!(err, DkimError::Multiple { .. }));
     }
 
     #[tokio::test]
@@ -507,4 +959,89 @@ mod test {
         assert!(matches!(res.dmarc.status, VerifyResultStatus::Info));
         assert!(matches!(res.a.status, VerifyResultStatus::Success));
     }
+
+    #[test]
+    fn dns_upstream_config() {
+        assert_eq!(
+            DnsUpstream::parse(None, None, None, None).unwrap(),
+            DnsUpstream::BuiltIn
+        );
+        assert_eq!(
+            DnsUpstream::parse(Some("system"), None, None, None).unwrap(),
+            DnsUpstream::System
+        );
+        assert_eq!(
+            DnsUpstream::parse(
+                Some("plain"),
+                Some("192.0.2.53, 198.51.100.53:5353"),
+                None,
+                None
+            )
+            .unwrap(),
+            DnsUpstream::Plain(vec![
+                "192.0.2.53:53".parse().unwrap(),
+                "198.51.100.53:5353".parse().unwrap()
+            ])
+        );
+        assert_eq!(
+            DnsUpstream::parse(
+                Some("https"),
+                Some("192.0.2.53"),
+                Some("dns.example".to_owned()),
+                None
+            )
+            .unwrap(),
+            DnsUpstream::Https {
+                servers: vec!["192.0.2.53:443".parse().unwrap()],
+                name: "dns.example".to_owned(),
+                endpoint: None,
+            }
+        );
+
+        // misconfiguration is reported
+        DnsUpstream::parse(Some("doh"), None, None, None).expect_err("unknown mode");
+        DnsUpstream::parse(Some("plain"), None, None, None).expect_err("missing servers");
+        DnsUpstream::parse(Some("plain"), Some("dns.example"), None, None)
+            .expect_err("server is not an IP");
+        DnsUpstream::parse(Some("tls"), Some("192.0.2.53"), None, None)
+            .expect_err("missing TLS name");
+    }
+
+    #[tokio::test]
+    async fn resolver_with_explicit_upstream() {
+        let upstream = DnsUpstream::Tls {
+            servers: vec![
+                "192.0.2.53:853".parse().unwrap(),
+                "198.51.100.53:8853".parse().unwrap(),
+            ],
+            name: "dns.example".to_owned(),
+        };
+        let resolver = upstream.build_resolver();
+
+        let name_servers = resolver.config().name_servers();
+        assert_eq!(name_servers.len(), 2);
+        assert_eq!(
+            name_servers[0].socket_addr,
+            "192.0.2.53:853".parse().unwrap()
+        );
+        assert_eq!(
+            name_servers[1].socket_addr,
+            "198.51.100.53:8853".parse().unwrap()
+        );
+        for name_server in name_servers {
+            assert_eq!(name_server.protocol, Protocol::Tls);
+            assert_eq!(name_server.tls_dns_name.as_deref(), Some("dns.example"));
+        }
+        assert_eq!(resolver.options().ip_strategy, Ipv4Only);
+
+        // plain DNS is queried over both UDP and TCP
+        let resolver = DnsUpstream::Plain(vec!["192.0.2.53:53".parse().unwrap()]).build_resolver();
+        let protocols: Vec<_> = resolver
+            .config()
+            .name_servers()
+            .iter()
+            .map(|name_server| name_server.protocol)
+            .collect();
+        assert_eq!(protocols, vec![Protocol::Udp, Protocol::Tcp]);
+    }
 }
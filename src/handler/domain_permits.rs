@@ -0,0 +1,177 @@
+//! Per destination domain limit on the number of concurrent deliveries
+//!
+//! Opening many simultaneous connections to a single receiver invites rate limiting and
+//! blocklisting. Deliveries to a domain that is at its limit wait for a permit instead of failing.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Number of concurrent deliveries to a single domain, unless configured otherwise
+const DEFAULT_DOMAIN_CONCURRENCY: usize = 8;
+/// Unused domain semaphores are cleaned up once this many domains are tracked
+const CLEANUP_THRESHOLD: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DomainConcurrency {
+    /// Maximum number of concurrent deliveries to any domain
+    pub(crate) default: usize,
+    /// Lower (or higher) limits for specific domains, e.g., `gmail.com`
+    pub(crate) overrides: HashMap<String, usize>,
+}
+
+impl Default for DomainConcurrency {
+    fn default() -> Self {
+        Self {
+            default: DEFAULT_DOMAIN_CONCURRENCY,
+            overrides: HashMap::new(),
+        }
+    }
+}
+
+impl DomainConcurrency {
+    /// Configure the limits using the following environment variables:
+    /// - `DOMAIN_CONCURRENCY`: maximum number of concurrent deliveries to a domain, defaults to 8
+    /// - `DOMAIN_CONCURRENCY_OVERRIDES`: comma-separated per domain limits,
+    ///   e.g., `gmail.com=4,outlook.com=2`
+    ///
+    /// Will panic if any of the limits cannot be parsed or is zero
+    #[cfg(not(test))]
+    pub fn from_env() -> Self {
+        let parse = |limit: &str, var: &str| {
+            limit
+                .trim()
+                .parse::<std::num::NonZeroUsize>()
+                .unwrap_or_else(|_| panic!("{var} must contain positive integers"))
+                .get()
+        };
+
+        let default = std::env::var("DOMAIN_CONCURRENCY")
+            .map(|limit| parse(&limit, "DOMAIN_CONCURRENCY"))
+            .unwrap_or(DEFAULT_DOMAIN_CONCURRENCY);
+
+        let overrides = std::env::var("DOMAIN_CONCURRENCY_OVERRIDES")
+            .map(|overrides| {
+                overrides
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|entry| !entry.is_empty())
+                    .map(|entry| {
+                        let (domain, limit) = entry.split_once('=').unwrap_or_else(|| {
+                            panic!("Invalid entry in DOMAIN_CONCURRENCY_OVERRIDES: {entry}")
+                        });
+                        (
+                            domain.trim().to_lowercase(),
+                            parse(limit, "DOMAIN_CONCURRENCY_OVERRIDES"),
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self { default, overrides }
+    }
+
+    fn limit(&self, domain: &str) -> usize {
+        self.overrides.get(domain).copied().unwrap_or(self.default)
+    }
+}
+
+/// Keyed semaphore handing out delivery permits per destination domain
+#[derive(Clone)]
+pub(crate) struct DomainPermits {
+    limits: Arc<DomainConcurrency>,
+    semaphores: Arc<Mutex<HashMap<String, Arc<Semaphore>>>>,
+}
+
+impl DomainPermits {
+    pub(crate) fn new(limits: DomainConcurrency) -> Self {
+        Self {
+            limits: Arc::new(limits),
+            semaphores: Default::default(),
+        }
+    }
+
+    /// Wait until a delivery to the (lowercase) domain is permitted
+    pub(crate) async fn acquire(&self, domain: &str) -> OwnedSemaphorePermit {
+        let semaphore = {
+            let mut semaphores = self.semaphores.lock().unwrap();
+
+            // a semaphore that is only referenced by the map has no (waiting) permits
+            if semaphores.len() >= CLEANUP_THRESHOLD {
+                semaphores.retain(|_, semaphore| Arc::strong_count(semaphore) > 1);
+            }
+
+            semaphores
+                .entry(domain.to_owned())
+                .or_insert_with(|| Arc::new(Semaphore::new(self.limits.limit(domain))))
+                .clone()
+        };
+
+        semaphore
+            .acquire_owned()
+            .await
+            .expect("domain semaphores are never closed")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+    use tokio::task::JoinSet;
+
+    /// Deliver to the domain many times at once, returning the highest observed concurrency
+    async fn max_concurrency(permits: &DomainPermits, domain: &'static str) -> usize {
+        let active = Arc::new(AtomicUsize::new(0));
+        let max = Arc::new(AtomicUsize::new(0));
+
+        let mut deliveries = JoinSet::new();
+        for _ in 0..50 {
+            let permits = permits.clone();
+            let active = active.clone();
+            let max = max.clone();
+            deliveries.spawn(async move {
+                let _permit = permits.acquire(domain).await;
+                let now_active = active.fetch_add(1, Ordering::SeqCst) + 1;
+                max.fetch_max(now_active, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                active.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        // all deliveries wait for a permit instead of failing
+        assert_eq!(deliveries.join_all().await.len(), 50);
+
+        max.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn domain_concurrency_cap() {
+        let permits = DomainPermits::new(DomainConcurrency {
+            default: 5,
+            overrides: HashMap::from([("gmail.com".to_string(), 2)]),
+        });
+
+        assert_eq!(max_concurrency(&permits, "example.com").await, 5);
+        assert_eq!(max_concurrency(&permits, "gmail.com").await, 2);
+    }
+
+    #[tokio::test]
+    async fn independent_domains() {
+        let permits = DomainPermits::new(DomainConcurrency {
+            default: 1,
+            overrides: HashMap::new(),
+        });
+
+        // a busy domain does not block deliveries to other domains
+        let _busy = permits.acquire("example.com").await;
+        tokio::time::timeout(Duration::from_secs(1), permits.acquire("example.org"))
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,147 @@
+//! Rewriting of the From header for DMARC alignment
+//!
+//! A relayed message with a From address outside the project's domains fails DMARC at the
+//! receiver. Projects can opt in to replace such a From header by the envelope sender, which is
+//! within the project's domain. The original sender is kept in the Reply-To header, such that
+//! replies still reach them.
+
+use base64ct::{Base64, Encoding};
+use email_address::EmailAddress;
+
+use crate::handler::header_fields::{Field, header_fields};
+
+/// Replace the From header of `raw_data` by `from`, mentioning `original_name` and `domain`
+/// in the display name, e.g., `"John Doe via example.com" <bounces@example.com>`
+///
+/// The original From header becomes the Reply-To header, unless the message already has one.
+/// Returns `None` if the message does not have exactly one From header.
+pub(super) fn rewrite_from(
+    raw_data: &[u8],
+    original_name: &str,
+    domain: &str,
+    from: &EmailAddress,
+) -> Option<Vec<u8>> {
+    let mut original_from = None;
+    let mut has_reply_to = false;
+    let mut headers = Vec::with_capacity(raw_data.len());
+    let mut body = &raw_data[raw_data.len()..];
+
+    for field in header_fields(raw_data) {
+        match field {
+            Field::Header { name, value, .. } if name.eq_ignore_ascii_case(b"from") => {
+                if original_from.replace(value).is_some() {
+                    return None;
+                }
+            }
+            Field::Header { name, raw, .. } => {
+                has_reply_to |= name.eq_ignore_ascii_case(b"reply-to");
+                headers.extend_from_slice(raw);
+            }
+            Field::Body(rest) => {
+                body = rest;
+                break;
+            }
+        }
+    }
+    let original_from = original_from?;
+
+    let mut rewritten = Vec::with_capacity(raw_data.len() + 100);
+    rewritten.extend_from_slice(
+        format!(
+            "From: {} <{}>\r\n",
+            display_name(&format!("{original_name} via {domain}")),
+            from.as_str()
+        )
+        .as_bytes(),
+    );
+    if !has_reply_to {
+        rewritten.extend_from_slice(b"Reply-To:");
+        rewritten.extend_from_slice(original_from);
+    }
+    rewritten.extend_from_slice(&headers);
+    rewritten.extend_from_slice(body);
+
+    Some(rewritten)
+}
+
+/// Quote the display name, or encode it if it contains non-ASCII characters (RFC 2047)
+fn display_name(name: &str) -> String {
+    if name.chars().all(|c| c.is_ascii() && !c.is_ascii_control()) {
+        format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        format!("=?utf-8?b?{}?=", Base64::encode_string(name.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrite() {
+        let from: EmailAddress = "john@example.com".parse().unwrap();
+        let message = b"Subject: Hi!\r\nFrom: Jane Doe\r\n <jane@forwarded.com>\r\nTo: james@test.com\r\n\r\nFrom: not a header\r\n";
+
+        let rewritten = rewrite_from(message, "Jane Doe", "example.com", &from).unwrap();
+        assert_eq!(
+            String::from_utf8(rewritten).unwrap(),
+            "From: \"Jane Doe via example.com\" <john@example.com>\r\n\
+             Reply-To: Jane Doe\r\n <jane@forwarded.com>\r\n\
+             Subject: Hi!\r\n\
+             To: james@test.com\r\n\
+             \r\n\
+             From: not a header\r\n"
+        );
+    }
+
+    #[test]
+    fn keep_reply_to() {
+        let from: EmailAddress = "john@example.com".parse().unwrap();
+        let message = b"from: jane@forwarded.com\nReply-To: list@forwarded.com\n\nHello\n";
+
+        let rewritten = rewrite_from(message, "Jane \"J\" Doe", "example.com", &from).unwrap();
+        assert_eq!(
+            String::from_utf8(rewritten).unwrap(),
+            "From: \"Jane \\\"J\\\" Doe via example.com\" <john@example.com>\r\n\
+             Reply-To: list@forwarded.com\n\
+             \n\
+             Hello\n"
+        );
+
+        let rewritten = rewrite_from(
+            b"From: j@forwarded.com\r\n\r\n",
+            "Jänë",
+            "example.com",
+            &from,
+        );
+        assert!(
+            String::from_utf8(rewritten.unwrap())
+                .unwrap()
+                .starts_with("From: =?utf-8?b?")
+        );
+    }
+
+    #[test]
+    fn requires_single_from() {
+        let from: EmailAddress = "john@example.com".parse().unwrap();
+
+        assert!(
+            rewrite_from(
+                b"To: james@test.com\r\n\r\nHi\r\n",
+                "",
+                "example.com",
+                &from
+            )
+            .is_none()
+        );
+        assert!(
+            rewrite_from(
+                b"From: a@forwarded.com\r\nFrom: b@forwarded.com\r\n\r\nHi\r\n",
+                "",
+                "example.com",
+                &from
+            )
+            .is_none()
+        );
+    }
+}
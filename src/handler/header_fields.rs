@@ -0,0 +1,83 @@
+//! Splitting of the header section of a message into header fields
+//!
+//! Used to rewrite or remove headers while keeping all other header fields byte for byte,
+//! such that the message is not altered beyond the changed headers.
+
+pub(super) enum Field<'a> {
+    Header {
+        name: &'a [u8],
+        /// Everything after the colon, including folded lines and the final line break
+        value: &'a [u8],
+        raw: &'a [u8],
+    },
+    /// The empty line separating the headers and the body, and the body itself
+    Body(&'a [u8]),
+}
+
+/// Split the header section into (possibly folded) header fields
+pub(super) fn header_fields(raw_data: &[u8]) -> impl Iterator<Item = Field<'_>> {
+    let mut rest = raw_data;
+
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        if rest.starts_with(b"\r\n") || rest.starts_with(b"\n") {
+            let body = rest;
+            rest = &[];
+            return Some(Field::Body(body));
+        }
+
+        // a field continues on lines starting with whitespace
+        let mut end = 0;
+        loop {
+            end += rest[end..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(rest.len() - end, |i| i + 1);
+            if !rest[end..].starts_with(b" ") && !rest[end..].starts_with(b"\t") {
+                break;
+            }
+        }
+
+        let (raw, remaining) = rest.split_at(end);
+        rest = remaining;
+        let colon = raw.iter().position(|&b| b == b':').unwrap_or(raw.len());
+
+        Some(Field::Header {
+            name: raw[..colon].trim_ascii(),
+            value: raw.get(colon + 1..).unwrap_or_default(),
+            raw,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_fields() {
+        let message =
+            b"Subject: Hi!\r\nTo: a@example.com,\r\n\tb@example.com\r\nX-Empty:\n\r\nTo: body\r\n";
+        let fields = header_fields(message)
+            .map(|field| match field {
+                Field::Header { name, value, raw } => (name, value, raw),
+                Field::Body(body) => (&b""[..], &b""[..], body),
+            })
+            .collect::<Vec<_>>();
+
+        let expected: [(&[u8], &[u8], &[u8]); 4] = [
+            (b"Subject", b" Hi!\r\n", b"Subject: Hi!\r\n"),
+            (
+                b"To",
+                b" a@example.com,\r\n\tb@example.com\r\n",
+                b"To: a@example.com,\r\n\tb@example.com\r\n",
+            ),
+            (b"X-Empty", b"\n", b"X-Empty:\n"),
+            // the body starts with the empty line, after which nothing is a header
+            (b"", b"", b"\r\nTo: body\r\n"),
+        ];
+        assert_eq!(fields, expected);
+    }
+}
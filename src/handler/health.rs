@@ -0,0 +1,143 @@
+//! Health endpoint of the outbound handler
+//!
+//! Messages are only scheduled for the outbound IPs the database assigns to a node, and the node
+//! only sends messages from the IPs it actually has. If these views diverge, messages get stuck,
+//! which is why the handler shuts down when it fails to save its IPs. This endpoint shows both
+//! views, so operators can see which IPs are out of sync.
+
+use crate::kubernetes::Kubernetes;
+use axum::{
+    Json, Router,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, net::IpAddr};
+use tokio::sync::watch;
+use tracing::error;
+
+#[derive(Clone)]
+struct HealthState {
+    k8s: Kubernetes,
+    outbound_ips: watch::Receiver<BTreeSet<IpAddr>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct OutboundIpReport {
+    node: String,
+    /// Whether the node is ready according to the database, `None` if the database does not
+    /// know the node at all
+    ready: Option<bool>,
+    /// The IPs this node sends messages from
+    local_ips: BTreeSet<IpAddr>,
+    /// The IPs assigned to this node in the database
+    database_ips: BTreeSet<IpAddr>,
+    /// IPs of this node for which no messages are scheduled
+    missing_in_database: BTreeSet<IpAddr>,
+    /// IPs for which messages are scheduled on this node, but that it cannot send from
+    missing_locally: BTreeSet<IpAddr>,
+    in_sync: bool,
+}
+
+pub(super) fn router(k8s: Kubernetes, outbound_ips: watch::Receiver<BTreeSet<IpAddr>>) -> Router {
+    Router::new()
+        .route("/health/outbound-ips", get(outbound_ips_report))
+        .with_state(HealthState { k8s, outbound_ips })
+}
+
+/// Responds with `503 Service Unavailable` if the outbound IPs of this node are out of sync
+async fn outbound_ips_report(State(state): State<HealthState>) -> Response {
+    let saved_node = match state.k8s.saved_node().await {
+        Ok(saved_node) => saved_node,
+        Err(e) => {
+            error!("failed to retrieve the outbound IPs of this node: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let local_ips = state.outbound_ips.borrow().clone();
+    let (ready, database_ips) = saved_node
+        .map(|node| (Some(node.ready), node.ips))
+        .unwrap_or_default();
+    let missing_in_database: BTreeSet<_> = local_ips.difference(&database_ips).copied().collect();
+    let missing_locally: BTreeSet<_> = database_ips.difference(&local_ips).copied().collect();
+    let in_sync = ready.is_some() && missing_in_database.is_empty() && missing_locally.is_empty();
+
+    let report = OutboundIpReport {
+        node: state.k8s.node_name().to_owned(),
+        ready,
+        local_ips,
+        database_ips,
+        missing_in_database,
+        missing_locally,
+        in_sync,
+    };
+
+    if in_sync {
+        (StatusCode::OK, Json(report)).into_response()
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, Json(report)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::Handler;
+    use axum::{body::Body, http::Request};
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    async fn get_report(handler: &Handler) -> (StatusCode, OutboundIpReport) {
+        let response = handler
+            .health_router()
+            .oneshot(
+                Request::get("/health/outbound-ips")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), 8192)
+            .await
+            .unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[sqlx::test(fixtures(path = "../fixtures", scripts("k8s_nodes")))]
+    async fn report_outbound_ips(pool: PgPool) {
+        let handler = Handler::test_handler(pool, 1025, None).await;
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+
+        handler
+            .outbound_ips
+            .send_replace(BTreeSet::from([localhost]));
+        let (status, report) = get_report(&handler).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            report,
+            OutboundIpReport {
+                node: "mock-node-1".to_owned(),
+                ready: Some(true),
+                local_ips: BTreeSet::from([localhost]),
+                database_ips: BTreeSet::from([localhost]),
+                missing_in_database: BTreeSet::new(),
+                missing_locally: BTreeSet::new(),
+                in_sync: true,
+            }
+        );
+
+        // the node lost 127.0.0.1 and gained an IP it did not save yet
+        let new_ip: IpAddr = "10.0.0.5".parse().unwrap();
+        handler.outbound_ips.send_replace(BTreeSet::from([new_ip]));
+        let (status, report) = get_report(&handler).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(report.missing_in_database, BTreeSet::from([new_ip]));
+        assert_eq!(report.missing_locally, BTreeSet::from([localhost]));
+        assert!(!report.in_sync);
+    }
+}
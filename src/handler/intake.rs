@@ -0,0 +1,87 @@
+use crate::{
+    bus::client::{BusEnvelope, BusMessage},
+    models::MessagePriority,
+};
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+/// Bounded queue of messages taken from the bus, but not yet handled
+///
+/// Messages are taken out by priority, and in order of arrival within the same priority,
+/// such that a backlog of bulk messages does not delay transactional messages.
+pub(super) struct IntakeQueue {
+    queue: BinaryHeap<Queued>,
+    capacity: usize,
+    received: u64,
+    /// The bus stream ended, no new messages will arrive
+    pub(super) closed: bool,
+}
+
+struct Queued {
+    priority: MessagePriority,
+    sequence: u64,
+    envelope: BusEnvelope,
+}
+
+impl Queued {
+    fn key(&self) -> (MessagePriority, std::cmp::Reverse<u64>) {
+        (self.priority, std::cmp::Reverse(self.sequence))
+    }
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl IntakeQueue {
+    pub(super) fn new(capacity: usize) -> Self {
+        Self {
+            queue: BinaryHeap::with_capacity(capacity),
+            capacity: capacity.max(1),
+            received: 0,
+            closed: false,
+        }
+    }
+
+    /// Queue a message that is ready to send, other bus messages are ignored
+    pub(super) fn push(&mut self, envelope: BusEnvelope) {
+        let BusMessage::EmailReadyToSend(_, _, _, priority) = envelope.message else {
+            return;
+        };
+
+        self.received += 1;
+        self.queue.push(Queued {
+            priority,
+            sequence: self.received,
+            envelope,
+        });
+    }
+
+    /// Take the message with the highest priority that arrived first
+    pub(super) fn pop(&mut self) -> Option<BusEnvelope> {
+        self.queue.pop().map(|queued| queued.envelope)
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub(super) fn is_full(&self) -> bool {
+        self.queue.len() >= self.capacity
+    }
+}
@@ -4,13 +4,26 @@
 pub struct Resolver {
     pub host: (&'static str, u16),
     pub txt: Vec<&'static str>,
+    /// Simulates a slow resolver when answering MX lookups
+    pub delay: std::time::Duration,
+    /// Simulates a resolver that fails to answer TXT lookups
+    pub txt_fails: bool,
+    /// If set, MX lookups only succeed for this (fully qualified) domain
+    pub mx_domain: Option<&'static str>,
 }
 
 impl Resolver {
     pub async fn mx_lookup(
         &self,
-        _: impl AsRef<str>,
+        domain: impl AsRef<str>,
     ) -> Result<[MX; 1], hickory_resolver::ResolveError> {
+        tokio::time::sleep(self.delay).await;
+        if self
+            .mx_domain
+            .is_some_and(|mx_domain| mx_domain != domain.as_ref())
+        {
+            return Err("mock MX lookup of an unexpected domain".into());
+        }
         Ok([MX(self.host.0, self.host.1)])
     }
 
@@ -25,6 +38,9 @@ impl Resolver {
         &self,
         _: impl AsRef<str>,
     ) -> Result<impl Iterator<Item = Txt>, hickory_resolver::ResolveError> {
+        if self.txt_fails {
+            return Err("mock TXT lookup failed".into());
+        }
         Ok(self.txt.iter().map(|txt| Txt(txt)))
     }
 }
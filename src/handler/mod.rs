@@ -1,39 +1,77 @@
-pub use crate::handler::connection_log::ConnectionLog;
+pub use crate::handler::{connection_log::ConnectionLog, transcript::Transcript};
 use crate::{
     Environment,
-    bus::client::{BusClient, BusMessage},
+    bus::client::{BusClient, BusEnvelope, BusMessage, BusStream, ReconnectBackoff},
     dkim::PrivateKey,
     handler::{
+        body::OutboundBody,
         connection_log::LogLevel,
         dns::{DnsResolver, DomainVerificationStatus, ResolveError, VerifyResultStatus},
+        domain_permits::{DomainConcurrency, DomainPermits},
+        header_fields::{Field, header_fields},
+        intake::IntakeQueue,
+        plus_addressing::PlusAddressing,
+        routing::{Route, RoutingTable},
+        smarthost::Smarthost,
+        spam::SpamScorer,
+        strip_headers::StrippedHeaders,
+        tls::OutboundTlsPolicy,
+        transcript::{Recording, Upstream},
+        verp::VerpAddress,
+        warmup::WarmupSchedule,
+        webhook::WebhookSender,
     },
     kubernetes::Kubernetes,
     models::{
-        DeliveryStatus, DomainRepository, Message, MessageId, MessageRepository, MessageStatus,
-        OrganizationRepository, ProjectRepository, QuotaStatus, SuppressedRepository,
+        Bounce, DeliveryStatus, DomainRepository, HoldReason, Message, MessageId,
+        MessageRepository, MessageStatus, OrganizationId, OrganizationRepository,
+        ProjectRepository, QuotaStatus, SuppressedRepository, UnauthorizedDomainPolicy,
     },
+    system_emails::{send_needs_review_emails, send_quota_alert_emails},
+    telemetry::{self, TraceContext},
 };
 use base64ct::{Base64, Encoding};
 use chrono::{Duration, Utc};
 use email_address::EmailAddress;
 use futures::StreamExt;
 use mail_parser::MessageParser;
-use mail_send::{SmtpClient, SmtpClientBuilder, smtp};
-use sqlx::PgPool;
-use std::{collections::BTreeSet, fmt::Display, net::IpAddr, sync::Arc};
+use mail_send::{Credentials, SmtpClient, smtp};
+use sqlx::{PgPool, types::ipnet::IpNet};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+};
 use thiserror::Error;
 use tokio::{
     io::{AsyncRead, AsyncWrite},
-    sync::Semaphore,
-    task::JoinHandle,
+    sync::{AcquireError, OwnedSemaphorePermit, Semaphore, watch},
+    task::{JoinHandle, JoinSet},
 };
 use tokio_rustls::rustls::{crypto, crypto::CryptoProvider};
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, error, info, trace, warn};
+use tracing::{Instrument, debug, error, info, info_span, trace, warn};
 
+mod body;
 mod connection_log;
+mod domain_permits;
+mod from_rewrite;
+mod header_fields;
+mod health;
+mod intake;
+mod plus_addressing;
+mod routing;
+mod smarthost;
+mod strip_headers;
+mod transcript;
+mod warmup;
 
 pub mod dns;
+pub mod spam;
+pub mod tls;
+pub mod verp;
+pub mod webhook;
 
 #[derive(Debug, Error)]
 pub enum HandlerError {
@@ -43,8 +81,6 @@ pub enum HandlerError {
     SerializeMessageData(serde_json::Error),
     #[error("failed to deserialize message data: {0}")]
     DeserializeMessageData(serde_json::Error),
-    #[error("Email failed to parse")]
-    EmailFailedToParse,
     #[error("message is being {0:?}: {1}")]
     MessageNotAccepted(MessageStatus, String),
     #[error("Message is in an illegal state: {0}, {1:?}")]
@@ -59,6 +95,35 @@ enum SendError {
     PermanentFailure,
     #[error("no MX server accepted the message")]
     TemporaryFailure,
+    #[error("the message has been greylisted")]
+    Greylisted,
+    #[error("the routing rules do not allow delivery to the domain")]
+    Rejected,
+}
+
+/// Why a message cannot be sent (yet)
+struct NotAccepted {
+    status: MessageStatus,
+    hold_reason: Option<HoldReason>,
+    reason: String,
+}
+
+impl NotAccepted {
+    fn held(hold_reason: HoldReason, reason: String) -> Self {
+        Self {
+            status: MessageStatus::Held,
+            hold_reason: Some(hold_reason),
+            reason,
+        }
+    }
+
+    fn rejected(reason: String) -> Self {
+        Self {
+            status: MessageStatus::Rejected,
+            hold_reason: None,
+            reason,
+        }
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -72,6 +137,7 @@ enum Protection {
 pub struct RetryConfig {
     pub(crate) delay: Duration,
     pub(crate) max_automatic_retries: i32,
+    pub(crate) greylist: GreylistRetryConfig,
 }
 
 impl RetryConfig {
@@ -79,6 +145,7 @@ impl RetryConfig {
         Self {
             delay: Duration::minutes(5),
             max_automatic_retries: 5,
+            greylist: Default::default(),
         }
     }
 }
@@ -89,12 +156,194 @@ impl Default for RetryConfig {
     }
 }
 
+/// Greylisting receivers temporarily reject mail from unknown senders and expect a retry
+/// within a few minutes, so greylisted messages are retried sooner than the regular schedule
+#[derive(Clone)]
+pub struct GreylistRetryConfig {
+    pub(crate) delay: Duration,
+    /// After this many attempts, greylisted messages follow the regular retry schedule
+    pub(crate) max_retries: i32,
+}
+
+impl Default for GreylistRetryConfig {
+    fn default() -> Self {
+        Self {
+            delay: Duration::minutes(2),
+            max_retries: 2,
+        }
+    }
+}
+
+impl GreylistRetryConfig {
+    /// Configure greylist retries using the following environment variables:
+    /// - `GREYLIST_RETRY_DELAY_SECS`: delay before retrying a greylisted message, defaults to 120
+    /// - `GREYLIST_MAX_RETRIES`: number of attempts after which greylisted messages follow the
+    ///   regular retry schedule, defaults to 2
+    ///
+    /// Will panic if any of the variables is set, but cannot be parsed
+    #[cfg(not(test))]
+    pub fn from_env() -> Self {
+        let default = Self::default();
+
+        Self {
+            delay: std::env::var("GREYLIST_RETRY_DELAY_SECS")
+                .map(|seconds| {
+                    Duration::seconds(
+                        seconds
+                            .parse::<std::num::NonZeroU32>()
+                            .expect(
+                                "GREYLIST_RETRY_DELAY_SECS must be a positive number of seconds",
+                            )
+                            .get()
+                            .into(),
+                    )
+                })
+                .unwrap_or(default.delay),
+            max_retries: std::env::var("GREYLIST_MAX_RETRIES")
+                .map(|retries| {
+                    retries
+                        .parse::<u16>()
+                        .expect("GREYLIST_MAX_RETRIES must be a number of retries")
+                        .into()
+                })
+                .unwrap_or(default.max_retries),
+        }
+    }
+}
+
+/// Timeouts for outbound SMTP connections
+#[derive(Clone)]
+pub struct DeliveryTimeouts {
+    /// Connecting, including the greeting, EHLO, and STARTTLS
+    pub(crate) connect: std::time::Duration,
+    /// Each of the envelope commands, i.e., MAIL FROM and RCPT TO
+    pub(crate) command: std::time::Duration,
+    /// Transferring the message body, up to the final reply of the receiver
+    pub(crate) data: std::time::Duration,
+}
+
+impl DeliveryTimeouts {
+    pub fn new() -> Self {
+        Self {
+            connect: std::time::Duration::from_secs(30),
+            command: std::time::Duration::from_secs(60),
+            data: std::time::Duration::from_secs(300),
+        }
+    }
+}
+
+impl Default for DeliveryTimeouts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Maximum size of an outbound message in bytes, unless configured otherwise
+pub const DEFAULT_MAX_OUTBOUND_SIZE: usize = 25 * 1024 * 1024;
+
+/// Maximum number of connection log lines kept per recipient, unless configured otherwise
+pub const DEFAULT_MAX_LOG_LINES: usize = 100;
+
+/// Percentages of the message quota at which the admins of an organization are alerted,
+/// unless configured otherwise
+pub const DEFAULT_QUOTA_ALERT_THRESHOLDS: [i32; 3] = [80, 95, 100];
+
+/// Number of times the message bus stream is restarted after it ended, unless configured otherwise
+///
+/// Together with the growing delay between restarts, this tolerates the message bus being
+/// unavailable for a few minutes.
+pub const DEFAULT_BUS_STREAM_RESTARTS: u32 = 10;
+
+/// A message bus stream that stayed open for this long resets the number of restarts,
+/// unless configured otherwise
+pub const DEFAULT_BUS_STREAM_HEALTHY_AFTER: std::time::Duration =
+    std::time::Duration::from_secs(300);
+
+/// Number of failed attempts in a row to save the outbound IPs of this node, after which the
+/// handler shuts down, unless configured otherwise
+pub const DEFAULT_NODE_IPS_SAVE_ATTEMPTS: u32 = 5;
+
+/// What the handler does while it fails to save the outbound IPs of this node in the database
+///
+/// In both cases, saving is retried with backoff, and messages are sent from the IPs that were
+/// saved last in the meantime.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NodeIpsSaveFailure {
+    /// Shut down after this many failed attempts in a row
+    Shutdown { max_attempts: u32 },
+    /// Never shut down, keep sending from the IPs that were saved last
+    KeepLastSaved,
+}
+
+impl Default for NodeIpsSaveFailure {
+    fn default() -> Self {
+        Self::Shutdown {
+            max_attempts: DEFAULT_NODE_IPS_SAVE_ATTEMPTS,
+        }
+    }
+}
+
+/// What the handler does with a message that is already delivered, but is processed again,
+/// e.g., because a retry raced with the delivery. It is never delivered again.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DeliveredReprocessing {
+    /// Skip the message without an error
+    #[default]
+    Skip,
+    /// Fail with [`HandlerError::IllegalMessageState`], useful when debugging such races
+    Error,
+}
+
 #[derive(Clone)]
 pub struct HandlerConfig {
     pub(crate) resolver: DnsResolver,
     pub(crate) domain: String,
     pub(crate) retry: RetryConfig,
+    pub(crate) timeouts: DeliveryTimeouts,
     pub(crate) environment: Environment,
+    /// Outbound IPs in these ranges are used, even if the built-in rules would reject them
+    pub(crate) allowed_outbound_cidrs: Vec<IpNet>,
+    /// Outbound IPs in these ranges are never used, this takes precedence over the allowed ranges
+    pub(crate) denied_outbound_cidrs: Vec<IpNet>,
+    /// Maximum number of messages this node handles concurrently
+    pub(crate) workers: usize,
+    /// Maximum number of destination domains this node delivers to concurrently
+    pub(crate) delivery_concurrency: usize,
+    /// Maximum number of concurrent deliveries to a single destination domain
+    pub(crate) domain_concurrency: DomainConcurrency,
+    /// Optional spam check of outbound messages, messages scoring too high are held
+    pub(crate) spam_scorer: SpamScorer,
+    /// Messages larger than this many bytes, including the headers added by Remails, are failed
+    /// instead of being sent, as receivers would reject them anyway
+    pub(crate) max_outbound_size: usize,
+    /// Minimum TLS version and allowed cipher suites of outbound connections
+    pub(crate) tls: OutboundTlsPolicy,
+    /// Older connection log lines of a recipient are dropped beyond this many lines,
+    /// such that messages with many delivery attempts do not grow indefinitely
+    pub(crate) max_log_lines: usize,
+    /// Percentages of the message quota at which the admins of an organization are alerted
+    pub(crate) quota_alert_thresholds: Vec<i32>,
+    /// Recipient domains for which `user+tag@domain` and `user@domain` share their suppression
+    pub(crate) plus_addressing: PlusAddressing,
+    /// Number of times the message bus stream is restarted after it ended, before the handler
+    /// shuts down. With 0, the end of the stream is fatal right away.
+    pub(crate) bus_stream_restarts: u32,
+    /// A message bus stream that stayed open for this long is considered healthy, such that
+    /// the number of restarts starts over when it ends
+    pub(crate) bus_stream_healthy_after: std::time::Duration,
+    /// Whether the handler shuts down when it keeps failing to save the outbound IPs of this node
+    pub(crate) node_ips_save_failure: NodeIpsSaveFailure,
+    /// Relay all messages through this server instead of delivering them to the MX servers
+    /// of the recipients
+    pub(crate) smarthost: Option<Smarthost>,
+    /// Destination domains that are routed differently than through the smarthost or MX servers
+    pub(crate) routes: RoutingTable,
+    /// Hourly limits of outbound IPs that are warming up
+    pub(crate) ip_warmup: WarmupSchedule,
+    /// Headers removed from messages before they are signed and sent
+    pub(crate) stripped_headers: StrippedHeaders,
+    /// Whether processing an already delivered message is skipped or fails
+    pub(crate) delivered_reprocessing: DeliveredReprocessing,
 }
 
 #[cfg(not(test))]
@@ -104,9 +353,187 @@ impl HandlerConfig {
             domain: std::env::var("SMTP_EHLO_DOMAIN")
                 .expect("Missing SMTP_EHLO_DOMAIN environment variable"),
             resolver: DnsResolver::new(),
-            retry: Default::default(),
+            retry: RetryConfig {
+                greylist: GreylistRetryConfig::from_env(),
+                ..Default::default()
+            },
+            timeouts: DeliveryTimeouts {
+                connect: Self::seconds_from_env("OUTBOUND_CONNECT_TIMEOUT_SECS", 30),
+                command: Self::seconds_from_env("OUTBOUND_COMMAND_TIMEOUT_SECS", 60),
+                data: Self::seconds_from_env("OUTBOUND_DATA_TIMEOUT_SECS", 300),
+            },
             environment: Environment::from_env(),
+            allowed_outbound_cidrs: Self::cidrs_from_env("ALLOWED_OUTBOUND_CIDRS"),
+            denied_outbound_cidrs: Self::cidrs_from_env("DENIED_OUTBOUND_CIDRS"),
+            workers: std::env::var("HANDLER_WORKERS")
+                .unwrap_or("100".to_owned())
+                .parse::<std::num::NonZeroUsize>()
+                .expect("HANDLER_WORKERS must be a positive integer")
+                .get(),
+            delivery_concurrency: std::env::var("DELIVERY_CONCURRENCY")
+                .unwrap_or("32".to_owned())
+                .parse::<std::num::NonZeroUsize>()
+                .expect("DELIVERY_CONCURRENCY must be a positive integer")
+                .get(),
+            domain_concurrency: DomainConcurrency::from_env(),
+            spam_scorer: SpamScorer::from_env(),
+            max_outbound_size: std::env::var("MAX_OUTBOUND_MESSAGE_SIZE")
+                .map(|size| {
+                    size.parse()
+                        .expect("MAX_OUTBOUND_MESSAGE_SIZE must be a number of bytes")
+                })
+                .unwrap_or(DEFAULT_MAX_OUTBOUND_SIZE),
+            tls: OutboundTlsPolicy::from_env(),
+            max_log_lines: std::env::var("CONNECTION_LOG_MAX_LINES")
+                .map(|lines| {
+                    lines
+                        .parse::<std::num::NonZeroUsize>()
+                        .expect("CONNECTION_LOG_MAX_LINES must be a positive number of lines")
+                        .get()
+                })
+                .unwrap_or(DEFAULT_MAX_LOG_LINES),
+            quota_alert_thresholds: Self::quota_alert_thresholds_from_env(),
+            plus_addressing: PlusAddressing::from_env(),
+            bus_stream_restarts: std::env::var("BUS_STREAM_RESTARTS")
+                .map(|restarts| {
+                    restarts
+                        .parse()
+                        .expect("BUS_STREAM_RESTARTS must be a number of restarts")
+                })
+                .unwrap_or(DEFAULT_BUS_STREAM_RESTARTS),
+            bus_stream_healthy_after: DEFAULT_BUS_STREAM_HEALTHY_AFTER,
+            node_ips_save_failure: Self::node_ips_save_failure_from_env(),
+            smarthost: Smarthost::from_env(),
+            routes: RoutingTable::from_env(),
+            ip_warmup: WarmupSchedule::from_env(),
+            stripped_headers: StrippedHeaders::from_env(),
+            delivered_reprocessing: Self::delivered_reprocessing_from_env(),
+        }
+    }
+
+    /// Either `skip` (the default) or `error`
+    fn delivered_reprocessing_from_env() -> DeliveredReprocessing {
+        match std::env::var("DELIVERED_MESSAGE_REPROCESSING").as_deref() {
+            Err(_) | Ok("skip") => DeliveredReprocessing::Skip,
+            Ok("error") => DeliveredReprocessing::Error,
+            Ok(behavior) => {
+                panic!("DELIVERED_MESSAGE_REPROCESSING must be `skip` or `error`, not {behavior}")
+            }
+        }
+    }
+
+    /// Either `shutdown` (the default) or `keep_last_saved`, the number of attempts before
+    /// shutting down is configured with `NODE_IPS_SAVE_ATTEMPTS`
+    fn node_ips_save_failure_from_env() -> NodeIpsSaveFailure {
+        let max_attempts = std::env::var("NODE_IPS_SAVE_ATTEMPTS")
+            .map(|attempts| {
+                attempts
+                    .parse::<std::num::NonZeroU32>()
+                    .expect("NODE_IPS_SAVE_ATTEMPTS must be a positive integer")
+                    .get()
+            })
+            .unwrap_or(DEFAULT_NODE_IPS_SAVE_ATTEMPTS);
+
+        match std::env::var("NODE_IPS_SAVE_FAILURE").as_deref() {
+            Err(_) | Ok("shutdown") => NodeIpsSaveFailure::Shutdown { max_attempts },
+            Ok("keep_last_saved") => NodeIpsSaveFailure::KeepLastSaved,
+            Ok(policy) => {
+                panic!(
+                    "NODE_IPS_SAVE_FAILURE must be `shutdown` or `keep_last_saved`, not {policy}"
+                )
+            }
+        }
+    }
+
+    /// Parse a comma-separated list of percentages, e.g., `80,95,100`
+    ///
+    /// Will panic if any of the thresholds is not a percentage between 1 and 100
+    fn quota_alert_thresholds_from_env() -> Vec<i32> {
+        std::env::var("QUOTA_ALERT_THRESHOLDS")
+            .map(|thresholds| {
+                thresholds
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|threshold| !threshold.is_empty())
+                    .map(|threshold| {
+                        threshold
+                            .parse()
+                            .ok()
+                            .filter(|percentage| (1..=100).contains(percentage))
+                            .unwrap_or_else(|| {
+                                panic!("Invalid percentage in QUOTA_ALERT_THRESHOLDS: {threshold}")
+                            })
+                    })
+                    .collect()
+            })
+            .unwrap_or(DEFAULT_QUOTA_ALERT_THRESHOLDS.to_vec())
+    }
+
+    /// Will panic if the variable is set, but is not a number of seconds
+    fn seconds_from_env(var: &str, default: u64) -> std::time::Duration {
+        let seconds = std::env::var(var)
+            .map(|seconds| {
+                seconds
+                    .parse()
+                    .unwrap_or_else(|_| panic!("{var} must be a number of seconds"))
+            })
+            .unwrap_or(default);
+
+        std::time::Duration::from_secs(seconds)
+    }
+
+    /// Parse a comma-separated list of CIDR ranges, e.g., `192.0.2.0/24,198.51.100.7/32`
+    ///
+    /// Will panic if any of the ranges cannot be parsed
+    fn cidrs_from_env(var: &str) -> Vec<IpNet> {
+        std::env::var(var)
+            .map(|cidrs| {
+                cidrs
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|cidr| !cidr.is_empty())
+                    .map(|cidr| {
+                        cidr.parse()
+                            .unwrap_or_else(|_| panic!("Invalid CIDR range in {var}: {cidr}"))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl HandlerConfig {
+    /// Check if we may send messages from this IP address
+    ///
+    /// On top of the built-in rules, the configured denied CIDR ranges exclude IPs
+    /// (e.g., a public management network), and the configured allowed CIDR ranges include IPs
+    /// that would otherwise be rejected. Denied ranges take precedence over allowed ranges.
+    fn is_usable_outbound_ip(&self, ip: Ipv4Addr) -> bool {
+        let addr = IpAddr::V4(ip);
+
+        if self
+            .denied_outbound_cidrs
+            .iter()
+            .any(|net| net.contains(&addr))
+        {
+            return false;
+        }
+
+        if self
+            .allowed_outbound_cidrs
+            .iter()
+            .any(|net| net.contains(&addr))
+        {
+            return true;
         }
+
+        (!ip.is_loopback() || cfg!(test)) &&
+            !ip.is_unspecified() &&
+            !ip.is_multicast() &&
+            !ip.is_broadcast() &&
+            !ip.is_documentation() &&
+            // only allow private IPs if we're in development mode
+            (!ip.is_private() || matches!(self.environment, Environment::Development))
     }
 }
 
@@ -117,6 +544,40 @@ impl Default for HandlerConfig {
     }
 }
 
+#[cfg(test)]
+impl HandlerConfig {
+    /// Configuration for tests with the default settings, and a mock resolver that
+    /// delivers to `localhost:1025`
+    pub(crate) fn test_default() -> Self {
+        Self {
+            domain: "test".to_string(),
+            resolver: DnsResolver::mock("localhost", 1025),
+            environment: Environment::Development,
+            retry: Default::default(),
+            timeouts: Default::default(),
+            allowed_outbound_cidrs: vec![],
+            denied_outbound_cidrs: vec![],
+            workers: 4,
+            delivery_concurrency: 4,
+            domain_concurrency: Default::default(),
+            spam_scorer: Default::default(),
+            max_outbound_size: DEFAULT_MAX_OUTBOUND_SIZE,
+            tls: Default::default(),
+            max_log_lines: DEFAULT_MAX_LOG_LINES,
+            quota_alert_thresholds: DEFAULT_QUOTA_ALERT_THRESHOLDS.to_vec(),
+            plus_addressing: Default::default(),
+            bus_stream_restarts: DEFAULT_BUS_STREAM_RESTARTS,
+            bus_stream_healthy_after: DEFAULT_BUS_STREAM_HEALTHY_AFTER,
+            node_ips_save_failure: Default::default(),
+            smarthost: None,
+            routes: Default::default(),
+            ip_warmup: Default::default(),
+            stripped_headers: Default::default(),
+            delivered_reprocessing: Default::default(),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Handler {
     message_repository: MessageRepository,
@@ -127,8 +588,12 @@ pub struct Handler {
     message_parser: MessageParser,
     k8s: Kubernetes,
     workers: Arc<Semaphore>,
+    deliveries: Arc<Semaphore>,
+    domain_permits: DomainPermits,
     bus_client: BusClient,
-    outbound_ips: BTreeSet<IpAddr>,
+    webhooks: WebhookSender,
+    /// The IPs this node sends messages from, refreshed periodically
+    outbound_ips: Arc<watch::Sender<BTreeSet<IpAddr>>>,
     shutdown: CancellationToken,
     config: Arc<HandlerConfig>,
 }
@@ -160,9 +625,12 @@ impl Handler {
             k8s: Kubernetes::new(pool.clone())
                 .await
                 .expect("Failed to initialize Kubernetes"),
-            workers: Arc::new(Semaphore::new(100)),
+            workers: Arc::new(Semaphore::new(config.workers)),
+            deliveries: Arc::new(Semaphore::new(config.delivery_concurrency)),
+            domain_permits: DomainPermits::new(config.domain_concurrency.clone()),
             bus_client,
-            outbound_ips: Default::default(),
+            webhooks: WebhookSender::new(pool.clone()),
+            outbound_ips: Arc::new(watch::Sender::new(BTreeSet::new())),
             shutdown,
             config,
         }
@@ -177,7 +645,7 @@ impl Handler {
             .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
     }
 
-    fn is_subdomain(subdomain: &str, domain: &str) -> bool {
+    pub(crate) fn is_subdomain(subdomain: &str, domain: &str) -> bool {
         if !Self::is_valid_domain(domain) {
             return false;
         }
@@ -192,14 +660,17 @@ impl Handler {
     /// Check if we are able to send this message, i.e., we are permitted to use the sender's domain,
     /// and then we sign the message with DKIM
     ///
+    /// If the project opted in, a From header outside the project's domain is rewritten
+    /// (see [`from_rewrite`]) before signing, and the rewritten message data is stored.
+    ///
     /// # Returns
     /// * `Ok(Ok(dkim_header))` if all checks passed and we successfully signed the message
-    /// * `Ok(Err((status, reason)))` when a message should be held or rejected for some reason
+    /// * `Ok(Err(not_accepted))` when a message should be held or rejected for some reason
     /// * `Err(handler_error)` on critical internal server errors (mostly related to the database)
     async fn check_and_sign_message(
         &self,
-        message: &Message,
-    ) -> Result<Result<String, (MessageStatus, String)>, HandlerError> {
+        message: &mut Message,
+    ) -> Result<Result<String, NotAccepted>, HandlerError> {
         let sender_domain = message.from_email.domain();
 
         let Some(domain) = self
@@ -208,79 +679,121 @@ impl Handler {
             .await
             .map_err(HandlerError::RepositoryError)?
         else {
-            return Ok(Err((
-                MessageStatus::Held,
-                format!("Project is not permitted to use domain {sender_domain}"),
-            )));
+            let reason = format!("Project is not permitted to use domain {sender_domain}");
+            let project = self.project_repository.get(message.project_id).await?;
+
+            return Ok(Err(match project.unauthorized_domain_policy {
+                UnauthorizedDomainPolicy::Hold => {
+                    NotAccepted::held(HoldReason::Configuration, reason)
+                }
+                UnauthorizedDomainPolicy::Reject => NotAccepted::rejected(reason),
+            }));
         };
 
         // check MAIL FROM domain (can be a subdomain)
         if !Self::is_subdomain(sender_domain, &domain.domain) {
-            return Ok(Err((
-                MessageStatus::Rejected,
-                format!(
-                    "MAIL FROM domain ({sender_domain}) is not a valid (sub-)domain of {}",
-                    domain.domain
-                ),
-            )));
+            return Ok(Err(NotAccepted::rejected(format!(
+                "MAIL FROM domain ({sender_domain}) is not a valid (sub-)domain of {}",
+                domain.domain
+            ))));
         }
 
-        let parsed_msg = self
-            .message_parser
-            .parse(&message.raw_data)
-            .ok_or(HandlerError::EmailFailedToParse)?; // should not happen because we already parsed it before
+        // Messages that could not be parsed are signed and sent as-is, without the header checks
+        let parsed_msg = self.message_parser.parse(&message.raw_data);
 
         // check From domain (can be a different subdomain)
-        if let Some(from) = parsed_msg.from() {
-            for addr in from.iter() {
-                if let Some(addr) = addr.address() {
+        let mut rewritten_from = None;
+        if let Some(from) = parsed_msg.as_ref().and_then(|m| m.from()) {
+            for original in from.iter() {
+                if let Some(addr) = original.address() {
                     let Ok(addr) = addr.parse::<EmailAddress>() else {
-                        return Ok(Err((
-                            MessageStatus::Rejected,
-                            format!("Invalid From address ({addr})"),
-                        )));
+                        return Ok(Err(NotAccepted::rejected(format!(
+                            "Invalid From address ({addr})"
+                        ))));
                     };
                     if !Self::is_subdomain(addr.domain(), &domain.domain) {
-                        return Ok(Err((
-                            MessageStatus::Rejected,
-                            format!(
+                        let project = self.project_repository.get(message.project_id).await?;
+                        rewritten_from = project
+                            .rewrite_unaligned_from
+                            .then(|| {
+                                from_rewrite::rewrite_from(
+                                    &message.raw_data,
+                                    original.name().unwrap_or(addr.as_str()),
+                                    &domain.domain,
+                                    &message.from_email,
+                                )
+                            })
+                            .flatten();
+
+                        if rewritten_from.is_none() {
+                            return Ok(Err(NotAccepted::rejected(format!(
                                 "From domain ({}) is not a valid (sub-)domain of {}",
                                 addr.domain(),
                                 domain.domain
-                            ),
-                        )));
+                            ))));
+                        }
+                        break;
                     }
                 }
             }
         };
 
         // check Return-Path domain (can be a different subdomain)
-        if let Some(return_path) = parsed_msg.return_address() {
+        if let Some(return_path) = parsed_msg.as_ref().and_then(|m| m.return_address()) {
             let Ok(return_path) = return_path.parse::<EmailAddress>() else {
-                return Ok(Err((
-                    MessageStatus::Rejected,
-                    format!("Invalid Return-Path address ({return_path})"),
-                )));
+                return Ok(Err(NotAccepted::rejected(format!(
+                    "Invalid Return-Path address ({return_path})"
+                ))));
             };
             if !Self::is_subdomain(return_path.domain(), &domain.domain) {
-                return Ok(Err((
-                    MessageStatus::Rejected,
-                    format!(
-                        "Return-Path domain ({}) is not a valid (sub-)domain of {}",
-                        return_path.domain(),
-                        domain.domain
-                    ),
-                )));
+                return Ok(Err(NotAccepted::rejected(format!(
+                    "Return-Path domain ({}) is not a valid (sub-)domain of {}",
+                    return_path.domain(),
+                    domain.domain
+                ))));
             }
         };
 
-        // check SPF record
+        // The rewritten message data is stored, as the stored data is streamed during delivery
+        let mut rewritten = false;
+        if let Some(raw_data) = rewritten_from {
+            debug!(
+                message_id = message.id().to_string(),
+                "rewriting From header outside of {}", domain.domain
+            );
+            message.raw_data = raw_data;
+            rewritten = true;
+        }
+        // The Return-Path header of the client is replaced by one naming the envelope sender
+        // when sending, which is aligned with the signing domain
+        if let Some(raw_data) = StrippedHeaders::return_path().strip(&message.raw_data) {
+            debug!(
+                message_id = message.id().to_string(),
+                "removing Return-Path header"
+            );
+            message.raw_data = raw_data;
+            rewritten = true;
+        }
+        if let Some(raw_data) = self.config.stripped_headers.strip(&message.raw_data) {
+            debug!(
+                message_id = message.id().to_string(),
+                "stripping configured headers"
+            );
+            message.raw_data = raw_data;
+            rewritten = true;
+        }
+        if rewritten {
+            self.message_repository.update_raw_data(message).await?;
+        }
+
+        // check SPF record, which must include ours
         let spf = self.config.resolver.verify_spf(sender_domain).await;
         if matches!(spf.status, VerifyResultStatus::Error) {
-            return Ok(Err((
-                MessageStatus::Held,
-                format!("invalid SPF on {sender_domain}: {}", spf.reason),
-            )));
+            let reason = match &spf.value {
+                Some(record) => format!("invalid SPF on {sender_domain}: {} {record}", spf.reason),
+                None => format!("invalid SPF on {sender_domain}: {}", spf.reason),
+            };
+            return Ok(Err(NotAccepted::held(HoldReason::Configuration, reason)));
         }
 
         // check DMARC record
@@ -294,8 +807,8 @@ impl Handler {
             Ok(key) => key,
             Err(e) => {
                 error!("error creating DKIM key: {e}");
-                return Ok(Err((
-                    MessageStatus::Held,
+                return Ok(Err(NotAccepted::held(
+                    HoldReason::Configuration,
                     "internal error: could not create DKIM key".to_string(),
                 )));
             }
@@ -313,7 +826,7 @@ impl Handler {
 
         let domain_status = DomainVerificationStatus {
             timestamp: Utc::now(),
-            dkim: dkim.into(),
+            dkim: dkim.clone().into(),
             spf,
             dmarc,
             a,
@@ -332,49 +845,132 @@ impl Handler {
             .ok();
 
         if let Err(reason) = dkim {
-            return Ok(Err((
-                MessageStatus::Held,
+            return Ok(Err(NotAccepted::held(
+                HoldReason::Configuration,
                 format!("invalid DKIM on {sender_domain}: {reason}"),
             )));
         }
 
         trace!("signing with dkim");
-        let dkim_header = match dkim_key.dkim_header(&parsed_msg) {
+        let dkim_header = match dkim_key.dkim_header(&message.raw_data) {
             Ok(header) => header,
             Err(e) => {
                 error!("error creating DKIM header: {e}");
-                return Ok(Err((
-                    MessageStatus::Held,
+                return Ok(Err(NotAccepted::held(
+                    HoldReason::Configuration,
                     "internal error: could not create DKIM header".to_string(),
                 )));
             }
         };
 
+        // Messages held as spam are only retried on user request, which releases them
+        if message.hold_reason != Some(HoldReason::Spam)
+            && let Some(verdict) = self.config.spam_scorer.check(&message.raw_data).await
+        {
+            info!(
+                message_id = message.id().to_string(),
+                score = verdict.score,
+                "holding message with a high spam score"
+            );
+            return Ok(Err(NotAccepted::held(
+                HoldReason::Spam,
+                format!(
+                    "spam score {:.1} exceeds the threshold of {:.1}",
+                    verdict.score, verdict.hold_threshold
+                ),
+            )));
+        }
+
         // The quota check needs to be the very last check,
         // as otherwise we might count messages that are held towards the quota.
         // Additionally,
         // we should only deduce the quota for messages
         // that are new and have not been counted to the quota before,
         // i.e., only messages in "Processing" and "Held" state.
-        #[allow(clippy::collapsible_if)]
         if matches!(
             message.status,
             MessageStatus::Processing | MessageStatus::Held
         ) {
-            if QuotaStatus::Exceeded
-                == self
-                    .organization_repository
-                    .reduce_quota(message.organization_id)
-                    .await?
-            {
-                return Ok(Err((MessageStatus::Held, "Quota exceeded".to_string())));
+            let quota = self
+                .organization_repository
+                .reduce_quota(
+                    message.organization_id,
+                    message.project_id,
+                    &self.config.quota_alert_thresholds,
+                )
+                .await?;
+
+            if let Some(threshold) = quota.crossed_threshold {
+                self.alert_quota_threshold(message.organization_id, threshold)
+                    .await;
+            }
+
+            if quota.status == QuotaStatus::Exceeded {
+                return Ok(Err(NotAccepted::held(
+                    HoldReason::Quota,
+                    "Quota exceeded".to_string(),
+                )));
             }
         }
 
         Ok(Ok(dkim_header))
     }
 
-    pub async fn handle_message(&self, message: &mut Message) -> Result<(), HandlerError> {
+    /// Let the admins of the organization know it has used `threshold` percent of its quota
+    ///
+    /// Failing to do so does not affect sending the message that crossed the threshold
+    async fn alert_quota_threshold(&self, org_id: OrganizationId, threshold: i32) {
+        info!(
+            organization_id = org_id.to_string(),
+            threshold, "organization reached quota alert threshold"
+        );
+
+        self.bus_client
+            .try_send(&BusMessage::QuotaThresholdReached(org_id, threshold))
+            .await;
+
+        if let Err(e) = send_quota_alert_emails(
+            &self.message_repository,
+            &self.organization_repository,
+            &self.bus_client,
+            self.config.retry.max_automatic_retries,
+            org_id,
+            threshold,
+        )
+        .await
+        {
+            error!(
+                organization_id = org_id.to_string(),
+                "failed to send quota alert emails: {e:?}"
+            );
+        }
+    }
+
+    /// Check, sign and update the status of the message before sending it, returns `false` if
+    /// the message is already delivered and must not be sent again
+    pub async fn handle_message(&self, message: &mut Message) -> Result<bool, HandlerError> {
+        if message.status == MessageStatus::Delivered {
+            return match self.config.delivered_reprocessing {
+                DeliveredReprocessing::Skip => {
+                    info!(
+                        message_id = message.id().to_string(),
+                        "Skipping message that is already delivered"
+                    );
+                    Ok(false)
+                }
+                DeliveredReprocessing::Error => {
+                    error!(
+                        message_id = message.id().to_string(),
+                        "Delivered message should not be processed"
+                    );
+                    Err(HandlerError::IllegalMessageState(
+                        MessageStatus::Delivered,
+                        message.id(),
+                    ))
+                }
+            };
+        }
+
         let result = self.check_and_sign_message(message).await?;
         match result {
             Ok(_) => match &message.status {
@@ -385,23 +981,15 @@ impl Handler {
                 // For messages that have been processed before, keep the status as is
                 MessageStatus::Reattempt
                 | MessageStatus::Failed
+                | MessageStatus::NeedsReview
                 | MessageStatus::Accepted
                 | MessageStatus::Rejected => {}
-                // Other messages should not be processed (but we do want to save the message if this happens)
-                MessageStatus::Delivered => {
-                    error!(
-                        message_id = message.id().to_string(),
-                        "Delivered message should not be processed"
-                    );
-                    return Err(HandlerError::IllegalMessageState(
-                        MessageStatus::Delivered,
-                        message.id(),
-                    ));
-                }
+                MessageStatus::Delivered => unreachable!("delivered messages are skipped above"),
             },
-            Err((ref status, _)) => message.status = status.clone(),
+            Err(ref not_accepted) => message.status = not_accepted.status.clone(),
         };
-        message.reason = result.as_ref().err().map(|e| e.1.clone());
+        message.hold_reason = result.as_ref().err().and_then(|e| e.hold_reason);
+        message.reason = result.as_ref().err().map(|e| e.reason.clone());
 
         message.set_next_retry(&self.config.retry);
 
@@ -412,14 +1000,58 @@ impl Handler {
 
         let dkim_header = match result {
             Ok(dkim_header) => dkim_header,
-            Err((status, reason)) => return Err(HandlerError::MessageNotAccepted(status, reason)),
+            Err(NotAccepted { status, reason, .. }) => {
+                return Err(HandlerError::MessageNotAccepted(status, reason));
+            }
         };
 
         trace!("adding DKIM header");
         trace!("{dkim_header:?}");
-        message.prepend_headers(&dkim_header);
+        message.prepend_headers(&[&dkim_header]);
 
-        Ok(())
+        Ok(true)
+    }
+
+    /// Send the message over an established connection, using the command timeout for the
+    /// envelope and the data timeout for transferring the message body
+    ///
+    /// Returns `false` without sending anything if the envelope requires `SMTPUTF8`, but the
+    /// server did not advertise it in its EHLO response (RFC 6531).
+    async fn transfer<T>(
+        &self,
+        client: &mut SmtpClient<T>,
+        capabilities: &smtp_proto::EhloResponse<String>,
+        smtputf8: bool,
+        message: smtp::message::Message<'_>,
+        body: &OutboundBody,
+    ) -> Result<bool, mail_send::Error>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        if smtputf8 && !capabilities.has_capability(smtp_proto::EXT_SMTP_UTF8) {
+            return Ok(false);
+        }
+
+        let timeouts = &self.config.timeouts;
+
+        client.timeout = timeouts.command;
+        client
+            .mail_from(
+                message.mail_from.email.as_ref(),
+                &message.mail_from.parameters,
+            )
+            .await?;
+        for rcpt in &message.rcpt_to {
+            client
+                .rcpt_to(rcpt.email.as_ref(), &rcpt.parameters)
+                .await?;
+        }
+
+        client.timeout = timeouts.data;
+        let result = body.transfer(client).await;
+        client.timeout = timeouts.command;
+
+        result.map(|()| true)
     }
 
     async fn quit_smtp<T, D>(client: SmtpClient<T>, hostname: D)
@@ -439,19 +1071,56 @@ impl Handler {
             .ok();
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn send_single_message(
         &self,
         recipient: &EmailAddress,
         message: smtp::message::Message<'_>,
+        smtputf8: bool,
+        body: &OutboundBody,
         security: Protection,
         outbound_ip: IpAddr,
         connection_log: &mut ConnectionLog,
+        transcript: &mut Transcript,
     ) -> Result<(), SendError> {
         let domain = recipient.domain();
 
+        let relay = match self.config.routes.route(domain) {
+            Some(Route::Reject) => {
+                info!(domain, "delivery rejected by routing rules");
+                connection_log.log(
+                    LogLevel::Warn,
+                    format!("delivery to domain {domain} is not allowed by the routing rules"),
+                );
+                return Err(SendError::Rejected);
+            }
+            Some(Route::Relay(relay)) => Some(relay),
+            Some(Route::Mx) => None,
+            None => self.config.smarthost.as_ref(),
+        };
+
+        if let Some(relay) = relay {
+            return self
+                .send_single_upstream(
+                    security,
+                    connection_log,
+                    transcript,
+                    domain,
+                    message,
+                    smtputf8,
+                    body,
+                    &relay.hostname,
+                    relay.port,
+                    relay.credentials.as_ref(),
+                    outbound_ip,
+                )
+                .await;
+        }
+
         let mut priority = 0..65536;
 
         let mut is_temporary_failure = false;
+        let mut is_greylisted = false;
 
         loop {
             match self
@@ -465,10 +1134,14 @@ impl Handler {
                         .send_single_upstream(
                             security,
                             connection_log,
+                            transcript,
                             domain,
                             message.clone(),
+                            smtputf8,
+                            body,
                             &hostname,
                             port,
+                            None,
                             outbound_ip,
                         )
                         .await
@@ -476,6 +1149,8 @@ impl Handler {
                         Ok(_) => return Ok(()),
                         Err(SendError::PermanentFailure) => {} // continue to try the next server
                         Err(SendError::TemporaryFailure) => is_temporary_failure = true,
+                        Err(SendError::Greylisted) => is_greylisted = true,
+                        Err(SendError::Rejected) => return Err(SendError::Rejected),
                     }
                 }
                 Err(ResolveError::AllServersExhausted) => {
@@ -495,10 +1170,130 @@ impl Handler {
                     is_temporary_failure = true;
                     break;
                 }
+                Err(ResolveError::Timeout) => {
+                    warn!(domain, "timed out resolving mail domain");
+                    connection_log.log(
+                        LogLevel::Warn,
+                        format!(
+                            "could not resolve domain '{domain}': no DNS resolver answered in time"
+                        ),
+                    );
+                    is_temporary_failure = true;
+                    break;
+                }
+                Err(ResolveError::InvalidDomain(err)) => {
+                    error!(domain, "invalid recipient domain: {err}");
+                    connection_log.log(LogLevel::Error, format!("invalid recipient domain: {err}"));
+                    break;
+                }
+            }
+        }
+
+        if is_greylisted {
+            Err(SendError::Greylisted)
+        } else if is_temporary_failure {
+            Err(SendError::TemporaryFailure)
+        } else {
+            Err(SendError::PermanentFailure)
+        }
+    }
+
+    /// Check if a transient SMTP reply indicates greylisting, e.g.,
+    /// `450 4.2.0 Recipient address rejected: Greylisted` or `451 4.7.1 Please try again later`
+    fn is_greylisted(response: &smtp_proto::Response<String>) -> bool {
+        if response.severity() != smtp_proto::Severity::TransientNegativeCompletion {
+            return false;
+        }
+
+        let message = response.message.to_lowercase();
+        if ["greylist", "graylist", "grey-list", "gray-list"]
+            .iter()
+            .any(|keyword| message.contains(keyword))
+        {
+            return true;
+        }
+
+        matches!(response.code, 450 | 451)
+            && matches!(response.esc, [4, 2, 0] | [4, 7, 0] | [4, 7, 1])
+            && message.contains("try again later")
+    }
+
+    /// Check if a negative SMTP reply is a soft bounce, which may succeed when reattempted later,
+    /// e.g., `452 4.2.2 Mailbox full` or `552 5.2.2 Mailbox full`, as opposed to a hard bounce
+    /// like `550 5.1.1 No such user`
+    fn is_soft_bounce(response: &smtp_proto::Response<String>) -> bool {
+        match response.severity() {
+            smtp_proto::Severity::TransientNegativeCompletion => true,
+            smtp_proto::Severity::PermanentNegativeCompletion => match response.esc {
+                // mailbox full, mail system full or not accepting messages, or network and
+                // routing problems, see RFC 3463
+                [5, 2, 2] | [5, 3, 1] | [5, 3, 2] | [5, 4, _] => true,
+                // "552 Requested mail action aborted: exceeded storage allocation"
+                [0, 0, 0] => response.code == 552,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Try to deliver the message to a single recipient, using each of the protection levels in order
+    ///
+    /// The `SMTPUTF8` parameter is added to the MAIL FROM command if either of the envelope
+    /// addresses is internationalized, or if `utf8_headers` is set (RFC 6531).
+    /// Servers that do not advertise `SMTPUTF8` are not sent such a message, and the delivery
+    /// is retried later instead.
+    #[allow(clippy::too_many_arguments)]
+    async fn send_to_recipient(
+        &self,
+        recipient: &EmailAddress,
+        mail_from: &str,
+        body: &OutboundBody,
+        utf8_headers: bool,
+        order: &[Protection],
+        outbound_ip: IpAddr,
+        connection_log: &mut ConnectionLog,
+        transcript: &mut Transcript,
+    ) -> Result<(), SendError> {
+        let mut is_temporary_failure = false;
+        let mut is_greylisted = false;
+
+        let smtputf8 = utf8_headers || !mail_from.is_ascii() || !recipient.email().is_ascii();
+        let mut envelope_sender = smtp::message::Address::from(mail_from);
+        if smtputf8 {
+            envelope_sender.parameters.add("SMTPUTF8");
+        }
+
+        for &protection in order {
+            // the envelope only, the message data is streamed by `body`
+            let smtp_message = smtp::message::Message {
+                mail_from: envelope_sender.clone(),
+                rcpt_to: vec![recipient.email().into()],
+                body: Default::default(),
+            };
+            match self
+                .send_single_message(
+                    recipient,
+                    smtp_message,
+                    smtputf8,
+                    body,
+                    protection,
+                    outbound_ip,
+                    connection_log,
+                    transcript,
+                )
+                .await
+            {
+                Ok(()) => return Ok(()),
+                Err(SendError::TemporaryFailure) => is_temporary_failure = true,
+                Err(SendError::Greylisted) => is_greylisted = true,
+                Err(SendError::PermanentFailure) => {}
+                Err(SendError::Rejected) => return Err(SendError::Rejected),
             }
         }
 
-        if is_temporary_failure {
+        if is_greylisted {
+            Err(SendError::Greylisted)
+        } else if is_temporary_failure {
             Err(SendError::TemporaryFailure)
         } else {
             Err(SendError::PermanentFailure)
@@ -510,53 +1305,90 @@ impl Handler {
         &self,
         security: Protection,
         connection_log: &mut ConnectionLog,
+        transcript: &mut Transcript,
         domain: &str,
         message: smtp::message::Message<'_>,
-        hostname: &String,
+        smtputf8: bool,
+        body: &OutboundBody,
+        hostname: &str,
         port: u16,
+        credentials: Option<&Credentials<String>>,
         outbound_ip: IpAddr,
     ) -> Result<(), SendError> {
-        let smtp = SmtpClientBuilder::new(&hostname, port)
-            .implicit_tls(false)
-            .local_ip(outbound_ip)
-            .say_ehlo(true)
-            .helo_host(&self.config.domain)
-            .timeout(std::time::Duration::from_secs(30));
+        // a server that requires authentication is only used over TLS,
+        // which is retried later, as TLS might work again by then
+        if matches!(security, Protection::Plaintext) && credentials.is_some() {
+            warn!(
+                domain,
+                hostname,
+                port,
+                "refusing to fall back to plaintext for a server that requires authentication"
+            );
+            connection_log.log(
+                LogLevel::Warn,
+                format!("not connecting to {hostname} on port {port} without TLS, as it requires authentication"),
+            );
+            return Err(SendError::TemporaryFailure);
+        }
+
+        let upstream = Upstream {
+            hostname,
+            port,
+            credentials,
+            local_ip: outbound_ip,
+            helo_host: &self.config.domain,
+            timeout: self.config.timeouts.connect,
+        };
+        let tls_connector = self
+            .config
+            .tls
+            .connector(matches!(security, Protection::TlsAllowInvalidCerts));
+        let recording = Recording::default();
+        let mut connected = false;
 
         let result = match security {
-            Protection::Tls => match smtp.connect().await {
+            Protection::Tls => match upstream.connect_tls(&tls_connector, &recording).await {
                 Err(err) => Err(err),
-                Ok(mut client) => {
+                Ok((mut client, capabilities)) => {
+                    connected = true;
                     trace!(domain, port, "securely connected to upstream server");
                     connection_log.log(
                         LogLevel::Info,
                         format!("securely connected to '{hostname}' with port {port} over TLS",),
                     );
-                    let result = client.send(message.clone()).await;
+                    let result = self
+                        .transfer(&mut client, &capabilities, smtputf8, message.clone(), body)
+                        .await;
                     Self::quit_smtp(client, &hostname).await;
                     result
                 }
             },
-            Protection::TlsAllowInvalidCerts => match smtp.allow_invalid_certs().connect().await {
-                Err(err) => Err(err),
-                Ok(mut client) => {
-                    trace!(
-                        domain,
-                        port,
-                        "insecurely connected to upstream server (allowing invalid certificates)"
-                    );
-                    connection_log.log(
+            Protection::TlsAllowInvalidCerts => {
+                match upstream.connect_tls(&tls_connector, &recording).await {
+                    Err(err) => Err(err),
+                    Ok((mut client, capabilities)) => {
+                        connected = true;
+                        trace!(
+                            domain,
+                            port,
+                            "insecurely connected to upstream server (allowing invalid certificates)"
+                        );
+                        connection_log.log(
                         LogLevel::Info,
                         format!("insecurely connected to '{hostname}' with port {port} over TLS (allowing invalid certificates)"),
                     );
-                    let result = client.send(message.clone()).await;
-                    Self::quit_smtp(client, &hostname).await;
-                    result
+                        let result = self
+                            .transfer(&mut client, &capabilities, smtputf8, message.clone(), body)
+                            .await;
+                        Self::quit_smtp(client, &hostname).await;
+                        result
+                    }
                 }
-            },
-            Protection::Plaintext => match smtp.connect_plain().await {
+            }
+            Protection::Plaintext => match upstream.connect_plain(&recording).await {
                 Err(err) => Err(err),
-                Ok(mut client) => {
+                Ok((mut client, capabilities)) => {
+                    connected = true;
                     trace!(domain, port, "INSECURELY connected to upstream server");
                     connection_log.log(
                         LogLevel::Info,
@@ -564,21 +1396,48 @@ impl Handler {
                             "INSECURELY connected to '{hostname}' with port {port} without TLS",
                         ),
                     );
-                    let result = client.send(message.clone()).await;
+                    let result = self
+                        .transfer(&mut client, &capabilities, smtputf8, message.clone(), body)
+                        .await;
                     Self::quit_smtp(client, &hostname).await;
                     result
                 }
             },
         };
+        transcript.append(recording.finish());
+
+        let err = match result {
+            Ok(true) => {
+                debug!(domain, port, "successfully send email");
+                connection_log.log(
+                    LogLevel::Info,
+                    format!("successfully sent email using hostname '{hostname}' and port {port}",),
+                );
+                return Ok(());
+            }
+            Ok(false) => {
+                // another server might support it, or this one might in a later attempt
+                info!(domain, port, "server does not support SMTPUTF8");
+                connection_log.log(
+                    LogLevel::Warn,
+                    format!("{hostname} on port {port} does not support SMTPUTF8, which is required for this message"),
+                );
+                return Err(SendError::TemporaryFailure);
+            }
+            Err(err) => err,
+        };
 
-        let Err(err) = result else {
-            debug!(domain, port, "successfully send email");
+        if !connected && matches!(err, mail_send::Error::Timeout) {
+            info!(domain, port, "timed out connecting to server");
             connection_log.log(
-                LogLevel::Info,
-                format!("successfully sent email using hostname '{hostname}' and port {port}",),
+                LogLevel::Warn,
+                format!(
+                    "could not connect to {hostname} on port {port} within {:?}",
+                    self.config.timeouts.connect
+                ),
             );
-            return Ok(());
-        };
+            return Err(SendError::TemporaryFailure);
+        }
 
         info!(domain, port, "could not use server: {err}");
         connection_log.log(
@@ -592,10 +1451,20 @@ impl Handler {
             mail_send::Error::Base64(_) => SendError::TemporaryFailure,
             mail_send::Error::Auth(_) => SendError::TemporaryFailure,
             mail_send::Error::UnparseableReply => SendError::TemporaryFailure,
+            mail_send::Error::UnexpectedReply(response) if Self::is_greylisted(&response) => {
+                connection_log.log(
+                    LogLevel::Info,
+                    format!(
+                        "greylisted by {hostname}, retrying in {} minutes",
+                        self.config.retry.greylist.delay.num_minutes()
+                    ),
+                );
+                SendError::Greylisted
+            }
             mail_send::Error::UnexpectedReply(response)
             | mail_send::Error::AuthenticationFailed(response) => {
-                // SMTP 4XX errors are temporary failures
-                if response.severity() == smtp_proto::Severity::TransientNegativeCompletion {
+                // SMTP 4XX errors and soft bounces are temporary failures
+                if Self::is_soft_bounce(&response) {
                     SendError::TemporaryFailure
                 } else {
                     SendError::PermanentFailure
@@ -625,11 +1494,49 @@ impl Handler {
         outbound_ip: IpAddr,
     ) -> Result<(), HandlerError> {
         info!("sending message");
+
+        // `handle_message` has prepended the DKIM header at this point, so it is accounted for
+        let size = message.raw_data.len();
+        if size > self.config.max_outbound_size {
+            warn!(
+                size,
+                max_outbound_size = self.config.max_outbound_size,
+                "message exceeds the maximum outbound size, not sending"
+            );
+            message.status = MessageStatus::Failed;
+            message.reason = Some(format!(
+                "message size ({size} bytes) exceeds the maximum outbound size of {} bytes",
+                self.config.max_outbound_size
+            ));
+            message.set_next_retry(&self.config.retry);
+
+            self.message_repository
+                .update_message_status(&mut message)
+                .await
+                .map_err(HandlerError::RepositoryError)?;
+
+            self.webhooks.notify((&message).into());
+
+            self.bus_client
+                .try_send(&BusMessage::EmailDeliveryAttempted(
+                    message.id(),
+                    message.status,
+                ))
+                .await;
+
+            return Ok(());
+        }
+
         let mut failures = 0u32;
         let mut should_reattempt = false;
+        let mut is_greylisted = false;
 
         let project = self.project_repository.get(message.project_id).await?;
-        let order: &[Protection] = if project.plaintext_fallback {
+        // In strict TLS mode, receivers that cannot meet the TLS policy are retried later
+        // instead of falling back to plaintext
+        let order: &'static [Protection] = if project.plaintext_fallback && self.config.tls.strict {
+            &[Protection::Tls, Protection::TlsAllowInvalidCerts]
+        } else if project.plaintext_fallback {
             &[
                 Protection::Tls,
                 Protection::TlsAllowInvalidCerts,
@@ -639,7 +1546,10 @@ impl Handler {
             &[Protection::Tls]
         };
 
-        'next_rcpt: for recipient in &message.recipients {
+        // recipients to deliver to in this attempt, grouped by destination domain
+        let mut pending: BTreeMap<String, Vec<(EmailAddress, String)>> = BTreeMap::new();
+
+        for recipient in &message.recipients {
             let delivery_details = message
                 .delivery_details
                 .entry(recipient.clone())
@@ -648,7 +1558,10 @@ impl Handler {
 
             if self
                 .suppressed_repository
-                .should_suppress(recipient, message.organization_id)
+                .should_suppress(
+                    &self.config.plus_addressing.normalize(recipient),
+                    message.organization_id,
+                )
                 .await?
             {
                 delivery_details.status = DeliveryStatus::Suppressed;
@@ -699,48 +1612,156 @@ impl Handler {
                 }
             }
 
-            let mut is_temporary_failure = false;
+            // The VERP address lives in the MAIL FROM domain, which has been checked to be
+            // a (sub-)domain of the DKIM signing domain in `check_and_sign_message`
+            let mail_from = if project.verp {
+                VerpAddress::new(message.id(), recipient).to_address(message.from_email.domain())
+            } else {
+                message.from_email.as_str().to_owned()
+            };
 
-            for &protection in order {
-                // restrict the recipients; this object is cheap to clone
-                let smtp_message = smtp::message::Message {
-                    mail_from: message.from_email.as_str().into(),
-                    rcpt_to: vec![recipient.email().into()],
-                    body: message.raw_data.as_slice().into(),
-                };
-                match self
-                    .send_single_message(
-                        recipient,
-                        smtp_message,
-                        protection,
-                        outbound_ip,
-                        connection_log,
-                    )
-                    .await
-                {
-                    Ok(()) => {
-                        delivery_details.status = DeliveryStatus::Success {
-                            delivered: chrono::Utc::now(),
-                        };
-                        self.suppressed_repository
-                            .unsuppress(recipient, message.organization_id)
-                            .await?;
-                        continue 'next_rcpt;
+            pending
+                .entry(recipient.domain().to_lowercase())
+                .or_default()
+                .push((recipient.clone(), mail_from));
+        }
+
+        // Header fields with UTF-8 can only be transferred using the SMTPUTF8 extension
+        let utf8_headers = header_fields(&message.raw_data)
+            .any(|field| matches!(field, Field::Header { raw, .. } if !raw.is_ascii()));
+
+        // Only the headers added by Remails are kept in memory during delivery,
+        // the stored message data is streamed from the database for each transfer
+        let body = OutboundBody::stored(
+            message.prepended_headers(),
+            self.message_repository.clone(),
+            message.organization_id,
+            message.id(),
+            message.stored_size(),
+        );
+        message.release_raw_data();
+
+        // Deliver to distinct domains concurrently, bounded by the delivery permits of this node.
+        // Recipients within the same domain are delivered sequentially to avoid opening many
+        // connections to the same receiver.
+        let mut attempted = Vec::new();
+        let mut deliveries = JoinSet::new();
+        for (domain, recipients) in pending {
+            attempted.extend(recipients.iter().map(|(recipient, _)| recipient.clone()));
+
+            let handler = self.clone();
+            let body = body.clone();
+            deliveries.spawn(
+                async move {
+                    // Wait for a permit of the destination domain first, such that deliveries
+                    // to a busy domain do not occupy the delivery permits of this node
+                    let _domain_permit = handler.domain_permits.acquire(&domain).await;
+                    let Ok(_p) = handler.deliveries.clone().acquire_owned().await else {
+                        error!(domain, "failed to acquire delivery semaphore permit");
+                        return Vec::new();
+                    };
+
+                    let mut results = Vec::with_capacity(recipients.len());
+                    for (recipient, mail_from) in recipients {
+                        // Receivers check DMARC alignment of the envelope sender, which is made
+                        // explicit by a Return-Path header. With VERP, it differs per recipient.
+                        let body = body.with_return_path(&mail_from);
+                        let mut connection_log = ConnectionLog::default();
+                        let mut transcript = Transcript::default();
+                        let result = handler
+                            .send_to_recipient(
+                                &recipient,
+                                &mail_from,
+                                &body,
+                                utf8_headers,
+                                order,
+                                outbound_ip,
+                                &mut connection_log,
+                                &mut transcript,
+                            )
+                            .await
+                            .map(|()| chrono::Utc::now());
+                        results.push((recipient, (result, connection_log, transcript)));
                     }
-                    Err(SendError::TemporaryFailure) => is_temporary_failure = true,
-                    Err(SendError::PermanentFailure) => {}
+                    results
                 }
+                .in_current_span(),
+            );
+        }
+
+        let mut results = HashMap::new();
+        while let Some(result) = deliveries.join_next().await {
+            match result {
+                Ok(domain_results) => results.extend(domain_results),
+                Err(err) => error!("delivery task failed: {err}"),
             }
-            failures += 1;
+        }
 
-            if is_temporary_failure {
-                should_reattempt = true;
-                delivery_details.status = DeliveryStatus::Reattempt;
-            } else {
-                self.suppressed_repository
-                    .report_failure(recipient, message.organization_id)
-                    .await?;
-                delivery_details.status = DeliveryStatus::Failed;
+        for recipient in attempted {
+            let delivery_details = message
+                .delivery_details
+                .entry(recipient.clone())
+                .or_default();
+
+            let result = match results.remove(&recipient) {
+                Some((result, connection_log, transcript)) => {
+                    delivery_details.log.append(connection_log);
+                    delivery_details.transcript = transcript;
+                    result
+                }
+                None => {
+                    delivery_details.log.log(
+                        LogLevel::Error,
+                        format!(
+                            "delivery to {} was interrupted by an internal error",
+                            recipient.email()
+                        ),
+                    );
+                    Err(SendError::TemporaryFailure)
+                }
+            };
+
+            match result {
+                Ok(delivered) => {
+                    delivery_details.status = DeliveryStatus::Success { delivered };
+                    delivery_details.bounce = None;
+                    self.suppressed_repository
+                        .unsuppress(
+                            &self.config.plus_addressing.normalize(&recipient),
+                            message.organization_id,
+                        )
+                        .await?;
+                }
+                Err(SendError::Greylisted) => {
+                    failures += 1;
+                    should_reattempt = true;
+                    is_greylisted = true;
+                    delivery_details.status = DeliveryStatus::Reattempt;
+                    delivery_details.bounce = Some(Bounce::Soft);
+                }
+                Err(SendError::TemporaryFailure) => {
+                    failures += 1;
+                    should_reattempt = true;
+                    delivery_details.status = DeliveryStatus::Reattempt;
+                    delivery_details.bounce = Some(Bounce::Soft);
+                }
+                Err(SendError::PermanentFailure) => {
+                    failures += 1;
+                    self.suppressed_repository
+                        .report_failure(
+                            &self.config.plus_addressing.normalize(&recipient),
+                            message.organization_id,
+                        )
+                        .await?;
+                    delivery_details.status = DeliveryStatus::Failed;
+                    delivery_details.bounce = Some(Bounce::Hard);
+                }
+                // not a bounce, so the recipient is not reported for suppression
+                Err(SendError::Rejected) => {
+                    failures += 1;
+                    delivery_details.status = DeliveryStatus::Failed;
+                    delivery_details.bounce = None;
+                }
             }
         }
 
@@ -772,13 +1793,26 @@ impl Handler {
             }
         };
 
+        for delivery_details in message.delivery_details.values_mut() {
+            delivery_details.log.rotate(self.config.max_log_lines);
+        }
+
         message.set_next_retry(&self.config.retry);
+        if is_greylisted {
+            message.set_next_greylist_retry(&self.config.retry);
+        }
 
         self.message_repository
             .update_message_status(&mut message)
             .await
             .map_err(HandlerError::RepositoryError)?;
 
+        self.webhooks.notify((&message).into());
+
+        if message.status == MessageStatus::NeedsReview {
+            self.alert_needs_review(&message).await;
+        }
+
         self.bus_client
             .try_send(&BusMessage::EmailDeliveryAttempted(
                 message.id(),
@@ -789,118 +1823,392 @@ impl Handler {
         Ok(())
     }
 
-    pub fn spawn(mut self) -> JoinHandle<()> {
-        tokio::spawn(async move {
-            let mut bus_stream = self
-                .bus_client
-                .receive_auto_reconnect(std::time::Duration::from_secs(1));
+    /// Let the admins of the organization know a message exhausted its automatic retries and
+    /// needs review
+    ///
+    /// Failing to do so does not affect the message itself
+    async fn alert_needs_review(&self, message: &Message) {
+        info!(
+            message_id = message.id().to_string(),
+            organization_id = message.organization_id.to_string(),
+            "message exhausted its automatic retries and needs review"
+        );
+
+        if let Err(e) = send_needs_review_emails(
+            &self.message_repository,
+            &self.organization_repository,
+            &self.bus_client,
+            self.config.retry.max_automatic_retries,
+            message.organization_id,
+            message.id(),
+            message.attempts,
+        )
+        .await
+        {
+            error!(
+                message_id = message.id().to_string(),
+                organization_id = message.organization_id.to_string(),
+                "failed to send needs review emails: {e:?}"
+            );
+        }
+    }
 
-            let mut interval = tokio::time::interval(std::time::Duration::from_secs(10));
-            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    /// Whether a message may be sent from the outbound IP right now. While the IP is warming up,
+    /// the message is counted towards its hourly limit.
+    async fn take_outbound_ip_slot(&self, outbound_ip: IpAddr) -> Result<bool, HandlerError> {
+        if self.config.ip_warmup.is_empty() {
+            return Ok(true);
+        }
 
-            loop {
-                tokio::select! {
-                    _ = self.shutdown.cancelled() => {
-                        info!("shutting down message handler");
-                        return;
+        let Some(started_at) = self
+            .message_repository
+            .outbound_ip_warmup_started_at(outbound_ip)
+            .await?
+        else {
+            return Ok(true);
+        };
+        let Some(hourly_limit) = self.config.ip_warmup.hourly_limit(started_at, Utc::now()) else {
+            return Ok(true);
+        };
+
+        Ok(self
+            .message_repository
+            .record_outbound_ip_usage(outbound_ip, hourly_limit.try_into().unwrap_or(i32::MAX))
+            .await?)
+    }
+
+    /// Retry the message once the hourly limit of the outbound IP resets, which does not count
+    /// as a delivery attempt. The retry may be assigned another outbound IP.
+    ///
+    /// The message has already been counted towards the quota, which the `Reattempt` status
+    /// prevents from happening twice.
+    async fn defer_to_next_hour(
+        &self,
+        message: &mut Message,
+        outbound_ip: IpAddr,
+    ) -> Result<(), HandlerError> {
+        info!(
+            outbound_ip = outbound_ip.to_string(),
+            "outbound IP reached its hourly limit while warming up, retrying in the next hour"
+        );
+
+        message.attempts = message.attempts.saturating_sub(1);
+        message.status = MessageStatus::Reattempt;
+        message.reason = Some(format!(
+            "outbound IP {outbound_ip} reached its hourly limit while warming up"
+        ));
+        message.retry_after = Some(warmup::next_hour(Utc::now()));
+
+        self.message_repository
+            .update_message_status(message)
+            .await
+            .map_err(HandlerError::RepositoryError)
+    }
+
+    /// Save the outbound IPs of this node in the database, after which messages are sent from them
+    ///
+    /// On failure, the IPs that were saved before are kept, and the handler shuts down according
+    /// to [`HandlerConfig::node_ips_save_failure`]. Returns whether the IPs were saved.
+    async fn save_outbound_ips(&self, ips: BTreeSet<IpAddr>, failures: &mut u32) -> bool {
+        match self.k8s.save_available_node_ips(ips.clone()).await {
+            Ok(()) => {
+                info!("new interface list: {:?}", ips);
+                self.outbound_ips.send_replace(ips);
+                *failures = 0;
+                true
+            }
+            Err(e) => {
+                *failures += 1;
+                match self.config.node_ips_save_failure {
+                    NodeIpsSaveFailure::Shutdown { max_attempts } if *failures >= max_attempts => {
+                        error!(
+                            failures = *failures,
+                            "failed to save available node IPs: {e}"
+                        );
+                        error!("Shutting down message handler as sending IPs are out of sync");
+                        self.shutdown.cancel();
                     }
-                    _ = interval.tick() => {
-                        trace!("reload network interfaces");
-                        let new_ips = if_addrs::get_if_addrs()
-                            .expect("Cannot retrieve host network interfaces")
-                            .into_iter()
-                            .map(|iface| iface.ip())
-                            // For now, we only handle IPv4 addresses. Maybe v6 will follow later
-                            .filter_map(|ip| {match ip {
-                                IpAddr::V4(v4) => Some(v4),
-                                IpAddr::V6(_) => None
-                            }})
-                            .filter(|ip|
-                                (!ip.is_loopback() || cfg!(test)) &&
-                                !ip.is_unspecified() &&
-                                !ip.is_multicast() &&
-                                !ip.is_broadcast() &&
-                                !ip.is_documentation() &&
-                                // only allow private IPs if we're in development mode
-                                (!ip.is_private() || matches!(self.config.environment, Environment::Development))
-                            )
-                            .map(Into::into)
-                            .collect();
-                        if new_ips != self.outbound_ips {
-                            self.outbound_ips = new_ips;
-                            info!("new interface list: {:?}", self.outbound_ips);
-                            match self.k8s.save_available_node_ips(self.outbound_ips.clone()).await {
-                                Ok(_) => {},
-                                Err(e) => {
-                                    error!("failed to save available node IPs: {e}");
-                                    error!("Shutting down message handler as sending IPs are out of sync");
-                                    self.shutdown.cancel();
-                                }
-                            }
+                    _ => {
+                        error!(
+                            failures = *failures,
+                            "failed to save available node IPs, retrying later: {e}"
+                        );
+                    }
+                }
+                false
+            }
+        }
+    }
+
+    /// Router of the health endpoint, reporting whether the outbound IPs of this node are in sync
+    /// with the database
+    pub fn health_router(&self) -> axum::Router {
+        health::router(self.k8s.clone(), self.outbound_ips.subscribe())
+    }
+
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(self.run(|bus_client| bus_client.receive_until_disconnected()))
+    }
+
+    /// Handle the messages of the bus stream created by `connect`, until the handler is shut down
+    ///
+    /// If the stream ends, e.g., because the message bus disconnected, it is created again
+    /// after a growing delay, up to [`HandlerConfig::bus_stream_restarts`] times in a row,
+    /// after which the whole handler shuts down. A stream that stayed open for
+    /// [`HandlerConfig::bus_stream_healthy_after`] resets the number of restarts.
+    async fn run<F>(self, connect: F)
+    where
+        F: for<'a> Fn(&'a BusClient) -> BusStream<'a>,
+    {
+        let mut bus_stream = connect(&self.bus_client);
+        let mut stream_started_at = tokio::time::Instant::now();
+        let mut stream_restarts = 0;
+        let mut stream_backoff = ReconnectBackoff::new(
+            std::time::Duration::from_secs(1),
+            std::time::Duration::from_secs(60),
+        );
+
+        let mut save_failures = 0;
+        let mut save_backoff = ReconnectBackoff::new(
+            std::time::Duration::from_secs(10),
+            std::time::Duration::from_secs(300),
+        );
+        let mut retry_save_at = tokio::time::Instant::now();
+
+        let mut intake = IntakeQueue::new(self.config.workers);
+
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(10));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+        loop {
+            tokio::select! {
+                _ = self.shutdown.cancelled() => {
+                    info!("shutting down message handler");
+                    return;
+                }
+                _ = interval.tick() => {
+                    trace!("reload network interfaces");
+                    let new_ips: BTreeSet<IpAddr> = if_addrs::get_if_addrs()
+                        .expect("Cannot retrieve host network interfaces")
+                        .into_iter()
+                        .map(|iface| iface.ip())
+                        // For now, we only handle IPv4 addresses. Maybe v6 will follow later
+                        .filter_map(|ip| {match ip {
+                            IpAddr::V4(v4) => Some(v4),
+                            IpAddr::V6(_) => None
+                        }})
+                        .filter(|ip| self.config.is_usable_outbound_ip(*ip))
+                        .map(Into::into)
+                        .collect();
+                    if new_ips != *self.outbound_ips.borrow() && tokio::time::Instant::now() >= retry_save_at {
+                        if self.save_outbound_ips(new_ips, &mut save_failures).await {
+                            save_backoff.reset();
+                        } else {
+                            retry_save_at = tokio::time::Instant::now() + save_backoff.next_delay();
                         }
                     }
-                    message = bus_stream.next() => {
-                        match message {
-                            None => {
-                                error!("Bus stream ended, shutting down");
+                }
+                next = Self::next_message(
+                    self.workers.clone(),
+                    &mut bus_stream,
+                    &mut intake,
+                    &|ip| self.outbound_ips.borrow().contains(&ip),
+                ) => {
+                    match next {
+                        Err(_) => {
+                            error!("failed to acquire worker semaphore permit, shutting down");
+                            self.shutdown.cancel();
+                        },
+                        Ok(None) => {
+                            if stream_started_at.elapsed() >= self.config.bus_stream_healthy_after {
+                                stream_restarts = 0;
+                                stream_backoff.reset();
+                            }
+
+                            let fatal = stream_restarts >= self.config.bus_stream_restarts;
+                            opentelemetry::global::meter(env!("CARGO_CRATE_NAME"))
+                                .u64_counter("bus_stream_ended")
+                                .with_description("Number of times the message bus stream of the handler ended")
+                                .build()
+                                .add(1, &[opentelemetry::KeyValue::new("fatal", fatal)]);
+
+                            if fatal {
+                                error!(
+                                    restarts = stream_restarts,
+                                    max_restarts = self.config.bus_stream_restarts,
+                                    "Bus stream ended, shutting down"
+                                );
                                 self.shutdown.cancel();
-                            },
-                            Some(BusMessage::EmailReadyToSend(id, outbound_ip)) => {
-                                if self.outbound_ips.contains(&outbound_ip) {
-                                    self.handle_ready_to_send(id, outbound_ip).await;
-                                } else {
-                                    trace!(
-                                        message_id = id.to_string(),
-                                        outbound_ip = outbound_ip.to_string(),
-                                        "skipping message as it should not be send from this node"
-                                    );
-                                }
-                            },
-                            _ => {} // ignore other messages
+                                return;
+                            } else {
+                                stream_restarts += 1;
+                                let delay = stream_backoff.next_delay();
+                                error!(
+                                    restarts = stream_restarts,
+                                    max_restarts = self.config.bus_stream_restarts,
+                                    "Bus stream ended, restarting it in {delay:?}"
+                                );
+                                let restarted = connect(&self.bus_client);
+                                bus_stream = Box::pin(async_stream::stream! {
+                                    tokio::time::sleep(delay).await;
+                                    for await envelope in restarted {
+                                        yield envelope;
+                                    }
+                                });
+                                stream_started_at = tokio::time::Instant::now() + delay;
+                                intake.closed = false;
+                            }
+                        },
+                        Ok(Some((permit, BusEnvelope {
+                            message: BusMessage::EmailReadyToSend(id, outbound_ip, correlation_id, _),
+                            trace_context,
+                            ..
+                        }))) => {
+                            // the outbound IPs of this node may have changed while the
+                            // message was queued
+                            if self.outbound_ips.borrow().contains(&outbound_ip) {
+                                self.handle_ready_to_send(
+                                    id,
+                                    outbound_ip,
+                                    correlation_id,
+                                    &trace_context,
+                                    permit,
+                                );
+                            } else {
+                                trace!(
+                                    message_id = id.to_string(),
+                                    outbound_ip = outbound_ip.to_string(),
+                                    "skipping message as it should not be send from this node"
+                                );
+                            }
+                        },
+                        _ => {} // ignore other messages
+                    }
+                }
+            }
+        }
+    }
+
+    /// Waits for a free worker before handing out the queued message with the highest priority
+    ///
+    /// This bounds the number of messages being handled by the number of workers. Meanwhile,
+    /// messages are taken from the bus until the intake queue is full, such that higher-priority
+    /// messages can overtake a backlog of lower-priority ones. While all workers are busy and the
+    /// queue is full, new messages are not consumed, and the message bus drops them for this node
+    /// if it cannot keep up. Those messages are picked up again by the periodic retry.
+    ///
+    /// Messages to be sent from an IP that `is_outbound_ip` does not accept are meant for other
+    /// nodes, those are skipped without taking a spot in the queue or waiting for a worker.
+    async fn next_message(
+        workers: Arc<Semaphore>,
+        bus_stream: &mut BusStream<'_>,
+        intake: &mut IntakeQueue,
+        is_outbound_ip: &impl Fn(IpAddr) -> bool,
+    ) -> Result<Option<(OwnedSemaphorePermit, BusEnvelope)>, AcquireError> {
+        loop {
+            if intake.closed && intake.is_empty() {
+                return Ok(None);
+            }
+
+            tokio::select! {
+                // prefer filling the queue, such that all waiting messages are considered
+                biased;
+                message = bus_stream.next(), if !intake.closed && !intake.is_full() => {
+                    match message {
+                        Some(BusEnvelope {
+                            message: BusMessage::EmailReadyToSend(id, outbound_ip, ..),
+                            ..
+                        }) if !is_outbound_ip(outbound_ip) => {
+                            trace!(
+                                message_id = id.to_string(),
+                                outbound_ip = outbound_ip.to_string(),
+                                "skipping message as it should not be send from this node"
+                            );
                         }
+                        Some(envelope) => intake.push(envelope),
+                        None => intake.closed = true,
                     }
                 }
+                permit = workers.clone().acquire_owned(), if !intake.is_empty() => {
+                    let envelope = intake.pop().expect("intake queue is not empty");
+                    return Ok(Some((permit?, envelope)));
+                }
             }
-        })
+        }
     }
 
-    async fn handle_ready_to_send(&self, id: MessageId, outbound_ip: IpAddr) {
-        info!("Ready to send {id}");
+    fn handle_ready_to_send(
+        &self,
+        id: MessageId,
+        outbound_ip: IpAddr,
+        correlation_id: Option<String>,
+        trace_context: &TraceContext,
+        permit: OwnedSemaphorePermit,
+    ) {
+        // correlate the delivery with the span that marked the message as ready to send
+        let span = info_span!(
+            "deliver_message",
+            message_id = id.to_string(),
+            correlation_id
+        );
+        telemetry::set_parent(&span, trace_context);
+        info!(parent: &span, "Ready to send {id}");
 
-        let Ok(permit) = self.workers.clone().acquire_owned().await else {
-            error!("failed to acquire worker semaphore permit, shutting down");
-            self.shutdown.cancel();
-            return;
-        };
         let self_clone = self.clone();
-        tokio::spawn(async move {
-            let _p = permit;
+        tokio::spawn(
+            async move {
+                let _p = permit;
 
-            // retrieve message from database
-            let mut message = match self_clone.message_repository.get_if_org_may_send(id).await {
-                Ok(message) => message,
-                Err(e) => {
-                    error!("failed to get message: {e:?}");
-                    return;
-                }
-            };
+                // retrieve message from database
+                let mut message = match self_clone.message_repository.get_if_org_may_send(id).await
+                {
+                    Ok(message) => message,
+                    Err(e) => {
+                        error!("failed to get message: {e:?}");
+                        return;
+                    }
+                };
 
-            message.attempts += 1;
+                let message_id = message.id().to_string();
+                message.attempts += 1;
 
-            let message_id = message.id().to_string();
-            if let Err(e) = self_clone.handle_message(&mut message).await {
-                if let HandlerError::MessageNotAccepted(MessageStatus::Held, reason) = &e {
-                    warn!(message_id, "Message held: {reason}")
-                } else {
-                    error!(message_id, "failed to handle message: {e:?}");
+                match self_clone.handle_message(&mut message).await {
+                    Ok(true) => {}
+                    Ok(false) => return,
+                    Err(HandlerError::MessageNotAccepted(MessageStatus::Held, reason)) => {
+                        warn!(message_id, "Message held: {reason}");
+                        return;
+                    }
+                    Err(e) => {
+                        error!(message_id, "failed to handle message: {e:?}");
+                        return;
+                    }
+                };
+
+                // only messages that are signed and counted towards the quota take a slot
+                match self_clone.take_outbound_ip_slot(outbound_ip).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        if let Err(e) = self_clone
+                            .defer_to_next_hour(&mut message, outbound_ip)
+                            .await
+                        {
+                            error!(message_id, "failed to defer message: {e:?}");
+                        }
+                        return;
+                    }
+                    Err(e) => {
+                        error!(message_id, "failed to check the outbound IP limit: {e:?}");
+                        return;
+                    }
                 }
-                return;
-            };
 
-            if let Err(e) = self_clone.send_message(message, outbound_ip).await {
-                error!(message_id, "failed to send message: {e:?}");
+                if let Err(e) = self_clone.send_message(message, outbound_ip).await {
+                    error!(message_id, "failed to send message: {e:?}");
+                }
             }
-        });
+            .instrument(span),
+        );
     }
 }
 
@@ -911,13 +2219,20 @@ pub mod mock;
 mod test {
     use super::*;
     use crate::{
+        bus::client::BUS_PROTOCOL_VERSION,
         handler::dns::DnsResolver,
-        models::{NewMessage, SmtpCredentialRepository, SmtpCredentialRequest},
-        test::{TestProjects, random_port},
+        models::{MessagePriority, NewMessage},
+        test::{TestProjects, random_port, test_credential},
+    };
+    use mail_send::{
+        mail_builder::{MessageBuilder, headers::raw::Raw},
+        smtp::message::IntoMessage,
     };
-    use mail_send::{mail_builder::MessageBuilder, smtp::message::IntoMessage};
     use mailcrab::TestMailServerHandle;
-    use std::net::Ipv4Addr;
+    use std::{
+        net::Ipv4Addr,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
 
     impl Handler {
         pub(crate) async fn test_handler(
@@ -926,17 +2241,17 @@ mod test {
             records: Option<Vec<&'static str>>,
         ) -> Self {
             let config = HandlerConfig {
-                domain: "test".to_string(),
                 resolver: if let Some(records) = records {
                     DnsResolver::mock_custom_records("localhost", mailcrab_port, records)
                 } else {
                     DnsResolver::mock("localhost", mailcrab_port) // default DKIM + SPF records
                 },
-                environment: Environment::Development,
                 retry: RetryConfig {
                     delay: Duration::minutes(5),
                     max_automatic_retries: 1,
+                    greylist: Default::default(),
                 },
+                ..HandlerConfig::test_default()
             };
             Handler::new(
                 pool,
@@ -948,94 +2263,2458 @@ mod test {
         }
     }
 
-    #[sqlx::test(fixtures(
-        path = "../fixtures",
-        scripts(
-            "organizations",
-            "projects",
-            "org_domains",
-            "proj_domains",
-            "k8s_nodes"
+    #[sqlx::test]
+    async fn custom_worker_count(pool: PgPool) {
+        let handler = Handler::test_handler(pool.clone(), 1025, None).await;
+        assert_eq!(handler.workers.available_permits(), 4);
+
+        let config = HandlerConfig {
+            workers: 7,
+            ..(*handler.config).clone()
+        };
+        let handler = Handler::new(
+            pool,
+            Arc::new(config),
+            BusClient::new_from_env_var().unwrap(),
+            CancellationToken::new(),
         )
-    ))]
-    async fn test_handle_message(pool: PgPool) {
-        let mailcrab_port = random_port();
-        let TestMailServerHandle { token, rx: _rx } =
-            mailcrab::development_mail_server(Ipv4Addr::new(127, 0, 0, 1), mailcrab_port).await;
-        let _drop_guard = token.drop_guard();
+        .await;
+        assert_eq!(handler.workers.available_permits(), 7);
+    }
 
-        let message: mail_send::smtp::message::Message = MessageBuilder::new()
-            .from(("John Doe", "john@test-org-1-project-1.com"))
-            .to(vec![
-                ("Jane Doe", "jane@test-org-1-project-1.com"),
-                ("James Smith", "james@test.com"),
-            ])
-            .subject("Hi!")
-            .html_body("<h1>Hello, world!</h1>")
-            .text_body("Hello world!")
-            .into_message()
-            .unwrap();
+    #[tokio::test]
+    async fn intake_backpressure() {
+        let workers = Arc::new(Semaphore::new(2));
+        let consumed = Arc::new(AtomicUsize::new(0));
+        let consumed_clone = consumed.clone();
 
-        let credential_request = SmtpCredentialRequest {
-            username: "user".to_string(),
-            description: "Test SMTP credential description".to_string(),
-        };
+        // flood the bus with messages
+        let messages = (0..20).map(|_| BusEnvelope {
+            message: BusMessage::EmailReadyToSend(
+                MessageId::new_v4(),
+                "127.0.0.1".parse().unwrap(),
+                None,
+                MessagePriority::High,
+            ),
+            version: BUS_PROTOCOL_VERSION,
+            trace_context: Default::default(),
+        });
+        let mut bus_stream: BusStream =
+            Box::pin(futures::stream::iter(messages).inspect(move |_| {
+                consumed_clone.fetch_add(1, Ordering::SeqCst);
+            }));
 
-        let (org_id, project_id) = TestProjects::Org1Project1.get_ids();
+        let mut intake = IntakeQueue::new(2);
+        let mut busy = Vec::new();
+        for _ in 0..2 {
+            let (permit, _) =
+                Handler::next_message(workers.clone(), &mut bus_stream, &mut intake, &|_| true)
+                    .await
+                    .unwrap()
+                    .unwrap();
+            busy.push(permit);
+        }
 
-        let credential_repo = SmtpCredentialRepository::new(pool.clone());
-        let credential = credential_repo
-            .generate(
-                org_id,
-                project_id,
-                &credential_request,
-                crate::models::SYSTEM,
-            )
-            .await
-            .unwrap();
+        // while all workers are busy, messages are only taken from the bus to fill the queue
+        let next = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            Handler::next_message(workers.clone(), &mut bus_stream, &mut intake, &|_| true),
+        )
+        .await;
+        assert!(next.is_err());
+        assert_eq!(consumed.load(Ordering::SeqCst), 4);
+        busy.clear();
 
-        let message = NewMessage::from_builder_message(message, credential.id());
-        let handler = Handler::test_handler(pool.clone(), mailcrab_port, None).await;
+        // handle the remaining messages like the handler does, in the background
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let mut tasks = JoinSet::new();
+        while let Some((permit, _)) =
+            Handler::next_message(workers.clone(), &mut bus_stream, &mut intake, &|_| true)
+                .await
+                .unwrap()
+        {
+            let in_flight = in_flight.clone();
+            let max_in_flight = max_in_flight.clone();
+            tasks.spawn(async move {
+                let _p = permit;
+                let n = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(n, Ordering::SeqCst);
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        tasks.join_all().await;
 
-        let message_id = handler.message_repository.create(message, 1).await.unwrap();
-        let mut message = handler
-            .message_repository
-            .get_if_org_may_send(message_id)
-            .await
-            .unwrap();
-        handler.handle_message(&mut message).await.unwrap();
-        handler
-            .send_message(message, "127.0.0.1".parse().unwrap())
-            .await
-            .unwrap();
+        assert_eq!(consumed.load(Ordering::SeqCst), 20);
+        assert!(max_in_flight.load(Ordering::SeqCst) <= 2);
     }
 
-    #[sqlx::test(fixtures(
-        path = "../fixtures",
-        scripts(
-            "organizations",
-            "projects",
-            "org_domains",
-            "proj_domains",
-            "k8s_nodes"
-        )
+    #[tokio::test]
+    async fn intake_priority() {
+        let workers = Arc::new(Semaphore::new(1));
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        let mut bus_stream: BusStream = Box::pin(receiver);
+        let send = |priority| {
+            let id = MessageId::new_v4();
+            sender
+                .unbounded_send(BusEnvelope {
+                    message: BusMessage::EmailReadyToSend(
+                        id,
+                        "127.0.0.1".parse().unwrap(),
+                        None,
+                        priority,
+                    ),
+                    version: BUS_PROTOCOL_VERSION,
+                    trace_context: Default::default(),
+                })
+                .unwrap();
+            id
+        };
+        let message_id = |envelope: BusEnvelope| {
+            let BusMessage::EmailReadyToSend(id, ..) = envelope.message else {
+                panic!("unexpected bus message");
+            };
+            id
+        };
+
+        // the only worker is busy while a backlog of bulk messages arrives
+        let mut intake = IntakeQueue::new(10);
+        let busy = workers.clone().acquire_owned().await.unwrap();
+        let bulk: Vec<_> = (0..5).map(|_| send(MessagePriority::Low)).collect();
+        let normal = send(MessagePriority::Normal);
+        let transactional = send(MessagePriority::High);
+        let next = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            Handler::next_message(workers.clone(), &mut bus_stream, &mut intake, &|_| true),
+        )
+        .await;
+        assert!(next.is_err());
+        drop(busy);
+
+        // the transactional message overtakes the backlog, which is then handled in order
+        let mut handled = Vec::new();
+        for _ in 0..7 {
+            let (permit, envelope) =
+                Handler::next_message(workers.clone(), &mut bus_stream, &mut intake, &|_| true)
+                    .await
+                    .unwrap()
+                    .unwrap();
+            handled.push(message_id(envelope));
+            drop(permit);
+        }
+        let mut expected = vec![transactional, normal];
+        expected.extend(bulk);
+        assert_eq!(handled, expected);
+
+        // other bus messages are ignored
+        sender
+            .unbounded_send(BusEnvelope {
+                message: BusMessage::EmailDeliveryAttempted(
+                    MessageId::new_v4(),
+                    MessageStatus::Delivered,
+                ),
+                version: BUS_PROTOCOL_VERSION,
+                trace_context: Default::default(),
+            })
+            .unwrap();
+        drop(sender);
+        assert!(
+            Handler::next_message(workers.clone(), &mut bus_stream, &mut intake, &|_| true)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn intake_skips_other_outbound_ips() {
+        // no worker is available
+        let workers = Arc::new(Semaphore::new(0));
+        let ours: IpAddr = "127.0.0.1".parse().unwrap();
+
+        // messages for other nodes, followed by one for this node
+        let messages = (0..20)
+            .map(|_| "127.0.0.2".parse().unwrap())
+            .chain([ours])
+            .map(|outbound_ip| BusEnvelope {
+                message: BusMessage::EmailReadyToSend(
+                    MessageId::new_v4(),
+                    outbound_ip,
+                    None,
+                    MessagePriority::Normal,
+                ),
+                version: BUS_PROTOCOL_VERSION,
+                trace_context: Default::default(),
+            });
+        let mut bus_stream: BusStream = Box::pin(futures::stream::iter(messages));
+
+        // the messages for other nodes neither fill the queue nor wait for a worker
+        let mut intake = IntakeQueue::new(2);
+        let next = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            Handler::next_message(workers.clone(), &mut bus_stream, &mut intake, &|ip| {
+                ip == ours
+            }),
+        )
+        .await;
+        assert!(next.is_err());
+        assert!(intake.closed);
+
+        // only the message for this node is handed out once a worker is available
+        workers.add_permits(1);
+        let (_permit, envelope) =
+            Handler::next_message(workers.clone(), &mut bus_stream, &mut intake, &|ip| {
+                ip == ours
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            envelope.message,
+            BusMessage::EmailReadyToSend(_, outbound_ip, ..) if outbound_ip == ours
+        ));
+        assert!(intake.is_empty());
+    }
+
+    #[sqlx::test(fixtures(path = "../fixtures", scripts("k8s_nodes")))]
+    async fn restart_ended_bus_stream(pool: PgPool) {
+        // the first stream ends right away, the second one after being open for a while,
+        // and the third one stays open
+        let run = |handler: Handler, connects: Arc<AtomicUsize>| {
+            handler.run(move |_| {
+                let stream: BusStream<'_> = match connects.fetch_add(1, Ordering::SeqCst) {
+                    0 => Box::pin(futures::stream::empty()),
+                    1 => Box::pin(
+                        futures::stream::once(async {
+                            tokio::time::sleep(std::time::Duration::from_millis(300)).await
+                        })
+                        .filter_map(|()| async { None }),
+                    ),
+                    _ => Box::pin(futures::stream::pending()),
+                };
+                stream
+            })
+        };
+
+        let handler = Handler::test_handler(pool.clone(), 1025, None).await;
+        let shutdown = handler.shutdown.clone();
+        let connects = Arc::new(AtomicUsize::new(0));
+        let task = tokio::spawn(run(handler, connects.clone()));
+
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+        assert!(!shutdown.is_cancelled());
+        shutdown.cancel();
+        task.await.unwrap();
+
+        // a stream that stayed open long enough resets the number of restarts, so the end of
+        // the second stream does not exceed the single allowed restart
+        let mut handler = Handler::test_handler(pool.clone(), 1025, None).await;
+        handler.config = Arc::new(HandlerConfig {
+            bus_stream_restarts: 1,
+            bus_stream_healthy_after: std::time::Duration::from_millis(100),
+            ..(*handler.config).clone()
+        });
+        let shutdown = handler.shutdown.clone();
+        let connects = Arc::new(AtomicUsize::new(0));
+        let task = tokio::spawn(run(handler, connects.clone()));
+
+        // the restarts are delayed by at most a second each
+        tokio::time::sleep(std::time::Duration::from_millis(2500)).await;
+        assert_eq!(connects.load(Ordering::SeqCst), 3);
+        assert!(!shutdown.is_cancelled());
+        shutdown.cancel();
+        task.await.unwrap();
+
+        // without restarts, the end of the stream shuts down the handler
+        let mut handler = Handler::test_handler(pool, 1025, None).await;
+        handler.config = Arc::new(HandlerConfig {
+            bus_stream_restarts: 0,
+            ..(*handler.config).clone()
+        });
+        let shutdown = handler.shutdown.clone();
+        let connects = Arc::new(AtomicUsize::new(0));
+        tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            run(handler, connects.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+        assert!(shutdown.is_cancelled());
+    }
+
+    #[sqlx::test(fixtures(path = "../fixtures", scripts("k8s_nodes")))]
+    async fn retry_saving_node_ips(pool: PgPool) {
+        let mut handler = Handler::test_handler(pool.clone(), 1025, None).await;
+        let saved = BTreeSet::from(["127.0.0.1".parse().unwrap()]);
+        let new_ips = BTreeSet::from(["10.0.0.5".parse().unwrap()]);
+        let mut failures = 0;
+        assert!(
+            handler
+                .save_outbound_ips(saved.clone(), &mut failures)
+                .await
+        );
+
+        // the database is temporarily unavailable
+        sqlx::query("ALTER TABLE outbound_ips RENAME TO outbound_ips_unavailable")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(
+            !handler
+                .save_outbound_ips(new_ips.clone(), &mut failures)
+                .await
+        );
+        assert_eq!(failures, 1);
+        assert!(!handler.shutdown.is_cancelled());
+        assert_eq!(*handler.outbound_ips.borrow(), saved);
+
+        // the retry succeeds once the database is back
+        sqlx::query("ALTER TABLE outbound_ips_unavailable RENAME TO outbound_ips")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(
+            handler
+                .save_outbound_ips(new_ips.clone(), &mut failures)
+                .await
+        );
+        assert_eq!(failures, 0);
+        assert_eq!(*handler.outbound_ips.borrow(), new_ips);
+        assert!(!handler.shutdown.is_cancelled());
+
+        // repeated failures shut down the handler, unless configured otherwise
+        sqlx::query("ALTER TABLE outbound_ips RENAME TO outbound_ips_unavailable")
+            .execute(&pool)
+            .await
+            .unwrap();
+        handler.config = Arc::new(HandlerConfig {
+            node_ips_save_failure: NodeIpsSaveFailure::KeepLastSaved,
+            ..(*handler.config).clone()
+        });
+        for _ in 0..DEFAULT_NODE_IPS_SAVE_ATTEMPTS {
+            assert!(
+                !handler
+                    .save_outbound_ips(saved.clone(), &mut failures)
+                    .await
+            );
+        }
+        assert!(!handler.shutdown.is_cancelled());
+
+        handler.config = Arc::new(HandlerConfig {
+            node_ips_save_failure: NodeIpsSaveFailure::Shutdown { max_attempts: 2 },
+            ..(*handler.config).clone()
+        });
+        failures = 0;
+        assert!(
+            !handler
+                .save_outbound_ips(saved.clone(), &mut failures)
+                .await
+        );
+        assert!(!handler.shutdown.is_cancelled());
+        assert!(!handler.save_outbound_ips(saved, &mut failures).await);
+        assert!(handler.shutdown.is_cancelled());
+        assert_eq!(*handler.outbound_ips.borrow(), new_ips);
+    }
+
+    #[test]
+    fn outbound_ip_filter() {
+        let mut config = HandlerConfig {
+            environment: Environment::Production,
+            ..HandlerConfig::test_default()
+        };
+
+        let public = Ipv4Addr::new(198, 18, 0, 1);
+        let management = Ipv4Addr::new(100, 64, 12, 34);
+        let private = Ipv4Addr::new(10, 1, 2, 3);
+        let multicast = Ipv4Addr::new(224, 0, 0, 1);
+
+        // built-in rules only
+        assert!(config.is_usable_outbound_ip(public));
+        assert!(config.is_usable_outbound_ip(management));
+        assert!(!config.is_usable_outbound_ip(private));
+        assert!(!config.is_usable_outbound_ip(multicast));
+
+        // a public management network can be excluded
+        config.denied_outbound_cidrs = vec!["100.64.0.0/16".parse().unwrap()];
+        assert!(config.is_usable_outbound_ip(public));
+        assert!(!config.is_usable_outbound_ip(management));
+
+        // a private network can be included
+        config.allowed_outbound_cidrs = vec!["10.1.0.0/16".parse().unwrap()];
+        assert!(config.is_usable_outbound_ip(private));
+        assert!(!config.is_usable_outbound_ip(Ipv4Addr::new(10, 2, 0, 1)));
+        assert!(!config.is_usable_outbound_ip(multicast));
+
+        // denied ranges take precedence over allowed ranges
+        config
+            .denied_outbound_cidrs
+            .push("10.1.2.0/24".parse().unwrap());
+        assert!(!config.is_usable_outbound_ip(private));
+        assert!(config.is_usable_outbound_ip(Ipv4Addr::new(10, 1, 3, 1)));
+    }
+
+    #[test]
+    fn greylisting_detection() {
+        let response = |code, esc, message: &str| smtp_proto::Response {
+            code,
+            esc,
+            message: message.to_owned(),
+        };
+
+        assert!(Handler::is_greylisted(&response(
+            450,
+            [4, 2, 0],
+            "<info@example.com>: Recipient address rejected: Greylisted, see http://postgrey.schweikert.ch/"
+        )));
+        assert!(Handler::is_greylisted(&response(
+            451,
+            [4, 7, 1],
+            "Please try again later"
+        )));
+        assert!(Handler::is_greylisted(&response(
+            421,
+            [4, 7, 0],
+            "Temporarily deferred due to graylisting"
+        )));
+
+        // other transient failures
+        assert!(!Handler::is_greylisted(&response(
+            452,
+            [4, 2, 2],
+            "Mailbox full, try again later"
+        )));
+        assert!(!Handler::is_greylisted(&response(
+            451,
+            [4, 3, 0],
+            "Temporary local problem"
+        )));
+
+        // permanent failures are never greylisting
+        assert!(!Handler::is_greylisted(&response(
+            550,
+            [5, 7, 1],
+            "Greylisting is not the issue, you are blocked"
+        )));
+    }
+
+    #[test]
+    fn bounce_classification() {
+        let response = |code, esc, message: &str| smtp_proto::Response {
+            code,
+            esc,
+            message: message.to_owned(),
+        };
+
+        // soft bounces
+        assert!(Handler::is_soft_bounce(&response(
+            452,
+            [4, 2, 2],
+            "Mailbox full, try again later"
+        )));
+        assert!(Handler::is_soft_bounce(&response(
+            421,
+            [4, 7, 0],
+            "Temporary System Problem"
+        )));
+        assert!(Handler::is_soft_bounce(&response(
+            552,
+            [5, 2, 2],
+            "The email account that you tried to reach is over quota"
+        )));
+        assert!(Handler::is_soft_bounce(&response(
+            552,
+            [0, 0, 0],
+            "Requested mail action aborted: exceeded storage allocation"
+        )));
+        assert!(Handler::is_soft_bounce(&response(
+            554,
+            [5, 4, 7],
+            "Delivery time expired"
+        )));
+
+        // hard bounces
+        assert!(!Handler::is_soft_bounce(&response(
+            550,
+            [5, 1, 1],
+            "The email account that you tried to reach does not exist"
+        )));
+        assert!(!Handler::is_soft_bounce(&response(
+            550,
+            [5, 2, 1],
+            "The email account that you tried to reach is disabled"
+        )));
+        assert!(!Handler::is_soft_bounce(&response(
+            553,
+            [5, 1, 3],
+            "Invalid recipient address syntax"
+        )));
+        assert!(!Handler::is_soft_bounce(&response(
+            550,
+            [0, 0, 0],
+            "Requested action not taken: mailbox unavailable"
+        )));
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_handle_message(pool: PgPool) {
+        let mailcrab_port = random_port();
+        let TestMailServerHandle { token, rx: _rx } =
+            mailcrab::development_mail_server(Ipv4Addr::new(127, 0, 0, 1), mailcrab_port).await;
+        let _drop_guard = token.drop_guard();
+
+        let message: mail_send::smtp::message::Message = MessageBuilder::new()
+            .from(("John Doe", "john@test-org-1-project-1.com"))
+            .to(vec![
+                ("Jane Doe", "jane@test-org-1-project-1.com"),
+                ("James Smith", "james@test.com"),
+            ])
+            .subject("Hi!")
+            .html_body("<h1>Hello, world!</h1>")
+            .text_body("Hello world!")
+            .into_message()
+            .unwrap();
+
+        let credential = test_credential(&pool).await;
+
+        let message = NewMessage::from_builder_message(message, credential.id());
+        let handler = Handler::test_handler(pool.clone(), mailcrab_port, None).await;
+
+        let message_id = handler
+            .message_repository
+            .create(message, 1)
+            .await
+            .unwrap()
+            .into_inner();
+        let mut message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+        handler.handle_message(&mut message).await.unwrap();
+        handler
+            .send_message(message, "127.0.0.1".parse().unwrap())
+            .await
+            .unwrap();
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_skip_delivered_message(pool: PgPool) {
+        let mailcrab_port = random_port();
+        let TestMailServerHandle { token, rx: _rx } =
+            mailcrab::development_mail_server(Ipv4Addr::new(127, 0, 0, 1), mailcrab_port).await;
+        let _drop_guard = token.drop_guard();
+
+        let message: mail_send::smtp::message::Message = MessageBuilder::new()
+            .from(("John Doe", "john@test-org-1-project-1.com"))
+            .to(vec![("James Smith", "james@test.com")])
+            .subject("Hi!")
+            .text_body("Hello world!")
+            .into_message()
+            .unwrap();
+
+        let credential = test_credential(&pool).await;
+
+        let mut handler = Handler::test_handler(pool.clone(), mailcrab_port, None).await;
+        let message_id = handler
+            .message_repository
+            .create(
+                NewMessage::from_builder_message(message, credential.id()),
+                1,
+            )
+            .await
+            .unwrap()
+            .into_inner();
+        let mut message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+        assert!(handler.handle_message(&mut message).await.unwrap());
+        handler
+            .send_message(message, "127.0.0.1".parse().unwrap())
+            .await
+            .unwrap();
+
+        // the delivered message is processed again, e.g., due to a racing retry
+        let mut message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+        assert_eq!(message.status, MessageStatus::Delivered);
+        let raw_data = message.raw_data.clone();
+
+        assert!(!handler.handle_message(&mut message).await.unwrap());
+        assert_eq!(message.raw_data, raw_data);
+        let stored = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+        assert_eq!(stored.status, MessageStatus::Delivered);
+
+        handler.config = Arc::new(HandlerConfig {
+            delivered_reprocessing: DeliveredReprocessing::Error,
+            ..(*handler.config).clone()
+        });
+        assert!(matches!(
+            handler.handle_message(&mut message).await,
+            Err(HandlerError::IllegalMessageState(
+                MessageStatus::Delivered,
+                id
+            )) if id == message_id
+        ));
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    #[tracing_test::traced_test]
+    async fn test_delivery_correlation_id(pool: PgPool) {
+        let mailcrab_port = random_port();
+        let TestMailServerHandle { token, rx: _rx } =
+            mailcrab::development_mail_server(Ipv4Addr::new(127, 0, 0, 1), mailcrab_port).await;
+        let _drop_guard = token.drop_guard();
+
+        let org_id = TestProjects::Org1Project1.org_id();
+        let credential = test_credential(&pool).await;
+
+        let message: mail_send::smtp::message::Message = MessageBuilder::new()
+            .from(("John Doe", "john@test-org-1-project-1.com"))
+            .to(vec![("Jane Doe", "jane@test-org-1-project-1.com")])
+            .subject("Hi!")
+            .text_body("Hello world!")
+            .into_message()
+            .unwrap();
+        let message = NewMessage::from_builder_message(message, credential.id());
+
+        let handler = Handler::test_handler(pool.clone(), mailcrab_port, None).await;
+        let message_id = handler
+            .message_repository
+            .create(message, 1)
+            .await
+            .unwrap()
+            .into_inner();
+
+        // as if the message was submitted via the API
+        sqlx::query!(
+            "UPDATE messages SET correlation_id = 'order-1234' WHERE id = $1",
+            *message_id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let BusMessage::EmailReadyToSend(id, _, correlation_id, _) = handler
+            .message_repository
+            .get_ready_to_send(message_id)
+            .await
+            .unwrap()
+        else {
+            panic!("expected a message that is ready to send");
+        };
+        assert_eq!(id, message_id);
+        assert_eq!(correlation_id.as_deref(), Some("order-1234"));
+
+        let permit = handler.workers.clone().acquire_owned().await.unwrap();
+        handler.handle_ready_to_send(
+            id,
+            "127.0.0.1".parse().unwrap(),
+            correlation_id,
+            &TraceContext::new(),
+            permit,
+        );
+
+        // wait for the delivery in the background
+        for _ in 0..50 {
+            let message = handler
+                .message_repository
+                .find_by_id(org_id, message_id)
+                .await
+                .unwrap();
+            if message.status() == &MessageStatus::Delivered {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+
+        assert!(logs_contain(r#"deliver_message{message_id="#));
+        assert!(logs_contain(r#"correlation_id="order-1234""#));
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_ip_warmup_limit(pool: PgPool) {
+        let mailcrab_port = random_port();
+        let TestMailServerHandle { token, rx: _rx } =
+            mailcrab::development_mail_server(Ipv4Addr::new(127, 0, 0, 1), mailcrab_port).await;
+        let _drop_guard = token.drop_guard();
+
+        let credential = test_credential(&pool).await;
+
+        // the outbound IPs of the fixture were just added, so they are on the first warm-up day
+        let mut handler = Handler::test_handler(pool.clone(), mailcrab_port, None).await;
+        handler.config = Arc::new(HandlerConfig {
+            ip_warmup: WarmupSchedule::new(vec![2, 10]),
+            ..(*handler.config).clone()
+        });
+        let outbound_ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let org_id: OrganizationId = "44729d9f-a7dc-4226-b412-36a7537f5176".parse().unwrap(); // test org 1
+        let quota_before = handler
+            .organization_repository
+            .remaining_quota(org_id)
+            .await
+            .unwrap();
+
+        let mut message_ids = Vec::new();
+        for _ in 0..3 {
+            let message: mail_send::smtp::message::Message = MessageBuilder::new()
+                .from(("John Doe", "john@test-org-1-project-1.com"))
+                .to(vec![("Jane Doe", "jane@test-org-1-project-1.com")])
+                .subject("Hi!")
+                .text_body("Hello world!")
+                .into_message()
+                .unwrap();
+            let message_id = handler
+                .message_repository
+                .create(
+                    NewMessage::from_builder_message(message, credential.id()),
+                    1,
+                )
+                .await
+                .unwrap()
+                .into_inner();
+
+            let permit = handler.workers.clone().acquire_owned().await.unwrap();
+            handler.handle_ready_to_send(
+                message_id,
+                outbound_ip,
+                None,
+                &TraceContext::new(),
+                permit,
+            );
+            message_ids.push(message_id);
+        }
+
+        // wait for the deliveries in the background
+        let mut messages = Vec::new();
+        for _ in 0..50 {
+            messages.clear();
+            for message_id in &message_ids {
+                messages.push(
+                    handler
+                        .message_repository
+                        .get_if_org_may_send(*message_id)
+                        .await
+                        .unwrap(),
+                );
+            }
+            if messages.iter().all(|message| {
+                matches!(
+                    message.status,
+                    MessageStatus::Delivered | MessageStatus::Reattempt
+                )
+            }) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+
+        let delivered = messages
+            .iter()
+            .filter(|message| message.status == MessageStatus::Delivered)
+            .count();
+        assert_eq!(delivered, 2);
+
+        // the message over the limit is retried in the next hour, without using an attempt
+        let deferred = messages
+            .iter()
+            .find(|message| message.status == MessageStatus::Reattempt)
+            .unwrap();
+        assert_eq!(deferred.attempts, 0);
+        assert!(deferred.retry_after.unwrap() > Utc::now());
+
+        let sent = sqlx::query_scalar!(
+            r#"
+            SELECT sum(sent)::int AS "sent!" FROM outbound_ip_hourly_usage u
+            JOIN outbound_ips ON outbound_ips.id = u.outbound_ip_id
+            WHERE outbound_ips.ip = '127.0.0.1'
+            "#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(sent, 2);
+
+        // all three messages are counted towards the quota, including the deferred one
+        let quota_after = handler
+            .organization_repository
+            .remaining_quota(org_id)
+            .await
+            .unwrap();
+        assert_eq!(quota_after, quota_before - 3);
+
+        // once the limit no longer applies, the deferred message goes out without being
+        // counted towards the quota a second time
+        let deferred_id = deferred.id();
+        handler.config = Arc::new(HandlerConfig {
+            ip_warmup: Default::default(),
+            ..(*handler.config).clone()
+        });
+        let permit = handler.workers.clone().acquire_owned().await.unwrap();
+        handler.handle_ready_to_send(deferred_id, outbound_ip, None, &TraceContext::new(), permit);
+
+        let mut status = MessageStatus::Reattempt;
+        for _ in 0..50 {
+            status = handler
+                .message_repository
+                .get_if_org_may_send(deferred_id)
+                .await
+                .unwrap()
+                .status;
+            if status == MessageStatus::Delivered {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert_eq!(status, MessageStatus::Delivered);
+        let quota_after = handler
+            .organization_repository
+            .remaining_quota(org_id)
+            .await
+            .unwrap();
+        assert_eq!(quota_after, quota_before - 3);
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_spam_scoring(pool: PgPool) {
+        let credential = test_credential(&pool).await;
+
+        let mut handler = Handler::test_handler(pool, 1025, None).await;
+        handler.config = Arc::new(HandlerConfig {
+            spam_scorer: SpamScorer::new(
+                spam::test::mock_scorer().await,
+                15.0,
+                std::time::Duration::from_secs(2),
+            ),
+            ..(*handler.config).clone()
+        });
+
+        let create_message = async |body: &str| {
+            let message: mail_send::smtp::message::Message = MessageBuilder::new()
+                .from(("John Doe", "john@test-org-1-project-1.com"))
+                .to(vec![("James Smith", "james@test.com")])
+                .subject("Hi!")
+                .text_body(body)
+                .into_message()
+                .unwrap();
+            let message_id = handler
+                .message_repository
+                .create(
+                    NewMessage::from_builder_message(message, credential.id()),
+                    1,
+                )
+                .await
+                .unwrap()
+                .into_inner();
+            handler
+                .message_repository
+                .get_if_org_may_send(message_id)
+                .await
+                .unwrap()
+        };
+
+        // a low-scoring message passes
+        let mut ham = create_message("Hello world!").await;
+        handler.handle_message(&mut ham).await.unwrap();
+        assert_eq!(ham.status, MessageStatus::Accepted);
+        assert_eq!(ham.hold_reason, None);
+
+        // a high-scoring message is held
+        let mut spam = create_message("Get FREE MONEY now!").await;
+        let result = handler.handle_message(&mut spam).await;
+        assert!(matches!(
+            result,
+            Err(HandlerError::MessageNotAccepted(MessageStatus::Held, _))
+        ));
+        assert_eq!(spam.status, MessageStatus::Held);
+        assert_eq!(spam.hold_reason, Some(HoldReason::Spam));
+        assert_eq!(
+            spam.reason.as_deref(),
+            Some("spam score 22.5 exceeds the threshold of 15.0")
+        );
+        assert!(spam.retry_after.is_none());
+
+        // retrying the held message on user request releases it
+        let mut spam = handler
+            .message_repository
+            .get_if_org_may_send(spam.id())
+            .await
+            .unwrap();
+        handler.handle_message(&mut spam).await.unwrap();
+        assert_eq!(spam.status, MessageStatus::Accepted);
+        assert_eq!(spam.hold_reason, None);
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_max_outbound_size(pool: PgPool) {
+        let credential = test_credential(&pool).await;
+
+        let message: mail_send::smtp::message::Message = MessageBuilder::new()
+            .from(("John Doe", "john@test-org-1-project-1.com"))
+            .to(vec![("James Smith", "james@test.com")])
+            .subject("Hi!")
+            .text_body("Hello world!")
+            .into_message()
+            .unwrap();
+
+        // nobody listens on this port, so any delivery attempt would be logged as a failure
+        let mut handler = Handler::test_handler(pool, random_port(), None).await;
+        let message_id = handler
+            .message_repository
+            .create(
+                NewMessage::from_builder_message(message, credential.id()),
+                1,
+            )
+            .await
+            .unwrap()
+            .into_inner();
+        let mut message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+
+        // the message itself fits, but not including the DKIM header added by Remails
+        let size_before_signing = message.raw_data.len();
+        handler.config = Arc::new(HandlerConfig {
+            max_outbound_size: size_before_signing + 10,
+            ..(*handler.config).clone()
+        });
+
+        handler.handle_message(&mut message).await.unwrap();
+        assert!(message.raw_data.len() > size_before_signing + 10);
+        handler
+            .send_message(message, "127.0.0.1".parse().unwrap())
+            .await
+            .unwrap();
+
+        let message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+        assert_eq!(message.status, MessageStatus::Failed);
+        assert!(
+            message
+                .reason
+                .unwrap()
+                .contains("exceeds the maximum outbound size")
+        );
+        assert!(message.retry_after.is_none());
+        // no delivery was attempted
+        assert!(message.delivery_details.is_empty());
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_unparseable_message(pool: PgPool) {
+        let credential = test_credential(&pool).await;
+
+        let mut message = NewMessage::new(
+            credential.id(),
+            "john@test-org-1-project-1.com".parse().unwrap(),
+        );
+        message.recipients = vec!["james@test.com".parse().unwrap()];
+        message.raw_data =
+            b"\r\n--boundary\r\nContent-Type: text/plain\r\n\r\nHello world!\r\n--boundary"
+                .to_vec();
+
+        let handler = Handler::test_handler(pool, random_port(), None).await;
+        let message_id = handler
+            .message_repository
+            .create(message, 1)
+            .await
+            .unwrap()
+            .into_inner();
+        let mut message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+
+        // the message is still accepted and signed
+        handler.handle_message(&mut message).await.unwrap();
+        assert_eq!(message.status, MessageStatus::Accepted);
+        assert!(message.prepended_headers().starts_with(b"DKIM-Signature: "));
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_send_large_message(pool: PgPool) {
+        let mailcrab_port = random_port();
+        let TestMailServerHandle { token, mut rx } =
+            mailcrab::development_mail_server(Ipv4Addr::new(127, 0, 0, 1), mailcrab_port).await;
+        let _drop_guard = token.drop_guard();
+
+        let credential = test_credential(&pool).await;
+
+        // spans many chunks when streamed from the database
+        let message: mail_send::smtp::message::Message = MessageBuilder::new()
+            .from(("John Doe", "john@test-org-1-project-1.com"))
+            .to(vec![("James Smith", "james@test.com")])
+            .subject("Hi!")
+            .text_body(".All work and no play makes Jack a dull boy.\r\n".repeat(100_000))
+            .into_message()
+            .unwrap();
+
+        let handler = Handler::test_handler(pool, mailcrab_port, None).await;
+        let message_id = handler
+            .message_repository
+            .create(
+                NewMessage::from_builder_message(message, credential.id()),
+                1,
+            )
+            .await
+            .unwrap()
+            .into_inner();
+        let mut message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+        assert!(message.raw_data.len() > 4_000_000);
+
+        handler.handle_message(&mut message).await.unwrap();
+        handler
+            .send_message(message, "127.0.0.1".parse().unwrap())
+            .await
+            .unwrap();
+
+        let recv = rx.recv().await.unwrap();
+        assert_eq!(recv.envelope_from.as_str(), "john@test-org-1-project-1.com");
+
+        let message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+        assert_eq!(message.status, MessageStatus::Delivered);
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_connect_timeout(pool: PgPool) {
+        // accepts TCP connections in the backlog, but never sends an SMTP greeting
+        let port = random_port();
+        let _listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .await
+            .unwrap();
+
+        let mut handler = Handler::test_handler(pool, port, None).await;
+        let connect_timeout = std::time::Duration::from_millis(500);
+        handler.config = Arc::new(HandlerConfig {
+            timeouts: DeliveryTimeouts {
+                connect: connect_timeout,
+                ..Default::default()
+            },
+            ..(*handler.config).clone()
+        });
+
+        let message = smtp::message::Message {
+            mail_from: "john@test-org-1-project-1.com".into(),
+            rcpt_to: vec!["jane@test.com".into()],
+            body: Default::default(),
+        };
+        let body = OutboundBody::buffered(b"Subject: Hi!\r\n\r\nHello world!\r\n");
+
+        let mut connection_log = ConnectionLog::default();
+        let start = std::time::Instant::now();
+        let result = handler
+            .send_single_upstream(
+                Protection::Plaintext,
+                &mut connection_log,
+                &mut Transcript::default(),
+                "test.com",
+                message,
+                false,
+                &body,
+                &"localhost".to_owned(),
+                port,
+                None,
+                "127.0.0.1".parse().unwrap(),
+            )
+            .await;
+        let elapsed = start.elapsed();
+
+        assert!(matches!(result, Err(SendError::TemporaryFailure)));
+        assert!(elapsed >= connect_timeout);
+        assert!(elapsed < connect_timeout * 2, "connecting took {elapsed:?}");
+    }
+
+    /// Accepts connections on a random port and forwards them to the mail server after a delay
+    async fn slow_receiver(upstream_port: u16, delay: std::time::Duration) -> u16 {
+        let port = random_port();
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .await
+            .unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((mut inbound, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    let mut upstream =
+                        tokio::net::TcpStream::connect((Ipv4Addr::LOCALHOST, upstream_port))
+                            .await
+                            .unwrap();
+                    let _ = tokio::io::copy_bidirectional(&mut inbound, &mut upstream).await;
+                });
+            }
+        });
+
+        port
+    }
+
+    /// Accepts connections on a random port and rejects every recipient with the given reply,
+    /// counting the number of `RCPT TO` commands it receives
+    ///
+    /// Advertises `SMTPUTF8`, unlike [`capturing_receiver`].
+    async fn rejecting_receiver(reply: &'static str, rcpt_count: Arc<AtomicUsize>) -> u16 {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let port = random_port();
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .await
+            .unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let rcpt_count = rcpt_count.clone();
+                tokio::spawn(async move {
+                    let (read, mut write) = stream.into_split();
+                    let mut lines = BufReader::new(read).lines();
+                    write.write_all(b"220 localhost ESMTP\r\n").await?;
+                    while let Some(line) = lines.next_line().await? {
+                        let command = line.to_ascii_uppercase();
+                        let response = if command.starts_with("RCPT") {
+                            rcpt_count.fetch_add(1, Ordering::SeqCst);
+                            reply
+                        } else if command.starts_with("EHLO") {
+                            "250-localhost\r\n250 SMTPUTF8"
+                        } else if command.starts_with("QUIT") {
+                            write.write_all(b"221 2.0.0 Bye\r\n").await?;
+                            break;
+                        } else {
+                            "250 OK"
+                        };
+                        write
+                            .write_all(format!("{response}\r\n").as_bytes())
+                            .await?;
+                    }
+                    Ok::<_, std::io::Error>(())
+                });
+            }
+        });
+
+        port
+    }
+
+    /// Accepts every message on a random port, and sends the envelope sender and data of each
+    /// message to the channel
+    async fn capturing_receiver() -> (u16, tokio::sync::mpsc::UnboundedReceiver<(String, String)>) {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let port = random_port();
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .await
+            .unwrap();
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    let (read, mut write) = stream.into_split();
+                    let mut lines = BufReader::new(read).lines();
+                    let mut mail_from = String::new();
+                    let mut data: Option<String> = None;
+                    write.write_all(b"220 localhost ESMTP\r\n").await?;
+                    while let Some(line) = lines.next_line().await? {
+                        let response = if let Some(message) = data.as_mut() {
+                            if line != "." {
+                                message.push_str(&line);
+                                message.push_str("\r\n");
+                                continue;
+                            }
+                            tx.send((
+                                std::mem::take(&mut mail_from),
+                                data.take().unwrap_or_default(),
+                            ))
+                            .ok();
+                            "250 OK"
+                        } else {
+                            let command = line.to_ascii_uppercase();
+                            if command.starts_with("MAIL FROM:") {
+                                mail_from =
+                                    line.split(['<', '>']).nth(1).unwrap_or_default().to_owned();
+                                "250 OK"
+                            } else if command.starts_with("DATA") {
+                                data = Some(String::new());
+                                "354 Go ahead"
+                            } else if command.starts_with("QUIT") {
+                                write.write_all(b"221 2.0.0 Bye\r\n").await?;
+                                break;
+                            } else {
+                                "250 OK"
+                            }
+                        };
+                        write
+                            .write_all(format!("{response}\r\n").as_bytes())
+                            .await?;
+                    }
+                    Ok::<_, std::io::Error>(())
+                });
+            }
+        });
+
+        (port, rx)
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_aligned_return_path(pool: PgPool) {
+        let (port, mut rx) = capturing_receiver().await;
+
+        let project_id = TestProjects::Org1Project1.project_id();
+        let credential = test_credential(&pool).await;
+        let handler = Handler::test_handler(pool.clone(), port, None).await;
+
+        let mut send = async |verp: bool| {
+            sqlx::query!(
+                "UPDATE projects SET verp = $2 WHERE id = $1",
+                *project_id,
+                verp,
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+
+            // the client sets a Return-Path of its own
+            let message: mail_send::smtp::message::Message = MessageBuilder::new()
+                .from(("John Doe", "john@test-org-1-project-1.com"))
+                .to(vec![("James Smith", "james@test.com")])
+                .header(
+                    "Return-Path",
+                    Raw::new("<bounces@test-org-1-project-1.com>"),
+                )
+                .subject("Hi!")
+                .text_body("Hello world!")
+                .into_message()
+                .unwrap();
+            let message_id = handler
+                .message_repository
+                .create(
+                    NewMessage::from_builder_message(message, credential.id()),
+                    1,
+                )
+                .await
+                .unwrap()
+                .into_inner();
+            let mut message = handler
+                .message_repository
+                .get_if_org_may_send(message_id)
+                .await
+                .unwrap();
+            handler.handle_message(&mut message).await.unwrap();
+            handler
+                .send_message(message, "127.0.0.1".parse().unwrap())
+                .await
+                .unwrap();
+
+            let (mail_from, data) = rx.recv().await.unwrap();
+            (message_id, mail_from, data)
+        };
+
+        // the Return-Path of the client is replaced by the aligned envelope sender,
+        // which precedes the DKIM signature
+        let (_, mail_from, data) = send(false).await;
+        assert_eq!(mail_from, "john@test-org-1-project-1.com");
+        assert!(
+            data.starts_with("Return-Path: <john@test-org-1-project-1.com>\r\nDKIM-Signature: ")
+        );
+        assert_eq!(data.to_ascii_lowercase().matches("return-path:").count(), 1);
+
+        // with VERP, the envelope sender and Return-Path are the VERP address of the recipient
+        let (message_id, mail_from, data) = send(true).await;
+        let verp = VerpAddress::new(message_id, &"james@test.com".parse().unwrap())
+            .to_address("test-org-1-project-1.com");
+        assert_eq!(mail_from, verp);
+        assert!(data.starts_with(&format!("Return-Path: <{verp}>\r\n")));
+        assert_eq!(data.to_ascii_lowercase().matches("return-path:").count(), 1);
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_suppress_after_hard_bounces(pool: PgPool) {
+        let org_id = TestProjects::Org1Project1.org_id();
+        let credential = test_credential(&pool).await;
+
+        let recipient: EmailAddress = "gone@test.com".parse().unwrap();
+        let rcpt_count = Arc::new(AtomicUsize::new(0));
+        let receiver_port = rejecting_receiver("550 5.1.1 No such user", rcpt_count.clone()).await;
+        let handler = Handler::test_handler(pool.clone(), receiver_port, None).await;
+
+        let send = async || {
+            let message: mail_send::smtp::message::Message = MessageBuilder::new()
+                .from(("John Doe", "john@test-org-1-project-1.com"))
+                .to(vec![("Gone", "gone@test.com")])
+                .subject("Hi!")
+                .text_body("Hello world!")
+                .into_message()
+                .unwrap();
+            let message = NewMessage::from_builder_message(message, credential.id());
+
+            let message_id = handler
+                .message_repository
+                .create(message, 1)
+                .await
+                .unwrap()
+                .into_inner();
+            let mut message = handler
+                .message_repository
+                .get_if_org_may_send(message_id)
+                .await
+                .unwrap();
+            handler.handle_message(&mut message).await.unwrap();
+            handler
+                .send_message(message, "127.0.0.1".parse().unwrap())
+                .await
+                .unwrap();
+
+            handler
+                .message_repository
+                .get_if_org_may_send(message_id)
+                .await
+                .unwrap()
+        };
+
+        // every hard bounce counts towards the suppression threshold
+        for _ in 0..SuppressedRepository::MAX_ATTEMPTS {
+            let message = send().await;
+            assert_eq!(message.status, MessageStatus::Failed);
+            let details = &message.delivery_details[&recipient];
+            assert!(matches!(details.status, DeliveryStatus::Failed));
+            assert_eq!(details.bounce, Some(Bounce::Hard));
+        }
+        assert!(
+            handler
+                .suppressed_repository
+                .should_suppress(&recipient, org_id)
+                .await
+                .unwrap()
+        );
+
+        // once suppressed, the recipient is skipped without contacting the remote
+        let attempts_before = rcpt_count.load(Ordering::SeqCst);
+        let message = send().await;
+        assert_eq!(message.status, MessageStatus::Failed);
+        let details = &message.delivery_details[&recipient];
+        assert!(matches!(details.status, DeliveryStatus::Suppressed));
+        assert_eq!(rcpt_count.load(Ordering::SeqCst), attempts_before);
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_suppress_plus_addressed_recipient(pool: PgPool) {
+        let org_id = TestProjects::Org1Project1.org_id();
+        let credential = test_credential(&pool).await;
+
+        let rcpt_count = Arc::new(AtomicUsize::new(0));
+        let receiver_port = rejecting_receiver("550 5.1.1 No such user", rcpt_count.clone()).await;
+        let mut handler = Handler::test_handler(pool.clone(), receiver_port, None).await;
+
+        // the mailbox itself is suppressed
+        let mailbox: EmailAddress = "gone@test.com".parse().unwrap();
+        handler
+            .suppressed_repository
+            .insert_suppression(&mailbox, org_id, Utc::now() + Duration::days(1), 0)
+            .await
+            .unwrap();
+
+        let recipient: EmailAddress = "gone+newsletter@test.com".parse().unwrap();
+        let send = async |handler: &Handler| {
+            let message: mail_send::smtp::message::Message = MessageBuilder::new()
+                .from(("John Doe", "john@test-org-1-project-1.com"))
+                .to(vec![("Gone", "gone+newsletter@test.com")])
+                .subject("Hi!")
+                .text_body("Hello world!")
+                .into_message()
+                .unwrap();
+            let message = NewMessage::from_builder_message(message, credential.id());
+
+            let message_id = handler
+                .message_repository
+                .create(message, 1)
+                .await
+                .unwrap()
+                .into_inner();
+            let mut message = handler
+                .message_repository
+                .get_if_org_may_send(message_id)
+                .await
+                .unwrap();
+            handler.handle_message(&mut message).await.unwrap();
+            handler
+                .send_message(message, "127.0.0.1".parse().unwrap())
+                .await
+                .unwrap();
+
+            handler
+                .message_repository
+                .get_if_org_may_send(message_id)
+                .await
+                .unwrap()
+        };
+
+        // without normalization, the plus-addressed recipient is a different address
+        let message = send(&handler).await;
+        let details = &message.delivery_details[&recipient];
+        assert!(matches!(details.status, DeliveryStatus::Failed));
+        assert_eq!(rcpt_count.load(Ordering::SeqCst), 1);
+
+        // with normalization, it shares the suppression of the mailbox
+        handler.config = Arc::new(HandlerConfig {
+            plus_addressing: PlusAddressing::new(["test.com"]),
+            ..(*handler.config).clone()
+        });
+        let message = send(&handler).await;
+        assert_eq!(message.recipients, vec![recipient.clone()]);
+        let details = &message.delivery_details[&recipient];
+        assert!(matches!(details.status, DeliveryStatus::Suppressed));
+        assert_eq!(rcpt_count.load(Ordering::SeqCst), 1);
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_smtp_transcript(pool: PgPool) {
+        let credential = test_credential(&pool).await;
+
+        let recipient: EmailAddress = "gone@test.com".parse().unwrap();
+        let receiver_port =
+            rejecting_receiver("550 5.1.1 No such user", Arc::new(AtomicUsize::new(0))).await;
+        let handler = Handler::test_handler(pool.clone(), receiver_port, None).await;
+
+        let message: mail_send::smtp::message::Message = MessageBuilder::new()
+            .from(("John Doe", "john@test-org-1-project-1.com"))
+            .to(vec![("Gone", "gone@test.com")])
+            .subject("Hi!")
+            .text_body("Hello world!")
+            .into_message()
+            .unwrap();
+        let message = NewMessage::from_builder_message(message, credential.id());
+        let message_id = handler
+            .message_repository
+            .create(message, 1)
+            .await
+            .unwrap()
+            .into_inner();
+        let mut message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+        handler.handle_message(&mut message).await.unwrap();
+        handler
+            .send_message(message, "127.0.0.1".parse().unwrap())
+            .await
+            .unwrap();
+
+        let message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+        let transcript =
+            serde_json::to_value(&message.delivery_details[&recipient].transcript).unwrap();
+        let lines: Vec<&str> = transcript["lines"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|line| line.as_str().unwrap())
+            .collect();
+
+        assert!(lines.contains(&"S: 220 localhost ESMTP"), "{lines:?}");
+        // the envelope and headers are ASCII only, so SMTPUTF8 is not needed
+        assert!(
+            lines.contains(&"C: MAIL FROM:<john@test-org-1-project-1.com>"),
+            "{lines:?}"
+        );
+        assert!(lines.contains(&"C: RCPT TO:<gone@test.com>"), "{lines:?}");
+        assert!(lines.contains(&"S: 550 5.1.1 No such user"), "{lines:?}");
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_smtputf8_mail_from(pool: PgPool) {
+        let credential = test_credential(&pool).await;
+
+        let recipient: EmailAddress = "jürgen@test.com".parse().unwrap();
+        let receiver_port =
+            rejecting_receiver("550 5.1.1 No such user", Arc::new(AtomicUsize::new(0))).await;
+        let handler = Handler::test_handler(pool.clone(), receiver_port, None).await;
+
+        let message: mail_send::smtp::message::Message = MessageBuilder::new()
+            .from(("John Doe", "john@test-org-1-project-1.com"))
+            .to(vec![("Jürgen", "jürgen@test.com")])
+            .subject("Hi!")
+            .text_body("Hello world!")
+            .into_message()
+            .unwrap();
+        let message = NewMessage::from_builder_message(message, credential.id());
+        let message_id = handler
+            .message_repository
+            .create(message, 1)
+            .await
+            .unwrap()
+            .into_inner();
+        let mut message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+        handler.handle_message(&mut message).await.unwrap();
+        handler
+            .send_message(message, "127.0.0.1".parse().unwrap())
+            .await
+            .unwrap();
+
+        let message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+        let transcript =
+            serde_json::to_value(&message.delivery_details[&recipient].transcript).unwrap();
+        let lines: Vec<&str> = transcript["lines"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|line| line.as_str().unwrap())
+            .collect();
+
+        // the internationalized recipient requires SMTPUTF8
+        assert!(
+            lines.contains(&"C: MAIL FROM:<john@test-org-1-project-1.com> SMTPUTF8"),
+            "{lines:?}"
+        );
+        assert!(lines.contains(&"C: RCPT TO:<jürgen@test.com>"), "{lines:?}");
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_smtputf8_not_supported(pool: PgPool) {
+        let credential = test_credential(&pool).await;
+
+        let recipient: EmailAddress = "jürgen@test.com".parse().unwrap();
+        // does not advertise SMTPUTF8 in its EHLO response
+        let (receiver_port, mut received) = capturing_receiver().await;
+        let handler = Handler::test_handler(pool.clone(), receiver_port, None).await;
+
+        let message: mail_send::smtp::message::Message = MessageBuilder::new()
+            .from(("John Doe", "john@test-org-1-project-1.com"))
+            .to(vec![("Jürgen", "jürgen@test.com")])
+            .subject("Hi!")
+            .text_body("Hello world!")
+            .into_message()
+            .unwrap();
+        let message = NewMessage::from_builder_message(message, credential.id());
+        let message_id = handler
+            .message_repository
+            .create(message, 1)
+            .await
+            .unwrap()
+            .into_inner();
+        let mut message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+        handler.handle_message(&mut message).await.unwrap();
+        handler
+            .send_message(message, "127.0.0.1".parse().unwrap())
+            .await
+            .unwrap();
+
+        let message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+        let details = &message.delivery_details[&recipient];
+        assert!(matches!(details.status, DeliveryStatus::Reattempt));
+
+        let transcript = serde_json::to_value(&details.transcript).unwrap();
+        let lines: Vec<&str> = transcript["lines"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|line| line.as_str().unwrap())
+            .collect();
+        // the envelope is never sent
+        assert!(
+            !lines.iter().any(|line| line.starts_with("C: MAIL FROM:")),
+            "{lines:?}"
+        );
+
+        let log = serde_json::to_value(&details.log).unwrap().to_string();
+        assert!(log.contains("does not support SMTPUTF8"), "{log}");
+        assert!(received.try_recv().is_err());
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_soft_bounces_do_not_suppress(pool: PgPool) {
+        let org_id = TestProjects::Org1Project1.org_id();
+        let credential = test_credential(&pool).await;
+
+        let recipient: EmailAddress = "full@test.com".parse().unwrap();
+        let rcpt_count = Arc::new(AtomicUsize::new(0));
+        let receiver_port = rejecting_receiver("552 5.2.2 Mailbox full", rcpt_count.clone()).await;
+        let handler = Handler::test_handler(pool.clone(), receiver_port, None).await;
+
+        for _ in 0..SuppressedRepository::MAX_ATTEMPTS + 1 {
+            let message: mail_send::smtp::message::Message = MessageBuilder::new()
+                .from(("John Doe", "john@test-org-1-project-1.com"))
+                .to(vec![("Full", "full@test.com")])
+                .subject("Hi!")
+                .text_body("Hello world!")
+                .into_message()
+                .unwrap();
+            let message = NewMessage::from_builder_message(message, credential.id());
+
+            let message_id = handler
+                .message_repository
+                .create(message, 1)
+                .await
+                .unwrap()
+                .into_inner();
+            let mut message = handler
+                .message_repository
+                .get_if_org_may_send(message_id)
+                .await
+                .unwrap();
+            handler.handle_message(&mut message).await.unwrap();
+            handler
+                .send_message(message, "127.0.0.1".parse().unwrap())
+                .await
+                .unwrap();
+
+            let message = handler
+                .message_repository
+                .get_if_org_may_send(message_id)
+                .await
+                .unwrap();
+            let details = &message.delivery_details[&recipient];
+            assert!(matches!(details.status, DeliveryStatus::Reattempt));
+            assert_eq!(details.bounce, Some(Bounce::Soft));
+        }
+
+        assert!(
+            !handler
+                .suppressed_repository
+                .should_suppress(&recipient, org_id)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_connection_log_rotation(pool: PgPool) {
+        let credential = test_credential(&pool).await;
+
+        let recipient: EmailAddress = "full@test.com".parse().unwrap();
+        let receiver_port =
+            rejecting_receiver("452 4.2.2 Mailbox full", Arc::new(AtomicUsize::new(0))).await;
+        let mut handler = Handler::test_handler(pool.clone(), receiver_port, None).await;
+        handler.config = Arc::new(HandlerConfig {
+            max_log_lines: 5,
+            ..(*handler.config).clone()
+        });
+
+        let message: mail_send::smtp::message::Message = MessageBuilder::new()
+            .from(("John Doe", "john@test-org-1-project-1.com"))
+            .to(vec![("Full", "full@test.com")])
+            .subject("Hi!")
+            .text_body("Hello world!")
+            .into_message()
+            .unwrap();
+        let message = NewMessage::from_builder_message(message, credential.id());
+        let message_id = handler
+            .message_repository
+            .create(message, 10)
+            .await
+            .unwrap()
+            .into_inner();
+        let mut message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+        handler.handle_message(&mut message).await.unwrap();
+
+        for _ in 0..5 {
+            handler
+                .send_message(message, "127.0.0.1".parse().unwrap())
+                .await
+                .unwrap();
+            message = handler
+                .message_repository
+                .get_if_org_may_send(message_id)
+                .await
+                .unwrap();
+        }
+
+        let details = &message.delivery_details[&recipient];
+        assert!(matches!(details.status, DeliveryStatus::Reattempt));
+        let log = serde_json::to_value(&details.log).unwrap();
+        let lines = log["lines"].as_array().unwrap();
+        assert_eq!(lines.len(), 5);
+        assert!(log["dropped"].as_u64().unwrap() > 0);
+        // the outcome of the last attempt is kept
+        assert_eq!(
+            lines.last().unwrap()["msg"],
+            "all mail servers for domain test.com exhausted"
+        );
+    }
+
+    /// Handles SMTP commands until the client quits or starts TLS, returning whether it started TLS
+    async fn tls12_receiver_session<S>(
+        stream: &mut tokio::io::BufReader<S>,
+        offer_starttls: bool,
+        delivered: &AtomicUsize,
+    ) -> std::io::Result<bool>
+    where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if stream.read_line(&mut line).await? == 0 {
+                return Ok(false);
+            }
+            let command = line.trim_end().to_ascii_uppercase();
+            let response = if command.starts_with("EHLO") && offer_starttls {
+                "250-localhost\r\n250 STARTTLS"
+            } else if command.starts_with("EHLO") {
+                "250 localhost"
+            } else if command.starts_with("STARTTLS") {
+                stream
+                    .write_all(b"220 2.0.0 Ready to start TLS\r\n")
+                    .await?;
+                return Ok(true);
+            } else if command.starts_with("DATA") {
+                stream.write_all(b"354 Go ahead\r\n").await?;
+                loop {
+                    line.clear();
+                    if stream.read_line(&mut line).await? == 0 || line == ".\r\n" {
+                        break;
+                    }
+                }
+                delivered.fetch_add(1, Ordering::SeqCst);
+                "250 2.0.0 OK"
+            } else if command.starts_with("QUIT") {
+                stream.write_all(b"221 2.0.0 Bye\r\n").await?;
+                return Ok(false);
+            } else {
+                "250 OK"
+            };
+            stream
+                .write_all(format!("{response}\r\n").as_bytes())
+                .await?;
+        }
+    }
+
+    /// Accepts connections on a random port, offering STARTTLS with TLS 1.2 only,
+    /// counting the number of messages it receives
+    async fn tls12_receiver(delivered: Arc<AtomicUsize>) -> u16 {
+        use tokio::io::{AsyncWriteExt, BufReader};
+        use tokio_rustls::{
+            TlsAcceptor,
+            rustls::{
+                ServerConfig,
+                pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+                version::TLS12,
+            },
+        };
+
+        let certs = CertificateDer::pem_file_iter("dev-secrets/cert.pem")
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let key = PrivateKeyDer::from_pem_file("dev-secrets/key.pem").unwrap();
+        let config =
+            ServerConfig::builder_with_provider(Arc::new(crypto::aws_lc_rs::default_provider()))
+                .with_protocol_versions(&[&TLS12])
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(certs, key)
+                .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let port = random_port();
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .await
+            .unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                let delivered = delivered.clone();
+                tokio::spawn(async move {
+                    let mut plain = BufReader::new(stream);
+                    plain.write_all(b"220 localhost ESMTP\r\n").await?;
+                    if tls12_receiver_session(&mut plain, true, &delivered).await? {
+                        let stream = acceptor.accept(plain.into_inner()).await?;
+                        tls12_receiver_session(&mut BufReader::new(stream), false, &delivered)
+                            .await?;
+                    }
+                    Ok::<_, std::io::Error>(())
+                });
+            }
+        });
+
+        port
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_strict_tls_minimum_version(pool: PgPool) {
+        let credential = test_credential(&pool).await;
+
+        let delivered = Arc::new(AtomicUsize::new(0));
+        let receiver_port = tls12_receiver(delivered.clone()).await;
+        let mut handler = Handler::test_handler(pool.clone(), receiver_port, None).await;
+
+        let send = async |handler: &Handler| {
+            let message: mail_send::smtp::message::Message = MessageBuilder::new()
+                .from(("John Doe", "john@test-org-1-project-1.com"))
+                .to(vec![("Eddy", "eddy@test.com")])
+                .subject("Hi!")
+                .text_body("Hello world!")
+                .into_message()
+                .unwrap();
+            let message = NewMessage::from_builder_message(message, credential.id());
+
+            let message_id = handler
+                .message_repository
+                .create(message, 1)
+                .await
+                .unwrap()
+                .into_inner();
+            let mut message = handler
+                .message_repository
+                .get_if_org_may_send(message_id)
+                .await
+                .unwrap();
+            handler.handle_message(&mut message).await.unwrap();
+            handler
+                .send_message(message, "127.0.0.1".parse().unwrap())
+                .await
+                .unwrap();
+
+            handler
+                .message_repository
+                .get_if_org_may_send(message_id)
+                .await
+                .unwrap()
+        };
+
+        // the receiver only speaks TLS 1.2, so it is refused when requiring TLS 1.3,
+        // even though the project allows falling back to plaintext
+        handler.config = Arc::new(HandlerConfig {
+            tls: OutboundTlsPolicy::new(tls::TlsVersion::Tls13, None, true),
+            ..(*handler.config).clone()
+        });
+        let message = send(&handler).await;
+        assert_eq!(message.status, MessageStatus::Reattempt);
+        assert_eq!(delivered.load(Ordering::SeqCst), 0);
+
+        // without strict mode, the message is sent over plaintext instead
+        handler.config = Arc::new(HandlerConfig {
+            tls: OutboundTlsPolicy::new(tls::TlsVersion::Tls13, None, false),
+            ..(*handler.config).clone()
+        });
+        let message = send(&handler).await;
+        assert_eq!(message.status, MessageStatus::Delivered);
+        assert_eq!(delivered.load(Ordering::SeqCst), 1);
+
+        // strict mode with TLS 1.2 as the minimum accepts the receiver
+        handler.config = Arc::new(HandlerConfig {
+            tls: OutboundTlsPolicy::new(tls::TlsVersion::Tls12, None, true),
+            ..(*handler.config).clone()
+        });
+        let message = send(&handler).await;
+        assert_eq!(message.status, MessageStatus::Delivered);
+        assert_eq!(delivered.load(Ordering::SeqCst), 2);
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_parallel_delivery(pool: PgPool) {
+        let mailcrab_port = random_port();
+        let TestMailServerHandle { token, rx: _rx } =
+            mailcrab::development_mail_server(Ipv4Addr::new(127, 0, 0, 1), mailcrab_port).await;
+        let _drop_guard = token.drop_guard();
+        let receiver_port =
+            slow_receiver(mailcrab_port, std::time::Duration::from_millis(500)).await;
+
+        let org_id = TestProjects::Org1Project1.org_id();
+        let credential = test_credential(&pool).await;
+
+        let mut handler = Handler::test_handler(pool.clone(), receiver_port, None).await;
+
+        // time the delivery of a message to four distinct domains, which all use the slow receiver
+        let mut deliver = async |delivery_concurrency: usize| {
+            handler.deliveries = Arc::new(Semaphore::new(delivery_concurrency));
+
+            let message: mail_send::smtp::message::Message = MessageBuilder::new()
+                .from(("John Doe", "john@test-org-1-project-1.com"))
+                .to(vec![
+                    ("Jane Doe", "jane@test.com"),
+                    ("James Smith", "james@example.com"),
+                    ("Jill Jones", "jill@example.org"),
+                    ("Jack Brown", "jack@example.net"),
+                ])
+                .subject("Hi!")
+                .text_body("Hello world!")
+                .into_message()
+                .unwrap();
+            let message = NewMessage::from_builder_message(message, credential.id());
+
+            let message_id = handler
+                .message_repository
+                .create(message, 1)
+                .await
+                .unwrap()
+                .into_inner();
+            let mut message = handler
+                .message_repository
+                .get_if_org_may_send(message_id)
+                .await
+                .unwrap();
+            handler.handle_message(&mut message).await.unwrap();
+
+            let start = std::time::Instant::now();
+            handler
+                .send_message(message, "127.0.0.1".parse().unwrap())
+                .await
+                .unwrap();
+            let elapsed = start.elapsed();
+
+            let message = handler
+                .message_repository
+                .find_by_id(org_id, message_id)
+                .await
+                .unwrap();
+            assert_eq!(message.status(), &MessageStatus::Delivered);
+
+            elapsed
+        };
+
+        let sequential = deliver(1).await;
+        let parallel = deliver(4).await;
+
+        // the parallel delivery should take about as long as a single recipient,
+        // while the sequential delivery takes as long as all recipients together
+        assert!(
+            parallel * 2 < sequential,
+            "parallel delivery took {parallel:?}, sequential delivery took {sequential:?}"
+        );
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_smarthost(pool: PgPool) {
+        let smarthost_port = random_port();
+        let TestMailServerHandle { token, mut rx } =
+            mailcrab::development_mail_server(Ipv4Addr::new(127, 0, 0, 1), smarthost_port).await;
+        let _drop_guard = token.drop_guard();
+        // the MX records of all recipient domains point to this receiver
+        let mx_rcpt_count = Arc::new(AtomicUsize::new(0));
+        let mx_port = rejecting_receiver("550 5.1.1 No such user", mx_rcpt_count.clone()).await;
+
+        let credential = test_credential(&pool).await;
+
+        let mut handler = Handler::test_handler(pool, mx_port, None).await;
+        handler.config = Arc::new(HandlerConfig {
+            smarthost: Some(Smarthost {
+                hostname: "localhost".to_owned(),
+                port: smarthost_port,
+                credentials: None,
+            }),
+            ..(*handler.config).clone()
+        });
+
+        let message: mail_send::smtp::message::Message = MessageBuilder::new()
+            .from(("John Doe", "john@test-org-1-project-1.com"))
+            .to(vec![("James Smith", "james@test.com")])
+            .subject("Hi!")
+            .text_body("Hello world!")
+            .into_message()
+            .unwrap();
+        let message_id = handler
+            .message_repository
+            .create(
+                NewMessage::from_builder_message(message, credential.id()),
+                1,
+            )
+            .await
+            .unwrap()
+            .into_inner();
+        let mut message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+        handler.handle_message(&mut message).await.unwrap();
+        handler
+            .send_message(message, "127.0.0.1".parse().unwrap())
+            .await
+            .unwrap();
+
+        let recv = rx.recv().await.unwrap();
+        assert_eq!(recv.envelope_from.as_str(), "john@test-org-1-project-1.com");
+        assert_eq!(mx_rcpt_count.load(Ordering::SeqCst), 0);
+
+        let message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+        assert_eq!(message.status, MessageStatus::Delivered);
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn smarthost_credentials_require_tls(pool: PgPool) {
+        // the smarthost does not offer STARTTLS, and accepts anything that is sent in plaintext
+        let rcpt_count = Arc::new(AtomicUsize::new(0));
+        let smarthost_port = rejecting_receiver("250 OK", rcpt_count.clone()).await;
+
+        let credential = test_credential(&pool).await;
+
+        let mut handler = Handler::test_handler(pool, smarthost_port, None).await;
+        handler.config = Arc::new(HandlerConfig {
+            smarthost: Some(Smarthost {
+                hostname: "localhost".to_owned(),
+                port: smarthost_port,
+                credentials: Some(Credentials::new("relay".to_owned(), "secret".to_owned())),
+            }),
+            ..(*handler.config).clone()
+        });
+
+        let message: mail_send::smtp::message::Message = MessageBuilder::new()
+            .from(("John Doe", "john@test-org-1-project-1.com"))
+            .to(vec![("James Smith", "james@test.com")])
+            .subject("Hi!")
+            .text_body("Hello world!")
+            .into_message()
+            .unwrap();
+        let message_id = handler
+            .message_repository
+            .create(
+                NewMessage::from_builder_message(message, credential.id()),
+                1,
+            )
+            .await
+            .unwrap()
+            .into_inner();
+        let mut message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+        handler.handle_message(&mut message).await.unwrap();
+        handler
+            .send_message(message, "127.0.0.1".parse().unwrap())
+            .await
+            .unwrap();
+
+        // the message is not sent without TLS, but retried later
+        assert_eq!(rcpt_count.load(Ordering::SeqCst), 0);
+        let message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+        assert_eq!(message.status, MessageStatus::Reattempt);
+        let recipient: EmailAddress = "james@test.com".parse().unwrap();
+        let details = &message.delivery_details[&recipient];
+        assert!(matches!(details.status, DeliveryStatus::Reattempt));
+        let transcript = serde_json::to_string(&details.transcript).unwrap();
+        assert!(!transcript.contains("AUTH"));
+        let log = serde_json::to_string(&details.log).unwrap();
+        assert!(log.contains("without TLS, as it requires authentication"));
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
     ))]
-    async fn test_handle_incorrect_dns_records(pool: PgPool) {
-        let (org_id, project_id) = TestProjects::Org1Project1.get_ids();
-        let credential_request = SmtpCredentialRequest {
-            username: "user".to_string(),
-            description: "Test SMTP credential description".to_string(),
-        };
-        let credential_repo = SmtpCredentialRepository::new(pool.clone());
-        let credential = credential_repo
-            .generate(
-                org_id,
-                project_id,
-                &credential_request,
-                crate::models::SYSTEM,
+    async fn test_delivery_routes(pool: PgPool) {
+        let mx_port = random_port();
+        let TestMailServerHandle { token, mut rx } =
+            mailcrab::development_mail_server(Ipv4Addr::new(127, 0, 0, 1), mx_port).await;
+        let _drop_guard = token.drop_guard();
+        let relay_rcpt_count = Arc::new(AtomicUsize::new(0));
+        let relay_port =
+            rejecting_receiver("550 5.7.1 Relaying denied", relay_rcpt_count.clone()).await;
+
+        let org_id = TestProjects::Org1Project1.org_id();
+        let credential = test_credential(&pool).await;
+
+        let mut handler = Handler::test_handler(pool, mx_port, None).await;
+        handler.config = Arc::new(HandlerConfig {
+            routes: RoutingTable::new([
+                (
+                    "*.corp.example",
+                    Route::Relay(Smarthost {
+                        hostname: "localhost".to_owned(),
+                        port: relay_port,
+                        credentials: None,
+                    }),
+                ),
+                ("blocked.example", Route::Reject),
+            ]),
+            ..(*handler.config).clone()
+        });
+
+        let message: mail_send::smtp::message::Message = MessageBuilder::new()
+            .from(("John Doe", "john@test-org-1-project-1.com"))
+            .to(vec![
+                ("James Smith", "james@test.com"),
+                ("Jane Doe", "jane@mail.corp.example"),
+                ("Jill Jones", "jill@blocked.example"),
+            ])
+            .subject("Hi!")
+            .text_body("Hello world!")
+            .into_message()
+            .unwrap();
+        let message_id = handler
+            .message_repository
+            .create(
+                NewMessage::from_builder_message(message, credential.id()),
+                1,
             )
             .await
+            .unwrap()
+            .into_inner();
+        let mut message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+        handler.handle_message(&mut message).await.unwrap();
+        handler
+            .send_message(message, "127.0.0.1".parse().unwrap())
+            .await
+            .unwrap();
+
+        let message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
             .unwrap();
+        let details = |recipient: &str| {
+            let recipient: EmailAddress = recipient.parse().unwrap();
+            &message.delivery_details[&recipient]
+        };
+
+        // the unmatched domain is delivered to its MX, which is the only message it receives
+        assert!(matches!(
+            details("james@test.com").status,
+            DeliveryStatus::Success { .. }
+        ));
+        let recv = rx.recv().await.unwrap();
+        assert_eq!(recv.envelope_from.as_str(), "john@test-org-1-project-1.com");
+        assert!(rx.try_recv().is_err());
+
+        // the matching domain is handed to the relay instead, which refuses it
+        assert!(matches!(
+            details("jane@mail.corp.example").status,
+            DeliveryStatus::Failed
+        ));
+        assert_ne!(relay_rcpt_count.load(Ordering::SeqCst), 0);
+
+        // the rejected domain is not contacted at all, nor suppressed
+        assert!(matches!(
+            details("jill@blocked.example").status,
+            DeliveryStatus::Failed
+        ));
+        assert_eq!(details("jill@blocked.example").bounce, None);
+        assert!(
+            !handler
+                .suppressed_repository
+                .should_suppress(&"jill@blocked.example".parse().unwrap(), org_id)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_spf_include(pool: PgPool) {
+        let credential = test_credential(&pool).await;
+
+        let handle = async |spf: &'static str| {
+            let dkim = "v=DKIM1; k=rsa; p=MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEAyQtyx8uwJIJoQ3+LEetDzd+bpIkebVIYSq94OCOimHu/Pv7tPY5pn99JVv0rmdGHluuWEGxQNBYDBdk0FQF4+HP0MlPitJSdxawmCRsIcUZR3TQLf6dDBm2YPJ3G4xUQ2pT4GPMwCX9N1aAfO5qj2fBsjT8LvLeTRKEbHXGDM+m2yMF0dgr6AJLLVYjs3MSD273DEL5GnqhGXieziz4PI5TCJpxR3CVByguImG9tg1BySMu3f7VFmiToLCVeuk1UzIYAPZN6fvCcmyalADfG9rZa/60lxFzeorBtVk/Ej0braeX8AT8RX2Ozw9lg2Wzkwx5NyvqOFAcnkhDX4oTeVQIDAQAB";
+            let handler = Handler::test_handler(pool.clone(), 1, Some(vec![dkim, spf])).await;
+
+            let message: mail_send::smtp::message::Message = MessageBuilder::new()
+                .from(("John Doe", "john@test-org-1-project-1.com"))
+                .to(vec![("James Smith", "james@test.com")])
+                .subject("Hi!")
+                .text_body("Hello world!")
+                .into_message()
+                .unwrap();
+            let message = NewMessage::from_builder_message(message, credential.id());
+            let message_id = handler
+                .message_repository
+                .create(message, 1)
+                .await
+                .unwrap()
+                .into_inner();
+            let mut message = handler
+                .message_repository
+                .get_if_org_may_send(message_id)
+                .await
+                .unwrap();
+            let result = handler.handle_message(&mut message).await;
+            (result, message)
+        };
+
+        // the SPF record includes ours
+        let (result, message) =
+            handle("v=spf1 include:test.com include:spf.remails.net ~all").await;
+        result.unwrap();
+        assert_eq!(message.status, MessageStatus::Accepted);
+
+        // the SPF record does not include ours, the reason tells what is missing and what was found
+        let (result, message) = handle("v=spf1 include:test.com -all").await;
+        assert!(matches!(
+            result,
+            Err(HandlerError::MessageNotAccepted(MessageStatus::Held, _))
+        ));
+        assert_eq!(message.hold_reason, Some(HoldReason::Configuration));
+        assert_eq!(
+            message.reason.unwrap(),
+            "invalid SPF on test-org-1-project-1.com: SPF record is missing \"include:spf.remails.net\": v=spf1 include:test.com -all"
+        );
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_handle_incorrect_dns_records(pool: PgPool) {
+        let credential = test_credential(&pool).await;
 
         let dns_records = [
             // Missing DKIM
@@ -1073,7 +4752,12 @@ mod test {
             let message = NewMessage::from_builder_message(message, credential.id());
             let handler = Handler::test_handler(pool.clone(), 1, Some(dns_records)).await;
 
-            let message_id = handler.message_repository.create(message, 1).await.unwrap();
+            let message_id = handler
+                .message_repository
+                .create(message, 1)
+                .await
+                .unwrap()
+                .into_inner();
             let mut message = handler
                 .message_repository
                 .get_if_org_may_send(message_id)
@@ -1097,21 +4781,7 @@ mod test {
         )
     ))]
     async fn test_handle_invalid_mail_from(pool: PgPool) {
-        let (org_id, project_id) = TestProjects::Org1Project1.get_ids();
-        let credential_request = SmtpCredentialRequest {
-            username: "user".to_string(),
-            description: "Test SMTP credential description".to_string(),
-        };
-        let credential_repo = SmtpCredentialRepository::new(pool.clone());
-        let credential = credential_repo
-            .generate(
-                org_id,
-                project_id,
-                &credential_request,
-                crate::models::SYSTEM,
-            )
-            .await
-            .unwrap();
+        let credential = test_credential(&pool).await;
 
         let we_cant_use_these_emails = [
             "john@gmail.com",
@@ -1137,7 +4807,12 @@ mod test {
                 NewMessage::from_builder_message_custom_from(message, credential.id(), from_email);
             let handler = Handler::test_handler(pool.clone(), 1, None).await;
 
-            let message_id = handler.message_repository.create(message, 1).await.unwrap();
+            let message_id = handler
+                .message_repository
+                .create(message, 1)
+                .await
+                .unwrap()
+                .into_inner();
             let mut message = handler
                 .message_repository
                 .get_if_org_may_send(message_id)
@@ -1161,21 +4836,7 @@ mod test {
         )
     ))]
     async fn test_handle_invalid_from(pool: PgPool) {
-        let (org_id, project_id) = TestProjects::Org1Project1.get_ids();
-        let credential_request = SmtpCredentialRequest {
-            username: "user".to_string(),
-            description: "Test SMTP credential description".to_string(),
-        };
-        let credential_repo = SmtpCredentialRepository::new(pool.clone());
-        let credential = credential_repo
-            .generate(
-                org_id,
-                project_id,
-                &credential_request,
-                crate::models::SYSTEM,
-            )
-            .await
-            .unwrap();
+        let credential = test_credential(&pool).await;
 
         let we_cant_use_these_emails = [
             "john@gmail.com",
@@ -1205,7 +4866,12 @@ mod test {
             );
             let handler = Handler::test_handler(pool.clone(), 1, None).await;
 
-            let message_id = handler.message_repository.create(message, 1).await.unwrap();
+            let message_id = handler
+                .message_repository
+                .create(message, 1)
+                .await
+                .unwrap()
+                .into_inner();
             let mut message = handler
                 .message_repository
                 .get_if_org_may_send(message_id)
@@ -1218,6 +4884,252 @@ mod test {
         }
     }
 
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn unauthorized_domain_policy(pool: PgPool) {
+        let project_id = TestProjects::Org1Project1.project_id();
+        let credential = test_credential(&pool).await;
+        let handler = Handler::test_handler(pool.clone(), 1, None).await;
+
+        let handle = async |policy: UnauthorizedDomainPolicy| {
+            sqlx::query!(
+                "UPDATE projects SET unauthorized_domain_policy = $2 WHERE id = $1",
+                *project_id,
+                policy as UnauthorizedDomainPolicy,
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+
+            // the project is not permitted to use this domain
+            let message: mail_send::smtp::message::Message = MessageBuilder::new()
+                .from(("John Doe", "john@not-my-domain.com"))
+                .to(vec![("James Smith", "james@test.com")])
+                .subject("Hi!")
+                .text_body("Hello world!")
+                .into_message()
+                .unwrap();
+            let message = NewMessage::from_builder_message(message, credential.id());
+            let message_id = handler
+                .message_repository
+                .create(message, 1)
+                .await
+                .unwrap()
+                .into_inner();
+            let mut message = handler
+                .message_repository
+                .get_if_org_may_send(message_id)
+                .await
+                .unwrap();
+            let result = handler.handle_message(&mut message).await;
+            (result, message)
+        };
+
+        let (result, message) = handle(UnauthorizedDomainPolicy::Hold).await;
+        assert!(matches!(
+            result,
+            Err(HandlerError::MessageNotAccepted(MessageStatus::Held, _))
+        ));
+        assert_eq!(message.status, MessageStatus::Held);
+        assert_eq!(message.hold_reason, Some(HoldReason::Configuration));
+
+        let (result, message) = handle(UnauthorizedDomainPolicy::Reject).await;
+        assert!(matches!(
+            result,
+            Err(HandlerError::MessageNotAccepted(MessageStatus::Rejected, _))
+        ));
+        assert_eq!(message.status, MessageStatus::Rejected);
+        assert_eq!(message.hold_reason, None);
+        assert_eq!(
+            message.reason.unwrap(),
+            "Project is not permitted to use domain not-my-domain.com"
+        );
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn rewrite_unaligned_from(pool: PgPool) {
+        let project_id = TestProjects::Org1Project1.project_id();
+        let credential = test_credential(&pool).await;
+        let handler = Handler::test_handler(pool.clone(), 1, None).await;
+
+        let handle = async |rewrite: bool| {
+            sqlx::query!(
+                "UPDATE projects SET rewrite_unaligned_from = $2 WHERE id = $1",
+                *project_id,
+                rewrite,
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+
+            // a forwarded message, the From header is outside the project's domain
+            let message: mail_send::smtp::message::Message = MessageBuilder::new()
+                .from(("Jane Doe", "jane@forwarded.com"))
+                .to(vec![("James Smith", "james@test.com")])
+                .subject("Hi!")
+                .text_body("Hello world!")
+                .into_message()
+                .unwrap();
+            let message = NewMessage::from_builder_message_custom_from(
+                message,
+                credential.id(),
+                "john@test-org-1-project-1.com",
+            );
+            let message_id = handler
+                .message_repository
+                .create(message, 1)
+                .await
+                .unwrap()
+                .into_inner();
+            let mut message = handler
+                .message_repository
+                .get_if_org_may_send(message_id)
+                .await
+                .unwrap();
+            let result = handler.handle_message(&mut message).await;
+            (result, message)
+        };
+
+        // rewriting is opt-in
+        let (result, message) = handle(false).await;
+        assert!(matches!(
+            result,
+            Err(HandlerError::MessageNotAccepted(MessageStatus::Rejected, _))
+        ));
+        assert_eq!(
+            message.reason.unwrap(),
+            "From domain (forwarded.com) is not a valid (sub-)domain of test-org-1-project-1.com"
+        );
+
+        let (result, message) = handle(true).await;
+        result.unwrap();
+        assert_eq!(message.status, MessageStatus::Accepted);
+
+        // the rewritten message is signed and stored
+        let signed = String::from_utf8(message.raw_data.clone()).unwrap();
+        assert!(signed.starts_with("DKIM-Signature: "));
+        let stored = handler
+            .message_repository
+            .get_if_org_may_send(message.id())
+            .await
+            .unwrap();
+        assert_eq!(
+            stored.raw_data,
+            message.raw_data[message.prepended_headers().len()..]
+        );
+
+        let parsed = MessageParser::new().parse(&stored.raw_data).unwrap();
+        let from = parsed.from().unwrap().first().unwrap();
+        assert_eq!(from.address(), Some("john@test-org-1-project-1.com"));
+        assert_eq!(from.name(), Some("Jane Doe via test-org-1-project-1.com"));
+        let reply_to = parsed.reply_to().unwrap().first().unwrap();
+        assert_eq!(reply_to.address(), Some("jane@forwarded.com"));
+        assert_eq!(reply_to.name(), Some("Jane Doe"));
+        assert_eq!(
+            stored
+                .raw_data
+                .windows(b"\nFrom:".len())
+                .filter(|w| w.eq_ignore_ascii_case(b"\nFrom:"))
+                .count(),
+            0
+        );
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn strip_configured_headers(pool: PgPool) {
+        let credential = test_credential(&pool).await;
+
+        // the header would be signed if it was still present when signing
+        sqlx::query!(
+            "UPDATE domains SET dkim_signed_headers = ARRAY['From', 'Subject', 'X-Originating-IP']"
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let mut handler = Handler::test_handler(pool.clone(), 1, None).await;
+        handler.config = Arc::new(HandlerConfig {
+            stripped_headers: StrippedHeaders::new(["X-Originating-IP"]).unwrap(),
+            ..(*handler.config).clone()
+        });
+
+        let message: mail_send::smtp::message::Message = MessageBuilder::new()
+            .from(("John Doe", "john@test-org-1-project-1.com"))
+            .to(vec![("James Smith", "james@test.com")])
+            .subject("Hi!")
+            .header("X-Originating-IP", Raw::new("[192.0.2.1]"))
+            .text_body("Hello world!")
+            .into_message()
+            .unwrap();
+        let message_id = handler
+            .message_repository
+            .create(
+                NewMessage::from_builder_message(message, credential.id()),
+                1,
+            )
+            .await
+            .unwrap()
+            .into_inner();
+        let mut message = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+        handler.handle_message(&mut message).await.unwrap();
+        assert_eq!(message.status, MessageStatus::Accepted);
+
+        // the stripped message is stored, as it is streamed during delivery
+        let stored = handler
+            .message_repository
+            .get_if_org_may_send(message_id)
+            .await
+            .unwrap();
+        assert_eq!(
+            stored.raw_data,
+            message.raw_data[message.prepended_headers().len()..]
+        );
+        let parsed = MessageParser::new().parse(&stored.raw_data).unwrap();
+        assert!(parsed.header("X-Originating-IP").is_none());
+        assert_eq!(parsed.subject(), Some("Hi!"));
+
+        // the signature was created after stripping, so it does not cover the header
+        let dkim_header = String::from_utf8(message.prepended_headers().to_vec()).unwrap();
+        let signed_headers = dkim_header
+            .split(';')
+            .map(|tag| tag.split_whitespace().collect::<String>())
+            .find_map(|tag| tag.strip_prefix("h=").map(str::to_lowercase))
+            .unwrap();
+        let signed_headers: Vec<&str> = signed_headers.split(':').collect();
+        assert!(signed_headers.contains(&"from"));
+        assert!(!signed_headers.contains(&"x-originating-ip"));
+    }
+
     #[sqlx::test(fixtures(
         path = "../fixtures",
         scripts(
@@ -1234,21 +5146,7 @@ mod test {
             mailcrab::development_mail_server(Ipv4Addr::new(127, 0, 0, 1), mailcrab_port).await;
         let _drop_guard = token.drop_guard();
 
-        let (org_id, project_id) = TestProjects::Org1Project1.get_ids();
-        let credential_request = SmtpCredentialRequest {
-            username: "user".to_string(),
-            description: "Test SMTP credential description".to_string(),
-        };
-        let credential_repo = SmtpCredentialRepository::new(pool.clone());
-        let credential = credential_repo
-            .generate(
-                org_id,
-                project_id,
-                &credential_request,
-                crate::models::SYSTEM,
-            )
-            .await
-            .unwrap();
+        let credential = test_credential(&pool).await;
 
         let message: mail_send::smtp::message::Message = MessageBuilder::new()
             .from(("John Doe", "john@subdomain.test-org-1-project-1.com"))
@@ -1270,7 +5168,12 @@ mod test {
         );
         let handler = Handler::test_handler(pool.clone(), mailcrab_port, None).await;
 
-        let message_id = handler.message_repository.create(message, 1).await.unwrap();
+        let message_id = handler
+            .message_repository
+            .create(message, 1)
+            .await
+            .unwrap()
+            .into_inner();
         let mut message = handler
             .message_repository
             .get_if_org_may_send(message_id)
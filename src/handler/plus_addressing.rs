@@ -0,0 +1,92 @@
+//! Normalization of plus-addressed recipients, e.g., `user+tag@example.com`
+//!
+//! Many providers deliver `user+tag@domain` to the mailbox of `user@domain`, so a hard bounce
+//! for one of these addresses says something about the others as well. Not every provider treats
+//! the `+` as a separator though, which is why this only applies to explicitly configured domains.
+
+use email_address::EmailAddress;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlusAddressing {
+    /// Lowercase recipient domains that ignore everything after the `+` in the local part
+    domains: Vec<String>,
+}
+
+impl PlusAddressing {
+    pub fn new<S: AsRef<str>>(domains: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            domains: domains
+                .into_iter()
+                .map(|domain| domain.as_ref().trim().to_lowercase())
+                .filter(|domain| !domain.is_empty())
+                .collect(),
+        }
+    }
+
+    /// Configure the domains using the `PLUS_ADDRESSING_DOMAINS` environment variable,
+    /// a comma-separated list of domains, e.g., `gmail.com,googlemail.com`
+    ///
+    /// Normalization is disabled if the variable is not set
+    #[cfg(not(test))]
+    pub fn from_env() -> Self {
+        std::env::var("PLUS_ADDRESSING_DOMAINS")
+            .map(|domains| Self::new(domains.split(',')))
+            .unwrap_or_default()
+    }
+
+    /// The address of the mailbox `email` is delivered to, used to match it against the
+    /// suppression list
+    ///
+    /// The original address is still used to deliver to.
+    pub fn normalize(&self, email: &EmailAddress) -> EmailAddress {
+        let domain = email.domain().to_lowercase();
+        if !self.domains.contains(&domain) {
+            return email.clone();
+        }
+
+        match email.local_part().split_once('+') {
+            // an address starting with a `+` has no mailbox to strip the tag from
+            Some((mailbox, _tag)) if !mailbox.is_empty() => format!("{mailbox}@{}", email.domain())
+                .parse()
+                .unwrap_or_else(|_| email.clone()),
+            _ => email.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize() {
+        let plus_addressing = PlusAddressing::new(["gmail.com", " Example.com "]);
+        let normalize = |email: &str| {
+            plus_addressing
+                .normalize(&email.parse().unwrap())
+                .as_str()
+                .to_owned()
+        };
+
+        assert_eq!(normalize("user+tag@gmail.com"), "user@gmail.com");
+        assert_eq!(normalize("user+tag+more@example.com"), "user@example.com");
+        assert_eq!(normalize("user+tag@EXAMPLE.COM"), "user@EXAMPLE.COM");
+        assert_eq!(normalize("user@gmail.com"), "user@gmail.com");
+
+        // only configured domains are normalized, subdomains are not
+        assert_eq!(normalize("user+tag@other.com"), "user+tag@other.com");
+        assert_eq!(
+            normalize("user+tag@mail.gmail.com"),
+            "user+tag@mail.gmail.com"
+        );
+
+        // there is no mailbox to strip the tag from
+        assert_eq!(normalize("+tag@gmail.com"), "+tag@gmail.com");
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let email: EmailAddress = "user+tag@gmail.com".parse().unwrap();
+        assert_eq!(PlusAddressing::default().normalize(&email), email);
+    }
+}
@@ -0,0 +1,134 @@
+//! Routing of outbound messages by destination domain
+//!
+//! By default, messages are delivered to the MX servers of the recipient's domain, or relayed
+//! through the smarthost if one is configured. Routing rules override this for specific domains,
+//! e.g., to hand internal domains to an internal relay.
+
+use crate::handler::smarthost::Smarthost;
+
+#[derive(Clone, PartialEq)]
+pub enum Route {
+    /// Deliver to the MX servers of the domain, even if a smarthost is configured
+    Mx,
+    /// Relay through this server
+    Relay(Smarthost),
+    /// Never deliver to the domain, recipients fail right away
+    Reject,
+}
+
+#[derive(Clone, Default, PartialEq)]
+pub struct RoutingTable {
+    /// Lowercase domain patterns and their routes, the first matching rule applies
+    rules: Vec<(String, Route)>,
+}
+
+impl RoutingTable {
+    /// A pattern is either a domain, e.g., `example.com`, or a wildcard matching all of its
+    /// subdomains, e.g., `*.example.com`
+    pub fn new<S: AsRef<str>>(rules: impl IntoIterator<Item = (S, Route)>) -> Self {
+        Self {
+            rules: rules
+                .into_iter()
+                .map(|(pattern, route)| (pattern.as_ref().trim().to_lowercase(), route))
+                .collect(),
+        }
+    }
+
+    /// Configure the rules using the `DELIVERY_ROUTES` environment variable, a comma-separated
+    /// list of `pattern=route`, where the route is `mx`, `reject`, or `relay:host:port`, e.g.,
+    /// `*.corp.example=relay:relay.corp.example:25,example.net=reject`
+    ///
+    /// Will panic if any of the rules is invalid
+    #[cfg(not(test))]
+    pub fn from_env() -> Self {
+        std::env::var("DELIVERY_ROUTES")
+            .map(|rules| {
+                Self::parse(&rules).expect(
+                    "DELIVERY_ROUTES must be a comma-separated list of `pattern=route`, \
+                     where the route is `mx`, `reject`, or `relay:host:port`",
+                )
+            })
+            .unwrap_or_default()
+    }
+
+    fn parse(rules: &str) -> Option<Self> {
+        let rules = rules
+            .split(',')
+            .filter(|rule| !rule.trim().is_empty())
+            .map(|rule| {
+                let (pattern, route) = rule.split_once('=')?;
+                let route = match route.trim() {
+                    "mx" => Route::Mx,
+                    "reject" => Route::Reject,
+                    route => {
+                        let relay = Smarthost::parse(route.strip_prefix("relay:")?, None)?;
+                        Route::Relay(relay)
+                    }
+                };
+
+                Some((pattern, route))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self::new(rules))
+    }
+
+    /// The route of the first rule matching `domain`, `None` if the default route applies
+    pub fn route(&self, domain: &str) -> Option<&Route> {
+        let domain = domain.trim_end_matches('.').to_lowercase();
+
+        self.rules
+            .iter()
+            .find(|(pattern, _)| match pattern.strip_prefix("*.") {
+                Some(parent) => domain
+                    .strip_suffix(parent)
+                    .is_some_and(|subdomain| subdomain.len() > 1 && subdomain.ends_with('.')),
+                None => *pattern == domain,
+            })
+            .map(|(_, route)| route)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route() {
+        let routes = RoutingTable::parse(
+            "*.corp.example=relay:relay.corp.example:25, Example.net=reject,mx.corp.example=mx",
+        )
+        .unwrap();
+
+        let Some(Route::Relay(relay)) = routes.route("mail.Corp.example.") else {
+            panic!("expected the relay route");
+        };
+        assert_eq!(relay.hostname, "relay.corp.example");
+        assert_eq!(relay.port, 25);
+        assert!(matches!(routes.route("example.net"), Some(Route::Reject)));
+
+        // the first matching rule applies
+        assert!(matches!(
+            routes.route("mx.corp.example"),
+            Some(Route::Relay(_))
+        ));
+
+        // wildcards only match subdomains
+        assert!(routes.route("corp.example").is_none());
+        assert!(routes.route("othercorp.example").is_none());
+        assert!(routes.route("sub.example.net").is_none());
+    }
+
+    #[test]
+    fn invalid_rules() {
+        assert!(RoutingTable::parse("example.com").is_none());
+        assert!(RoutingTable::parse("example.com=direct").is_none());
+        assert!(RoutingTable::parse("example.com=relay:relay.example.com").is_none());
+        assert!(
+            RoutingTable::parse("")
+                .unwrap()
+                .route("example.com")
+                .is_none()
+        );
+    }
+}
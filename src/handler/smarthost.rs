@@ -0,0 +1,69 @@
+//! Relaying of all outbound messages through a smarthost
+//!
+//! Some deployments may not connect to the MX servers of recipients themselves, and must hand
+//! all messages to a relay instead. Messages are DKIM-signed all the same, as the relay is
+//! expected to forward them unchanged.
+
+use mail_send::Credentials;
+
+#[derive(Clone, PartialEq)]
+pub struct Smarthost {
+    pub hostname: String,
+    pub port: u16,
+    /// Used to authenticate with the smarthost after saying EHLO, if set
+    pub credentials: Option<Credentials<String>>,
+}
+
+impl Smarthost {
+    /// Configure the smarthost using the `SMARTHOST` environment variable, e.g.,
+    /// `relay.example.com:587`, and optionally `SMARTHOST_USERNAME` and `SMARTHOST_PASSWORD`
+    ///
+    /// Messages are delivered to the MX servers of the recipients if `SMARTHOST` is not set.
+    /// Will panic if it is set, but is not a hostname followed by a port
+    #[cfg(not(test))]
+    pub fn from_env() -> Option<Self> {
+        let address = std::env::var("SMARTHOST")
+            .ok()
+            .filter(|address| !address.is_empty())?;
+        let credentials = std::env::var("SMARTHOST_USERNAME").ok().map(|username| {
+            let password = std::env::var("SMARTHOST_PASSWORD")
+                .expect("SMARTHOST_PASSWORD must be set with SMARTHOST_USERNAME");
+            Credentials::new(username, password)
+        });
+
+        Some(
+            Self::parse(&address, credentials)
+                .expect("SMARTHOST must be a hostname and port, e.g., relay.example.com:587"),
+        )
+    }
+
+    /// Parse `hostname:port`
+    pub(super) fn parse(address: &str, credentials: Option<Credentials<String>>) -> Option<Self> {
+        let (hostname, port) = address.rsplit_once(':')?;
+        if hostname.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            hostname: hostname.to_owned(),
+            port: port.parse().ok()?,
+            credentials,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let smarthost = Smarthost::parse("relay.example.com:587", None).unwrap();
+        assert_eq!(smarthost.hostname, "relay.example.com");
+        assert_eq!(smarthost.port, 587);
+
+        assert!(Smarthost::parse("relay.example.com", None).is_none());
+        assert!(Smarthost::parse("relay.example.com:smtp", None).is_none());
+        assert!(Smarthost::parse(":25", None).is_none());
+    }
+}
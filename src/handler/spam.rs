@@ -0,0 +1,184 @@
+//! Optional spam scoring of outbound messages before delivery
+//!
+//! The raw message is posted to an rspamd-compatible HTTP endpoint (e.g. `/checkv2`), which
+//! responds with a JSON object containing the `score` of the message.
+
+use serde::Deserialize;
+use std::{env, sync::Arc, time::Duration};
+use tracing::{debug, warn};
+use url::Url;
+
+/// Messages scoring at least this much are held, unless configured otherwise
+const DEFAULT_HOLD_THRESHOLD: f64 = 15.0;
+
+#[derive(Clone, Default)]
+pub struct SpamScorer {
+    /// `None` if spam scoring is disabled
+    inner: Option<Arc<Inner>>,
+}
+
+struct Inner {
+    client: reqwest::Client,
+    url: Url,
+    hold_threshold: f64,
+}
+
+#[derive(Deserialize)]
+struct ScanResult {
+    score: f64,
+}
+
+/// A message that scored too high to be delivered
+#[derive(Debug, PartialEq)]
+pub struct SpamVerdict {
+    pub score: f64,
+    pub hold_threshold: f64,
+}
+
+impl SpamScorer {
+    /// Configure spam scoring using the following environment variables:
+    /// - `SPAM_SCORER_URL`: endpoint to post messages to, spam scoring is disabled if not set
+    /// - `SPAM_HOLD_THRESHOLD`: messages with at least this score are held, defaults to 15
+    /// - `SPAM_SCORER_TIMEOUT_MS`: timeout of the scoring request, defaults to 5000 ms
+    pub fn from_env() -> Self {
+        let Ok(url) = env::var("SPAM_SCORER_URL") else {
+            return Self::disabled();
+        };
+
+        let url = url.parse().expect("Invalid SPAM_SCORER_URL env var");
+        let hold_threshold = env::var("SPAM_HOLD_THRESHOLD")
+            .map(|s| s.parse().expect("Invalid SPAM_HOLD_THRESHOLD env var"))
+            .unwrap_or(DEFAULT_HOLD_THRESHOLD);
+        let timeout = env::var("SPAM_SCORER_TIMEOUT_MS")
+            .map(|s| {
+                Duration::from_millis(s.parse().expect("Invalid SPAM_SCORER_TIMEOUT_MS env var"))
+            })
+            .unwrap_or(Duration::from_secs(5));
+
+        Self::new(url, hold_threshold, timeout)
+    }
+
+    pub fn new(url: Url, hold_threshold: f64, timeout: Duration) -> Self {
+        let client = reqwest::ClientBuilder::new()
+            .timeout(timeout)
+            .build()
+            .expect("Could not create spam scorer client");
+
+        Self {
+            inner: Some(Arc::new(Inner {
+                client,
+                url,
+                hold_threshold,
+            })),
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self { inner: None }
+    }
+
+    /// Score the raw message, returning a verdict if the message should be held
+    ///
+    /// If scoring is disabled or the scorer could not be reached, the message is never held.
+    pub async fn check(&self, raw_data: &[u8]) -> Option<SpamVerdict> {
+        let inner = self.inner.as_ref()?;
+
+        let score = match inner.score(raw_data).await {
+            Ok(score) => score,
+            Err(e) => {
+                warn!("could not score message for spam, skipping spam check: {e}");
+                return None;
+            }
+        };
+
+        debug!(score, "scored message for spam");
+
+        (score >= inner.hold_threshold).then_some(SpamVerdict {
+            score,
+            hold_threshold: inner.hold_threshold,
+        })
+    }
+}
+
+impl Inner {
+    async fn score(&self, raw_data: &[u8]) -> Result<f64, reqwest::Error> {
+        let result: ScanResult = self
+            .client
+            .post(self.url.clone())
+            .body(raw_data.to_vec())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(result.score)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use axum::{Json, Router, routing::post};
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    /// Scores messages containing "FREE MONEY" as spam
+    pub(crate) async fn mock_scorer() -> Url {
+        let router = Router::new().route(
+            "/checkv2",
+            post(|body: String| async move {
+                let score = if body.contains("FREE MONEY") {
+                    22.5
+                } else {
+                    0.3
+                };
+                Json(json!({ "score": score, "required_score": 15.0, "action": "no action" }))
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        format!("http://{addr}/checkv2").parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn spam_verdict() {
+        let scorer = SpamScorer::new(mock_scorer().await, 15.0, Duration::from_secs(2));
+
+        let ham = b"Subject: Hi!\r\n\r\nHello world!\r\n";
+        assert_eq!(scorer.check(ham).await, None);
+
+        let spam = b"Subject: Hi!\r\n\r\nGet FREE MONEY now!\r\n";
+        assert_eq!(
+            scorer.check(spam).await,
+            Some(SpamVerdict {
+                score: 22.5,
+                hold_threshold: 15.0
+            })
+        );
+
+        // disabled scoring never holds messages
+        assert_eq!(SpamScorer::disabled().check(spam).await, None);
+    }
+
+    #[tokio::test]
+    async fn unreachable_scorer() {
+        // bind and immediately drop a listener to get a port nobody listens on
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let scorer = SpamScorer::new(
+            format!("http://{addr}/checkv2").parse().unwrap(),
+            15.0,
+            Duration::from_millis(500),
+        );
+        let spam = b"Subject: Hi!\r\n\r\nGet FREE MONEY now!\r\n";
+        assert_eq!(scorer.check(spam).await, None);
+    }
+}
@@ -0,0 +1,148 @@
+//! Removal of configured headers from submitted messages
+//!
+//! Clients may add headers that should not leave Remails, e.g., `X-Originating-IP` revealing the
+//! address of the sender, or internal routing headers. These are removed before the message is
+//! signed, such that the DKIM signature covers the message as it is relayed.
+
+use crate::handler::header_fields::{Field, header_fields};
+
+/// Headers that identify the message or its sender, or that are needed to read or reply to it,
+/// which can never be stripped
+const PROTECTED_HEADERS: [&str; 11] = [
+    "From",
+    "Sender",
+    "Reply-To",
+    "To",
+    "Cc",
+    "Date",
+    "Message-ID",
+    "Subject",
+    "MIME-Version",
+    "Content-Type",
+    "Content-Transfer-Encoding",
+];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StrippedHeaders {
+    /// Lowercase names of the headers to remove
+    names: Vec<String>,
+}
+
+impl StrippedHeaders {
+    /// Fails with the first protected header in `names`, if any
+    pub fn new<S: AsRef<str>>(names: impl IntoIterator<Item = S>) -> Result<Self, String> {
+        let names: Vec<String> = names
+            .into_iter()
+            .map(|name| name.as_ref().trim().to_lowercase())
+            .filter(|name| !name.is_empty())
+            .collect();
+
+        if let Some(name) = names.iter().find(|name| {
+            PROTECTED_HEADERS
+                .iter()
+                .any(|protected| protected.eq_ignore_ascii_case(name))
+        }) {
+            return Err(name.clone());
+        }
+
+        Ok(Self { names })
+    }
+
+    /// Only the `Return-Path` header, which is replaced by one naming the envelope sender
+    pub(super) fn return_path() -> Self {
+        Self {
+            names: vec!["return-path".to_owned()],
+        }
+    }
+
+    /// Configure the headers using the `STRIP_HEADERS` environment variable, a comma-separated
+    /// list of header names, e.g., `X-Originating-IP,X-Internal-Route`
+    ///
+    /// Will panic if any of the headers is protected, e.g., `From` or `Date`
+    #[cfg(not(test))]
+    pub fn from_env() -> Self {
+        std::env::var("STRIP_HEADERS")
+            .map(|names| {
+                Self::new(names.split(','))
+                    .unwrap_or_else(|name| panic!("STRIP_HEADERS must not contain {name}"))
+            })
+            .unwrap_or_default()
+    }
+
+    /// Remove all occurrences of the configured headers from `raw_data`, returns `None` if the
+    /// message contains none of them
+    pub(super) fn strip(&self, raw_data: &[u8]) -> Option<Vec<u8>> {
+        if self.names.is_empty() {
+            return None;
+        }
+
+        let mut stripped = Vec::with_capacity(raw_data.len());
+        let mut any_stripped = false;
+
+        for field in header_fields(raw_data) {
+            match field {
+                Field::Header { name, raw, .. } => {
+                    if self
+                        .names
+                        .iter()
+                        .any(|stripped_name| name.eq_ignore_ascii_case(stripped_name.as_bytes()))
+                    {
+                        any_stripped = true;
+                    } else {
+                        stripped.extend_from_slice(raw);
+                    }
+                }
+                Field::Body(body) => {
+                    stripped.extend_from_slice(body);
+                    break;
+                }
+            }
+        }
+
+        any_stripped.then_some(stripped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip() {
+        let headers = StrippedHeaders::new(["X-Originating-IP", " x-internal-route "]).unwrap();
+        let message = b"From: john@example.com\r\n\
+            X-Originating-IP: [192.0.2.1]\r\n\
+            Subject: Hi!\r\n\
+            X-Internal-Route: a,\r\n b\r\n\
+            X-ORIGINATING-IP: [192.0.2.2]\r\n\
+            \r\n\
+            X-Originating-IP: in the body\r\n";
+
+        assert_eq!(
+            headers.strip(message).unwrap(),
+            b"From: john@example.com\r\n\
+            Subject: Hi!\r\n\
+            \r\n\
+            X-Originating-IP: in the body\r\n"
+        );
+        assert!(
+            headers
+                .strip(b"From: john@example.com\r\n\r\nHi!")
+                .is_none()
+        );
+        assert!(StrippedHeaders::default().strip(message).is_none());
+    }
+
+    #[test]
+    fn protected_headers() {
+        assert_eq!(
+            StrippedHeaders::new(["X-Originating-IP", "from"]),
+            Err("from".to_string())
+        );
+        assert!(StrippedHeaders::new(["Date"]).is_err());
+        assert!(StrippedHeaders::new(["Message-Id"]).is_err());
+        assert!(StrippedHeaders::new(["content-type"]).is_err());
+        assert!(StrippedHeaders::new(["Content-Transfer-Encoding"]).is_err());
+        assert!(StrippedHeaders::new(["Reply-To"]).is_err());
+    }
+}
@@ -0,0 +1,196 @@
+//! TLS policy of outbound connections
+//!
+//! Operators can require a minimum TLS version and restrict the offered cipher suites, e.g., to
+//! meet compliance requirements. In strict mode, messages are never sent over plaintext, such that
+//! receivers that cannot meet the policy are retried later instead.
+
+use std::{env, fmt::Debug, sync::Arc};
+use tokio_rustls::{
+    TlsConnector,
+    rustls::{
+        ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+        SupportedProtocolVersion,
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::{self, CryptoProvider, verify_tls12_signature, verify_tls13_signature},
+        pki_types::{CertificateDer, ServerName, UnixTime},
+        version::{TLS12, TLS13},
+    },
+};
+
+/// Lowest TLS version offered to receivers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TlsVersion {
+    #[default]
+    Tls12,
+    Tls13,
+}
+
+impl TlsVersion {
+    fn protocol_versions(self) -> &'static [&'static SupportedProtocolVersion] {
+        match self {
+            TlsVersion::Tls12 => &[&TLS13, &TLS12],
+            TlsVersion::Tls13 => &[&TLS13],
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct OutboundTlsPolicy {
+    min_version: TlsVersion,
+    /// Only these cipher suites are offered, by their IANA name, all supported suites if `None`
+    cipher_suites: Option<Vec<String>>,
+    /// Never fall back to plaintext, even if the project allows it
+    pub(crate) strict: bool,
+    connector: TlsConnector,
+    allow_invalid_certs_connector: TlsConnector,
+}
+
+impl OutboundTlsPolicy {
+    /// Configure the outbound TLS policy using the following environment variables:
+    /// - `OUTBOUND_TLS_MIN_VERSION`: either `1.2` (default) or `1.3`
+    /// - `OUTBOUND_TLS_CIPHER_SUITES`: comma-separated list of cipher suites to offer,
+    ///   e.g., `TLS13_AES_256_GCM_SHA384,TLS13_CHACHA20_POLY1305_SHA256`, defaults to all supported
+    /// - `OUTBOUND_TLS_STRICT`: `true` to never fall back to plaintext, defaults to `false`
+    ///
+    /// Will panic if any of the variables is set to an invalid value
+    pub fn from_env() -> Self {
+        let min_version = match env::var("OUTBOUND_TLS_MIN_VERSION").as_deref() {
+            Err(_) | Ok("1.2") => TlsVersion::Tls12,
+            Ok("1.3") => TlsVersion::Tls13,
+            Ok(other) => panic!("Invalid OUTBOUND_TLS_MIN_VERSION env var: {other}"),
+        };
+        let cipher_suites = env::var("OUTBOUND_TLS_CIPHER_SUITES").ok().map(|suites| {
+            suites
+                .split(',')
+                .map(|suite| suite.trim().to_owned())
+                .filter(|suite| !suite.is_empty())
+                .collect()
+        });
+        let strict = env::var("OUTBOUND_TLS_STRICT")
+            .map(|strict| strict.parse().expect("Invalid OUTBOUND_TLS_STRICT env var"))
+            .unwrap_or(false);
+
+        Self::new(min_version, cipher_suites, strict)
+    }
+
+    /// Will panic if none of the `cipher_suites` are supported for the minimum TLS version,
+    /// or if any of them is unknown
+    pub fn new(min_version: TlsVersion, cipher_suites: Option<Vec<String>>, strict: bool) -> Self {
+        let mut provider = crypto::aws_lc_rs::default_provider();
+        if let Some(allowed) = &cipher_suites {
+            for name in allowed {
+                assert!(
+                    provider
+                        .cipher_suites
+                        .iter()
+                        .any(|suite| suite.suite().as_str() == Some(name.as_str())),
+                    "Unsupported outbound TLS cipher suite: {name}"
+                );
+            }
+            provider.cipher_suites.retain(|suite| {
+                suite
+                    .suite()
+                    .as_str()
+                    .is_some_and(|name| allowed.iter().any(|allowed| allowed == name))
+            });
+        }
+        let provider = Arc::new(provider);
+
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        let config = ClientConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(min_version.protocol_versions())
+            .expect("No outbound TLS cipher suites left for the minimum TLS version")
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        let allow_invalid_certs_config = ClientConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(min_version.protocol_versions())
+            .expect("No outbound TLS cipher suites left for the minimum TLS version")
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)))
+            .with_no_client_auth();
+
+        Self {
+            min_version,
+            cipher_suites,
+            strict,
+            connector: TlsConnector::from(Arc::new(config)),
+            allow_invalid_certs_connector: TlsConnector::from(Arc::new(allow_invalid_certs_config)),
+        }
+    }
+
+    pub(crate) fn connector(&self, allow_invalid_certs: bool) -> TlsConnector {
+        if allow_invalid_certs {
+            self.allow_invalid_certs_connector.clone()
+        } else {
+            self.connector.clone()
+        }
+    }
+}
+
+impl Default for OutboundTlsPolicy {
+    fn default() -> Self {
+        Self::new(TlsVersion::default(), None, false)
+    }
+}
+
+impl Debug for OutboundTlsPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OutboundTlsPolicy")
+            .field("min_version", &self.min_version)
+            .field("cipher_suites", &self.cipher_suites)
+            .field("strict", &self.strict)
+            .finish()
+    }
+}
+
+/// Accepts any certificate, but still checks the handshake signatures
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
@@ -0,0 +1,462 @@
+//! Transcript of the SMTP exchange with receivers
+//!
+//! The connection to the receiver is wrapped in a [`Recorded`] stream, such that the commands
+//! and replies that `mail-send` exchanges on our behalf end up in the transcript as well. The
+//! message data and authentication credentials are never recorded, and the transcript is bounded
+//! in size, as it is stored with the message.
+
+use mail_send::{Credentials, SmtpClient, smtp::AssertReply};
+use serde::{Deserialize, Serialize};
+use smtp_proto::{EXT_START_TLS, EhloResponse};
+use std::{
+    fmt::Display,
+    io,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpSocket, TcpStream},
+};
+use tokio_rustls::{TlsConnector, client::TlsStream};
+use utoipa::ToSchema;
+
+const MAX_LINES: usize = 200;
+const MAX_LINE_LENGTH: usize = 512;
+
+/// Commands (`C:`) and replies (`S:`) exchanged with the receivers of a recipient,
+/// during the last delivery attempt
+#[derive(Debug, Deserialize, Serialize, Default, Clone, ToSchema)]
+pub struct Transcript {
+    lines: Vec<String>,
+    /// Lines were left out, because the transcript grew too large
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    truncated: bool,
+}
+
+impl Transcript {
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Add a line that is not part of the SMTP exchange, e.g., to mark a new connection
+    pub fn note(&mut self, note: impl Display) {
+        self.push(format!("* {note}"));
+    }
+
+    /// Append the lines of another transcript, e.g., one of a separate connection
+    pub fn append(&mut self, other: Transcript) {
+        self.truncated |= other.truncated;
+        for line in other.lines {
+            self.push(line);
+        }
+    }
+
+    fn push(&mut self, mut line: String) {
+        if self.lines.len() >= MAX_LINES {
+            self.truncated = true;
+            return;
+        }
+
+        if line.len() > MAX_LINE_LENGTH {
+            let mut end = MAX_LINE_LENGTH;
+            while !line.is_char_boundary(end) {
+                end -= 1;
+            }
+            line.truncate(end);
+            line.push_str("...");
+        }
+        self.lines.push(line);
+    }
+}
+
+/// Where the client is in the SMTP exchange, to know what not to record
+#[derive(Debug, Default, PartialEq, Eq)]
+enum State {
+    #[default]
+    Command,
+    /// Sent `DATA`, waiting for the `354` reply
+    AwaitingData,
+    /// Sending the message data, with the last bytes sent to detect the terminating `.` line
+    Data(Vec<u8>),
+    /// Sent `AUTH`, the client responds to `334` challenges with credentials
+    Auth,
+}
+
+#[derive(Debug, Default)]
+struct Recorder {
+    transcript: Transcript,
+    state: State,
+    client: Vec<u8>,
+    server: Vec<u8>,
+}
+
+impl Recorder {
+    fn client_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if let State::Data(tail) = &mut self.state {
+                if tail.len() == 5 {
+                    tail.remove(0);
+                }
+                tail.push(byte);
+                if tail == b"\r\n.\r\n" {
+                    self.transcript.push("C: [message data omitted]".to_owned());
+                    self.transcript.push("C: .".to_owned());
+                    self.state = State::Command;
+                }
+                continue;
+            }
+
+            if byte == b'\n' {
+                let line = std::mem::take(&mut self.client);
+                self.client_line(String::from_utf8_lossy(&line).trim_end());
+            } else if self.client.len() <= MAX_LINE_LENGTH {
+                self.client.push(byte);
+            }
+        }
+    }
+
+    fn server_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if byte == b'\n' {
+                let line = std::mem::take(&mut self.server);
+                self.server_line(String::from_utf8_lossy(&line).trim_end());
+            } else if self.server.len() <= MAX_LINE_LENGTH {
+                self.server.push(byte);
+            }
+        }
+    }
+
+    fn client_line(&mut self, line: &str) {
+        let command = line.to_ascii_uppercase();
+        let line = if self.state == State::Auth {
+            "C: [credentials redacted]".to_owned()
+        } else if command.starts_with("AUTH ") {
+            self.state = State::Auth;
+            let mechanism = line.split_whitespace().nth(1).unwrap_or_default();
+            format!("C: AUTH {mechanism} [credentials redacted]")
+        } else {
+            if command == "DATA" {
+                self.state = State::AwaitingData;
+            }
+            format!("C: {line}")
+        };
+        self.transcript.push(line);
+    }
+
+    fn server_line(&mut self, line: &str) {
+        self.transcript.push(format!("S: {line}"));
+
+        // only the last line of a multiline reply has a space after the code
+        let is_last = line
+            .as_bytes()
+            .get(3)
+            .is_none_or(|&separator| separator == b' ');
+        if !is_last {
+            return;
+        }
+
+        let code = line.get(..3).unwrap_or_default();
+        self.state = match (std::mem::take(&mut self.state), code) {
+            (State::AwaitingData, "354") => State::Data(b"\r\n".to_vec()),
+            (State::Auth, "334") => State::Auth,
+            (State::AwaitingData | State::Auth, _) => State::Command,
+            (state, _) => state,
+        };
+    }
+}
+
+/// Shared handle to the transcript of a connection, such that it survives the connection itself
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Recording(Arc<Mutex<Recorder>>);
+
+impl Recording {
+    pub(crate) fn note(&self, note: impl Display) {
+        self.lock().transcript.note(note);
+    }
+
+    /// Take the recorded transcript, including any unfinished lines
+    pub(crate) fn finish(&self) -> Transcript {
+        let mut recorder = self.lock();
+        for partial in [
+            std::mem::take(&mut recorder.client),
+            std::mem::take(&mut recorder.server),
+        ] {
+            if !partial.is_empty() {
+                let partial = String::from_utf8_lossy(&partial).into_owned();
+                recorder
+                    .transcript
+                    .note(format!("incomplete line: {partial}"));
+            }
+        }
+        std::mem::take(&mut recorder.transcript)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Recorder> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Stream that records the SMTP exchange passing through it
+pub(crate) struct Recorded<S> {
+    inner: S,
+    recording: Recording,
+}
+
+impl<S> Recorded<S> {
+    fn new(inner: S, recording: &Recording) -> Self {
+        Self {
+            inner,
+            recording: recording.clone(),
+        }
+    }
+
+    fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Recorded<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            this.recording.lock().server_bytes(&buf.filled()[before..]);
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Recorded<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            this.recording.lock().client_bytes(&buf[..written]);
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Connection parameters, mirroring those of [`mail_send::SmtpClientBuilder`]
+pub(crate) struct Upstream<'a> {
+    pub hostname: &'a str,
+    pub port: u16,
+    /// Used to authenticate after saying EHLO, if set
+    pub credentials: Option<&'a Credentials<String>>,
+    pub local_ip: IpAddr,
+    pub helo_host: &'a str,
+    pub timeout: Duration,
+}
+
+impl Upstream<'_> {
+    /// Connect over plaintext and say EHLO, like [`mail_send::SmtpClientBuilder::connect_plain`]
+    ///
+    /// Never authenticates, such that credentials are not sent without TLS.
+    /// Returns the client along with the capabilities the server advertised.
+    pub(crate) async fn connect_plain(
+        &self,
+        recording: &Recording,
+    ) -> mail_send::Result<(SmtpClient<Recorded<TcpStream>>, EhloResponse<String>)> {
+        tokio::time::timeout(self.timeout, async {
+            let mut client = SmtpClient {
+                stream: Recorded::new(self.tcp_stream(recording).await?, recording),
+                timeout: self.timeout,
+            };
+            client.read().await?.assert_positive_completion()?;
+            let capabilities = client.capabilities(self.helo_host, false).await?;
+            Ok((client, capabilities))
+        })
+        .await
+        .map_err(|_| mail_send::Error::Timeout)?
+    }
+
+    /// Connect using STARTTLS and say EHLO, like [`mail_send::SmtpClientBuilder::connect`]
+    ///
+    /// Returns the client along with the capabilities the server advertised after STARTTLS.
+    pub(crate) async fn connect_tls(
+        &self,
+        tls_connector: &TlsConnector,
+        recording: &Recording,
+    ) -> mail_send::Result<(
+        SmtpClient<Recorded<TlsStream<TcpStream>>>,
+        EhloResponse<String>,
+    )> {
+        tokio::time::timeout(self.timeout, async {
+            let mut client = SmtpClient {
+                stream: Recorded::new(self.tcp_stream(recording).await?, recording),
+                timeout: self.timeout,
+            };
+            client.read().await?.assert_positive_completion()?;
+            let response = client.ehlo(self.helo_host).await?;
+            if !response.has_capability(EXT_START_TLS) {
+                return Err(mail_send::Error::MissingStartTls);
+            }
+            client
+                .cmd(b"STARTTLS\r\n")
+                .await?
+                .assert_positive_completion()?;
+
+            // the TLS handshake itself is not part of the transcript
+            let client = SmtpClient {
+                stream: client.stream.into_inner(),
+                timeout: self.timeout,
+            }
+            .into_tls(tls_connector, self.hostname)
+            .await?;
+            let (_, connection) = client.stream.get_ref();
+            let version = connection
+                .protocol_version()
+                .and_then(|version| version.as_str())
+                .unwrap_or("unknown version");
+            let suite = connection
+                .negotiated_cipher_suite()
+                .and_then(|suite| suite.suite().as_str())
+                .unwrap_or("unknown cipher suite");
+            recording.note(format_args!("TLS established using {version} with {suite}"));
+
+            let mut client = SmtpClient {
+                stream: Recorded::new(client.stream, recording),
+                timeout: self.timeout,
+            };
+            let capabilities = client.capabilities(self.helo_host, false).await?;
+            if let Some(credentials) = self.credentials {
+                client.authenticate(credentials, &capabilities).await?;
+            }
+            Ok((client, capabilities))
+        })
+        .await
+        .map_err(|_| mail_send::Error::Timeout)?
+    }
+
+    async fn tcp_stream(&self, recording: &Recording) -> io::Result<TcpStream> {
+        let mut last_err = None;
+        for addr in tokio::net::lookup_host((self.hostname, self.port)).await? {
+            recording.note(format_args!("connecting to {} ({addr})", self.hostname));
+            let socket = match self.local_ip {
+                IpAddr::V4(_) => TcpSocket::new_v4()?,
+                IpAddr::V6(_) => TcpSocket::new_v6()?,
+            };
+            socket.bind(SocketAddr::new(self.local_ip, 0))?;
+
+            match socket.connect(addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(err) => {
+                    recording.note(format_args!("could not connect: {err}"));
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any address",
+            )
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(exchange: &[(bool, &[u8])]) -> Vec<String> {
+        let recording = Recording::default();
+        for (from_client, bytes) in exchange {
+            let mut recorder = recording.lock();
+            if *from_client {
+                recorder.client_bytes(bytes);
+            } else {
+                recorder.server_bytes(bytes);
+            }
+        }
+        recording.finish().lines
+    }
+
+    #[test]
+    fn omits_message_data() {
+        let lines = record(&[
+            (true, b"DATA\r\n"),
+            (false, b"354 Start mail input\r\n"),
+            (true, b"Subject: secret\r\n\r\nHello"),
+            (true, b" world\r\n"),
+            (true, b".\r\n"),
+            (false, b"250 2.0.0 OK\r\n"),
+            (true, b"QUIT\r\n"),
+        ]);
+
+        assert_eq!(
+            lines,
+            [
+                "C: DATA",
+                "S: 354 Start mail input",
+                "C: [message data omitted]",
+                "C: .",
+                "S: 250 2.0.0 OK",
+                "C: QUIT"
+            ]
+        );
+    }
+
+    #[test]
+    fn redacts_credentials() {
+        let lines = record(&[
+            (true, b"AUTH LOGIN dXNlcg==\r\n"),
+            (false, b"334 UGFzc3dvcmQ6\r\n"),
+            (true, b"c2VjcmV0\r\n"),
+            (false, b"235 2.7.0 Authentication successful\r\n"),
+            (true, b"MAIL FROM:<john@example.com>\r\n"),
+        ]);
+
+        assert_eq!(
+            lines,
+            [
+                "C: AUTH LOGIN [credentials redacted]",
+                "S: 334 UGFzc3dvcmQ6",
+                "C: [credentials redacted]",
+                "S: 235 2.7.0 Authentication successful",
+                "C: MAIL FROM:<john@example.com>"
+            ]
+        );
+    }
+
+    #[test]
+    fn bounded_size() {
+        let mut transcript = Transcript::default();
+        for _ in 0..MAX_LINES + 10 {
+            transcript.push("x".repeat(MAX_LINE_LENGTH * 2));
+        }
+
+        assert_eq!(transcript.lines.len(), MAX_LINES);
+        assert!(transcript.truncated);
+        assert!(
+            transcript
+                .lines
+                .iter()
+                .all(|line| line.len() == MAX_LINE_LENGTH + 3)
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! Variable Envelope Return Path (VERP)
+//!
+//! Encodes the message ID and the recipient in the envelope sender of each outgoing message,
+//! such that a bounce can be attributed to the exact recipient that bounced.
+//! A VERP address looks like `bounce+<message id>-<recipient hash>@<sender domain>`.
+
+use aws_lc_rs::digest;
+use email_address::EmailAddress;
+use std::fmt::Write;
+
+use crate::models::MessageId;
+
+const PREFIX: &str = "bounce+";
+/// Number of bytes of the SHA-256 digest of the recipient that are included in the address
+const HASH_BYTES: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerpAddress {
+    message_id: MessageId,
+    recipient_hash: String,
+}
+
+impl VerpAddress {
+    pub fn new(message_id: MessageId, recipient: &EmailAddress) -> Self {
+        Self {
+            message_id,
+            recipient_hash: Self::hash(recipient),
+        }
+    }
+
+    /// Truncated, hex-encoded SHA-256 digest of the (case-insensitive) recipient address
+    fn hash(recipient: &EmailAddress) -> String {
+        let digest = digest::digest(&digest::SHA256, recipient.email().to_lowercase().as_bytes());
+
+        digest.as_ref()[..HASH_BYTES].iter().fold(
+            String::with_capacity(2 * HASH_BYTES),
+            |mut hex, b| {
+                let _ = write!(hex, "{b:02x}");
+                hex
+            },
+        )
+    }
+
+    /// Envelope sender address within `domain`
+    ///
+    /// The local part is 56 characters long, which is well within the 64-character limit of RFC 5321
+    pub fn to_address(&self, domain: &str) -> String {
+        format!(
+            "{PREFIX}{}-{}@{domain}",
+            self.message_id.simple(),
+            self.recipient_hash
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn address_format() {
+        let message_id: MessageId = "e165562a-fb6d-423b-b318-fd26f4610634".parse().unwrap();
+        let recipients: Vec<EmailAddress> = vec![
+            "info@recipient1.com".parse().unwrap(),
+            "info@recipient2.com".parse().unwrap(),
+        ];
+
+        let addresses = recipients
+            .iter()
+            .map(|recipient| VerpAddress::new(message_id, recipient).to_address("test-org-1.com"))
+            .collect::<Vec<_>>();
+
+        for address in &addresses {
+            assert!(address.starts_with("bounce+e165562afb6d423bb318fd26f4610634-"));
+            assert!(address.ends_with("@test-org-1.com"));
+
+            // the address must be a valid email address within the sender domain
+            let parsed_address: EmailAddress = address.parse().unwrap();
+            assert_eq!(parsed_address.domain(), "test-org-1.com");
+            assert!(parsed_address.local_part().len() <= 64);
+        }
+
+        // each recipient gets its own address
+        assert_ne!(addresses[0], addresses[1]);
+    }
+
+    #[test]
+    fn recipient_is_case_insensitive() {
+        let message_id = MessageId::new_v4();
+
+        assert_eq!(
+            VerpAddress::new(message_id, &"Info@Recipient1.com".parse().unwrap()),
+            VerpAddress::new(message_id, &"info@recipient1.com".parse().unwrap())
+        );
+    }
+}
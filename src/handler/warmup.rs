@@ -0,0 +1,120 @@
+//! Pacing of outbound IPs that are warming up
+//!
+//! Receivers distrust IPs without a sending history, and a new IP that suddenly sends many
+//! messages is likely to be blocklisted. New IPs therefore send a limited number of messages per
+//! hour, a limit that increases with every day since the IP was added. Messages beyond the limit
+//! are retried in the next hour, possibly from another IP.
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WarmupSchedule {
+    /// Maximum number of messages per hour on each day of the warm-up, the IP is warm after the
+    /// last day. An empty schedule does not limit any IP.
+    hourly_limits: Vec<u32>,
+}
+
+impl WarmupSchedule {
+    pub fn new(hourly_limits: Vec<u32>) -> Self {
+        Self { hourly_limits }
+    }
+
+    /// Configure the schedule using the `IP_WARMUP_SCHEDULE` environment variable, a
+    /// comma-separated list of hourly limits for each day, e.g., `50,100,250,500,1000`
+    ///
+    /// Will panic if any of the limits is not a positive integer
+    #[cfg(not(test))]
+    pub fn from_env() -> Self {
+        std::env::var("IP_WARMUP_SCHEDULE")
+            .map(|schedule| {
+                Self::parse(&schedule).expect(
+                    "IP_WARMUP_SCHEDULE must be a comma-separated list of positive hourly limits",
+                )
+            })
+            .unwrap_or_default()
+    }
+
+    fn parse(schedule: &str) -> Option<Self> {
+        let hourly_limits = schedule
+            .split(',')
+            .map(str::trim)
+            .filter(|limit| !limit.is_empty())
+            .map(|limit| {
+                limit
+                    .parse::<std::num::NonZeroU32>()
+                    .ok()
+                    .map(|limit| limit.get())
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self::new(hourly_limits))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hourly_limits.is_empty()
+    }
+
+    /// The number of messages an IP that started warming up at `started_at` may send this hour,
+    /// `None` if it is warm
+    pub fn hourly_limit(&self, started_at: DateTime<Utc>, now: DateTime<Utc>) -> Option<u32> {
+        let day = (now - started_at).num_days().max(0);
+
+        self.hourly_limits.get(usize::try_from(day).ok()?).copied()
+    }
+}
+
+/// The start of the next hour, when the hourly limits reset
+pub(super) fn next_hour(now: DateTime<Utc>) -> DateTime<Utc> {
+    let hour = Duration::hours(1);
+
+    now.duration_trunc(hour).unwrap_or(now) + hour
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hourly_limit() {
+        let schedule = WarmupSchedule::parse("50, 100,250").unwrap();
+        let started_at: DateTime<Utc> = "2026-10-01T12:00:00Z".parse().unwrap();
+        let at = |time: &str| time.parse::<DateTime<Utc>>().unwrap();
+
+        assert_eq!(
+            schedule.hourly_limit(started_at, at("2026-10-01T12:00:00Z")),
+            Some(50)
+        );
+        assert_eq!(
+            schedule.hourly_limit(started_at, at("2026-10-02T11:59:59Z")),
+            Some(50)
+        );
+        assert_eq!(
+            schedule.hourly_limit(started_at, at("2026-10-02T12:00:00Z")),
+            Some(100)
+        );
+        assert_eq!(
+            schedule.hourly_limit(started_at, at("2026-10-03T18:00:00Z")),
+            Some(250)
+        );
+        assert_eq!(
+            schedule.hourly_limit(started_at, at("2026-10-04T12:00:00Z")),
+            None
+        );
+
+        assert!(WarmupSchedule::parse("").unwrap().is_empty());
+        assert!(WarmupSchedule::parse("50,0").is_none());
+        assert!(WarmupSchedule::parse("50,lots").is_none());
+    }
+
+    #[test]
+    fn next_hour_resets() {
+        assert_eq!(
+            next_hour("2026-10-01T12:34:56.789Z".parse().unwrap()),
+            "2026-10-01T13:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(
+            next_hour("2026-10-01T23:00:00Z".parse().unwrap()),
+            "2026-10-02T00:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+    }
+}
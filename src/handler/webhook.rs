@@ -0,0 +1,408 @@
+//! Delivery webhooks
+//!
+//! After each delivery attempt, an event is posted to the webhook of the organization, if it
+//! configured one. Requests carry a `Remails-Signature` header of the form
+//! `t=<unix timestamp>,v1=<signature>`, where the signature is the Base64-encoded HMAC-SHA256
+//! of `<timestamp>.<request body>` using the current signing secret of the webhook.
+//!
+//! Receivers should accept a signature made with either the current or the previous secret,
+//! such that the secret can be rotated without rejecting requests in the meantime.
+//!
+//! Webhook hosts are resolved right before sending, and only public addresses are connected to,
+//! such that a host name that later resolves to an internal address can't be used to reach
+//! services within our own network.
+
+use aws_lc_rs::hmac;
+use base64ct::{Base64, Encoding};
+use chrono::{DateTime, Utc};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::{net::IpAddr, sync::Arc, time::Duration};
+use tokio::sync::Semaphore;
+use tracing::{debug, warn};
+use url::{Host, Url};
+
+use crate::models::{
+    Message, MessageId, MessageStatus, OrganizationId, ProjectId, WebhookRepository,
+    is_public_address,
+};
+
+pub const SIGNATURE_HEADER: &str = "Remails-Signature";
+/// Maximum age of a signature timestamp accepted by [`verify`], to prevent replaying requests
+const SIGNATURE_TOLERANCE: chrono::Duration = chrono::Duration::minutes(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Maximum number of webhook requests in flight, further events are dropped until one finishes
+const MAX_CONCURRENT_REQUESTS: usize = 64;
+
+/// Sign a webhook request body, returns the value of the signature header
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, &signed_payload(timestamp, body));
+
+    format!("t={timestamp},v1={}", Base64::encode_string(tag.as_ref()))
+}
+
+/// Verify the signature header of a webhook request against any of the given secrets
+///
+/// Pass both the current and previous secret while a rotation is in progress.
+pub fn verify(secrets: &[&str], header: &str, body: &[u8], now: DateTime<Utc>) -> bool {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", t)) => timestamp = t.parse::<i64>().ok(),
+            Some(("v1", signature)) => signatures.extend(Base64::decode_vec(signature).ok()),
+            _ => {}
+        }
+    }
+
+    let Some(timestamp) = timestamp else {
+        return false;
+    };
+    let Some(signed_at) = DateTime::from_timestamp(timestamp, 0) else {
+        return false;
+    };
+    if (now - signed_at).abs() > SIGNATURE_TOLERANCE {
+        return false;
+    }
+
+    let payload = signed_payload(timestamp, body);
+    secrets.iter().any(|secret| {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        signatures
+            .iter()
+            .any(|signature| hmac::verify(&key, &payload, signature).is_ok())
+    })
+}
+
+fn signed_payload(timestamp: i64, body: &[u8]) -> Vec<u8> {
+    let mut payload = format!("{timestamp}.").into_bytes();
+    payload.extend_from_slice(body);
+    payload
+}
+
+/// Event posted to the webhook after a delivery attempt
+#[derive(Debug, Serialize)]
+#[cfg_attr(test, derive(serde::Deserialize))]
+pub struct DeliveryEvent {
+    pub message_id: MessageId,
+    pub organization_id: OrganizationId,
+    pub project_id: ProjectId,
+    pub status: MessageStatus,
+    pub reason: Option<String>,
+    pub attempts: i32,
+    pub occurred_at: DateTime<Utc>,
+}
+
+impl From<&Message> for DeliveryEvent {
+    fn from(message: &Message) -> Self {
+        Self {
+            message_id: message.id(),
+            organization_id: message.organization_id,
+            project_id: message.project_id,
+            status: message.status.clone(),
+            reason: message.reason.clone(),
+            attempts: message.attempts,
+            occurred_at: Utc::now(),
+        }
+    }
+}
+
+/// Resolves webhook hosts to the addresses webhook requests may be sent to
+struct WebhookResolver {
+    allow_address: fn(IpAddr) -> bool,
+}
+
+impl Resolve for WebhookResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let allow_address = self.allow_address;
+        Box::pin(async move {
+            let addresses = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|address| allow_address(address.ip()))
+                .collect::<Vec<_>>();
+
+            if addresses.is_empty() {
+                return Err(format!(
+                    "webhook host {} does not resolve to a public address",
+                    name.as_str()
+                )
+                .into());
+            }
+
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct WebhookSender {
+    repository: WebhookRepository,
+    client: reqwest::Client,
+    allow_address: fn(IpAddr) -> bool,
+    requests: Arc<Semaphore>,
+}
+
+impl WebhookSender {
+    pub fn new(pool: PgPool) -> Self {
+        Self::with_address_filter(pool, is_public_address)
+    }
+
+    fn with_address_filter(pool: PgPool, allow_address: fn(IpAddr) -> bool) -> Self {
+        let client = reqwest::ClientBuilder::new()
+            .timeout(REQUEST_TIMEOUT)
+            .user_agent("remails")
+            .redirect(reqwest::redirect::Policy::none())
+            .dns_resolver(Arc::new(WebhookResolver { allow_address }))
+            .build()
+            .expect("Could not create webhook client");
+
+        Self {
+            repository: WebhookRepository::new(pool),
+            client,
+            allow_address,
+            requests: Arc::new(Semaphore::new(MAX_CONCURRENT_REQUESTS)),
+        }
+    }
+
+    /// Post the event to the webhook of the organization in the background
+    ///
+    /// Webhook requests are sent once, failures are logged but do not affect the delivery.
+    /// The event is dropped if too many webhook requests are in flight already.
+    pub fn notify(&self, event: DeliveryEvent) {
+        let message_id = event.message_id.to_string();
+        let Ok(permit) = self.requests.clone().try_acquire_owned() else {
+            warn!(
+                message_id,
+                "too many webhook requests in flight, dropping webhook event"
+            );
+            return;
+        };
+
+        let sender = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = sender.send(&event).await {
+                warn!(message_id, "failed to deliver webhook event: {e}");
+            }
+            drop(permit);
+        });
+    }
+
+    pub(crate) async fn send(&self, event: &DeliveryEvent) -> Result<(), WebhookError> {
+        let Some((url, secrets)) = self
+            .repository
+            .delivery_target(event.organization_id)
+            .await?
+        else {
+            return Ok(());
+        };
+
+        // host names are checked by the resolver, but IP addresses are connected to directly
+        let parsed = Url::parse(&url).map_err(|_| WebhookError::InvalidUrl)?;
+        let ip = match parsed.host() {
+            Some(Host::Ipv4(ip)) => Some(IpAddr::from(ip)),
+            Some(Host::Ipv6(ip)) => Some(IpAddr::from(ip)),
+            Some(Host::Domain(_)) => None,
+            None => return Err(WebhookError::InvalidUrl),
+        };
+        if let Some(ip) = ip
+            && !(self.allow_address)(ip)
+        {
+            return Err(WebhookError::InternalAddress(ip.to_string()));
+        }
+
+        let body = serde_json::to_vec(event)?;
+        let signature = sign(&secrets.current, Utc::now().timestamp(), &body);
+
+        let response = self
+            .client
+            .post(&url)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, signature)
+            .body(body)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(WebhookError::Status(response.status()));
+        }
+
+        debug!(
+            message_id = event.message_id.to_string(),
+            "delivered webhook event"
+        );
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookError {
+    #[error(transparent)]
+    Repository(#[from] crate::models::Error),
+    #[error(transparent)]
+    Serialization(#[from] serde_json::Error),
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+    #[error("webhook responded with status {0}")]
+    Status(http::StatusCode),
+    #[error("invalid webhook URL")]
+    InvalidUrl,
+    #[error("webhook host {0} does not resolve to a public address")]
+    InternalAddress(String),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        models::{SYSTEM, WebhookRequest},
+        test::TestProjects,
+    };
+    use axum::{Router, extract::State, http::HeaderMap, routing::post};
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn signature_round_trip() {
+        let now = Utc::now();
+        let header = sign("secret", now.timestamp(), b"{}");
+
+        assert!(verify(&["secret"], &header, b"{}", now));
+        assert!(!verify(&["other secret"], &header, b"{}", now));
+        assert!(!verify(&["secret"], &header, b"{\"tampered\":1}", now));
+        // replayed requests are rejected
+        assert!(!verify(
+            &["secret"],
+            &header,
+            b"{}",
+            now + chrono::Duration::minutes(6)
+        ));
+        assert!(!verify(&["secret"], "v1=invalid", b"{}", now));
+    }
+
+    #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations")))]
+    async fn both_secrets_are_valid_during_rotation(pool: PgPool) {
+        let org_id = TestProjects::Org1Project1.org_id();
+        let repository = WebhookRepository::new(pool);
+        repository
+            .set(
+                org_id,
+                &WebhookRequest {
+                    url: "https://example.com/webhook".to_string(),
+                },
+                SYSTEM,
+            )
+            .await
+            .unwrap();
+
+        let now = Utc::now();
+        let original = repository.secrets(org_id).await.unwrap();
+        assert!(original.previous.is_none());
+        let signed_before = sign(&original.current, now.timestamp(), b"{}");
+
+        let rotated = repository.rotate_secret(org_id, SYSTEM).await.unwrap();
+        assert_ne!(rotated.current, original.current);
+        assert_eq!(rotated.previous.as_deref(), Some(original.current.as_str()));
+        let signed_after = sign(&rotated.current, now.timestamp(), b"{}");
+
+        // a receiver accepting both secrets accepts requests signed before and after the rotation
+        let accepted = [
+            rotated.current.as_str(),
+            rotated.previous.as_deref().unwrap(),
+        ];
+        assert!(verify(&accepted, &signed_before, b"{}", now));
+        assert!(verify(&accepted, &signed_after, b"{}", now));
+        // once the receiver switched to the new secret, old signatures are rejected
+        assert!(!verify(
+            &[rotated.current.as_str()],
+            &signed_before,
+            b"{}",
+            now
+        ));
+
+        // after another rotation, the original secret is no longer valid
+        let rotated_again = repository.rotate_secret(org_id, SYSTEM).await.unwrap();
+        let accepted = [
+            rotated_again.current.as_str(),
+            rotated_again.previous.as_deref().unwrap(),
+        ];
+        assert!(verify(&accepted, &signed_after, b"{}", now));
+        assert!(!verify(&accepted, &signed_before, b"{}", now));
+    }
+
+    #[derive(Clone, Default)]
+    struct Received(Arc<Mutex<Vec<(String, Vec<u8>)>>>);
+
+    async fn receive(
+        State(received): State<Received>,
+        headers: HeaderMap,
+        body: axum::body::Bytes,
+    ) {
+        let signature = headers[SIGNATURE_HEADER].to_str().unwrap().to_string();
+        received.0.lock().unwrap().push((signature, body.to_vec()));
+    }
+
+    #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "projects")))]
+    async fn sender_signs_with_current_secret(pool: PgPool) {
+        let received = Received::default();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = Router::new()
+            .route("/webhook", post(receive))
+            .with_state(received.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let (org_id, project_id) = TestProjects::Org1Project1.get_ids();
+        // the receiver listens on a loopback address, which can't be set as webhook URL
+        sqlx::query!(
+            "INSERT INTO webhooks (organization_id, url, signing_secret) VALUES ($1, $2, 'secret')",
+            *org_id,
+            format!("http://localhost:{port}/webhook"),
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let repository = WebhookRepository::new(pool.clone());
+        let previous = repository.secrets(org_id).await.unwrap();
+        let secrets = repository.rotate_secret(org_id, SYSTEM).await.unwrap();
+
+        let event = DeliveryEvent {
+            message_id: "e165562a-fb6d-423b-b318-fd26f4610634".parse().unwrap(),
+            organization_id: org_id,
+            project_id,
+            status: MessageStatus::Delivered,
+            reason: None,
+            attempts: 1,
+            occurred_at: Utc::now(),
+        };
+        // a host that resolves to an internal address is refused
+        assert!(WebhookSender::new(pool.clone()).send(&event).await.is_err());
+        assert!(received.0.lock().unwrap().is_empty());
+
+        WebhookSender::with_address_filter(pool, |ip| ip.is_loopback())
+            .send(&event)
+            .await
+            .unwrap();
+
+        let received = received.0.lock().unwrap();
+        let (signature, body) = &received[0];
+        assert!(verify(
+            &[secrets.current.as_str()],
+            signature,
+            body,
+            Utc::now()
+        ));
+        assert!(!verify(
+            &[previous.current.as_str()],
+            signature,
+            body,
+            Utc::now()
+        ));
+
+        let delivered: DeliveryEvent = serde_json::from_slice(body).unwrap();
+        assert_eq!(delivered.message_id, event.message_id);
+        assert_eq!(delivered.status, MessageStatus::Delivered);
+    }
+}
@@ -4,7 +4,7 @@ use crate::kubernetes::mock_k8s_api::mock_service;
 use k8s_openapi::api::core::v1::Node;
 use kube::{Api, api::ListParams};
 use sqlx::{PgPool, types::ipnet::IpNet};
-use std::env;
+use std::{collections::BTreeSet, env, net::IpAddr};
 use tracing::{error, info, trace, warn};
 
 #[derive(Clone)]
@@ -31,6 +31,14 @@ struct K8sApiServerNodes {
     ready: Vec<bool>,
 }
 
+/// A node as known in the database
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedNode {
+    pub ready: bool,
+    /// The outbound IPs assigned to the node
+    pub ips: BTreeSet<IpAddr>,
+}
+
 impl K8sApiServerNodes {
     fn push(&mut self, hostname: String, provider_id: String, is_ready: bool) {
         self.hostnames.push(hostname);
@@ -178,6 +186,33 @@ impl Kubernetes {
             .ok_or(Error::NotFound)
     }
 
+    pub fn node_name(&self) -> &str {
+        &self.node_name
+    }
+
+    /// The database's view on this node, `None` if the node is not in the database
+    pub async fn saved_node(&self) -> Result<Option<SavedNode>, Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT node.ready, ip AS "ip?"
+            FROM k8s_nodes AS node
+            LEFT JOIN outbound_ips ON outbound_ips.node_id = node.id
+            WHERE node.hostname = $1
+            "#,
+            self.node_name
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(rows.first().map(|row| SavedNode {
+            ready: row.ready,
+            ips: rows
+                .iter()
+                .filter_map(|row| row.ip.map(|ip| ip.addr()))
+                .collect(),
+        }))
+    }
+
     pub async fn save_available_node_ips<T>(&self, ips: T) -> Result<(), Error>
     where
         T: IntoIterator,
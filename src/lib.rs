@@ -1,4 +1,4 @@
-use api::ApiServer;
+use api::{ApiServer, RemailsConfig};
 use derive_more::FromStr;
 use handler::Handler;
 use serde::Serialize;
@@ -27,9 +27,11 @@ mod test;
 mod kubernetes;
 mod moneybird;
 mod system_emails;
+pub mod telemetry;
 
 pub use kubernetes::Kubernetes;
 pub use moneybird::*;
+use telemetry::TelemetryConfig;
 
 #[derive(Debug, Default, Clone, Copy, FromStr, Serialize, ToSchema)]
 #[cfg_attr(test, derive(serde::Deserialize))]
@@ -52,7 +54,41 @@ impl Environment {
     }
 }
 
+/// Output format of the logs, configured with the `LOG_FORMAT` env var
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromStr)]
+pub enum LogFormat {
+    Json,
+    Pretty,
+    Compact,
+}
+
+impl LogFormat {
+    pub fn from_env(environment: Environment) -> Self {
+        Self::select(env::var("LOG_FORMAT").ok().as_deref(), environment)
+    }
+
+    /// Defaults to human-readable logs during development, and JSON logs otherwise
+    fn select(value: Option<&str>, environment: Environment) -> Self {
+        match value {
+            Some(value) => value
+                .parse()
+                .expect("Invalid LOG_FORMAT env var, must be one of: json, pretty, or compact"),
+            None => match environment {
+                Environment::Development => LogFormat::Pretty,
+                Environment::Staging | Environment::Production => LogFormat::Json,
+            },
+        }
+    }
+}
+
 pub fn init_tracing() {
+    let format = LogFormat::from_env(Environment::from_env());
+    let telemetry = TelemetryConfig::from_env().map(|config| {
+        config
+            .layer()
+            .expect("failed to initialize OpenTelemetry trace export")
+    });
+
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
@@ -63,7 +99,10 @@ pub fn init_tracing() {
                 .into()
             }),
         )
-        .with(tracing_subscriber::fmt::layer().json())
+        .with((format == LogFormat::Json).then(|| tracing_subscriber::fmt::layer().json()))
+        .with((format == LogFormat::Pretty).then(|| tracing_subscriber::fmt::layer().pretty()))
+        .with((format == LogFormat::Compact).then(|| tracing_subscriber::fmt::layer().compact()))
+        .with(telemetry)
         .init();
 }
 
@@ -105,6 +144,7 @@ pub async fn run_api_server(
         with_frontend,
         with_docs,
         bus_client,
+        RemailsConfig::default(),
     )
     .await;
 
@@ -131,3 +171,44 @@ pub async fn shutdown_signal(token: CancellationToken) {
         _ = terminate => {},
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_format_selection() {
+        assert_eq!(
+            LogFormat::select(None, Environment::Development),
+            LogFormat::Pretty
+        );
+        assert_eq!(
+            LogFormat::select(None, Environment::Staging),
+            LogFormat::Json
+        );
+        assert_eq!(
+            LogFormat::select(None, Environment::Production),
+            LogFormat::Json
+        );
+
+        // an explicit format overrides the default of the environment
+        assert_eq!(
+            LogFormat::select(Some("json"), Environment::Development),
+            LogFormat::Json
+        );
+        assert_eq!(
+            LogFormat::select(Some("pretty"), Environment::Production),
+            LogFormat::Pretty
+        );
+        assert_eq!(
+            LogFormat::select(Some("compact"), Environment::Production),
+            LogFormat::Compact
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid LOG_FORMAT env var")]
+    fn invalid_log_format() {
+        LogFormat::select(Some("xml"), Environment::Production);
+    }
+}
@@ -1,14 +1,20 @@
 use crate::models::{Error, OrgBlockStatus, OrganizationId};
+use aws_lc_rs::digest;
 use chrono::{DateTime, Utc};
 use derive_more::{Display, From, FromStr};
 use email_address::EmailAddress;
 use garde::Validate;
 use rand::distr::{Alphanumeric, SampleString};
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
+use sqlx::{PgPool, types::ipnet::IpNet};
+use std::{env, fmt::Write, net::IpAddr, sync::LazyLock};
 use totp_rs::{Algorithm, Secret, TOTP};
 use tracing::trace;
 use utoipa::{IntoParams, ToSchema};
+use webauthn_rs::prelude::{
+    CreationChallengeResponse, Passkey, PasskeyAuthentication, PasskeyRegistration,
+    PublicKeyCredential, RegisterPublicKeyCredential, RequestChallengeResponse, Webauthn,
+};
 
 id!(
     #[derive(IntoParams)]
@@ -16,12 +22,26 @@ id!(
     ApiUserId
 );
 
+/// Number of failed password logins from a single IP address, across all accounts, after which
+/// the IP address may not log in until its window of 15 minutes resets
+const MAX_FAILED_LOGINS_PER_IP: i32 = 10;
+
+/// Number of failed password logins for a single account, from any IP address, after which
+/// the account may not log in by password until its window of 15 minutes resets
+const MAX_FAILED_LOGINS_PER_ACCOUNT: i32 = 10;
+
 id!(
     #[derive(IntoParams)]
     #[into_params(names("totp_id"))]
     TotpId
 );
 
+id!(
+    #[derive(IntoParams)]
+    #[into_params(names("passkey_id"))]
+    PasskeyId
+);
+
 id!(
     #[derive(IntoParams)]
     #[into_params(names("pw_reset_id"))]
@@ -46,6 +66,93 @@ impl Password {
     pub fn new(password: String) -> Self {
         Password(password)
     }
+
+    /// Upper case, hex-encoded SHA-1 digest of the password, used for breach lookups
+    pub fn sha1_hex(&self) -> String {
+        let digest = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, self.0.as_bytes());
+
+        digest
+            .as_ref()
+            .iter()
+            .fold(String::with_capacity(40), |mut hex, b| {
+                let _ = write!(hex, "{b:02X}");
+                hex
+            })
+    }
+}
+
+/// Requirements for newly chosen passwords on top of the length requirements of [`Password`]
+#[derive(Debug, Clone, Copy)]
+pub struct PasswordPolicy {
+    /// Minimum strength score (0 - 4) as estimated by zxcvbn
+    pub min_strength: u8,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self { min_strength: 3 }
+    }
+}
+
+impl PasswordPolicy {
+    /// Reads the policy from the `PASSWORD_MIN_STRENGTH` environment variable
+    pub fn from_env() -> Self {
+        let min_strength = env::var("PASSWORD_MIN_STRENGTH")
+            .map(|s| {
+                s.parse()
+                    .ok()
+                    .filter(|&score| score <= 4)
+                    .expect("PASSWORD_MIN_STRENGTH must be a number between 0 and 4")
+            })
+            .unwrap_or(Self::default().min_strength);
+
+        Self { min_strength }
+    }
+
+    /// Check the password, `user_inputs` such as the user's name and email address make a
+    /// password easier to guess when it contains them
+    pub fn check(&self, password: &Password, user_inputs: &[&str]) -> Result<(), String> {
+        let entropy = zxcvbn::zxcvbn(&password.0, user_inputs);
+        if u8::from(entropy.score()) >= self.min_strength {
+            return Ok(());
+        }
+
+        let mut message = "password is too easy to guess".to_string();
+        if let Some(feedback) = entropy.feedback() {
+            if let Some(warning) = feedback.warning() {
+                message.push_str(&format!(": {warning}"));
+            }
+            for suggestion in feedback.suggestions() {
+                message.push_str(&format!(" {suggestion}"));
+            }
+        }
+
+        Err(message)
+    }
+}
+
+static PASSWORD_POLICY: LazyLock<PasswordPolicy> = LazyLock::new(PasswordPolicy::from_env);
+
+/// Reject a newly chosen password of a user if it is easy to guess
+pub fn check_password_strength(password: &Password, name: &str, email: &str) -> Result<(), Error> {
+    let local_part = email
+        .split_once('@')
+        .map_or(email, |(local_part, _)| local_part);
+
+    PASSWORD_POLICY
+        .check(password, &[name, email, local_part])
+        .map_err(Error::BadRequest)
+}
+
+/// Garde validator for the password of a new user, rejects passwords that are easy to guess
+pub fn validate_password_strength<'a>(
+    name: &'a str,
+    email: &'a EmailAddress,
+) -> impl FnOnce(&Password, &()) -> garde::Result + 'a {
+    move |password, _| {
+        check_password_strength(password, name, email.as_str())
+            .map_err(|e| garde::Error::new(e.to_string()))
+    }
 }
 
 #[derive(
@@ -174,6 +281,33 @@ impl TotpCodeDetails {
     }
 }
 
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct PasskeyFinishEnroll {
+    /// The response of the browser to the challenge of the enrollment, see
+    /// `navigator.credentials.create()`
+    #[garde(skip)]
+    #[schema(value_type = Object)]
+    credential: RegisterPublicKeyCredential,
+    #[schema(max_length = 100)]
+    #[garde(length(max = 100))]
+    #[serde(default)]
+    description: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[cfg_attr(test, derive(Deserialize))]
+pub struct PasskeyDetails {
+    pub id: PasskeyId,
+    pub description: String,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+impl PasskeyDetails {
+    pub fn id(&self) -> &PasskeyId {
+        &self.id
+    }
+}
+
 impl ApiUser {
     pub fn id(&self) -> &ApiUserId {
         &self.id
@@ -247,7 +381,10 @@ impl TryFrom<PgApiUser> for ApiUser {
 pub enum ResetLinkCheck {
     NotActive,
     ActiveWithout2Fa,
+    /// The reset requires a TOTP code
     ActiveWith2Fa,
+    /// The reset requires signing a challenge with a passkey, which is required over TOTP
+    ActiveWithPasskey,
 }
 
 #[derive(derive_more::Debug)]
@@ -378,7 +515,8 @@ impl ApiUserRepository {
     pub async fn mfa_enabled(&self, user_id: &ApiUserId) -> Result<bool, Error> {
         Ok(sqlx::query_scalar!(
             r#"
-            SELECT EXISTS(SELECT 1 FROM totp WHERE user_id = $1 AND state = 'enabled') as "exists!"
+            SELECT EXISTS(SELECT 1 FROM totp WHERE user_id = $1 AND state = 'enabled')
+                OR EXISTS(SELECT 1 FROM passkeys WHERE user_id = $1) as "exists!"
             "#,
             **user_id
         )
@@ -427,7 +565,7 @@ impl ApiUserRepository {
     }
 
     async fn check_and_increase_totp_try_counter(&self, user_id: &ApiUserId) -> Result<(), Error> {
-        let counter = sqlx::query_scalar!(
+        let counter = sqlx::query!(
             r#"
             UPDATE api_users
             SET totp_try_counter       = CASE
@@ -437,14 +575,14 @@ impl ApiUserRepository {
                                              WHEN totp_try_counter_reset < now() THEN now() + '1 min'
                                              ELSE totp_try_counter_reset END
             WHERE id = $1
-            RETURNING totp_try_counter;
+            RETURNING totp_try_counter, totp_try_counter_reset;
             "#,
             **user_id
         )
             .fetch_one(&self.pool).await?;
 
-        if counter > 3 {
-            Err(Error::TooManyRequests)
+        if counter.totp_try_counter > 3 {
+            Err(Error::TooManyRequests(counter.totp_try_counter_reset))
         } else {
             Ok(())
         }
@@ -484,6 +622,226 @@ impl ApiUserRepository {
         .into())
     }
 
+    pub async fn start_enroll_passkey(
+        &self,
+        webauthn: &Webauthn,
+        user_id: &ApiUserId,
+    ) -> Result<CreationChallengeResponse, Error> {
+        let user = sqlx::query!(
+            r#"SELECT email, name FROM api_users WHERE id = $1"#,
+            **user_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        // Make sure the same authenticator is not enrolled twice
+        let exclude_credentials = self
+            .webauthn_passkeys(user_id)
+            .await?
+            .iter()
+            .map(|passkey| passkey.cred_id().clone())
+            .collect();
+
+        let (challenge, registration) = webauthn
+            .start_passkey_registration(
+                **user_id,
+                &user.email,
+                &user.name,
+                Some(exclude_credentials),
+            )
+            .map_err(|err| Error::Internal(format!("cannot start passkey enrollment: {err}")))?;
+
+        // Only one passkey can be enrolling at a time
+        sqlx::query!(
+            r#"
+            UPDATE api_users SET passkey_registration = $2 WHERE id = $1
+            "#,
+            **user_id,
+            serde_json::to_value(&registration)?
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(challenge)
+    }
+
+    pub async fn finish_enroll_passkey(
+        &self,
+        webauthn: &Webauthn,
+        user_id: &ApiUserId,
+        finish: PasskeyFinishEnroll,
+    ) -> Result<PasskeyDetails, Error> {
+        // Each challenge can only be answered once
+        let registration = sqlx::query_scalar!(
+            r#"
+            UPDATE api_users u SET passkey_registration = NULL
+            FROM api_users old
+            WHERE u.id = $1
+              AND old.id = u.id
+            RETURNING old.passkey_registration
+            "#,
+            **user_id
+        )
+        .fetch_one(&self.pool)
+        .await?
+        .ok_or(Error::BadRequest(
+            "No passkey enrollment in progress".to_string(),
+        ))?;
+        let registration: PasskeyRegistration = serde_json::from_value(registration)?;
+
+        let passkey = webauthn
+            .finish_passkey_registration(&finish.credential, &registration)
+            .map_err(|err| Error::BadRequest(format!("Invalid passkey: {err}")))?;
+        let credential_id: &[u8] = passkey.cred_id().as_ref();
+
+        Ok(sqlx::query_as!(
+            PasskeyDetails,
+            r#"
+            INSERT INTO passkeys (id, description, user_id, credential_id, passkey)
+            VALUES (gen_random_uuid(), $2, $1, $3, $4)
+            RETURNING
+                id,
+                description,
+                last_used
+            "#,
+            **user_id,
+            finish.description,
+            credential_id,
+            serde_json::to_value(&passkey)?
+        )
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    /// Start logging in with one of the passkeys of the user, fails if the user has none
+    pub async fn start_passkey_login(
+        &self,
+        webauthn: &Webauthn,
+        user_id: &ApiUserId,
+    ) -> Result<RequestChallengeResponse, Error> {
+        let passkeys = self.webauthn_passkeys(user_id).await?;
+        if passkeys.is_empty() {
+            return Err(Error::NotFound("passkey"));
+        }
+
+        let (challenge, authentication) = webauthn
+            .start_passkey_authentication(&passkeys)
+            .map_err(|err| Error::Internal(format!("cannot start passkey login: {err}")))?;
+
+        sqlx::query!(
+            r#"
+            UPDATE api_users SET passkey_authentication = $2 WHERE id = $1
+            "#,
+            **user_id,
+            serde_json::to_value(&authentication)?
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(challenge)
+    }
+
+    /// Check whether `credential` answers the challenge of the passkey login in progress
+    pub async fn check_passkey(
+        &self,
+        webauthn: &Webauthn,
+        user_id: &ApiUserId,
+        credential: &PublicKeyCredential,
+    ) -> Result<bool, Error> {
+        // Each challenge can only be answered once
+        let authentication = sqlx::query_scalar!(
+            r#"
+            UPDATE api_users u SET passkey_authentication = NULL
+            FROM api_users old
+            WHERE u.id = $1
+              AND old.id = u.id
+            RETURNING old.passkey_authentication
+            "#,
+            **user_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        let Some(authentication) = authentication else {
+            return Ok(false);
+        };
+        let authentication: PasskeyAuthentication = serde_json::from_value(authentication)?;
+
+        let result = match webauthn.finish_passkey_authentication(credential, &authentication) {
+            Ok(result) => result,
+            Err(err) => {
+                trace!(user_id = user_id.to_string(), "invalid passkey: {err}");
+                return Ok(false);
+            }
+        };
+
+        // Store the updated signature counter, which reveals cloned authenticators
+        for mut passkey in self.webauthn_passkeys(user_id).await? {
+            if passkey.update_credential(&result).is_some() {
+                let credential_id: &[u8] = passkey.cred_id().as_ref();
+
+                sqlx::query!(
+                    r#"
+                    UPDATE passkeys SET passkey = $2, last_used = now()
+                    WHERE credential_id = $1
+                    "#,
+                    credential_id,
+                    serde_json::to_value(&passkey)?
+                )
+                .execute(&self.pool)
+                .await?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    async fn webauthn_passkeys(&self, user_id: &ApiUserId) -> Result<Vec<Passkey>, Error> {
+        sqlx::query_scalar!(
+            r#"
+            SELECT passkey FROM passkeys WHERE user_id = $1
+            "#,
+            **user_id
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|passkey| Ok(serde_json::from_value(passkey)?))
+        .collect()
+    }
+
+    pub async fn passkeys(&self, user_id: &ApiUserId) -> Result<Vec<PasskeyDetails>, Error> {
+        Ok(sqlx::query_as!(
+            PasskeyDetails,
+            r#"
+            SELECT id, description, last_used FROM passkeys
+            WHERE user_id = $1
+            "#,
+            **user_id
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    pub async fn delete_passkey(
+        &self,
+        user_id: &ApiUserId,
+        passkey_id: &PasskeyId,
+    ) -> Result<PasskeyId, Error> {
+        Ok(sqlx::query_scalar!(
+            r#"
+            DELETE FROM passkeys
+            WHERE id = $2
+              AND user_id = $1
+            RETURNING id
+            "#,
+            **user_id,
+            **passkey_id
+        )
+        .fetch_one(&self.pool)
+        .await?
+        .into())
+    }
+
     pub async fn find_by_github_id(&self, github_id: i64) -> Result<Option<ApiUser>, Error> {
         sqlx::query_as!(
             PgApiUser,
@@ -589,16 +947,16 @@ impl ApiUserRepository {
         update: PasswordUpdate,
         user_id: &ApiUserId,
     ) -> Result<(), Error> {
-        let hash = sqlx::query_scalar!(
+        let user = sqlx::query!(
             r#"
-            SELECT password_hash FROM api_users WHERE id = $1
+            SELECT password_hash, name, email FROM api_users WHERE id = $1
             "#,
             **user_id
         )
         .fetch_one(&self.pool)
         .await?;
 
-        if let Some(hash) = hash {
+        if let Some(hash) = user.password_hash {
             update
                 .current_password
                 .ok_or(Error::BadRequest(
@@ -611,6 +969,8 @@ impl ApiUserRepository {
                 .map_err(|_| Error::BadRequest("wrong password".to_string()))?;
         }
 
+        check_password_strength(&update.new_password, &user.name, &user.email)?;
+
         let hash = update.new_password.generate_hash();
         sqlx::query!(
             r#"
@@ -670,46 +1030,94 @@ impl ApiUserRepository {
         &self,
         pw_reset_id: PwResetId,
     ) -> Result<ResetLinkCheck, Error> {
-        let count = sqlx::query_scalar!(
+        let record = sqlx::query!(
             r#"
-            SELECT count(t.id) as "count!"
+            SELECT EXISTS(SELECT 1 FROM totp t WHERE t.user_id = r.api_user_id AND t.state = 'enabled') AS "totp!",
+                   EXISTS(SELECT 1 FROM passkeys p WHERE p.user_id = r.api_user_id) AS "passkey!"
             FROM password_reset r
-                LEFT JOIN totp t on r.api_user_id = t.user_id
             WHERE r.id = $1
               AND r.created_at > now() - '15 minutes'::interval
-              AND (t.state IS NULL OR t.state = 'enabled')
-            GROUP BY r.id
             "#,
             *pw_reset_id
         )
         .fetch_optional(&self.pool)
         .await?;
 
-        match count {
-            None => Ok(ResetLinkCheck::NotActive),
-            Some(0) => Ok(ResetLinkCheck::ActiveWithout2Fa),
-            Some(_) => Ok(ResetLinkCheck::ActiveWith2Fa),
-        }
+        Ok(match record {
+            None => ResetLinkCheck::NotActive,
+            Some(record) if record.passkey => ResetLinkCheck::ActiveWithPasskey,
+            Some(record) if record.totp => ResetLinkCheck::ActiveWith2Fa,
+            Some(_) => ResetLinkCheck::ActiveWithout2Fa,
+        })
+    }
+
+    /// Check the secret of an active password reset, returns the user whose password is reset
+    pub async fn verify_password_reset(
+        &self,
+        pw_reset_id: PwResetId,
+        reset_secret: &Password,
+    ) -> Result<ApiUserId, Error> {
+        let Some(record) = sqlx::query!(
+            r#"
+            SELECT reset_secret, api_user_id
+            FROM password_reset
+            WHERE id = $1
+              AND created_at > now() - '15 minutes'::interval
+            "#,
+            *pw_reset_id
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        else {
+            return Err(Error::NotFound("invalid password reset secret"));
+        };
+
+        reset_secret
+            .verify_password(&record.reset_secret)
+            .map_err(|_| Error::NotFound("invalid password reset secret"))?;
+
+        Ok(record.api_user_id.into())
     }
 
+    /// Start signing a password reset with one of the passkeys of the user
+    pub async fn start_password_reset_passkey(
+        &self,
+        webauthn: &Webauthn,
+        pw_reset_id: PwResetId,
+        reset_secret: &Password,
+    ) -> Result<RequestChallengeResponse, Error> {
+        let user_id = self
+            .verify_password_reset(pw_reset_id, reset_secret)
+            .await?;
+
+        self.start_passkey_login(webauthn, &user_id).await
+    }
+
+    /// Set a new password using a password reset secret
+    ///
+    /// If the user has a second factor, the reset must be confirmed with it: users with a passkey
+    /// must answer the challenge of [`Self::start_password_reset_passkey`], which is required
+    /// over TOTP, users with only TOTP must provide a TOTP code.
     pub async fn finish_password_reset(
         &self,
+        webauthn: &Webauthn,
         pw_reset_id: PwResetId,
         reset_secret: Password,
         new_password: Password,
         totp_code: Option<TotpCode>,
+        passkey_credential: Option<&PublicKeyCredential>,
     ) -> Result<(), Error> {
         let mut tx = self.pool.begin().await?;
 
         let Some(record) = sqlx::query!(
             r#"
-            SELECT pwr.reset_secret, pwr.api_user_id, count(t.id) AS "totp_count!"
+            SELECT pwr.reset_secret, pwr.api_user_id, u.name, u.email,
+                   EXISTS(SELECT 1 FROM totp t WHERE t.user_id = u.id AND t.state = 'enabled') AS "totp!",
+                   EXISTS(SELECT 1 FROM passkeys p WHERE p.user_id = u.id) AS "passkey!"
             FROM password_reset pwr
-                LEFT JOIN totp t ON t.user_id = api_user_id
+                JOIN api_users u ON u.id = pwr.api_user_id
             WHERE pwr.id = $1
               AND pwr.created_at > now() - '15 minutes'::interval
-              AND (t.state IS NULL OR t.state = 'enabled')
-            GROUP BY pwr.id
             "#,
             *pw_reset_id
         )
@@ -723,18 +1131,25 @@ impl ApiUserRepository {
             .verify_password(&record.reset_secret)
             .map_err(|_| Error::NotFound("invalid password reset secret"))?;
 
-        if record.totp_count > 0 {
+        let user_id: ApiUserId = record.api_user_id.into();
+        if record.passkey {
+            let Some(credential) = passkey_credential else {
+                return Err(Error::BadRequest("Missing passkey".to_string()));
+            };
+            if !self.check_passkey(webauthn, &user_id, credential).await? {
+                return Err(Error::BadRequest("Invalid passkey".to_string()));
+            }
+        } else if record.totp {
             let Some(totp_code) = totp_code else {
                 return Err(Error::BadRequest("Missing TOTP code".to_string()));
             };
-            if !self
-                .check_totp_code(&record.api_user_id.into(), totp_code.as_ref())
-                .await?
-            {
+            if !self.check_totp_code(&user_id, totp_code.as_ref()).await? {
                 return Err(Error::BadRequest("Invalid TOTP code".to_string()));
             }
         }
 
+        check_password_strength(&new_password, &record.name, &record.email)?;
+
         sqlx::query!(
             r#"
             UPDATE api_users
@@ -789,16 +1204,16 @@ impl ApiUserRepository {
         current_password: Password,
         user_id: &ApiUserId,
     ) -> Result<(), Error> {
-        let hash = sqlx::query_scalar!(
+        let user = sqlx::query!(
             r#"
-            SELECT password_hash FROM api_users WHERE id = $1
+            SELECT password_hash, name, email FROM api_users WHERE id = $1
             "#,
             **user_id
         )
         .fetch_one(&self.pool)
         .await?;
 
-        if let Some(hash) = hash {
+        if let Some(hash) = user.password_hash {
             current_password
                 .verify_password(&hash)
                 .inspect_err(|err| trace!(user_id = user_id.to_string(), "wrong password: {}", err))
@@ -961,6 +1376,126 @@ impl ApiUserRepository {
         }
         Err(Error::NotFound("User not found or wrong password"))
     }
+
+    /// Returns [`Error::TooManyRequests`] if too many password logins from `ip` failed recently,
+    /// regardless of the accounts they tried
+    pub async fn check_failed_logins_by_ip(&self, ip: IpAddr) -> Result<(), Error> {
+        let window = sqlx::query!(
+            r#"
+            SELECT failures, reset_at FROM failed_logins_by_ip WHERE ip = $1 AND reset_at > now()
+            "#,
+            IpNet::from(ip),
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match window {
+            Some(window) if window.failures >= MAX_FAILED_LOGINS_PER_IP => {
+                tracing::warn!(
+                    ip = ip.to_string(),
+                    failures = window.failures,
+                    "Too many failed password attempts from IP address"
+                );
+                Err(Error::TooManyRequests(window.reset_at))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Count a failed password login from `ip`, starting a new window if the last one expired
+    pub async fn record_failed_login(&self, ip: IpAddr) -> Result<(), Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO failed_logins_by_ip (ip, failures, reset_at)
+            VALUES ($1, 1, now() + '15 min')
+            ON CONFLICT (ip) DO UPDATE
+            SET failures = CASE
+                               WHEN failed_logins_by_ip.reset_at < now() THEN 1
+                               ELSE failed_logins_by_ip.failures + 1 END,
+                reset_at = CASE
+                               WHEN failed_logins_by_ip.reset_at < now() THEN now() + '15 min'
+                               ELSE failed_logins_by_ip.reset_at END
+            "#,
+            IpNet::from(ip),
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns [`Error::TooManyRequests`] if too many password logins for `email` failed recently,
+    /// regardless of the IP addresses they came from
+    pub async fn check_failed_logins_by_account(&self, email: &EmailAddress) -> Result<(), Error> {
+        let window = sqlx::query!(
+            r#"
+            SELECT failures, reset_at FROM failed_logins_by_account WHERE email = $1 AND reset_at > now()
+            "#,
+            email.as_str().to_lowercase(),
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match window {
+            Some(window) if window.failures >= MAX_FAILED_LOGINS_PER_ACCOUNT => {
+                tracing::warn!(
+                    failures = window.failures,
+                    "Too many failed password attempts for account {email}"
+                );
+                Err(Error::TooManyRequests(window.reset_at))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Count a failed password login for `email`, starting a new window if the last one expired
+    pub async fn record_failed_login_by_account(&self, email: &EmailAddress) -> Result<(), Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO failed_logins_by_account (email, failures, reset_at)
+            VALUES ($1, 1, now() + '15 min')
+            ON CONFLICT (email) DO UPDATE
+            SET failures = CASE
+                               WHEN failed_logins_by_account.reset_at < now() THEN 1
+                               ELSE failed_logins_by_account.failures + 1 END,
+                reset_at = CASE
+                               WHEN failed_logins_by_account.reset_at < now() THEN now() + '15 min'
+                               ELSE failed_logins_by_account.reset_at END
+            "#,
+            email.as_str().to_lowercase(),
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_failed_logins_expired_before(
+        &self,
+        before: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            r#"
+            DELETE FROM failed_logins_by_ip
+            WHERE reset_at < $1
+            "#,
+            before
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM failed_logins_by_account
+            WHERE reset_at < $1
+            "#,
+            before
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -985,6 +1520,45 @@ mod test {
         }
     }
 
+    #[test]
+    fn password_sha1() {
+        assert_eq!(
+            Password::new("abc".to_string()).sha1_hex(),
+            "A9993E364706816ABA3E25717850C26C9CD0D89D"
+        );
+    }
+
+    #[test]
+    fn password_policy() {
+        let policy = PasswordPolicy::default();
+
+        // long enough, but easy to guess
+        let weak = Password::new("password1234567".to_string());
+        assert!(weak.validate().is_ok());
+        assert!(policy.check(&weak, &[]).is_err());
+        assert!(
+            policy
+                .check(&Password::new("aaaaaaaaaaaaaaaa".to_string()), &[])
+                .is_err()
+        );
+
+        let strong = Password::new("correct-Horse-7-battery-Staple".to_string());
+        assert!(policy.check(&strong, &[]).is_ok());
+
+        // passwords based on the user's name or email address are easy to guess
+        let personal = Password::new("xyqzvblwrtkmnp2024!".to_string());
+        assert!(policy.check(&personal, &[]).is_ok());
+        assert!(
+            policy
+                .check(&personal, &["Xyqzvbl Wrtkmnp", "xyqzvblwrtkmnp"])
+                .is_err()
+        );
+
+        // the threshold can be lowered
+        let lenient = PasswordPolicy { min_strength: 0 };
+        assert!(lenient.check(&weak, &[]).is_ok());
+    }
+
     impl PartialEq<NewApiUser> for ApiUser {
         fn eq(&self, other: &NewApiUser) -> bool {
             self.github_user_id == other.github_user_id
@@ -1,6 +1,7 @@
 use crate::{
-    handler::dns::{DnsResolver, DomainVerificationStatus},
-    models::{Actor, AuditLogRepository, Error, OrganizationId, ProjectId},
+    dkim::DEFAULT_SIGNED_HEADERS,
+    handler::dns::{DkimError, DnsResolver, DomainVerificationStatus},
+    models::{Actor, AuditLogRepository, Error, OrganizationId, ProjectId, deserialize_present},
 };
 use aws_lc_rs::{encoding::AsDer, rsa::KeySize, signature::KeyPair};
 use base64ct::{Base64, Encoding};
@@ -17,7 +18,7 @@ use std::{
     sync::atomic::{AtomicUsize, Ordering},
 };
 use tokio_rustls::rustls::pki_types::PrivatePkcs8KeyDer;
-use tracing::{error, info, trace};
+use tracing::{error, info, trace, warn};
 use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
@@ -35,6 +36,22 @@ pub enum DkimKeyType {
     Ed25519,
 }
 
+/// Canonicalization algorithm used for both the headers and the body of DKIM signed messages
+///
+/// This ends up as either `c=relaxed/relaxed` or `c=simple/simple` in the DKIM-Signature header
+#[derive(
+    Clone, Copy, Default, sqlx::Type, Serialize, Deserialize, Debug, PartialEq, Eq, ToSchema,
+)]
+#[sqlx(type_name = "dkim_canonicalization", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum DkimCanonicalization {
+    /// Tolerates common modifications in transit, such as whitespace changes and header folding
+    #[default]
+    Relaxed,
+    /// Does not tolerate any modification of the message in transit
+    Simple,
+}
+
 pub enum DkimKey {
     Ed25519(aws_lc_rs::signature::Ed25519KeyPair),
     RsaSha256(aws_lc_rs::rsa::KeyPair),
@@ -120,6 +137,9 @@ pub struct ApiDomain {
     domain: String,
     dkim_key_type: DkimKeyType,
     dkim_public_key: String,
+    /// Headers that are included in the DKIM signature
+    dkim_signed_headers: Vec<String>,
+    dkim_canonicalization: DkimCanonicalization,
     verification_status: DomainVerificationStatus,
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
@@ -141,6 +161,14 @@ impl ApiDomain {
     pub fn domain(&self) -> &str {
         &self.domain
     }
+
+    pub fn dkim_signed_headers(&self) -> &[String] {
+        &self.dkim_signed_headers
+    }
+
+    pub fn dkim_canonicalization(&self) -> DkimCanonicalization {
+        self.dkim_canonicalization
+    }
 }
 
 #[derive(Debug)]
@@ -150,6 +178,9 @@ pub struct Domain {
     project_ids: Vec<ProjectId>,
     pub(crate) domain: String,
     pub(crate) dkim_key: DkimKey,
+    /// Headers to include in the DKIM signature, `None` means the default set of headers
+    pub(crate) dkim_signed_headers: Option<Vec<String>>,
+    pub(crate) dkim_canonicalization: DkimCanonicalization,
     verification_status: DomainVerificationStatus,
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
@@ -162,6 +193,8 @@ struct PgDomain {
     project_ids: Vec<Uuid>,
     dkim_key_type: DkimKeyType,
     dkim_pkcs8_der: Vec<u8>,
+    dkim_signed_headers: Option<Vec<String>>,
+    dkim_canonicalization: DkimCanonicalization,
     verification_status: serde_json::Value,
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
@@ -179,6 +212,8 @@ impl TryFrom<PgDomain> for Domain {
             project_ids: pg.project_ids.into_iter().map(Into::into).collect(),
             domain: pg.domain,
             dkim_key,
+            dkim_signed_headers: pg.dkim_signed_headers,
+            dkim_canonicalization: pg.dkim_canonicalization,
             verification_status: serde_json::from_value(pg.verification_status)?,
             created_at: pg.created_at,
             updated_at: pg.updated_at,
@@ -200,6 +235,13 @@ impl From<Domain> for ApiDomain {
             domain: d.domain,
             dkim_key_type,
             dkim_public_key: Base64::encode_string(d.dkim_key.pub_key().expect("As we generate the keys ourselves, we should never run into a marshalling problem").as_ref()),
+            dkim_signed_headers: d.dkim_signed_headers.unwrap_or_else(|| {
+                DEFAULT_SIGNED_HEADERS
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect()
+            }),
+            dkim_canonicalization: d.dkim_canonicalization,
             verification_status: d.verification_status,
             created_at: d.created_at,
             updated_at: d.updated_at,
@@ -218,6 +260,71 @@ pub struct NewDomain {
     pub dkim_key_type: DkimKeyType,
 }
 
+const MAX_SIGNED_HEADERS: usize = 64;
+
+/// DKIM settings of a domain, settings that are omitted are left unchanged
+#[derive(Debug, Serialize, Deserialize, ToSchema, Validate)]
+pub struct DkimSettings {
+    /// Headers to include in the DKIM signature, or `null` to use the default set of headers.
+    /// The `From` header must always be included. Left unchanged if omitted.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[garde(custom(validate_signed_headers))]
+    #[schema(value_type = Option<Vec<String>>, max_items = 64)]
+    pub signed_headers: Option<Option<Vec<String>>>,
+    /// Canonicalization of both the headers and the body, or `null` to use the default,
+    /// `relaxed`. Left unchanged if omitted.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[garde(skip)]
+    #[schema(value_type = Option<DkimCanonicalization>)]
+    pub canonicalization: Option<Option<DkimCanonicalization>>,
+}
+
+fn validate_signed_headers(headers: &Option<Option<Vec<String>>>, _: &()) -> garde::Result {
+    let Some(Some(headers)) = headers else {
+        return Ok(());
+    };
+
+    if headers.len() > MAX_SIGNED_HEADERS {
+        return Err(garde::Error::new(format!(
+            "at most {MAX_SIGNED_HEADERS} headers can be signed"
+        )));
+    }
+
+    // header field names consist of printable US-ASCII characters, except for the colon (RFC 5322 section 2.2)
+    if let Some(invalid) = headers
+        .iter()
+        .find(|h| h.is_empty() || !h.bytes().all(|b| b.is_ascii_graphic() && b != b':'))
+    {
+        return Err(garde::Error::new(format!(
+            "invalid header name: {invalid:?}"
+        )));
+    }
+
+    if !headers.iter().any(|h| h.eq_ignore_ascii_case("From")) {
+        return Err(garde::Error::new("the From header must always be signed"));
+    }
+
+    Ok(())
+}
+
+/// A domain whose DKIM record no longer matches its key, after it verified successfully before
+#[derive(Debug)]
+pub struct DkimDrift {
+    pub domain_id: DomainId,
+    pub organization_id: OrganizationId,
+    pub domain: String,
+    /// Why the DKIM verification failed
+    pub reason: String,
+}
+
 #[derive(Clone)]
 pub struct DomainRepository {
     pool: sqlx::PgPool,
@@ -305,6 +412,8 @@ impl DomainRepository {
                    ) AS "project_ids!",
                    d.dkim_key_type as "dkim_key_type: DkimKeyType",
                    d.dkim_pkcs8_der,
+                   d.dkim_signed_headers,
+                   d.dkim_canonicalization AS "dkim_canonicalization: DkimCanonicalization",
                    d.verification_status,
                    d.created_at,
                    d.updated_at
@@ -377,10 +486,58 @@ impl DomainRepository {
         Ok(())
     }
 
+    /// Flag a domain whose DKIM record no longer matches its key, or clear the flag once it
+    /// matches again
+    async fn store_dkim_drift(&self, domain_id: &DomainId, drifted: bool) -> Result<(), Error> {
+        sqlx::query!(
+            r#"
+            UPDATE domains
+            SET dkim_drifted_at     = CASE WHEN $2 THEN coalesce(dkim_drifted_at, now()) END,
+                dkim_drift_notified = dkim_drift_notified AND $2
+            WHERE id = $1
+            "#,
+            **domain_id,
+            drifted,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Domains that drifted since the last call, each drift is only returned once
+    pub async fn take_unnotified_dkim_drifts(&self) -> Result<Vec<DkimDrift>, Error> {
+        Ok(sqlx::query_as!(
+            DkimDrift,
+            r#"
+            UPDATE domains
+            SET dkim_drift_notified = true
+            WHERE dkim_drifted_at IS NOT NULL
+              AND NOT dkim_drift_notified
+            RETURNING id AS "domain_id: DomainId",
+                      organization_id AS "organization_id: OrganizationId",
+                      domain,
+                      coalesce(verification_status->'dkim'->>'reason', '') AS "reason!"
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Re-verify the DNS records of all domains that were not verified recently,
+    /// flagging domains whose DKIM record no longer matches their key
     pub async fn verify_all(&self) -> Result<(), Error> {
         let domains = query!(
             r#"
-            SELECT id, domain, dkim_key_type AS "kind:DkimKeyType", dkim_pkcs8_der
+            SELECT id,
+                   domain,
+                   dkim_key_type AS "kind:DkimKeyType",
+                   dkim_pkcs8_der,
+                   coalesce(
+                       verification_status->'dkim'->>'status' = 'Success'
+                           OR dkim_drifted_at IS NOT NULL,
+                       false
+                   ) AS "dkim_verified!"
             FROM domains
             WHERE last_verification_time < now() - '20 hours'::interval
             "#
@@ -406,32 +563,56 @@ impl DomainRepository {
                         Ok(pk) => pk,
                     };
 
-                    let verification = match self
+                    let (verification, dkim_error) = self
                         .resolver
-                        .verify_domain(
+                        .verify_domain_dkim(
                             &domain.domain,
                             pk.pub_key()
                                 .expect("We only generate the key internally, so they should work")
                                 .as_ref(),
                         )
-                        .await
-                    {
-                        Ok(v) => v,
-                        Err(err) => {
-                            error!(
+                        .await;
+
+                    // Only domains that had a matching DKIM record can drift, and only a record
+                    // that is missing or differs counts, not one that could not be retrieved
+                    let drifted = match &dkim_error {
+                        None => Some(false),
+                        Some(err) if err.is_drift() => Some(domain.dkim_verified),
+                        Some(DkimError::Lookup { .. }) if domain.dkim_verified => {
+                            // keep the previous status, such that the domain is checked for drift
+                            // again during the next run
+                            warn!(
                                 domain_id = domain.id.to_string(),
                                 domain = domain.domain,
-                                "Encountered error while updating domain verification status: {err}"
+                                reason = verification.dkim.reason,
+                                "Could not retrieve the DKIM record of domain, retrying later"
                             );
                             error_count.fetch_add(1, Ordering::Relaxed);
                             return;
                         }
+                        Some(_) => None,
                     };
+                    if drifted == Some(true) {
+                        warn!(
+                            domain_id = domain.id.to_string(),
+                            domain = domain.domain,
+                            reason = verification.dkim.reason,
+                            "DKIM record of domain no longer matches its key"
+                        );
+                    }
 
-                    match self
-                        .store_verification_status(&domain.id.into(), &verification)
-                        .await
-                    {
+                    let stored = async {
+                        self.store_verification_status(&domain.id.into(), &verification)
+                            .await?;
+                        if domain.dkim_verified
+                            && let Some(drifted) = drifted
+                        {
+                            self.store_dkim_drift(&domain.id.into(), drifted).await?;
+                        }
+                        Ok::<_, Error>(())
+                    };
+
+                    match stored.await {
                         Ok(()) => {
                             trace!(
                                 domain_id = domain.id.to_string(),
@@ -568,6 +749,50 @@ impl DomainRepository {
         Ok(domain)
     }
 
+    pub async fn update_dkim_settings(
+        &self,
+        org_id: OrganizationId,
+        domain_id: DomainId,
+        settings: &DkimSettings,
+        actor: impl Into<Actor>,
+    ) -> Result<Domain, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE domains
+            SET dkim_signed_headers = CASE WHEN $5 THEN $3 ELSE dkim_signed_headers END,
+                dkim_canonicalization = COALESCE($4, dkim_canonicalization)
+            WHERE id = $2 AND organization_id = $1
+            RETURNING id
+            "#,
+            *org_id,
+            *domain_id,
+            settings.signed_headers.as_ref().and_then(Option::as_deref),
+            settings.canonicalization.map(Option::unwrap_or_default)
+                as Option<DkimCanonicalization>,
+            settings.signed_headers.is_some(),
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let domain = Self::get_one(&mut tx, org_id, domain_id).await?;
+
+        self.audit_log
+            .log(
+                &mut tx,
+                actor,
+                (domain.id, org_id),
+                "Updated DKIM settings",
+                Some(json!(settings)),
+            )
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(domain)
+    }
+
     pub async fn list(&self, org_id: OrganizationId) -> Result<Vec<Domain>, Error> {
         sqlx::query_as!(
             PgDomain,
@@ -581,6 +806,8 @@ impl DomainRepository {
                    ) AS "project_ids!",
                    d.dkim_key_type as "dkim_key_type: DkimKeyType",
                    d.dkim_pkcs8_der,
+                   d.dkim_signed_headers,
+                   d.dkim_canonicalization AS "dkim_canonicalization: DkimCanonicalization",
                    d.verification_status,
                    d.created_at,
                    d.updated_at
@@ -653,6 +880,8 @@ impl DomainRepository {
                    ) AS "project_ids!",
                    d.dkim_key_type as "dkim_key_type: DkimKeyType",
                    d.dkim_pkcs8_der,
+                   d.dkim_signed_headers,
+                   d.dkim_canonicalization AS "dkim_canonicalization: DkimCanonicalization",
                    d.verification_status,
                    d.created_at,
                    d.updated_at
@@ -1299,4 +1528,75 @@ mod test {
             );
         }
     }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "projects", "org_domains", "proj_domains")
+    ))]
+    async fn dkim_drift(db: PgPool) {
+        let domain_id: DomainId = "ed28baa5-57f7-413f-8c77-7797ba6a8780".parse().unwrap();
+        let drifted_at = async || {
+            sqlx::query_scalar!(
+                "SELECT dkim_drifted_at FROM domains WHERE id = $1",
+                *domain_id
+            )
+            .fetch_one(&db)
+            .await
+            .unwrap()
+        };
+
+        // a record that cannot be retrieved is not a drift
+        let mut resolver = DnsResolver::mock("localhost", 1025);
+        resolver.resolver.txt_fails = true;
+        let repo = DomainRepository::new(db.clone(), resolver);
+        repo.verify_all().await.unwrap();
+        assert!(drifted_at().await.is_none());
+
+        // the published record no longer contains the key of the domain
+        let repo = DomainRepository::new(
+            db.clone(),
+            DnsResolver::mock_custom_records(
+                "localhost",
+                1025,
+                vec![
+                    "v=DKIM1; k=rsa; p=MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEA",
+                    "v=spf1 include:spf.remails.net -all",
+                ],
+            ),
+        );
+        repo.verify_all().await.unwrap();
+        assert!(drifted_at().await.is_some());
+
+        let drifts = repo.take_unnotified_dkim_drifts().await.unwrap();
+        let drift = drifts
+            .iter()
+            .find(|drift| drift.domain_id == domain_id)
+            .unwrap();
+        assert_eq!(drift.domain, "test-org-1.com");
+        assert_eq!(
+            drift.organization_id,
+            "44729d9f-a7dc-4226-b412-36a7537f5176".parse().unwrap()
+        );
+        assert!(!drift.reason.is_empty());
+
+        // each drift is only notified once
+        assert!(repo.take_unnotified_dkim_drifts().await.unwrap().is_empty());
+
+        // nor does a failing lookup clear the flag
+        sqlx::query!("UPDATE domains SET last_verification_time = now() - '1 day'::interval")
+            .execute(&db)
+            .await
+            .unwrap();
+        let mut resolver = DnsResolver::mock("localhost", 1025);
+        resolver.resolver.txt_fails = true;
+        let repo = DomainRepository::new(db.clone(), resolver);
+        repo.verify_all().await.unwrap();
+        assert!(drifted_at().await.is_some());
+
+        // the flag is cleared once the record matches again
+        let repo = DomainRepository::new(db.clone(), DnsResolver::mock("localhost", 1025));
+        repo.verify_all().await.unwrap();
+        assert!(drifted_at().await.is_none());
+        assert!(repo.take_unnotified_dkim_drifts().await.unwrap().is_empty());
+    }
 }
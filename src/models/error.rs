@@ -1,3 +1,5 @@
+use crate::models::RateLimitStatus;
+use chrono::{DateTime, Utc};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -30,12 +32,23 @@ pub enum Error {
     FromUtf8(#[from] std::string::FromUtf8Error),
     #[error("totp error")]
     Totp(#[from] totp_rs::TotpUrlError),
+    /// Throttled until the given moment
     #[error("too many requests, try again later")]
-    TooManyRequests,
+    TooManyRequests(DateTime<Utc>),
+    #[error("rate limit exceeded, try again later")]
+    RateLimited(RateLimitStatus),
+    #[error("message quota exceeded")]
+    QuotaExceeded(Option<DateTime<Utc>>),
     #[error("organization has been blocked")]
     OrgBlocked,
+    #[error("organization has paused sending")]
+    SendingPaused,
     #[error("Template could not be rendered")]
     Askama(#[from] askama::Error),
+    #[error("archive could not be written: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("file could not be accessed: {0}")]
+    Io(#[from] std::io::Error),
 }
 
 impl From<sqlx::Error> for Error {
@@ -1,4 +1,5 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeDelta, Utc};
+use email_address::EmailAddress;
 use rand::distr::{Alphanumeric, SampleString};
 use serde::Serialize;
 use serde_json::json;
@@ -28,17 +29,14 @@ pub struct CreatedInviteWithPassword {
 }
 
 impl CreatedInviteWithPassword {
-    #[cfg(test)]
     pub fn password(&self) -> &String {
         &self.password
     }
 
-    #[cfg_attr(not(test), allow(dead_code))]
     pub fn id(&self) -> &InviteId {
         &self.id
     }
 
-    #[cfg(test)]
     pub fn organization_id(&self) -> &OrganizationId {
         &self.organization_id
     }
@@ -47,6 +45,10 @@ impl CreatedInviteWithPassword {
     pub fn created_by(&self) -> &ApiUserId {
         &self.created_by
     }
+
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        self.expires_at
+    }
 }
 
 #[derive(Serialize, ToSchema)]
@@ -62,6 +64,8 @@ pub struct ApiInvite {
     created_by_name: String,
     created_at: DateTime<Utc>,
     expires_at: DateTime<Utc>,
+    /// Address the invite link was emailed to, if any
+    email: Option<String>,
 }
 
 impl ApiInvite {
@@ -77,6 +81,18 @@ impl ApiInvite {
         self.role
     }
 
+    pub fn organization_name(&self) -> &str {
+        &self.organization_name
+    }
+
+    pub fn created_by_name(&self) -> &str {
+        &self.created_by_name
+    }
+
+    pub fn email(&self) -> Option<&str> {
+        self.email.as_deref()
+    }
+
     #[cfg_attr(not(test), allow(dead_code))]
     pub fn id(&self) -> &InviteId {
         &self.id
@@ -158,6 +174,92 @@ impl InviteRepository {
         })
     }
 
+    /// Generate a new password for an invite and email its link to `email`
+    ///
+    /// Only the password hash is stored, so the previous link stops working. The new password is
+    /// only stored if `send` succeeds, such that the previous link keeps working if the email
+    /// could not be sent. Fails with [`Error::TooManyRequests`] if the invite link was already
+    /// (re)sent less than `min_interval` ago.
+    pub async fn resend<F>(
+        &self,
+        invite_id: InviteId,
+        org_id: OrganizationId,
+        email: &EmailAddress,
+        min_interval: TimeDelta,
+        actor: impl Into<Actor>,
+        send: F,
+    ) -> Result<CreatedInviteWithPassword, Error>
+    where
+        F: AsyncFnOnce(&CreatedInviteWithPassword) -> Result<(), Error>,
+    {
+        let password = Alphanumeric.sample_string(&mut rand::rng(), 32);
+        let password_hash = password_auth::generate_hash(password.as_bytes());
+
+        let mut tx = self.pool.begin().await?;
+
+        // the row stays locked until the email has been sent, so concurrent resends are throttled
+        let Some(invite) = sqlx::query!(
+            r#"
+            UPDATE organization_invites
+            SET password_hash = $3,
+                last_sent_at = now(),
+                email = $5
+            WHERE id = $1 AND organization_id = $2
+              AND (last_sent_at IS NULL OR last_sent_at <= now() - make_interval(secs => $4))
+            RETURNING id, organization_id, role as "role: Role", created_by, created_at, expires_at
+            "#,
+            *invite_id,
+            *org_id,
+            password_hash,
+            min_interval.num_seconds() as f64,
+            email.as_str(),
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            let resend_at = sqlx::query_scalar!(
+                r#"
+                SELECT last_sent_at + make_interval(secs => $3) AS "resend_at!"
+                FROM organization_invites
+                WHERE id = $1 AND organization_id = $2
+                "#,
+                *invite_id,
+                *org_id,
+                min_interval.num_seconds() as f64,
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+
+            return Err(Error::TooManyRequests(resend_at));
+        };
+
+        let resent = CreatedInviteWithPassword {
+            id: invite.id.into(),
+            password,
+            organization_id: invite.organization_id.into(),
+            role: invite.role,
+            created_by: invite.created_by.into(),
+            created_at: invite.created_at,
+            expires_at: invite.expires_at,
+        };
+
+        send(&resent).await?;
+
+        self.audit_log
+            .log(
+                &mut tx,
+                actor,
+                (invite_id, org_id),
+                "Resent invite link",
+                Some(json!({ "email": email.as_str() })),
+            )
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(resent)
+    }
+
     pub async fn get_by_org(&self, org_id: OrganizationId) -> Result<Vec<ApiInvite>, Error> {
         Ok(sqlx::query_as!(
             ApiInvite,
@@ -165,7 +267,7 @@ impl InviteRepository {
             SELECT i.id, i.organization_id, o.name AS organization_name,
                 i.role as "role: Role", i.password_hash,
                 i.created_by, a.name AS created_by_name, 
-                i.created_at, i.expires_at
+                i.created_at, i.expires_at, i.email
             FROM organization_invites i
             JOIN organizations o ON o.id = i.organization_id
             JOIN api_users a ON a.id = i.created_by
@@ -188,7 +290,7 @@ impl InviteRepository {
             SELECT i.id, i.organization_id, o.name AS organization_name, 
                 i.role as "role: Role", i.password_hash,
                 i.created_by, a.name AS created_by_name, 
-                i.created_at, i.expires_at
+                i.created_at, i.expires_at, i.email
             FROM organization_invites i
             JOIN organizations o ON o.id = i.organization_id
             JOIN api_users a ON a.id = i.created_by
@@ -8,7 +8,7 @@ use utoipa::{
     openapi::{ObjectBuilder, RefOr, Schema, Type},
 };
 
-#[derive(Validate, Serialize, sqlx::Type, Display, Debug, Deref, PartialEq, Eq)]
+#[derive(Validate, Serialize, sqlx::Type, Display, Debug, Clone, Deref, PartialEq, Eq)]
 #[serde(transparent)]
 #[sqlx(transparent)]
 #[garde(transparent)]
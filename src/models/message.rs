@@ -1,26 +1,32 @@
 use crate::{
     SubscriptionStatus,
     bus::client::BusMessage,
-    handler::{ConnectionLog, RetryConfig},
+    handler::{ConnectionLog, RetryConfig, Transcript},
     models::{
         ApiKeyId, Error, OrgBlockStatus, OrganizationId, SmtpCredentialId, labels::Label,
         projects::ProjectId,
     },
 };
+use async_stream::try_stream;
+use aws_lc_rs::digest;
 use chrono::{DateTime, Utc};
 use derive_more::{Display, FromStr};
 use email_address::EmailAddress;
+use futures::Stream;
 use garde::Validate;
 use mail_builder::MessageBuilder;
 use mail_parser::{HeaderName, MessageParser, MimeHeaders};
 use rand::RngExt;
 use serde::{Deserialize, Deserializer, Serialize};
-use std::{cmp::min, collections::HashMap, mem, str::FromStr};
-use tracing::{debug, error, span, trace};
+use sqlx::types::ipnet::IpNet;
+use std::{cmp::min, collections::HashMap, fmt::Write, mem, net::IpAddr, str::FromStr};
+use tracing::{debug, error, span, trace, warn};
 use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
 const API_RAW_TRUNCATE_LENGTH: i32 = 10_000;
+/// Number of bytes fetched from the database at once when streaming the full raw data
+const RAW_STREAM_CHUNK_SIZE: i32 = 256 * 1024;
 
 id!(MessageId);
 
@@ -43,6 +49,53 @@ pub enum MessageStatus {
     Delivered,
     Reattempt,
     Failed,
+    /// Failed after exhausting its automatic retries, waiting for a user to requeue it
+    #[serde(rename = "needs_review")]
+    #[sqlx(rename = "needs_review")]
+    NeedsReview,
+}
+
+/// Why a message is on `held`
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Deserialize, Serialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "hold_reason", rename_all = "lowercase")]
+pub enum HoldReason {
+    /// The organization ran out of message quota, the message is retried once the quota resets
+    Quota,
+    /// The domain or DKIM configuration is invalid, the message is only retried on user request
+    Configuration,
+    /// The message scored too high on the spam check, it is only sent on user request
+    Spam,
+}
+
+/// How urgently a message should be delivered
+///
+/// When a node is busy, higher-priority messages are delivered first.
+/// Variants are ordered from lowest to highest priority.
+#[derive(
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    Deserialize,
+    Serialize,
+    FromStr,
+    sqlx::Type,
+    ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "message_priority", rename_all = "lowercase")]
+pub enum MessagePriority {
+    /// For bulk messages such as newsletters
+    Low,
+    Normal,
+    /// For transactional messages such as password resets, the default
+    #[default]
+    High,
 }
 
 impl MessageStatus {
@@ -55,6 +108,7 @@ impl MessageStatus {
             MessageStatus::Delivered => false,
             MessageStatus::Reattempt => true,
             MessageStatus::Failed => false,
+            MessageStatus::NeedsReview => false,
         }
     }
 }
@@ -67,6 +121,7 @@ pub struct Message {
     pub(crate) smtp_credential_id: Option<SmtpCredentialId>,
     pub(crate) api_key_id: Option<ApiKeyId>,
     pub status: MessageStatus,
+    pub hold_reason: Option<HoldReason>,
     pub reason: Option<String>,
     pub delivery_details: HashMap<EmailAddress, DeliveryDetails>,
     pub from_email: EmailAddress,
@@ -80,6 +135,14 @@ pub struct Message {
     pub retry_after: Option<DateTime<Utc>>,
     pub attempts: i32,
     pub max_attempts: i32,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Whether the message is put on `needs_review` instead of `failed` once it exhausted its
+    /// automatic retries, as configured for its project when the message was created
+    #[serde(default)]
+    pub(crate) review_when_exhausted: bool,
+    /// Number of bytes prepended to `raw_data` since it was loaded from the database
+    #[serde(skip)]
+    prepended_len: usize,
 }
 
 #[derive(Serialize, ToSchema)]
@@ -99,6 +162,8 @@ pub struct ApiMessageMetadata {
     pub id: MessageId,
     project_id: ProjectId,
     pub status: MessageStatus,
+    /// Only set for messages on `held`
+    hold_reason: Option<HoldReason>,
     reason: Option<String>,
     /// Delivery details for each recipient Remails tried to deliver to already.
     /// Uses the recipient email as key and `DeliveryDetails` as value.
@@ -114,10 +179,58 @@ pub struct ApiMessageMetadata {
     updated_at: DateTime<Utc>,
     retry_after: Option<DateTime<Utc>>,
     pub label: Option<Label>,
+    pub priority: MessagePriority,
     #[schema(minimum = 0)]
     attempts: i32,
     #[schema(minimum = 0)]
     max_attempts: i32,
+    /// After this moment, the message is not retried anymore
+    expires_at: Option<DateTime<Utc>>,
+    /// The message could not be parsed as MIME, so it has no message data and is sent as-is
+    unparseable: bool,
+    /// IP address of the client that submitted the message, only visible to organization admins
+    #[schema(value_type = Option<String>)]
+    pub client_ip: Option<IpAddr>,
+    /// Correlation ID of the API request that submitted the message,
+    /// as returned in the `X-Correlation-Id` response header
+    pub correlation_id: Option<String>,
+}
+
+impl ApiMessage {
+    pub fn hide_admin_details(&mut self) {
+        self.metadata.hide_admin_details();
+    }
+
+    /// Strong entity tag that changes whenever the message row is updated. Responses with and
+    /// without the admin details get different tags, as their bodies differ.
+    pub fn etag(&self, admin_details: bool) -> String {
+        let version = format!(
+            "{}:{}:{admin_details}",
+            self.metadata.id,
+            self.metadata.updated_at.timestamp_micros()
+        );
+        let digest = digest::digest(&digest::SHA256, version.as_bytes());
+
+        let tag = digest.as_ref()[..16]
+            .iter()
+            .fold(String::with_capacity(32), |mut hex, b| {
+                let _ = write!(hex, "{b:02x}");
+                hex
+            });
+
+        format!("\"{tag}\"")
+    }
+}
+
+impl ApiMessageMetadata {
+    /// Hide the IP address of the submitting client and the SMTP transcripts,
+    /// for users that are not organization admins
+    pub fn hide_admin_details(&mut self) {
+        self.client_ip = None;
+        for details in self.delivery_details.values_mut() {
+            details.transcript = Transcript::default();
+        }
+    }
 }
 
 #[derive(Serialize, Default, ToSchema)]
@@ -155,17 +268,58 @@ pub enum DeliveryStatus {
     Suppressed,
 }
 
+/// Classification of a failed delivery attempt
+///
+/// Soft bounces, like a full mailbox, are reattempted. Hard bounces, like a non-existent mailbox,
+/// are not, and count towards suppressing the recipient.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Bounce {
+    Soft,
+    Hard,
+}
+
 /// Details of the email transmission for a specific recipient
 #[derive(Debug, Deserialize, Serialize, Default, ToSchema)]
 pub struct DeliveryDetails {
     pub status: DeliveryStatus,
+    /// How the last delivery attempt failed, if it failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bounce: Option<Bounce>,
     pub log: ConnectionLog,
+    /// The SMTP exchange of the last delivery attempt, only shown to organization admins
+    #[serde(default, skip_serializing_if = "Transcript::is_empty")]
+    pub transcript: Transcript,
 }
 
 impl DeliveryDetails {
     pub fn new(status: DeliveryStatus, log: ConnectionLog) -> Self {
-        Self { status, log }
+        Self {
+            status,
+            bounce: None,
+            log,
+            transcript: Transcript::default(),
+        }
+    }
+}
+
+/// Prepend the headers to the raw message data, in the given order
+///
+/// The message is copied exactly once into a buffer of the final size, instead of moving
+/// the (potentially multi-megabyte) message data for each of the headers.
+fn prepend_headers(raw_data: &mut Vec<u8>, headers: &[&str]) {
+    let hdr_size: usize = headers.iter().map(|h| h.len()).sum();
+    if hdr_size == 0 {
+        return;
     }
+
+    let mut data = Vec::with_capacity(hdr_size + raw_data.len());
+    for header in headers {
+        data.extend_from_slice(header.as_bytes());
+    }
+    data.extend_from_slice(raw_data);
+
+    *raw_data = data;
 }
 
 impl Message {
@@ -173,15 +327,26 @@ impl Message {
         self.id
     }
 
-    pub fn prepend_headers(&mut self, headers: &str) {
-        // TODO: we could 'overallocate' the original raw message data to prepend this stuff without
-        // needing to allocate or move data around.
-        let hdr_size = headers.len();
-        let msg_len = self.raw_data.len();
+    /// Prepend all header groups to the raw message data at once, see [`prepend_headers`]
+    pub fn prepend_headers(&mut self, headers: &[&str]) {
+        prepend_headers(&mut self.raw_data, headers);
+        self.prepended_len += headers.iter().map(|h| h.len()).sum::<usize>();
+    }
+
+    /// The headers prepended to the raw message data, which are not stored in the database
+    pub fn prepended_headers(&self) -> &[u8] {
+        &self.raw_data[..self.prepended_len]
+    }
+
+    /// Size of the raw message data as stored in the database
+    pub fn stored_size(&self) -> usize {
+        self.raw_data.len() - self.prepended_len
+    }
 
-        self.raw_data.resize(msg_len + hdr_size, Default::default());
-        self.raw_data.copy_within(..msg_len, hdr_size);
-        self.raw_data[..hdr_size].copy_from_slice(headers.as_bytes());
+    /// Free the raw message data, e.g., once it is streamed from the database instead
+    pub fn release_raw_data(&mut self) {
+        self.raw_data = Vec::new();
+        self.prepended_len = 0;
     }
 
     pub fn set_next_retry(&mut self, config: &RetryConfig) {
@@ -194,25 +359,86 @@ impl Message {
             return;
         }
 
-        if self.attempts < config.max_automatic_retries {
-            let timeout = config
-                .delay
-                .checked_mul(self.attempts)
-                .unwrap_or(chrono::TimeDelta::days(1))
-                .checked_add(&chrono::TimeDelta::seconds(
-                    rand::rng().random_range(0..300),
-                ))
-                .unwrap_or(chrono::TimeDelta::days(1));
-            self.retry_after = Some(chrono::Utc::now() + timeout);
+        // Quota holds are retried once the quota resets, and configuration holds only on user
+        // request, see `MessageRepository::find_messages_ready_for_retry`
+        if self.status == MessageStatus::Held && self.hold_reason.is_some() {
+            self.retry_after = None;
+            return;
+        }
+
+        let timeout = config
+            .delay
+            .checked_mul(self.attempts)
+            .unwrap_or(chrono::TimeDelta::days(1))
+            .checked_add(&chrono::TimeDelta::seconds(
+                rand::rng().random_range(0..300),
+            ))
+            .unwrap_or(chrono::TimeDelta::days(1));
+        let retry_after = chrono::Utc::now() + timeout;
+
+        // messages are not retried beyond their maximum age, even if attempts are left
+        let expired = self
+            .expires_at
+            .is_some_and(|expires_at| retry_after > expires_at);
+
+        if self.attempts < self.max_attempts && !expired {
+            self.retry_after = Some(retry_after);
         } else {
             match &self.status {
                 MessageStatus::Held => self.status = MessageStatus::Rejected,
+                // expired messages cannot be requeued, so they are never reviewed
+                MessageStatus::Reattempt if self.review_when_exhausted && !expired => {
+                    self.status = MessageStatus::NeedsReview
+                }
                 MessageStatus::Reattempt => self.status = MessageStatus::Failed,
                 _ => {}
             };
             self.retry_after = None;
         }
     }
+
+    /// Move a scheduled retry forward for greylisted messages, as greylisting receivers
+    /// expect a retry within a few minutes. Only applies to the first few attempts.
+    pub fn set_next_greylist_retry(&mut self, config: &RetryConfig) {
+        let Some(retry_after) = self.retry_after else {
+            return;
+        };
+
+        if self.attempts <= config.greylist.max_retries {
+            self.retry_after = Some(min(retry_after, chrono::Utc::now() + config.greylist.delay));
+        }
+    }
+}
+
+/// Rate limit and quota state of an organization right after a message creation attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitStatus {
+    /// Number of messages that can still be created before getting rate limited
+    pub remaining: i64,
+    /// The moment at which all rate limit tokens have been refilled
+    pub reset: DateTime<Utc>,
+    /// The moment at which the next rate limit token is added, if none are left
+    pub retry_after: Option<DateTime<Utc>>,
+    /// Number of messages left in the organization's message quota
+    pub remaining_quota: i64,
+}
+
+/// Result of storing a new message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Created<T> {
+    /// The message was stored as a new message
+    New(T),
+    /// A message with the same `Message-ID` header was already stored within the project's
+    /// deduplication window, this is the existing message
+    Duplicate(T),
+}
+
+impl<T> Created<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            Created::New(t) | Created::Duplicate(t) => t,
+        }
+    }
 }
 
 /// A new email coming from the in-bound SMTP server
@@ -223,6 +449,8 @@ pub struct NewMessage {
     pub from_email: EmailAddress,
     pub recipients: Vec<EmailAddress>,
     pub raw_data: Vec<u8>,
+    /// IP address of the SMTP client that submitted the message
+    pub client_ip: Option<IpAddr>,
 }
 
 impl NewMessage {
@@ -233,6 +461,7 @@ impl NewMessage {
             from_email,
             recipients: vec![],
             raw_data: vec![],
+            client_ip: None,
         }
     }
 }
@@ -246,6 +475,11 @@ pub struct NewApiMessage {
     pub label: Option<Label>,
     pub recipients: Vec<EmailAddress>,
     pub raw_data: Vec<u8>,
+    /// IP address of the HTTP client that submitted the message
+    pub client_ip: Option<IpAddr>,
+    /// Ties the log lines of the API request to those of the delivery
+    pub correlation_id: String,
+    pub priority: MessagePriority,
 }
 
 #[derive(Debug, Clone)]
@@ -275,6 +509,26 @@ pub struct MessageFilter {
     before: Option<DateTime<Utc>>,
     #[garde(skip)]
     pub project: Option<ProjectId>,
+    /// Only list messages submitted via SMTP or via the API
+    #[garde(skip)]
+    source: Option<MessageSource>,
+}
+
+/// How a message has been submitted to Remails
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageSource {
+    Smtp,
+    Api,
+}
+
+impl MessageSource {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MessageSource::Smtp => "smtp",
+            MessageSource::Api => "api",
+        }
+    }
 }
 
 fn deserialize_comma_separated_list<'de, D, T>(deserializer: D) -> Result<Option<Vec<T>>, D::Error>
@@ -304,10 +558,124 @@ impl Default for MessageFilter {
             labels: None,
             before: None,
             project: None,
+            source: None,
+        }
+    }
+}
+
+/// Only list stuck messages that have not been updated for at least this many minutes, by default
+const fn default_stuck_after_minutes() -> i64 {
+    60
+}
+
+#[derive(Debug, Deserialize, IntoParams, Validate)]
+#[serde(default)]
+pub struct StuckMessageFilter {
+    /// Only list messages that have not been updated for at least this many minutes
+    #[param(minimum = 1, default = default_stuck_after_minutes)]
+    #[garde(range(min = 1))]
+    older_than_minutes: i64,
+    #[param(minimum = 1, maximum = 100, default = default_limit)]
+    #[garde(range(min = 1, max = 100))]
+    limit: i64,
+}
+
+impl Default for StuckMessageFilter {
+    fn default() -> Self {
+        Self {
+            older_than_minutes: default_stuck_after_minutes(),
+            limit: default_limit(),
         }
     }
 }
 
+/// Number of messages fetched at once when exporting
+const EXPORT_PAGE_SIZE: i64 = 1000;
+
+#[derive(Debug, Default, Deserialize, IntoParams, Validate)]
+#[serde(default)]
+pub struct ExportFilter {
+    /// Only export messages created at or after this moment
+    #[garde(skip)]
+    after: Option<DateTime<Utc>>,
+    /// Only export messages created before this moment
+    #[garde(skip)]
+    before: Option<DateTime<Utc>>,
+}
+
+/// Message metadata as exported for reporting
+#[derive(Serialize)]
+#[cfg_attr(test, derive(Deserialize))]
+pub struct ExportedMessage {
+    pub id: MessageId,
+    pub status: MessageStatus,
+    pub from_email: String,
+    pub recipient_count: i32,
+    pub created_at: DateTime<Utc>,
+    /// When the last recipient accepted the message, only set for delivered messages
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+/// The delivery progress of a message, for clients polling until it is delivered
+#[cfg_attr(test, derive(Deserialize))]
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiMessageStatus {
+    pub status: MessageStatus,
+    /// Number of delivery attempts so far
+    #[schema(minimum = 0)]
+    pub attempts: i32,
+    /// The message is not retried once it reached this number of attempts
+    #[schema(minimum = 0)]
+    pub max_attempts: i32,
+    /// When the next delivery attempt is made, only set while the message is on `reattempt`
+    pub retry_after: Option<DateTime<Utc>>,
+}
+
+/// A message in a non-terminal state, as listed for operators across all organizations
+#[cfg_attr(test, derive(Deserialize))]
+#[derive(Serialize, ToSchema)]
+pub struct StuckMessage {
+    pub id: MessageId,
+    pub organization_id: OrganizationId,
+    project_id: ProjectId,
+    pub status: MessageStatus,
+    /// Only set for messages on `held`
+    hold_reason: Option<HoldReason>,
+    reason: Option<String>,
+    #[schema(minimum = 0)]
+    attempts: i32,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    retry_after: Option<DateTime<Utc>>,
+}
+
+/// Size of the delivery backlog across all organizations, for monitoring the send pipeline
+#[cfg_attr(test, derive(Deserialize))]
+#[derive(Serialize, ToSchema)]
+pub struct QueueDepth {
+    /// Number of messages on `processing`
+    #[schema(minimum = 0)]
+    pub processing: i64,
+    /// Number of messages on `accepted`
+    #[schema(minimum = 0)]
+    pub accepted: i64,
+    /// Number of messages on `reattempt`
+    #[schema(minimum = 0)]
+    pub reattempt: i64,
+    /// Number of messages on `held`, which wait for the quota to reset or for a user
+    #[schema(minimum = 0)]
+    pub held: i64,
+    /// Number of messages on `needs_review`, which exhausted their retries and wait for a user
+    #[schema(minimum = 0)]
+    pub needs_review: i64,
+    /// When the oldest message that is waiting to be delivered was created,
+    /// i.e., on `processing`, `accepted`, or `reattempt`
+    pub oldest_pending_at: Option<DateTime<Utc>>,
+    /// Age of the oldest message that is waiting to be delivered in seconds
+    #[schema(minimum = 0)]
+    pub oldest_pending_age_seconds: Option<i64>,
+}
+
 struct PgMessage {
     id: MessageId,
     organization_id: OrganizationId,
@@ -315,6 +683,7 @@ struct PgMessage {
     smtp_credential_id: Option<Uuid>,
     api_key_id: Option<Uuid>,
     status: MessageStatus,
+    hold_reason: Option<HoldReason>,
     reason: Option<String>,
     delivery_details: serde_json::Value,
     from_email: String,
@@ -327,8 +696,14 @@ struct PgMessage {
     updated_at: DateTime<Utc>,
     retry_after: Option<DateTime<Utc>>,
     label: Option<Label>,
+    priority: MessagePriority,
     attempts: i32,
     max_attempts: i32,
+    expires_at: Option<DateTime<Utc>>,
+    review_when_exhausted: bool,
+    unparseable: bool,
+    client_ip: Option<IpNet>,
+    correlation_id: Option<String>,
 }
 
 impl TryFrom<PgMessage> for Message {
@@ -342,6 +717,7 @@ impl TryFrom<PgMessage> for Message {
             smtp_credential_id: m.smtp_credential_id.map(Into::into),
             api_key_id: m.api_key_id.map(Into::into),
             status: m.status,
+            hold_reason: m.hold_reason,
             reason: m.reason,
             delivery_details: serde_json::from_value(m.delivery_details)?,
             from_email: EmailAddress::from_str(&m.from_email)?,
@@ -359,6 +735,9 @@ impl TryFrom<PgMessage> for Message {
             retry_after: m.retry_after,
             attempts: m.attempts,
             max_attempts: m.max_attempts,
+            expires_at: m.expires_at,
+            review_when_exhausted: m.review_when_exhausted,
+            prepended_len: 0,
         })
     }
 }
@@ -422,6 +801,7 @@ impl TryFrom<PgMessage> for ApiMessageMetadata {
             id: m.id,
             project_id: m.project_id,
             status: m.status,
+            hold_reason: m.hold_reason,
             reason: m.reason,
             delivery_details: serde_json::from_value(m.delivery_details)?,
             smtp_credential_id: m.smtp_credential_id.map(Into::into),
@@ -438,13 +818,30 @@ impl TryFrom<PgMessage> for ApiMessageMetadata {
             updated_at: m.updated_at,
             retry_after: m.retry_after,
             label: m.label,
+            priority: m.priority,
             attempts: m.attempts,
             max_attempts: m.max_attempts,
+            expires_at: m.expires_at,
+            unparseable: m.unparseable,
+            client_ip: m.client_ip.map(|ip| ip.addr()),
+            correlation_id: m.correlation_id,
         })
     }
 }
 
+/// The result of parsing a newly received message
+struct ParsedMessage {
+    message_data: serde_json::Value,
+    message_id_header: String,
+    label: Option<Label>,
+    priority: Option<MessagePriority>,
+    unparseable: bool,
+}
+
 impl MessageRepository {
+    /// Deleted messages can be restored for this many days, after which their data is purged
+    pub const RECOVERY_WINDOW_DAYS: i64 = 7;
+
     pub fn new(pool: sqlx::PgPool) -> Self {
         Self {
             pool,
@@ -452,17 +849,28 @@ impl MessageRepository {
         }
     }
 
+    /// Assign an outbound IP to the message such that it can be sent
+    ///
+    /// IPs dedicated to the organization of the message are preferred. The shared IPs, i.e., those
+    /// not dedicated to any organization, are only used if none of the dedicated IPs are ready.
+    ///
+    /// Fails with [`Error::SendingPaused`] if the organization paused sending, in which case
+    /// the message is picked up by the retry scan once sending is resumed.
     pub async fn get_ready_to_send(&self, message_id: MessageId) -> Result<BusMessage, Error> {
         // TODO: do not rely on random outbound IPs
-        match sqlx::query_scalar!(
+        match sqlx::query!(
             r#"
-            SELECT ip AS outbound_ip
+            SELECT ip AS outbound_ip, o.sending_paused, m.correlation_id,
+                   m.priority AS "priority: MessagePriority"
             FROM outbound_ips
             JOIN k8s_nodes AS node on outbound_ips.node_id = node.id
+            LEFT JOIN organization_outbound_ips dedicated ON dedicated.outbound_ip_id = outbound_ips.id
             JOIN messages m ON m.id = $1
             JOIN organizations o ON o.id = m.organization_id
             WHERE node.ready AND o.block_status = 'not_blocked' AND octet_length(raw_data) > 0
-            ORDER BY RANDOM()
+              AND m.deleted_at IS NULL
+              AND (dedicated.organization_id IS NULL OR dedicated.organization_id = o.id)
+            ORDER BY dedicated.organization_id IS NULL, RANDOM()
             LIMIT 1
             "#,
             *message_id
@@ -470,9 +878,13 @@ impl MessageRepository {
         .fetch_optional(&self.pool)
         .await
         {
-            Ok(Some(outbound_ip)) => {
-                Ok(BusMessage::EmailReadyToSend(message_id, outbound_ip.addr()))
-            }
+            Ok(Some(row)) if row.sending_paused => Err(Error::SendingPaused),
+            Ok(Some(row)) => Ok(BusMessage::EmailReadyToSend(
+                message_id,
+                row.outbound_ip.addr(),
+                row.correlation_id,
+                row.priority,
+            )),
             Ok(None) => Err(Error::Internal(
                 "failed to assign outbound IP to message: none available".to_string(),
             )),
@@ -482,6 +894,46 @@ impl MessageRepository {
         }
     }
 
+    /// When the outbound IP started warming up, `None` if it is considered warm
+    pub async fn outbound_ip_warmup_started_at(
+        &self,
+        outbound_ip: IpAddr,
+    ) -> Result<Option<DateTime<Utc>>, Error> {
+        Ok(sqlx::query_scalar!(
+            r#"
+            SELECT warmup_started_at FROM outbound_ips WHERE ip = $1
+            "#,
+            IpNet::from(outbound_ip)
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .flatten())
+    }
+
+    /// Count a message sent from the outbound IP in the current hour, unless `hourly_limit`
+    /// messages have been sent from it already. Returns whether the message may be sent.
+    pub async fn record_outbound_ip_usage(
+        &self,
+        outbound_ip: IpAddr,
+        hourly_limit: i32,
+    ) -> Result<bool, Error> {
+        Ok(sqlx::query_scalar!(
+            r#"
+            INSERT INTO outbound_ip_hourly_usage (outbound_ip_id, hour, sent)
+            SELECT id, date_trunc('hour', now()), 1 FROM outbound_ips WHERE ip = $1 AND $2 > 0
+            ON CONFLICT (outbound_ip_id, hour) DO UPDATE
+                SET sent = outbound_ip_hourly_usage.sent + 1
+                WHERE outbound_ip_hourly_usage.sent < $2
+            RETURNING sent
+            "#,
+            IpNet::from(outbound_ip),
+            hourly_limit
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .is_some())
+    }
+
     /// Generate a unique message ID to be included as email header in case no message ID was provided
     pub fn generate_message_id_header(id: &MessageId, from_email: &EmailAddress) -> String {
         let sender_domain = from_email.domain();
@@ -496,11 +948,36 @@ impl MessageRepository {
         raw_data: &mut Vec<u8>,
         id: &MessageId,
         from_email: &EmailAddress,
-    ) -> Result<(serde_json::Value, String, Option<Label>), Error> {
-        let mut parsed_msg = self
+    ) -> Result<ParsedMessage, Error> {
+        // Messages that cannot be parsed as MIME, or in which no header could be found at all,
+        // are stored and sent as-is. Only the Message-ID and Date headers are added.
+        let Some(mut parsed_msg) = self
             .message_parser
             .parse(raw_data)
-            .ok_or(Error::EmailFailedToParse)?;
+            .filter(|parsed_msg| !parsed_msg.headers().is_empty())
+        else {
+            warn!(
+                message_id = id.to_string(),
+                "could not parse message, storing it as-is"
+            );
+            let message_id_header = MessageRepository::generate_message_id_header(id, from_email);
+            let date = Utc::now().to_rfc2822();
+            prepend_headers(
+                raw_data,
+                &[
+                    &format!("Message-ID: <{message_id_header}>\r\n"),
+                    &format!("Date: {date}\r\n"),
+                ],
+            );
+
+            return Ok(ParsedMessage {
+                message_data: serde_json::Value::Null,
+                message_id_header,
+                label: None,
+                priority: None,
+                unparseable: true,
+            });
+        };
 
         let mut new_headers = Vec::new();
 
@@ -519,13 +996,10 @@ impl MessageRepository {
 
         if !new_headers.is_empty() {
             trace!("updating message {}", id);
-            let headers = new_headers.join("");
-            let hdr_size = headers.len();
-            let msg_len = raw_data.len();
-
-            raw_data.resize(msg_len + hdr_size, Default::default());
-            raw_data.copy_within(..msg_len, hdr_size);
-            raw_data[..hdr_size].copy_from_slice(headers.as_bytes());
+            prepend_headers(
+                raw_data,
+                &new_headers.iter().map(String::as_str).collect::<Vec<_>>(),
+            );
 
             // we need to re-parse the message because the data has shifted
             parsed_msg = self
@@ -537,6 +1011,9 @@ impl MessageRepository {
         let label = parsed_msg
             .remove_header("X-REMAILS-LABEL")
             .and_then(|l| l.as_text().map(Label::new));
+        let priority = parsed_msg
+            .remove_header("X-REMAILS-PRIORITY")
+            .and_then(|p| p.as_text().and_then(|p| p.trim().parse().ok()));
 
         let message_data = serde_json::to_value(&parsed_msg).map_err(Error::Serialization)?;
         let message_id_header =
@@ -547,32 +1024,61 @@ impl MessageRepository {
                     "failed to get Message ID header".to_owned(), // should not happen
                 ))?;
 
-        Ok((message_data, message_id_header, label))
+        Ok(ParsedMessage {
+            message_data,
+            message_id_header,
+            label,
+            priority,
+            unparseable: false,
+        })
     }
 
+    /// Store a message received via SMTP
+    ///
+    /// If the project has deduplication enabled and a message with the same `Message-ID` header
+    /// was created within the deduplication window, the existing message ID is returned instead.
     pub async fn create(
         &self,
         mut message: NewMessage,
         max_attempts: i32,
-    ) -> Result<MessageId, Error> {
-        let (message_data, message_id_header, label) = self.parse_message(
+    ) -> Result<Created<MessageId>, Error> {
+        let parsed = self.parse_message(
             &mut message.raw_data,
             &message.message_id,
             &message.from_email,
         )?;
 
-        Ok(sqlx::query_scalar!(
+        let project_id: ProjectId = sqlx::query_scalar!(
+            r#"
+            SELECT project_id FROM smtp_credentials WHERE id = $1
+            "#,
+            *message.smtp_credential_id,
+        )
+        .fetch_one(&self.pool)
+        .await?
+        .into();
+        self.release_dedup_key(project_id, &parsed.message_id_header)
+            .await?;
+
+        let id = sqlx::query_scalar!(
             r#"
             INSERT INTO messages AS m (
                 id, organization_id, project_id, smtp_credential_id,
-                from_email, recipients, raw_data, max_attempts,
-                message_data, message_id_header, label
+                from_email, recipients, raw_data, max_attempts, expires_at, review_when_exhausted,
+                message_data, message_id_header, label, unparseable, client_ip,
+                priority, dedup_key
             )
-            SELECT $1, o.id, p.id, $2, $3, $4, $5, $6, $7, $8, $9
+            SELECT $1, o.id, p.id, $2, $3, $4, $5,
+                   COALESCE(p.max_automatic_retries, $6),
+                   now() + p.max_message_age_minutes * INTERVAL '1 minute',
+                   p.review_exhausted_messages,
+                   $7, $8, $9, $10, $11, $12,
+                   CASE WHEN p.dedup_window_minutes IS NOT NULL THEN $8::varchar END
             FROM smtp_credentials s
                 JOIN projects p ON p.id = s.project_id
                 JOIN organizations o ON o.id = p.organization_id
             WHERE s.id = $2
+            ON CONFLICT (project_id, dedup_key) WHERE dedup_key IS NOT NULL DO NOTHING
             RETURNING
                 m.id
             "#,
@@ -586,13 +1092,106 @@ impl MessageRepository {
                 .collect::<Vec<_>>(),
             message.raw_data,
             max_attempts,
-            message_data,
+            parsed.message_data,
+            parsed.message_id_header,
+            parsed.label.as_deref(),
+            parsed.unparseable,
+            message.client_ip.map(IpNet::from),
+            parsed.priority.unwrap_or_default() as MessagePriority,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match id {
+            Some(id) => Ok(Created::New(id.into())),
+            None => Ok(Created::Duplicate(
+                self.find_duplicate(project_id, &parsed.message_id_header)
+                    .await?
+                    .id,
+            )),
+        }
+    }
+
+    /// Allow the `Message-ID` header to be used again by a new message, if the message that
+    /// used it before has left the project's deduplication window or has been removed
+    async fn release_dedup_key(
+        &self,
+        project_id: ProjectId,
+        message_id_header: &str,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            r#"
+            UPDATE messages m
+            SET dedup_key = NULL
+            FROM projects p
+            WHERE p.id = m.project_id
+                AND m.project_id = $1
+                AND m.dedup_key = $2
+                AND (
+                    p.dedup_window_minutes IS NULL
+                    OR m.created_at <= now() - p.dedup_window_minutes * INTERVAL '1 minute'
+                    OR m.deleted_at IS NOT NULL
+                )
+            "#,
+            *project_id,
             message_id_header,
-            label.as_deref(),
         )
-        .fetch_one(&self.pool)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Find the message in the project that prevented a new message with the same `Message-ID`
+    /// header from being stored
+    async fn find_duplicate(
+        &self,
+        project_id: ProjectId,
+        message_id_header: &str,
+    ) -> Result<ApiMessageMetadata, Error> {
+        sqlx::query_as!(
+            PgMessage,
+            r#"
+            SELECT
+                m.id,
+                m.organization_id,
+                m.project_id,
+                m.smtp_credential_id,
+                m.api_key_id,
+                m.status AS "status: _",
+                m.hold_reason AS "hold_reason: _",
+                m.reason,
+                m.delivery_details,
+                m.from_email,
+                m.recipients,
+                ''::bytea AS "raw_data!",
+                NULL::jsonb AS "message_data",
+                octet_length(m.raw_data) AS "raw_size!",
+                m.message_id_header,
+                m.created_at,
+                m.updated_at,
+                m.retry_after,
+                m.attempts,
+                m.max_attempts,
+                m.expires_at,
+                m.review_when_exhausted,
+                m.unparseable,
+                m.client_ip,
+                m.correlation_id,
+                m.label AS "label:Label",
+                m.priority AS "priority: _"
+            FROM messages m
+            WHERE m.project_id = $1
+                AND m.dedup_key = $2
+            "#,
+            *project_id,
+            message_id_header,
+        )
+        .fetch_optional(&self.pool)
         .await?
-        .into())
+        // the duplicate has been released again in the meantime
+        .ok_or(Error::Conflict)?
+        .try_into()
     }
 
     async fn internal_email_config(&self) -> Result<(EmailAddress, ProjectId), Error> {
@@ -638,18 +1237,21 @@ impl MessageRepository {
             .write_to_vec()
             .map_err(|err| Error::Internal(format!("Failed to create internal email: {err}")))?;
 
-        let (message_data, message_id_header, _) =
-            self.parse_message(&mut raw_message, &message_id, &from_email)?;
+        let parsed = self.parse_message(&mut raw_message, &message_id, &from_email)?;
 
         let to = [to.to_string()];
         sqlx::query!(
             r#"
             INSERT INTO messages AS m (
                 id, organization_id, project_id,
-                from_email, recipients, raw_data, max_attempts,
-                message_data, message_id_header, label
+                from_email, recipients, raw_data, max_attempts, expires_at, review_when_exhausted,
+                message_data, message_id_header, label, unparseable
             )
-            SELECT $1, o.id, $2, $3, $4, $5, $6, $7, $8, $9
+            SELECT $1, o.id, $2, $3, $4, $5,
+                   COALESCE(p.max_automatic_retries, $6),
+                   now() + p.max_message_age_minutes * INTERVAL '1 minute',
+                   p.review_exhausted_messages,
+                   $7, $8, $9, $10
             FROM projects p
                 JOIN organizations o ON o.id = p.organization_id
             WHERE p.id = $2
@@ -660,9 +1262,10 @@ impl MessageRepository {
             to.as_slice(),
             raw_message,
             max_attempts,
-            message_data,
-            message_id_header,
-            label.as_str()
+            parsed.message_data,
+            parsed.message_id_header,
+            label.as_str(),
+            parsed.unparseable,
         )
         .execute(&self.pool)
         .await?;
@@ -670,30 +1273,45 @@ impl MessageRepository {
         Ok(message_id)
     }
 
+    /// Store a message received via the REST API
+    ///
+    /// If the project has deduplication enabled and a message with the same `Message-ID` header
+    /// was created within the deduplication window, the existing message is returned instead.
     pub async fn create_from_api(
         &self,
         mut message: NewApiMessage,
         max_attempts: i32,
-    ) -> Result<ApiMessageMetadata, Error> {
-        // the REST API provides its own message label and does not use the X-REMAILS-LABEL header
-        let (message_data, message_id_header, _) = self.parse_message(
+    ) -> Result<Created<ApiMessageMetadata>, Error> {
+        // the REST API provides its own message label and priority,
+        // and does not use the X-REMAILS-LABEL and X-REMAILS-PRIORITY headers
+        let parsed = self.parse_message(
             &mut message.raw_data,
             &message.message_id,
             &message.from_email,
         )?;
 
-        sqlx::query_as!(
+        self.release_dedup_key(message.project_id, &parsed.message_id_header)
+            .await?;
+
+        let created = sqlx::query_as!(
             PgMessage,
             r#"
             INSERT INTO messages AS m (
                 id, organization_id, project_id, api_key_id,
-                from_email, recipients, raw_data, max_attempts,
-                message_data, message_id_header, label
+                from_email, recipients, raw_data, max_attempts, expires_at, review_when_exhausted,
+                message_data, message_id_header, label, unparseable, client_ip,
+                correlation_id, priority, dedup_key
             )
-            SELECT $1, o.id, $2, $3, $4, $5, $6, $7, $8, $9, $10
+            SELECT $1, o.id, $2, $3, $4, $5, $6,
+                   COALESCE(p.max_automatic_retries, $7),
+                   now() + p.max_message_age_minutes * INTERVAL '1 minute',
+                   p.review_exhausted_messages,
+                   $8, $9, $10, $11, $12, $13, $14,
+                   CASE WHEN p.dedup_window_minutes IS NOT NULL THEN $9::varchar END
             FROM projects p
                 JOIN organizations o ON o.id = p.organization_id
             WHERE p.id = $2
+            ON CONFLICT (project_id, dedup_key) WHERE dedup_key IS NOT NULL DO NOTHING
             RETURNING
                 m.id,
                 m.organization_id,
@@ -701,6 +1319,7 @@ impl MessageRepository {
                 m.smtp_credential_id,
                 m.api_key_id,
                 m.status as "status: _",
+                m.hold_reason as "hold_reason: _",
                 m.reason,
                 m.delivery_details,
                 m.from_email,
@@ -714,7 +1333,13 @@ impl MessageRepository {
                 m.retry_after,
                 m.attempts,
                 m.max_attempts,
-                m.label AS "label:Label"
+                m.expires_at,
+                m.review_when_exhausted,
+                m.unparseable,
+                m.client_ip,
+                m.correlation_id,
+                m.label AS "label:Label",
+                m.priority AS "priority: _"
             "#,
             *message.message_id,
             *message.project_id,
@@ -727,13 +1352,24 @@ impl MessageRepository {
                 .collect::<Vec<_>>(),
             message.raw_data,
             max_attempts,
-            message_data,
-            message_id_header,
-            message.label.as_deref()
+            parsed.message_data,
+            parsed.message_id_header,
+            message.label.as_deref(),
+            parsed.unparseable,
+            message.client_ip.map(IpNet::from),
+            message.correlation_id,
+            message.priority as MessagePriority,
         )
-        .fetch_one(&self.pool)
-        .await?
-        .try_into()
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match created {
+            Some(created) => Ok(Created::New(created.try_into()?)),
+            None => Ok(Created::Duplicate(
+                self.find_duplicate(message.project_id, &parsed.message_id_header)
+                    .await?,
+            )),
+        }
     }
 
     pub async fn update_message_status(&self, message: &mut Message) -> Result<(), Error> {
@@ -748,7 +1384,8 @@ impl MessageRepository {
                 delivery_details = $4,
                 retry_after = $5,
                 attempts = $6,
-                max_attempts = $7
+                max_attempts = $7,
+                hold_reason = $8
             WHERE id = $1
             "#,
             *message.id,
@@ -758,6 +1395,26 @@ impl MessageRepository {
             message.retry_after,
             message.attempts,
             message.max_attempts,
+            message.hold_reason as _,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Replace the stored message data, e.g., after Remails rewrote some of its headers
+    ///
+    /// Headers prepended since the message was loaded are not stored
+    pub async fn update_raw_data(&self, message: &Message) -> Result<(), Error> {
+        sqlx::query!(
+            r#"
+            UPDATE messages
+            SET raw_data = $2
+            WHERE id = $1
+            "#,
+            *message.id,
+            &message.raw_data[message.prepended_len..],
         )
         .execute(&self.pool)
         .await?;
@@ -780,6 +1437,7 @@ impl MessageRepository {
                 smtp_credential_id,
                 api_key_id,
                 status AS "status: _",
+                hold_reason AS "hold_reason: _",
                 reason,
                 delivery_details,
                 from_email,
@@ -793,14 +1451,24 @@ impl MessageRepository {
                 retry_after,
                 attempts,
                 max_attempts,
-                label AS "label:Label"
+                expires_at,
+                review_when_exhausted,
+                unparseable,
+                client_ip,
+                correlation_id,
+                label AS "label:Label",
+                priority AS "priority: _"
             FROM messages m
             WHERE organization_id = $1
                 AND ($2::uuid IS NULL OR project_id = $2)
                 AND ($3::message_status[] IS NULL OR status = ANY($3))
                 AND ($4::timestamptz IS NULL OR created_at <= $4)
                 AND ($5::text[] IS NULL OR label = ANY($5))
-                AND octet_length(raw_data) > 0 -- don't show deleted messages
+                AND ($7::text IS NULL
+                    OR ($7 = 'smtp' AND smtp_credential_id IS NOT NULL)
+                    OR ($7 = 'api' AND api_key_id IS NOT NULL))
+                AND octet_length(raw_data) > 0 -- don't show expired messages
+                AND deleted_at IS NULL
             ORDER BY created_at DESC
             LIMIT $6
             "#,
@@ -810,6 +1478,7 @@ impl MessageRepository {
             filter.before,
             filter.labels as Option<Vec<Label>>,
             std::cmp::min(filter.limit, 100) + 1, // plus one to indicate there are more entries available
+            filter.source.map(|source| source.as_str()),
         )
         .fetch_all(&self.pool)
         .await?
@@ -832,6 +1501,7 @@ impl MessageRepository {
                 m.smtp_credential_id,
                 m.api_key_id,
                 m.status as "status: _",
+                m.hold_reason as "hold_reason: _",
                 m.reason,
                 m.delivery_details,
                 m.from_email,
@@ -845,12 +1515,19 @@ impl MessageRepository {
                 m.retry_after,
                 m.attempts,
                 m.max_attempts,
-                m.label AS "label:Label"
+                m.expires_at,
+                m.review_when_exhausted,
+                m.unparseable,
+                m.client_ip,
+                m.correlation_id,
+                m.label AS "label:Label",
+                m.priority AS "priority: _"
             FROM messages m
             JOIN organizations o ON o.id = m.organization_id
             WHERE m.id = $1
               AND o.block_status = 'not_blocked'
               AND octet_length(raw_data) > 0
+              AND m.deleted_at IS NULL
             "#,
             *message_id,
         )
@@ -874,6 +1551,7 @@ impl MessageRepository {
                 m.smtp_credential_id,
                 m.api_key_id,
                 m.status as "status: _",
+                m.hold_reason as "hold_reason: _",
                 m.reason,
                 m.delivery_details,
                 m.from_email,
@@ -888,11 +1566,18 @@ impl MessageRepository {
                 m.retry_after,
                 m.attempts,
                 m.max_attempts,
-                m.label AS "label:Label"
+                m.expires_at,
+                m.review_when_exhausted,
+                m.unparseable,
+                m.client_ip,
+                m.correlation_id,
+                m.label AS "label:Label",
+                m.priority AS "priority: _"
             FROM messages m
             WHERE m.id = $1
               AND m.organization_id = $2
-              AND octet_length(m.raw_data) > 0 -- don't show deleted messages
+              AND octet_length(m.raw_data) > 0 -- don't show expired messages
+              AND m.deleted_at IS NULL
             "#,
             *message_id,
             *org_id,
@@ -903,11 +1588,10 @@ impl MessageRepository {
             .try_into()
     }
 
-    /// Remove a message from the repository
+    /// Mark a message as deleted, which hides it and stops any further delivery attempts
     ///
-    /// This removes the message's content and the recipient data, but keeps the other metadata of
-    /// message in the database to keep track of statistics. Eventually, the message's metadata will
-    /// be fully removed once its statistics have been aggregated.
+    /// The message can be restored using [`Self::restore`] until its data is purged
+    /// by [`Self::purge_deleted_messages`], after [`Self::RECOVERY_WINDOW_DAYS`].
     pub async fn remove(
         &self,
         org_id: OrganizationId,
@@ -916,26 +1600,78 @@ impl MessageRepository {
         Ok(sqlx::query_scalar!(
             r#"
             UPDATE messages
-            SET raw_data = '',
-                message_data = NULL,
-                recipients = '{}',
-                delivery_details = '{}'
-            WHERE id = $1 AND organization_id = $2
+            SET deleted_at = now()
+            WHERE id = $1
+              AND organization_id = $2
+              AND deleted_at IS NULL
+              AND octet_length(raw_data) > 0
+            RETURNING id
+            "#,
+            *message_id,
+            *org_id,
+        )
+        .fetch_one(&self.pool)
+        .await?
+        .into())
+    }
+
+    /// Undo the deletion of a message, if it is still within the recovery window
+    pub async fn restore(
+        &self,
+        org_id: OrganizationId,
+        message_id: MessageId,
+    ) -> Result<MessageId, Error> {
+        Ok(sqlx::query_scalar!(
+            r#"
+            UPDATE messages
+            SET deleted_at = NULL
+            WHERE id = $1
+              AND organization_id = $2
+              AND deleted_at > now() - $3 * INTERVAL '1 day'
+              AND octet_length(raw_data) > 0
             RETURNING id
             "#,
             *message_id,
             *org_id,
+            Self::RECOVERY_WINDOW_DAYS,
         )
         .fetch_one(&self.pool)
         .await?
         .into())
     }
 
+    /// Remove the message data of messages that were deleted longer than the recovery window ago
+    ///
+    /// Like [`Self::remove_expired_message_data`], the rows are kept to keep track of statistics.
+    pub async fn purge_deleted_messages(&self) -> Result<(), Error> {
+        let rows = sqlx::query!(
+            r#"
+            UPDATE messages m
+            SET raw_data = '',
+                message_data = NULL,
+                recipients = '{}',
+                delivery_details = '{}'
+            WHERE m.deleted_at < now() - $1 * INTERVAL '1 day'
+              AND octet_length(m.raw_data) > 0
+            "#,
+            Self::RECOVERY_WINDOW_DAYS,
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+
+        if rows > 0 {
+            debug!("Purged {rows} deleted messages");
+        }
+
+        Ok(())
+    }
+
     /// Remove message data from messages which are out of their retention period.
     ///
     /// Currently, the retention period is 30 days for all messages.
     ///
-    /// Similar to `remove()`, the rows are not yet deleted to keep track of statistics.
+    /// Like [`Self::purge_deleted_messages`], the rows are not yet deleted to keep track of statistics.
     pub async fn remove_expired_message_data(&self) -> Result<(), Error> {
         trace!("Clearing message data from old messages");
         let rows = sqlx::query!(
@@ -964,24 +1700,30 @@ impl MessageRepository {
 
     /// Messages which should be retried are either:
     ///
-    /// - on `held` or `reattempt`, not on timeout, with attempts left
+    /// - on `reattempt`, or on `held` without a hold reason, not on timeout, with attempts left,
+    ///   and not past their maximum age
     /// - on `accepted` or `processing`, and not having been updated in 2 minutes
     ///
-    /// and the organization must be allowed to send messages (must not be blocked)
+    /// and the organization must be allowed to send messages (must not be blocked or paused).
+    /// Messages on `held` due to the quota are resumed by [`Self::resume_quota_held_messages`],
+    /// and messages on `held` due to their configuration are only retried on user request.
     pub async fn find_messages_ready_for_retry(&self) -> Result<Vec<MessageId>, Error> {
         Ok(sqlx::query_scalar!(
             r#"
             SELECT m.id FROM messages m
             JOIN organizations o ON o.id = m.organization_id
             WHERE o.block_status = 'not_blocked'
+              AND NOT o.sending_paused
               AND octet_length(m.raw_data) > 0
-              AND (
-                (m.status = 'held' OR m.status = 'reattempt')
+              AND m.deleted_at IS NULL
+              AND ((
+                ((m.status = 'held' AND m.hold_reason IS NULL) OR m.status = 'reattempt')
                 AND now() > m.retry_after AND m.attempts < m.max_attempts
+                AND (m.expires_at IS NULL OR now() < m.expires_at)
               ) OR (
                 (m.status = 'accepted' OR m.status = 'processing')
                 AND now() > m.updated_at + '5 minutes'
-              )
+              ))
             "#,
         )
         .fetch_all(&self.pool)
@@ -991,16 +1733,332 @@ impl MessageRepository {
         .collect())
     }
 
-    pub async fn message_status(
+    /// Give up on messages waiting for a retry that have passed their maximum age,
+    /// even if they have attempts left, and return their IDs
+    pub async fn fail_expired_messages(&self) -> Result<Vec<MessageId>, Error> {
+        Ok(sqlx::query_scalar!(
+            r#"
+            UPDATE messages m
+            SET status = CASE WHEN m.status = 'held' THEN 'rejected' ELSE 'failed' END::message_status,
+                retry_after = NULL
+            WHERE ((m.status = 'held' AND m.hold_reason IS NULL) OR m.status = 'reattempt')
+              AND now() >= m.expires_at
+            RETURNING m.id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(Into::into)
+        .collect())
+    }
+
+    /// Give up on messages stuck in `accepted` or `processing` that are older than `max_age`,
+    /// instead of picking them up for a retry forever, and return their IDs
+    ///
+    /// Messages of organizations that paused sending are left alone, they are sent once sending resumes.
+    pub async fn fail_stuck_messages(
+        &self,
+        max_age: chrono::Duration,
+    ) -> Result<Vec<MessageId>, Error> {
+        let created_before = Utc::now() - max_age;
+        let reason = format!(
+            "message was not sent within {} hours, it will not be retried",
+            max_age.num_hours()
+        );
+
+        Ok(sqlx::query_scalar!(
+            r#"
+            UPDATE messages m
+            SET status = 'failed',
+                reason = $2,
+                retry_after = NULL
+            FROM organizations o
+            WHERE m.organization_id = o.id
+              AND NOT o.sending_paused
+              AND (m.status = 'accepted' OR m.status = 'processing')
+              AND now() > m.updated_at + '5 minutes'
+              AND m.created_at < $1
+            RETURNING m.id
+            "#,
+            created_before,
+            reason,
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(Into::into)
+        .collect())
+    }
+
+    /// List messages across all organizations that are in a non-terminal state
+    /// and have not been updated for a while, least recently updated first
+    pub async fn list_stuck(
+        &self,
+        filter: &StuckMessageFilter,
+    ) -> Result<Vec<StuckMessage>, Error> {
+        Ok(sqlx::query_as!(
+            StuckMessage,
+            r#"
+            SELECT m.id,
+                   m.organization_id,
+                   m.project_id,
+                   m.status AS "status: _",
+                   m.hold_reason AS "hold_reason: _",
+                   m.reason,
+                   m.attempts,
+                   m.created_at,
+                   m.updated_at,
+                   m.retry_after
+            FROM messages m
+            WHERE m.status IN ('accepted', 'processing', 'held', 'reattempt')
+              AND m.updated_at < now() - $1 * INTERVAL '1 minute'
+              AND m.deleted_at IS NULL
+            ORDER BY m.updated_at
+            LIMIT $2
+            "#,
+            filter.older_than_minutes,
+            filter.limit,
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// Count the messages in a non-terminal state and find the oldest one that is waiting
+    /// to be delivered, across all organizations
+    pub async fn queue_depth(&self) -> Result<QueueDepth, Error> {
+        let depth = sqlx::query!(
+            r#"
+            SELECT count(*) FILTER (WHERE m.status = 'processing') AS "processing!",
+                   count(*) FILTER (WHERE m.status = 'accepted') AS "accepted!",
+                   count(*) FILTER (WHERE m.status = 'reattempt') AS "reattempt!",
+                   count(*) FILTER (WHERE m.status = 'held') AS "held!",
+                   count(*) FILTER (WHERE m.status = 'needs_review') AS "needs_review!",
+                   min(m.created_at) FILTER (
+                       WHERE m.status IN ('accepted', 'processing', 'reattempt')
+                   ) AS oldest_pending_at
+            FROM messages m
+            WHERE m.status IN ('accepted', 'processing', 'held', 'reattempt', 'needs_review')
+              AND m.deleted_at IS NULL
+            "#,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(QueueDepth {
+            processing: depth.processing,
+            accepted: depth.accepted,
+            reattempt: depth.reattempt,
+            held: depth.held,
+            needs_review: depth.needs_review,
+            oldest_pending_at: depth.oldest_pending_at,
+            oldest_pending_age_seconds: depth
+                .oldest_pending_at
+                .map(|created_at| (Utc::now() - created_at).num_seconds().max(0)),
+        })
+    }
+
+    /// Get the status of a message, regardless of the organization it belongs to
+    pub async fn status_of(&self, message_id: MessageId) -> Result<MessageStatus, Error> {
+        Ok(sqlx::query_scalar!(
+            r#"
+            SELECT m.status AS "status:MessageStatus"
+            FROM messages m
+            WHERE m.id = $1 AND m.deleted_at IS NULL
+            "#,
+            *message_id,
+        )
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    /// Move messages on `held` due to the quota back to `processing` for organizations that have
+    /// quota left again, and return their IDs so they can be sent.
+    ///
+    /// Per organization, at most as many messages as the remaining quota are resumed, oldest first
+    pub async fn resume_quota_held_messages(&self) -> Result<Vec<MessageId>, Error> {
+        Ok(sqlx::query_scalar!(
+            r#"
+            UPDATE messages m
+            SET status = 'processing',
+                hold_reason = NULL,
+                retry_after = NULL
+            FROM (
+                SELECT m.id,
+                       row_number() OVER (PARTITION BY m.organization_id ORDER BY m.created_at) AS position,
+                       o.total_message_quota - o.used_message_quota AS remaining
+                FROM messages m
+                JOIN organizations o ON o.id = m.organization_id
+                WHERE m.status = 'held' AND m.hold_reason = 'quota'
+                  AND o.block_status = 'not_blocked'
+                  AND NOT o.sending_paused
+                  AND octet_length(m.raw_data) > 0
+                  AND m.deleted_at IS NULL
+            ) held
+            WHERE m.id = held.id
+              AND m.status = 'held'
+              AND held.position <= held.remaining
+            RETURNING m.id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(Into::into)
+        .collect())
+    }
+
+    /// Size in bytes of the full raw data of a message that has not been deleted
+    pub async fn raw_size(
         &self,
         org_id: OrganizationId,
         message_id: MessageId,
-    ) -> Result<MessageStatus, Error> {
+    ) -> Result<i32, Error> {
         Ok(sqlx::query_scalar!(
             r#"
-            SELECT m.status AS "status:MessageStatus"
+            SELECT octet_length(m.raw_data) AS "raw_size!"
+            FROM messages m
+            WHERE m.id = $1
+              AND m.organization_id = $2
+              AND octet_length(m.raw_data) > 0 -- don't show expired messages
+              AND m.deleted_at IS NULL
+            "#,
+            *message_id,
+            *org_id,
+        )
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    /// Stream the full raw data of a message in chunks, such that large messages
+    /// do not need to be loaded into memory at once
+    ///
+    /// The raw data is stored uncompressed, such that each chunk only reads its own part
+    pub fn stream_raw_data(
+        &self,
+        org_id: OrganizationId,
+        message_id: MessageId,
+        raw_size: i32,
+    ) -> impl Stream<Item = Result<Vec<u8>, Error>> + Send + 'static {
+        let pool = self.pool.clone();
+
+        try_stream! {
+            // substring positions in Postgres start at 1
+            let mut offset = 1;
+            let mut streamed = 0;
+            while offset <= raw_size {
+                let chunk = sqlx::query_scalar!(
+                    r#"
+                    SELECT substring(m.raw_data FROM $3 FOR $4) AS "chunk!"
+                    FROM messages m
+                    WHERE m.id = $1
+                      AND m.organization_id = $2
+                      AND m.deleted_at IS NULL
+                    "#,
+                    *message_id,
+                    *org_id,
+                    offset,
+                    RAW_STREAM_CHUNK_SIZE,
+                )
+                .fetch_one(&pool)
+                .await
+                .map_err(Error::from)?;
+
+                if chunk.is_empty() {
+                    break;
+                }
+
+                offset += RAW_STREAM_CHUNK_SIZE;
+                streamed += chunk.len();
+                yield chunk;
+            }
+
+            // the message data has been removed or replaced since its size was determined
+            if streamed != raw_size as usize {
+                Err::<(), _>(Error::Internal(format!(
+                    "message data ended after {streamed} of {raw_size} bytes"
+                )))?;
+            }
+        }
+    }
+
+    /// Stream the metadata of the messages of a project, oldest first
+    ///
+    /// The messages are fetched page by page, such that large exports are never held
+    /// in memory as a whole.
+    pub fn stream_export(
+        &self,
+        org_id: OrganizationId,
+        project_id: ProjectId,
+        filter: ExportFilter,
+    ) -> impl Stream<Item = Result<Vec<ExportedMessage>, Error>> + Send + 'static {
+        let pool = self.pool.clone();
+
+        try_stream! {
+            let mut last: Option<(DateTime<Utc>, MessageId)> = None;
+            loop {
+                let page = sqlx::query_as!(
+                    ExportedMessage,
+                    r#"
+                    SELECT m.id,
+                           m.status AS "status: _",
+                           m.from_email,
+                           cardinality(m.recipients) AS "recipient_count!",
+                           m.created_at,
+                           CASE WHEN m.status = 'delivered' THEN (
+                               SELECT max((d.value #>> '{status,delivered}')::timestamptz)
+                               FROM jsonb_each(m.delivery_details) d
+                           ) END AS "delivered_at"
+                    FROM messages m
+                    WHERE m.organization_id = $1
+                      AND m.project_id = $2
+                      AND ($3::timestamptz IS NULL OR m.created_at >= $3)
+                      AND ($4::timestamptz IS NULL OR m.created_at < $4)
+                      AND ($5::timestamptz IS NULL OR (m.created_at, m.id) > ($5, $6::uuid))
+                      AND octet_length(m.raw_data) > 0 -- don't export expired messages
+                      AND m.deleted_at IS NULL
+                    ORDER BY m.created_at, m.id
+                    LIMIT $7
+                    "#,
+                    *org_id,
+                    *project_id,
+                    filter.after,
+                    filter.before,
+                    last.map(|(created_at, _)| created_at),
+                    last.map(|(_, id)| *id),
+                    EXPORT_PAGE_SIZE,
+                )
+                .fetch_all(&pool)
+                .await
+                .map_err(Error::from)?;
+
+                let Some(last_message) = page.last() else {
+                    break;
+                };
+                last = Some((last_message.created_at, last_message.id));
+                let is_last_page = (page.len() as i64) < EXPORT_PAGE_SIZE;
+
+                yield page;
+
+                if is_last_page {
+                    break;
+                }
+            }
+        }
+    }
+
+    pub async fn message_status(
+        &self,
+        org_id: OrganizationId,
+        message_id: MessageId,
+    ) -> Result<ApiMessageStatus, Error> {
+        Ok(sqlx::query_as!(
+            ApiMessageStatus,
+            r#"
+            SELECT m.status AS "status:MessageStatus", m.attempts, m.max_attempts,
+                   CASE WHEN m.status = 'reattempt' THEN m.retry_after END AS retry_after
             FROM messages m
-            WHERE m.organization_id = $1 AND m.id = $2
+            WHERE m.organization_id = $1 AND m.id = $2 AND m.deleted_at IS NULL
             "#,
             *org_id,
             *message_id,
@@ -1009,12 +2067,51 @@ impl MessageRepository {
         .await?)
     }
 
-    /// Returns true if the project has reached it's rate limit, false if it may still send emails
+    /// Give a message on `needs_review` another round of automatic retries, the first of which is
+    /// picked up by the next retry scan
+    ///
+    /// Fails with [`Error::Conflict`] if the message is not on `needs_review`
+    pub async fn requeue_for_retries(
+        &self,
+        org_id: OrganizationId,
+        message_id: MessageId,
+        max_automatic_retries: i32,
+    ) -> Result<(), Error> {
+        sqlx::query_scalar!(
+            r#"
+            UPDATE messages m
+            SET status = 'reattempt',
+                max_attempts = m.attempts + COALESCE(p.max_automatic_retries, $3),
+                retry_after = now(),
+                reason = 'requeued after review'
+            FROM projects p
+            WHERE p.id = m.project_id
+              AND m.organization_id = $1
+              AND m.id = $2
+              AND m.status = 'needs_review'
+              AND m.deleted_at IS NULL
+            RETURNING m.id
+            "#,
+            *org_id,
+            *message_id,
+            max_automatic_retries,
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(Error::Conflict)?;
+
+        Ok(())
+    }
+
+    /// Returns [`Error::RateLimited`] if the project has reached it's rate limit,
+    /// or the remaining rate limit and quota if it may still send emails
+    ///
+    /// The remaining quota is limited by the project's own quota, if it has one
     ///
     /// Automatically resets when the time span has expired, if so, it starts a new time span
     ///
     /// Also checks if the organization is allowed to receive new emails (is not blocked)
-    pub async fn email_creation_rate_limit(&self, id: ProjectId) -> Result<(), Error> {
+    pub async fn email_creation_rate_limit(&self, id: ProjectId) -> Result<RateLimitStatus, Error> {
         let mut tx = self
             .pool
             .begin()
@@ -1025,7 +2122,12 @@ impl MessageRepository {
 
         let org = sqlx::query!(
                 r#"
-                SELECT o.id, current_subscription, rate_limit_tokens, rate_limit_last_used, block_status AS "block_status:OrgBlockStatus"
+                SELECT o.id, current_subscription, rate_limit_tokens, rate_limit_last_used, block_status AS "block_status:OrgBlockStatus",
+                       -- the project's own quota only applies if it has one, LEAST ignores NULL
+                       LEAST(
+                           o.total_message_quota - o.used_message_quota,
+                           p.message_quota - p.used_message_quota
+                       ) AS "remaining_quota!"
                 FROM organizations o
                          JOIN projects p ON o.id = p.organization_id
                 WHERE p.id = $1
@@ -1059,12 +2161,26 @@ impl MessageRepository {
         let tokens_to_add = time_delta_size_last_use.num_milliseconds()
             / product.token_refill_time().num_milliseconds();
 
+        // the moment at which the rate limit tokens will have been fully refilled
+        let full_refill = |timestamp: DateTime<Utc>, available_tokens: i64| {
+            i32::try_from(product.max_rate_limit_tokens() - available_tokens)
+                .ok()
+                .and_then(|missing| product.token_refill_time().checked_mul(missing))
+                .and_then(|refill_time| timestamp.checked_add_signed(refill_time))
+                .unwrap_or(DateTime::<Utc>::MAX_UTC)
+        };
+
         let mut available_tokens = min(
             org.rate_limit_tokens + tokens_to_add,
             product.max_rate_limit_tokens(),
         );
         if available_tokens <= 0 {
-            return Err(Error::TooManyRequests);
+            return Err(Error::RateLimited(RateLimitStatus {
+                remaining: 0,
+                reset: full_refill(org.rate_limit_last_used, 0),
+                retry_after: Some(org.rate_limit_last_used + product.token_refill_time()),
+                remaining_quota: org.remaining_quota,
+            }));
         }
 
         let new_timestamp = if available_tokens == product.max_rate_limit_tokens() {
@@ -1107,7 +2223,13 @@ impl MessageRepository {
             "organization has still {} rate limit tokens",
             available_tokens
         );
-        Ok(())
+        Ok(RateLimitStatus {
+            remaining: available_tokens,
+            reset: full_refill(new_timestamp, available_tokens),
+            retry_after: (available_tokens == 0)
+                .then(|| new_timestamp + product.token_refill_time()),
+            remaining_quota: org.remaining_quota,
+        })
     }
 
     /// Lists all labels within the organization. It only shows labels for which at least one message exists
@@ -1116,7 +2238,7 @@ impl MessageRepository {
             r#"
             SELECT DISTINCT label AS "label!:Label"
             FROM messages
-            WHERE organization_id = $1 AND label IS NOT NULL
+            WHERE organization_id = $1 AND label IS NOT NULL AND deleted_at IS NULL
             ORDER BY label
             "#,
             *organization_id,
@@ -1131,14 +2253,12 @@ mod test {
     use mail_builder::MessageBuilder;
     use mail_send::smtp::message::IntoMessage;
     use sqlx::PgPool;
+    use std::collections::HashSet;
 
     use super::*;
     use crate::{
-        models::{
-            ApiKeyRepository, ApiKeyRequest, OrganizationRepository, Role,
-            SmtpCredentialRepository, SmtpCredentialRequest,
-        },
-        test::TestProjects,
+        models::{ApiKeyRepository, ApiKeyRequest, OrganizationRepository, Role},
+        test::{TestProjects, test_credential},
     };
 
     impl ApiMessage {
@@ -1157,6 +2277,10 @@ mod test {
         pub fn status(&self) -> &MessageStatus {
             &self.metadata.status
         }
+
+        pub fn hold_reason(&self) -> Option<HoldReason> {
+            self.metadata.hold_reason
+        }
     }
 
     impl NewMessage {
@@ -1197,6 +2321,83 @@ mod test {
         assert_eq!(labels.len(), 0);
     }
 
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn soft_delete_and_purge(pool: PgPool) {
+        let repository = MessageRepository::new(pool.clone());
+        let org_id = TestProjects::Org1Project1.org_id();
+
+        let credential = test_credential(&pool).await;
+        let message = MessageBuilder::new()
+            .from(("John Doe", "john@test-org-1-project-1.com"))
+            .to(vec![("James Smith", "james@test.com")])
+            .subject("Hi!")
+            .text_body("Hello world!")
+            .into_message()
+            .unwrap();
+        let message_id = repository
+            .create(
+                NewMessage::from_builder_message(message, credential.id()),
+                5,
+            )
+            .await
+            .unwrap()
+            .into_inner();
+
+        // a deleted message is hidden, and is not sent
+        repository.remove(org_id, message_id).await.unwrap();
+        let messages = repository
+            .list_message_metadata(org_id, MessageFilter::default())
+            .await
+            .unwrap();
+        assert!(messages.is_empty());
+        assert!(matches!(
+            repository.find_by_id(org_id, message_id).await,
+            Err(Error::NotFound(_))
+        ));
+        assert!(repository.get_if_org_may_send(message_id).await.is_err());
+        assert!(repository.get_ready_to_send(message_id).await.is_err());
+
+        // within the recovery window, the message is not purged and can be restored
+        repository.purge_deleted_messages().await.unwrap();
+        repository.restore(org_id, message_id).await.unwrap();
+        let message = repository.find_by_id(org_id, message_id).await.unwrap();
+        assert!(!message.truncated_raw_data.is_empty());
+
+        // after the recovery window, the message data is purged
+        repository.remove(org_id, message_id).await.unwrap();
+        sqlx::query!(
+            "UPDATE messages SET deleted_at = now() - $2 * INTERVAL '1 day' - INTERVAL '1 minute' WHERE id = $1",
+            *message_id,
+            MessageRepository::RECOVERY_WINDOW_DAYS,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        repository.purge_deleted_messages().await.unwrap();
+
+        let raw_size = sqlx::query_scalar!(
+            r#"SELECT octet_length(raw_data) AS "raw_size!" FROM messages WHERE id = $1"#,
+            *message_id,
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(raw_size, 0);
+        assert!(matches!(
+            repository.restore(org_id, message_id).await,
+            Err(Error::NotFound(_))
+        ));
+    }
+
     #[sqlx::test(fixtures(
         path = "../fixtures",
         scripts(
@@ -1209,7 +2410,7 @@ mod test {
     ))]
     async fn message_repository(pool: PgPool) {
         let repository = MessageRepository::new(pool.clone());
-        let (org_id, project_id) = TestProjects::Org1Project1.get_ids();
+        let org_id = TestProjects::Org1Project1.org_id();
 
         let message = MessageBuilder::new()
             .from(("John Doe", "john@test-org-1-project-1.com"))
@@ -1224,23 +2425,15 @@ mod test {
             .unwrap();
 
         // create SMTP credential
-        let smtp_credential_repo = SmtpCredentialRepository::new(pool);
-        let credential = smtp_credential_repo
-            .generate(
-                org_id,
-                project_id,
-                &SmtpCredentialRequest {
-                    username: "user".to_string(),
-                    description: "Test SMTP credential description".to_string(),
-                },
-                crate::models::SYSTEM,
-            )
-            .await
-            .unwrap();
+        let credential = test_credential(&pool).await;
 
         // create message
         let new_message = NewMessage::from_builder_message(message, credential.id());
-        let message_id = repository.create(new_message, 5).await.unwrap();
+        let message_id = repository
+            .create(new_message, 5)
+            .await
+            .unwrap()
+            .into_inner();
 
         // get message
         let mut fetched_message = repository.find_by_id(org_id, message_id).await.unwrap();
@@ -1270,6 +2463,7 @@ mod test {
                     labels: None,
                     before: None,
                     project: None,
+                    source: None,
                 },
             )
             .await
@@ -1290,6 +2484,7 @@ mod test {
                     labels: None,
                     before: None,
                     project: None,
+                    source: None,
                 },
             )
             .await
@@ -1301,34 +2496,104 @@ mod test {
         path = "../fixtures",
         scripts("organizations", "projects", "org_domains", "proj_domains")
     ))]
-    async fn create_message_from_api(pool: PgPool) {
+    async fn deduplicate_message_id(pool: PgPool) {
         let repository = MessageRepository::new(pool.clone());
         let (org_id, project_id) = TestProjects::Org1Project1.get_ids();
 
-        let from = "john@test-org-1-project-1.com";
-        let message_id = MessageId::new_v4();
-        let message_id_header =
-            MessageRepository::generate_message_id_header(&message_id, &from.parse().unwrap());
-        let message = MessageBuilder::new()
-            .from(("John Doe", "john@test-org-1-project-1.com"))
-            .to(vec![
-                ("James Smith", "james@test.com"),
-                ("Jane Doe", "jane@test-org-1-project-1.com"),
-            ])
-            .subject("Hi!")
-            .html_body("<h1>Hello, world!</h1>")
-            .text_body("Hello world!")
-            .message_id(message_id_header.as_str())
-            .into_message()
-            .unwrap();
+        sqlx::query!(
+            "UPDATE projects SET dedup_window_minutes = 60 WHERE id = $1",
+            *project_id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let credential = test_credential(&pool).await;
+
+        let new_message = || {
+            let message = MessageBuilder::new()
+                .from(("John Doe", "john@test-org-1-project-1.com"))
+                .to(vec![("James Smith", "james@test.com")])
+                .subject("Hi!")
+                .text_body("Hello world!")
+                .message_id("resubmitted@test-org-1-project-1.com")
+                .into_message()
+                .unwrap();
+            NewMessage::from_builder_message(message, credential.id())
+        };
 
-        // create API key
-        let api_key_repository = ApiKeyRepository::new(pool);
-        let api_key = api_key_repository
-            .create(
+        // concurrent submissions are stored only once
+        let (first, second) = tokio::join!(
+            repository.create(new_message(), 5),
+            repository.create(new_message(), 5)
+        );
+        let (first, second) = match (first.unwrap(), second.unwrap()) {
+            (Created::New(new), other) | (other, Created::New(new)) => (new, other),
+            _ => panic!("one of the submissions should create a new message"),
+        };
+        assert_eq!(second, Created::Duplicate(first));
+
+        let messages = repository
+            .list_message_metadata(
                 org_id,
-                &ApiKeyRequest {
-                    description: "Test API key".to_string(),
+                MessageFilter {
+                    limit: 5,
+                    status: None,
+                    labels: None,
+                    before: None,
+                    project: None,
+                    source: None,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].id, first);
+
+        // outside the window, the message is stored again
+        sqlx::query!(
+            "UPDATE messages SET created_at = now() - INTERVAL '2 hours' WHERE id = $1",
+            *first
+        )
+        .execute(&repository.pool)
+        .await
+        .unwrap();
+        let third = repository.create(new_message(), 5).await.unwrap();
+        assert!(matches!(third, Created::New(id) if id != first));
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "projects", "org_domains", "proj_domains")
+    ))]
+    async fn create_message_from_api(pool: PgPool) {
+        let repository = MessageRepository::new(pool.clone());
+        let (org_id, project_id) = TestProjects::Org1Project1.get_ids();
+
+        let from = "john@test-org-1-project-1.com";
+        let message_id = MessageId::new_v4();
+        let message_id_header =
+            MessageRepository::generate_message_id_header(&message_id, &from.parse().unwrap());
+        let message = MessageBuilder::new()
+            .from(("John Doe", "john@test-org-1-project-1.com"))
+            .to(vec![
+                ("James Smith", "james@test.com"),
+                ("Jane Doe", "jane@test-org-1-project-1.com"),
+            ])
+            .subject("Hi!")
+            .html_body("<h1>Hello, world!</h1>")
+            .text_body("Hello world!")
+            .message_id(message_id_header.as_str())
+            .into_message()
+            .unwrap();
+
+        // create API key
+        let api_key_repository = ApiKeyRepository::new(pool);
+        let api_key = api_key_repository
+            .create(
+                org_id,
+                &ApiKeyRequest {
+                    description: "Test API key".to_string(),
                     role: Role::Maintainer,
                 },
                 crate::models::SYSTEM,
@@ -1348,10 +2613,22 @@ mod test {
                 "jane@test-org-1-project-1.com".parse().unwrap(),
             ],
             raw_data: message.into_message().unwrap().body.to_vec(),
+            client_ip: Some("2001:db8::1".parse().unwrap()),
+            correlation_id: "test-correlation-id".to_owned(),
+            priority: MessagePriority::Low,
         };
-        let message = repository.create_from_api(new_message, 5).await.unwrap();
+        let message = repository
+            .create_from_api(new_message, 5)
+            .await
+            .unwrap()
+            .into_inner();
         assert_eq!(message.message_id_header, message_id_header);
         assert_eq!(message.label, Some(Label::new("up-date")));
+        assert_eq!(message.client_ip, Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(
+            message.correlation_id.as_deref(),
+            Some("test-correlation-id")
+        );
 
         // get message
         let mut fetched_message = repository.find_by_id(org_id, message.id).await.unwrap();
@@ -1375,6 +2652,7 @@ mod test {
             message_id_header
         );
         assert_eq!(fetched_message.metadata.label, Some(Label::new("up-date")));
+        assert_eq!(fetched_message.metadata.priority, MessagePriority::Low);
     }
 
     #[sqlx::test(fixtures(
@@ -1409,6 +2687,101 @@ mod test {
         );
     }
 
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "smtp_credentials",
+            "messages"
+        )
+    ))]
+    async fn stream_truncated_raw_data(pool: PgPool) {
+        use futures::TryStreamExt;
+
+        let repository = MessageRepository::new(pool.clone());
+        let org_id = TestProjects::Org1Project1.org_id();
+        let message_id: MessageId = "e165562a-fb6d-423b-b318-fd26f4610634".parse().unwrap();
+        let raw_size = sqlx::query_scalar!(
+            r#"SELECT octet_length(raw_data) AS "raw_size!" FROM messages WHERE id = $1"#,
+            *message_id
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+
+        let streamed: Vec<Vec<u8>> = repository
+            .stream_raw_data(org_id, message_id, raw_size)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(streamed.concat().len(), raw_size as usize);
+
+        // a stream that ends before the expected size is an error, not a complete message
+        let result: Result<Vec<Vec<u8>>, _> = repository
+            .stream_raw_data(org_id, message_id, raw_size + 1)
+            .try_collect()
+            .await;
+        assert!(matches!(result, Err(Error::Internal(_))));
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "smtp_credentials",
+            "api_keys",
+            "messages"
+        )
+    ))]
+    async fn list_messages_by_source(pool: PgPool) {
+        let repository = MessageRepository::new(pool.clone());
+        let org_id = TestProjects::Org1Project1.org_id();
+        let api_message: MessageId = "e165562a-fb6d-423b-b318-fd26f4610634".parse().unwrap();
+
+        // pretend one of the messages has been submitted via the API
+        sqlx::query!(
+            r#"
+            UPDATE messages
+            SET smtp_credential_id = NULL,
+                api_key_id = '951ec618-bcc9-4224-9cf1-ed41a84f41d8'
+            WHERE id = $1
+            "#,
+            *api_message
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let list = |source| {
+            repository.list_message_metadata(
+                org_id,
+                MessageFilter {
+                    limit: 100,
+                    source,
+                    ..Default::default()
+                },
+            )
+        };
+
+        let all = list(None).await.unwrap();
+        let api = list(Some(MessageSource::Api)).await.unwrap();
+        let smtp = list(Some(MessageSource::Smtp)).await.unwrap();
+
+        assert_eq!(api.len(), 1);
+        assert_eq!(api[0].id, api_message);
+        assert!(api[0].api_key_id.is_some());
+
+        assert_eq!(smtp.len(), all.len() - 1);
+        assert!(smtp.iter().all(|m| m.smtp_credential_id.is_some()));
+        assert!(!smtp.iter().any(|m| m.id == api_message));
+    }
+
     #[sqlx::test(fixtures(
         path = "../fixtures",
         scripts(
@@ -1485,4 +2858,684 @@ mod test {
 
         messages.email_creation_rate_limit(proj_id).await.unwrap(); // can receive again
     }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "smtp_credentials",
+            "messages",
+            "k8s_nodes"
+        )
+    ))]
+    async fn paused_sending(pool: PgPool) {
+        let organizations = OrganizationRepository::new(pool.clone());
+        let messages = MessageRepository::new(pool.clone());
+
+        let (org_id, proj_id) = TestProjects::Org1Project1.get_ids();
+        let message_id = "e165562a-fb6d-423b-b318-fd26f4610634".parse().unwrap();
+
+        // a message waiting to be retried
+        let mut message = messages.get_if_org_may_send(message_id).await.unwrap();
+        message.status = MessageStatus::Reattempt;
+        message.attempts = 1;
+        message.max_attempts = 5;
+        message.retry_after = Some(Utc::now() - chrono::Duration::minutes(1));
+        messages.update_message_status(&mut message).await.unwrap();
+        assert!(
+            messages
+                .find_messages_ready_for_retry()
+                .await
+                .unwrap()
+                .contains(&message_id)
+        );
+
+        organizations
+            .update_sending_paused(org_id, true, crate::models::SYSTEM)
+            .await
+            .unwrap();
+
+        // new messages are still accepted
+        messages.email_creation_rate_limit(proj_id).await.unwrap();
+
+        // but messages are not sent, nor retried
+        let err = messages.get_ready_to_send(message_id).await.unwrap_err();
+        assert!(matches!(err, Error::SendingPaused));
+        assert!(
+            !messages
+                .find_messages_ready_for_retry()
+                .await
+                .unwrap()
+                .contains(&message_id)
+        );
+        let message = messages.find_by_id(org_id, message_id).await.unwrap();
+        assert_eq!(message.metadata.status, MessageStatus::Reattempt);
+
+        // once resumed, the queued message is picked up again
+        organizations
+            .update_sending_paused(org_id, false, crate::models::SYSTEM)
+            .await
+            .unwrap();
+
+        messages.get_ready_to_send(message_id).await.unwrap();
+        assert!(
+            messages
+                .find_messages_ready_for_retry()
+                .await
+                .unwrap()
+                .contains(&message_id)
+        );
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "smtp_credentials",
+            "messages",
+            "k8s_nodes"
+        )
+    ))]
+    async fn dedicated_outbound_ips(pool: PgPool) {
+        let messages = MessageRepository::new(pool.clone());
+        let org_1 = TestProjects::Org1Project1.org_id();
+        let org_2 = TestProjects::Org2Project1.org_id();
+        let message_id = "e165562a-fb6d-423b-b318-fd26f4610634".parse().unwrap(); // of org 1
+        let ready_node: Uuid = "44da8272-1b1d-4ab9-aa6b-27eff39c0510".parse().unwrap();
+        let unready_node: Uuid = "46d37e7f-4b52-425c-a419-ed9488c83e47".parse().unwrap();
+        let dedicated_ip: IpAddr = "10.0.0.2".parse().unwrap();
+
+        // a ready node has a shared IP (127.0.0.1 and 10.0.0.1) and one dedicated to org 1
+        sqlx::query!(
+            r#"
+            INSERT INTO outbound_ips (id, ip, node_id)
+            VALUES ('f1a1c44e-3f2b-4c4e-9a41-0d1fba6c2e10', '10.0.0.1', $1),
+                   ('b6d3e0a2-81c7-4a9e-b5a5-5e5ac7c7f402', '10.0.0.2', $1)
+            "#,
+            ready_node
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let dedicate_to = async |org_id: OrganizationId| {
+            sqlx::query!(
+                r#"
+                INSERT INTO organization_outbound_ips (outbound_ip_id, organization_id)
+                VALUES ('b6d3e0a2-81c7-4a9e-b5a5-5e5ac7c7f402', $1)
+                ON CONFLICT (outbound_ip_id) DO UPDATE SET organization_id = $1
+                "#,
+                *org_id
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+        };
+        let assigned_ips = async || {
+            let mut ips = HashSet::new();
+            for _ in 0..20 {
+                let BusMessage::EmailReadyToSend(_, ip, _, _) =
+                    messages.get_ready_to_send(message_id).await.unwrap()
+                else {
+                    panic!("expected the message to be ready to send");
+                };
+                ips.insert(ip);
+            }
+            ips
+        };
+
+        // the dedicated IP is always used while it is healthy
+        dedicate_to(org_1).await;
+        assert_eq!(assigned_ips().await, HashSet::from([dedicated_ip]));
+
+        // IPs dedicated to other organizations are never used
+        dedicate_to(org_2).await;
+        let ips = assigned_ips().await;
+        assert!(!ips.contains(&dedicated_ip));
+
+        // the shared IPs are used while the dedicated IP is unhealthy
+        dedicate_to(org_1).await;
+        sqlx::query!(
+            "UPDATE outbound_ips SET node_id = $1 WHERE ip = '10.0.0.2'",
+            unready_node
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let ips = assigned_ips().await;
+        assert!(!ips.is_empty());
+        assert!(!ips.contains(&dedicated_ip));
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "smtp_credentials",
+            "messages"
+        )
+    ))]
+    async fn greylist_retry(pool: PgPool) {
+        let messages = MessageRepository::new(pool.clone());
+        let message_id = "e165562a-fb6d-423b-b318-fd26f4610634".parse().unwrap();
+        let mut message = messages.get_if_org_may_send(message_id).await.unwrap();
+
+        let config = RetryConfig::new();
+        message.status = MessageStatus::Reattempt;
+        message.max_attempts = config.max_automatic_retries;
+
+        // the first attempts are retried soon
+        message.attempts = 1;
+        message.set_next_retry(&config);
+        message.set_next_greylist_retry(&config);
+        let retry_after = message.retry_after.unwrap();
+        assert!(retry_after <= Utc::now() + config.greylist.delay);
+
+        // afterward, the regular schedule applies
+        message.attempts = config.greylist.max_retries + 1;
+        message.set_next_retry(&config);
+        message.set_next_greylist_retry(&config);
+        let retry_after = message.retry_after.unwrap();
+        assert!(retry_after > Utc::now() + config.greylist.delay);
+
+        // messages without a scheduled retry are not retried
+        message.status = MessageStatus::Delivered;
+        message.set_next_retry(&config);
+        message.set_next_greylist_retry(&config);
+        assert!(message.retry_after.is_none());
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "smtp_credentials",
+            "messages"
+        )
+    ))]
+    async fn hold_reason_retries(pool: PgPool) {
+        let messages = MessageRepository::new(pool.clone());
+        let org_id = TestProjects::Org1Project1.org_id();
+        let message_held_id = "10d5ad5f-04ae-489b-9f5a-f5d7e73bc12a".parse().unwrap();
+        let config = RetryConfig::new();
+
+        let set_used_quota = async |used: i64| {
+            sqlx::query!(
+                "UPDATE organizations SET used_message_quota = $2 WHERE id = $1",
+                *org_id,
+                used
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+        };
+
+        // held due to the quota, without attempts left
+        let mut message = messages.get_if_org_may_send(message_held_id).await.unwrap();
+        message.status = MessageStatus::Held;
+        message.hold_reason = Some(HoldReason::Quota);
+        message.attempts = config.max_automatic_retries;
+        message.set_next_retry(&config);
+        assert_eq!(message.status, MessageStatus::Held);
+        assert!(message.retry_after.is_none());
+        messages.update_message_status(&mut message).await.unwrap();
+
+        // not resumed while the quota is exhausted
+        set_used_quota(800).await;
+        let resumed = messages.resume_quota_held_messages().await.unwrap();
+        assert!(resumed.is_empty());
+
+        // resumed once the quota resets
+        set_used_quota(0).await;
+        let resumed = messages.resume_quota_held_messages().await.unwrap();
+        assert_eq!(resumed, vec![message_held_id]);
+        let mut message = messages.get_if_org_may_send(message_held_id).await.unwrap();
+        assert_eq!(message.status, MessageStatus::Processing);
+        assert!(message.hold_reason.is_none());
+
+        // held due to the configuration, which is not retried automatically
+        message.status = MessageStatus::Held;
+        message.hold_reason = Some(HoldReason::Configuration);
+        message.attempts = 1;
+        message.set_next_retry(&config);
+        assert_eq!(message.status, MessageStatus::Held);
+        assert!(message.retry_after.is_none());
+        message.retry_after = Some(Utc::now() - chrono::Duration::hours(1));
+        messages.update_message_status(&mut message).await.unwrap();
+
+        let ready = messages.find_messages_ready_for_retry().await.unwrap();
+        assert!(!ready.contains(&message_held_id));
+
+        let message = messages.find_by_id(org_id, message_held_id).await.unwrap();
+        assert_eq!(message.hold_reason(), Some(HoldReason::Configuration));
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "smtp_credentials",
+            "messages"
+        )
+    ))]
+    async fn resume_quota_held_messages_within_quota(pool: PgPool) {
+        let messages = MessageRepository::new(pool.clone());
+        let org_id = TestProjects::Org1Project1.org_id();
+        let held = [
+            "e165562a-fb6d-423b-b318-fd26f4610634".parse().unwrap(),
+            "10d5ad5f-04ae-489b-9f5a-f5d7e73bc12a".parse().unwrap(),
+        ];
+
+        for message_id in held {
+            let mut message = messages.get_if_org_may_send(message_id).await.unwrap();
+            message.status = MessageStatus::Held;
+            message.hold_reason = Some(HoldReason::Quota);
+            messages.update_message_status(&mut message).await.unwrap();
+        }
+
+        // only a single message fits in the remaining quota
+        sqlx::query!(
+            "UPDATE organizations SET used_message_quota = total_message_quota - 1 WHERE id = $1",
+            *org_id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let resumed = messages.resume_quota_held_messages().await.unwrap();
+        assert_eq!(resumed.len(), 1);
+        assert!(held.contains(&resumed[0]));
+
+        // the other message stays on hold, and is not resumed twice
+        let resumed = messages.resume_quota_held_messages().await.unwrap();
+        assert!(resumed.is_empty());
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "projects", "org_domains", "proj_domains")
+    ))]
+    async fn internationalized_recipients(pool: PgPool) {
+        let repository = MessageRepository::new(pool.clone());
+        let org_id = TestProjects::Org1Project1.org_id();
+
+        let credential = test_credential(&pool).await;
+
+        let mut new_message = NewMessage::new(
+            credential.id(),
+            "jöhn@test-org-1-project-1.com".parse().unwrap(),
+        );
+        new_message.recipients = vec!["用户@例子.广告".parse().unwrap()];
+        new_message.raw_data = b"Subject: Hi!\r\n\r\nHello world!\r\n".to_vec();
+
+        let message_id = repository
+            .create(new_message, 5)
+            .await
+            .unwrap()
+            .into_inner();
+
+        // UTF-8 addresses are stored without any encoding
+        let fetched_message = repository.find_by_id(org_id, message_id).await.unwrap();
+        assert_eq!(
+            fetched_message.metadata.from_email.as_str(),
+            "jöhn@test-org-1-project-1.com"
+        );
+        assert_eq!(fetched_message.metadata.recipients.len(), 1);
+        assert_eq!(
+            fetched_message.metadata.recipients[0].as_str(),
+            "用户@例子.广告"
+        );
+        assert_eq!(fetched_message.metadata.recipients[0].domain(), "例子.广告");
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "projects", "org_domains", "proj_domains")
+    ))]
+    async fn unparseable_message(pool: PgPool) {
+        let repository = MessageRepository::new(pool.clone());
+        let org_id = TestProjects::Org1Project1.org_id();
+
+        let credential = test_credential(&pool).await;
+
+        // a MIME part without any message headers
+        let malformed =
+            b"\r\n--boundary\r\nContent-Type: text/plain\r\n\r\nHello world!\r\n--boundary";
+        let mut new_message = NewMessage::new(
+            credential.id(),
+            "john@test-org-1-project-1.com".parse().unwrap(),
+        );
+        new_message.recipients = vec!["james@test.com".parse().unwrap()];
+        new_message.raw_data = malformed.to_vec();
+
+        let message_id = repository
+            .create(new_message, 5)
+            .await
+            .unwrap()
+            .into_inner();
+
+        let message = repository.find_by_id(org_id, message_id).await.unwrap();
+        assert!(message.metadata.unparseable);
+        assert!(message.message_data.subject.is_none());
+        assert!(message.message_data.text_body.is_none());
+
+        // the Message-ID and Date headers are added in front of the untouched message
+        let expected_id = format!("Message-ID: <{}>\r\n", message.metadata.message_id_header);
+        assert!(message.truncated_raw_data.starts_with(&expected_id));
+        assert!(message.truncated_raw_data[expected_id.len()..].starts_with("Date: "));
+        assert!(
+            message
+                .truncated_raw_data
+                .ends_with(str::from_utf8(malformed).unwrap())
+        );
+
+        // regular messages are not flagged
+        let mut new_message = NewMessage::new(
+            credential.id(),
+            "john@test-org-1-project-1.com".parse().unwrap(),
+        );
+        new_message.recipients = vec!["james@test.com".parse().unwrap()];
+        new_message.raw_data = b"Subject: Hi!\r\n\r\nHello world!\r\n".to_vec();
+        let message_id = repository
+            .create(new_message, 5)
+            .await
+            .unwrap()
+            .into_inner();
+        let message = repository.find_by_id(org_id, message_id).await.unwrap();
+        assert!(!message.metadata.unparseable);
+        assert_eq!(message.message_data.subject.as_deref(), Some("Hi!"));
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "projects", "org_domains", "proj_domains")
+    ))]
+    async fn project_retry_limits(pool: PgPool) {
+        let repository = MessageRepository::new(pool.clone());
+        let project_id = TestProjects::Org1Project1.project_id();
+        let config = RetryConfig::new();
+
+        sqlx::query!(
+            "UPDATE projects SET max_automatic_retries = 2, max_message_age_minutes = 60 WHERE id = $1",
+            *project_id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let credential = test_credential(&pool).await;
+
+        let message = MessageBuilder::new()
+            .from(("John Doe", "john@test-org-1-project-1.com"))
+            .to(vec![("James Smith", "james@test.com")])
+            .subject("Hi!")
+            .text_body("Hello world!")
+            .into_message()
+            .unwrap();
+        let message_id = repository
+            .create(
+                NewMessage::from_builder_message(message, credential.id()),
+                config.max_automatic_retries,
+            )
+            .await
+            .unwrap()
+            .into_inner();
+
+        // the project overrides the global configuration
+        let mut message = repository.get_if_org_may_send(message_id).await.unwrap();
+        assert_eq!(message.max_attempts, 2);
+        let expires_at = message.expires_at.unwrap();
+        assert!(expires_at > Utc::now() + chrono::Duration::minutes(59));
+        assert!(expires_at <= Utc::now() + chrono::Duration::minutes(60));
+
+        // failing by attempt count
+        message.status = MessageStatus::Reattempt;
+        message.attempts = 1;
+        message.set_next_retry(&config);
+        assert_eq!(message.status, MessageStatus::Reattempt);
+        assert!(message.retry_after.is_some());
+
+        message.attempts = 2;
+        message.set_next_retry(&config);
+        assert_eq!(message.status, MessageStatus::Failed);
+        assert!(message.retry_after.is_none());
+
+        // failing by age, as the next retry would be after the message expires
+        message.status = MessageStatus::Reattempt;
+        message.attempts = 1;
+        message.expires_at = Some(Utc::now() + chrono::Duration::minutes(1));
+        message.set_next_retry(&config);
+        assert_eq!(message.status, MessageStatus::Failed);
+        assert!(message.retry_after.is_none());
+
+        // messages waiting for a retry are failed once they expire
+        message.status = MessageStatus::Reattempt;
+        message.retry_after = Some(Utc::now() - chrono::Duration::minutes(1));
+        repository
+            .update_message_status(&mut message)
+            .await
+            .unwrap();
+        sqlx::query!(
+            "UPDATE messages SET expires_at = now() - INTERVAL '1 minute' WHERE id = $1",
+            *message_id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let ready = repository.find_messages_ready_for_retry().await.unwrap();
+        assert!(!ready.contains(&message_id));
+
+        let failed = repository.fail_expired_messages().await.unwrap();
+        assert_eq!(failed, vec![message_id]);
+        let message = repository.get_if_org_may_send(message_id).await.unwrap();
+        assert_eq!(message.status, MessageStatus::Failed);
+        assert!(message.retry_after.is_none());
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "projects", "org_domains", "proj_domains")
+    ))]
+    async fn review_exhausted_messages(pool: PgPool) {
+        let repository = MessageRepository::new(pool.clone());
+        let (org_id, project_id) = TestProjects::Org1Project1.get_ids();
+        let config = RetryConfig::new();
+
+        sqlx::query!(
+            "UPDATE projects SET max_automatic_retries = 2, review_exhausted_messages = true WHERE id = $1",
+            *project_id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let credential = test_credential(&pool).await;
+
+        let message = MessageBuilder::new()
+            .from(("John Doe", "john@test-org-1-project-1.com"))
+            .to(vec![("James Smith", "james@test.com")])
+            .subject("Hi!")
+            .text_body("Hello world!")
+            .into_message()
+            .unwrap();
+        let message_id = repository
+            .create(
+                NewMessage::from_builder_message(message, credential.id()),
+                config.max_automatic_retries,
+            )
+            .await
+            .unwrap()
+            .into_inner();
+
+        let mut message = repository.get_if_org_may_send(message_id).await.unwrap();
+        assert!(message.review_when_exhausted);
+
+        // a message that exhausted its retries needs review instead of failing
+        message.status = MessageStatus::Reattempt;
+        message.attempts = 2;
+        message.set_next_retry(&config);
+        assert_eq!(message.status, MessageStatus::NeedsReview);
+        assert!(message.retry_after.is_none());
+        repository
+            .update_message_status(&mut message)
+            .await
+            .unwrap();
+
+        let ready = repository.find_messages_ready_for_retry().await.unwrap();
+        assert!(!ready.contains(&message_id));
+
+        // requeueing gives it another round of retries, starting right away
+        repository
+            .requeue_for_retries(org_id, message_id, config.max_automatic_retries)
+            .await
+            .unwrap();
+        let message = repository.get_if_org_may_send(message_id).await.unwrap();
+        assert_eq!(message.status, MessageStatus::Reattempt);
+        assert_eq!(message.max_attempts, 4);
+        let ready = repository.find_messages_ready_for_retry().await.unwrap();
+        assert!(ready.contains(&message_id));
+
+        // only messages on `needs_review` can be requeued
+        assert!(matches!(
+            repository
+                .requeue_for_retries(org_id, message_id, config.max_automatic_retries)
+                .await,
+            Err(Error::Conflict)
+        ));
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "smtp_credentials",
+            "messages"
+        )
+    ))]
+    async fn stuck_messages(pool: PgPool) {
+        let repository = MessageRepository::new(pool.clone());
+        let stuck_id: MessageId = "e165562a-fb6d-423b-b318-fd26f4610634".parse().unwrap();
+        let recent_id: MessageId = "c1e03226-8aad-42a9-8c43-380a5b25cb79".parse().unwrap();
+
+        // allow setting `updated_at` to a moment in the past
+        sqlx::query!("ALTER TABLE messages DISABLE TRIGGER update_messages_updated_at")
+            .execute(&pool)
+            .await
+            .unwrap();
+        for (id, age) in [
+            (stuck_id, chrono::Duration::days(4)),
+            (recent_id, chrono::Duration::hours(1)),
+        ] {
+            sqlx::query!(
+                r#"
+                UPDATE messages
+                SET status = 'processing',
+                    created_at = $2,
+                    updated_at = now() - INTERVAL '10 minutes'
+                WHERE id = $1
+                "#,
+                *id,
+                Utc::now() - age,
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        // messages of an organization that paused sending are not failed
+        let org_id = TestProjects::Org1Project1.org_id();
+        let organizations = OrganizationRepository::new(pool.clone());
+        organizations
+            .update_sending_paused(org_id, true, crate::models::SYSTEM)
+            .await
+            .unwrap();
+        let failed = repository
+            .fail_stuck_messages(chrono::Duration::days(3))
+            .await
+            .unwrap();
+        assert!(failed.is_empty());
+        organizations
+            .update_sending_paused(org_id, false, crate::models::SYSTEM)
+            .await
+            .unwrap();
+
+        // only the message older than the maximum age is failed
+        let failed = repository
+            .fail_stuck_messages(chrono::Duration::days(3))
+            .await
+            .unwrap();
+        assert_eq!(failed, vec![stuck_id]);
+
+        let message = repository.get_if_org_may_send(stuck_id).await.unwrap();
+        assert_eq!(message.status, MessageStatus::Failed);
+        assert!(message.reason.unwrap().contains("not sent within 72 hours"));
+        assert!(message.retry_after.is_none());
+
+        // and is no longer picked up for a retry, unlike the more recent one
+        let ready = repository.find_messages_ready_for_retry().await.unwrap();
+        assert!(!ready.contains(&stuck_id));
+        assert!(ready.contains(&recent_id));
+    }
+
+    #[test]
+    fn prepend_headers_large_message() {
+        let body = "All work and no play makes Jack a dull boy.\r\n".repeat(100_000);
+        let original = format!(
+            "From: john@test-org-1-project-1.com\r\nTo: james@test.com\r\nSubject: Hi!\r\n\r\n{body}"
+        );
+        assert!(original.len() > 4_000_000);
+
+        let mut raw_data = original.clone().into_bytes();
+        let dkim = "DKIM-Signature: v=1; a=rsa-sha256; d=test-org-1-project-1.com; s=remails;\r\n";
+        let message_id = "Message-ID: <large@test-org-1-project-1.com>\r\n";
+
+        let start = std::time::Instant::now();
+        super::prepend_headers(&mut raw_data, &[dkim, message_id]);
+        debug!(
+            "prepended headers to {} bytes in {:?}",
+            original.len(),
+            start.elapsed()
+        );
+
+        let expected_len = dkim.len() + message_id.len() + original.len();
+        assert_eq!(raw_data.len(), expected_len);
+
+        // headers appear in order, before the untouched original message
+        assert!(raw_data.starts_with(format!("{dkim}{message_id}").as_bytes()));
+        assert!(raw_data.ends_with(original.as_bytes()));
+
+        // nothing to prepend leaves the message untouched
+        super::prepend_headers(&mut raw_data, &[]);
+        assert_eq!(raw_data.len(), expected_len);
+
+        // the message re-parses correctly
+        let parsed = MessageParser::default().parse(&raw_data).unwrap();
+        assert_eq!(parsed.message_id(), Some("large@test-org-1-project-1.com"));
+        assert_eq!(parsed.subject(), Some("Hi!"));
+        assert!(parsed.header("DKIM-Signature").is_some());
+        assert!(
+            parsed
+                .body_text(0)
+                .unwrap()
+                .starts_with("All work and no play")
+        );
+    }
 }
@@ -31,11 +31,13 @@ mod invites;
 mod labels;
 mod message;
 mod organization;
+mod organization_export;
 mod projects;
 mod runtime_config;
 mod smtp_credential;
 mod statistics;
 mod suppressed;
+mod webhook;
 
 pub(crate) use api_keys::*;
 pub(crate) use api_user::*;
@@ -46,8 +48,10 @@ pub(crate) use invites::*;
 pub(crate) use labels::*;
 pub(crate) use message::*;
 pub(crate) use organization::*;
+pub(crate) use organization_export::*;
 pub(crate) use projects::*;
 pub(crate) use runtime_config::*;
 pub(crate) use smtp_credential::*;
 pub(crate) use statistics::*;
 pub(crate) use suppressed::*;
+pub(crate) use webhook::*;
@@ -1,8 +1,8 @@
 use crate::{
-    models::{Actor, ApiUser, ApiUserId, AuditLogRepository, Error, Role},
+    models::{Actor, ApiUser, ApiUserId, AuditLogRepository, Error, ProjectId, Role},
     moneybird::{MoneybirdContactId, SubscriptionStatus},
 };
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveTime, Utc};
 use garde::Validate;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
@@ -55,6 +55,8 @@ pub struct Organization {
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
     block_status: OrgBlockStatus,
+    /// Outbound delivery is paused by the organization itself, new messages are still accepted
+    sending_paused: bool,
 }
 
 impl Organization {
@@ -97,6 +99,45 @@ impl OrganizationMember {
     pub fn role(&self) -> &Role {
         &self.role
     }
+
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+}
+
+/// Message quota usage of an organization during a single billing period
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[cfg_attr(test, derive(Deserialize))]
+pub struct UsageSnapshot {
+    /// The quota reset moment that ended this period, if the organization had one
+    pub period_end: Option<DateTime<Utc>>,
+    pub used_message_quota: i64,
+    pub total_message_quota: i64,
+    /// The moment the quota was reset and this snapshot was taken
+    pub created_at: DateTime<Utc>,
+}
+
+/// The time of day at which the message quota of an organization resets, on the last day of
+/// each subscription period
+///
+/// Fields that are left out use the Remails default.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, ToSchema, Validate)]
+pub struct QuotaResetSchedule {
+    #[garde(skip)]
+    #[schema(value_type = Option<String>, example = "09:00:00")]
+    pub time: Option<NaiveTime>,
+    /// IANA timezone, e.g., `Europe/Amsterdam`
+    #[garde(length(max = 64), custom(validate_timezone))]
+    pub timezone: Option<String>,
+}
+
+fn validate_timezone(timezone: &Option<String>, _: &()) -> garde::Result {
+    match timezone {
+        Some(timezone) if timezone.parse::<chrono_tz::Tz>().is_err() => Err(garde::Error::new(
+            "must be an IANA timezone, e.g., Europe/Amsterdam",
+        )),
+        _ => Ok(()),
+    }
 }
 
 struct PgOrganization {
@@ -112,6 +153,7 @@ struct PgOrganization {
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
     block_status: OrgBlockStatus,
+    sending_paused: bool,
 }
 
 impl TryFrom<PgOrganization> for Organization {
@@ -131,6 +173,7 @@ impl TryFrom<PgOrganization> for Organization {
             created_at: pg.created_at,
             updated_at: pg.updated_at,
             block_status: pg.block_status,
+            sending_paused: pg.sending_paused,
         })
     }
 }
@@ -147,6 +190,16 @@ pub enum QuotaStatus {
     Below(u64),
 }
 
+/// Result of counting a message towards the message quota
+#[derive(Debug, PartialEq, Eq)]
+pub struct QuotaUpdate {
+    pub status: QuotaStatus,
+    /// Alert threshold, in percent of the quota, that this message crossed first
+    ///
+    /// Each threshold is reported at most once per quota period
+    pub crossed_threshold: Option<i32>,
+}
+
 impl OrganizationRepository {
     pub fn new(pool: sqlx::PgPool) -> Self {
         Self {
@@ -155,24 +208,218 @@ impl OrganizationRepository {
         }
     }
 
-    pub async fn reduce_quota(&self, id: OrganizationId) -> Result<QuotaStatus, Error> {
-        let quota = sqlx::query_scalar!(
+    /// Count a message sent by `project_id` towards the message quota
+    ///
+    /// The quota is exceeded if either the organization's quota is used up,
+    /// or the project has reached its own quota, if it has one.
+    /// In both cases, the message is not counted.
+    ///
+    /// `alert_thresholds` are percentages of the organization's quota, the highest threshold
+    /// the usage reaches is reported if the organization has not been alerted about it yet
+    pub async fn reduce_quota(
+        &self,
+        id: OrganizationId,
+        project_id: ProjectId,
+        alert_thresholds: &[i32],
+    ) -> Result<QuotaUpdate, Error> {
+        let quota = sqlx::query!(
+            r#"
+            WITH org AS (
+                UPDATE organizations
+                SET used_message_quota = LEAST(used_message_quota + 1, total_message_quota)
+                WHERE id = $1
+                  AND NOT EXISTS (
+                      SELECT 1 FROM projects
+                      WHERE id = $2
+                        AND used_message_quota >= message_quota
+                  )
+                RETURNING used_message_quota, total_message_quota, quota_alert_threshold
+            ), project AS (
+                UPDATE projects
+                SET used_message_quota = used_message_quota + 1
+                WHERE id = $2
+                  AND (SELECT total_message_quota - used_message_quota FROM org) > 0
+            )
+            SELECT used_message_quota AS "used!",
+                   total_message_quota AS "total!",
+                   quota_alert_threshold AS "alerted!"
+            FROM org
+            "#,
+            *id,
+            *project_id,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(quota) = quota else {
+            return Ok(QuotaUpdate {
+                status: QuotaStatus::Exceeded,
+                crossed_threshold: None,
+            });
+        };
+
+        let remaining = quota.total - quota.used;
+        let status = if remaining > 0 {
+            QuotaStatus::Below(remaining as u64)
+        } else {
+            QuotaStatus::Exceeded
+        };
+
+        let reached = alert_thresholds
+            .iter()
+            .copied()
+            .filter(|&threshold| quota.used * 100 >= i64::from(threshold) * quota.total)
+            .max();
+        let crossed_threshold = match reached {
+            Some(threshold) if threshold > quota.alerted => {
+                self.mark_quota_alerted(id, threshold).await?
+            }
+            _ => None,
+        };
+
+        Ok(QuotaUpdate {
+            status,
+            crossed_threshold,
+        })
+    }
+
+    /// Record that the organization has been alerted about reaching `threshold` percent of its
+    /// quota, returns `None` if it already has been, e.g., by a concurrently sent message
+    async fn mark_quota_alerted(
+        &self,
+        id: OrganizationId,
+        threshold: i32,
+    ) -> Result<Option<i32>, Error> {
+        Ok(sqlx::query_scalar!(
             r#"
             UPDATE organizations
-            SET used_message_quota = LEAST(used_message_quota + 1, total_message_quota)
+            SET quota_alert_threshold = $2
+            WHERE id = $1
+              AND quota_alert_threshold < $2
+            RETURNING quota_alert_threshold
+            "#,
+            *id,
+            threshold,
+        )
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    /// Number of messages left in the organization's message quota, without reducing it
+    pub async fn remaining_quota(&self, id: OrganizationId) -> Result<i64, Error> {
+        Ok(sqlx::query_scalar!(
+            r#"
+            SELECT (total_message_quota - used_message_quota) AS "remaining!"
+            FROM organizations
+            WHERE id = $1
+            "#,
+            *id
+        )
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    /// Number of messages `project_id` can still send, without reducing the quota
+    ///
+    /// This is the least of what is left in the organization's quota and in the project's own
+    /// quota, if it has one, like when creating a message.
+    pub async fn remaining_project_quota(&self, project_id: ProjectId) -> Result<i64, Error> {
+        Ok(sqlx::query_scalar!(
+            r#"
+            -- the project's own quota only applies if it has one, LEAST ignores NULL
+            SELECT LEAST(
+                       o.total_message_quota - o.used_message_quota,
+                       p.message_quota - p.used_message_quota
+                   ) AS "remaining!"
+            FROM organizations o
+                     JOIN projects p ON o.id = p.organization_id
+            WHERE p.id = $1
+            "#,
+            *project_id
+        )
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    /// Returns [`Error::QuotaExceeded`] if the organization used up its message quota,
+    /// along with the moment the quota resets, if it has one
+    pub async fn check_quota(&self, id: OrganizationId) -> Result<(), Error> {
+        let quota = sqlx::query!(
+            r#"
+            SELECT (total_message_quota - used_message_quota) AS "remaining!", quota_reset
+            FROM organizations
             WHERE id = $1
-            RETURNING (total_message_quota - used_message_quota) as "remaining!"
             "#,
             *id
         )
         .fetch_one(&self.pool)
         .await?;
 
-        if quota <= 0 {
-            Ok(QuotaStatus::Exceeded)
-        } else {
-            Ok(QuotaStatus::Below(quota as u64))
+        if quota.remaining <= 0 {
+            return Err(Error::QuotaExceeded(quota.quota_reset));
         }
+
+        Ok(())
+    }
+
+    pub async fn quota_reset_schedule(
+        &self,
+        id: OrganizationId,
+    ) -> Result<QuotaResetSchedule, Error> {
+        Ok(sqlx::query_as!(
+            QuotaResetSchedule,
+            r#"
+            SELECT quota_reset_time AS time, quota_reset_timezone AS timezone
+            FROM organizations
+            WHERE id = $1
+            "#,
+            *id
+        )
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    /// Change the time of day at which the organization's quota resets
+    ///
+    /// This does not change the moment of the upcoming reset, see
+    /// [`MoneyBird::refresh_quota_reset`](crate::moneybird::MoneyBird::refresh_quota_reset).
+    pub async fn update_quota_reset_schedule(
+        &self,
+        id: OrganizationId,
+        schedule: &QuotaResetSchedule,
+        actor: impl Into<Actor>,
+    ) -> Result<QuotaResetSchedule, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let updated = sqlx::query_as!(
+            QuotaResetSchedule,
+            r#"
+            UPDATE organizations
+            SET quota_reset_time = $2,
+                quota_reset_timezone = $3
+            WHERE id = $1
+            RETURNING quota_reset_time AS time, quota_reset_timezone AS timezone
+            "#,
+            *id,
+            schedule.time,
+            schedule.timezone,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        self.audit_log
+            .log(
+                &mut tx,
+                actor,
+                id,
+                "Updated quota reset time",
+                Some(json!(schedule)),
+            )
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(updated)
     }
 
     pub async fn create(
@@ -198,7 +445,8 @@ impl OrganizationRepository {
                       rate_limit_tokens,
                       rate_limit_last_used,
                       current_subscription,
-                      block_status as "block_status: OrgBlockStatus"
+                      block_status as "block_status: OrgBlockStatus",
+                      sending_paused
             "#,
             organization.name.trim(),
         )
@@ -249,7 +497,8 @@ impl OrganizationRepository {
                 rate_limit_last_used,
                 rate_limit_tokens,
                 current_subscription,
-                block_status as "block_status: OrgBlockStatus"
+                block_status as "block_status: OrgBlockStatus",
+                sending_paused
             "#,
             *id,
             organization.name.trim(),
@@ -288,7 +537,8 @@ impl OrganizationRepository {
                    rate_limit_last_used,
                    rate_limit_tokens,
                    current_subscription,
-                   block_status as "block_status: OrgBlockStatus"
+                   block_status as "block_status: OrgBlockStatus",
+                   sending_paused
             FROM organizations
             WHERE ($1::uuid[] IS NULL OR id = ANY($1))
             ORDER BY updated_at DESC
@@ -317,7 +567,8 @@ impl OrganizationRepository {
                    rate_limit_last_used,
                    rate_limit_tokens,
                    current_subscription,
-                   block_status as "block_status: OrgBlockStatus"
+                   block_status as "block_status: OrgBlockStatus",
+                   sending_paused
             FROM organizations
             WHERE id = $1
             "#,
@@ -348,6 +599,25 @@ impl OrganizationRepository {
         Ok(project_limit.is_none_or(|limit| i64::from(limit) > row.project_count))
     }
 
+    pub async fn can_create_new_domain(&self, id: OrganizationId) -> Result<bool, Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT current_subscription,
+                   (SELECT COUNT(*) FROM domains WHERE organization_id = $1) AS "domain_count!"
+            FROM organizations
+            WHERE id = $1
+            "#,
+            *id,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        let subscription: SubscriptionStatus = serde_json::from_value(row.current_subscription)?;
+        let domain_limit = subscription.active_product().domain_limit();
+
+        Ok(domain_limit.is_none_or(|limit| i64::from(limit) > row.domain_count))
+    }
+
     pub async fn max_retention_period(&self, id: OrganizationId) -> Result<i32, Error> {
         let row = sqlx::query!(
             r#"
@@ -364,6 +634,25 @@ impl OrganizationRepository {
         Ok(subscription.active_product().max_retention_period())
     }
 
+    /// List the message quota usage of past billing periods, oldest first
+    pub async fn usage_history(&self, id: OrganizationId) -> Result<Vec<UsageSnapshot>, Error> {
+        Ok(sqlx::query_as!(
+            UsageSnapshot,
+            r#"
+            SELECT period_end,
+                   used_message_quota,
+                   total_message_quota,
+                   created_at
+            FROM usage_snapshots
+            WHERE organization_id = $1
+            ORDER BY created_at
+            "#,
+            *id,
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
     pub async fn remove(&self, id: OrganizationId) -> Result<OrganizationId, Error> {
         Ok(sqlx::query_scalar!(
             r#"
@@ -492,6 +781,55 @@ impl OrganizationRepository {
         Ok(updated_user_id)
     }
 
+    /// Pause or resume outbound delivery of the organization's messages
+    pub async fn update_sending_paused(
+        &self,
+        id: OrganizationId,
+        sending_paused: bool,
+        actor: impl Into<Actor>,
+    ) -> Result<Organization, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let updated: Organization = sqlx::query_as!(
+            PgOrganization,
+            r#"
+            UPDATE organizations
+            SET sending_paused = $2
+            WHERE id = $1
+            RETURNING
+                id,
+                name,
+                total_message_quota,
+                used_message_quota,
+                quota_reset,
+                created_at,
+                updated_at,
+                moneybird_contact_id AS "moneybird_contact_id: MoneybirdContactId",
+                rate_limit_last_used,
+                rate_limit_tokens,
+                current_subscription,
+                block_status as "block_status: OrgBlockStatus",
+                sending_paused
+            "#,
+            *id,
+            sending_paused,
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .try_into()?;
+
+        let action = if sending_paused {
+            "Paused sending"
+        } else {
+            "Resumed sending"
+        };
+        self.audit_log.log(&mut tx, actor, id, action, None).await?;
+
+        tx.commit().await?;
+
+        Ok(updated)
+    }
+
     pub async fn update_block_status(
         &self,
         org_id: OrganizationId,
@@ -515,7 +853,8 @@ impl OrganizationRepository {
                 rate_limit_last_used,
                 rate_limit_tokens,
                 current_subscription,
-                block_status as "block_status: OrgBlockStatus"
+                block_status as "block_status: OrgBlockStatus",
+                sending_paused
             "#,
             *org_id,
             block_status as OrgBlockStatus,
@@ -529,7 +868,13 @@ impl OrganizationRepository {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::models::{ApiUserRepository, AuditLogRepository, NewApiUser, SYSTEM};
+    use crate::{
+        models::{
+            ApiUserRepository, AuditLogRepository, NewApiUser, NewProject, ProjectRepository,
+            SYSTEM,
+        },
+        test::TestProjects,
+    };
     use sqlx::PgPool;
 
     impl Organization {
@@ -681,4 +1026,144 @@ mod test {
                 .any(|m| m.user_id == user_3 && m.role == Role::Maintainer)
         );
     }
+
+    #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "projects")))]
+    async fn project_quota_ceiling(db: PgPool) {
+        let repo = OrganizationRepository::new(db.clone());
+        let (org_1, proj_1) = TestProjects::Org1Project1.get_ids();
+        let proj_2 = TestProjects::Org1Project2.project_id();
+
+        sqlx::query!(
+            "UPDATE organizations SET total_message_quota = 100, used_message_quota = 0 WHERE id = $1",
+            *org_1
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        sqlx::query!(
+            "UPDATE projects SET message_quota = 2 WHERE id = $1",
+            *proj_1
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        // the project can send up to its own quota
+        for remaining in [99, 98] {
+            assert_eq!(
+                repo.reduce_quota(org_1, proj_1, &[]).await.unwrap().status,
+                QuotaStatus::Below(remaining)
+            );
+        }
+
+        // the project hits its ceiling, while the organization still has quota left
+        assert_eq!(
+            repo.reduce_quota(org_1, proj_1, &[]).await.unwrap().status,
+            QuotaStatus::Exceeded
+        );
+        assert_eq!(repo.remaining_quota(org_1).await.unwrap(), 98);
+
+        // projects without a quota can still use the organization's quota
+        assert_eq!(
+            repo.reduce_quota(org_1, proj_2, &[]).await.unwrap().status,
+            QuotaStatus::Below(97)
+        );
+
+        // the project quotas can't exceed the organization's quota together
+        let projects = ProjectRepository::new(db.clone());
+        let project_update = |name: &str, message_quota| NewProject {
+            message_quota,
+            ..NewProject::test(name)
+        };
+        assert!(matches!(
+            projects
+                .update(
+                    org_1,
+                    proj_2,
+                    &project_update("Project 2 Organization 1", Some(99)),
+                    SYSTEM
+                )
+                .await,
+            Err(Error::BadRequest(_))
+        ));
+        let project = projects
+            .update(
+                org_1,
+                proj_2,
+                &project_update("Project 2 Organization 1", Some(98)),
+                SYSTEM,
+            )
+            .await
+            .unwrap();
+        assert_eq!(project.message_quota, Some(98));
+
+        // concurrent updates can't allocate more than the organization's quota together
+        sqlx::query!(
+            "UPDATE projects SET message_quota = NULL WHERE organization_id = $1",
+            *org_1
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        let update_1 = project_update("Project 1 Organization 1", Some(60));
+        let update_2 = project_update("Project 2 Organization 1", Some(60));
+        let (result_1, result_2) = tokio::join!(
+            projects.update(org_1, proj_1, &update_1, SYSTEM),
+            projects.update(org_1, proj_2, &update_2, SYSTEM),
+        );
+        assert!(result_1.is_ok() != result_2.is_ok());
+
+        // decreasing the organization's quota scales down the project quotas
+        sqlx::query!(
+            "UPDATE projects SET message_quota = CASE WHEN id = $1 THEN 20 ELSE 80 END WHERE organization_id = $2",
+            *proj_1,
+            *org_1
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        sqlx::query!(
+            "UPDATE organizations SET total_message_quota = 50 WHERE id = $1",
+            *org_1
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        assert_eq!(projects.get(proj_1).await.unwrap().message_quota, Some(10));
+        assert_eq!(projects.get(proj_2).await.unwrap().message_quota, Some(40));
+    }
+
+    #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "projects")))]
+    async fn quota_alert_threshold_crossed_once(db: PgPool) {
+        let repo = OrganizationRepository::new(db.clone());
+        let (org_1, proj_1) = TestProjects::Org1Project1.get_ids();
+
+        sqlx::query!(
+            "UPDATE organizations SET total_message_quota = 100, used_message_quota = 70 WHERE id = $1",
+            *org_1
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        // concurrent messages cross the 80% threshold, but only one of them reports it
+        let updates = futures::future::join_all(
+            (0..20).map(|_| repo.reduce_quota(org_1, proj_1, &[80, 100])),
+        )
+        .await;
+        let crossed = updates
+            .into_iter()
+            .filter_map(|update| update.unwrap().crossed_threshold)
+            .collect::<Vec<_>>();
+        assert_eq!(crossed, vec![80]);
+
+        // the threshold is not reported again within the same quota period
+        assert_eq!(
+            repo.reduce_quota(org_1, proj_1, &[80, 100])
+                .await
+                .unwrap()
+                .crossed_threshold,
+            None
+        );
+    }
 }
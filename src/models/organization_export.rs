@@ -0,0 +1,403 @@
+use async_stream::try_stream;
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use futures::{Stream, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgConnection, PgPool};
+use std::{fs::File, io::Write, path::Path, pin::pin};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tracing::error;
+use utoipa::ToSchema;
+use zip::{ZipWriter, write::SimpleFileOptions};
+
+use crate::{
+    handler::dns::DnsResolver,
+    models::{
+        ApiDomain, ApiKeyRepository, ApiUser, AuditLogRepository, DomainRepository, Error,
+        ExportFilter, MessageRepository, OrganizationId, OrganizationRepository, ProjectRepository,
+    },
+};
+
+/// Size of the chunks archives are stored and downloaded in
+const ARCHIVE_CHUNK_SIZE: usize = 1024 * 1024;
+
+id!(OrganizationExportId);
+
+#[derive(
+    Debug, Display, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, sqlx::Type, ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "organization_export_status", rename_all = "snake_case")]
+pub enum OrganizationExportStatus {
+    Pending,
+    Ready,
+    Failed,
+}
+
+/// An archive with all data of an organization, as requested by one of its admins
+#[derive(Debug, Serialize, ToSchema)]
+#[cfg_attr(test, derive(Deserialize))]
+pub struct OrganizationExport {
+    id: OrganizationExportId,
+    organization_id: OrganizationId,
+    status: OrganizationExportStatus,
+    created_at: DateTime<Utc>,
+    /// After this moment, the archive is removed and can no longer be downloaded
+    expires_at: DateTime<Utc>,
+}
+
+impl OrganizationExport {
+    pub fn id(&self) -> OrganizationExportId {
+        self.id
+    }
+
+    pub fn status(&self) -> OrganizationExportStatus {
+        self.status
+    }
+
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        self.expires_at
+    }
+}
+
+#[derive(Clone)]
+pub struct OrganizationExportRepository {
+    pool: PgPool,
+    organizations: OrganizationRepository,
+    projects: ProjectRepository,
+    domains: DomainRepository,
+    api_keys: ApiKeyRepository,
+    messages: MessageRepository,
+    audit_log: AuditLogRepository,
+}
+
+impl OrganizationExportRepository {
+    /// Number of hours an archive can be downloaded after the export has been requested
+    pub const VALIDITY_HOURS: i64 = 24;
+
+    pub fn new(pool: PgPool, resolver: DnsResolver) -> Self {
+        Self {
+            organizations: OrganizationRepository::new(pool.clone()),
+            projects: ProjectRepository::new(pool.clone()),
+            domains: DomainRepository::new(pool.clone(), resolver),
+            api_keys: ApiKeyRepository::new(pool.clone()),
+            messages: MessageRepository::new(pool.clone()),
+            audit_log: AuditLogRepository::new(pool.clone()),
+            pool,
+        }
+    }
+
+    /// Request a new export, the archive is assembled by [`Self::generate_pending`]
+    ///
+    /// Fails with a conflict if another export of the organization is still pending
+    pub async fn create(
+        &self,
+        org_id: OrganizationId,
+        requested_by: &ApiUser,
+    ) -> Result<OrganizationExport, Error> {
+        let mut tx = self.pool.begin().await?;
+        let export = sqlx::query_as!(
+            OrganizationExport,
+            r#"
+            INSERT INTO organization_exports (organization_id, requested_by, expires_at)
+            SELECT $1::uuid, $2::uuid, now() + $3 * INTERVAL '1 hour'
+            WHERE NOT EXISTS (
+                -- an export that has been pending for an hour is assumed to be lost
+                SELECT 1 FROM organization_exports
+                WHERE organization_id = $1
+                  AND status = 'pending'
+                  AND created_at > now() - INTERVAL '1 hour'
+            )
+            RETURNING id,
+                      organization_id,
+                      status AS "status: OrganizationExportStatus",
+                      created_at,
+                      expires_at
+            "#,
+            *org_id,
+            **requested_by.id(),
+            Self::VALIDITY_HOURS,
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(Error::Conflict)?;
+
+        self.audit_log
+            .log(
+                &mut tx,
+                requested_by,
+                org_id,
+                "Requested organization export",
+                Some(json!({ "export_id": export.id })),
+            )
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(export)
+    }
+
+    pub async fn get(
+        &self,
+        org_id: OrganizationId,
+        export_id: OrganizationExportId,
+    ) -> Result<OrganizationExport, Error> {
+        Ok(sqlx::query_as!(
+            OrganizationExport,
+            r#"
+            SELECT id,
+                   organization_id,
+                   status AS "status: OrganizationExportStatus",
+                   created_at,
+                   expires_at
+            FROM organization_exports
+            WHERE id = $1
+              AND organization_id = $2
+              AND expires_at > now()
+            "#,
+            *export_id,
+            *org_id,
+        )
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    /// Stream the archive of an export in chunks, if it is ready and has not yet expired
+    pub async fn stream_archive(
+        &self,
+        org_id: OrganizationId,
+        export_id: OrganizationExportId,
+    ) -> Result<impl Stream<Item = Result<Vec<u8>, Error>> + Send + 'static, Error> {
+        let export = self.get(org_id, export_id).await?;
+        if export.status != OrganizationExportStatus::Ready {
+            return Err(Error::NotFound("export is not ready"));
+        }
+
+        let pool = self.pool.clone();
+
+        Ok(try_stream! {
+            for position in 0_i32.. {
+                let chunk = sqlx::query_scalar!(
+                    r#"
+                    SELECT data
+                    FROM organization_export_chunks
+                    WHERE export_id = $1
+                      AND position = $2
+                    "#,
+                    *export_id,
+                    position,
+                )
+                .fetch_optional(&pool)
+                .await
+                .map_err(Error::from)?;
+
+                let Some(chunk) = chunk else {
+                    break;
+                };
+
+                yield chunk;
+            }
+        })
+    }
+
+    /// Assemble the archives of all pending exports, oldest first, and mark the exports as ready
+    ///
+    /// If the archive of an export could not be assembled, that export is marked as failed instead.
+    /// Exports are claimed with a row lock, such that multiple runners don't generate the same export.
+    pub async fn generate_pending(&self) -> Result<(), Error> {
+        loop {
+            let mut tx = self.pool.begin().await?;
+            let Some(export) = sqlx::query!(
+                r#"
+                SELECT id, organization_id
+                FROM organization_exports
+                WHERE status = 'pending'
+                ORDER BY created_at
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+                "#,
+            )
+            .fetch_optional(&mut *tx)
+            .await?
+            else {
+                return Ok(());
+            };
+            let export_id = OrganizationExportId::from(export.id);
+            let org_id = OrganizationId::from(export.organization_id);
+
+            let path = std::env::temp_dir().join(format!("remails-export-{export_id}.zip"));
+            let stored = self.store_archive(&mut tx, export_id, org_id, &path).await;
+            tokio::fs::remove_file(&path).await.ok();
+
+            if let Err(e) = stored {
+                error!(
+                    organization_id = org_id.to_string(),
+                    export_id = export_id.to_string(),
+                    "failed to generate organization export: {e:?}"
+                );
+                tx.rollback().await?;
+
+                sqlx::query!(
+                    "UPDATE organization_exports SET status = 'failed' WHERE id = $1",
+                    *export_id,
+                )
+                .execute(&self.pool)
+                .await?;
+
+                continue;
+            }
+
+            sqlx::query!(
+                "UPDATE organization_exports SET status = 'ready' WHERE id = $1",
+                *export_id,
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            tx.commit().await?;
+        }
+    }
+
+    /// Write the archive to a temporary file at `path`, and store it in chunks
+    async fn store_archive(
+        &self,
+        conn: &mut PgConnection,
+        export_id: OrganizationExportId,
+        org_id: OrganizationId,
+        path: &Path,
+    ) -> Result<(), Error> {
+        let file = self.assemble(org_id, path).await?;
+        let mut file = tokio::fs::File::from_std(file);
+        file.rewind().await?;
+
+        for position in 0_i32.. {
+            let mut chunk = Vec::with_capacity(ARCHIVE_CHUNK_SIZE);
+            (&mut file)
+                .take(ARCHIVE_CHUNK_SIZE as u64)
+                .read_to_end(&mut chunk)
+                .await?;
+            if chunk.is_empty() {
+                break;
+            }
+
+            sqlx::query!(
+                r#"
+                INSERT INTO organization_export_chunks (export_id, position, data)
+                VALUES ($1, $2, $3)
+                "#,
+                *export_id,
+                position,
+                chunk,
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Zip archive with a JSON file for each kind of data of the organization, written to a file
+    /// at `path` such that it does not need to fit in memory
+    ///
+    /// This only contains data that organization admins can view in the API as well,
+    /// secrets like password hashes and private DKIM keys are left out.
+    /// The file and archive are written on the blocking thread pool, in between fetching data.
+    async fn assemble(&self, org_id: OrganizationId, path: &Path) -> Result<File, Error> {
+        let organization = self
+            .organizations
+            .get_by_id(org_id)
+            .await?
+            .ok_or(Error::NotFound("organization not found"))?;
+        let members = self.organizations.list_members(org_id).await?;
+        let projects = self.projects.list(org_id).await?;
+        let domains = self
+            .domains
+            .list(org_id)
+            .await?
+            .into_iter()
+            .map(ApiDomain::from)
+            .collect::<Vec<_>>();
+        let api_keys = self.api_keys.list(org_id).await?;
+        let project_ids = projects.iter().map(|p| p.id()).collect::<Vec<_>>();
+
+        let path = path.to_owned();
+        let mut archive = blocking(move || {
+            let file = File::options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)?;
+            let mut archive = ZipWriter::new(file);
+            add_json(&mut archive, "organization.json", &organization)?;
+            add_json(&mut archive, "members.json", &members)?;
+            add_json(&mut archive, "projects.json", &projects)?;
+            add_json(&mut archive, "domains.json", &domains)?;
+            add_json(&mut archive, "api_keys.json", &api_keys)?;
+
+            archive.start_file("messages.json", SimpleFileOptions::default())?;
+            archive.write_all(b"[")?;
+
+            Ok(archive)
+        })
+        .await?;
+
+        // messages are written page by page, as there may be too many to load at once
+        let mut first = true;
+        for project_id in project_ids {
+            let mut pages = pin!(self.messages.stream_export(
+                org_id,
+                project_id,
+                ExportFilter::default()
+            ));
+            while let Some(page) = pages.try_next().await? {
+                (archive, first) = blocking(move || {
+                    for message in page {
+                        if !first {
+                            archive.write_all(b",")?;
+                        }
+                        first = false;
+                        serde_json::to_writer(&mut archive, &message)?;
+                    }
+
+                    Ok((archive, first))
+                })
+                .await?;
+            }
+        }
+
+        blocking(move || {
+            archive.write_all(b"]")?;
+
+            Ok(archive.finish()?)
+        })
+        .await
+    }
+
+    /// Remove exports, including their archives, that can no longer be downloaded
+    pub async fn remove_expired(&self) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM organization_exports WHERE expires_at <= now()")
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Run blocking file I/O on the blocking thread pool, such that it does not stall the runtime
+async fn blocking<T>(f: impl FnOnce() -> Result<T, Error> + Send + 'static) -> Result<T, Error>
+where
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| Error::Internal(format!("blocking archive task failed: {e}")))?
+}
+
+fn add_json(archive: &mut ZipWriter<File>, name: &str, data: &impl Serialize) -> Result<(), Error> {
+    archive.start_file(name, SimpleFileOptions::default())?;
+    serde_json::to_writer_pretty(archive, data)?;
+
+    Ok(())
+}
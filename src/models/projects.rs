@@ -3,6 +3,7 @@ use chrono::{DateTime, Utc};
 use garde::Validate;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use sqlx::{Postgres, Transaction};
 use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
@@ -28,6 +29,33 @@ pub struct Project {
     pub plaintext_fallback: bool,
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
+    pub verp: bool,
+    pub dedup_window_minutes: Option<i32>,
+    pub max_automatic_retries: Option<i32>,
+    pub max_message_age_minutes: Option<i32>,
+    pub default_from_email: Option<String>,
+    pub default_from_name: Option<String>,
+    /// Maximum number of messages this project may send per quota period, if limited
+    pub message_quota: Option<i64>,
+    /// Number of messages this project has sent in the current quota period
+    pub used_message_quota: i64,
+    pub unauthorized_domain_policy: UnauthorizedDomainPolicy,
+    pub rewrite_unaligned_from: bool,
+    pub review_exhausted_messages: bool,
+}
+
+/// What happens to messages sent from a domain the project is not permitted to use
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "unauthorized_domain_policy", rename_all = "snake_case")]
+pub enum UnauthorizedDomainPolicy {
+    /// Hold the message, such that it can be retried once the domain has been configured
+    #[default]
+    Hold,
+    /// Reject the message right away
+    Reject,
 }
 
 impl Project {
@@ -55,6 +83,90 @@ pub struct NewProject {
     /// if delivery over TLS fails.
     #[garde(skip)]
     pub plaintext_fallback: bool,
+    /// If set true, emails in the project are sent with a Variable Envelope Return Path (VERP),
+    /// i.e., an envelope sender that is unique per message and recipient,
+    /// such that bounces can be attributed to a specific recipient.
+    #[garde(skip)]
+    #[serde(default)]
+    pub verp: bool,
+    /// If set, a message with the same `Message-ID` header as a message created within this
+    /// many minutes in this project is not stored again. Instead, the existing message is returned.
+    #[schema(minimum = 1, maximum = 10080)]
+    #[garde(range(min = 1, max = 10080))]
+    #[serde(default)]
+    pub dedup_window_minutes: Option<i32>,
+    /// If set, overrides the number of delivery attempts for messages in this project
+    /// after which a message is considered failed.
+    #[schema(minimum = 1, maximum = 20)]
+    #[garde(range(min = 1, max = 20))]
+    #[serde(default)]
+    pub max_automatic_retries: Option<i32>,
+    /// If set, messages in this project are considered failed once they are this many minutes old,
+    /// even if delivery attempts are left.
+    #[schema(minimum = 1, maximum = 10080)]
+    #[garde(range(min = 1, max = 10080))]
+    #[serde(default)]
+    pub max_message_age_minutes: Option<i32>,
+    /// If set, messages submitted via the API without a `from` address are sent from this address.
+    ///
+    /// The project must be permitted to use the domain of this address.
+    #[schema(format = "Email")]
+    #[garde(email)]
+    #[serde(default)]
+    pub default_from_email: Option<String>,
+    /// The display name used together with `default_from_email`.
+    #[schema(min_length = 1, max_length = 100)]
+    #[garde(length(min = 1, max = 100))]
+    #[serde(default)]
+    pub default_from_name: Option<String>,
+    /// If set, this project can send at most this many messages per quota period,
+    /// such that it can't use up the message quota of the whole organization.
+    ///
+    /// The quotas of all projects together can't exceed the message quota of the organization.
+    /// Projects without a quota share the organization's quota freely.
+    #[schema(minimum = 0)]
+    #[garde(range(min = 0))]
+    #[serde(default)]
+    pub message_quota: Option<i64>,
+    /// Whether messages sent from a domain the project is not permitted to use are held,
+    /// such that they can be retried once the domain has been configured, or rejected right away.
+    #[garde(skip)]
+    #[serde(default)]
+    pub unauthorized_domain_policy: UnauthorizedDomainPolicy,
+    /// If set true, the From header of messages sent from a domain outside the project's domains
+    /// is rewritten to the envelope sender, instead of rejecting the message.
+    /// The original From header is kept as Reply-To header.
+    #[garde(skip)]
+    #[serde(default)]
+    pub rewrite_unaligned_from: bool,
+    /// If set true, messages that failed after exhausting their automatic retries are put on
+    /// `needs_review` and the organization admins are notified, instead of failing silently.
+    /// Such messages can be requeued for another round of retries.
+    #[garde(skip)]
+    #[serde(default)]
+    pub review_exhausted_messages: bool,
+}
+
+#[cfg(test)]
+impl NewProject {
+    /// A project with the given name, the minimal retention period and all options off
+    pub(crate) fn test(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            retention_period_days: 1,
+            plaintext_fallback: false,
+            verp: false,
+            dedup_window_minutes: None,
+            max_automatic_retries: None,
+            max_message_age_minutes: None,
+            default_from_email: None,
+            default_from_name: None,
+            message_quota: None,
+            unauthorized_domain_policy: UnauthorizedDomainPolicy::Hold,
+            rewrite_unaligned_from: false,
+            review_exhausted_messages: false,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -85,17 +197,46 @@ impl ProjectRepository {
         }
 
         let mut tx = self.pool.begin().await?;
+        Self::check_quota_allocation(&mut tx, organization_id, None, new.message_quota).await?;
+
         let project = sqlx::query_as!(
             Project,
             r#"
-            INSERT INTO projects (id, organization_id, name, retention_period_days, plaintext_fallback)
-            VALUES (gen_random_uuid(), $1, $2, $3, $4)
-            RETURNING *
+            INSERT INTO projects (id, organization_id, name, retention_period_days, plaintext_fallback, verp, dedup_window_minutes, max_automatic_retries, max_message_age_minutes, default_from_email, default_from_name, message_quota, unauthorized_domain_policy, rewrite_unaligned_from, review_exhausted_messages)
+            VALUES (gen_random_uuid(), $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+            RETURNING id,
+                      organization_id,
+                      name,
+                      retention_period_days,
+                      plaintext_fallback,
+                      created_at,
+                      updated_at,
+                      verp,
+                      dedup_window_minutes,
+                      max_automatic_retries,
+                      max_message_age_minutes,
+                      default_from_email,
+                      default_from_name,
+                      message_quota,
+                      used_message_quota,
+                      unauthorized_domain_policy AS "unauthorized_domain_policy: UnauthorizedDomainPolicy",
+                      rewrite_unaligned_from,
+                      review_exhausted_messages
             "#,
             *organization_id,
             new.name.trim(),
             new.retention_period_days,
-            new.plaintext_fallback
+            new.plaintext_fallback,
+            new.verp,
+            new.dedup_window_minutes,
+            new.max_automatic_retries,
+            new.max_message_age_minutes,
+            new.default_from_email,
+            new.default_from_name,
+            new.message_quota,
+            new.unauthorized_domain_policy as UnauthorizedDomainPolicy,
+            new.rewrite_unaligned_from,
+            new.review_exhausted_messages,
         )
         .fetch_one(&mut *tx)
         .await?;
@@ -114,11 +255,80 @@ impl ProjectRepository {
         Ok(project)
     }
 
+    /// Check that the quotas of all projects of the organization together, including the new
+    /// `message_quota` of `project_id`, do not exceed the message quota of the organization
+    ///
+    /// This locks the organization until the transaction ends, such that concurrent project
+    /// updates and changes to the organization's quota can't invalidate the check
+    async fn check_quota_allocation(
+        tx: &mut Transaction<'_, Postgres>,
+        organization_id: OrganizationId,
+        project_id: Option<ProjectId>,
+        message_quota: Option<i64>,
+    ) -> Result<(), Error> {
+        let Some(message_quota) = message_quota else {
+            return Ok(());
+        };
+
+        let total_message_quota = sqlx::query_scalar!(
+            r#"
+            SELECT total_message_quota
+            FROM organizations
+            WHERE id = $1
+            FOR UPDATE
+            "#,
+            *organization_id,
+        )
+        .fetch_one(&mut **tx)
+        .await?;
+
+        // runs after acquiring the lock, so it sees the quotas committed by concurrent updates
+        let allocated = sqlx::query_scalar!(
+            r#"
+            SELECT COALESCE(SUM(message_quota), 0)::bigint AS "allocated!"
+            FROM projects
+            WHERE organization_id = $1
+              AND id IS DISTINCT FROM $2
+            "#,
+            *organization_id,
+            project_id.map(|id| *id),
+        )
+        .fetch_one(&mut **tx)
+        .await?;
+
+        if allocated + message_quota > total_message_quota {
+            return Err(Error::BadRequest(format!(
+                "The project quotas together can't exceed the organization's message quota of {total_message_quota}, of which {allocated} is already allocated to other projects"
+            )));
+        }
+
+        Ok(())
+    }
+
     pub async fn get(&self, project_id: ProjectId) -> Result<Project, Error> {
         Ok(sqlx::query_as!(
             Project,
             r#"
-            SELECT * FROM projects WHERE id = $1
+            SELECT id,
+                   organization_id,
+                   name,
+                   retention_period_days,
+                   plaintext_fallback,
+                   created_at,
+                   updated_at,
+                   verp,
+                   dedup_window_minutes,
+                   max_automatic_retries,
+                   max_message_age_minutes,
+                   default_from_email,
+                   default_from_name,
+                   message_quota,
+                   used_message_quota,
+                   unauthorized_domain_policy AS "unauthorized_domain_policy: UnauthorizedDomainPolicy",
+                   rewrite_unaligned_from,
+                   review_exhausted_messages
+            FROM projects
+            WHERE id = $1
             "#,
             *project_id,
         )
@@ -130,7 +340,27 @@ impl ProjectRepository {
         Ok(sqlx::query_as!(
             Project,
             r#"
-            SELECT * FROM projects WHERE organization_id = $1 ORDER BY updated_at DESC
+            SELECT id,
+                   organization_id,
+                   name,
+                   retention_period_days,
+                   plaintext_fallback,
+                   created_at,
+                   updated_at,
+                   verp,
+                   dedup_window_minutes,
+                   max_automatic_retries,
+                   max_message_age_minutes,
+                   default_from_email,
+                   default_from_name,
+                   message_quota,
+                   used_message_quota,
+                   unauthorized_domain_policy AS "unauthorized_domain_policy: UnauthorizedDomainPolicy",
+                   rewrite_unaligned_from,
+                   review_exhausted_messages
+            FROM projects
+            WHERE organization_id = $1
+            ORDER BY updated_at DESC
             "#,
             *organization_id,
         )
@@ -153,22 +383,67 @@ impl ProjectRepository {
         }
 
         let mut tx = self.pool.begin().await?;
+        Self::check_quota_allocation(
+            &mut tx,
+            organization_id,
+            Some(project_id),
+            update.message_quota,
+        )
+        .await?;
+
         let project = sqlx::query_as!(
             Project,
             r#"
             UPDATE projects 
             SET name = $3,
                 retention_period_days = $4,
-                plaintext_fallback = $5
+                plaintext_fallback = $5,
+                verp = $6,
+                dedup_window_minutes = $7,
+                max_automatic_retries = $8,
+                max_message_age_minutes = $9,
+                default_from_email = $10,
+                default_from_name = $11,
+                message_quota = $12,
+                unauthorized_domain_policy = $13,
+                rewrite_unaligned_from = $14,
+                review_exhausted_messages = $15
             WHERE id = $2
               AND organization_id = $1
-            RETURNING *
+            RETURNING id,
+                      organization_id,
+                      name,
+                      retention_period_days,
+                      plaintext_fallback,
+                      created_at,
+                      updated_at,
+                      verp,
+                      dedup_window_minutes,
+                      max_automatic_retries,
+                      max_message_age_minutes,
+                      default_from_email,
+                      default_from_name,
+                      message_quota,
+                      used_message_quota,
+                      unauthorized_domain_policy AS "unauthorized_domain_policy: UnauthorizedDomainPolicy",
+                      rewrite_unaligned_from,
+                      review_exhausted_messages
             "#,
             *organization_id,
             *project_id,
             update.name.trim(),
             update.retention_period_days,
             update.plaintext_fallback,
+            update.verp,
+            update.dedup_window_minutes,
+            update.max_automatic_retries,
+            update.max_message_age_minutes,
+            update.default_from_email,
+            update.default_from_name,
+            update.message_quota,
+            update.unauthorized_domain_policy as UnauthorizedDomainPolicy,
+            update.rewrite_unaligned_from,
+            update.review_exhausted_messages,
         )
         .fetch_one(&mut *tx)
         .await?;
@@ -244,21 +519,18 @@ mod test {
 
         // create project
         let project = repo
-            .create(
-                &NewProject {
-                    name: "New Project".to_owned(),
-                    retention_period_days: 1,
-                    plaintext_fallback: false,
-                },
-                org_1,
-                SYSTEM,
-            )
+            .create(&NewProject::test("New Project"), org_1, SYSTEM)
             .await
             .unwrap();
         assert_eq!(project.name, "New Project");
         assert_eq!(project.retention_period_days, 1);
         assert_eq!(project.organization_id, org_1);
         assert!(!project.plaintext_fallback);
+        assert!(!project.verp);
+        assert_eq!(
+            project.unauthorized_domain_policy,
+            UnauthorizedDomainPolicy::Hold
+        );
         let audit_entries = audit_log.list(org_1).await.unwrap();
         assert_eq!(audit_entries.len(), 1);
         assert_eq!(audit_entries[0].target_id, Some(*project.id()));
@@ -282,9 +554,13 @@ mod test {
                 org_1,
                 project.id(),
                 &NewProject {
-                    name: "Updated Project".to_owned(),
                     retention_period_days: 3,
-                    plaintext_fallback: false,
+                    verp: true,
+                    dedup_window_minutes: Some(60),
+                    max_automatic_retries: Some(3),
+                    max_message_age_minutes: Some(1440),
+                    unauthorized_domain_policy: UnauthorizedDomainPolicy::Reject,
+                    ..NewProject::test("Updated Project")
                 },
                 SYSTEM,
             )
@@ -292,6 +568,14 @@ mod test {
             .unwrap();
         assert_eq!(project.name, "Updated Project");
         assert_eq!(project.retention_period_days, 3);
+        assert!(project.verp);
+        assert_eq!(project.dedup_window_minutes, Some(60));
+        assert_eq!(project.max_automatic_retries, Some(3));
+        assert_eq!(project.max_message_age_minutes, Some(1440));
+        assert_eq!(
+            project.unauthorized_domain_policy,
+            UnauthorizedDomainPolicy::Reject
+        );
         assert_eq!(project.organization_id, org_1);
         assert_eq!(projects[0].id(), project.id());
         let audit_entries = audit_log.list(org_1).await.unwrap();
@@ -326,9 +610,8 @@ mod test {
         let mut new_project = |retention_period_days| {
             n += 1;
             NewProject {
-                name: format!("Project {n}"),
                 retention_period_days,
-                plaintext_fallback: false,
+                ..NewProject::test(&format!("Project {n}"))
             }
         };
 
@@ -1,9 +1,21 @@
 use crate::models::{Actor, AuditLogRepository, Error, OrganizationId, ProjectId};
+use aws_lc_rs::{constant_time, digest, hmac, pbkdf2};
+use base64ct::{Base64, Encoding};
+use email_address::EmailAddress;
 use garde::Validate;
+use md5::{
+    Digest, Md5,
+    block_api::Md5Core,
+    digest::{
+        block_api::{CoreProxy, UpdateCore},
+        common::hazmat::{SerializableState, SerializedState},
+    },
+};
 use rand::distr::{Alphanumeric, SampleString};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::json;
 use sqlx::types::chrono::{DateTime, Utc};
+use std::{fmt::Display, num::NonZeroU32, str::FromStr};
 use utoipa::ToSchema;
 
 id!(SmtpCredentialId);
@@ -17,6 +29,19 @@ pub struct SmtpCredential {
     #[serde(skip)]
     #[debug("******")]
     password_hash: String,
+    /// Key for CRAM-MD5 authentication, only set if the credential has been provisioned for it.
+    /// See [`CramMd5Key`] for the format.
+    #[serde(skip)]
+    #[debug("******")]
+    cram_md5_secret: Option<Vec<u8>>,
+    /// Not set for credentials created before SCRAM-SHA-256 authentication was supported
+    #[serde(skip)]
+    #[debug("******")]
+    scram_sha256_verifier: Option<String>,
+    /// Domains the credential may send messages to, or `null` to allow any recipient
+    allowed_recipient_domains: Option<Vec<String>>,
+    /// Addresses the credential may send messages from, or `null` to allow any sender
+    allowed_sender_addresses: Option<Vec<String>>,
     project_id: ProjectId,
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
@@ -31,6 +56,11 @@ pub struct SmtpCredentialRequest {
     #[garde(pattern("^[a-zA-Z0-9_-]{3,256}$"))]
     #[schema(pattern = "^[a-zA-Z0-9_-]{3,256}$")]
     pub(crate) username: String,
+    /// Also allow authenticating with CRAM-MD5, which requires storing a key that suffices
+    /// to authenticate with CRAM-MD5, although it does not reveal the password
+    #[serde(default)]
+    #[garde(skip)]
+    pub(crate) cram_md5: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema, Validate)]
@@ -39,6 +69,81 @@ pub struct SmtpCredentialUpdateRequest {
     #[garde(length(max = 500))]
     #[schema(max_length = 500)]
     pub(crate) description: String,
+    /// Domains the credential may send messages to, or `null` to allow any recipient.
+    /// Subdomains have to be listed separately. Left unchanged if omitted.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[garde(custom(validate_recipient_domains))]
+    #[schema(value_type = Option<Vec<String>>, max_items = 100)]
+    pub(crate) allowed_recipient_domains: Option<Option<Vec<String>>>,
+    /// Addresses the credential may send messages from, or `null` to allow any sender.
+    /// Left unchanged if omitted.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[garde(custom(validate_sender_addresses))]
+    #[schema(value_type = Option<Vec<String>>, max_items = 100)]
+    pub(crate) allowed_sender_addresses: Option<Option<Vec<String>>>,
+}
+
+/// Distinguishes a field set to `null` (`Some(None)`) from an omitted field (`None`)
+pub(crate) fn deserialize_present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+const MAX_RESTRICTIONS: usize = 100;
+
+fn validate_recipient_domains(domains: &Option<Option<Vec<String>>>, _: &()) -> garde::Result {
+    let Some(Some(domains)) = domains else {
+        return Ok(());
+    };
+
+    if domains.len() > MAX_RESTRICTIONS {
+        return Err(garde::Error::new(format!(
+            "at most {MAX_RESTRICTIONS} domains can be allowed"
+        )));
+    }
+
+    if let Some(invalid) = domains
+        .iter()
+        .find(|d| d.is_empty() || d.len() > 253 || d.contains(['@', ' ']))
+    {
+        return Err(garde::Error::new(format!("invalid domain: {invalid:?}")));
+    }
+
+    Ok(())
+}
+
+fn validate_sender_addresses(addresses: &Option<Option<Vec<String>>>, _: &()) -> garde::Result {
+    let Some(Some(addresses)) = addresses else {
+        return Ok(());
+    };
+
+    if addresses.len() > MAX_RESTRICTIONS {
+        return Err(garde::Error::new(format!(
+            "at most {MAX_RESTRICTIONS} addresses can be allowed"
+        )));
+    }
+
+    if let Some(invalid) = addresses
+        .iter()
+        .find(|a| a.parse::<EmailAddress>().is_err())
+    {
+        return Err(garde::Error::new(format!(
+            "invalid email address: {invalid:?}"
+        )));
+    }
+
+    Ok(())
 }
 
 #[derive(Serialize, derive_more::Debug, ToSchema)]
@@ -78,6 +183,49 @@ impl SmtpCredential {
         password_auth::verify_password(password.as_bytes(), &self.password_hash).is_ok()
     }
 
+    /// Verify the HMAC-MD5 digest of the challenge sent to the client (RFC 2195)
+    pub fn verify_cram_md5(&self, challenge: &str, digest: &[u8]) -> bool {
+        let Some(key) = self
+            .cram_md5_secret
+            .as_deref()
+            .and_then(CramMd5Key::from_bytes)
+        else {
+            return false;
+        };
+
+        constant_time::verify_slices_are_equal(&key.sign(challenge.as_bytes()), digest).is_ok()
+    }
+
+    /// The SCRAM-SHA-256 verifier, if the credential has one
+    pub fn scram_sha256(&self) -> Option<ScramVerifier> {
+        self.scram_sha256_verifier.as_deref()?.parse().ok()
+    }
+
+    /// Whether the sender and all recipients of a message are allowed for this credential
+    pub fn may_send(&self, from: &EmailAddress, recipients: &[EmailAddress]) -> bool {
+        let sender_allowed = self
+            .allowed_sender_addresses
+            .as_ref()
+            .is_none_or(|allowed| {
+                allowed
+                    .iter()
+                    .any(|address| address.eq_ignore_ascii_case(from.as_str()))
+            });
+
+        let recipients_allowed = self
+            .allowed_recipient_domains
+            .as_ref()
+            .is_none_or(|allowed| {
+                recipients.iter().all(|recipient| {
+                    allowed
+                        .iter()
+                        .any(|domain| domain.eq_ignore_ascii_case(recipient.domain()))
+                })
+            });
+
+        sender_allowed && recipients_allowed
+    }
+
     pub fn id(&self) -> SmtpCredentialId {
         self.id
     }
@@ -92,6 +240,205 @@ impl SmtpCredential {
     }
 }
 
+/// Salted SCRAM-SHA-256 verifier of a password (RFC 5802, 3)
+///
+/// Stored as `SCRAM-SHA-256$<iterations>:<salt>$<StoredKey>:<ServerKey>` (RFC 5803)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScramVerifier {
+    iterations: NonZeroU32,
+    salt: Vec<u8>,
+    stored_key: Vec<u8>,
+    server_key: Vec<u8>,
+}
+
+impl ScramVerifier {
+    const ITERATIONS: NonZeroU32 = NonZeroU32::new(4096).unwrap();
+
+    pub fn generate(password: &str) -> Self {
+        let salt: [u8; 16] = rand::random();
+
+        Self::derive(password, &salt, Self::ITERATIONS)
+    }
+
+    pub(crate) fn derive(password: &str, salt: &[u8], iterations: NonZeroU32) -> Self {
+        let mut salted_password = [0u8; digest::SHA256_OUTPUT_LEN];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            iterations,
+            salt,
+            password.as_bytes(),
+            &mut salted_password,
+        );
+        let salted_password = hmac::Key::new(hmac::HMAC_SHA256, &salted_password);
+
+        let client_key = hmac::sign(&salted_password, b"Client Key");
+        let stored_key = digest::digest(&digest::SHA256, client_key.as_ref());
+        let server_key = hmac::sign(&salted_password, b"Server Key");
+
+        Self {
+            iterations,
+            salt: salt.to_vec(),
+            stored_key: stored_key.as_ref().to_vec(),
+            server_key: server_key.as_ref().to_vec(),
+        }
+    }
+
+    /// Verifier for a user that does not exist, with which the exchange fails at the proof,
+    /// just like it does for a wrong password (RFC 5802, 5.1)
+    pub(crate) fn unknown_user(salt: &[u8]) -> Self {
+        Self {
+            iterations: Self::ITERATIONS,
+            salt: salt.to_vec(),
+            stored_key: rand::random::<[u8; digest::SHA256_OUTPUT_LEN]>().to_vec(),
+            server_key: rand::random::<[u8; digest::SHA256_OUTPUT_LEN]>().to_vec(),
+        }
+    }
+
+    pub fn iterations(&self) -> NonZeroU32 {
+        self.iterations
+    }
+
+    pub fn salt(&self) -> &[u8] {
+        &self.salt
+    }
+
+    /// Check the `ClientProof` the client sent for the `AuthMessage` of the exchange
+    pub fn verify_proof(&self, auth_message: &str, proof: &[u8]) -> bool {
+        let stored_key = hmac::Key::new(hmac::HMAC_SHA256, &self.stored_key);
+        let client_signature = hmac::sign(&stored_key, auth_message.as_bytes());
+        if proof.len() != client_signature.as_ref().len() {
+            return false;
+        }
+
+        let client_key = proof
+            .iter()
+            .zip(client_signature.as_ref())
+            .map(|(p, s)| p ^ s)
+            .collect::<Vec<_>>();
+        let computed = digest::digest(&digest::SHA256, &client_key);
+
+        constant_time::verify_slices_are_equal(computed.as_ref(), &self.stored_key).is_ok()
+    }
+
+    /// The `ServerSignature` proving to the client that the server knows the verifier
+    pub fn server_signature(&self, auth_message: &str) -> Vec<u8> {
+        let server_key = hmac::Key::new(hmac::HMAC_SHA256, &self.server_key);
+
+        hmac::sign(&server_key, auth_message.as_bytes())
+            .as_ref()
+            .to_vec()
+    }
+}
+
+impl Display for ScramVerifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "SCRAM-SHA-256${}:{}${}:{}",
+            self.iterations,
+            Base64::encode_string(&self.salt),
+            Base64::encode_string(&self.stored_key),
+            Base64::encode_string(&self.server_key),
+        )
+    }
+}
+
+impl FromStr for ScramVerifier {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::Internal("invalid SCRAM-SHA-256 verifier".to_string());
+
+        let parts = s.strip_prefix("SCRAM-SHA-256$").ok_or_else(invalid)?;
+        let (salt, keys) = parts.split_once('$').ok_or_else(invalid)?;
+        let (iterations, salt) = salt.split_once(':').ok_or_else(invalid)?;
+        let (stored_key, server_key) = keys.split_once(':').ok_or_else(invalid)?;
+
+        Ok(Self {
+            iterations: iterations.parse().map_err(|_| invalid())?,
+            salt: Base64::decode_vec(salt).map_err(|_| invalid())?,
+            stored_key: Base64::decode_vec(stored_key).map_err(|_| invalid())?,
+            server_key: Base64::decode_vec(server_key).map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// HMAC-MD5 key of a password for CRAM-MD5 authentication (RFC 2195)
+///
+/// Like Dovecot, this only keeps the MD5 states after hashing the key XOR-ed with the inner and
+/// outer pad, which is all HMAC-MD5 needs (RFC 2104, 4), instead of the password itself.
+/// Stored as the inner state followed by the outer state, as little-endian words.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CramMd5Key {
+    inner: [u8; 16],
+    outer: [u8; 16],
+}
+
+impl CramMd5Key {
+    const BLOCK_LEN: usize = 64;
+    const STORED_LEN: usize = 32;
+
+    fn derive(password: &str) -> Self {
+        let mut key = [0u8; Self::BLOCK_LEN];
+        if password.len() > Self::BLOCK_LEN {
+            key[..16].copy_from_slice(&Md5::digest(password.as_bytes()));
+        } else {
+            key[..password.len()].copy_from_slice(password.as_bytes());
+        }
+
+        let pad_state = |pad: u8| {
+            let mut core = Md5Core::default();
+            core.update_blocks(&[key.map(|b| b ^ pad).into()]);
+
+            let mut state = [0u8; 16];
+            state.copy_from_slice(&core.serialize()[..16]);
+            state
+        };
+
+        Self {
+            inner: pad_state(0x36),
+            outer: pad_state(0x5c),
+        }
+    }
+
+    /// Continue hashing from `state`, the MD5 state after hashing a single (padded key) block
+    fn resume(state: &[u8; 16]) -> Md5 {
+        let mut serialized = SerializedState::<Md5Core>::default();
+        serialized[..16].copy_from_slice(state);
+        serialized[16..].copy_from_slice(&1u64.to_le_bytes());
+        let core = Md5Core::deserialize(&serialized).expect("any MD5 state is valid");
+
+        Md5::compose(core, Default::default())
+    }
+
+    /// The HMAC-MD5 digest of `message`
+    fn sign(&self, message: &[u8]) -> [u8; 16] {
+        let inner = Self::resume(&self.inner).chain_update(message).finalize();
+
+        Self::resume(&self.outer)
+            .chain_update(inner)
+            .finalize()
+            .into()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        [self.inner, self.outer].concat()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::STORED_LEN {
+            return None;
+        }
+
+        let (inner, outer) = bytes.split_at(16);
+
+        Some(Self {
+            inner: inner.try_into().ok()?,
+            outer: outer.try_into().ok()?,
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SmtpCredentialRepository {
     pool: sqlx::PgPool,
@@ -134,19 +481,25 @@ impl SmtpCredentialRepository {
 
         let password = Alphanumeric.sample_string(&mut rand::rng(), 20);
         let password_hash = password_auth::generate_hash(password.as_bytes());
+        let cram_md5_secret = new_credential
+            .cram_md5
+            .then(|| CramMd5Key::derive(&password).to_bytes());
+        let scram_sha256_verifier = ScramVerifier::generate(&password).to_string();
 
         let mut tx = self.pool.begin().await?;
 
         let generated = sqlx::query_as!(
             SmtpCredential,
             r#"
-            INSERT INTO smtp_credentials (id, description, username, password_hash, project_id)
-            VALUES (gen_random_uuid(), $1, $2, $3, $4)
+            INSERT INTO smtp_credentials (id, description, username, password_hash, cram_md5_secret, scram_sha256_verifier, project_id)
+            VALUES (gen_random_uuid(), $1, $2, $3, $4, $5, $6)
             RETURNING *
             "#,
             new_credential.description,
             username,
             password_hash,
+            cram_md5_secret,
+            scram_sha256_verifier,
             *project_id
         )
         .fetch_one(&mut *tx)
@@ -225,7 +578,9 @@ impl SmtpCredentialRepository {
             SmtpCredential,
             r#"
             UPDATE smtp_credentials cred
-            SET description = $1
+            SET description = $1,
+                allowed_recipient_domains = CASE WHEN $7 THEN $5 ELSE cred.allowed_recipient_domains END,
+                allowed_sender_addresses = CASE WHEN $8 THEN $6 ELSE cred.allowed_sender_addresses END
             FROM projects p
             WHERE cred.id = $2
               AND cred.project_id = p.id
@@ -237,6 +592,10 @@ impl SmtpCredentialRepository {
                 cred.project_id,
                 cred.created_at,
                 '' AS "password_hash!",
+                NULL::bytea AS "cram_md5_secret?",
+                NULL::text AS "scram_sha256_verifier?",
+                cred.allowed_recipient_domains,
+                cred.allowed_sender_addresses,
                 cred.description,
                 cred.username
             "#,
@@ -244,6 +603,10 @@ impl SmtpCredentialRepository {
             *credential_id,
             *project_id,
             *org_id,
+            update.allowed_recipient_domains.as_ref().and_then(Option::as_deref),
+            update.allowed_sender_addresses.as_ref().and_then(Option::as_deref),
+            update.allowed_recipient_domains.is_some(),
+            update.allowed_sender_addresses.is_some(),
         )
         .fetch_one(&mut *tx)
         .await?;
@@ -263,6 +626,70 @@ impl SmtpCredentialRepository {
         Ok(credential)
     }
 
+    /// Replace the password of a credential, keeping its id and username
+    ///
+    /// The new password is only returned once, the old password can no longer be used
+    pub async fn rotate(
+        &self,
+        org_id: OrganizationId,
+        project_id: ProjectId,
+        credential_id: SmtpCredentialId,
+        actor: impl Into<Actor>,
+    ) -> Result<SmtpCredentialResponse, Error> {
+        let password = Alphanumeric.sample_string(&mut rand::rng(), 20);
+        let password_hash = password_auth::generate_hash(password.as_bytes());
+        let cram_md5_secret = CramMd5Key::derive(&password).to_bytes();
+        let scram_sha256_verifier = ScramVerifier::generate(&password).to_string();
+
+        let mut tx = self.pool.begin().await?;
+        let rotated = sqlx::query_as!(
+            SmtpCredential,
+            r#"
+            UPDATE smtp_credentials cred
+            SET password_hash = $1,
+                -- a credential keeps supporting CRAM-MD5 if it has been provisioned for it
+                cram_md5_secret = CASE WHEN cred.cram_md5_secret IS NULL THEN NULL ELSE $2::bytea END,
+                scram_sha256_verifier = $3
+            FROM projects p
+            WHERE cred.id = $4
+              AND cred.project_id = p.id
+              AND cred.project_id = $5
+              AND p.organization_id = $6
+            RETURNING cred.*
+            "#,
+            password_hash,
+            cram_md5_secret,
+            scram_sha256_verifier,
+            *credential_id,
+            *project_id,
+            *org_id,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        self.audit_log
+            .log(
+                &mut tx,
+                actor,
+                (rotated.id, org_id),
+                "Rotated SMTP credential password",
+                Some(json!({ "project_id": project_id })),
+            )
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(SmtpCredentialResponse {
+            id: rotated.id,
+            description: rotated.description,
+            username: rotated.username,
+            cleartext_password: password,
+            project_id: rotated.project_id,
+            created_at: rotated.created_at,
+            updated_at: rotated.updated_at,
+        })
+    }
+
     pub async fn remove(
         &self,
         org_id: OrganizationId,
@@ -312,6 +739,7 @@ mod test {
         models::{AuditLogRepository, MessageRepository, SYSTEM},
         test::TestProjects,
     };
+    use ::hmac::{Hmac, KeyInit, Mac};
     use sqlx::PgPool;
 
     impl SmtpCredentialResponse {
@@ -320,11 +748,48 @@ mod test {
         }
     }
 
+    #[test]
+    fn cram_md5_key() {
+        // example from RFC 2195
+        let key = CramMd5Key::derive("tanstaaftanstaaf");
+        assert_eq!(
+            key.sign(b"<1896.697170952@postoffice.reston.mci.net>"),
+            [
+                0xb9, 0x13, 0xa6, 0x02, 0xc7, 0xed, 0xa7, 0xa4, 0x95, 0xb4, 0xe6, 0xe7, 0x33, 0x4d,
+                0x38, 0x90
+            ]
+        );
+
+        // the stored key does not contain the password
+        let stored = key.to_bytes();
+        assert_eq!(stored.len(), 32);
+        assert!(!stored.windows(8).any(|w| w == b"tanstaaf"));
+        assert_eq!(CramMd5Key::from_bytes(&stored), Some(key));
+        assert_eq!(CramMd5Key::from_bytes(b"tanstaaftanstaaf"), None);
+
+        // matches HMAC-MD5 for any length of password and message
+        for password in ["", "secret", "long password ".repeat(10).as_str()] {
+            for message in [
+                "",
+                "<challenge@localhost>",
+                "long message ".repeat(20).as_str(),
+            ] {
+                let mut mac = Hmac::<Md5>::new_from_slice(password.as_bytes()).unwrap();
+                mac.update(message.as_bytes());
+                assert_eq!(
+                    CramMd5Key::derive(password).sign(message.as_bytes()),
+                    mac.finalize().into_bytes().as_slice()
+                );
+            }
+        }
+    }
+
     #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "projects")))]
     async fn generate_happy_flow(pool: PgPool) {
         let credential_request = SmtpCredentialRequest {
             username: "test".to_string(),
             description: "Test SMTP credential description".to_string(),
+            cram_md5: false,
         };
         let credential_repo = SmtpCredentialRepository::new(pool.clone());
         let audit_log = AuditLogRepository::new(pool.clone());
@@ -354,6 +819,18 @@ mod test {
             .unwrap();
 
         assert!(get_credential.verify_password(credential.cleartext_password.as_str()));
+
+        // challenge-response authentication
+        assert!(!get_credential.verify_cram_md5("<challenge@localhost>", &[0; 16]));
+        let verifier = get_credential.scram_sha256().unwrap();
+        assert_eq!(
+            verifier,
+            ScramVerifier::derive(
+                &credential.cleartext_password,
+                verifier.salt(),
+                verifier.iterations()
+            )
+        );
     }
 
     #[sqlx::test(fixtures(
@@ -485,6 +962,8 @@ mod test {
                 credential_id,
                 &SmtpCredentialUpdateRequest {
                     description: "Updated description".to_string(),
+                    allowed_recipient_domains: Some(Some(vec!["example.com".to_string()])),
+                    allowed_sender_addresses: None,
                 },
                 SYSTEM,
             )
@@ -492,12 +971,116 @@ mod test {
             .unwrap();
         assert_eq!(credential_id, update.id);
         assert_eq!("Updated description", update.description);
+        assert_eq!(
+            update.allowed_recipient_domains,
+            Some(vec!["example.com".to_string()])
+        );
+
+        // restrictions that are omitted from the update are left unchanged
+        let update: SmtpCredentialUpdateRequest =
+            serde_json::from_value(json!({ "description": "Only the description" })).unwrap();
+        let unchanged = credential_repo
+            .update(org_id, project_id, credential_id, &update, SYSTEM)
+            .await
+            .unwrap();
+        assert_eq!("Only the description", unchanged.description);
+        assert_eq!(
+            unchanged.allowed_recipient_domains,
+            Some(vec!["example.com".to_string()])
+        );
+
+        // whereas `null` removes the restriction
+        let update: SmtpCredentialUpdateRequest = serde_json::from_value(
+            json!({ "description": "Unrestricted", "allowed_recipient_domains": null }),
+        )
+        .unwrap();
+        let cleared = credential_repo
+            .update(org_id, project_id, credential_id, &update, SYSTEM)
+            .await
+            .unwrap();
+        assert_eq!(cleared.allowed_recipient_domains, None);
+
         let audit_entries = audit_log.list(org_id).await.unwrap();
-        assert_eq!(audit_entries.len(), 1);
+        assert_eq!(audit_entries.len(), 3);
         assert_eq!(audit_entries[0].target_id, Some(*credential_id));
         assert_eq!(audit_entries[0].action, "Updated SMTP credential");
     }
 
+    #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "projects")))]
+    async fn rotate_happy_flow(db: PgPool) {
+        let credential_repo = SmtpCredentialRepository::new(db.clone());
+        let audit_log = AuditLogRepository::new(db.clone());
+
+        let (org_id, project_id) = TestProjects::Org1Project1.get_ids();
+        let credential = credential_repo
+            .generate(
+                org_id,
+                project_id,
+                &SmtpCredentialRequest {
+                    username: "test".to_string(),
+                    description: "Test SMTP credential description".to_string(),
+                    cram_md5: true,
+                },
+                SYSTEM,
+            )
+            .await
+            .unwrap();
+
+        let rotated = credential_repo
+            .rotate(org_id, project_id, credential.id(), SYSTEM)
+            .await
+            .unwrap();
+        assert_eq!(rotated.id(), credential.id());
+        assert_eq!(rotated.username, credential.username);
+        assert_ne!(rotated.cleartext_password, credential.cleartext_password);
+
+        let audit_entries = audit_log.list(org_id).await.unwrap();
+        assert_eq!(audit_entries[0].action, "Rotated SMTP credential password");
+
+        // only the new password can be used to authenticate
+        let found = credential_repo
+            .find_by_username(&credential.username)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(found.verify_password(&rotated.cleartext_password));
+        assert!(!found.verify_password(&credential.cleartext_password));
+
+        let verifier = found.scram_sha256().unwrap();
+        let derive = |password: &str| {
+            ScramVerifier::derive(password, verifier.salt(), verifier.iterations())
+        };
+        assert_eq!(verifier, derive(&rotated.cleartext_password));
+        assert_ne!(verifier, derive(&credential.cleartext_password));
+
+        let challenge = "<1896.697170952@localhost>";
+        let cram_md5 = |password: &str| {
+            let mut mac = Hmac::<Md5>::new_from_slice(password.as_bytes()).unwrap();
+            mac.update(challenge.as_bytes());
+            mac.finalize().into_bytes()
+        };
+        assert!(found.verify_cram_md5(challenge, &cram_md5(&rotated.cleartext_password)));
+        assert!(!found.verify_cram_md5(challenge, &cram_md5(&credential.cleartext_password)));
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "projects", "smtp_credentials")
+    ))]
+    async fn rotate_org_does_not_match_proj(db: PgPool) {
+        let credential_repo = SmtpCredentialRepository::new(db.clone());
+
+        let (_org_id, project_id) = TestProjects::Org1Project1.get_ids();
+        let org_id = TestProjects::Org2Project1.org_id();
+        let credential_id = "9442cbbf-9897-4af7-9766-4ac9c1bf49cf".parse().unwrap();
+
+        let not_found = credential_repo
+            .rotate(org_id, project_id, credential_id, SYSTEM)
+            .await
+            .unwrap_err();
+        assert!(matches!(not_found, Error::NotFound(_)));
+    }
+
     #[sqlx::test(fixtures(
         path = "../fixtures",
         scripts("organizations", "projects", "smtp_credentials")
@@ -516,6 +1099,8 @@ mod test {
                 credential_id,
                 &SmtpCredentialUpdateRequest {
                     description: "Should not work".to_string(),
+                    allowed_recipient_domains: None,
+                    allowed_sender_addresses: None,
                 },
                 SYSTEM,
             )
@@ -542,6 +1127,8 @@ mod test {
                 credential_id,
                 &SmtpCredentialUpdateRequest {
                     description: "Should not work".to_string(),
+                    allowed_recipient_domains: None,
+                    allowed_sender_addresses: None,
                 },
                 SYSTEM,
             )
@@ -1,8 +1,9 @@
 use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
-use serde::Serialize;
+use garde::Validate;
+use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use tracing::debug;
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 
 use crate::models::{Error, OrganizationId, ProjectId};
 
@@ -31,6 +32,27 @@ impl Statistics {
     }
 }
 
+/// Delivery outcomes of all recipients within a single recipient domain
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[cfg_attr(test, derive(PartialEq, serde::Deserialize))]
+pub struct DomainStatistics {
+    pub domain: String,
+    pub delivered: i64,
+    pub deferred: i64,
+    pub failed: i64,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams, Validate)]
+#[serde(default)]
+pub struct DomainStatisticsFilter {
+    /// Only include messages created at or after this moment, defaults to 30 days before `to`
+    #[garde(skip)]
+    pub from: Option<DateTime<Utc>>,
+    /// Only include messages created before this moment, defaults to now
+    #[garde(skip)]
+    pub to: Option<DateTime<Utc>>,
+}
+
 #[derive(Debug, Clone)]
 pub struct StatisticsRepository {
     pool: PgPool,
@@ -48,6 +70,51 @@ impl StatisticsRepository {
         })
     }
 
+    /// Gets the delivery outcomes per recipient domain of the messages of a project that were
+    /// created in the time range `[from, to)`.
+    ///
+    /// Recipients that have been delivered count as delivered, recipients that still have to be
+    /// (re)attempted count as deferred, and failed or suppressed recipients count as failed.
+    pub async fn get_domain_stats(
+        &self,
+        organization_id: OrganizationId,
+        project_id: ProjectId,
+        filter: &DomainStatisticsFilter,
+    ) -> Result<Vec<DomainStatistics>, Error> {
+        let to = filter.to.unwrap_or_else(Utc::now);
+        let from = filter.from.unwrap_or(to - Duration::days(30));
+
+        Ok(sqlx::query_as!(
+            DomainStatistics,
+            r#"
+            SELECT lower(substring(recipient.key FROM '@([^@]+)$')) AS "domain!",
+                COUNT(*) FILTER (
+                    WHERE recipient.value -> 'status' ->> 'type' = 'Success'
+                ) AS "delivered!",
+                COUNT(*) FILTER (
+                    WHERE recipient.value -> 'status' ->> 'type' IN ('None', 'Reattempt')
+                ) AS "deferred!",
+                COUNT(*) FILTER (
+                    WHERE recipient.value -> 'status' ->> 'type' IN ('Failed', 'Suppressed')
+                ) AS "failed!"
+            FROM messages m
+                CROSS JOIN LATERAL jsonb_each(m.delivery_details) recipient
+            WHERE m.organization_id = $1
+                AND m.project_id = $2
+                AND m.created_at >= $3
+                AND m.created_at < $4
+            GROUP BY 1
+            ORDER BY 1
+            "#,
+            *organization_id,
+            *project_id,
+            from,
+            to
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
     /// Gets daily stats for the past 30 days
     async fn get_daily_stats(
         &self,
@@ -246,4 +313,66 @@ mod test {
 
         assert_eq!(stats, new_stats);
     }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "smtp_credentials",
+            "delivery_details"
+        )
+    ))]
+    async fn test_get_domain_statistics(pool: PgPool) {
+        let repo = StatisticsRepository::new(pool);
+        let (org_1, proj_1) = TestProjects::Org1Project1.get_ids();
+
+        let stats = repo
+            .get_domain_stats(org_1, proj_1, &DomainStatisticsFilter::default())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            stats,
+            vec![
+                DomainStatistics {
+                    domain: "gmail.com".to_string(),
+                    delivered: 2,
+                    deferred: 0,
+                    failed: 1,
+                },
+                DomainStatistics {
+                    domain: "outlook.com".to_string(),
+                    delivered: 1,
+                    deferred: 1,
+                    failed: 1,
+                },
+            ]
+        );
+
+        // the message of 40 days ago is only included when explicitly requested
+        let stats = repo
+            .get_domain_stats(
+                org_1,
+                proj_1,
+                &DomainStatisticsFilter {
+                    from: Some(Utc::now() - Duration::days(50)),
+                    to: Some(Utc::now() - Duration::days(30)),
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            stats,
+            vec![DomainStatistics {
+                domain: "gmail.com".to_string(),
+                delivered: 1,
+                deferred: 0,
+                failed: 0,
+            }]
+        );
+    }
 }
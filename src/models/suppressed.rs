@@ -22,16 +22,18 @@ impl SuppressedRepository {
         Self { pool }
     }
 
-    /// Report a delivery failure for an email address within an organization
+    /// Number of consecutive hard bounces after which an email address is suppressed
+    pub const MAX_ATTEMPTS: i32 = 5;
+
+    /// Report a hard bounce for an email address within an organization
     ///
-    /// When there are enough consecutive delivery failures, it will run out of attempts and suppress further emails.
-    /// Suppressed emails are still tried once per day to check if they now work.
+    /// When there are enough consecutive hard bounces, it will run out of attempts and suppress further emails.
+    /// Suppressed emails are tried again after 30 days to check if they now work.
     pub async fn report_failure(
         &self,
         email: &EmailAddress,
         org: OrganizationId,
     ) -> Result<(), Error> {
-        const MAX_ATTEMPTS: i32 = 5;
         let retry_after = Utc::now() + Duration::days(30);
 
         sqlx::query!(
@@ -46,7 +48,7 @@ impl SuppressedRepository {
             email.as_str(),
             *org,
             retry_after,
-            MAX_ATTEMPTS - 1
+            Self::MAX_ATTEMPTS - 1
         )
         .execute(&self.pool)
         .await?;
@@ -184,7 +186,7 @@ mod tests {
 
     #[sqlx::test(fixtures(path = "../fixtures", scripts("organizations", "api_users")))]
     async fn test_suppression_system(pool: PgPool) {
-        const MAX_ATTEMPTS: i32 = 5;
+        const MAX_ATTEMPTS: i32 = SuppressedRepository::MAX_ATTEMPTS;
         let repo = SuppressedRepository::new(pool);
         let org_1 = "44729d9f-a7dc-4226-b412-36a7537f5176".parse().unwrap();
         let org_2 = "5d55aec5-136a-407c-952f-5348d4398204".parse().unwrap();
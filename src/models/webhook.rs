@@ -0,0 +1,351 @@
+use chrono::{DateTime, Utc};
+use garde::Validate;
+use rand::distr::{Alphanumeric, SampleString};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+use std::net::IpAddr;
+use url::{Host, Url};
+use utoipa::ToSchema;
+
+use crate::models::{Actor, AuditLogRepository, Error, OrganizationId};
+
+/// The endpoint delivery events of an organization are posted to
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Webhook {
+    organization_id: OrganizationId,
+    url: String,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Validate)]
+pub struct WebhookRequest {
+    #[schema(max_length = 2048)]
+    #[garde(length(min = 1, max = 2048))]
+    pub url: String,
+}
+
+/// The secrets webhook requests are signed with
+///
+/// Requests are always signed with the `current` secret.
+/// After a rotation, the `previous` secret remains available, such that receivers can accept
+/// signatures made with either secret until they have switched to the new one.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[cfg_attr(test, derive(Deserialize))]
+pub struct WebhookSecrets {
+    pub current: String,
+    pub previous: Option<String>,
+    pub rotated_at: Option<DateTime<Utc>>,
+}
+
+/// Whether webhook requests may be sent to `ip`
+///
+/// Loopback, private, link-local, and other special-purpose addresses are excluded, such that
+/// webhooks can't reach services within our own network, like cloud metadata endpoints.
+pub fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // "this network"
+                || a == 0
+                // shared address space for carrier-grade NAT
+                || (a == 100 && (64..128).contains(&b))
+                // IETF protocol assignments
+                || (a == 192 && b == 0 && c == 0)
+                // benchmarking
+                || (a == 198 && (18..20).contains(&b))
+                // reserved
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ipv4) = ip.to_ipv4_mapped() {
+                return is_public_address(ipv4.into());
+            }
+            let [first, second, ..] = ip.segments();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local()
+                // NAT64, which may translate to any IPv4 address
+                || (first == 0x64 && second == 0xff9b)
+                // documentation
+                || (first == 0x2001 && second == 0xdb8))
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookRepository {
+    pool: PgPool,
+    audit_log: AuditLogRepository,
+}
+
+impl WebhookRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            audit_log: AuditLogRepository::new(pool.clone()),
+            pool,
+        }
+    }
+
+    fn new_secret() -> String {
+        Alphanumeric.sample_string(&mut rand::rng(), 40)
+    }
+
+    fn validate_url(url: &str) -> Result<(), Error> {
+        let url = Url::parse(url).map_err(|e| Error::BadRequest(format!("invalid URL: {e}")))?;
+
+        // plain HTTP is only accepted for local development
+        let allowed = if cfg!(debug_assertions) {
+            matches!(url.scheme(), "https" | "http")
+        } else {
+            url.scheme() == "https"
+        };
+        if !allowed || url.host().is_none() {
+            return Err(Error::BadRequest(
+                "webhook URL must be an https URL".to_string(),
+            ));
+        }
+
+        // host names are checked again once they are resolved, right before sending
+        let internal = match url.host() {
+            Some(Host::Ipv4(ip)) => !is_public_address(ip.into()),
+            Some(Host::Ipv6(ip)) => !is_public_address(ip.into()),
+            Some(Host::Domain(domain)) => {
+                let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+                domain == "localhost" || domain.ends_with(".localhost")
+            }
+            None => true,
+        };
+        if internal {
+            return Err(Error::BadRequest(
+                "webhook URL must not point to an internal address".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub async fn get(&self, org_id: OrganizationId) -> Result<Option<Webhook>, Error> {
+        Ok(sqlx::query_as!(
+            Webhook,
+            r#"
+            SELECT organization_id, url, created_at, updated_at
+            FROM webhooks
+            WHERE organization_id = $1
+            "#,
+            *org_id
+        )
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    /// Set the webhook URL of an organization
+    ///
+    /// A signing secret is generated when the organization did not have a webhook yet,
+    /// changing the URL of an existing webhook keeps its secrets.
+    pub async fn set(
+        &self,
+        org_id: OrganizationId,
+        request: &WebhookRequest,
+        actor: impl Into<Actor>,
+    ) -> Result<Webhook, Error> {
+        Self::validate_url(&request.url)?;
+
+        let mut tx = self.pool.begin().await?;
+        let webhook = sqlx::query_as!(
+            Webhook,
+            r#"
+            INSERT INTO webhooks (organization_id, url, signing_secret)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (organization_id) DO UPDATE
+            SET url = EXCLUDED.url,
+                updated_at = now()
+            RETURNING organization_id, url, created_at, updated_at
+            "#,
+            *org_id,
+            request.url,
+            Self::new_secret(),
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        self.audit_log
+            .log(
+                &mut tx,
+                actor,
+                org_id,
+                "Updated webhook",
+                Some(json!(request)),
+            )
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(webhook)
+    }
+
+    pub async fn remove(
+        &self,
+        org_id: OrganizationId,
+        actor: impl Into<Actor>,
+    ) -> Result<(), Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(
+            r#"
+            DELETE FROM webhooks
+            WHERE organization_id = $1
+            RETURNING organization_id
+            "#,
+            *org_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        self.audit_log
+            .log(&mut tx, actor, org_id, "Removed webhook", None)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// The URL and secrets used to deliver events to the webhook of an organization, if any
+    pub async fn delivery_target(
+        &self,
+        org_id: OrganizationId,
+    ) -> Result<Option<(String, WebhookSecrets)>, Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT url, signing_secret, previous_signing_secret, secret_rotated_at
+            FROM webhooks
+            WHERE organization_id = $1
+            "#,
+            *org_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| {
+            (
+                row.url,
+                WebhookSecrets {
+                    current: row.signing_secret,
+                    previous: row.previous_signing_secret,
+                    rotated_at: row.secret_rotated_at,
+                },
+            )
+        }))
+    }
+
+    pub async fn secrets(&self, org_id: OrganizationId) -> Result<WebhookSecrets, Error> {
+        Ok(sqlx::query_as!(
+            WebhookSecrets,
+            r#"
+            SELECT signing_secret AS current,
+                   previous_signing_secret AS previous,
+                   secret_rotated_at AS rotated_at
+            FROM webhooks
+            WHERE organization_id = $1
+            "#,
+            *org_id
+        )
+        .fetch_one(&self.pool)
+        .await?)
+    }
+
+    /// Replace the signing secret by a new one, keeping the replaced secret as the previous secret
+    ///
+    /// Any secret that was kept from an earlier rotation is no longer valid afterward.
+    pub async fn rotate_secret(
+        &self,
+        org_id: OrganizationId,
+        actor: impl Into<Actor>,
+    ) -> Result<WebhookSecrets, Error> {
+        let mut tx = self.pool.begin().await?;
+        let secrets = sqlx::query_as!(
+            WebhookSecrets,
+            r#"
+            UPDATE webhooks
+            SET previous_signing_secret = signing_secret,
+                signing_secret = $2,
+                secret_rotated_at = now(),
+                updated_at = now()
+            WHERE organization_id = $1
+            RETURNING signing_secret AS current,
+                      previous_signing_secret AS previous,
+                      secret_rotated_at AS rotated_at
+            "#,
+            *org_id,
+            Self::new_secret(),
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        self.audit_log
+            .log(
+                &mut tx,
+                actor,
+                org_id,
+                "Rotated webhook signing secret",
+                None,
+            )
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(secrets)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn internal_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fc00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+        ] {
+            assert!(!is_public_address(ip.parse().unwrap()), "{ip}");
+        }
+
+        for ip in ["93.184.215.14", "2606:2800:21f:cb07:6820:80da:af6b:8b2c"] {
+            assert!(is_public_address(ip.parse().unwrap()), "{ip}");
+        }
+
+        for url in [
+            "https://169.254.169.254/latest/meta-data/",
+            "https://10.0.0.1/webhook",
+            "https://[::1]/webhook",
+            "https://[::ffff:a9fe:a9fe]/webhook",
+            "https://localhost/webhook",
+            "https://api.localhost./webhook",
+        ] {
+            assert!(WebhookRepository::validate_url(url).is_err(), "{url}");
+        }
+        assert!(WebhookRepository::validate_url("https://example.com/webhook").is_ok());
+    }
+}
@@ -1,9 +1,9 @@
 use crate::moneybird::{
     Error, MoneybirdApi, Webhook,
     model::{
-        AdministrationId, Contact, MoneybirdContactId, ProductIdentifier, RecurringSalesInvoiceId,
-        Subscription, SubscriptionId, SubscriptionStatus, SubscriptionTemplate,
-        SubscriptionTemplateId, WebhookId,
+        AdministrationId, Contact, Invoice, InvoiceStatus, MoneybirdContactId, ProductIdentifier,
+        RecurringSalesInvoiceId, Subscription, SubscriptionId, SubscriptionStatus,
+        SubscriptionTemplate, SubscriptionTemplateId, WebhookId,
     },
 };
 use async_trait::async_trait;
@@ -24,12 +24,33 @@ pub fn mock_subscription<T>(product: ProductIdentifier, end_date: T) -> Subscrip
     }
 }
 
+pub fn mock_invoices() -> Vec<Invoice> {
+    vec![
+        Invoice {
+            invoice_number: "2025-0002".to_string(),
+            date: NaiveDate::from_ymd_opt(2025, 12, 23).unwrap(),
+            amount: "12.10".to_string(),
+            currency: "EUR".to_string(),
+            status: InvoiceStatus::Open,
+            url: "https://tweedegolf.com/invoices/2025-0002".parse().unwrap(),
+        },
+        Invoice {
+            invoice_number: "2025-0001".to_string(),
+            date: NaiveDate::from_ymd_opt(2025, 11, 23).unwrap(),
+            amount: "12.10".to_string(),
+            currency: "EUR".to_string(),
+            status: InvoiceStatus::Paid,
+            url: "https://tweedegolf.com/invoices/2025-0001".parse().unwrap(),
+        },
+    ]
+}
+
 pub(super) struct MockMoneybirdApi {}
 
 #[async_trait]
 impl MoneybirdApi for MockMoneybirdApi {
-    async fn register_webhook(&self) -> Option<Webhook> {
-        Some(Webhook {
+    async fn register_webhook(&self) -> Result<Webhook, Error> {
+        Ok(Webhook {
             id: WebhookId("mock_webhook_id".to_string()),
             administration_id: AdministrationId("mock administration".to_string()),
             url: "https://dump.tweede.golf/dump/moneybird".parse().unwrap(),
@@ -89,6 +110,10 @@ impl MoneybirdApi for MockMoneybirdApi {
         Ok("https://tweedegolf.com".parse()?)
     }
 
+    async fn list_invoices(&self, _contact_id: &MoneybirdContactId) -> Result<Vec<Invoice>, Error> {
+        Ok(mock_invoices())
+    }
+
     async fn customer_contact_portal(
         &self,
         _moneybird_contact_id: MoneybirdContactId,
@@ -6,25 +6,37 @@ pub use model::*;
 
 use crate::{
     Environment,
-    models::OrganizationId,
+    models::{OrganizationId, QuotaResetSchedule},
     moneybird::{mock::MockMoneybirdApi, production_api::ProductionMoneybirdApi},
 };
 use async_trait::async_trait;
-use chrono::{DateTime, Days, NaiveDate, Utc};
-#[cfg(not(test))]
-use rand::RngExt;
+use chrono::{DateTime, Days, NaiveDate, NaiveTime, TimeDelta, Utc};
+use chrono_tz::Tz;
 use sqlx::PgPool;
-#[cfg(not(test))]
-use std::time::Duration;
-use std::{cmp::Ordering, env, sync::Arc};
+use std::{cmp::Ordering, env, sync::Arc, time::Duration};
 use tracing::{debug, error, info, trace, warn};
 use url::Url;
 
 #[cfg(test)]
-pub use mock::mock_subscription;
+pub use mock::{mock_invoices, mock_subscription};
 
 const MONEYBIRD_API_URL: &str = "https://moneybird.com/api/v2";
 
+/// Postgres advisory lock held by the instance that registers the Moneybird webhook
+const WEBHOOK_REGISTRATION_LOCK: i64 = 0x6d6f_6e65_7962_6972;
+/// Number of attempts to register the webhook when Moneybird returns a transient error
+const WEBHOOK_REGISTRATION_ATTEMPTS: u32 = 5;
+/// Time to wait before trying again if the webhook could not be registered
+const WEBHOOK_REGISTRATION_RETRY_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug, PartialEq)]
+enum WebhookRegistration {
+    Registered,
+    AlreadyRegistered,
+    /// Another instance holds the registration lock
+    InProgress,
+}
+
 impl PartialOrd for SubscriptionStatus {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         match self {
@@ -126,15 +138,92 @@ where
     }
 }
 
+/// The moment of the day at which message quotas reset
+///
+/// The subscription determines the date of the reset, this configures the time of that day.
+/// Organizations can override either part with a [`QuotaResetSchedule`], the global default
+/// applies to the other.
+#[derive(Debug, Clone, PartialEq)]
+struct QuotaResetTime {
+    time: NaiveTime,
+    timezone: Tz,
+}
+
+impl Default for QuotaResetTime {
+    fn default() -> Self {
+        Self {
+            time: NaiveTime::from_hms_opt(23, 59, 59).unwrap(),
+            timezone: Tz::UTC,
+        }
+    }
+}
+
+impl QuotaResetTime {
+    fn from_env() -> Self {
+        let default = Self::default();
+
+        let time = env::var("QUOTA_RESET_TIME")
+            .map(|time| {
+                NaiveTime::parse_from_str(&time, "%H:%M:%S")
+                    .or_else(|_| NaiveTime::parse_from_str(&time, "%H:%M"))
+                    .expect("QUOTA_RESET_TIME must be a time of day formatted as HH:MM[:SS]")
+            })
+            .unwrap_or(default.time);
+
+        let timezone = env::var("QUOTA_RESET_TIMEZONE")
+            .map(|timezone| {
+                timezone
+                    .parse()
+                    .expect("QUOTA_RESET_TIMEZONE must be an IANA timezone, e.g., Europe/Amsterdam")
+            })
+            .unwrap_or(default.timezone);
+
+        Self { time, timezone }
+    }
+
+    fn with_schedule(&self, schedule: QuotaResetSchedule) -> Result<Self, Error> {
+        let timezone = match schedule.timezone {
+            Some(timezone) => timezone.parse().map_err(|_| {
+                Error::Moneybird(format!("Invalid quota reset timezone: {timezone}"))
+            })?,
+            None => self.timezone,
+        };
+
+        Ok(Self {
+            time: schedule.time.unwrap_or(self.time),
+            timezone,
+        })
+    }
+
+    /// The moment the quota resets on `date`
+    ///
+    /// If the time of day does not exist on that date due to a DST transition, the quota resets
+    /// an hour later. If it occurs twice, the quota resets on the first occurrence.
+    fn on(&self, date: NaiveDate) -> Option<DateTime<Utc>> {
+        let local = date.and_time(self.time);
+
+        local
+            .and_local_timezone(self.timezone)
+            .earliest()
+            .or_else(|| {
+                (local + TimeDelta::hours(1))
+                    .and_local_timezone(self.timezone)
+                    .earliest()
+            })
+            .map(|datetime| datetime.to_utc())
+    }
+}
+
 #[derive(Clone)]
 pub struct MoneyBird {
     api: Arc<dyn MoneybirdApi + Send + Sync>,
     pool: PgPool,
+    quota_reset_time: QuotaResetTime,
 }
 
 #[async_trait]
 trait MoneybirdApi {
-    async fn register_webhook(&self) -> Option<Webhook>;
+    async fn register_webhook(&self) -> Result<Webhook, Error>;
     async fn next_invoice_date(
         &self,
         recurring_sales_invoice_id: &RecurringSalesInvoiceId,
@@ -149,6 +238,8 @@ trait MoneybirdApi {
         &self,
         moneybird_contact_id: MoneybirdContactId,
     ) -> Result<Url, Error>;
+    /// Lists the invoices sent to a contact, newest first
+    async fn list_invoices(&self, contact_id: &MoneybirdContactId) -> Result<Vec<Invoice>, Error>;
     /// Allows the user to manage their subscription in Moneybird.
     ///
     /// The created link is only valid for one hour. See also
@@ -216,80 +307,118 @@ impl MoneyBird {
             }
         };
 
-        let res = Self { api, pool };
+        let res = Self {
+            api,
+            pool,
+            quota_reset_time: QuotaResetTime::from_env(),
+        };
 
         Ok(res)
     }
 
     /// Asynchronously register a webhook at moneybird.
     /// This function will immediately return and register the webhook in the background,
-    /// logging possible errors.
+    /// retrying until the webhook is registered by this or another instance (Pod).
     pub(crate) fn register_webhook(&self) {
         let self_clone = self.clone();
         tokio::spawn(async move {
-            #[cfg(not(test))]
-            {
-                // Cannot inline this "random_delay", see: https://stackoverflow.com/a/75227719
-                let random_delay = {
-                    let mut rng = rand::rng();
-                    rng.random_range(0..10)
-                };
-
-                // If multiple instances (Pods) start at the same time,
-                // try to avoid race conditions by introducing a random delay
-                // so that only one will register the webhook
-                tokio::time::sleep(Duration::from_secs(random_delay)).await;
+            loop {
+                match self_clone.try_register_webhook().await {
+                    Ok(
+                        WebhookRegistration::Registered | WebhookRegistration::AlreadyRegistered,
+                    ) => {
+                        return;
+                    }
+                    Ok(WebhookRegistration::InProgress) => {
+                        debug!("Moneybird webhook is being registered by another instance");
+                    }
+                    Err(err) => {
+                        error!(
+                            "Error registering Moneybird webhook, retrying in {} minutes: {err}",
+                            WEBHOOK_REGISTRATION_RETRY_INTERVAL.as_secs() / 60
+                        );
+                    }
+                }
+
+                tokio::time::sleep(WEBHOOK_REGISTRATION_RETRY_INTERVAL).await;
             }
+        });
+    }
 
-            match sqlx::query_scalar!(
-                r#"
-                SELECT true AS "exists!" FROM moneybird_webhook
-                "#
-            )
-            .fetch_optional(&self_clone.pool)
-            .await
-            {
-                Ok(Some(true)) => {
-                    info!("Moneybird webhook already registered");
-                    return;
-                }
-                Err(err) => {
+    /// Registers the webhook unless it is already registered.
+    ///
+    /// If multiple instances (Pods) start at the same time, only the instance that obtains the
+    /// advisory lock registers the webhook. The lock is released when the transaction ends.
+    async fn try_register_webhook(&self) -> Result<WebhookRegistration, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let locked = sqlx::query_scalar!(
+            r#"
+            SELECT pg_try_advisory_xact_lock($1) AS "locked!"
+            "#,
+            WEBHOOK_REGISTRATION_LOCK
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if !locked {
+            return Ok(WebhookRegistration::InProgress);
+        }
+
+        let exists = sqlx::query_scalar!(
+            r#"
+            SELECT true AS "exists!" FROM moneybird_webhook
+            "#
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .is_some();
+
+        if exists {
+            info!("Moneybird webhook already registered");
+            return Ok(WebhookRegistration::AlreadyRegistered);
+        }
+
+        info!("registering Moneybird webhook");
+
+        let mut attempt = 1;
+        let webhook = loop {
+            match self.api.register_webhook().await {
+                Ok(webhook) => break webhook,
+                Err(err) if err.is_transient() && attempt < WEBHOOK_REGISTRATION_ATTEMPTS => {
+                    let backoff = Duration::from_secs(2u64.pow(attempt));
                     warn!(
-                        "Error checking if moneybird webhook is already registered: {}",
-                        err
+                        attempt,
+                        "Transient error registering Moneybird webhook, retrying in {}s: {err}",
+                        backoff.as_secs()
                     );
-                    return;
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
                 }
-                _ => {}
-            };
+                Err(err) => return Err(err),
+            }
+        };
 
-            info!("registering Moneybird webhook");
+        sqlx::query!(
+            r#"
+            INSERT INTO moneybird_webhook (moneybird_id, token_hash) VALUES ($1, $2)
+            "#,
+            *webhook.id,
+            webhook.token.generate_hash()
+        )
+        .execute(&mut *tx)
+        .await?;
 
-            let Some(webhook) = self_clone.api.register_webhook().await else {
-                return;
-            };
+        tx.commit().await?;
 
-            if let Err(err) = sqlx::query!(
-                r#"
-                INSERT INTO moneybird_webhook (moneybird_id, token_hash) VALUES ($1, $2)
-                "#,
-                *webhook.id,
-                webhook.token.generate_hash()
-            )
-            .execute(&self_clone.pool)
-            .await
-            {
-                error!("Error storing Moneybird webhook in database: {}", err);
-                return;
-            };
+        info!(
+            administration_id = webhook.administration_id.as_str(),
+            webhook_id = webhook.id.as_str(),
+            url = webhook.url.as_str(),
+            "Moneybird webhook registered"
+        );
 
-            info!(
-                administration_id = webhook.administration_id.as_str(),
-                webhook_id = webhook.id.as_str(),
-                url = webhook.url.as_str(),
-                "Moneybird webhook registered"
-            );
-        });
+        Ok(WebhookRegistration::Registered)
     }
 
     async fn authorize_webhook_call(
@@ -422,39 +551,77 @@ impl MoneyBird {
 
         let subscription_status: SubscriptionStatus = [&subscription].into();
 
-        self.store_subscription_status(&subscription_status, &organization_id.into())
-            .await
+        // Skip deliveries older than the last processed one, e.g., because Moneybird retried
+        // them. Redeliveries of the last processed event are applied again.
+        let applied = self
+            .store_subscription_status(
+                &subscription_status,
+                &organization_id.into(),
+                subscription.updated_at,
+            )
+            .await?;
+
+        if !applied {
+            info!(
+                webhook_id = webhook_id.as_str(),
+                subscription_id = subscription.id.as_str(),
+                organization_id = organization_id.to_string(),
+                updated_at = ?subscription.updated_at,
+                "Skipping outdated subscription webhook"
+            );
+        }
+
+        Ok(())
     }
 
+    /// Store the subscription status of an organization
+    ///
+    /// If `updated_at` is given, the status is only stored if it is at least as new as the last
+    /// stored status, in a single update such that concurrent deliveries can't interleave.
+    /// Returns whether the status was stored.
     async fn store_subscription_status(
         &self,
         subscription_status: &SubscriptionStatus,
         organization_id: &OrganizationId,
-    ) -> Result<(), Error> {
+        updated_at: Option<DateTime<Utc>>,
+    ) -> Result<bool, Error> {
         let quota_reset = self
-            .calculate_quota_reset_datetime(subscription_status)
+            .calculate_quota_reset_datetime(organization_id, subscription_status)
             .await?;
 
         let product = subscription_status.active_product();
 
-        self.make_user_admin_on_first_subscription(subscription_status, organization_id)
-            .await?;
-
-        sqlx::query!(
+        let Some(old_subscription_json) = sqlx::query_scalar!(
             r#"
+            WITH old AS (
+                SELECT current_subscription
+                FROM organizations
+                WHERE id = $1
+                FOR UPDATE
+            )
             UPDATE organizations
             SET total_message_quota = $2,
                 quota_reset = $3,
-                current_subscription = $4
+                current_subscription = $4,
+                subscription_updated_at = COALESCE($5, subscription_updated_at)
+            FROM old
             WHERE id = $1
+              AND ($5::timestamptz IS NULL
+                   OR subscription_updated_at IS NULL
+                   OR subscription_updated_at <= $5)
+            RETURNING old.current_subscription
             "#,
             **organization_id,
             product.monthly_quota() as i64,
             quota_reset,
-            serde_json::to_value(&subscription_status)?
+            serde_json::to_value(subscription_status)?,
+            updated_at,
         )
-        .execute(&self.pool)
-        .await?;
+        .fetch_optional(&self.pool)
+        .await?
+        else {
+            return Ok(false);
+        };
 
         debug!(
             organization_id = organization_id.to_string(),
@@ -464,6 +631,15 @@ impl MoneyBird {
             "Updated subscription information in database"
         );
 
+        let old_subscription_status: SubscriptionStatus =
+            serde_json::from_value(old_subscription_json)?;
+        self.make_user_admin_on_first_subscription(
+            &old_subscription_status,
+            subscription_status,
+            organization_id,
+        )
+        .await?;
+
         // Lower the project retention based on active subscription
         //
         // We don't lower expired subscriptions because they might resubscribe and we don't want
@@ -473,7 +649,7 @@ impl MoneyBird {
                 .await?;
         }
 
-        Ok(())
+        Ok(true)
     }
 
     async fn enforce_retention_limits(
@@ -518,25 +694,12 @@ impl MoneyBird {
     /// This function elevates the privileges from read-only to admin for this initial user when the organization subscribed for the first time.
     async fn make_user_admin_on_first_subscription(
         &self,
+        old_subscription_status: &SubscriptionStatus,
         new_subscription_status: &SubscriptionStatus,
         organization_id: &OrganizationId,
     ) -> Result<(), Error> {
-        let old_subscription_json = sqlx::query_scalar!(
-            r#"
-            SELECT current_subscription AS old_subscription_json
-            FROM organizations
-            WHERE id = $1
-            "#,
-            **organization_id,
-        )
-        .fetch_one(&self.pool)
-        .await?;
-
-        let old_subscription_status: SubscriptionStatus =
-            serde_json::from_value(old_subscription_json)?;
-
         if !matches!(
-            (&old_subscription_status, &new_subscription_status),
+            (old_subscription_status, new_subscription_status),
             (&SubscriptionStatus::None, &SubscriptionStatus::Active(_))
         ) {
             trace!(
@@ -593,6 +756,7 @@ impl MoneyBird {
 
     async fn calculate_quota_reset_datetime(
         &self,
+        organization_id: &OrganizationId,
         subscription: &SubscriptionStatus,
     ) -> Result<Option<DateTime<Utc>>, Error> {
         let date = match subscription {
@@ -625,16 +789,63 @@ impl MoneyBird {
                 None
             }
         };
-        Ok(match date {
-            None => None,
-            Some(date) => Some(
-                date.and_hms_opt(23, 59, 59)
-                    .ok_or(Error::Moneybird(
-                        "Could not add time to subscription end".to_string(),
-                    ))?
-                    .and_utc(),
-            ),
-        })
+        let Some(date) = date else {
+            return Ok(None);
+        };
+
+        let schedule = sqlx::query_as!(
+            QuotaResetSchedule,
+            r#"
+            SELECT quota_reset_time AS time, quota_reset_timezone AS timezone
+            FROM organizations
+            WHERE id = $1
+            "#,
+            **organization_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(Some(
+            self.quota_reset_time
+                .with_schedule(schedule)?
+                .on(date)
+                .ok_or(Error::Moneybird(
+                    "Could not add time to subscription end".to_string(),
+                ))?,
+        ))
+    }
+
+    /// Recalculate the upcoming quota reset of the organization from its stored subscription,
+    /// e.g., after its [`QuotaResetSchedule`] changed
+    pub async fn refresh_quota_reset(
+        &self,
+        organization_id: OrganizationId,
+    ) -> Result<Option<DateTime<Utc>>, Error> {
+        let subscription_json = sqlx::query_scalar!(
+            r#"
+            SELECT current_subscription FROM organizations WHERE id = $1
+            "#,
+            *organization_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        let subscription_status: SubscriptionStatus = serde_json::from_value(subscription_json)?;
+
+        let quota_reset = self
+            .calculate_quota_reset_datetime(&organization_id, &subscription_status)
+            .await?;
+
+        sqlx::query!(
+            r#"
+            UPDATE organizations SET quota_reset = $2 WHERE id = $1
+            "#,
+            *organization_id,
+            quota_reset,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(quota_reset)
     }
 
     pub async fn reset_all_quotas(&self) -> Result<(), Error> {
@@ -678,29 +889,54 @@ impl MoneyBird {
         };
 
         let reset_date = self
-            .calculate_quota_reset_datetime(&subscription_status)
+            .calculate_quota_reset_datetime(&organization_id, &subscription_status)
             .await?;
 
-        self.store_subscription_status(&subscription_status, &organization_id)
+        self.store_subscription_status(&subscription_status, &organization_id, None)
             .await?;
 
         let quota = subscription_status.active_product().monthly_quota();
 
+        let mut tx = self.pool.begin().await?;
+
+        // keep track of the usage of the period that ends now
+        sqlx::query!(
+            r#"
+            INSERT INTO usage_snapshots (organization_id, period_end, used_message_quota, total_message_quota)
+            SELECT id, quota_reset, used_message_quota, total_message_quota
+            FROM organizations
+            WHERE id = $1
+            "#,
+            *organization_id,
+        )
+        .execute(&mut *tx)
+        .await?;
+
         sqlx::query!(
             r#"
             UPDATE organizations
             SET quota_reset = $2,
                 total_message_quota = $3,
-                used_message_quota = 0
+                used_message_quota = 0,
+                quota_alert_threshold = 0
             WHERE id = $1
             "#,
             *organization_id,
             reset_date,
             quota as i64
         )
-        .execute(&self.pool)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "UPDATE projects SET used_message_quota = 0 WHERE organization_id = $1",
+            *organization_id,
+        )
+        .execute(&mut *tx)
         .await?;
 
+        tx.commit().await?;
+
         Ok(())
     }
 
@@ -734,8 +970,11 @@ impl MoneyBird {
         Ok(contact)
     }
 
-    async fn get_contact_id(&self, org_id: OrganizationId) -> Result<MoneybirdContactId, Error> {
-        let contact_id: Option<MoneybirdContactId> = sqlx::query_scalar!(
+    async fn existing_contact_id(
+        &self,
+        org_id: OrganizationId,
+    ) -> Result<Option<MoneybirdContactId>, Error> {
+        Ok(sqlx::query_scalar!(
             r#"
             SELECT moneybird_contact_id FROM organizations WHERE id = $1
             "#,
@@ -743,9 +982,11 @@ impl MoneyBird {
         )
         .fetch_one(&self.pool)
         .await?
-        .map(|id| id.into());
+        .map(|id| id.into()))
+    }
 
-        if let Some(contact_id) = contact_id {
+    async fn get_contact_id(&self, org_id: OrganizationId) -> Result<MoneybirdContactId, Error> {
+        if let Some(contact_id) = self.existing_contact_id(org_id).await? {
             trace!(
                 organization_id = %org_id,
                 contact_id = contact_id.as_str(),
@@ -763,19 +1004,70 @@ impl MoneyBird {
         self.api.create_sales_link(contact_id).await
     }
 
-    pub async fn refresh_subscription_status(
+    /// Lists the invoices of an organization, empty if it never had a Moneybird contact
+    pub async fn list_invoices(&self, org_id: OrganizationId) -> Result<Vec<Invoice>, Error> {
+        let Some(contact_id) = self.existing_contact_id(org_id).await? else {
+            trace!(
+                organization_id = %org_id,
+                "No moneybird contact found"
+            );
+            return Ok(Vec::new());
+        };
+
+        self.api.list_invoices(&contact_id).await
+    }
+
+    /// Previews changing the organization's subscription to `product`, based on the stored
+    /// subscription status
+    pub async fn preview_subscription_change(
         &self,
         org_id: OrganizationId,
-    ) -> Result<SubscriptionStatus, Error> {
-        let contact_id: Option<MoneybirdContactId> = sqlx::query_scalar!(
+        product: ProductIdentifier,
+    ) -> Result<SubscriptionChangePreview, Error> {
+        let subscription_json = sqlx::query_scalar!(
             r#"
-            SELECT moneybird_contact_id FROM organizations WHERE id = $1
+            SELECT current_subscription FROM organizations WHERE id = $1
             "#,
             *org_id
         )
         .fetch_one(&self.pool)
-        .await?
-        .map(|id| id.into());
+        .await?;
+        let subscription_status: SubscriptionStatus = serde_json::from_value(subscription_json)?;
+
+        let current_product = subscription_status.active_product().clone();
+        let current_monthly_quota = current_product.monthly_quota();
+        let monthly_quota = product.monthly_quota();
+
+        let change = match monthly_quota.cmp(&current_monthly_quota) {
+            Ordering::Greater => SubscriptionChange::Upgrade,
+            Ordering::Less => SubscriptionChange::Downgrade,
+            Ordering::Equal => SubscriptionChange::Unchanged,
+        };
+
+        let effective_at = match change {
+            SubscriptionChange::Upgrade => None,
+            SubscriptionChange::Downgrade | SubscriptionChange::Unchanged => {
+                self.calculate_quota_reset_datetime(&org_id, &subscription_status)
+                    .await?
+            }
+        }
+        .unwrap_or_else(Utc::now);
+
+        Ok(SubscriptionChangePreview {
+            current_product,
+            current_monthly_quota,
+            product,
+            monthly_quota,
+            change,
+            effective_at,
+        })
+    }
+
+    pub async fn refresh_subscription_status(
+        &self,
+        org_id: OrganizationId,
+    ) -> Result<SubscriptionStatus, Error> {
+        let contact_id = self.existing_contact_id(org_id).await?;
 
         let status = match contact_id {
             Some(contact_id) => self
@@ -799,7 +1091,8 @@ impl MoneyBird {
             }
         };
 
-        self.store_subscription_status(&status, &org_id).await?;
+        self.store_subscription_status(&status, &org_id, None)
+            .await?;
 
         Ok(status)
     }
@@ -836,7 +1129,7 @@ mod test {
         models::{OrganizationRepository, ProjectRepository, Role},
         test::TestProjects,
     };
-    use chrono::{Months, NaiveTime};
+    use chrono::Months;
     use std::ops::Add;
 
     impl<T> Default for Subscription<T>
@@ -857,6 +1150,45 @@ mod test {
         }
     }
 
+    #[test]
+    fn quota_reset_time_in_timezone() {
+        let reset_time = QuotaResetTime {
+            time: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            timezone: chrono_tz::Europe::Amsterdam,
+        };
+
+        // CET, UTC+1
+        let winter = NaiveDate::from_ymd_opt(2026, 1, 31).unwrap();
+        assert_eq!(
+            reset_time.on(winter).unwrap(),
+            "2026-01-31T08:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+
+        // CEST, UTC+2
+        let summer = NaiveDate::from_ymd_opt(2026, 7, 31).unwrap();
+        assert_eq!(
+            reset_time.on(summer).unwrap(),
+            "2026-07-31T07:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+
+        // 02:30 does not exist when the clocks move forward
+        let reset_time = QuotaResetTime {
+            time: NaiveTime::from_hms_opt(2, 30, 0).unwrap(),
+            timezone: chrono_tz::Europe::Amsterdam,
+        };
+        let dst_start = NaiveDate::from_ymd_opt(2026, 3, 29).unwrap();
+        assert_eq!(
+            reset_time.on(dst_start).unwrap(),
+            "2026-03-29T01:30:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+
+        let default = QuotaResetTime::default();
+        assert_eq!(
+            default.on(winter).unwrap(),
+            "2026-01-31T23:59:59Z".parse::<DateTime<Utc>>().unwrap()
+        );
+    }
+
     #[test]
     fn subscription_ordering() {
         let active_none = SubscriptionStatus::Active(Subscription {
@@ -1011,7 +1343,7 @@ mod test {
         });
 
         moneybird
-            .make_user_admin_on_first_subscription(&new, &org_id)
+            .make_user_admin_on_first_subscription(&SubscriptionStatus::None, &new, &org_id)
             .await
             .unwrap();
 
@@ -1054,6 +1386,7 @@ mod test {
                     ..Default::default()
                 }),
                 &org_1,
+                None,
             )
             .await
             .unwrap();
@@ -1066,4 +1399,34 @@ mod test {
         let proj_2 = org_1_projects.iter().find(|p| p.id() == proj_2_id).unwrap();
         assert_eq!(proj_2.retention_period_days, 1);
     }
+
+    #[sqlx::test]
+    async fn webhook_registered_once(db: PgPool) {
+        let moneybird = MoneyBird::new(db.clone()).await.unwrap();
+
+        let results = futures::future::join_all(
+            (0..5).map(|_| async { moneybird.try_register_webhook().await.unwrap() }),
+        )
+        .await;
+
+        assert_eq!(
+            results
+                .iter()
+                .filter(|r| **r == WebhookRegistration::Registered)
+                .count(),
+            1
+        );
+
+        let webhooks = sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM moneybird_webhook"#)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(webhooks, 1);
+
+        // later attempts see the registered webhook
+        assert_eq!(
+            moneybird.try_register_webhook().await.unwrap(),
+            WebhookRegistration::AlreadyRegistered
+        );
+    }
 }
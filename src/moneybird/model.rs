@@ -102,6 +102,9 @@ pub(super) struct MoneybirdSubscription {
     pub(super) product: Product,
     pub(super) start_date: NaiveDate,
     pub(super) end_date: Option<NaiveDate>,
+    /// Used to order webhook deliveries, which may arrive out of order when Moneybird retries
+    #[serde(default)]
+    pub(super) updated_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -112,6 +115,66 @@ pub struct Product {
     pub(super) description: String,
 }
 
+/// This models the relevant part of a sales invoice returned by Moneybird
+#[derive(Debug, Deserialize)]
+pub(super) struct MoneybirdSalesInvoice {
+    /// The invoice number, not present for draft invoices
+    pub(super) invoice_id: Option<String>,
+    pub(super) invoice_date: Option<NaiveDate>,
+    pub(super) state: InvoiceStatus,
+    pub(super) total_price_incl_tax: String,
+    pub(super) currency: String,
+    pub(super) url: Url,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum InvoiceStatus {
+    Draft,
+    Open,
+    Scheduled,
+    PendingPayment,
+    Late,
+    Reminded,
+    Paid,
+    Uncollectible,
+    #[serde(untagged)]
+    Unknown(String),
+}
+
+/// Summary of an invoice sent to an organization
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, ToSchema)]
+pub struct Invoice {
+    pub(super) invoice_number: String,
+    pub(super) date: NaiveDate,
+    /// Total amount including tax, as a decimal number
+    pub(super) amount: String,
+    pub(super) currency: String,
+    pub(super) status: InvoiceStatus,
+    /// Public link to the invoice, from which the PDF can be downloaded
+    pub(super) url: Url,
+}
+
+impl TryFrom<MoneybirdSalesInvoice> for Invoice {
+    type Error = ();
+
+    /// Fails for draft invoices, which have not been sent to the customer
+    fn try_from(invoice: MoneybirdSalesInvoice) -> Result<Self, Self::Error> {
+        if invoice.state == InvoiceStatus::Draft {
+            return Err(());
+        }
+
+        Ok(Invoice {
+            invoice_number: invoice.invoice_id.ok_or(())?,
+            date: invoice.invoice_date.ok_or(())?,
+            amount: invoice.total_price_incl_tax,
+            currency: invoice.currency,
+            status: invoice.state,
+            url: invoice.url,
+        })
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub(super) struct RecurringSalesInvoice {
     pub(super) id: RecurringSalesInvoiceId,
@@ -212,6 +275,21 @@ impl ProductIdentifier {
         }
     }
 
+    pub fn domain_limit(&self) -> Option<u32> {
+        match self {
+            ProductIdentifier::NotSubscribed => Some(0),
+            ProductIdentifier::RmlsFree
+            | ProductIdentifier::RmlsHobbyMonthly
+            | ProductIdentifier::RmlsHobbyYearly => Some(1),
+            ProductIdentifier::RmlsTinyMonthly | ProductIdentifier::RmlsTinyYearly => Some(3),
+            ProductIdentifier::RmlsSmallMonthly | ProductIdentifier::RmlsSmallYearly => Some(10),
+            ProductIdentifier::RmlsMediumMonthly | ProductIdentifier::RmlsMediumYearly => None,
+            ProductIdentifier::RmlsLargeMonthly | ProductIdentifier::RmlsLargeYearly => None,
+            #[cfg(test)]
+            ProductIdentifier::Unlimited => None,
+        }
+    }
+
     pub fn max_rate_limit_tokens(&self) -> i64 {
         match self {
             ProductIdentifier::NotSubscribed => 0,
@@ -280,6 +358,27 @@ impl SubscriptionStatus {
     }
 }
 
+#[derive(Deserialize, Serialize, PartialEq, Debug, Clone, Copy, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionChange {
+    Upgrade,
+    Downgrade,
+    Unchanged,
+}
+
+/// The effect of changing to another product, without committing the change
+#[derive(Deserialize, Serialize, PartialEq, Debug, Clone, ToSchema)]
+pub struct SubscriptionChangePreview {
+    pub current_product: ProductIdentifier,
+    pub current_monthly_quota: u32,
+    pub product: ProductIdentifier,
+    pub monthly_quota: u32,
+    /// Whether the new product has a higher or lower monthly quota
+    pub change: SubscriptionChange,
+    /// Upgrades take effect immediately, downgrades at the end of the current subscription period
+    pub effective_at: DateTime<Utc>,
+}
+
 #[derive(Deserialize, Serialize, PartialEq, Debug, Clone, ToSchema)]
 pub struct Subscription<EndDate = Option<NaiveDate>> {
     pub(super) subscription_id: SubscriptionId,
@@ -371,3 +470,19 @@ pub enum Error {
     #[error("Precondition failed: {0}")]
     PreconditionFailed(&'static str),
 }
+
+impl Error {
+    /// Whether the request might succeed when tried again, e.g., on timeouts or server errors
+    pub(super) fn is_transient(&self) -> bool {
+        match self {
+            Error::Reqwest(err) => {
+                err.is_timeout()
+                    || err.is_connect()
+                    || err.status().is_some_and(|status| {
+                        status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                    })
+            }
+            _ => false,
+        }
+    }
+}
@@ -1,14 +1,15 @@
 use crate::moneybird::{
     Error, MONEYBIRD_API_URL, MoneybirdApi, Webhook,
     model::{
-        AdministrationId, Contact, MoneybirdContactId, MoneybirdSubscription,
-        RecurringSalesInvoice, RecurringSalesInvoiceId, SubscriptionStatus, SubscriptionTemplate,
+        AdministrationId, Contact, Invoice, MoneybirdContactId, MoneybirdSalesInvoice,
+        MoneybirdSubscription, RecurringSalesInvoice, RecurringSalesInvoiceId, SubscriptionStatus,
+        SubscriptionTemplate,
     },
 };
 use async_trait::async_trait;
 use chrono::NaiveDate;
 use http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE};
-use tracing::{error, warn};
+use tracing::warn;
 use url::Url;
 
 #[derive(Clone)]
@@ -51,8 +52,8 @@ impl ProductionMoneybirdApi {
 
 #[async_trait]
 impl MoneybirdApi for ProductionMoneybirdApi {
-    async fn register_webhook(&self) -> Option<Webhook> {
-        match self
+    async fn register_webhook(&self) -> Result<Webhook, Error> {
+        Ok(self
             .client
             .post(self.url("webhooks"))
             .json(&serde_json::json!({
@@ -66,25 +67,10 @@ impl MoneybirdApi for ProductionMoneybirdApi {
                 ]
             }))
             .send()
-            .await
-        {
-            Ok(res) if res.status().is_success() => res.json().await.unwrap_or_else(|err| {
-                error!("Error registering Moneybird webhook: {}", err);
-                None
-            }),
-            Err(err) => {
-                error!("Error registering Moneybird webhook: {}", err);
-                None
-            }
-            Ok(res) => {
-                error!(
-                    "Error registering Moneybird webhook: Status {}, Response: {}",
-                    res.status(),
-                    res.text().await.unwrap()
-                );
-                None
-            }
-        }
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
     }
 
     async fn next_invoice_date(
@@ -185,6 +171,22 @@ impl MoneybirdApi for ProductionMoneybirdApi {
         Ok(sales_link.parse()?)
     }
 
+    async fn list_invoices(&self, contact_id: &MoneybirdContactId) -> Result<Vec<Invoice>, Error> {
+        Ok(self
+            .client
+            .get(self.url(&format!(
+                "sales_invoices?filter=contact_id:{contact_id},state:all"
+            )))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Vec<MoneybirdSalesInvoice>>()
+            .await?
+            .into_iter()
+            .filter_map(|invoice| Invoice::try_from(invoice).ok())
+            .collect())
+    }
+
     async fn customer_contact_portal(
         &self,
         moneybird_contact_id: MoneybirdContactId,
@@ -1,12 +1,14 @@
 use crate::{
     MoneyBird,
     bus::client::BusClient,
-    handler::dns::DnsResolver,
+    handler::{RetryConfig, dns::DnsResolver},
     models::{
-        self, ApiUserRepository, DomainRepository, InviteRepository, MessageRepository,
-        StatisticsRepository, SuppressedRepository,
+        self, ApiUserRepository, DomainRepository, InviteRepository, MessageId, MessageRepository,
+        OrganizationExportRepository, OrganizationRepository, StatisticsRepository,
+        SuppressedRepository,
     },
     moneybird,
+    system_emails::send_dkim_drift_emails,
 };
 use chrono::{Duration, Utc};
 use sqlx::PgPool;
@@ -22,10 +24,17 @@ pub struct Periodically {
     statistics_repository: StatisticsRepository,
     domain_repository: DomainRepository,
     suppressed_repository: SuppressedRepository,
+    export_repository: OrganizationExportRepository,
+    organization_repository: OrganizationRepository,
     moneybird: MoneyBird,
     bus_client: BusClient,
+    /// Messages stuck in `accepted` or `processing` for longer than this are failed
+    max_stuck_message_age: Duration,
 }
 
+/// Maximum age of messages stuck in `accepted` or `processing`, unless configured otherwise
+const DEFAULT_MAX_STUCK_MESSAGE_AGE: Duration = Duration::days(3);
+
 pub fn run_periodically<F, E, Fut>(task: F, period: Duration, cancel: CancellationToken)
 where
     F: Fn() -> Fut + Send + 'static,
@@ -58,15 +67,51 @@ impl Periodically {
             invite_repository: InviteRepository::new(pool.clone()),
             user_repository: ApiUserRepository::new(pool.clone()),
             statistics_repository: StatisticsRepository::new(pool.clone()),
-            domain_repository: DomainRepository::new(pool.clone(), resolver),
+            domain_repository: DomainRepository::new(pool.clone(), resolver.clone()),
             suppressed_repository: SuppressedRepository::new(pool.clone()),
+            export_repository: OrganizationExportRepository::new(pool.clone(), resolver),
+            organization_repository: OrganizationRepository::new(pool.clone()),
             moneybird: MoneyBird::new(pool).await?,
             bus_client,
+            max_stuck_message_age: Self::max_stuck_message_age_from_env(),
         })
     }
 
-    /// Retry all messages that are ready to be retried
+    /// Will panic if `MAX_STUCK_MESSAGE_AGE_HOURS` is set, but is not a positive number of hours
+    fn max_stuck_message_age_from_env() -> Duration {
+        std::env::var("MAX_STUCK_MESSAGE_AGE_HOURS")
+            .map(|hours| {
+                let hours = hours
+                    .parse::<std::num::NonZeroU32>()
+                    .expect("MAX_STUCK_MESSAGE_AGE_HOURS must be a positive number of hours");
+                Duration::hours(hours.get().into())
+            })
+            .unwrap_or(DEFAULT_MAX_STUCK_MESSAGE_AGE)
+    }
+
+    /// Retry all messages that are ready to be retried,
+    /// including messages that were held due to the quota if the quota allows it again.
+    /// Messages that passed their maximum age are failed instead,
+    /// as are messages that are stuck in `accepted` or `processing` for too long.
     pub async fn retry_messages(&self) -> Result<(), models::Error> {
+        for message_id in self.message_repository.fail_expired_messages().await? {
+            tracing::info!(
+                message_id = message_id.to_string(),
+                "Message passed its maximum age, not retrying"
+            );
+        }
+
+        for message_id in self
+            .message_repository
+            .fail_stuck_messages(self.max_stuck_message_age)
+            .await?
+        {
+            tracing::warn!(
+                message_id = message_id.to_string(),
+                "Message was stuck for too long, not retrying"
+            );
+        }
+
         debug!("Retrying messages");
         let messages = self
             .message_repository
@@ -75,22 +120,43 @@ impl Periodically {
 
         for message_id in messages {
             tracing::info!(message_id = message_id.to_string(), "Retrying message");
-            match self.message_repository.get_ready_to_send(message_id).await {
-                Ok(bus_message) => {
-                    self.bus_client.try_send(&bus_message).await;
-                }
-                Err(e) => {
-                    error!(message_id = message_id.to_string(), "{e:?}");
-                }
-            }
+            self.send_message(message_id).await;
+        }
+
+        self.resume_quota_held_messages().await
+    }
+
+    /// Send messages that were held due to the quota,
+    /// as far as the remaining quota of their organization allows
+    async fn resume_quota_held_messages(&self) -> Result<(), models::Error> {
+        let messages = self.message_repository.resume_quota_held_messages().await?;
+
+        for message_id in messages {
+            tracing::info!(
+                message_id = message_id.to_string(),
+                "Resuming message held due to the quota"
+            );
+            self.send_message(message_id).await;
         }
 
         Ok(())
     }
 
-    /// Clean up organization invites and password reset links which have been expired for more than
-    /// a day, as well as messages that are out of their retention period and/or message that are
-    /// ready to be deleted, and suppressed email addresses which were not used for a while
+    async fn send_message(&self, message_id: MessageId) {
+        match self.message_repository.get_ready_to_send(message_id).await {
+            Ok(bus_message) => {
+                self.bus_client.try_send(&bus_message).await;
+            }
+            Err(e) => {
+                error!(message_id = message_id.to_string(), "{e:?}");
+            }
+        }
+    }
+
+    /// Clean up organization invites, password reset links and failed login counters which have
+    /// been expired for more than a day, as well as messages that are out of their retention
+    /// period and/or message that are ready to be deleted, and suppressed email addresses which
+    /// were not used for a while
     pub async fn clean_up(&self) -> Result<(), models::Error> {
         self.invite_repository
             .remove_expired_before(Utc::now() - Duration::days(1))
@@ -100,10 +166,18 @@ impl Periodically {
             .remove_password_reset_expired_before(Utc::now() - Duration::days(1))
             .await?;
 
+        self.user_repository
+            .remove_failed_logins_expired_before(Utc::now() - Duration::days(1))
+            .await?;
+
         self.message_repository
             .remove_expired_message_data()
             .await?;
 
+        self.message_repository.purge_deleted_messages().await?;
+
+        self.export_repository.remove_expired().await?;
+
         self.statistics_repository
             .aggregate_and_archive_messages()
             .await?;
@@ -113,13 +187,49 @@ impl Periodically {
             .await
     }
 
+    /// Re-verify the DNS records of all domains, and notify the organizations whose domains no
+    /// longer publish a DKIM record matching their key
     pub async fn verify_domains(&self) -> Result<(), models::Error> {
-        self.domain_repository.verify_all().await
+        let verified = self.domain_repository.verify_all().await;
+
+        // Domains that drifted before another domain failed to verify are notified as well
+        for drift in self.domain_repository.take_unnotified_dkim_drifts().await? {
+            if let Err(e) = send_dkim_drift_emails(
+                &self.message_repository,
+                &self.organization_repository,
+                &self.bus_client,
+                RetryConfig::default().max_automatic_retries,
+                &drift,
+            )
+            .await
+            {
+                error!(
+                    domain_id = drift.domain_id.to_string(),
+                    organization_id = drift.organization_id.to_string(),
+                    "failed to send DKIM drift emails: {e:?}"
+                );
+            }
+        }
+
+        verified
     }
 
-    /// Reset quotas for all organizations where the quota is ready to be reset
+    /// Generate the archives of organization exports that have been requested
+    pub async fn generate_exports(&self) -> Result<(), models::Error> {
+        self.export_repository.generate_pending().await
+    }
+
+    /// Reset quotas for all organizations where the quota is ready to be reset,
+    /// and send the messages that were held due to the quota right away
     pub async fn reset_all_quotas(&self) -> Result<(), moneybird::Error> {
-        self.moneybird.reset_all_quotas().await
+        self.moneybird.reset_all_quotas().await?;
+
+        // Not critical for resetting the quotas, the next retry round will try again
+        if let Err(e) = self.resume_quota_held_messages().await {
+            error!("failed to resume messages held due to the quota: {e:?}");
+        }
+
+        Ok(())
     }
 }
 
@@ -127,10 +237,10 @@ impl Periodically {
 mod test {
     use super::*;
     use crate::{
-        Environment, HandlerConfig,
+        HandlerConfig,
         bus::{client::BusMessage, server::Bus},
-        handler::{Handler, RetryConfig, dns::DnsResolver},
-        models::{MessageId, MessageStatus},
+        handler::{Handler, HandlerError, RetryConfig, dns::DnsResolver},
+        models::{HoldReason, MessageId, MessagePriority, MessageStatus},
         test::{TestProjects, random_port},
     };
     use chrono::Duration;
@@ -162,13 +272,13 @@ mod test {
         let bus_port = Bus::spawn_random_port().await;
         let bus_client = BusClient::new(bus_port, "localhost".to_owned()).unwrap();
         let config = HandlerConfig {
-            domain: "test".to_owned(),
             resolver: DnsResolver::mock("localhost", mailcrab_port),
-            environment: Environment::Development,
             retry: RetryConfig {
                 delay: Duration::minutes(60),
                 max_automatic_retries: 3,
+                greylist: Default::default(),
             },
+            ..HandlerConfig::test_default()
         };
         let handler = Handler::new(
             pool.clone(),
@@ -253,6 +363,8 @@ mod test {
             .send(&BusMessage::EmailReadyToSend(
                 message_out_of_attempts,
                 "127.0.0.1".parse().unwrap(),
+                None,
+                MessagePriority::High,
             ))
             .await
             .unwrap();
@@ -260,6 +372,8 @@ mod test {
             .send(&BusMessage::EmailReadyToSend(
                 message_on_timeout,
                 "127.0.0.1".parse().unwrap(),
+                None,
+                MessagePriority::High,
             ))
             .await
             .unwrap();
@@ -305,13 +419,13 @@ mod test {
         let bus_port = Bus::spawn_random_port().await;
         let bus_client = BusClient::new(bus_port, "localhost".to_owned()).unwrap();
         let config = HandlerConfig {
-            domain: "test".to_owned(),
             resolver: DnsResolver::mock("localhost", mailcrab_port),
             retry: RetryConfig {
                 delay: Duration::minutes(60),
                 max_automatic_retries: 3,
+                greylist: Default::default(),
             },
-            environment: Environment::Development,
+            ..HandlerConfig::test_default()
         };
         let handler = Handler::new(
             pool.clone(),
@@ -361,4 +475,105 @@ mod test {
 
         assert_eq!(remaining, 799);
     }
+
+    #[sqlx::test(fixtures(
+        path = "./fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "smtp_credentials",
+            "messages",
+            "k8s_nodes"
+        )
+    ))]
+    async fn resume_quota_held_messages_after_reset(pool: PgPool) {
+        let mailcrab_port = random_port();
+        let TestMailServerHandle {
+            token,
+            rx: mut mailcrab_rx,
+        } = mailcrab::development_mail_server(Ipv4Addr::new(127, 0, 0, 1), mailcrab_port).await;
+        let _drop_guard = token.drop_guard();
+
+        let org_id = TestProjects::Org1Project1.org_id();
+        let message_held_id = "10d5ad5f-04ae-489b-9f5a-f5d7e73bc12a".parse().unwrap();
+
+        let bus_port = Bus::spawn_random_port().await;
+        let bus_client = BusClient::new(bus_port, "localhost".to_owned()).unwrap();
+        let mut stream = bus_client.receive().await.unwrap();
+        let config = HandlerConfig {
+            resolver: DnsResolver::mock("localhost", mailcrab_port),
+            ..HandlerConfig::test_default()
+        };
+        let handler = Handler::new(
+            pool.clone(),
+            Arc::new(config),
+            bus_client.clone(),
+            CancellationToken::new(),
+        )
+        .await;
+        handler.clone().spawn();
+
+        tokio::time::sleep(core::time::Duration::from_secs(1)).await;
+
+        let periodically = Periodically::new(
+            pool.clone(),
+            bus_client,
+            DnsResolver::mock("localhost", 1025),
+        )
+        .await
+        .unwrap();
+        let message_repo = MessageRepository::new(pool.clone());
+
+        // exhaust the quota, which is due to be reset
+        sqlx::query!(
+            r#"
+            UPDATE organizations
+            SET used_message_quota = total_message_quota,
+                quota_reset = now() - '1 minute'::interval,
+                moneybird_contact_id = 'quota_held_test_org'
+            WHERE id = $1
+            "#,
+            *org_id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let mut message = message_repo
+            .get_if_org_may_send(message_held_id)
+            .await
+            .unwrap();
+        let err = handler.handle_message(&mut message).await.unwrap_err();
+        assert!(matches!(
+            err,
+            HandlerError::MessageNotAccepted(MessageStatus::Held, _)
+        ));
+
+        let message = message_repo
+            .find_by_id(org_id, message_held_id)
+            .await
+            .unwrap();
+        assert_eq!(message.hold_reason(), Some(HoldReason::Quota));
+
+        periodically.reset_all_quotas().await.unwrap();
+        BusClient::wait_for_attempt(1, &mut stream).await;
+
+        // one email with two recipients
+        for _ in 0..2 {
+            let recv = mailcrab_rx.recv().await.unwrap();
+            assert_eq!(
+                recv.envelope_from.as_str(),
+                "email-held@test-org-1-project-1.com"
+            );
+        }
+
+        let message = message_repo
+            .find_by_id(org_id, message_held_id)
+            .await
+            .unwrap();
+        assert_eq!(message.status(), &MessageStatus::Delivered);
+        assert_eq!(message.hold_reason(), None);
+    }
 }
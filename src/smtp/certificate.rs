@@ -0,0 +1,109 @@
+//! Monitoring of the expiry of the TLS certificate of the SMTP server
+//!
+//! Clients fail to connect once the certificate expired, typically without telling us. The expiry
+//! date is therefore checked whenever the certificate is (re)loaded, and exposed via the health
+//! endpoint of the inbound server.
+
+use axum::{Json, Router, extract::State, routing::get};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{path::Path, time::SystemTime};
+use tokio::sync::watch;
+use tokio_rustls::rustls::pki_types::CertificateDer;
+use tracing::{error, info, warn};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CertificateStatus {
+    /// The end of the validity period of the certificate
+    pub not_after: DateTime<Utc>,
+    /// Whether the certificate expires within the configured warning period
+    pub expires_soon: bool,
+}
+
+impl CertificateStatus {
+    pub(super) fn new(not_after: DateTime<Utc>, warning: chrono::Duration) -> Self {
+        Self {
+            not_after,
+            expires_soon: not_after - Utc::now() < warning,
+        }
+    }
+
+    /// Log and record the remaining validity of the certificate
+    pub(super) fn report(&self) {
+        let remaining = self.not_after - Utc::now();
+
+        opentelemetry::global::meter(env!("CARGO_CRATE_NAME"))
+            .i64_gauge("smtp_certificate_remaining_days")
+            .with_description("Number of days until the TLS certificate of the SMTP server expires")
+            .build()
+            .record(remaining.num_days(), &[]);
+
+        let not_after = self.not_after.to_rfc3339();
+        if remaining <= chrono::Duration::zero() {
+            error!(not_after, "The SMTP TLS certificate has expired");
+        } else if self.expires_soon {
+            warn!(
+                not_after,
+                remaining_days = remaining.num_days(),
+                "The SMTP TLS certificate expires soon"
+            );
+        } else {
+            info!(not_after, "The SMTP TLS certificate is valid");
+        }
+    }
+}
+
+/// The end of the validity period of a DER-encoded certificate
+pub(super) fn not_after(certificate: &CertificateDer<'_>) -> Option<DateTime<Utc>> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(certificate).ok()?;
+
+    DateTime::from_timestamp(certificate.validity().not_after.timestamp(), 0)
+}
+
+/// The last modification time of the certificate and key files, used to reload them when they
+/// change on disk
+pub(super) fn modified(cert_file: &Path, key_file: &Path) -> Option<(SystemTime, SystemTime)> {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+
+    Some((modified(cert_file)?, modified(key_file)?))
+}
+
+/// Router of the health endpoint, reporting the expiry of the certificate
+pub(super) fn router(status: watch::Receiver<Option<CertificateStatus>>) -> Router {
+    Router::new()
+        .route("/health/certificate", get(certificate_status))
+        .with_state(status)
+}
+
+async fn certificate_status(
+    State(status): State<watch::Receiver<Option<CertificateStatus>>>,
+) -> Json<Option<CertificateStatus>> {
+    Json(status.borrow().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_not_after() {
+        let mut params = rcgen::CertificateParams::new(vec!["localhost".to_owned()]).unwrap();
+        params.not_after = rcgen::date_time_ymd(2031, 5, 17);
+        let key_pair = rcgen::KeyPair::generate().unwrap();
+        let certificate = params.self_signed(&key_pair).unwrap();
+
+        assert_eq!(
+            not_after(certificate.der()).unwrap(),
+            "2031-05-17T00:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert!(not_after(&CertificateDer::from(vec![0x30, 0x00])).is_none());
+    }
+
+    #[test]
+    fn expires_soon() {
+        let in_a_week = Utc::now() + chrono::Duration::days(7);
+
+        assert!(CertificateStatus::new(in_a_week, chrono::Duration::days(14)).expires_soon);
+        assert!(!CertificateStatus::new(in_a_week, chrono::Duration::days(3)).expires_soon);
+    }
+}
@@ -2,7 +2,9 @@ use smtp_proto::Request;
 use std::net::SocketAddr;
 use thiserror::Error;
 use tokio::{
-    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    io::{
+        AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter,
+    },
     time::{Duration, timeout},
 };
 use tracing::{debug, info, trace};
@@ -10,7 +12,13 @@ use tracing::{debug, info, trace};
 use crate::{
     bus::client::BusClient,
     models::{MessageRepository, SmtpCredentialRepository},
-    smtp::session::{DataReply, SessionReply, SmtpResponse, SmtpSession},
+    smtp::{
+        LoopDetectionConfig, TarpitConfig,
+        session::{
+            AuthReply, DataReply, PlaintextReply, PlaintextSession, SessionReply, SmtpResponse,
+            SmtpSession,
+        },
+    },
 };
 
 #[derive(Debug, Error)]
@@ -30,41 +38,114 @@ pub enum ConnectionError {
 const BUFFER_SIZE: usize = 1024;
 const CODE_READY: u16 = 220;
 
+/// Talk plaintext SMTP until the client issues STARTTLS (RFC 3207), returns whether the
+/// connection should switch to TLS, which is not the case if the client quit before
+pub async fn negotiate_starttls(
+    stream: &mut (impl AsyncReadExt + AsyncWriteExt + Unpin),
+    server_name: String,
+    greeting: String,
+    peer_addr: SocketAddr,
+    tarpit: TarpitConfig,
+) -> Result<bool, ConnectionError> {
+    let (source, sink) = tokio::io::split(stream);
+
+    let mut buffer = Vec::with_capacity(BUFFER_SIZE);
+    let session = PlaintextSession::new(server_name, peer_addr);
+
+    let mut reader = BufReader::new(source);
+    let mut sink = BufWriter::new(sink);
+
+    trace!("handling plaintext connection with {peer_addr}");
+
+    greet(greeting, tarpit, &mut sink).await?;
+
+    loop {
+        flush_if_idle(&reader, &mut sink).await?;
+        read_line(&mut reader, &mut buffer).await?;
+
+        match session.handle(Request::parse(&mut buffer.iter())) {
+            PlaintextReply::ReplyAndContinue(response) => {
+                write_reply(response, &mut sink).await?;
+            }
+            PlaintextReply::RawReply(buf) => {
+                sink.write_all(&buf).await.map_err(ConnectionError::Write)?;
+            }
+            PlaintextReply::ReplyAndStop(response) => {
+                write_reply(response, &mut sink).await?;
+                sink.flush().await.map_err(ConnectionError::Write)?;
+
+                return Ok(false);
+            }
+            PlaintextReply::StartTls(response) => {
+                write_reply(response, &mut sink).await?;
+                sink.flush().await.map_err(ConnectionError::Write)?;
+
+                // anything the client pipelined after STARTTLS was not protected by TLS,
+                // and is discarded along with the reader (RFC 3207, 6)
+                if !reader.buffer().is_empty() {
+                    debug!("discarding plaintext pipelined after STARTTLS");
+                }
+
+                return Ok(true);
+            }
+        }
+    }
+}
+
+/// Handle an SMTP session over TLS, the client is not greeted if `greeting` is `None`,
+/// i.e., if it was greeted before the connection switched to TLS using STARTTLS
+#[allow(clippy::too_many_arguments)]
 pub async fn handle(
     stream: &mut (impl AsyncReadExt + AsyncWriteExt + Unpin),
     server_name: String,
+    greeting: Option<String>,
     peer_addr: SocketAddr,
     bus_client: BusClient,
     user_repository: SmtpCredentialRepository,
     message_repository: MessageRepository,
     max_automatic_retries: i32,
+    loop_detection: LoopDetectionConfig,
+    tarpit: TarpitConfig,
+    challenge_response_auth: bool,
 ) -> Result<(), ConnectionError> {
-    let (source, mut sink) = tokio::io::split(stream);
+    let (source, sink) = tokio::io::split(stream);
 
     // NOTE: we re-use this Vec<u8> to avoid re-allocating buffer
     let mut buffer = Vec::with_capacity(BUFFER_SIZE);
     let mut session = SmtpSession::new(
+        server_name,
         peer_addr,
         bus_client,
         user_repository,
         message_repository,
         max_automatic_retries,
+        loop_detection,
+        tarpit,
+        challenge_response_auth,
     );
 
     let mut reader = BufReader::new(source);
+    // replies are buffered such that pipelined commands are answered at once (RFC 2920)
+    let mut sink = BufWriter::new(sink);
 
     trace!("handling connection with {}", &session.peer());
 
-    write_reply((CODE_READY, server_name).into(), &mut sink).await?;
+    if let Some(greeting) = greeting {
+        greet(greeting, tarpit, &mut sink).await?;
+    }
 
     'session: loop {
+        flush_if_idle(&reader, &mut sink).await?;
         read_line(&mut reader, &mut buffer).await?;
 
         let request = Request::parse(&mut buffer.iter());
 
         trace!("received request: {:?}", request);
 
-        match session.handle(request).await {
+        let reply = session.handle(request).await;
+        session.tarpit().await;
+
+        match reply {
             SessionReply::ReplyAndContinue(response) => {
                 write_reply(response, &mut sink).await?;
                 continue;
@@ -74,14 +155,17 @@ pub async fn handle(
                 break;
             }
             SessionReply::RawReply(buf) => {
-                sink.write(&buf).await.map_err(ConnectionError::Write)?;
+                sink.write_all(&buf).await.map_err(ConnectionError::Write)?;
                 continue;
             }
             SessionReply::IngestData(response) => {
                 write_reply(response, &mut sink).await?;
 
+                // the message data is read line by line, as the client may pipeline
+                // commands right after the end of data indicator
                 'data: loop {
-                    read_buf(&mut reader, &mut buffer).await?;
+                    flush_if_idle(&reader, &mut sink).await?;
+                    read_line(&mut reader, &mut buffer).await?;
 
                     match session.handle_data(&buffer).await {
                         DataReply::ContinueIngest => continue 'data,
@@ -92,41 +176,41 @@ pub async fn handle(
                     }
                 }
             }
-            SessionReply::IngestAuth(response) => {
-                write_reply(response, &mut sink).await?;
+            SessionReply::IngestAuth(mut challenge) => loop {
+                write_reply(challenge, &mut sink).await?;
+                flush_if_idle(&reader, &mut sink).await?;
                 read_line(&mut reader, &mut buffer).await?;
 
-                let response = session.handle_plain_auth(&mut buffer).await;
-                write_reply(response, &mut sink).await?;
-            }
+                // challenge-response mechanisms take multiple round trips
+                match session.handle_auth_response(&mut buffer).await {
+                    AuthReply::Challenge(next) => challenge = next,
+                    AuthReply::Done(response) => {
+                        session.tarpit().await;
+                        write_reply(response, &mut sink).await?;
+                        break;
+                    }
+                }
+            },
         }
     }
 
+    sink.flush().await.map_err(ConnectionError::Write)?;
+
     info!("connection handled");
 
     Ok(())
 }
 
-async fn read_buf(
-    reader: impl AsyncBufReadExt + Unpin,
-    buffer: &mut Vec<u8>,
-) -> Result<usize, ConnectionError> {
-    buffer.clear();
+async fn greet(
+    greeting: String,
+    tarpit: TarpitConfig,
+    sink: impl AsyncWriteExt + Unpin,
+) -> Result<(), ConnectionError> {
+    if !tarpit.greeting_delay.is_zero() {
+        tokio::time::sleep(tarpit.greeting_delay).await;
+    }
 
-    timeout(
-        Duration::from_secs(300),
-        reader.take(BUFFER_SIZE as u64).read_buf(buffer),
-    )
-    .await
-    .map_err(ConnectionError::Timeout)?
-    .map_err(ConnectionError::Read)
-    .and_then(|size| {
-        if size > 0 {
-            Ok(size)
-        } else {
-            Err(ConnectionError::Dropped)
-        }
-    })
+    write_reply((CODE_READY, greeting).into(), sink).await
 }
 
 async fn read_line(
@@ -151,12 +235,26 @@ async fn read_line(
     })
 }
 
+/// Send the buffered replies before waiting for the client, i.e.,
+/// once all commands received so far have been handled (RFC 2920, 3.2)
+async fn flush_if_idle(
+    reader: &BufReader<impl AsyncRead>,
+    sink: &mut (impl AsyncWrite + Unpin),
+) -> Result<(), ConnectionError> {
+    if reader.buffer().is_empty() {
+        sink.flush().await.map_err(ConnectionError::Write)?;
+    }
+
+    Ok(())
+}
+
 async fn write_reply(
     response: SmtpResponse,
     mut sink: impl AsyncWriteExt + Unpin,
 ) -> Result<(), ConnectionError> {
-    let n = sink
-        .write(format!("{response}\r\n").as_bytes())
+    let reply = format!("{response}\r\n");
+    let n = reply.len();
+    sink.write_all(reply.as_bytes())
         .await
         .map_err(ConnectionError::Write)?;
 
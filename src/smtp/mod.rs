@@ -1,29 +1,173 @@
 use crate::{Environment, handler::RetryConfig};
-use std::{env, path::PathBuf};
+use std::{env, net::SocketAddr, path::PathBuf, time::Duration};
 
+mod certificate;
 mod connection;
 mod proxy_protocol;
+mod sasl;
 pub mod server;
 mod session;
 
+/// How a listener secures its connections
+#[derive(Clone, Copy, Debug, PartialEq, Eq, derive_more::Display)]
+pub enum TlsMode {
+    /// TLS from the first byte, typically on port 465 (RFC 8314)
+    #[display("implicit TLS")]
+    Implicit,
+    /// Plaintext until the client issues STARTTLS, typically on port 587 (RFC 3207)
+    #[display("STARTTLS")]
+    StartTls,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SmtpListener {
+    pub addr: SocketAddr,
+    pub tls: TlsMode,
+}
+
+impl SmtpListener {
+    /// Parse a comma-separated list of `address=mode`, where the mode is `implicit` or
+    /// `starttls`, e.g., `0.0.0.0:465=implicit,0.0.0.0:587=starttls`
+    fn parse_list(listeners: &str) -> Option<Vec<Self>> {
+        let listeners = listeners
+            .split(',')
+            .filter(|listener| !listener.trim().is_empty())
+            .map(|listener| {
+                let (addr, tls) = listener.split_once('=')?;
+                let tls = match tls.trim() {
+                    "implicit" => TlsMode::Implicit,
+                    "starttls" => TlsMode::StartTls,
+                    _ => return None,
+                };
+
+                Some(Self {
+                    addr: addr.trim().parse().ok()?,
+                    tls,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        (!listeners.is_empty()).then_some(listeners)
+    }
+}
+
 #[derive(Clone)]
 pub struct SmtpConfig {
-    pub listen_addr: core::net::SocketAddr,
+    /// Addresses to accept connections on, each with its own TLS mode
+    pub listeners: Vec<SmtpListener>,
     pub server_name: String,
+    /// Text following the server name in the greeting, e.g., `ESMTP Remails`
+    pub banner: Option<String>,
     pub cert_file: PathBuf,
     pub key_file: PathBuf,
     pub environment: Environment,
     pub retry: RetryConfig,
+    pub loop_detection: LoopDetectionConfig,
+    pub tarpit: TarpitConfig,
+    /// Whether CRAM-MD5 and SCRAM-SHA-256 are advertised, clients that support them
+    /// will prefer them over PLAIN, so only credentials provisioned for them can be used
+    pub challenge_response_auth: bool,
+    /// Warn about the TLS certificate once it expires within this period
+    pub certificate_expiry_warning: chrono::Duration,
 }
 
-impl Default for SmtpConfig {
+/// Thresholds used to detect mail loops in incoming messages
+#[derive(Clone, Copy, Debug)]
+pub struct LoopDetectionConfig {
+    /// Maximum number of `Received` headers of any message (RFC 5321, 6.3)
+    pub max_received: usize,
+    /// Maximum number of `Received` headers of a message marked with `Auto-Submitted: auto-replied`
+    pub max_auto_replied_received: usize,
+}
+
+impl Default for LoopDetectionConfig {
+    /// Reads the thresholds from the `SMTP_LOOP_MAX_RECEIVED` and
+    /// `SMTP_LOOP_MAX_AUTO_REPLIED_RECEIVED` environment variables,
+    /// defaulting to 100 and 5 respectively
+    ///
+    /// Will panic if either of them is set to anything that cannot be parsed as a `usize`
     fn default() -> Self {
-        let listen_addr = env::var("SMTP_LISTEN_ADDR")
-            .expect("Missing SMTP_LISTEN_ADDR environment variable")
+        let max_received = env::var("SMTP_LOOP_MAX_RECEIVED")
+            .unwrap_or("100".to_owned())
             .parse()
-            .expect("Invalid SMTP_LISTEN_ADDR");
+            .expect("SMTP_LOOP_MAX_RECEIVED must be a usize");
+        let max_auto_replied_received = env::var("SMTP_LOOP_MAX_AUTO_REPLIED_RECEIVED")
+            .unwrap_or("5".to_owned())
+            .parse()
+            .expect("SMTP_LOOP_MAX_AUTO_REPLIED_RECEIVED must be a usize");
+
+        Self {
+            max_received,
+            max_auto_replied_received,
+        }
+    }
+}
+
+/// Delays slowing down clients that appear to be abusive, e.g., by brute-forcing credentials
+#[derive(Clone, Copy, Debug)]
+pub struct TarpitConfig {
+    /// Delay before greeting any client
+    pub greeting_delay: Duration,
+    /// Number of failed authentication attempts on a connection after which replies are delayed,
+    /// zero disables the incremental delays
+    pub auth_failure_threshold: u32,
+    /// Delay added for every failed attempt from the threshold onward
+    pub delay_step: Duration,
+    /// Maximum delay of a single reply
+    pub max_delay: Duration,
+}
+
+impl Default for TarpitConfig {
+    /// Reads the delays (in milliseconds) and threshold from the `SMTP_GREETING_DELAY_MS`,
+    /// `SMTP_TARPIT_AUTH_FAILURES`, `SMTP_TARPIT_DELAY_MS`, and `SMTP_TARPIT_MAX_DELAY_MS`
+    /// environment variables, defaulting to 0, 3, 1000, and 30000 respectively
+    ///
+    /// Will panic if any of them is set to anything that cannot be parsed as an integer
+    fn default() -> Self {
+        let millis = |var: &str, default: u64| {
+            Duration::from_millis(
+                env::var(var)
+                    .map(|ms| ms.parse().unwrap_or_else(|_| panic!("{var} must be a u64")))
+                    .unwrap_or(default),
+            )
+        };
+
+        Self {
+            greeting_delay: millis("SMTP_GREETING_DELAY_MS", 0),
+            auth_failure_threshold: env::var("SMTP_TARPIT_AUTH_FAILURES")
+                .unwrap_or("3".to_owned())
+                .parse()
+                .expect("SMTP_TARPIT_AUTH_FAILURES must be a u32"),
+            delay_step: millis("SMTP_TARPIT_DELAY_MS", 1000),
+            max_delay: millis("SMTP_TARPIT_MAX_DELAY_MS", 30_000),
+        }
+    }
+}
+
+impl Default for SmtpConfig {
+    fn default() -> Self {
+        // `SMTP_LISTENERS` configures any number of listeners, `SMTP_LISTEN_ADDR` a single
+        // implicit TLS listener
+        let listeners = match env::var("SMTP_LISTENERS") {
+            Ok(listeners) => SmtpListener::parse_list(&listeners).expect(
+                "SMTP_LISTENERS must be a comma-separated list of `address=mode`, \
+                 where the mode is `implicit` or `starttls`",
+            ),
+            Err(_) => vec![SmtpListener {
+                addr: env::var("SMTP_LISTEN_ADDR")
+                    .expect("Missing SMTP_LISTEN_ADDR or SMTP_LISTENERS environment variable")
+                    .parse()
+                    .expect("Invalid SMTP_LISTEN_ADDR"),
+                tls: TlsMode::Implicit,
+            }],
+        };
         let server_name =
             env::var("SMTP_SERVER_NAME").expect("Missing SMTP_SERVER_NAME environment variable");
+        let banner = env::var("SMTP_BANNER")
+            .ok()
+            .filter(|banner| !banner.is_empty());
+        let challenge_response_auth =
+            env::var("SMTP_CHALLENGE_RESPONSE_AUTH").is_ok_and(|v| v == "true");
         let cert_file = env::var("SMTP_CERT_FILE")
             .expect("Missing SMTP_CERT_FILE environment variable")
             .parse()
@@ -32,14 +176,35 @@ impl Default for SmtpConfig {
             .expect("Missing SMTP_KEY_FILE environment variable")
             .parse()
             .expect("Invalid SMTP_KEY_FILE path");
+        let certificate_expiry_warning = chrono::Duration::days(
+            env::var("SMTP_CERT_EXPIRY_WARNING_DAYS")
+                .unwrap_or("14".to_owned())
+                .parse()
+                .expect("SMTP_CERT_EXPIRY_WARNING_DAYS must be a number of days"),
+        );
 
         Self {
-            listen_addr,
+            listeners,
             server_name,
+            banner,
             cert_file,
             key_file,
             environment: Environment::from_env(),
             retry: Default::default(),
+            loop_detection: Default::default(),
+            tarpit: Default::default(),
+            challenge_response_auth,
+            certificate_expiry_warning,
+        }
+    }
+}
+
+impl SmtpConfig {
+    /// The text of the greeting sent when a client connects (RFC 5321, 4.3.1)
+    pub(crate) fn greeting(&self) -> String {
+        match &self.banner {
+            Some(banner) => format!("{} {banner}", self.server_name),
+            None => self.server_name.clone(),
         }
     }
 }
@@ -48,34 +213,80 @@ impl Default for SmtpConfig {
 mod test {
     use crate::{
         bus::client::BusClient,
+        handler::Handler,
         models::{
             Label, MessageRepository, MessageStatus, SmtpCredentialRepository,
-            SmtpCredentialRequest,
+            SmtpCredentialRequest, SmtpCredentialUpdateRequest,
+        },
+        smtp::{
+            LoopDetectionConfig, SmtpConfig, SmtpListener, TarpitConfig, TlsMode,
+            server::SmtpServer,
         },
-        smtp::{SmtpConfig, server::SmtpServer},
         test::{TestProjects, random_port},
     };
+    use base64ct::Encoding;
+    use hmac::{Hmac, KeyInit, Mac};
     use mail_builder::headers::text::Text;
     use mail_parser::MessageParser;
     use mail_send::{SmtpClientBuilder, mail_builder::MessageBuilder};
+    use md5::Md5;
     use sqlx::PgPool;
     use std::{
         net::{Ipv4Addr, SocketAddrV4},
         sync::Arc,
+        time::{Duration, Instant},
+    };
+    use tokio::{
+        io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader},
+        task::JoinHandle,
     };
-    use tokio::task::JoinHandle;
     use tokio_util::sync::CancellationToken;
 
     async fn setup_server(
         pool: PgPool,
     ) -> (CancellationToken, JoinHandle<()>, u16, String, String) {
-        let smtp_port = random_port();
+        setup_server_with_tarpit(pool, Default::default()).await
+    }
+
+    async fn setup_server_with_tarpit(
+        pool: PgPool,
+        tarpit: TarpitConfig,
+    ) -> (CancellationToken, JoinHandle<()>, u16, String, String) {
+        setup_server_with_config(pool, tarpit, false).await
+    }
+
+    async fn setup_server_with_config(
+        pool: PgPool,
+        tarpit: TarpitConfig,
+        challenge_response_auth: bool,
+    ) -> (CancellationToken, JoinHandle<()>, u16, String, String) {
+        let (shutdown, server_handle, ports, username, password) = setup_server_with_listeners(
+            pool,
+            tarpit,
+            challenge_response_auth,
+            &[TlsMode::Implicit],
+        )
+        .await;
+
+        (shutdown, server_handle, ports[0], username, password)
+    }
+
+    /// Start a server with a listener on a random port for each of `tls_modes`,
+    /// returns the ports in the same order
+    async fn setup_server_with_listeners(
+        pool: PgPool,
+        tarpit: TarpitConfig,
+        challenge_response_auth: bool,
+        tls_modes: &[TlsMode],
+    ) -> (CancellationToken, JoinHandle<()>, Vec<u16>, String, String) {
+        let smtp_ports = tls_modes.iter().map(|_| random_port()).collect::<Vec<_>>();
 
         let (org_id, project_id) = TestProjects::Org1Project1.get_ids();
 
         let credential_request = SmtpCredentialRequest {
             username: "john".to_string(),
             description: "Test SMTP credential description".to_string(),
+            cram_md5: true,
         };
 
         let credential_repo = SmtpCredentialRepository::new(pool.clone());
@@ -89,12 +300,21 @@ mod test {
             .await
             .unwrap();
 
-        let socket = SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), smtp_port);
+        let listeners = smtp_ports
+            .iter()
+            .zip(tls_modes)
+            .map(|(port, tls)| SmtpListener {
+                addr: SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), *port).into(),
+                tls: *tls,
+            })
+            .collect();
         let config = Arc::new(SmtpConfig {
-            listen_addr: socket.into(),
+            listeners,
             server_name: "localhost".to_string(),
             cert_file: "dev-secrets/cert.pem".into(),
             key_file: "dev-secrets/key.pem".into(),
+            tarpit,
+            challenge_response_auth,
             ..Default::default()
         });
         let shutdown = CancellationToken::new();
@@ -110,7 +330,7 @@ mod test {
         (
             shutdown,
             server_handle,
-            smtp_port,
+            smtp_ports,
             credential.username(),
             credential.cleartext_password(),
         )
@@ -164,6 +384,11 @@ mod test {
             .unwrap();
         assert_eq!(received_messages.len(), 1);
         assert_eq!(received_messages[0].status, MessageStatus::Processing);
+        // the client connected directly, without a load balancer in between
+        assert_eq!(
+            received_messages[0].client_ip,
+            Some(Ipv4Addr::LOCALHOST.into())
+        );
         assert_eq!(
             received_messages[0].from_email,
             "john@test-org-1-project-1.com".parse().unwrap()
@@ -320,6 +545,140 @@ mod test {
         );
     }
 
+    /// Accepts connections on a random port, and sends the message data of each delivery
+    /// with the transparency procedure undone
+    async fn capturing_receiver(data_tx: tokio::sync::mpsc::UnboundedSender<Vec<u8>>) -> u16 {
+        let port = random_port();
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .await
+            .unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let data_tx = data_tx.clone();
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    stream.write_all(b"220 localhost ESMTP\r\n").await?;
+                    let mut line = Vec::new();
+                    loop {
+                        line.clear();
+                        if stream.read_until(b'\n', &mut line).await? == 0 {
+                            break;
+                        }
+                        let reply: &[u8] = if line.eq_ignore_ascii_case(b"DATA\r\n") {
+                            stream.write_all(b"354 Start mail input\r\n").await?;
+                            let mut data = Vec::new();
+                            loop {
+                                line.clear();
+                                stream.read_until(b'\n', &mut line).await?;
+                                if line == b".\r\n" {
+                                    break;
+                                }
+                                data.extend_from_slice(
+                                    line.strip_prefix(b".").unwrap_or(&line[..]),
+                                );
+                            }
+                            // the line break before the end of data indicator
+                            data.truncate(data.len().saturating_sub(2));
+                            data_tx.send(data).unwrap();
+                            b"250 2.0.0 OK\r\n"
+                        } else if line.to_ascii_uppercase().starts_with(b"QUIT") {
+                            stream.write_all(b"221 2.0.0 Bye\r\n").await?;
+                            break;
+                        } else {
+                            b"250 OK\r\n"
+                        };
+                        stream.write_all(reply).await?;
+                    }
+                    Ok::<_, std::io::Error>(())
+                });
+            }
+        });
+
+        port
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_relay_period_prefixed_lines(pool: PgPool) {
+        let (shutdown, server_handle, port, username, pwd) = setup_server(pool.clone()).await;
+
+        // lines starting with periods, including a line with a single period and one that
+        // only looks like the end of data indicator after unstuffing
+        let raw = "From: \"John Doe\" <john@test-org-1-project-1.com>\r\n\
+            To: \"Jane Doe\" <jane@test-org-1-project-1.com>\r\n\
+            Subject: Periods\r\n\
+            \r\n\
+            .leading period\r\n\
+            ..two leading periods\r\n\
+            .\r\n\
+            ..\r\n\
+            . \r\n\
+            middle.of.line.\r\n\
+            the end\r\n";
+
+        SmtpClientBuilder::new("localhost", port)
+            .implicit_tls(true)
+            .allow_invalid_certs()
+            .credentials((username.as_str(), pwd.as_str()))
+            .connect()
+            .await
+            .unwrap()
+            .send(mail_send::smtp::message::Message::new(
+                "john@test-org-1-project-1.com",
+                ["jane@test-org-1-project-1.com"],
+                raw.as_bytes(),
+            ))
+            .await
+            .unwrap();
+
+        shutdown.cancel();
+        server_handle.await.unwrap();
+
+        // the message is stored exactly as submitted
+        let org_id = TestProjects::Org1Project1.org_id();
+        let messages = MessageRepository::new(pool.clone());
+        let received_messages = messages
+            .list_message_metadata(org_id, Default::default())
+            .await
+            .unwrap();
+        assert_eq!(received_messages.len(), 1);
+        let mut message = messages
+            .get_if_org_may_send(received_messages[0].id)
+            .await
+            .unwrap();
+        assert_eq!(message.raw_data, raw.as_bytes());
+
+        // and relayed exactly as stored, including the headers added by the handler
+        let (data_tx, mut data_rx) = tokio::sync::mpsc::unbounded_channel();
+        let receiver_port = capturing_receiver(data_tx).await;
+        let handler = Handler::test_handler(pool, receiver_port, None).await;
+        handler.handle_message(&mut message).await.unwrap();
+        let expected = message.raw_data.clone();
+        handler
+            .send_message(message, "127.0.0.1".parse().unwrap())
+            .await
+            .unwrap();
+
+        let relayed = tokio::time::timeout(Duration::from_secs(5), data_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&relayed),
+            String::from_utf8_lossy(&expected)
+        );
+        assert_eq!(relayed, expected);
+    }
+
     #[sqlx::test(fixtures(
         path = "../fixtures",
         scripts("organizations", "projects", "org_domains", "proj_domains")
@@ -339,4 +698,476 @@ mod test {
         shutdown.cancel();
         server_handle.await.unwrap();
     }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_mail_loop_rejected(pool: PgPool) {
+        let (shutdown, server_handle, port, username, pwd) = setup_server(pool.clone()).await;
+
+        let received = "Received: from mx.test-org-1-project-1.com (mx.test-org-1-project-1.com [192.0.2.1])\r\n\
+            \tby remails.net; Thu, 20 Nov 2025 14:33:48 +0000\r\n";
+
+        // an auto-reply that has been passed around too often
+        let looping = format!(
+            "{}Auto-Submitted: auto-replied\r\n\
+            From: \"John Doe\" <john@test-org-1-project-1.com>\r\n\
+            To: \"Jane Doe\" <jane@test-org-1-project-1.com>\r\n\
+            Subject: Out of office\r\n\
+            \r\n\
+            I'm out of office!",
+            received.repeat(LoopDetectionConfig::default().max_auto_replied_received + 1)
+        );
+        let message = MessageParser::default().parse(&looping).unwrap();
+
+        let result = SmtpClientBuilder::new("localhost", port)
+            .implicit_tls(true)
+            .allow_invalid_certs()
+            .credentials((username.as_str(), pwd.as_str()))
+            .connect()
+            .await
+            .unwrap()
+            .send(message)
+            .await;
+        assert!(result.is_err());
+
+        // a regular message with some hops is accepted
+        let normal = format!(
+            "{}From: \"John Doe\" <john@test-org-1-project-1.com>\r\n\
+            To: \"Jane Doe\" <jane@test-org-1-project-1.com>\r\n\
+            Subject: Hi!\r\n\
+            \r\n\
+            Hello world!",
+            received.repeat(2)
+        );
+        let message = MessageParser::default().parse(&normal).unwrap();
+
+        SmtpClientBuilder::new("localhost", port)
+            .implicit_tls(true)
+            .allow_invalid_certs()
+            .credentials((username.as_str(), pwd.as_str()))
+            .connect()
+            .await
+            .unwrap()
+            .send(message)
+            .await
+            .unwrap();
+
+        shutdown.cancel();
+        server_handle.await.unwrap();
+
+        // only the regular message should be stored
+        let org_id = TestProjects::Org1Project1.org_id();
+        let messages = MessageRepository::new(pool);
+        let received_messages = messages
+            .list_message_metadata(org_id, Default::default())
+            .await
+            .unwrap();
+        assert_eq!(received_messages.len(), 1);
+        assert_eq!(
+            received_messages[0].from_email,
+            "john@test-org-1-project-1.com".parse().unwrap()
+        );
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_credential_restrictions(pool: PgPool) {
+        let (shutdown, server_handle, port, username, pwd) = setup_server(pool.clone()).await;
+
+        // only allow sending to the domain of the project
+        let (org_id, project_id) = TestProjects::Org1Project1.get_ids();
+        let credential_repo = SmtpCredentialRepository::new(pool.clone());
+        let credential = credential_repo
+            .find_by_username(&username)
+            .await
+            .unwrap()
+            .unwrap();
+        credential_repo
+            .update(
+                org_id,
+                project_id,
+                credential.id(),
+                &SmtpCredentialUpdateRequest {
+                    description: "Restricted credential".to_string(),
+                    allowed_recipient_domains: Some(Some(vec![
+                        "test-org-1-project-1.com".to_string(),
+                    ])),
+                    allowed_sender_addresses: None,
+                },
+                crate::models::SYSTEM,
+            )
+            .await
+            .unwrap();
+
+        let message = |to: &'static str| {
+            MessageBuilder::new()
+                .from(("John Doe", "john@test-org-1-project-1.com"))
+                .to(to)
+                .subject("Hi!")
+                .text_body("Hello world!")
+        };
+
+        // a recipient outside of the allowed domains is rejected
+        let result = SmtpClientBuilder::new("localhost", port)
+            .implicit_tls(true)
+            .allow_invalid_certs()
+            .credentials((username.as_str(), pwd.as_str()))
+            .connect()
+            .await
+            .unwrap()
+            .send(message("james@test.com"))
+            .await;
+        assert!(result.is_err());
+
+        // an allowed recipient is accepted
+        SmtpClientBuilder::new("localhost", port)
+            .implicit_tls(true)
+            .allow_invalid_certs()
+            .credentials((username.as_str(), pwd.as_str()))
+            .connect()
+            .await
+            .unwrap()
+            .send(message("jane@test-org-1-project-1.com"))
+            .await
+            .unwrap();
+
+        shutdown.cancel();
+        server_handle.await.unwrap();
+
+        let messages = MessageRepository::new(pool);
+        let received_messages = messages
+            .list_message_metadata(org_id, Default::default())
+            .await
+            .unwrap();
+        assert_eq!(received_messages.len(), 1);
+        assert_eq!(
+            received_messages[0].recipients,
+            vec!["jane@test-org-1-project-1.com".parse().unwrap()]
+        );
+    }
+
+    /// Read the replies to `count` commands, returning their status codes
+    async fn read_replies(reader: &mut (impl AsyncBufRead + Unpin), count: usize) -> Vec<u16> {
+        let mut codes = Vec::with_capacity(count);
+        let mut line = String::new();
+
+        while codes.len() < count {
+            line.clear();
+            reader.read_line(&mut line).await.unwrap();
+            // the last line of a multiline reply has a space after the code
+            if line.as_bytes()[3] == b' ' {
+                codes.push(line[..3].parse().unwrap());
+            }
+        }
+
+        codes
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_pipelining(pool: PgPool) {
+        let (shutdown, server_handle, port, username, pwd) = setup_server(pool.clone()).await;
+
+        let client = SmtpClientBuilder::new("localhost", port)
+            .implicit_tls(true)
+            .allow_invalid_certs()
+            .credentials((username.as_str(), pwd.as_str()))
+            .connect()
+            .await
+            .unwrap();
+        let mut stream = BufReader::new(client.stream);
+
+        // improper sequencing is still rejected
+        stream
+            .get_mut()
+            .write_all(b"RCPT TO:<jane@test.com>\r\nDATA\r\n")
+            .await
+            .unwrap();
+        assert_eq!(read_replies(&mut stream, 2).await, [503, 503]);
+
+        // the replies to a group of commands are sent in order
+        stream
+            .get_mut()
+            .write_all(
+                b"MAIL FROM:<john@test-org-1-project-1.com>\r\n\
+                RCPT TO:<jane@test.com>\r\n\
+                RCPT TO:<not-an-address>\r\n\
+                RCPT TO:<james@test.com>\r\n\
+                DATA\r\n",
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            read_replies(&mut stream, 5).await,
+            [250, 250, 553, 250, 354]
+        );
+
+        // commands may directly follow the end of the message data
+        stream
+            .get_mut()
+            .write_all(
+                b"From: john@test-org-1-project-1.com\r\n\
+                To: jane@test.com\r\n\
+                Subject: Hi!\r\n\
+                \r\n\
+                Hello world!\r\n\
+                .\r\n\
+                NOOP\r\n\
+                QUIT\r\n",
+            )
+            .await
+            .unwrap();
+        assert_eq!(read_replies(&mut stream, 3).await, [250, 250, 221]);
+
+        shutdown.cancel();
+        server_handle.await.unwrap();
+
+        let org_id = TestProjects::Org1Project1.org_id();
+        let messages = MessageRepository::new(pool);
+        let received_messages = messages
+            .list_message_metadata(org_id, Default::default())
+            .await
+            .unwrap();
+        assert_eq!(received_messages.len(), 1);
+        assert_eq!(
+            received_messages[0].recipients,
+            vec![
+                "jane@test.com".parse().unwrap(),
+                "james@test.com".parse().unwrap()
+            ]
+        );
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "projects", "org_domains", "proj_domains")
+    ))]
+    async fn test_tarpit_after_auth_failures(pool: PgPool) {
+        let step = Duration::from_millis(300);
+        let tarpit = TarpitConfig {
+            greeting_delay: Duration::ZERO,
+            auth_failure_threshold: 2,
+            delay_step: step,
+            max_delay: Duration::from_secs(5),
+        };
+        let (shutdown, server_handle, port, username, pwd) =
+            setup_server_with_tarpit(pool, tarpit).await;
+
+        let client = SmtpClientBuilder::new("localhost", port)
+            .implicit_tls(true)
+            .allow_invalid_certs()
+            .connect()
+            .await
+            .unwrap();
+        let mut stream = BufReader::new(client.stream);
+
+        let mut attempt = async |password: &str| {
+            let credentials =
+                base64ct::Base64::encode_string(format!("\0{username}\0{password}").as_bytes());
+            let start = Instant::now();
+            stream
+                .get_mut()
+                .write_all(format!("AUTH PLAIN {credentials}\r\n").as_bytes())
+                .await
+                .unwrap();
+            let code = read_replies(&mut stream, 1).await[0];
+            (code, start.elapsed())
+        };
+
+        // the first failure is answered right away
+        let (code, elapsed) = attempt("wrong").await;
+        assert_eq!(code, 535);
+        assert!(elapsed < step);
+
+        // from the threshold onward, every failure increases the delay
+        let (code, elapsed) = attempt("wrong").await;
+        assert_eq!(code, 535);
+        assert!(elapsed >= step);
+
+        let (code, elapsed) = attempt("wrong").await;
+        assert_eq!(code, 535);
+        assert!(elapsed >= 2 * step);
+
+        // a client that authenticates successfully is no longer delayed
+        let (code, elapsed) = attempt(&pwd).await;
+        assert_eq!(code, 235);
+        assert!(elapsed < step);
+
+        shutdown.cancel();
+        server_handle.await.unwrap();
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts("organizations", "projects", "smtp_credentials")
+    ))]
+    async fn test_cram_md5_auth(pool: PgPool) {
+        let (shutdown, server_handle, port, username, pwd) =
+            setup_server_with_config(pool, Default::default(), true).await;
+
+        let client = SmtpClientBuilder::new("localhost", port)
+            .implicit_tls(true)
+            .allow_invalid_certs()
+            .connect()
+            .await
+            .unwrap();
+        let mut stream = BufReader::new(client.stream);
+
+        let mut attempt = async |username: &str, password: &str| {
+            stream
+                .get_mut()
+                .write_all(b"AUTH CRAM-MD5\r\n")
+                .await
+                .unwrap();
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            let challenge = line.trim_end().strip_prefix("334 ").unwrap();
+            let challenge = base64ct::Base64::decode_vec(challenge).unwrap();
+
+            let mut mac = Hmac::<Md5>::new_from_slice(password.as_bytes()).unwrap();
+            mac.update(&challenge);
+            let digest = mac
+                .finalize()
+                .into_bytes()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect::<String>();
+            let response =
+                base64ct::Base64::encode_string(format!("{username} {digest}").as_bytes());
+            stream
+                .get_mut()
+                .write_all(format!("{response}\r\n").as_bytes())
+                .await
+                .unwrap();
+            read_replies(&mut stream, 1).await[0]
+        };
+
+        // the password has to match
+        assert_eq!(attempt(&username, "wrong").await, 535);
+
+        // credentials that are not provisioned for CRAM-MD5 can't use it
+        assert_eq!(attempt("marc", "unknown").await, 535);
+
+        assert_eq!(attempt(&username, &pwd).await, 235);
+
+        shutdown.cancel();
+        server_handle.await.unwrap();
+    }
+
+    #[test]
+    fn parse_listeners() {
+        assert_eq!(
+            SmtpListener::parse_list("0.0.0.0:465=implicit, [::]:587=starttls").unwrap(),
+            [
+                SmtpListener {
+                    addr: "0.0.0.0:465".parse().unwrap(),
+                    tls: TlsMode::Implicit,
+                },
+                SmtpListener {
+                    addr: "[::]:587".parse().unwrap(),
+                    tls: TlsMode::StartTls,
+                },
+            ]
+        );
+
+        assert!(SmtpListener::parse_list("").is_none());
+        assert!(SmtpListener::parse_list("0.0.0.0:465").is_none());
+        assert!(SmtpListener::parse_list("0.0.0.0:465=tls").is_none());
+        assert!(SmtpListener::parse_list("localhost:465=implicit").is_none());
+    }
+
+    #[sqlx::test(fixtures(
+        path = "../fixtures",
+        scripts(
+            "organizations",
+            "projects",
+            "org_domains",
+            "proj_domains",
+            "k8s_nodes"
+        )
+    ))]
+    async fn test_implicit_tls_and_starttls_listeners(pool: PgPool) {
+        let (shutdown, server_handle, ports, username, pwd) = setup_server_with_listeners(
+            pool.clone(),
+            Default::default(),
+            false,
+            &[TlsMode::Implicit, TlsMode::StartTls],
+        )
+        .await;
+
+        let message = |subject: &str| {
+            MessageBuilder::new()
+                .from(("John Doe", "john@test-org-1-project-1.com"))
+                .to(("Jane Doe", "jane@test-org-1-project-1.com"))
+                .subject(subject.to_owned())
+                .text_body("Hello world!")
+        };
+
+        // both listeners accept messages, using their own TLS mode
+        for (port, implicit_tls) in [(ports[0], true), (ports[1], false)] {
+            SmtpClientBuilder::new("localhost", port)
+                .implicit_tls(implicit_tls)
+                .allow_invalid_certs()
+                .credentials((username.as_str(), pwd.as_str()))
+                .connect()
+                .await
+                .unwrap()
+                .send(message(&format!("implicit TLS: {implicit_tls}")))
+                .await
+                .unwrap();
+        }
+
+        // the STARTTLS listener does not accept credentials or messages in plaintext
+        let stream = tokio::net::TcpStream::connect(("127.0.0.1", ports[1]))
+            .await
+            .unwrap();
+        let mut stream = BufReader::new(stream);
+        assert_eq!(read_replies(&mut stream, 1).await, [220]);
+        stream
+            .get_mut()
+            .write_all(
+                b"EHLO client.example.com\r\n\
+                AUTH PLAIN AGpvaG4Ac2VjcmV0\r\n\
+                MAIL FROM:<john@test-org-1-project-1.com>\r\n\
+                QUIT\r\n",
+            )
+            .await
+            .unwrap();
+        assert_eq!(read_replies(&mut stream, 4).await, [250, 530, 530, 221]);
+
+        shutdown.cancel();
+        server_handle.await.unwrap();
+
+        let org_id = TestProjects::Org1Project1.org_id();
+        let messages = MessageRepository::new(pool);
+        let received_messages = messages
+            .list_message_metadata(org_id, Default::default())
+            .await
+            .unwrap();
+        assert_eq!(received_messages.len(), 2);
+    }
 }
@@ -0,0 +1,254 @@
+use aws_lc_rs::hmac;
+use base64ct::{Base64, Encoding};
+use rand::distr::{Alphanumeric, SampleString};
+use std::sync::LazyLock;
+
+use crate::models::ScramVerifier;
+
+/// Key to derive the made up salts of unknown users from, see [`unknown_user_verifier`]
+static UNKNOWN_USER_SALT_KEY: LazyLock<hmac::Key> =
+    LazyLock::new(|| hmac::Key::new(hmac::HMAC_SHA256, &rand::random::<[u8; 32]>()));
+
+/// Split a CRAM-MD5 response into the username and the HMAC-MD5 digest (RFC 2195, 2)
+pub(super) fn parse_cram_md5(response: &str) -> Option<(&str, Vec<u8>)> {
+    let (username, digest) = response.rsplit_once(' ')?;
+    if digest.len() != 32 {
+        return None;
+    }
+
+    let digest = (0..digest.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(digest.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+
+    Some((username, digest))
+}
+
+/// The first message of a SCRAM exchange (RFC 5802, 7)
+pub(super) struct ClientFirst<'a> {
+    gs2_header: &'a str,
+    bare: &'a str,
+    nonce: &'a str,
+    pub username: String,
+}
+
+impl<'a> ClientFirst<'a> {
+    pub fn parse(message: &'a str) -> Option<Self> {
+        let mut parts = message.splitn(3, ',');
+        let channel_binding = parts.next()?;
+        let _authzid = parts.next()?;
+        let bare = parts.next()?;
+
+        // channel binding is not supported, as the PLUS variants are not advertised
+        if channel_binding != "n" && channel_binding != "y" {
+            return None;
+        }
+
+        // mandatory extensions would precede the username, and are not supported either
+        let mut attributes = bare.split(',');
+        let username = decode_saslname(attributes.next()?.strip_prefix("n=")?)?;
+        let nonce = attributes.next()?.strip_prefix("r=")?;
+        if nonce.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            gs2_header: &message[..message.len() - bare.len()],
+            bare,
+            nonce,
+            username,
+        })
+    }
+}
+
+/// Decode `=2C` and `=3D` in a username, which encode `,` and `=` respectively
+fn decode_saslname(name: &str) -> Option<String> {
+    let mut decoded = String::with_capacity(name.len());
+    let mut rest = name;
+
+    while let Some(i) = rest.find('=') {
+        decoded.push_str(&rest[..i]);
+        match rest.get(i + 1..i + 3)? {
+            "2C" => decoded.push(','),
+            "3D" => decoded.push('='),
+            _ => return None,
+        }
+        rest = &rest[i + 3..];
+    }
+    decoded.push_str(rest);
+
+    Some(decoded)
+}
+
+/// A made up verifier for a username that does not exist (RFC 5802, 5.1)
+///
+/// The salt is derived from the username, such that repeated attempts for the same username
+/// get the same salt, just like for a username that does exist.
+pub(super) fn unknown_user_verifier(username: &str) -> ScramVerifier {
+    let salt = hmac::sign(&UNKNOWN_USER_SALT_KEY, username.as_bytes());
+
+    ScramVerifier::unknown_user(&salt.as_ref()[..16])
+}
+
+/// The server side of a SCRAM-SHA-256 exchange, after receiving the client's first message
+pub(super) struct ScramExchange {
+    verifier: ScramVerifier,
+    gs2_header: String,
+    nonce: String,
+    /// `client-first-message-bare "," server-first-message`, the start of the `AuthMessage`
+    auth_message_prefix: String,
+    server_first: String,
+}
+
+impl ScramExchange {
+    pub fn new(client_first: &ClientFirst<'_>, verifier: ScramVerifier) -> Self {
+        let server_nonce = Alphanumeric.sample_string(&mut rand::rng(), 24);
+
+        Self::with_server_nonce(client_first, verifier, &server_nonce)
+    }
+
+    fn with_server_nonce(
+        client_first: &ClientFirst<'_>,
+        verifier: ScramVerifier,
+        server_nonce: &str,
+    ) -> Self {
+        let nonce = format!("{}{server_nonce}", client_first.nonce);
+        let server_first = format!(
+            "r={nonce},s={},i={}",
+            Base64::encode_string(verifier.salt()),
+            verifier.iterations()
+        );
+
+        Self {
+            auth_message_prefix: format!("{},{server_first}", client_first.bare),
+            gs2_header: client_first.gs2_header.to_owned(),
+            verifier,
+            nonce,
+            server_first,
+        }
+    }
+
+    pub fn server_first(&self) -> &str {
+        &self.server_first
+    }
+
+    /// Verify the final message of the client, returning the final message of the server
+    /// if the client proved to know the password
+    pub fn finish(&self, client_final: &str) -> Option<String> {
+        let (without_proof, proof) = client_final.rsplit_once(",p=")?;
+        let mut attributes = without_proof.split(',');
+        let channel_binding = attributes.next()?.strip_prefix("c=")?;
+        let nonce = attributes.next()?.strip_prefix("r=")?;
+
+        if Base64::decode_vec(channel_binding).ok()? != self.gs2_header.as_bytes()
+            || nonce != self.nonce
+        {
+            return None;
+        }
+
+        let proof = Base64::decode_vec(proof).ok()?;
+        let auth_message = format!("{},{without_proof}", self.auth_message_prefix);
+        if !self.verifier.verify_proof(&auth_message, &proof) {
+            return None;
+        }
+
+        let signature = self.verifier.server_signature(&auth_message);
+
+        Some(format!("v={}", Base64::encode_string(&signature)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroU32;
+
+    #[test]
+    fn test_parse_cram_md5() {
+        // example from RFC 2195
+        let (username, digest) = parse_cram_md5("tim b913a602c7eda7a495b4e6e7334d3890").unwrap();
+        assert_eq!(username, "tim");
+        assert_eq!(digest[..4], [0xb9, 0x13, 0xa6, 0x02]);
+
+        assert!(parse_cram_md5("tim").is_none());
+        assert!(parse_cram_md5("tim b913a602").is_none());
+        assert!(parse_cram_md5("tim b913a602c7eda7a495b4e6e7334d389z").is_none());
+    }
+
+    #[test]
+    fn test_scram_sha256_exchange() {
+        // example from RFC 7677, 3
+        let verifier = ScramVerifier::derive(
+            "pencil",
+            &Base64::decode_vec("W22ZaJ0SNY7soEsUEjb6gQ==").unwrap(),
+            NonZeroU32::new(4096).unwrap(),
+        );
+
+        let client_first = ClientFirst::parse("n,,n=user,r=rOprNGfwEbeRWgbNEkqO").unwrap();
+        assert_eq!(client_first.username, "user");
+
+        let exchange = ScramExchange::with_server_nonce(
+            &client_first,
+            verifier,
+            "%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0",
+        );
+        assert_eq!(
+            exchange.server_first(),
+            "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096"
+        );
+
+        let server_final = exchange.finish(
+            "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=",
+        );
+        assert_eq!(
+            server_final.as_deref(),
+            Some("v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=")
+        );
+
+        // a wrong proof or nonce is rejected
+        assert!(
+            exchange
+                .finish("c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=AAAAZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=")
+                .is_none()
+        );
+        assert!(
+            exchange
+                .finish(
+                    "c=biws,r=rOprNGfwEbeRWgbNEkqO,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+                )
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_unknown_user_verifier() {
+        // the salt of an unknown user does not change, like that of an existing user
+        let verifier = unknown_user_verifier("unknown");
+        assert_eq!(verifier.salt(), unknown_user_verifier("unknown").salt());
+        assert_ne!(verifier.salt(), unknown_user_verifier("other").salt());
+        assert_eq!(verifier.salt().len(), 16);
+
+        // the exchange continues, but no proof is accepted
+        let client_first = ClientFirst::parse("n,,n=unknown,r=rOprNGfwEbeRWgbNEkqO").unwrap();
+        let exchange = ScramExchange::with_server_nonce(&client_first, verifier, "nonce");
+        assert!(exchange.server_first().contains(",i=4096"));
+        assert!(
+            exchange
+                .finish("c=biws,r=rOprNGfwEbeRWgbNEkqOnonce,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_parse_client_first() {
+        let client_first = ClientFirst::parse("y,,n=a=2Cb=3Dc,r=abc").unwrap();
+        assert_eq!(client_first.username, "a,b=c");
+        assert_eq!(client_first.gs2_header, "y,,");
+
+        // channel binding and mandatory extensions are not supported
+        assert!(ClientFirst::parse("p=tls-unique,,n=user,r=abc").is_none());
+        assert!(ClientFirst::parse("n,,m=ext,n=user,r=abc").is_none());
+        assert!(ClientFirst::parse("n,,n=us=er,r=abc").is_none());
+        assert!(ClientFirst::parse("n,,n=user").is_none());
+    }
+}
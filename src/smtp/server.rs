@@ -3,26 +3,35 @@ use crate::{
     bus::client::BusClient,
     models::{MessageRepository, SmtpCredentialRepository},
     smtp::{
-        SmtpConfig,
+        LoopDetectionConfig, SmtpConfig, TarpitConfig, TlsMode,
+        certificate::{self, CertificateStatus},
         connection::{self, ConnectionError},
         proxy_protocol::{self, Error, handle_proxy_protocol},
     },
 };
 use rand::random_range;
 use sqlx::PgPool;
-use std::{fs::File, io, sync::Arc, time::Duration};
+use std::{
+    fs::File,
+    io,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 use thiserror::Error;
-use tokio::{io::AsyncWriteExt, net::TcpListener, select, sync::RwLock, task::JoinHandle};
+use tokio::{io::AsyncWriteExt, net::TcpListener, select, sync::watch, task::JoinHandle};
 use tokio_rustls::{
     TlsAcceptor,
     rustls::{
         self, crypto,
         crypto::CryptoProvider,
         pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+        server::{ClientHello, ResolvesServerCert},
+        sign::CertifiedKey,
     },
 };
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, error, info, info_span, trace, trace_span};
+use tracing::{debug, error, info, info_span, trace, trace_span, warn};
 
 #[derive(Debug, Error)]
 pub enum SmtpServerError {
@@ -42,12 +51,53 @@ pub enum SmtpServerError {
     ProxyProtocol(#[from] proxy_protocol::Error),
 }
 
+/// Resolves the certificate of new TLS handshakes, such that it can be replaced without
+/// restarting the server
+///
+/// Connections that completed their handshake before keep using the previous certificate.
+#[derive(Debug)]
+struct ReloadableCertificate(RwLock<Arc<CertifiedKey>>);
+
+impl ReloadableCertificate {
+    fn new(certified_key: Arc<CertifiedKey>) -> Self {
+        Self(RwLock::new(certified_key))
+    }
+
+    fn replace(&self, certified_key: Arc<CertifiedKey>) {
+        *self.0.write().unwrap() = certified_key;
+    }
+}
+
+impl ResolvesServerCert for ReloadableCertificate {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.read().unwrap().clone())
+    }
+}
+
+/// Everything needed to handle a connection, shared by all listeners
+#[derive(Clone)]
+struct ConnectionContext {
+    environment: Environment,
+    acceptor: TlsAcceptor,
+    server_name: String,
+    greeting: String,
+    bus_client: BusClient,
+    user_repository: SmtpCredentialRepository,
+    message_repository: MessageRepository,
+    max_automatic_retries: i32,
+    loop_detection: LoopDetectionConfig,
+    tarpit: TarpitConfig,
+    challenge_response_auth: bool,
+}
+
 pub struct SmtpServer {
     user_repository: SmtpCredentialRepository,
     message_repository: MessageRepository,
     bus_client: BusClient,
     shutdown: CancellationToken,
     config: Arc<SmtpConfig>,
+    /// The expiry of the currently loaded certificate
+    certificate: Arc<watch::Sender<Option<CertificateStatus>>>,
 }
 
 impl SmtpServer {
@@ -67,9 +117,15 @@ impl SmtpServer {
             bus_client,
             shutdown,
             config,
+            certificate: Arc::new(watch::Sender::new(None)),
         }
     }
 
+    /// Router of the health endpoint, reporting the expiry of the TLS certificate
+    pub fn health_router(&self) -> axum::Router {
+        certificate::router(self.certificate.subscribe())
+    }
+
     async fn load_tls_config(
         &self,
     ) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), SmtpServerError> {
@@ -87,26 +143,61 @@ impl SmtpServer {
         Ok((certs, key))
     }
 
-    async fn build_tls_acceptor(&self) -> Result<TlsAcceptor, SmtpServerError> {
+    async fn load_certificate(&self) -> Result<Arc<CertifiedKey>, SmtpServerError> {
         let (certs, key) = self.load_tls_config().await?;
 
+        let not_after = certs.first().and_then(certificate::not_after);
+
+        let provider = CryptoProvider::get_default().expect("Crypto provider is not installed");
+        let certified_key =
+            CertifiedKey::from_der(certs, key, provider).map_err(SmtpServerError::Tls)?;
+
+        // only report the expiry of a certificate that is actually served
+        match not_after {
+            Some(not_after) => {
+                let status =
+                    CertificateStatus::new(not_after, self.config.certificate_expiry_warning);
+                status.report();
+                self.certificate.send_replace(Some(status));
+            }
+            None => warn!("could not read the expiry date of the SMTP TLS certificate"),
+        }
+
+        Ok(Arc::new(certified_key))
+    }
+
+    /// Serve the certificate and key files to subsequent handshakes
+    async fn reload_certificate(
+        &self,
+        resolver: &ReloadableCertificate,
+    ) -> Result<(), SmtpServerError> {
+        resolver.replace(self.load_certificate().await?);
+
+        Ok(())
+    }
+
+    fn build_tls_acceptor(resolver: Arc<ReloadableCertificate>) -> TlsAcceptor {
         let config = rustls::ServerConfig::builder()
             .with_no_client_auth()
-            .with_single_cert(certs, key)
-            .map_err(SmtpServerError::Tls)?;
+            .with_cert_resolver(resolver);
 
-        Ok(TlsAcceptor::from(Arc::new(config)))
+        TlsAcceptor::from(Arc::new(config))
     }
 
     pub async fn serve(self) -> Result<(), SmtpServerError> {
-        let environment = self.config.environment;
-        let listener = TcpListener::bind(&self.config.listen_addr)
-            .await
-            .map_err(SmtpServerError::Listen)?;
-
-        let acceptor = Arc::new(RwLock::new(self.build_tls_acceptor().await?));
+        // all listeners are bound before accepting any connection,
+        // such that the server fails right away if any of the addresses is in use
+        let mut listeners = Vec::with_capacity(self.config.listeners.len());
+        for listener in &self.config.listeners {
+            let tcp_listener = TcpListener::bind(listener.addr)
+                .await
+                .map_err(SmtpServerError::Listen)?;
+            info!("smtp server on {} using {}", listener.addr, listener.tls);
+            listeners.push((tcp_listener, listener.tls));
+        }
 
-        info!("smtp server on {}", self.config.listen_addr);
+        let resolver = Arc::new(ReloadableCertificate::new(self.load_certificate().await?));
+        let acceptor = Self::build_tls_acceptor(resolver.clone());
 
         let certificate_reload_interval =
             Duration::from_secs(60 * 60 * 23 + random_range(0..(60 * 60)));
@@ -115,35 +206,80 @@ impl SmtpServer {
             certificate_reload_interval
         );
 
-        let server_name = self.config.server_name.clone();
-        let bus_client = self.bus_client.clone();
-        let user_repository = self.user_repository.clone();
-        let message_repository = self.message_repository.clone();
-        let max_automatic_retries = self.config.retry.max_automatic_retries;
+        let context = ConnectionContext {
+            environment: self.config.environment,
+            acceptor,
+            server_name: self.config.server_name.clone(),
+            greeting: self.config.greeting(),
+            bus_client: self.bus_client.clone(),
+            user_repository: self.user_repository.clone(),
+            message_repository: self.message_repository.clone(),
+            max_automatic_retries: self.config.retry.max_automatic_retries,
+            loop_detection: self.config.loop_detection,
+            tarpit: self.config.tarpit,
+            challenge_response_auth: self.config.challenge_response_auth,
+        };
         let shutdown = self.shutdown.clone();
 
-        let acceptor_clone = acceptor.clone();
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(certificate_reload_interval);
+            interval.tick().await;
+            // the certificate is also reloaded as soon as it changes on disk
+            let mut change_interval = tokio::time::interval(Duration::from_secs(60));
+            let mut modified = certificate::modified(&self.config.cert_file, &self.config.key_file);
             loop {
-                interval.tick().await;
-                let mut a = acceptor_clone.write().await;
+                select! {
+                    _ = interval.tick() => {}
+                    _ = change_interval.tick() => {
+                        let new_modified =
+                            certificate::modified(&self.config.cert_file, &self.config.key_file);
+                        if new_modified == modified {
+                            continue;
+                        }
+                        modified = new_modified;
+                        info!("The SMTP TLS certificate changed on disk");
+                    }
+                }
+
                 info!("Reloading the SMTP TLS certificate");
-                *a = self.build_tls_acceptor().await.unwrap();
+                match self.reload_certificate(&resolver).await {
+                    Ok(()) => {}
+                    Err(e) => error!(
+                        "failed to reload the SMTP TLS certificate, keeping the previous one: {e}"
+                    ),
+                }
             }
         });
+
+        futures::future::join_all(
+            listeners.into_iter().map(|(listener, tls)| {
+                Self::accept(listener, tls, context.clone(), shutdown.clone())
+            }),
+        )
+        .await;
+
+        info!("shutting down smtp server");
+
+        Ok(())
+    }
+
+    /// Accept connections on `listener` until the server shuts down
+    async fn accept(
+        listener: TcpListener,
+        tls: TlsMode,
+        context: ConnectionContext,
+        shutdown: CancellationToken,
+    ) {
         loop {
             select! {
                 _ = shutdown.cancelled() => {
-                    info!("shutting down smtp server");
-
-                    return Ok(());
+                    return;
                 }
                 result = listener.accept() => match result {
                     Ok((mut stream, peer_addr)) => {
 
                         let mut connection_info = None;
-                        if !matches!(environment, Environment::Development) {
+                        if !matches!(context.environment, Environment::Development) {
                             (stream, connection_info) = match handle_proxy_protocol(stream).await {
                                 Ok((stream, connection_info)) => {(stream, connection_info)}
                                 Err(err) => {
@@ -157,6 +293,11 @@ impl SmtpServer {
                             }
                         }
 
+                        // the client address as reported by the load balancer, if any
+                        let client_addr = connection_info.as_ref().map_or(peer_addr, |info| {
+                            SocketAddr::new(info.source_ip, info.source_port)
+                        });
+
                         let span = if let Some(connection_info) = connection_info {
                             info_span!(
                                 "TCP connection",
@@ -173,26 +314,50 @@ impl SmtpServer {
                             )
                         };
                         trace!("new TCP connection");
-                        let acceptor = acceptor.clone();
-                        let server_name = server_name.clone();
-                        let bus_client = bus_client.clone();
-                        let user_repository = user_repository.clone();
-                        let message_repository = message_repository.clone();
+                        let context = context.clone();
 
                         let task = async move || {
-                            let mut tls_stream = acceptor.read().await
+                            let greeting = match tls {
+                                TlsMode::Implicit => Some(context.greeting),
+                                TlsMode::StartTls => {
+                                    let start_tls = connection::negotiate_starttls(
+                                        &mut stream,
+                                        context.server_name.clone(),
+                                        context.greeting,
+                                        client_addr,
+                                        context.tarpit,
+                                    )
+                                    .await?;
+                                    if !start_tls {
+                                        return stream
+                                            .shutdown()
+                                            .await
+                                            .map_err(ConnectionError::Write);
+                                    }
+
+                                    // the client was greeted before switching to TLS
+                                    None
+                                }
+                            };
+
+                            let mut tls_stream = context
+                                .acceptor
                                 .accept(stream)
                                 .await
                                 .map_err(ConnectionError::Accept)?;
 
                             connection::handle(
                                 &mut tls_stream,
-                                server_name,
-                                peer_addr,
-                                bus_client,
-                                user_repository,
-                                message_repository,
-                                max_automatic_retries,
+                                context.server_name,
+                                greeting,
+                                client_addr,
+                                context.bus_client,
+                                context.user_repository,
+                                context.message_repository,
+                                context.max_automatic_retries,
+                                context.loop_detection,
+                                context.tarpit,
+                                context.challenge_response_auth,
                             )
                             .await?;
                             tls_stream.shutdown().await.map_err(ConnectionError::Write)
@@ -227,3 +392,108 @@ impl SmtpServer {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, DuplexStream};
+    use tokio_rustls::{
+        TlsConnector, client,
+        rustls::{ClientConfig, RootCertStore, pki_types::ServerName},
+        server,
+    };
+
+    /// Write a new self-signed certificate for `localhost` to the certificate and key files
+    fn write_certificate(config: &SmtpConfig) -> CertificateDer<'static> {
+        let rcgen::CertifiedKey { cert, signing_key } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        std::fs::write(&config.cert_file, cert.pem()).unwrap();
+        std::fs::write(&config.key_file, signing_key.serialize_pem()).unwrap();
+
+        cert.der().clone()
+    }
+
+    /// Connect to `acceptor`, returning the certificate it presented and both ends of the connection
+    async fn handshake(
+        acceptor: &TlsAcceptor,
+        trusted: &[CertificateDer<'static>],
+    ) -> (
+        CertificateDer<'static>,
+        client::TlsStream<DuplexStream>,
+        server::TlsStream<DuplexStream>,
+    ) {
+        let mut roots = RootCertStore::empty();
+        for certificate in trusted {
+            roots.add(certificate.clone()).unwrap();
+        }
+        let connector = TlsConnector::from(Arc::new(
+            ClientConfig::builder()
+                .with_root_certificates(roots)
+                .with_no_client_auth(),
+        ));
+
+        let (client, server) = tokio::io::duplex(16 * 1024);
+        let (client, server) = tokio::join!(
+            connector.connect(ServerName::try_from("localhost").unwrap(), client),
+            acceptor.accept(server)
+        );
+        let client = client.unwrap();
+        let certificate = client.get_ref().1.peer_certificates().unwrap()[0].clone();
+
+        (certificate, client, server.unwrap())
+    }
+
+    #[sqlx::test]
+    async fn reload_certificate(pool: PgPool) {
+        let dir = std::env::temp_dir().join(format!("remails-smtp-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = SmtpConfig {
+            cert_file: dir.join("cert.pem"),
+            key_file: dir.join("key.pem"),
+            ..Default::default()
+        };
+        let first = write_certificate(&config);
+
+        let server = SmtpServer::new(
+            pool,
+            Arc::new(config),
+            BusClient::new_from_env_var().unwrap(),
+            CancellationToken::new(),
+        );
+        let resolver = Arc::new(ReloadableCertificate::new(
+            server.load_certificate().await.unwrap(),
+        ));
+        let acceptor = SmtpServer::build_tls_acceptor(resolver.clone());
+        let (certificate, mut client, mut server_end) =
+            handshake(&acceptor, &[first.clone()]).await;
+        assert_eq!(certificate, first);
+
+        // the renewed certificate is presented to new connections
+        let second = write_certificate(&server.config);
+        server.reload_certificate(&resolver).await.unwrap();
+        let (certificate, ..) = handshake(&acceptor, &[first.clone(), second.clone()]).await;
+        assert_eq!(certificate, second);
+
+        // while the connection that was established before keeps working
+        client.write_all(b"NOOP\r\n").await.unwrap();
+        let mut received = [0; 6];
+        server_end.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"NOOP\r\n");
+
+        // a certificate that does not match the key is not loaded, nor is its expiry reported
+        let status = server.certificate.borrow().clone();
+        let mut params = rcgen::CertificateParams::new(vec!["localhost".to_owned()]).unwrap();
+        params.not_after = rcgen::date_time_ymd(2031, 5, 17);
+        let mismatched = params
+            .self_signed(&rcgen::KeyPair::generate().unwrap())
+            .unwrap();
+        std::fs::write(&server.config.cert_file, mismatched.pem()).unwrap();
+        assert!(server.reload_certificate(&resolver).await.is_err());
+        assert!(status.is_some());
+        assert_eq!(*server.certificate.borrow(), status);
+        let (certificate, ..) = handshake(&acceptor, &[first, second.clone()]).await;
+        assert_eq!(certificate, second);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,27 +1,46 @@
-use base64ct::Encoding;
+use base64ct::{Base64, Encoding};
+use chrono::Utc;
 use email_address::EmailAddress;
 use smtp_proto::{
-    AUTH_PLAIN, EXT_8BIT_MIME, EXT_AUTH, EXT_ENHANCED_STATUS_CODES, EXT_SMTP_UTF8, EhloResponse,
-    Request,
+    AUTH_CRAM_MD5, AUTH_PLAIN, AUTH_SCRAM_SHA_256, EXT_8BIT_MIME, EXT_AUTH,
+    EXT_ENHANCED_STATUS_CODES, EXT_PIPELINING, EXT_SIZE, EXT_SMTP_UTF8, EXT_START_TLS,
+    EhloResponse, MAIL_SMTPUTF8, Request, response::generate::BitToString,
 };
-use std::{borrow::Cow, fmt::Display, net::SocketAddr};
-use tracing::{debug, error, trace};
+use std::{borrow::Cow, fmt::Display, net::SocketAddr, time::Duration};
+use tracing::{debug, error, trace, warn};
 
 use crate::{
     bus::client::BusClient,
-    models::{Error, MessageRepository, NewMessage, SmtpCredential, SmtpCredentialRepository},
+    models::{
+        Created, Error, MessageRepository, NewMessage, SmtpCredential, SmtpCredentialRepository,
+    },
+    smtp::{
+        LoopDetectionConfig, TarpitConfig,
+        sasl::{self, ClientFirst, ScramExchange},
+    },
 };
 
 pub struct SmtpSession {
+    server_name: String,
     bus_client: BusClient,
     smtp_credentials: SmtpCredentialRepository,
     message_repository: MessageRepository,
     max_automatic_retries: i32,
+    loop_detection: LoopDetectionConfig,
+    tarpit: TarpitConfig,
+    /// Whether CRAM-MD5 and SCRAM-SHA-256 are offered next to PLAIN
+    challenge_response_auth: bool,
 
     peer_addr: SocketAddr,
     peer_name: Option<String>,
     authenticated_credential: Option<SmtpCredential>,
+    /// Authentication exchange waiting for the next response of the client
+    pending_auth: Option<AuthExchange>,
     current_message: Option<NewMessage>,
+    /// Whether the client announced the SMTPUTF8 parameter for the current message (RFC 6531)
+    smtputf8: bool,
+    /// Number of failed authentication attempts (and hit rate limits) on this connection
+    strikes: u32,
 }
 
 pub struct SmtpResponse(u16, String);
@@ -68,17 +87,29 @@ impl SmtpResponse {
     const NOVALID_RECIPIENTS: ConstResponse = (554, "5.5.1 No valid recipients");
     const INVALID_SENDER: ConstResponse = (553, "5.1.7 This sender address is not valid");
     const INVALID_EMAIL: ConstResponse = (553, "5.1.3 This email address is not valid");
+    const SMTPUTF8_REQUIRED: ConstResponse = (
+        553,
+        "5.6.7 Non-ASCII addresses require the SMTPUTF8 parameter",
+    );
     const NESTED_MAIL: ConstResponse = (503, "5.5.1 Error: nested MAIL command");
     const ALREADY_AUTHENTICATED: ConstResponse = (503, "5.5.1 Already authenticated");
     const AUTH_ERROR: ConstResponse = (535, "5.7.8 Authentication credentials invalid");
+    const AUTH_CANCELLED: ConstResponse = (501, "5.7.0 Authentication cancelled");
     const AUTHENTICATION_REQUIRED: ConstResponse = (530, "5.7.1 Authentication required");
     const ALREADY_TLS: ConstResponse = (504, "5.7.4 Already in TLS mode");
+    const READY_TO_START_TLS: ConstResponse = (220, "2.0.0 Ready to start TLS");
+    const STARTTLS_FIRST: ConstResponse = (530, "5.7.0 Must issue a STARTTLS command first");
     const COMMAND_NOT_IMPLEMENTED: ConstResponse = (502, "5.5.1 Command not implemented");
     const MUST_USE_ESMTP: ConstResponse = (502, "5.5.1 Must use EHLO");
     const NO_VRFY: ConstResponse = (502, "5.5.1 VRFY command is disabled");
     const INGEST_AUTH: ConstResponse = (334, "Tell me your secret.");
     const RATE_LIMIT: ConstResponse = (450, "4.3.2 Sent too many messages, try again later");
     const INTERNAL_ERROR: ConstResponse = (455, "4.0.0 Internal server error, try again later");
+    const MAIL_LOOP: ConstResponse = (554, "5.4.6 Routing loop detected");
+    const NOT_ALLOWED: ConstResponse = (
+        550,
+        "5.7.1 Sender or recipient not allowed for these credentials",
+    );
 }
 
 pub enum SessionReply {
@@ -89,11 +120,46 @@ pub enum SessionReply {
     IngestAuth(SmtpResponse),
 }
 
+/// Reply to a command received before the connection switched to TLS
+pub enum PlaintextReply {
+    ReplyAndContinue(SmtpResponse),
+    ReplyAndStop(SmtpResponse),
+    RawReply(Vec<u8>),
+    /// Reply, and start the TLS handshake right after (RFC 3207, 4)
+    StartTls(SmtpResponse),
+}
+
 pub enum DataReply {
     ReplyAndContinue(SmtpResponse),
     ContinueIngest,
 }
 
+pub enum AuthReply {
+    /// Send a challenge and wait for the next response of the client
+    Challenge(SmtpResponse),
+    Done(SmtpResponse),
+}
+
+/// Progress of an authentication exchange (RFC 4954, 4)
+enum AuthExchange {
+    Plain,
+    CramMd5 {
+        challenge: String,
+    },
+    /// Waiting for the first message of the client
+    ScramSha256Start,
+    /// The server sent its first message, and waits for the final message of the client
+    ScramSha256Proof {
+        /// Not set for unknown usernames, for which the exchange continues with a made up salt
+        credential: Option<SmtpCredential>,
+        exchange: Box<ScramExchange>,
+    },
+    /// The server sent its final message, and waits for the client to acknowledge it
+    ScramSha256Done {
+        credential: SmtpCredential,
+    },
+}
+
 struct AttemptedAuth<'a> {
     username: &'a str,
     password: &'a str,
@@ -104,25 +170,149 @@ enum AttemptedAuthError {
     Utf8Error,
 }
 
+/// Handles the commands on a STARTTLS listener until the client switches to TLS
+///
+/// Only EHLO, STARTTLS, NOOP, RSET, and QUIT are accepted, such that credentials and
+/// messages are never sent in plaintext.
+pub struct PlaintextSession {
+    server_name: String,
+    peer_addr: SocketAddr,
+}
+
+impl PlaintextSession {
+    pub fn new(server_name: String, peer_addr: SocketAddr) -> Self {
+        Self {
+            server_name,
+            peer_addr,
+        }
+    }
+
+    /// The multiline EHLO reply, only advertising STARTTLS (RFC 3207, 4)
+    fn ehlo_reply(server_name: &str) -> Vec<u8> {
+        let mut response = EhloResponse::new(server_name);
+        response.capabilities = EXT_ENHANCED_STATUS_CODES | EXT_PIPELINING | EXT_START_TLS;
+
+        let mut buf = Vec::with_capacity(128);
+        response.write(&mut buf).ok();
+
+        buf
+    }
+
+    pub fn handle(
+        &self,
+        request: Result<Request<Cow<'_, str>>, smtp_proto::Error>,
+    ) -> PlaintextReply {
+        let request = match request {
+            Ok(r) => r,
+            Err(e) => {
+                debug!("failed to parse plaintext request: {e}");
+
+                return PlaintextReply::ReplyAndContinue(SmtpResponse(554, e.to_string()));
+            }
+        };
+
+        // never log the credentials of clients that try to authenticate in plaintext
+        if !matches!(request, Request::Auth { .. }) {
+            trace!(
+                "received plaintext request: {request:?} from {}",
+                self.peer_addr
+            );
+        }
+
+        match request {
+            Request::Ehlo { .. } => PlaintextReply::RawReply(Self::ehlo_reply(&self.server_name)),
+            Request::Helo { .. } => {
+                PlaintextReply::ReplyAndContinue(SmtpResponse::MUST_USE_ESMTP.into())
+            }
+            Request::StartTls => PlaintextReply::StartTls(SmtpResponse::READY_TO_START_TLS.into()),
+            Request::Noop { .. } | Request::Rset => {
+                PlaintextReply::ReplyAndContinue(SmtpResponse::OK.into())
+            }
+            Request::Quit => PlaintextReply::ReplyAndStop(SmtpResponse::BYE.into()),
+            _ => PlaintextReply::ReplyAndContinue(SmtpResponse::STARTTLS_FIRST.into()),
+        }
+    }
+}
+
 impl SmtpSession {
     const MAX_BODY_SIZE: u64 = 20 * 1024 * 1024;
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        server_name: String,
         peer_addr: SocketAddr,
         bus_client: BusClient,
         smtp_credentials: SmtpCredentialRepository,
         message_repository: MessageRepository,
         max_automatic_retries: i32,
+        loop_detection: LoopDetectionConfig,
+        tarpit: TarpitConfig,
+        challenge_response_auth: bool,
     ) -> Self {
         Self {
+            server_name,
             bus_client,
             smtp_credentials,
             message_repository,
             max_automatic_retries,
+            loop_detection,
+            tarpit,
+            challenge_response_auth,
             peer_addr,
             peer_name: None,
             current_message: None,
+            smtputf8: false,
             authenticated_credential: None,
+            pending_auth: None,
+            strikes: 0,
+        }
+    }
+
+    /// The multiline EHLO reply, listing the supported extensions (RFC 5321, 4.1.1.1)
+    ///
+    /// STARTTLS is not advertised, as the connection already uses TLS.
+    fn ehlo_reply(server_name: &str, challenge_response_auth: bool) -> Vec<u8> {
+        let mut response = EhloResponse::new(server_name);
+        response.capabilities = EXT_ENHANCED_STATUS_CODES
+            | EXT_8BIT_MIME
+            | EXT_SMTP_UTF8
+            | EXT_AUTH
+            | EXT_SIZE
+            | EXT_PIPELINING;
+        response.auth_mechanisms = AUTH_PLAIN;
+        if challenge_response_auth {
+            response.auth_mechanisms |= AUTH_CRAM_MD5 | AUTH_SCRAM_SHA_256;
+        }
+        response.size = Self::MAX_BODY_SIZE as usize;
+
+        let mut buf = Vec::with_capacity(256);
+        response.write(&mut buf).ok();
+
+        buf
+    }
+
+    /// Delay of the next reply, increasing with every strike from the threshold onward
+    fn tarpit_delay(&self) -> Duration {
+        let threshold = self.tarpit.auth_failure_threshold;
+        if threshold == 0 || self.strikes < threshold {
+            return Duration::ZERO;
+        }
+
+        self.tarpit
+            .delay_step
+            .saturating_mul(self.strikes - threshold + 1)
+            .min(self.tarpit.max_delay)
+    }
+
+    /// Slow down clients that appear to be abusive before replying to them
+    pub async fn tarpit(&self) {
+        let delay = self.tarpit_delay();
+        if !delay.is_zero() {
+            debug!(
+                strikes = self.strikes,
+                "delaying reply to {} by {delay:?}", self.peer_addr
+            );
+            tokio::time::sleep(delay).await;
         }
     }
 
@@ -149,7 +339,8 @@ impl SmtpSession {
             // Without this if statement, we would print the user password as base64 string in the logs
             // which we want to avoid
             trace!(
-                "received AUTH with mechanism {mechanism} request from {}",
+                "received AUTH with mechanism {} request from {}",
+                mechanism.to_mechanism(),
                 self.peer_addr
             );
         } else {
@@ -158,19 +349,12 @@ impl SmtpSession {
 
         match request {
             Request::Ehlo { host } => {
-                // RFC5231, 4.1.1.1
-                let mut response = EhloResponse::new(&host);
-                response.capabilities =
-                    EXT_ENHANCED_STATUS_CODES | EXT_8BIT_MIME | EXT_SMTP_UTF8 | EXT_AUTH;
-
-                response.auth_mechanisms = AUTH_PLAIN;
-
-                let mut buf = Vec::with_capacity(64);
-                response.write(&mut buf).ok();
-
                 self.peer_name = Some(host.to_string());
 
-                SessionReply::RawReply(buf)
+                SessionReply::RawReply(Self::ehlo_reply(
+                    &self.server_name,
+                    self.challenge_response_auth,
+                ))
             }
             Request::Lhlo { host: _ } => {
                 // we do not currently support LMTP
@@ -190,21 +374,38 @@ impl SmtpSession {
                     );
                 }
 
-                if mechanism != AUTH_PLAIN {
-                    debug!("Received unsupported AUTH request");
-                    return SessionReply::ReplyAndContinue(SmtpResponse::AUTH_ERROR.into());
-                }
+                let (exchange, challenge) = match mechanism {
+                    AUTH_PLAIN => (AuthExchange::Plain, SmtpResponse::INGEST_AUTH.into()),
+                    AUTH_CRAM_MD5 if self.challenge_response_auth => {
+                        let challenge = self.cram_md5_challenge();
+                        let response =
+                            SmtpResponse(334, Base64::encode_string(challenge.as_bytes()));
+                        (AuthExchange::CramMd5 { challenge }, response)
+                    }
+                    AUTH_SCRAM_SHA_256 if self.challenge_response_auth => (
+                        AuthExchange::ScramSha256Start,
+                        SmtpResponse(334, String::new()),
+                    ),
+                    _ => {
+                        debug!("Received unsupported AUTH request");
+                        self.strikes += 1;
+                        return SessionReply::ReplyAndContinue(SmtpResponse::AUTH_ERROR.into());
+                    }
+                };
 
-                debug!("Received AUTH PLAIN");
+                debug!("Received AUTH {}", mechanism.to_mechanism());
+                self.pending_auth = Some(exchange);
 
-                if initial_response.is_empty() {
-                    return SessionReply::IngestAuth(SmtpResponse::INGEST_AUTH.into());
+                // CRAM-MD5 starts with a challenge of the server, so there is no initial response
+                if initial_response.is_empty() || mechanism == AUTH_CRAM_MD5 {
+                    return SessionReply::IngestAuth(challenge);
                 }
 
                 let mut initial_response = initial_response.into_owned().into_bytes();
-                let response = self.handle_plain_auth(&mut initial_response).await;
-
-                SessionReply::ReplyAndContinue(response)
+                match self.handle_auth_response(&mut initial_response).await {
+                    AuthReply::Challenge(response) => SessionReply::IngestAuth(response),
+                    AuthReply::Done(response) => SessionReply::ReplyAndContinue(response),
+                }
             }
             Request::Quit => {
                 // RFC5321, 4.1.1.10
@@ -218,6 +419,12 @@ impl SmtpSession {
                 // RFC5231, 4.1.1.2
                 debug!("received MAIL FROM: {}", from.address);
 
+                // RFC 6531, 3.4
+                let smtputf8 = from.flags & MAIL_SMTPUTF8 != 0;
+                if !smtputf8 && !from.address.is_ascii() {
+                    return SessionReply::ReplyAndContinue(SmtpResponse::SMTPUTF8_REQUIRED.into());
+                }
+
                 let Ok(from_address) = from.address.parse::<EmailAddress>() else {
                     return SessionReply::ReplyAndContinue(SmtpResponse::INVALID_SENDER.into());
                 };
@@ -237,8 +444,9 @@ impl SmtpSession {
                     .email_creation_rate_limit(credential.project_id())
                     .await
                 {
-                    Ok(()) => {}
-                    Err(Error::TooManyRequests) => {
+                    Ok(_) => {}
+                    Err(Error::RateLimited(_)) => {
+                        self.strikes = self.strikes.max(self.tarpit.auth_failure_threshold);
                         return SessionReply::ReplyAndStop(SmtpResponse::RATE_LIMIT.into());
                     }
                     Err(Error::OrgBlocked) => {
@@ -249,7 +457,10 @@ impl SmtpSession {
                     }
                 };
 
-                self.current_message = Some(NewMessage::new(credential.id(), from_address));
+                let mut message = NewMessage::new(credential.id(), from_address);
+                message.client_ip = Some(self.peer_addr.ip());
+                self.current_message = Some(message);
+                self.smtputf8 = smtputf8;
 
                 SessionReply::ReplyAndContinue(SmtpResponse::from_ok(from.address))
             }
@@ -265,6 +476,10 @@ impl SmtpSession {
                     return SessionReply::ReplyAndContinue(SmtpResponse::MAIL_FIRST.into());
                 };
 
+                if !self.smtputf8 && !to_address.as_str().is_ascii() {
+                    return SessionReply::ReplyAndContinue(SmtpResponse::SMTPUTF8_REQUIRED.into());
+                }
+
                 message.recipients.push(to_address);
 
                 SessionReply::ReplyAndContinue(SmtpResponse::to_ok(to.address))
@@ -345,7 +560,46 @@ impl SmtpSession {
         Ok(AttemptedAuth { username, password })
     }
 
-    pub(super) async fn handle_plain_auth(&mut self, data: &mut [u8]) -> SmtpResponse {
+    /// Continue the pending authentication exchange with the response of the client
+    pub(super) async fn handle_auth_response(&mut self, data: &mut [u8]) -> AuthReply {
+        let Some(exchange) = self.pending_auth.take() else {
+            return AuthReply::Done(SmtpResponse::BAD_SEQUENCE.into());
+        };
+
+        // RFC 4954, 4
+        if data.trim_ascii() == b"*" {
+            return AuthReply::Done(SmtpResponse::AUTH_CANCELLED.into());
+        }
+
+        match exchange {
+            AuthExchange::Plain => AuthReply::Done(self.handle_plain_auth(data).await),
+            AuthExchange::CramMd5 { challenge } => {
+                AuthReply::Done(self.handle_cram_md5_auth(&challenge, data).await)
+            }
+            exchange => self.handle_scram_auth(exchange, data).await,
+        }
+    }
+
+    /// A unique challenge in the format of RFC 2195, e.g., `<1896.697170952@mx.remails.net>`
+    fn cram_md5_challenge(&self) -> String {
+        format!(
+            "<{}.{}@{}>",
+            rand::random::<u32>(),
+            Utc::now().timestamp(),
+            self.server_name
+        )
+    }
+
+    /// Decode a base64 encoded response of the client
+    fn decode_auth_response(data: &mut [u8]) -> Option<&str> {
+        // we may need to trim off a trailing CR/LF
+        let ascii_len = data.trim_ascii_end().len();
+        let decoded = base64ct::Base64::decode_in_place(&mut data[..ascii_len]).ok()?;
+
+        std::str::from_utf8(decoded).ok()
+    }
+
+    async fn handle_plain_auth(&mut self, data: &mut [u8]) -> SmtpResponse {
         let Ok(AttemptedAuth { username, password }) = Self::decode_plain_auth(data) else {
             return SmtpResponse::SYNTAX_ERROR.into();
         };
@@ -356,13 +610,109 @@ impl SmtpSession {
         );
 
         let Ok(Some(credential)) = self.smtp_credentials.find_by_username(username).await else {
+            self.strikes += 1;
             return SmtpResponse::AUTH_ERROR.into();
         };
 
         if !credential.verify_password(password) {
+            self.strikes += 1;
+            return SmtpResponse::AUTH_ERROR.into();
+        }
+
+        self.authenticated(credential)
+    }
+
+    async fn handle_cram_md5_auth(&mut self, challenge: &str, data: &mut [u8]) -> SmtpResponse {
+        let Some((username, digest)) =
+            Self::decode_auth_response(data).and_then(sasl::parse_cram_md5)
+        else {
+            return SmtpResponse::SYNTAX_ERROR.into();
+        };
+
+        let Ok(Some(credential)) = self.smtp_credentials.find_by_username(username).await else {
+            self.strikes += 1;
+            return SmtpResponse::AUTH_ERROR.into();
+        };
+
+        if !credential.verify_cram_md5(challenge, &digest) {
+            self.strikes += 1;
             return SmtpResponse::AUTH_ERROR.into();
         }
 
+        self.authenticated(credential)
+    }
+
+    /// Handle a step of a SCRAM-SHA-256 exchange (RFC 5802, 5)
+    async fn handle_scram_auth(&mut self, exchange: AuthExchange, data: &mut [u8]) -> AuthReply {
+        let Some(message) = Self::decode_auth_response(data) else {
+            return AuthReply::Done(SmtpResponse::SYNTAX_ERROR.into());
+        };
+
+        match exchange {
+            AuthExchange::ScramSha256Start => {
+                let Some(client_first) = ClientFirst::parse(message) else {
+                    return AuthReply::Done(SmtpResponse::SYNTAX_ERROR.into());
+                };
+
+                let Ok(credential) = self
+                    .smtp_credentials
+                    .find_by_username(&client_first.username)
+                    .await
+                else {
+                    self.strikes += 1;
+                    return AuthReply::Done(SmtpResponse::AUTH_ERROR.into());
+                };
+
+                // unknown users fail at the proof, like wrong passwords, instead of revealing
+                // which usernames exist
+                let (credential, verifier) =
+                    match credential.and_then(|c| c.scram_sha256().map(|v| (c, v))) {
+                        Some((credential, verifier)) => (Some(credential), verifier),
+                        None => (None, sasl::unknown_user_verifier(&client_first.username)),
+                    };
+
+                let exchange = Box::new(ScramExchange::new(&client_first, verifier));
+                let challenge = Base64::encode_string(exchange.server_first().as_bytes());
+                self.pending_auth = Some(AuthExchange::ScramSha256Proof {
+                    credential,
+                    exchange,
+                });
+
+                AuthReply::Challenge(SmtpResponse(334, challenge))
+            }
+            AuthExchange::ScramSha256Proof {
+                credential,
+                exchange,
+            } => {
+                let (Some(server_final), Some(credential)) = (exchange.finish(message), credential)
+                else {
+                    self.strikes += 1;
+                    return AuthReply::Done(SmtpResponse::AUTH_ERROR.into());
+                };
+
+                self.pending_auth = Some(AuthExchange::ScramSha256Done { credential });
+
+                AuthReply::Challenge(SmtpResponse(
+                    334,
+                    Base64::encode_string(server_final.as_bytes()),
+                ))
+            }
+            AuthExchange::ScramSha256Done { credential } => {
+                if !message.is_empty() {
+                    return AuthReply::Done(SmtpResponse::SYNTAX_ERROR.into());
+                }
+
+                AuthReply::Done(self.authenticated(credential))
+            }
+            AuthExchange::Plain | AuthExchange::CramMd5 { .. } => {
+                AuthReply::Done(SmtpResponse::BAD_SEQUENCE.into())
+            }
+        }
+    }
+
+    fn authenticated(&mut self, credential: SmtpCredential) -> SmtpResponse {
+        // authenticated clients are not penalized for earlier typos
+        self.strikes = 0;
         self.authenticated_credential = Some(credential);
         SmtpResponse::AUTH_SUCCESS.into()
     }
@@ -393,6 +743,42 @@ impl SmtpSession {
         buffer.truncate(write);
     }
 
+    /// Detect mail loops, e.g., caused by an auto-responder that replies to our messages
+    /// which end up being relayed back to the auto-responder, by counting the `Received` headers
+    fn is_mail_loop(raw_data: &[u8], config: &LoopDetectionConfig) -> bool {
+        let mut received = 0;
+        let mut auto_replied = false;
+
+        // the header section ends at the first empty line (RFC 5322, 2.1)
+        for line in raw_data.split(|&b| b == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.is_empty() {
+                break;
+            }
+
+            // skip folded continuation lines
+            if line[0] == b' ' || line[0] == b'\t' {
+                continue;
+            }
+
+            let Some(colon) = line.iter().position(|&b| b == b':') else {
+                continue;
+            };
+            let (name, value) = (line[..colon].trim_ascii(), line[colon + 1..].trim_ascii());
+
+            if name.eq_ignore_ascii_case(b"Received") {
+                received += 1;
+            } else if name.eq_ignore_ascii_case(b"Auto-Submitted") {
+                // the keyword may be followed by optional parameters (RFC 3834, 5)
+                let keyword = value.split(|&b| b == b';').next().unwrap_or_default();
+                auto_replied = keyword.trim_ascii().eq_ignore_ascii_case(b"auto-replied");
+            }
+        }
+
+        received > config.max_received
+            || (auto_replied && received > config.max_auto_replied_received)
+    }
+
     pub async fn handle_data(&mut self, data: &[u8]) -> DataReply {
         let Some(NewMessage {
             raw_data: buffer, ..
@@ -411,8 +797,19 @@ impl SmtpSession {
 
         const DATA_END: &[u8] = b"\r\n.\r\n";
 
-        if buffer.ends_with(DATA_END) || buffer == &DATA_END[2..] {
-            buffer.truncate(buffer.len() - DATA_END.len());
+        // The end of data indicator is a line with a single period. The line break before it is
+        // not stored, as it is written again when relaying the message. An empty message consists
+        // of the indicator only, so there is no preceding line break to strip.
+        let data_len = if buffer.as_slice() == &DATA_END[2..] {
+            Some(0)
+        } else if buffer.ends_with(DATA_END) {
+            Some(buffer.len() - DATA_END.len())
+        } else {
+            None
+        };
+
+        if let Some(data_len) = data_len {
+            buffer.truncate(data_len);
 
             Self::unstuff_periods(buffer);
 
@@ -422,13 +819,40 @@ impl SmtpSession {
 
             trace!("received message ({} bytes)", message.raw_data.len());
 
+            if Self::is_mail_loop(&message.raw_data, &self.loop_detection) {
+                warn!(
+                    message_id = message.message_id.to_string(),
+                    from_email = message.from_email.as_str(),
+                    "rejected message because of a mail loop"
+                );
+                return DataReply::ReplyAndContinue(SmtpResponse::MAIL_LOOP.into());
+            }
+
+            if let Some(credential) = &self.authenticated_credential
+                && !credential.may_send(&message.from_email, &message.recipients)
+            {
+                debug!(
+                    message_id = message.message_id.to_string(),
+                    from_email = message.from_email.as_str(),
+                    "rejected message because of the restrictions of the SMTP credential"
+                );
+                return DataReply::ReplyAndContinue(SmtpResponse::NOT_ALLOWED.into());
+            }
+
             // Store message in database
             let message_id = match self
                 .message_repository
                 .create(message, self.max_automatic_retries)
                 .await
             {
-                Ok(m) => m,
+                Ok(Created::New(m)) => m,
+                Ok(Created::Duplicate(m)) => {
+                    debug!(
+                        message_id = m.to_string(),
+                        "accepted duplicate message without storing it again"
+                    );
+                    return DataReply::ReplyAndContinue(SmtpResponse::MESSAGE_ACCEPTED.into());
+                }
                 Err(e) => {
                     debug!("failed to create message: {e}");
                     return DataReply::ReplyAndContinue(SmtpResponse::MESSAGE_REJECTED.into());
@@ -439,6 +863,12 @@ impl SmtpSession {
                 Ok(bus_message) => {
                     self.bus_client.try_send(&bus_message).await;
                 }
+                Err(Error::SendingPaused) => {
+                    debug!(
+                        message_id = message_id.to_string(),
+                        "organization paused sending, message is sent once resumed"
+                    );
+                }
                 Err(e) => {
                     error!(message_id = message_id.to_string(), "{e:?}");
                 }
@@ -453,7 +883,10 @@ impl SmtpSession {
 
 #[cfg(test)]
 mod tests {
-    use crate::smtp::session::SmtpSession;
+    use crate::smtp::{
+        LoopDetectionConfig,
+        session::{PlaintextSession, SmtpSession},
+    };
 
     #[test]
     fn test_unstuff_periods() {
@@ -477,4 +910,107 @@ mod tests {
         SmtpSession::unstuff_periods(&mut buffer);
         assert_eq!(buffer, b"");
     }
+
+    #[test]
+    fn test_ehlo_reply() {
+        let reply = String::from_utf8(SmtpSession::ehlo_reply("mx.remails.net", false)).unwrap();
+        let lines = reply
+            .strip_suffix("\r\n")
+            .unwrap()
+            .split("\r\n")
+            .collect::<Vec<_>>();
+
+        // every line but the last continues the reply
+        let (last, continued) = lines.split_last().unwrap();
+        assert!(continued.iter().all(|line| line.starts_with("250-")));
+        assert!(last.starts_with("250 "));
+        assert!(continued[0].starts_with("250-mx.remails.net"));
+
+        let mut keywords = lines[1..].iter().map(|line| &line[4..]).collect::<Vec<_>>();
+        keywords.sort();
+        assert_eq!(
+            keywords,
+            [
+                "8BITMIME",
+                "AUTH PLAIN",
+                "ENHANCEDSTATUSCODES",
+                "PIPELINING",
+                "SIZE 20971520",
+                "SMTPUTF8"
+            ]
+        );
+
+        // challenge-response mechanisms are only advertised if enabled
+        let reply = String::from_utf8(SmtpSession::ehlo_reply("mx.remails.net", true)).unwrap();
+        assert!(reply.contains("AUTH PLAIN CRAM-MD5 SCRAM-SHA-256\r\n"));
+    }
+
+    #[test]
+    fn test_plaintext_ehlo_reply() {
+        let reply = String::from_utf8(PlaintextSession::ehlo_reply("mx.remails.net")).unwrap();
+        let mut keywords = reply
+            .strip_suffix("\r\n")
+            .unwrap()
+            .split("\r\n")
+            .skip(1)
+            .map(|line| &line[4..])
+            .collect::<Vec<_>>();
+        keywords.sort();
+
+        // authentication is only offered once the connection switched to TLS
+        assert_eq!(keywords, ["ENHANCEDSTATUSCODES", "PIPELINING", "STARTTLS"]);
+    }
+
+    #[test]
+    fn test_mail_loop_detection() {
+        let config = LoopDetectionConfig {
+            max_received: 10,
+            max_auto_replied_received: 2,
+        };
+
+        let received = "Received: from mx.example.com (mx.example.com [192.0.2.1])\r\n\
+            \tby remails.net; Thu, 20 Nov 2025 14:33:48 +0000\r\n";
+        let message = |headers: &str| {
+            format!("{headers}From: john@example.com\r\nSubject: Out of office\r\n\r\nbody\r\n")
+        };
+
+        // a regular message passes
+        let normal = message(&received.repeat(3));
+        assert!(!SmtpSession::is_mail_loop(normal.as_bytes(), &config));
+
+        // an auto-reply with few hops passes
+        let auto_reply = message(&format!(
+            "Auto-Submitted: auto-replied\r\n{}",
+            received.repeat(2)
+        ));
+        assert!(!SmtpSession::is_mail_loop(auto_reply.as_bytes(), &config));
+
+        // an auto-reply with many hops is rejected
+        let looping = message(&format!(
+            "Auto-Submitted: Auto-Replied; owner-email=\"john@example.com\"\r\n{}",
+            received.repeat(3)
+        ));
+        assert!(SmtpSession::is_mail_loop(looping.as_bytes(), &config));
+
+        // auto-generated (not a reply) messages only hit the regular limit
+        let auto_generated = message(&format!(
+            "Auto-Submitted: auto-generated\r\n{}",
+            received.repeat(3)
+        ));
+        assert!(!SmtpSession::is_mail_loop(
+            auto_generated.as_bytes(),
+            &config
+        ));
+
+        // any message with too many hops is rejected
+        let too_many_hops = message(&received.repeat(11));
+        assert!(SmtpSession::is_mail_loop(too_many_hops.as_bytes(), &config));
+
+        // Received headers in the body don't count
+        let in_body = format!(
+            "Auto-Submitted: auto-replied\r\n{}",
+            message(&received.repeat(2)) + &received.repeat(3)
+        );
+        assert!(!SmtpSession::is_mail_loop(in_body.as_bytes(), &config));
+    }
 }
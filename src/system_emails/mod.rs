@@ -1,13 +1,16 @@
 use crate::{
     api::ApiState,
     bus::client::BusClient,
-    models::{ApiUserRepository, Error, Label, MessageRepository},
+    models::{
+        ApiUserRepository, CreatedInviteWithPassword, DkimDrift, Error, Label, MessageId,
+        MessageRepository, OrgBlockStatus, OrganizationId, OrganizationRepository, Role,
+    },
 };
 use askama::Template;
 use axum::extract::FromRef;
 use email_address::EmailAddress;
 use std::sync::Arc;
-use tracing::{error, warn};
+use tracing::{debug, error, warn};
 
 #[derive(Template)]
 #[template(path = "password_reset.html")]
@@ -23,6 +26,76 @@ struct TxtTemplate<'a> {
     name: &'a str,
 }
 
+#[derive(Template)]
+#[template(path = "invite.html")]
+struct InviteHtmlTemplate<'a> {
+    invite_link: &'a str,
+    organization_name: &'a str,
+    invited_by: &'a str,
+    expires_at: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "invite.txt")]
+struct InviteTxtTemplate<'a> {
+    invite_link: &'a str,
+    organization_name: &'a str,
+    invited_by: &'a str,
+    expires_at: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "block_status.html")]
+struct BlockStatusHtmlTemplate<'a> {
+    organization_name: &'a str,
+    explanation: &'a str,
+    blocked: bool,
+}
+
+#[derive(Template)]
+#[template(path = "block_status.txt")]
+struct BlockStatusTxtTemplate<'a> {
+    organization_name: &'a str,
+    explanation: &'a str,
+    blocked: bool,
+}
+
+#[derive(Template)]
+#[template(path = "quota_alert.html")]
+struct QuotaAlertHtmlTemplate<'a> {
+    explanation: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "quota_alert.txt")]
+struct QuotaAlertTxtTemplate<'a> {
+    explanation: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "needs_review.html")]
+struct NeedsReviewHtmlTemplate<'a> {
+    explanation: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "needs_review.txt")]
+struct NeedsReviewTxtTemplate<'a> {
+    explanation: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "dkim_drift.html")]
+struct DkimDriftHtmlTemplate<'a> {
+    explanation: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "dkim_drift.txt")]
+struct DkimDriftTxtTemplate<'a> {
+    explanation: &'a str,
+}
+
 struct InternalEmail {
     to: EmailAddress,
     subject: String,
@@ -83,10 +156,322 @@ pub async fn send_password_reset_email(
     Ok(())
 }
 
+pub async fn send_invite_email(
+    api_state: &ApiState,
+    email_address: EmailAddress,
+    invite: &CreatedInviteWithPassword,
+    organization_name: &str,
+    invited_by: &str,
+) -> Result<(), Error> {
+    let link = format!(
+        "https://{}/invite/{}/{}/{}",
+        api_state.api_server_name(),
+        invite.organization_id(),
+        invite.id(),
+        invite.password()
+    );
+    let expires_at = invite.expires_at().format("%Y-%m-%d %H:%M UTC").to_string();
+
+    let html = InviteHtmlTemplate {
+        invite_link: &link,
+        organization_name,
+        invited_by,
+        expires_at: &expires_at,
+    }
+    .render()?;
+
+    let text = InviteTxtTemplate {
+        invite_link: &link,
+        organization_name,
+        invited_by,
+        expires_at: &expires_at,
+    }
+    .render()?;
+
+    send_internal_email(
+        api_state,
+        InternalEmail {
+            to: email_address,
+            subject: format!("Invitation to join {organization_name} on Remails"),
+            text,
+            html,
+            label: "invite".parse().unwrap(),
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Notifies an organization admin that the Remails admins changed the block status of their organization
+pub async fn send_block_status_email(
+    api_state: &ApiState,
+    email_address: EmailAddress,
+    organization_name: &str,
+    block_status: OrgBlockStatus,
+) -> Result<(), Error> {
+    let explanation = match block_status {
+        OrgBlockStatus::NotBlocked => format!(
+            "All restrictions on the {organization_name} organization have been lifted. \
+            It can send and receive emails again."
+        ),
+        OrgBlockStatus::NoSending => format!(
+            "Sending emails has been blocked for the {organization_name} organization. \
+            Incoming emails are still received."
+        ),
+        OrgBlockStatus::NoSendingOrReceiving => format!(
+            "Sending and receiving emails has been blocked for the {organization_name} organization."
+        ),
+        OrgBlockStatus::FullFreeze => format!(
+            "The {organization_name} organization has been frozen. \
+            It can no longer send or receive emails, and its settings can no longer be changed."
+        ),
+    };
+    let blocked = block_status != OrgBlockStatus::NotBlocked;
+
+    let html = BlockStatusHtmlTemplate {
+        organization_name,
+        explanation: &explanation,
+        blocked,
+    }
+    .render()?;
+
+    let text = BlockStatusTxtTemplate {
+        organization_name,
+        explanation: &explanation,
+        blocked,
+    }
+    .render()?;
+
+    let subject = if blocked {
+        format!("{organization_name} has been restricted on Remails")
+    } else {
+        format!("{organization_name} is no longer restricted on Remails")
+    };
+
+    send_internal_email(
+        api_state,
+        InternalEmail {
+            to: email_address,
+            subject,
+            text,
+            html,
+            label: "block-status".parse().unwrap(),
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Notifies the admins of an organization that it has used `threshold` percent of its message quota
+///
+/// Unlike the other system emails, this is sent by the outbound handler instead of the API
+pub async fn send_quota_alert_emails(
+    message_repo: &MessageRepository,
+    organization_repo: &OrganizationRepository,
+    bus: &BusClient,
+    max_automatic_retries: i32,
+    org_id: OrganizationId,
+    threshold: i32,
+) -> Result<(), Error> {
+    let organization = organization_repo
+        .get_by_id(org_id)
+        .await?
+        .ok_or(Error::NotFound("organization not found"))?;
+    let organization_name = &organization.name;
+
+    let explanation = if threshold >= 100 {
+        format!(
+            "The {organization_name} organization has used its full message quota. \
+            New emails are held until the quota resets."
+        )
+    } else {
+        format!(
+            "The {organization_name} organization has used {threshold}% of its message quota. \
+            Once the quota is used up, new emails are held until the quota resets."
+        )
+    };
+
+    let html = QuotaAlertHtmlTemplate {
+        explanation: &explanation,
+    }
+    .render()?;
+
+    let text = QuotaAlertTxtTemplate {
+        explanation: &explanation,
+    }
+    .render()?;
+
+    send_to_admins(
+        message_repo,
+        organization_repo,
+        bus,
+        max_automatic_retries,
+        org_id,
+        format!("{organization_name} has used {threshold}% of its Remails quota"),
+        text,
+        html,
+        "quota-alert".parse().unwrap(),
+    )
+    .await
+}
+
+/// Notifies the admins of an organization that a message exhausted its automatic retries, and
+/// is not retried until it is requeued
+///
+/// Like the quota alerts, this is sent by the outbound handler
+pub async fn send_needs_review_emails(
+    message_repo: &MessageRepository,
+    organization_repo: &OrganizationRepository,
+    bus: &BusClient,
+    max_automatic_retries: i32,
+    org_id: OrganizationId,
+    message_id: MessageId,
+    attempts: i32,
+) -> Result<(), Error> {
+    let organization = organization_repo
+        .get_by_id(org_id)
+        .await?
+        .ok_or(Error::NotFound("organization not found"))?;
+    let organization_name = &organization.name;
+
+    let explanation = format!(
+        "An email of the {organization_name} organization (ID {message_id}) could not be \
+        delivered after {attempts} attempts. It is not retried until it is requeued."
+    );
+
+    let html = NeedsReviewHtmlTemplate {
+        explanation: &explanation,
+    }
+    .render()?;
+
+    let text = NeedsReviewTxtTemplate {
+        explanation: &explanation,
+    }
+    .render()?;
+
+    send_to_admins(
+        message_repo,
+        organization_repo,
+        bus,
+        max_automatic_retries,
+        org_id,
+        format!("An email of {organization_name} needs review"),
+        text,
+        html,
+        "needs-review".parse().unwrap(),
+    )
+    .await
+}
+
+/// Notifies the admins of an organization that the DKIM record of one of its domains no longer
+/// matches the key of that domain
+///
+/// Like the quota alerts, this is not sent by the API, but by the periodic domain verification
+pub async fn send_dkim_drift_emails(
+    message_repo: &MessageRepository,
+    organization_repo: &OrganizationRepository,
+    bus: &BusClient,
+    max_automatic_retries: i32,
+    drift: &DkimDrift,
+) -> Result<(), Error> {
+    let domain = &drift.domain;
+    let explanation = format!(
+        "The DKIM record of {domain} no longer matches the key Remails signs its emails with: {}. \
+        Receivers may reject emails from {domain} or mark them as spam until the record is restored.",
+        drift.reason
+    );
+
+    let html = DkimDriftHtmlTemplate {
+        explanation: &explanation,
+    }
+    .render()?;
+
+    let text = DkimDriftTxtTemplate {
+        explanation: &explanation,
+    }
+    .render()?;
+
+    send_to_admins(
+        message_repo,
+        organization_repo,
+        bus,
+        max_automatic_retries,
+        drift.organization_id,
+        format!("The DKIM record of {domain} no longer matches"),
+        text,
+        html,
+        "dkim-drift".parse().unwrap(),
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn send_to_admins(
+    message_repo: &MessageRepository,
+    organization_repo: &OrganizationRepository,
+    bus: &BusClient,
+    max_automatic_retries: i32,
+    org_id: OrganizationId,
+    subject: String,
+    text: String,
+    html: String,
+    label: Label,
+) -> Result<(), Error> {
+    let admins = organization_repo
+        .list_members(org_id)
+        .await?
+        .into_iter()
+        .filter(|member| *member.role() == Role::Admin);
+
+    for admin in admins {
+        let Ok(to) = admin.email().parse() else {
+            warn!(
+                organization_id = org_id.to_string(),
+                user_id = admin.user_id().to_string(),
+                "could not parse email address of organization admin"
+            );
+            continue;
+        };
+
+        create_and_send_internal_email(
+            message_repo,
+            bus,
+            max_automatic_retries,
+            InternalEmail {
+                to,
+                subject: subject.clone(),
+                text: text.clone(),
+                html: html.clone(),
+                label: label.clone(),
+            },
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
 async fn send_internal_email(api_state: &ApiState, email: InternalEmail) -> Result<(), Error> {
     let message_repo = MessageRepository::from_ref(api_state);
     let bus = Arc::<BusClient>::from_ref(api_state);
 
+    create_and_send_internal_email(
+        &message_repo,
+        &bus,
+        api_state.retry_config.max_automatic_retries,
+        email,
+    )
+    .await
+}
+
+async fn create_and_send_internal_email(
+    message_repo: &MessageRepository,
+    bus: &BusClient,
+    max_automatic_retries: i32,
+    email: InternalEmail,
+) -> Result<(), Error> {
     let message_id = message_repo
         .create_system_email(
             email.to,
@@ -94,7 +479,7 @@ async fn send_internal_email(api_state: &ApiState, email: InternalEmail) -> Resu
             email.text,
             email.html,
             email.label,
-            api_state.retry_config.max_automatic_retries,
+            max_automatic_retries,
         )
         .await?;
 
@@ -102,6 +487,12 @@ async fn send_internal_email(api_state: &ApiState, email: InternalEmail) -> Resu
         Ok(bus_message) => {
             bus.try_send(&bus_message).await;
         }
+        Err(Error::SendingPaused) => {
+            debug!(
+                message_id = message_id.to_string(),
+                "organization paused sending, message is sent once resumed"
+            );
+        }
         Err(e) => {
             error!(message_id = message_id.to_string(), "{e:?}");
         }
@@ -0,0 +1,125 @@
+use opentelemetry::{global, trace::TracerProvider};
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    trace::{Sampler, SdkTracer, SdkTracerProvider},
+};
+use std::{collections::HashMap, env};
+use tracing::Subscriber;
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+/// W3C trace context, used to correlate spans across services, e.g., via the message bus
+pub type TraceContext = HashMap<String, String>;
+
+/// Configures the export of spans to an OpenTelemetry (OTLP) collector
+///
+/// The exporter itself is configured with the standard `OTEL_EXPORTER_OTLP_*` env vars, and
+/// the service name with `OTEL_SERVICE_NAME`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TelemetryConfig {
+    /// Fraction of the traces that is exported, between 0 and 1
+    sampling_ratio: f64,
+}
+
+impl TelemetryConfig {
+    /// Returns `None` if no OTLP endpoint is configured, in which case no spans are exported
+    pub fn from_env() -> Option<Self> {
+        Self::new(
+            env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok().as_deref(),
+            env::var("OTEL_TRACES_SAMPLER_ARG").ok().as_deref(),
+        )
+    }
+
+    fn new(endpoint: Option<&str>, sampling_ratio: Option<&str>) -> Option<Self> {
+        endpoint.filter(|endpoint| !endpoint.is_empty())?;
+
+        let sampling_ratio = sampling_ratio
+            .map(|ratio| {
+                ratio
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|ratio| (0.0..=1.0).contains(ratio))
+                    .expect("OTEL_TRACES_SAMPLER_ARG must be a number between 0 and 1")
+            })
+            .unwrap_or(1.0);
+
+        Some(Self { sampling_ratio })
+    }
+
+    fn tracer_provider(&self) -> anyhow::Result<SdkTracerProvider> {
+        let exporter = SpanExporter::builder().with_http().build()?;
+
+        Ok(SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                self.sampling_ratio,
+            ))))
+            .build())
+    }
+
+    /// Creates the tracing layer exporting spans, and installs the trace context propagator
+    /// used by [`current_trace_context`] and [`set_parent`]
+    pub fn layer<S>(&self) -> anyhow::Result<OpenTelemetryLayer<S, SdkTracer>>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let provider = self.tracer_provider()?;
+        let tracer = provider.tracer(env!("CARGO_CRATE_NAME"));
+
+        global::set_tracer_provider(provider);
+        global::set_text_map_propagator(TraceContextPropagator::new());
+
+        Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+    }
+}
+
+/// The trace context of the current span, empty if OpenTelemetry is not configured
+pub fn current_trace_context() -> TraceContext {
+    let context = tracing::Span::current().context();
+    let mut trace_context = TraceContext::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut trace_context)
+    });
+
+    trace_context
+}
+
+/// Makes `span` a child of the span the `trace_context` originates from
+pub fn set_parent(span: &tracing::Span, trace_context: &TraceContext) {
+    if trace_context.is_empty() {
+        return;
+    }
+
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(trace_context));
+    span.set_parent(parent);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tracing_subscriber::Registry;
+
+    #[test]
+    fn disabled_without_endpoint() {
+        assert_eq!(TelemetryConfig::new(None, Some("0.5")), None);
+        assert_eq!(TelemetryConfig::new(Some(""), None), None);
+    }
+
+    #[test]
+    fn layer_initializes_with_endpoint() {
+        let config = TelemetryConfig::new(Some("http://localhost:4318"), None).unwrap();
+        assert_eq!(config.sampling_ratio, 1.0);
+
+        let config = TelemetryConfig::new(Some("http://localhost:4318"), Some("0.25")).unwrap();
+        assert_eq!(config.sampling_ratio, 0.25);
+
+        config.layer::<Registry>().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "OTEL_TRACES_SAMPLER_ARG must be a number between 0 and 1")]
+    fn invalid_sampling_ratio() {
+        TelemetryConfig::new(Some("http://localhost:4318"), Some("2"));
+    }
+}
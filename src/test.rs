@@ -1,13 +1,13 @@
 use crate::{
-    Environment,
     bus::{client::BusClient, server::Bus},
     handler::{HandlerConfig, RetryConfig, dns::DnsResolver},
     models::{
         ApiKey, ApiMessage, ApiMessageMetadata, CreatedApiKeyWithPassword, MessageStatus,
-        OrgBlockStatus, OrganizationId, Project, ProjectId, SmtpCredential, SmtpCredentialResponse,
+        OrgBlockStatus, OrganizationId, Project, ProjectId, SmtpCredential,
+        SmtpCredentialRepository, SmtpCredentialRequest, SmtpCredentialResponse,
     },
     run_api_server, run_mta,
-    smtp::SmtpConfig,
+    smtp::{SmtpConfig, SmtpListener, TlsMode},
 };
 use http::{HeaderMap, StatusCode, header, header::CONTENT_TYPE};
 use mail_send::{SmtpClientBuilder, mail_builder::MessageBuilder};
@@ -66,6 +66,24 @@ impl TestProjects {
     }
 }
 
+/// Generates an SMTP credential of [`TestProjects::Org1Project1`] to create test messages with
+pub(crate) async fn test_credential(pool: &PgPool) -> SmtpCredentialResponse {
+    let (org_id, project_id) = TestProjects::Org1Project1.get_ids();
+    SmtpCredentialRepository::new(pool.clone())
+        .generate(
+            org_id,
+            project_id,
+            &SmtpCredentialRequest {
+                username: "user".to_string(),
+                description: "Test SMTP credential description".to_string(),
+                cram_md5: false,
+            },
+            crate::models::SYSTEM,
+        )
+        .await
+        .unwrap()
+}
+
 async fn setup(
     pool: PgPool,
 ) -> (
@@ -99,22 +117,30 @@ async fn setup(
     let retry_config = RetryConfig {
         delay: chrono::Duration::minutes(5),
         max_automatic_retries: 2,
+        greylist: Default::default(),
     };
 
     let smtp_config = SmtpConfig {
-        listen_addr: smtp_socket.into(),
+        listeners: vec![SmtpListener {
+            addr: smtp_socket.into(),
+            tls: TlsMode::Implicit,
+        }],
         server_name: "localhost".to_string(),
+        banner: None,
         cert_file: "dev-secrets/cert.pem".into(),
         key_file: "dev-secrets/key.pem".into(),
         environment: Default::default(),
         retry: retry_config.clone(),
+        loop_detection: Default::default(),
+        tarpit: Default::default(),
+        challenge_response_auth: false,
+        certificate_expiry_warning: chrono::Duration::days(14),
     };
 
     let handler_config = HandlerConfig {
-        domain: "test".to_owned(),
         resolver: DnsResolver::mock("localhost", mailcrab_random_port),
-        environment: Environment::Development,
         retry: retry_config,
+        ..HandlerConfig::test_default()
     };
 
     let bus_port = Bus::spawn_random_port().await;